@@ -5,15 +5,30 @@ import 'dart:convert';
 import 'dart:ffi';
 import 'dart:io';
 import 'dart:isolate';
+import 'dart:math' show max, min;
 import 'dart:typed_data';
 
 import 'package:ffi/ffi.dart';
 
 const String _libName = 'cribcall_quic';
 
+/// The C ABI these bindings are written against (`CC_QUIC_ABI_VERSION`).
+const int _abiVersion = 2;
+
+/// `CC_QUIC_ALLOW_UNPINNED`, the `cc_quic_client_connect` flag.
+const int _allowUnpinned = 1 << 0;
+
 class CribcallQuic {
   CribcallQuic({DynamicLibrary? dynamicLibrary})
     : _bindings = _NativeBindings(dynamicLibrary ?? _loadLibrary()) {
+    final abi = _bindings.abiVersion();
+    if (abi != _abiVersion) {
+      throw CribcallQuicException(
+        'abi_version',
+        CcQuicStatus.unsupported,
+        'native library speaks ABI $abi, these bindings $_abiVersion',
+      );
+    }
     _initDartApi();
   }
 
@@ -38,46 +53,296 @@ class CribcallQuic {
     return ptr.cast<Utf8>().toDartString();
   }
 
-  QuicConfigHandle createConfig() {
+  /// Newest event schema the loaded native library can emit.
+  int eventSchemaVersion() => _bindings.eventSchemaVersion();
+
+  /// Creates a config that posts the newest event schema both this wrapper
+  /// and the native library understand, starting from [preset] if given.
+  QuicConfigHandle createConfig({QuicPreset? preset}) {
     final configPtrPtr = calloc<Pointer<CcQuicConfig>>();
-    final status = _bindings.configNew(configPtrPtr);
-    _throwIfError(status, 'config init');
+    final int status;
+    if (preset == null) {
+      status = _bindings.configNew(configPtrPtr);
+    } else {
+      final namePtr = switch (preset) {
+        QuicPreset.lanLowLatency => 'lan-low-latency',
+        QuicPreset.cellularRemote => 'cellular-remote',
+        QuicPreset.bulkTransfer => 'bulk-transfer',
+      }.toNativeUtf8();
+      status = _bindings.configNewPreset(namePtr, configPtrPtr);
+      calloc.free(namePtr);
+    }
+    return _adoptConfig(status, configPtrPtr);
+  }
+
+  /// Like [createConfig], for [startClient] only: server-only setters and
+  /// [startServer] throw with [CcQuicStatus.wrongRole].
+  QuicConfigHandle createClientConfig() {
+    final configPtrPtr = calloc<Pointer<CcQuicConfig>>();
+    return _adoptConfig(_bindings.configNewClient(configPtrPtr), configPtrPtr);
+  }
+
+  /// Like [createConfig], for [startServer] only: client-only setters and
+  /// [startClient] throw with [CcQuicStatus.wrongRole].
+  QuicConfigHandle createServerConfig() {
+    final configPtrPtr = calloc<Pointer<CcQuicConfig>>();
+    return _adoptConfig(_bindings.configNewServer(configPtrPtr), configPtrPtr);
+  }
+
+  QuicConfigHandle _adoptConfig(
+    int status,
+    Pointer<Pointer<CcQuicConfig>> configPtrPtr,
+  ) {
     final handle = configPtrPtr.value;
     calloc.free(configPtrPtr);
+    _throwIfError(status, 'config init');
     final isNullHandle = handle == Pointer<CcQuicConfig>.fromAddress(0);
     if (isNullHandle) {
       _throwIfError(CcQuicStatus.internal.code, 'config allocation');
     }
+    final config = QuicConfigHandle._(handle, _bindings);
+    config.setEventSchema(min(eventSchemaVersion(), quicEventSchemaVersion));
+    return config;
+  }
+
+  /// Creates a config from a document covering every tunable (see
+  /// `rust/src/jsonconfig.rs` for the schema). Unlike [createConfig] the
+  /// event schema is left as [json] sets it. A rejected document throws with
+  /// the native message naming the offending field.
+  QuicConfigHandle createConfigFromJson(Map<String, Object?> json) {
+    final configPtrPtr = calloc<Pointer<CcQuicConfig>>();
+    final jsonPtr = jsonEncode(json).toNativeUtf8();
+    final status = _bindings.configFromJson(jsonPtr, configPtrPtr);
+    calloc.free(jsonPtr);
+    final handle = configPtrPtr.value;
+    calloc.free(configPtrPtr);
+    final parsed = CcQuicStatus.fromCode(status);
+    if (parsed != CcQuicStatus.ok) {
+      throw CribcallQuicException('config from json', parsed, _lastError());
+    }
     return QuicConfigHandle._(handle, _bindings);
   }
 
+  /// Records every accepted, rejected and closed connection to [path]
+  /// (rotated past [maxBytes], 256 KiB when 0), for [queryAudit]; `null`
+  /// stops recording.
+  void enableAudit(String? path, {int maxBytes = 0}) {
+    final pathPtr = path?.toNativeUtf8() ?? nullptr;
+    final status = _bindings.auditEnable(pathPtr, maxBytes);
+    if (pathPtr != nullptr) calloc.free(pathPtr);
+    _throwIfError(status, 'audit_enable');
+  }
+
+  /// Serves Prometheus text-format metrics for every handle at
+  /// `http://<bindAddr>/metrics`, with [bindAddr] as `ip:port`. Needs a
+  /// native build with the `metrics` feature.
+  void serveMetrics(String bindAddr) {
+    final metricsServe = _bindings.metricsServe;
+    if (metricsServe == null) {
+      throw UnsupportedError('Native library built without metrics');
+    }
+    final addrPtr = bindAddr.toNativeUtf8();
+    final status = metricsServe(addrPtr);
+    calloc.free(addrPtr);
+    _throwIfError(status, 'metrics_serve');
+  }
+
+  /// Audit records from [since] on (all kept records when omitted), oldest
+  /// first.
+  List<QuicAuditRecord> queryAudit({DateTime? since}) {
+    final sinceMs = since?.millisecondsSinceEpoch ?? 0;
+    final lenPtr = calloc<UintPtr>();
+    var capacity = 64 * 1024;
+    try {
+      while (true) {
+        final buf = calloc<Uint8>(capacity);
+        try {
+          final status = _bindings.auditQuery(sinceMs, buf, capacity, lenPtr);
+          if (status == CcQuicStatus.configError.code &&
+              lenPtr.value >= capacity) {
+            capacity = lenPtr.value + 1;
+            continue;
+          }
+          _throwIfError(status, 'audit_query');
+          return const LineSplitter()
+              .convert(utf8.decode(buf.asTypedList(lenPtr.value)))
+              .map(QuicAuditRecord.fromJson)
+              .toList();
+        } finally {
+          calloc.free(buf);
+        }
+      }
+    } finally {
+      calloc.free(lenPtr);
+    }
+  }
+
+  /// A token for [user] (no '.') that a server configured with
+  /// [QuicConfigHandle.setAuthHmac] and the same [secret] accepts until
+  /// [expires], or forever when omitted.
+  String mintAuthToken(Uint8List secret, String user, {DateTime? expires}) {
+    final secretPtr = calloc<Uint8>(secret.length);
+    secretPtr.asTypedList(secret.length).setAll(0, secret);
+    final userPtr = user.toNativeUtf8();
+    final lenPtr = calloc<UintPtr>();
+    const capacity = 4097;
+    final buf = calloc<Uint8>(capacity);
+    try {
+      final status = _bindings.authMintToken(
+        secretPtr,
+        secret.length,
+        userPtr,
+        (expires?.millisecondsSinceEpoch ?? 0) ~/ 1000,
+        buf.cast(),
+        capacity,
+        lenPtr,
+      );
+      _throwIfError(status, 'auth_mint_token');
+      return utf8.decode(buf.asTypedList(lenPtr.value));
+    } finally {
+      calloc.free(secretPtr);
+      calloc.free(userPtr);
+      calloc.free(lenPtr);
+      calloc.free(buf);
+    }
+  }
+
+  /// Encrypts [key] (e.g. the device private key PEM) under [passphrase]
+  /// for storage at rest, with scrypt and ChaCha20-Poly1305 from the native
+  /// library. The blob is self-describing; open it with [openKey].
+  Uint8List sealKey(Uint8List key, String passphrase) => _keyCrypt(
+    _bindings.keySeal,
+    key,
+    passphrase,
+    key.length + 51,
+    'key_seal',
+  );
+
+  /// Recovers a key sealed with [sealKey]. A wrong [passphrase] or altered
+  /// [blob] throws with [CcQuicStatus.keyOpenFailed].
+  Uint8List openKey(Uint8List blob, String passphrase) =>
+      _keyCrypt(_bindings.keyOpen, blob, passphrase, blob.length, 'key_open');
+
+  Uint8List _keyCrypt(
+    _KeyCryptFn op,
+    Uint8List input,
+    String passphrase,
+    int capacity,
+    String operation,
+  ) {
+    final pass = utf8.encode(passphrase);
+    final inPtr = calloc<Uint8>(max(input.length, 1));
+    final passPtr = calloc<Uint8>(max(pass.length, 1));
+    final outPtr = calloc<Uint8>(max(capacity, 1));
+    final lenPtr = calloc<UintPtr>();
+    try {
+      inPtr.asTypedList(input.length).setAll(0, input);
+      passPtr.asTypedList(pass.length).setAll(0, pass);
+      final status = op(
+        inPtr,
+        input.length,
+        passPtr,
+        pass.length,
+        outPtr,
+        capacity,
+        lenPtr,
+      );
+      _throwIfError(status, operation);
+      return Uint8List.fromList(outPtr.asTypedList(lenPtr.value));
+    } finally {
+      // Plaintext keys and the passphrase should not linger in freed memory.
+      inPtr.asTypedList(input.length).fillRange(0, input.length, 0);
+      passPtr.asTypedList(pass.length).fillRange(0, pass.length, 0);
+      outPtr.asTypedList(capacity).fillRange(0, capacity, 0);
+      calloc
+        ..free(inPtr)
+        ..free(passPtr)
+        ..free(outPtr)
+        ..free(lenPtr);
+    }
+  }
+
+  String? _lastError() {
+    const capacity = 1024;
+    final buf = calloc<Uint8>(capacity);
+    try {
+      final len = _bindings.lastError(buf, capacity);
+      if (len <= 0) return null;
+      return utf8.decode(buf.asTypedList(len));
+    } finally {
+      calloc.free(buf);
+    }
+  }
+
+  /// With [proxyUrl] the connection goes through that proxy, and [host]
+  /// must be an IP address. `https://host[:port]`, optionally with an RFC
+  /// 9298 path template, is a MASQUE proxy, sent [proxyAuth] as
+  /// `proxy-authorization` unless it is empty. `socks5://host[:port]` is a
+  /// SOCKS5 proxy, with [proxyAuth] as `user:password` or empty. A MASQUE
+  /// proxy's certificate is checked against the CAs in [proxyCaPath] (a
+  /// PEM file or directory); without it only desktop Linux has a default
+  /// store to check against.
+  ///
+  /// Without [certPemPath] and [keyPemPath] the client presents no
+  /// certificate, which only a server with
+  /// [QuicConfigHandle.setClientCertOptional] admits.
+  ///
+  /// An empty [expectedServerFingerprint] throws with
+  /// [CcQuicStatus.configError] unless [allowUnpinned] is set. The client
+  /// then trusts any certificate its CA does and posts a
+  /// [QuicVerificationSkipped] before [QuicConnected].
   Future<QuicNativeConnection> startClient({
     required QuicConfigHandle config,
     required String host,
     required int port,
     required String serverName,
     required String expectedServerFingerprint,
-    required String certPemPath,
-    required String keyPemPath,
+    String? certPemPath,
+    String? keyPemPath,
+    String? proxyUrl,
+    String proxyAuth = '',
+    String? proxyCaPath,
+    bool allowUnpinned = false,
   }) async {
     final portStream = ReceivePort();
     final handlePtr = calloc<Uint64>();
     final hostPtr = host.toNativeUtf8();
     final serverPtr = serverName.toNativeUtf8();
     final expectedPtr = expectedServerFingerprint.toNativeUtf8();
-    final certPtr = certPemPath.toNativeUtf8();
-    final keyPtr = keyPemPath.toNativeUtf8();
-    final status = _bindings.clientConnect(
-      config.take(),
-      hostPtr,
-      port,
-      serverPtr,
-      expectedPtr,
-      certPtr,
-      keyPtr,
-      portStream.sendPort.nativePort,
-      handlePtr,
-    );
+    final certPtr = (certPemPath ?? '').toNativeUtf8();
+    final keyPtr = (keyPemPath ?? '').toNativeUtf8();
+    final proxyPtr = (proxyUrl ?? '').toNativeUtf8();
+    final proxyAuthPtr = proxyAuth.toNativeUtf8();
+    final proxyCaPtr = (proxyCaPath ?? '').toNativeUtf8();
+    final flags = allowUnpinned ? _allowUnpinned : 0;
+    final status = proxyUrl == null
+        ? _bindings.clientConnect(
+            config._live(),
+            hostPtr,
+            port,
+            serverPtr,
+            expectedPtr,
+            certPtr,
+            keyPtr,
+            flags,
+            portStream.sendPort.nativePort,
+            handlePtr,
+          )
+        : _bindings.clientConnectViaProxy(
+            config._live(),
+            proxyPtr,
+            proxyAuthPtr,
+            proxyCaPtr,
+            hostPtr,
+            port,
+            serverPtr,
+            expectedPtr,
+            certPtr,
+            keyPtr,
+            flags,
+            portStream.sendPort.nativePort,
+            handlePtr,
+          );
     final handle = handlePtr.value;
     calloc.free(handlePtr);
     calloc
@@ -85,24 +350,69 @@ class CribcallQuic {
       ..free(serverPtr)
       ..free(expectedPtr)
       ..free(certPtr)
-      ..free(keyPtr);
+      ..free(keyPtr)
+      ..free(proxyPtr)
+      ..free(proxyAuthPtr)
+      ..free(proxyCaPtr);
     if (status != CcQuicStatus.ok.code) {
       portStream.close();
-      _throwIfError(status, 'client_connect');
+      throw CribcallQuicException(
+        'client_connect',
+        CcQuicStatus.fromCode(status),
+        _lastError(),
+      );
     }
     final controller = StreamController<QuicEvent>.broadcast();
+    final reverse = StreamController<QuicNativeConnection>.broadcast();
+    // Connections dialed back on a reverse connect request post to this
+    // port too, each under its own handle.
+    final dialed = <int, StreamController<QuicEvent>>{};
     late StreamSubscription sub;
     void cleanup() {
       sub.cancel();
       portStream.close();
       controller.close();
+      reverse.close();
+      // Their events come through this port, so they end with it.
+      for (final entry in dialed.entries) {
+        _bindings.close(entry.key);
+        entry.value.close();
+      }
+      dialed.clear();
     }
 
     sub = portStream.listen((dynamic message) {
       if (message is String) {
         final event = QuicEvent.fromJson(message);
+        final dialBack = dialed.isEmpty ? null : dialed[_eventHandle(message)];
+        if (dialBack != null) {
+          dialBack.add(event);
+          if (event is QuicClosed || event is QuicError) {
+            dialed.removeWhere((_, events) => events == dialBack);
+            dialBack.close();
+          }
+          return;
+        }
         controller.add(event);
-        if (event is QuicClosed || event is QuicError) {
+        final dialedHandle = event is QuicReverseConnect
+            ? event.dialedHandle
+            : null;
+        if (dialedHandle != null) {
+          final events = StreamController<QuicEvent>.broadcast();
+          dialed[dialedHandle] = events;
+          reverse.add(
+            QuicNativeConnection(
+              handle: dialedHandle,
+              events: events.stream,
+              // Never used: the events arrive through [portStream].
+              port: ReceivePort()..close(),
+              bindings: _bindings,
+              onDispose: () => dialed.remove(dialedHandle)?.close(),
+            ),
+          );
+        }
+        if ((event is QuicClosed && event.handle == handle) ||
+            (event is QuicError && event.handle == handle)) {
           cleanup();
         }
       }
@@ -113,9 +423,13 @@ class CribcallQuic {
       port: portStream,
       bindings: _bindings,
       onDispose: cleanup,
+      reverseConnections: reverse.stream,
     );
   }
 
+  /// Each of [trustedFingerprints] is a fingerprint, or `*` for any
+  /// client, with optional `;label=` and `;expires=` (Unix seconds or UTC
+  /// ISO date) attributes; an empty list trusts every client.
   Future<QuicNativeConnection> startServer({
     required QuicConfigHandle config,
     required String bindAddress,
@@ -131,7 +445,7 @@ class CribcallQuic {
     final keyPtr = keyPemPath.toNativeUtf8();
     final trustedPtr = trustedFingerprints.join(',').toNativeUtf8();
     final status = _bindings.serverStart(
-      config.take(),
+      config._live(),
       bindPtr,
       port,
       certPtr,
@@ -149,8 +463,101 @@ class CribcallQuic {
       ..free(trustedPtr);
     if (status != CcQuicStatus.ok.code) {
       portStream.close();
-      _throwIfError(status, 'server_start');
+      throw CribcallQuicException(
+        'server_start',
+        CcQuicStatus.fromCode(status),
+        _lastError(),
+      );
+    }
+    return _serverConnection(handle, portStream, const []);
+  }
+
+  /// Like [startServer], listening on every `host:port` in [bindAddresses]
+  /// (IPv6 as `[addr]:port`) from one native worker and handle. The
+  /// addresses actually bound, with ports picked for port 0, are in
+  /// [QuicNativeConnection.boundAddresses].
+  Future<QuicNativeConnection> startServerMulti({
+    required QuicConfigHandle config,
+    required List<String> bindAddresses,
+    required String certPemPath,
+    required String keyPemPath,
+    List<String> trustedFingerprints = const [],
+  }) async {
+    const boundCapacity = 4096;
+    final portStream = ReceivePort();
+    final handlePtr = calloc<Uint64>();
+    final bindPtr = bindAddresses.join(',').toNativeUtf8();
+    final certPtr = certPemPath.toNativeUtf8();
+    final keyPtr = keyPemPath.toNativeUtf8();
+    final trustedPtr = trustedFingerprints.join(',').toNativeUtf8();
+    final boundPtr = calloc<Uint8>(boundCapacity);
+    final status = _bindings.serverStartMulti(
+      config._live(),
+      bindPtr,
+      certPtr,
+      keyPtr,
+      trustedPtr,
+      portStream.sendPort.nativePort,
+      handlePtr,
+      boundPtr.cast(),
+      boundCapacity,
+    );
+    final handle = handlePtr.value;
+    final bound = status == CcQuicStatus.ok.code
+        ? boundPtr.cast<Utf8>().toDartString().split(',')
+        : const <String>[];
+    calloc
+      ..free(handlePtr)
+      ..free(bindPtr)
+      ..free(certPtr)
+      ..free(keyPtr)
+      ..free(trustedPtr)
+      ..free(boundPtr);
+    if (status != CcQuicStatus.ok.code) {
+      portStream.close();
+      throw CribcallQuicException(
+        'server_start_multi',
+        CcQuicStatus.fromCode(status),
+        _lastError(),
+      );
+    }
+    return _serverConnection(handle, portStream, bound);
+  }
+
+  /// Events of the already started [handle] in [classes] ([QuicEventMask]
+  /// bits), delivered to this isolate alongside the port the handle was
+  /// started with; e.g. a background audio isolate can take just messages
+  /// and media. Cancelling the subscription unsubscribes.
+  Stream<QuicEvent> subscribeEvents(
+    int handle, {
+    int classes = QuicEventMask.all,
+  }) {
+    final port = ReceivePort();
+    final nativePort = port.sendPort.nativePort;
+    final status = _bindings.subscribe(handle, nativePort, classes);
+    if (status != CcQuicStatus.ok.code) {
+      port.close();
+      _throwIfError(status, 'subscribe');
     }
+    final controller = StreamController<QuicEvent>(
+      onCancel: () {
+        _bindings.subscribe(handle, nativePort, 0);
+        port.close();
+      },
+    );
+    port.listen((dynamic message) {
+      if (message is String) {
+        controller.add(QuicEvent.fromJson(message));
+      }
+    });
+    return controller.stream;
+  }
+
+  QuicNativeConnection _serverConnection(
+    int handle,
+    ReceivePort portStream,
+    List<String> boundAddresses,
+  ) {
     final controller = StreamController<QuicEvent>.broadcast();
     late StreamSubscription sub;
     void cleanup() {
@@ -174,6 +581,7 @@ class CribcallQuic {
       port: portStream,
       bindings: _bindings,
       onDispose: cleanup,
+      boundAddresses: boundAddresses,
     );
   }
 }
@@ -185,6 +593,8 @@ class QuicNativeConnection {
     required this.port,
     required this.bindings,
     required this.onDispose,
+    this.boundAddresses = const [],
+    this.reverseConnections = const Stream.empty(),
   }) {
     _subscription = events.listen((event) {
       _trackEvent(event);
@@ -194,6 +604,14 @@ class QuicNativeConnection {
 
   final int handle;
   final ReceivePort port;
+
+  /// Addresses a [CribcallQuic.startServerMulti] server listens on.
+  final List<String> boundAddresses;
+
+  /// Client only: the connections dialed back for the server's connect
+  /// requests (see [QuicConfigHandle.setReverseConnect]). Closing this
+  /// connection closes them too.
+  final Stream<QuicNativeConnection> reverseConnections;
   final _NativeBindings bindings;
   final void Function() onDispose;
   final _controller = StreamController<QuicEvent>.broadcast();
@@ -202,6 +620,27 @@ class QuicNativeConnection {
   String? _lastConnectionId;
   StreamSubscription<QuicEvent>? _subscription;
 
+  /// Calls [call] with the raw bytes of [connectionId] (by default the
+  /// latest connection) copied to native memory for the call.
+  T _withConnId<T>(
+    String? connectionId,
+    String purpose,
+    T Function(Pointer<Uint8> connPtr, int connLen) call,
+  ) {
+    final targetId = connectionId ?? _lastConnectionId;
+    if (targetId == null) {
+      throw StateError('No connection available for $purpose');
+    }
+    final connBytes = _connIdBytes(targetId);
+    final connPtr = calloc<Uint8>(connBytes.length);
+    try {
+      connPtr.asTypedList(connBytes.length).setAll(0, connBytes);
+      return call(connPtr, connBytes.length);
+    } finally {
+      calloc.free(connPtr);
+    }
+  }
+
   void _trackEvent(QuicEvent event) {
     final id = event.connectionId;
     if (id != null) {
@@ -219,127 +658,3032 @@ class QuicNativeConnection {
   }
 
   void send(Uint8List data, {String? connectionId}) {
-    final targetId = connectionId ?? _lastConnectionId;
-    if (targetId == null) {
-      throw StateError('No connection available for send');
+    final dataPtr = calloc<Uint8>(data.length);
+    dataPtr.asTypedList(data.length).setAll(0, data);
+    try {
+      final status = _withConnId(
+        connectionId,
+        'send',
+        (connPtr, connLen) =>
+            bindings.send(handle, connPtr, connLen, dataPtr, data.length),
+      );
+      _throwIfError(status, 'conn_send');
+    } finally {
+      calloc.free(dataPtr);
     }
-    final connBytes = utf8.encode(targetId);
-    final connPtr = calloc<Uint8>(connBytes.length);
-    connPtr.asTypedList(connBytes.length).setAll(0, connBytes);
+  }
+
+  /// Sends [data] reliably on the control stream. Bytes the stream has no
+  /// credit for yet wait natively instead of being cut off; a [QuicError]
+  /// reports a full backlog.
+  void sendControl(Uint8List data, {String? connectionId}) {
     final dataPtr = calloc<Uint8>(data.length);
     dataPtr.asTypedList(data.length).setAll(0, data);
-    bindings.send(handle, connPtr, connBytes.length, dataPtr, data.length);
-    calloc.free(connPtr);
-    calloc.free(dataPtr);
+    try {
+      final status = _withConnId(
+        connectionId,
+        'send',
+        (connPtr, connLen) => bindings.controlSend(
+          handle,
+          connPtr,
+          connLen,
+          dataPtr,
+          data.length,
+        ),
+      );
+      _throwIfError(status, 'control_send');
+    } finally {
+      calloc.free(dataPtr);
+    }
   }
 
-  void close() {
-    bindings.close(handle);
-    port.close();
-    _subscription?.cancel();
-    _controller.close();
-    onDispose();
+  /// Sends [data] as one unreliable media datagram; [timestamp] is in the
+  /// codec clock and [marker] flags e.g. the start of a talkspurt.
+  void sendMedia(
+    Uint8List data, {
+    required int timestamp,
+    bool marker = false,
+    String? connectionId,
+  }) {
+    final dataPtr = calloc<Uint8>(data.length);
+    dataPtr.asTypedList(data.length).setAll(0, data);
+    try {
+      final status = _withConnId(
+        connectionId,
+        'send',
+        (connPtr, connLen) => bindings.mediaSend(
+          handle,
+          connPtr,
+          connLen,
+          timestamp,
+          marker,
+          dataPtr,
+          data.length,
+        ),
+      );
+      _throwIfError(status, 'media_send');
+    } finally {
+      calloc.free(dataPtr);
+    }
   }
-}
 
-class QuicConfigHandle {
-  QuicConfigHandle._(this._pointer, this._bindings);
+  /// Adds [n] - [k] FEC parity datagrams after every [k] media frames (XOR for
+  /// one, Reed-Solomon beyond); [k] == [n] turns FEC off.
+  void setMediaFec({required int k, required int n, String? connectionId}) {
+    final status = _withConnId(
+      connectionId,
+      'FEC',
+      (connPtr, connLen) =>
+          bindings.mediaSetFec(handle, connPtr, connLen, k, n),
+    );
+    _throwIfError(status, 'media_set_fec');
+  }
 
-  Pointer<CcQuicConfig>? _pointer;
-  final _NativeBindings _bindings;
+  /// Sends each media frame [copies] times spread over [spread]; receivers
+  /// drop the extras by sequence. One copy turns it off.
+  void setMediaRedundancy({
+    required int copies,
+    required Duration spread,
+    String? connectionId,
+  }) {
+    final status = _withConnId(
+      connectionId,
+      'redundancy',
+      (connPtr, connLen) => bindings.mediaSetRedundancy(
+        handle,
+        connPtr,
+        connLen,
+        copies,
+        spread.inMilliseconds,
+      ),
+    );
+    _throwIfError(status, 'media_set_redundancy');
+  }
 
-  Pointer<CcQuicConfig> take() {
-    final ptr = _pointer;
-    if (ptr == null) {
-      throw StateError('Config already consumed or freed');
-    }
-    _pointer = null;
-    return ptr;
+  /// Caps everything this side sends on the connection at [maxBps] bits
+  /// per second, on top of congestion control; zero removes the cap.
+  void setRateLimit(int maxBps, {String? connectionId}) {
+    final status = _withConnId(
+      connectionId,
+      'rate limit',
+      (connPtr, connLen) =>
+          bindings.connSetRateLimit(handle, connPtr, connLen, maxBps),
+    );
+    _throwIfError(status, 'conn_set_rate_limit');
   }
 
-  void dispose() {
-    final ptr = _pointer;
-    if (ptr != null) {
-      _bindings.configFree(ptr);
-      _pointer = null;
-    }
+  /// Overrides how [setRateLimit] paces the connection: [enabled] false
+  /// lets it send unthrottled while keeping the rate, and each burst lets
+  /// out at least [quantumBytes] and at most [maxBurstBytes] (zero for one
+  /// 1350-byte packet and 10 ms at the rate). The defaults restore them.
+  void setPacing({
+    bool enabled = true,
+    int quantumBytes = 0,
+    int maxBurstBytes = 0,
+    String? connectionId,
+  }) {
+    final status = _withConnId(
+      connectionId,
+      'pacing',
+      (connPtr, connLen) => bindings.connSetPacing(
+        handle,
+        connPtr,
+        connLen,
+        enabled,
+        quantumBytes,
+        maxBurstBytes,
+      ),
+    );
+    _throwIfError(status, 'conn_set_pacing');
   }
-}
 
-abstract class QuicEvent {
-  const QuicEvent({this.connectionId});
+  /// Tags the connection's events from now on with [tag] (see
+  /// [QuicEvent.tag]) so they can be routed without a map of connection
+  /// ids; `null` removes it. It is dropped after the connection's close.
+  void setTag(int? tag, {String? connectionId}) {
+    final status = _withConnId(
+      connectionId,
+      'tag',
+      (connPtr, connLen) =>
+          bindings.connSetTag(handle, connPtr, connLen, tag ?? 0),
+    );
+    _throwIfError(status, 'conn_set_tag');
+  }
 
-  final String? connectionId;
+  /// Tags this handle's events that no connection tag covers, such as
+  /// `listening` or a client's events before it is connected.
+  void setHandleTag(int? tag) {
+    _throwIfError(
+      bindings.connSetTag(handle, nullptr, 0, tag ?? 0),
+      'conn_set_tag',
+    );
+  }
 
-  factory QuicEvent.fromJson(String raw) {
-    final map = jsonDecode(raw) as Map<String, dynamic>;
-    final connId = map['connection_id'] as String?;
-    switch (map['type'] as String) {
-      case 'connected':
-        return QuicConnected(
-          handle: map['handle'] as int,
-          connectionId: connId,
-          peerFingerprint: map['peer_fingerprint'] as String? ?? '',
-        );
-      case 'message':
-        return QuicMessage(
-          handle: map['handle'] as int,
-          connectionId: connId,
-          data: base64Decode(map['data_base64'] as String),
-        );
-      case 'closed':
-        return QuicClosed(
-          handle: map['handle'] as int,
-          connectionId: connId,
-          reason: map['reason'] as String?,
-        );
-      case 'error':
-      default:
-        return QuicError(
-          handle: map['handle'] as int? ?? 0,
-          connectionId: connId,
-          message: map['message'] as String? ?? 'unknown error',
-        );
+  /// [length] bytes (1 to 8160) of TLS exporter keying material (RFC 8446
+  /// §7.5) for [label] and [context]: both ends of the connection get the
+  /// same bytes and no other connection does, e.g. a key for the clips
+  /// cached from it. Needs [QuicConfigHandle.setKeyingMaterialExport].
+  /// Clear the result once it is no longer needed.
+  Uint8List exportKeyingMaterial(
+    String label, {
+    required int length,
+    Uint8List? context,
+    String? connectionId,
+  }) {
+    RangeError.checkValueInInterval(length, 1, 8160, 'length');
+    final labelPtr = label.toNativeUtf8();
+    final contextLen = context?.length ?? 0;
+    final Pointer<Uint8> contextPtr = contextLen == 0
+        ? nullptr
+        : calloc<Uint8>(contextLen);
+    if (contextLen > 0) contextPtr.asTypedList(contextLen).setAll(0, context!);
+    final outPtr = calloc<Uint8>(length);
+    try {
+      _throwIfError(
+        _withConnId(
+          connectionId,
+          'export',
+          (connPtr, connLen) => bindings.connExportKeyingMaterial(
+            handle,
+            connPtr,
+            connLen,
+            labelPtr,
+            contextPtr,
+            contextLen,
+            outPtr,
+            length,
+          ),
+        ),
+        'conn_export_keying_material',
+      );
+      return Uint8List.fromList(outPtr.asTypedList(length));
+    } finally {
+      outPtr.asTypedList(length).fillRange(0, length, 0);
+      calloc
+        ..free(labelPtr)
+        ..free(outPtr);
+      if (contextPtr != nullptr) calloc.free(contextPtr);
     }
   }
-}
 
-class QuicConnected extends QuicEvent {
-  const QuicConnected({
-    required this.handle,
-    required this.peerFingerprint,
+  /// Writes every datagram sent or received on the connection from now on,
+  /// still encrypted, to [path] as pcapng for Wireshark, until the file
+  /// would pass [maxBytes] (16 MiB when 0). A `null` [path] stops the
+  /// capture.
+  void capturePackets(String? path, {int maxBytes = 0, String? connectionId}) {
+    final pathPtr = path?.toNativeUtf8() ?? nullptr;
+    try {
+      final status = _withConnId(
+        connectionId,
+        'capture',
+        (connPtr, connLen) =>
+            bindings.connCapture(handle, connPtr, connLen, pathPtr, maxBytes),
+      );
+      _throwIfError(status, 'conn_capture');
+    } finally {
+      if (pathPtr != nullptr) calloc.free(pathPtr);
+    }
+  }
+
+  /// Test hook: impairs what this side sends on the connection, dropping
+  /// [lossPct] percent of packets, holding [reorderPct] percent back, adding
+  /// [latencyMs] give or take [jitterMs] and queueing behind a
+  /// [bandwidthKbps] link. A non-zero [seed] makes runs repeatable; all
+  /// zero removes the impairment. Needs a native build with the
+  /// `impairment` feature.
+  void setImpairment({
+    double lossPct = 0,
+    double reorderPct = 0,
+    int latencyMs = 0,
+    int jitterMs = 0,
+    int bandwidthKbps = 0,
+    int seed = 0,
     String? connectionId,
-  }) : super(connectionId: connectionId);
+  }) {
+    final setImpairment = bindings.connSetImpairment;
+    if (setImpairment == null) {
+      throw UnsupportedError('Native library built without impairment');
+    }
+    final status = _withConnId(
+      connectionId,
+      'impairment',
+      (connPtr, connLen) => setImpairment(
+        handle,
+        connPtr,
+        connLen,
+        lossPct,
+        reorderPct,
+        latencyMs,
+        jitterMs,
+        bandwidthKbps,
+        seed,
+      ),
+    );
+    _throwIfError(status, 'conn_set_impairment');
+  }
 
-  final int handle;
-  final String peerFingerprint;
-}
+  /// Opens channel [name] on its own stream; both sides get a
+  /// [QuicChannelOpened] once the peer accepts, and [QuicMessage]s sent on
+  /// it carry the name in [QuicMessage.channel].
+  void openChannel(String name, {String? connectionId}) {
+    _channelCommand(name, connectionId, 'channel_open', bindings.channelOpen);
+  }
 
-class QuicMessage extends QuicEvent {
-  const QuicMessage({
-    required this.handle,
-    required this.data,
-    String? connectionId,
-  }) : super(connectionId: connectionId);
+  /// Sends [data] on open channel [name].
+  void sendChannel(String name, Uint8List data, {String? connectionId}) {
+    final dataPtr = calloc<Uint8>(data.length);
+    dataPtr.asTypedList(data.length).setAll(0, data);
+    try {
+      _channelCommand(
+        name,
+        connectionId,
+        'channel_send',
+        (handle, connPtr, connLen, namePtr) => bindings.channelSend(
+          handle,
+          connPtr,
+          connLen,
+          namePtr,
+          dataPtr,
+          data.length,
+        ),
+      );
+    } finally {
+      calloc.free(dataPtr);
+    }
+  }
 
-  final int handle;
-  final Uint8List data;
-}
+  /// Closes channel [name]; the peer gets a [QuicChannelClosed].
+  void closeChannel(String name, {String? connectionId}) {
+    _channelCommand(name, connectionId, 'channel_close', bindings.channelClose);
+  }
 
-class QuicClosed extends QuicEvent {
-  const QuicClosed({required this.handle, this.reason, String? connectionId})
-    : super(connectionId: connectionId);
+  /// Resets channel stream [streamId] (from [QuicChannelOpened.streamId]):
+  /// what has not been delivered yet is dropped and the peer gets a
+  /// [QuicStreamReset] carrying [errorCode]. The channel ends.
+  void resetStream(int streamId, {int errorCode = 0, String? connectionId}) {
+    _streamAbort(
+      streamId,
+      errorCode,
+      connectionId,
+      'stream_reset',
+      bindings.streamReset,
+    );
+  }
 
-  final int handle;
-  final String? reason;
-}
+  /// Asks the peer to stop sending on channel stream [streamId], for
+  /// example to cancel a download: it drops the rest and gets a
+  /// [QuicStreamStopped] carrying [errorCode]. The channel still carries
+  /// what this side sends until [closeChannel].
+  void stopSending(int streamId, {int errorCode = 0, String? connectionId}) {
+    _streamAbort(
+      streamId,
+      errorCode,
+      connectionId,
+      'stream_stop_sending',
+      bindings.streamStopSending,
+    );
+  }
+
+  void _streamAbort(
+    int streamId,
+    int errorCode,
+    String? connectionId,
+    String op,
+    int Function(int, Pointer<Uint8>, int, int, int) call,
+  ) {
+    final status = _withConnId(
+      connectionId,
+      'stream abort',
+      (connPtr, connLen) => call(handle, connPtr, connLen, streamId, errorCode),
+    );
+    _throwIfError(status, op);
+  }
+
+  void _channelCommand(
+    String name,
+    String? connectionId,
+    String op,
+    int Function(int, Pointer<Uint8>, int, Pointer<Utf8>) call,
+  ) {
+    final namePtr = name.toNativeUtf8();
+    try {
+      final status = _withConnId(
+        connectionId,
+        'channel',
+        (connPtr, connLen) => call(handle, connPtr, connLen, namePtr),
+      );
+      _throwIfError(status, op);
+    } finally {
+      calloc.free(namePtr);
+    }
+  }
+
+  /// Probes the peer's clock every [interval], producing [QuicTimeSync]
+  /// events; [Duration.zero] stops probing.
+  void startTimeSync({required Duration interval, String? connectionId}) {
+    final status = _withConnId(
+      connectionId,
+      'time sync',
+      (connPtr, connLen) => bindings.timesyncStart(
+        handle,
+        connPtr,
+        connLen,
+        interval.inMilliseconds,
+      ),
+    );
+    _throwIfError(status, 'timesync_start');
+  }
+
+  /// Switches to the certificate and key at [certPemPath]/[keyPemPath]: a
+  /// server presents them from the next handshake, and every connected peer
+  /// gets a [QuicPeerIdentityRotated] with the new fingerprint so it can
+  /// re-pin. A client only announces; reconnect to use the new identity.
+  void rotateIdentity({
+    required String certPemPath,
+    required String keyPemPath,
+  }) {
+    final certPtr = certPemPath.toNativeUtf8();
+    final keyPtr = keyPemPath.toNativeUtf8();
+    final status = bindings.identityRotate(handle, certPtr, keyPtr);
+    calloc
+      ..free(certPtr)
+      ..free(keyPtr);
+    _throwIfError(status, 'identity_rotate');
+  }
+
+  /// Server only: drops every datagram from [cidr] (`addr/prefix`, or a
+  /// bare address) before any QUIC processing. Open connections from the
+  /// range fall silent and time out. See [QuicStats.blocklist].
+  void blockAddress(String cidr) => _editBlocklist(cidr, block: true);
+
+  /// Lifts a block added with [blockAddress].
+  void unblockAddress(String cidr) => _editBlocklist(cidr, block: false);
+
+  void _editBlocklist(String cidr, {required bool block}) {
+    final cidrPtr = cidr.toNativeUtf8();
+    final status = block
+        ? bindings.serverBlockAddr(handle, cidrPtr)
+        : bindings.serverUnblockAddr(handle, cidrPtr);
+    calloc.free(cidrPtr);
+    _throwIfError(status, block ? 'server_block_addr' : 'server_unblock_addr');
+  }
+
+  /// Server only: steers clients to [address] (`ip:port`), one of the
+  /// addresses this server listens on. Each client validates a path to it
+  /// and moves its connection over without a new handshake, posting
+  /// [QuicServerRelocated]. Null stops announcing.
+  void setPreferredAddress(String? address) {
+    final addrPtr = address == null ? nullptr : address.toNativeUtf8();
+    final status = bindings.serverSetPreferredAddress(handle, addrPtr);
+    if (addrPtr != nullptr) calloc.free(addrPtr);
+    _throwIfError(status, 'server_set_preferred_address');
+  }
+
+  /// Server only: asks the client on [connectionId], in reverse connect
+  /// mode ([QuicConfigHandle.setReverseConnect]), to dial a new connection
+  /// back to [address] (`ip:port`), or else to the address it reached this
+  /// server on. [pin] names the certificate to expect there and must be one
+  /// of the client's pins; null means this server's. The client answers
+  /// with [QuicReverseConnect]; one without the mode ignores the request.
+  void requestConnect(String connectionId, {String? address, String? pin}) {
+    final addrPtr = address == null ? nullptr : address.toNativeUtf8();
+    final pinPtr = pin == null ? nullptr : pin.toNativeUtf8();
+    final status = _withConnId(
+      connectionId,
+      'connect request',
+      (connPtr, connLen) => bindings.serverRequestConnect(
+        handle,
+        connPtr,
+        connLen,
+        addrPtr,
+        pinPtr,
+      ),
+    );
+    if (addrPtr != nullptr) calloc.free(addrPtr);
+    if (pinPtr != nullptr) calloc.free(pinPtr);
+    _throwIfError(status, 'server_request_connect');
+  }
+
+  /// Server only: trusts the fingerprints in the file at [path] instead of
+  /// the list given at start, one per line or comma separated with `#`
+  /// comments, and reloads it whenever it changes ([QuicAllowlistReloaded]).
+  /// Clients no longer listed are disconnected; an empty file trusts no
+  /// one. Null stops watching and keeps the current list.
+  void setAllowlistFile(String? path) {
+    final pathPtr = path == null ? nullptr : path.toNativeUtf8();
+    final status = bindings.serverSetAllowlistFile(handle, pathPtr);
+    if (pathPtr != nullptr) calloc.free(pathPtr);
+    _throwIfAllowlistError(status, 'server_set_allowlist_file');
+  }
+
+  /// Writes a JSON diagnostics bundle to [path] for a bug report: worker
+  /// options, connections and their counters, per-peer usage, recent events
+  /// (without payloads) and recent native log lines.
+  void dumpDiagnostics(String path) {
+    final pathPtr = path.toNativeUtf8();
+    final status = bindings.diagnosticsDump(handle, pathPtr);
+    calloc.free(pathPtr);
+    _throwIfError(status, 'diagnostics_dump');
+  }
+
+  /// Server only: reads the allowlist file again now.
+  void reloadAllowlist() {
+    _throwIfAllowlistError(
+      bindings.serverReloadAllowlist(handle),
+      'server_reload_allowlist',
+    );
+  }
+
+  void _throwIfAllowlistError(int status, String op) {
+    final parsed = CcQuicStatus.fromCode(status);
+    if (parsed == CcQuicStatus.ok) return;
+    const capacity = 1024;
+    final buf = calloc<Uint8>(capacity);
+    try {
+      final len = bindings.lastError(buf, capacity);
+      final detail = len > 0 ? utf8.decode(buf.asTypedList(len)) : null;
+      throw CribcallQuicException(op, parsed, detail);
+    } finally {
+      calloc.free(buf);
+    }
+  }
+
+  /// Server only: shows the peer with SHA-256 [fingerprint] as [label] on
+  /// the roster (see [QuicConfigHandle.setRoster]); null clears it. Labels
+  /// may be set before the peer connects.
+  void setPeerLabel(String fingerprint, String? label) {
+    final fpPtr = fingerprint.toNativeUtf8();
+    final labelPtr = label == null ? nullptr : label.toNativeUtf8();
+    final status = bindings.serverSetPeerLabel(handle, fpPtr, labelPtr);
+    calloc.free(fpPtr);
+    if (labelPtr != nullptr) calloc.free(labelPtr);
+    _throwIfError(status, 'server_set_peer_label');
+  }
+
+  /// Server only: answers a [QuicAuthRequest]. An accepted connection goes
+  /// on to [QuicConnected] with [QuicConnected.user] set to [user]; a
+  /// refused one is closed.
+  void authVerdict(String connectionId, bool accept, {String? user}) {
+    final userPtr = user == null ? nullptr : user.toNativeUtf8();
+    final status = _withConnId(
+      connectionId,
+      'auth verdict',
+      (connPtr, connLen) => bindings.serverAuthVerdict(
+        handle,
+        connPtr,
+        connLen,
+        accept,
+        userPtr,
+      ),
+    );
+    if (userPtr != nullptr) calloc.free(userPtr);
+    _throwIfError(status, 'server_auth_verdict');
+  }
+
+  /// Server only: answers a [QuicConnectionPending]; an accepted client
+  /// goes on to [QuicConnected], a refused one is closed.
+  void decide(String connectionId, bool accept) {
+    final status = _withConnId(
+      connectionId,
+      'decision',
+      (connPtr, connLen) =>
+          bindings.serverDecide(handle, connPtr, connLen, accept),
+    );
+    _throwIfError(status, 'server_decide');
+  }
+
+  /// Server only: refuses [user] from now on and closes their open
+  /// connections; [revoked] false lets them back in.
+  void revokeUser(String user, {bool revoked = true}) {
+    final userPtr = user.toNativeUtf8();
+    final status = bindings.serverRevokeUser(handle, userPtr, revoked);
+    calloc.free(userPtr);
+    _throwIfError(status, 'server_revoke_user');
+  }
+
+  /// Client only: subscribes to server [topic]; publications arrive as
+  /// [QuicMessage]s with [QuicMessage.topic] set. May be called before the
+  /// connection is established.
+  void subscribe(String topic) => _topicCommand(topic, true);
+
+  /// Client only: stops publications on [topic].
+  void unsubscribe(String topic) => _topicCommand(topic, false);
+
+  void _topicCommand(String topic, bool subscribe) {
+    final topicPtr = topic.toNativeUtf8();
+    final status = subscribe
+        ? bindings.topicSubscribe(handle, topicPtr)
+        : bindings.topicUnsubscribe(handle, topicPtr);
+    calloc.free(topicPtr);
+    _throwIfError(status, subscribe ? 'topic_subscribe' : 'topic_unsubscribe');
+  }
+
+  /// Server only: sends [data] to every client subscribed to [topic]
+  /// (see [QuicSubscription]), whichever worker holds its connection.
+  void publish(String topic, Uint8List data) {
+    final topicPtr = topic.toNativeUtf8();
+    final dataPtr = calloc<Uint8>(data.length);
+    dataPtr.asTypedList(data.length).setAll(0, data);
+    final status = bindings.serverPublish(
+      handle,
+      topicPtr,
+      dataPtr,
+      data.length,
+    );
+    calloc.free(topicPtr);
+    calloc.free(dataPtr);
+    _throwIfError(status, 'server_publish');
+  }
+
+  /// Bytes moved with each peer fingerprint over every connection of this
+  /// handle so far; open connections are at most a second behind.
+  List<QuicPeerUsage> usageStats() {
+    final lenPtr = calloc<UintPtr>();
+    var capacity = 4096;
+    try {
+      while (true) {
+        final buf = calloc<Uint8>(capacity);
+        try {
+          final status = bindings.usageStats(handle, buf, capacity, lenPtr);
+          if (status == CcQuicStatus.configError.code &&
+              lenPtr.value >= capacity) {
+            capacity = lenPtr.value + 1;
+            continue;
+          }
+          _throwIfError(status, 'usage_stats');
+          final list =
+              jsonDecode(utf8.decode(buf.asTypedList(lenPtr.value)))
+                  as List<dynamic>;
+          return list
+              .map((e) => QuicPeerUsage.fromJson(e as Map<String, dynamic>))
+              .toList();
+        } finally {
+          calloc.free(buf);
+        }
+      }
+    } finally {
+      calloc.free(lenPtr);
+    }
+  }
+
+  /// Requests a scheduling class for the native thread(s) behind this handle.
+  /// [QuicThreadPriority.realtime] typically needs elevated privileges.
+  void setThreadPriority(QuicThreadPriority priority) {
+    _throwIfError(
+      bindings.setThreadPriority(handle, priority.index),
+      'set_thread_priority',
+    );
+  }
+
+  void close() {
+    bindings.close(handle);
+    port.close();
+    _subscription?.cancel();
+    _controller.close();
+    onDispose();
+  }
+}
+
+/// Mirrors `CC_QUIC_THREAD_PRIORITY_*`; the index is the native code.
+/// One peer of a connection seen as two traffic classes: reliable control
+/// messages on stream 0 and unreliable media datagrams, budgeted natively
+/// (see [QuicConfigHandle.setDualChannel]) so neither blocks the other.
+class QuicDualChannel {
+  QuicDualChannel(this.connection, this.connectionId);
+
+  final QuicNativeConnection connection;
+  final String connectionId;
+
+  Stream<QuicMessage> get control => connection.events
+      .where((event) => event.connectionId == connectionId)
+      .where((event) => event is QuicMessage)
+      .cast<QuicMessage>();
+
+  Stream<QuicMedia> get media => connection.events
+      .where((event) => event.connectionId == connectionId)
+      .where((event) => event is QuicMedia)
+      .cast<QuicMedia>();
+
+  void sendControl(Uint8List data) =>
+      connection.sendControl(data, connectionId: connectionId);
+
+  void sendMedia(
+    Uint8List data, {
+    required int timestamp,
+    bool marker = false,
+  }) => connection.sendMedia(
+    data,
+    timestamp: timestamp,
+    marker: marker,
+    connectionId: connectionId,
+  );
+}
+
+enum QuicThreadPriority { normal, audio, realtime }
+
+/// Event classes for [CribcallQuic.subscribeEvents]; mirrors
+/// `CC_QUIC_EVENTS_*`.
+abstract final class QuicEventMask {
+  /// Connected, closed, handshake progress, addresses, auth and roster.
+  static const connection = 1 << 0;
+
+  /// Messages, including channel and topic data.
+  static const message = 1 << 1;
+  static const media = 1 << 2;
+
+  /// Channel opens and closes and stream resets, stops and credit.
+  static const channel = 1 << 3;
+  static const stats = 1 << 4;
+
+  /// Errors, dropped events, worker health and certificate expiry.
+  static const health = 1 << 5;
+  static const all = (1 << 6) - 1;
+}
+
+/// Tuned starting points for [CribcallQuic.createConfig]: idle timeout,
+/// flow windows, congestion control, datagram queues and keepalive.
+enum QuicPreset { lanLowLatency, cellularRemote, bulkTransfer }
+
+/// Which received datagram is discarded when the receive queue is full.
+enum QuicDgramDropPolicy { front, back }
+
+/// Control-frame compression; [zstd] is reserved and not built in yet.
+enum QuicCompression { off, deflate, zstd }
+
+/// Which receive cap a [QuicMessageTooLarge] hit.
+enum QuicRecvLimitScope { message, connection }
+
+/// One connection attempt from [CribcallQuic.queryAudit].
+class QuicAuditRecord {
+  const QuicAuditRecord({
+    required this.timestamp,
+    required this.handle,
+    required this.role,
+    required this.outcome,
+    required this.connectionId,
+    required this.fingerprint,
+    this.remoteAddress,
+    this.reason,
+  });
+
+  factory QuicAuditRecord.fromJson(String line) {
+    final map = jsonDecode(line) as Map<String, dynamic>;
+    return QuicAuditRecord(
+      timestamp: DateTime.fromMillisecondsSinceEpoch(map['ts_ms'] as int),
+      handle: map['handle'] as int,
+      role: map['role'] as String,
+      outcome: QuicAuditOutcome.values.byName(map['outcome'] as String),
+      connectionId: map['connection_id'] as String,
+      fingerprint: map['fingerprint'] as String,
+      remoteAddress: map['remote'] as String?,
+      reason: map['reason'] as String?,
+    );
+  }
+
+  final DateTime timestamp;
+  final int handle;
+
+  /// `client` or `server`: which end of the connection this process was.
+  final String role;
+  final QuicAuditOutcome outcome;
+  final String connectionId;
+  final String fingerprint;
+  final String? remoteAddress;
+  final String? reason;
+}
+
+enum QuicAuditOutcome { accepted, rejected, closed }
+
+/// Traffic with one peer from [QuicNativeConnection.usageStats].
+class QuicPeerUsage {
+  const QuicPeerUsage({
+    required this.fingerprint,
+    required this.sentBytes,
+    required this.recvBytes,
+    required this.connections,
+  });
+
+  factory QuicPeerUsage.fromJson(Map<String, dynamic> map) => QuicPeerUsage(
+    fingerprint: map['fingerprint'] as String,
+    sentBytes: map['sent_bytes'] as int,
+    recvBytes: map['recv_bytes'] as int,
+    connections: map['connections'] as int,
+  );
+
+  final String fingerprint;
+  final int sentBytes;
+  final int recvBytes;
+
+  /// Connections counted, including any still open.
+  final int connections;
+}
+
+/// Whose certificate a [QuicCertExpiringSoon] is about.
+enum QuicCertSide { local, peer }
+
+/// Which limit a [QuicConnectionReaped] enforced.
+enum QuicReapReason { handshake, lifetime }
+
+/// How far a handshake got, in the order a [QuicHandshakeProgress] reports
+/// them: our first packet sent, handshake keys, 1-RTT keys (the TLS
+/// handshake completed here), and confirmed by both sides.
+enum QuicHandshakeStage {
+  initialSent('initial_sent'),
+  handshakeKeys('handshake_keys'),
+  oneRttKeys('one_rtt_keys'),
+  confirmed('confirmed');
+
+  const QuicHandshakeStage(this.wireName);
+
+  final String wireName;
+
+  static QuicHandshakeStage fromWire(String name) =>
+      values.firstWhere((stage) => stage.wireName == name);
+}
+
+/// A feature offered in the capability exchange (see
+/// [QuicConfigHandle.setCapabilities]).
+enum QuicCapability {
+  framingV2('framing_v2', 1),
+
+  /// Deflate on the control stream; only offered when the connection
+  /// negotiated it.
+  compression('compression', 2),
+
+  /// Media over datagrams; only offered when both sides enabled them.
+  mediaDatagrams('media_datagrams', 4),
+  fec('fec', 8);
+
+  const QuicCapability(this.wireName, this.bit);
+
+  final String wireName;
+  final int bit;
+
+  static Set<QuicCapability> fromWire(List<dynamic> names) => {
+    for (final name in names) values.firstWhere((c) => c.wireName == name),
+  };
+}
+
+/// Why a connection closed before it was announced; see
+/// [QuicHandshakeFailed].
+enum QuicHandshakeFailure {
+  /// Nothing came back: likely a firewall, a wrong address or port, or no
+  /// server running.
+  noResponse('no_response'),
+  versionMismatch('version_mismatch'),
+  tlsAlert('tls_alert'),
+
+  /// One side could not parse or verify the other's certificate.
+  certInvalid('cert_invalid'),
+
+  /// A valid certificate that was not the pinned or allowlisted one.
+  fingerprintMismatch('fingerprint_mismatch'),
+
+  /// The server hit its anti-amplification limit and the client went
+  /// quiet.
+  amplificationLimited('amplification_limited'),
+  timeout('timeout'),
+  other('other');
+
+  const QuicHandshakeFailure(this.wireName);
+
+  final String wireName;
+
+  static QuicHandshakeFailure fromWire(String name) => values.firstWhere(
+    (cause) => cause.wireName == name,
+    orElse: () => QuicHandshakeFailure.other,
+  );
+}
+
+/// Native config. Starting a client or server reads it without taking it,
+/// so one handle can back many connections; [dispose] it when done.
+class QuicConfigHandle {
+  QuicConfigHandle._(this._pointer, this._bindings);
+
+  Pointer<CcQuicConfig>? _pointer;
+  final _NativeBindings _bindings;
+
+  /// Count the ECN codepoints of received datagrams into [QuicStats]
+  /// (Linux/Android). Sends are never marked, as quiche does not react to
+  /// congestion marks.
+  void setEcn(bool enabled) {
+    _throwIfError(_bindings.configSetEcn(_live(), enabled), 'config_set_ecn');
+  }
+
+  /// Include the peer's DER certificate in [QuicConnected.peerCertificate]
+  /// (off by default to keep events small).
+  void setPeerCertExport(bool enabled) {
+    _throwIfError(
+      _bindings.configSetPeerCertExport(_live(), enabled),
+      'config_set_peer_cert_export',
+    );
+  }
+
+  /// Keep each connection's TLS exporter secret for
+  /// [QuicNativeConnection.exportKeyingMaterial]. Off by default, so the
+  /// secret never leaves quiche.
+  void setKeyingMaterialExport(bool enabled) {
+    _throwIfError(
+      _bindings.configSetKeyingMaterialExport(_live(), enabled),
+      'config_set_keying_material_export',
+    );
+  }
+
+  /// Post a [QuicStreamWritable] whenever an open channel's stream gains
+  /// send credit, so a large transfer can send what the stream takes
+  /// instead of fixed-size chunks. Off by default.
+  void setWritableEvents(bool enabled) {
+    _throwIfError(
+      _bindings.configSetWritableEvents(_live(), enabled),
+      'config_set_writable_events',
+    );
+  }
+
+  /// Server only: tell each client the address its packets arrive from, as
+  /// a [QuicObservedAddress] on the client side. Clients without support
+  /// would see the reports as messages, so this is off by default.
+  void setObservedAddress(bool enabled) {
+    _throwIfError(
+      _bindings.configSetObservedAddress(_live(), enabled),
+      'config_set_observed_address',
+    );
+  }
+
+  /// Server only: keep every client up to date with who else is connected
+  /// (labels from [QuicNativeConnection.setPeerLabel]); both sides get
+  /// [QuicRosterChanged]. Clients without support would see the roster as
+  /// messages, so this is off by default.
+  void setRoster(bool enabled) {
+    _throwIfError(
+      _bindings.configSetRoster(_live(), enabled),
+      'config_set_roster',
+    );
+  }
+
+  /// Offer [features] to each peer once connected; both sides get a
+  /// [QuicCapabilitiesNegotiated] when the peer's offer is in. Peers
+  /// without support would see the offer as a message, so an empty set,
+  /// the default, sends none.
+  void setCapabilities(Set<QuicCapability> features) {
+    _throwIfError(
+      _bindings.configSetCapabilities(
+        _live(),
+        features.fold(0, (bits, feature) => bits | feature.bit),
+      ),
+      'config_set_capabilities',
+    );
+  }
+
+  /// Client only: presents [token] (see [CribcallQuic.mintAuthToken]) to
+  /// servers that require one, once their certificate passes the pin
+  /// check; null clears it.
+  void setAuthToken(String? token) {
+    final tokenPtr = token == null ? nullptr : token.toNativeUtf8();
+    final status = _bindings.configSetAuthToken(_live(), tokenPtr);
+    if (tokenPtr != nullptr) calloc.free(tokenPtr);
+    _throwIfError(status, 'config_set_auth_token');
+  }
+
+  /// Server only: admits only clients with a token minted under [secret];
+  /// [QuicConnected.user] names the token's user. Null turns the check off.
+  void setAuthHmac(Uint8List? secret) {
+    final length = secret?.length ?? 0;
+    final secretPtr = length == 0 ? nullptr : calloc<Uint8>(length);
+    if (secret != null && length > 0) {
+      secretPtr.asTypedList(length).setAll(0, secret);
+    }
+    final status = _bindings.configSetAuthHmac(_live(), secretPtr, length);
+    if (secretPtr != nullptr) calloc.free(secretPtr);
+    _throwIfError(status, 'config_set_auth_hmac');
+  }
+
+  /// Server only: holds every connection until the app answers its
+  /// [QuicAuthRequest] with [QuicNativeConnection.authVerdict], or closes
+  /// it after 10 seconds.
+  void setAuthCallback(bool enabled) {
+    _throwIfError(
+      _bindings.configSetAuthCallback(_live(), enabled),
+      'config_set_auth_callback',
+    );
+  }
+
+  /// Server only: admits clients without a certificate on their token
+  /// alone, with a null [QuicConnected.peerFingerprint]; clients with one
+  /// still need an allowlist entry. Needs [setAuthHmac] or
+  /// [setAuthCallback] before the server starts. Off by default, which
+  /// refuses clients without a certificate.
+  void setClientCertOptional(bool enabled) {
+    _throwIfError(
+      _bindings.configSetClientCertOptional(_live(), enabled),
+      'config_set_client_cert_optional',
+    );
+  }
+
+  /// Server only: fails the handshake of a client that presents no
+  /// certificate with the TLS `certificate_required` alert, before the
+  /// allowlist or token is looked at. Cannot be combined with
+  /// [setClientCertOptional]; the server start fails if both are on.
+  void setRequireClientCert(bool enabled) {
+    _throwIfError(
+      _bindings.configSetRequireClientCert(_live(), enabled),
+      'config_set_require_client_cert',
+    );
+  }
+
+  /// Server only: asks the app about every client that passed the other
+  /// checks with a [QuicConnectionPending], holding it until
+  /// [QuicNativeConnection.decide] answers or [timeout] passes. Nothing the
+  /// client sends arrives before then. [Duration.zero] turns this off.
+  void setConnectionApproval(Duration timeout) {
+    _throwIfError(
+      _bindings.configSetConnectionApproval(_live(), timeout.inMilliseconds),
+      'config_set_connection_approval',
+    );
+  }
+
+  /// Server only: presents [certPemPath] to clients whose SNI is
+  /// [serverName] and admits them by [trustedFingerprints] (as
+  /// [CribcallQuic.startServer] reads them) instead of the server's own
+  /// list, so one port can serve several identities. Clients asking for
+  /// another name, or none, get the default identity.
+  void addSniIdentity(
+    String serverName, {
+    required String certPemPath,
+    required String keyPemPath,
+    List<String> trustedFingerprints = const [],
+  }) {
+    final namePtr = serverName.toNativeUtf8();
+    final certPtr = certPemPath.toNativeUtf8();
+    final keyPtr = keyPemPath.toNativeUtf8();
+    final trustedPtr = trustedFingerprints.join(',').toNativeUtf8();
+    final status = _bindings.configAddSniIdentity(
+      _live(),
+      namePtr,
+      certPtr,
+      keyPtr,
+      trustedPtr,
+    );
+    calloc
+      ..free(namePtr)
+      ..free(certPtr)
+      ..free(keyPtr)
+      ..free(trustedPtr);
+    _throwIfError(status, 'config_add_sni_identity');
+  }
+
+  /// Client only: a [CribcallQuic.startClient] pinned to a fingerprint that
+  /// already has an established connection joins it instead of dialling,
+  /// getting the same handle and a replayed [QuicConnected]. The connection
+  /// closes once every holder has closed it.
+  void setCoalesce(bool enabled) {
+    _throwIfError(
+      _bindings.configSetCoalesce(_live(), enabled),
+      'config_set_coalesce',
+    );
+  }
+
+  /// Client only: a reconnect to the same server within [maxAge] starts
+  /// with the last connection's congestion window (up to 64 packets)
+  /// instead of ramping up from scratch. [Duration.zero] turns it off.
+  void setCwndResume(Duration maxAge) {
+    _throwIfError(
+      _bindings.configSetCwndResume(_live(), maxAge.inMilliseconds),
+      'config_set_cwnd_resume',
+    );
+  }
+
+  /// Client only: reverse connect mode, for a monitor whose router nothing
+  /// can dial through. Its connection to the listener or a relay becomes a
+  /// signaling connection: when that server calls
+  /// [QuicNativeConnection.requestConnect], the client dials a new
+  /// connection back with the same settings and identity, emits
+  /// [QuicReverseConnect] and hands the new connection to
+  /// [QuicNativeConnection.reverseConnections]. It pins the signaling
+  /// server's fingerprint, or one of [pins] when the request names it.
+  void setReverseConnect(bool enabled, {List<String> pins = const []}) {
+    final pinsPtr = pins.join(',').toNativeUtf8();
+    final status = _bindings.configSetReverseConnect(
+      _live(),
+      enabled,
+      pinsPtr,
+    );
+    calloc.free(pinsPtr);
+    _throwIfError(status, 'config_set_reverse_connect');
+  }
+
+  /// Carry QUIC over TCP [port] (443 gets through most firewalls) for
+  /// networks that drop UDP; 0 turns it off. A server also listens on
+  /// [port] over TCP and emits a second [QuicListening] for it. A client
+  /// that hears nothing over UDP within [udpTimeout] (3 s when zero), or
+  /// finds the UDP port unreachable, emits [QuicHandshakeFailed] and
+  /// [QuicTransportFallback] for that attempt and handshakes again through
+  /// the tunnel, with the same pinning and events.
+  void setTcpFallback(int port, {Duration udpTimeout = Duration.zero}) {
+    _throwIfError(
+      _bindings.configSetTcpFallback(_live(), port, udpTimeout.inMilliseconds),
+      'config_set_tcp_fallback',
+    );
+  }
+
+  /// Also verify peers against the CAs in [path], a PEM file or a
+  /// directory of hashed PEM names, for certificates from a private CA.
+  /// Null trusts the system store only.
+  void setCaPath(String? path) {
+    final pathPtr = path == null ? nullptr : path.toNativeUtf8();
+    final status = _bindings.configSetCaPath(_live(), pathPtr);
+    if (pathPtr != nullptr) calloc.free(pathPtr);
+    _throwIfError(status, 'config_set_ca_path');
+  }
+
+  /// Emit [QuicCertExpiringSoon] for certificates expiring within [days]
+  /// (30 by default); 0 turns the warnings off. Starting with an expired
+  /// local certificate throws [CcQuicStatus.certExpired] regardless.
+  void setCertExpiryWarning(int days) {
+    _throwIfError(
+      _bindings.configSetCertExpiryWarning(_live(), days),
+      'config_set_cert_expiry_warning',
+    );
+  }
+
+  /// Path MTU discovery: when [enabled], packets start at 1200 bytes and
+  /// probe up to [maxUdpPayload]; otherwise they go straight to it. Zero
+  /// keeps the 1350-byte default. [QuicStats.pmtu] shows the result.
+  void setPmtuDiscovery(bool enabled, {int maxUdpPayload = 0}) {
+    _throwIfError(
+      _bindings.configSetPmtuDiscovery(_live(), enabled, maxUdpPayload),
+      'config_set_pmtu_discovery',
+    );
+  }
+
+  /// Toggles UDP GSO/GRO offload (enabled by default where supported).
+  void setUdpOffload(bool enabled) {
+    _throwIfError(
+      _bindings.configSetUdpOffload(_live(), enabled),
+      'config_set_udp_offload',
+    );
+  }
+
+  /// HyStart++ (on by default) ends slow start when delay rises rather than
+  /// at the first loss. Off ramps up faster after idle periods, such as an
+  /// unmute, at the risk of overshooting shallow queues.
+  void setHystart(bool enabled) {
+    _throwIfError(
+      _bindings.configSetHystart(_live(), enabled),
+      'config_set_hystart',
+    );
+  }
+
+  /// Paces sends over the RTT (on by default), capped at [maxRateKbps]
+  /// when non-zero. A cap needs pacing [enabled].
+  void setPacing(bool enabled, {int maxRateKbps = 0}) {
+    _throwIfError(
+      _bindings.configSetPacing(_live(), enabled, maxRateKbps),
+      'config_set_pacing',
+    );
+  }
+
+  /// Advertises how long this side may hold an ACK ([maxAckDelay], under
+  /// 16384 ms), which the peer adds to its probe timeout, and the
+  /// [exponent] (at most 20) its ACK delays are scaled by. Null keeps the
+  /// 25 ms / 3 defaults.
+  void setAckDelay({Duration? maxAckDelay, int? exponent}) {
+    _throwIfError(
+      _bindings.configSetAckDelay(
+        _live(),
+        maxAckDelay?.inMilliseconds ?? 0,
+        exponent ?? 0,
+      ),
+      'config_set_ack_delay',
+    );
+  }
+
+  /// Emits a [QuicStats] event per connection at [interval]; zero disables.
+  void setStatsInterval(Duration interval) {
+    _throwIfError(
+      _bindings.configSetStatsInterval(_live(), interval.inMilliseconds),
+      'config_set_stats_interval',
+    );
+  }
+
+  /// Drives client connections from one shared native poller thread instead
+  /// of a thread per connection. Has no effect on servers.
+  void setSharedRuntime(bool enabled) {
+    _throwIfError(
+      _bindings.configSetSharedRuntime(_live(), enabled),
+      'config_set_shared_runtime',
+    );
+  }
+
+  /// Serves from [workers] native threads sharing the port (Linux/Android).
+  void setServerWorkers(int workers) {
+    _throwIfError(
+      _bindings.configSetServerWorkers(_live(), workers),
+      'config_set_server_workers',
+    );
+  }
+
+  /// Server only: closes connections still handshaking after
+  /// [maxHandshake] and established ones older than [maxLifetime], posting
+  /// [QuicConnectionReaped]. A null limit is off.
+  void setConnectionLimits({Duration? maxLifetime, Duration? maxHandshake}) {
+    _throwIfError(
+      _bindings.configSetConnectionLimits(
+        _live(),
+        maxLifetime?.inMilliseconds ?? 0,
+        maxHandshake?.inMilliseconds ?? 0,
+      ),
+      'config_set_connection_limits',
+    );
+  }
+
+  /// Emits [QuicWorkerStalled] when a native worker loop pass exceeds
+  /// [threshold]; zero disables. Defaults to two seconds.
+  void setWatchdog(Duration threshold) {
+    _throwIfError(
+      _bindings.configSetWatchdog(_live(), threshold.inMilliseconds),
+      'config_set_watchdog',
+    );
+  }
+
+  /// Sizes the DATAGRAM receive/send queues, in datagrams.
+  void setDgramQueues({required int recvLen, required int sendLen}) {
+    _throwIfError(
+      _bindings.configSetDgramQueues(_live(), recvLen, sendLen),
+      'config_set_dgram_queues',
+    );
+  }
+
+  void setDgramDropPolicy(QuicDgramDropPolicy policy) {
+    _throwIfError(
+      _bindings.configSetDgramDropPolicy(_live(), policy.index),
+      'config_set_dgram_drop_policy',
+    );
+  }
+
+  /// Media frames held back behind a missing sequence before it is counted
+  /// lost; zero releases frames as they arrive.
+  void setMediaReorderWindow(int packets) {
+    _throwIfError(
+      _bindings.configSetMediaReorderWindow(_live(), packets),
+      'config_set_media_reorder_window',
+    );
+  }
+
+  /// Paces received media through a native jitter buffer on a [clockRate]
+  /// media clock, keeping the playout delay between [minDelay] and
+  /// [maxDelay]. A [clockRate] of zero disables it.
+  void setJitterBuffer({
+    required int clockRate,
+    Duration minDelay = const Duration(milliseconds: 20),
+    Duration maxDelay = const Duration(milliseconds: 200),
+  }) {
+    _throwIfError(
+      _bindings.configSetJitterBuffer(
+        _live(),
+        clockRate,
+        minDelay.inMilliseconds,
+        maxDelay.inMilliseconds,
+      ),
+      'config_set_jitter_buffer',
+    );
+  }
+
+  /// Compresses control frames of at least [minSize] bytes when the peer
+  /// enables the same [mode]; frames reach Dart uncompressed either way.
+  /// Deflate uses the platform zlib, so Windows builds throw unsupported.
+  void setCompression(QuicCompression mode, {int minSize = 256}) {
+    _throwIfError(
+      _bindings.configSetCompression(_live(), mode.index, minSize),
+      'config_set_compression',
+    );
+  }
+
+  /// Bounds receive-window auto-tuning: streams start with [minBytes] of
+  /// credit and grow with RTT and delivery rate up to [maxBytes] (1.5x for
+  /// the whole connection). Zero keeps the 1 MiB / 16 MiB defaults.
+  void setFlowWindow({int minBytes = 0, int maxBytes = 0}) {
+    _throwIfError(
+      _bindings.configSetFlowWindow(_live(), minBytes, maxBytes),
+      'config_set_flow_window',
+    );
+  }
+
+  /// Caps received stream data: [maxMessageBytes] per control frame (or per
+  /// other stream) and [maxConnectionBytes] over the connection's life; zero
+  /// leaves either unlimited. Going over yields [QuicMessageTooLarge].
+  void setRecvLimits({int maxMessageBytes = 0, int maxConnectionBytes = 0}) {
+    _throwIfError(
+      _bindings.configSetRecvLimits(
+        _live(),
+        maxMessageBytes,
+        maxConnectionBytes,
+      ),
+      'config_set_recv_limits',
+    );
+  }
+
+  /// Budgets the dual-channel mode: media is paced to [mediaKbps] (zero for
+  /// unpaced) and yields while control data waits; up to
+  /// [controlBacklogBytes] of [QuicNativeConnection.sendControl] data may
+  /// wait for stream credit (zero for the 1 MiB default).
+  void setDualChannel({int mediaKbps = 0, int controlBacklogBytes = 0}) {
+    _throwIfError(
+      _bindings.configSetDualChannel(_live(), mediaKbps, controlBacklogBytes),
+      'config_set_dual_channel',
+    );
+  }
+
+  /// Sends queued datagrams before control data waiting for stream credit,
+  /// so live audio is not held behind a large
+  /// [QuicNativeConnection.sendControl] flush (off by default: media yields
+  /// to control).
+  void setDatagramsFirst(bool enabled) {
+    _throwIfError(
+      _bindings.configSetDatagramsFirst(_live(), enabled),
+      'config_set_datagrams_first',
+    );
+  }
+
+  /// Selects the native event schema (1 or 2); see [QuicEvent.schema].
+  void setEventSchema(int version) {
+    _throwIfError(
+      _bindings.configSetEventSchema(_live(), version),
+      'config_set_event_schema',
+    );
+  }
+
+  /// Caps events of each class in [classes] ([QuicEventMask] bits) at
+  /// [maxPerSecond]; zero lifts the cap. What is held back is counted in a
+  /// [QuicEventsSuppressed]. Connected, closed and worker-died events always
+  /// go out.
+  void setEventRateLimit(int classes, int maxPerSecond) {
+    _throwIfError(
+      _bindings.configSetEventRateLimit(_live(), classes, maxPerSecond),
+      'config_set_event_rate_limit',
+    );
+  }
+
+  /// Holds back a [QuicError] repeating one posted less than [window] ago,
+  /// counting it in a [QuicEventsSuppressed]; [Duration.zero] turns it off.
+  void setErrorCoalescing(Duration window) {
+    _throwIfError(
+      _bindings.configSetErrorCoalescing(_live(), window.inMilliseconds),
+      'config_set_error_coalescing',
+    );
+  }
+
+  /// Test hook: the next server started with this config and the next
+  /// connect with [client] talk over an in-memory transport, so a test
+  /// runs the full handshake without sockets. Bind address, host and port
+  /// are ignored; the server listens on a synthetic `192.0.2.1:4433`.
+  void linkLoopback(QuicConfigHandle client) {
+    _throwIfError(
+      _bindings.testLoopbackPair(_live(), client._live()),
+      'test_loopback_pair',
+    );
+  }
+
+  Pointer<CcQuicConfig> _live() {
+    final ptr = _pointer;
+    if (ptr == null) {
+      throw StateError('Config already freed');
+    }
+    return ptr;
+  }
+
+  void dispose() {
+    final ptr = _pointer;
+    if (ptr != null) {
+      _bindings.configFree(ptr);
+      _pointer = null;
+    }
+  }
+}
+
+/// Newest native event schema this wrapper parses.
+const quicEventSchemaVersion = 2;
+
+abstract class QuicEvent {
+  const QuicEvent({
+    this.connectionId,
+    this.seq = 0,
+    this.schema = 1,
+    this.timestampUs,
+    this.tag,
+  });
+
+  final String? connectionId;
+
+  /// quiche's trace id for the connection, which prefixes its log lines
+  /// and the native library's own; the same as [connectionId].
+  String? get traceId => connectionId;
+
+  /// Per-handle sequence number from the native side; a gap means events
+  /// were lost (see [QuicEventsDropped]). Zero when unsequenced.
+  final int seq;
+
+  /// Schema version the native side encoded this event with.
+  final int schema;
+
+  /// Native monotonic timestamp in microseconds (schema 2 and later).
+  final int? timestampUs;
+
+  /// The tag set with [QuicNativeConnection.setTag] for this event's
+  /// connection, or else for its handle.
+  final int? tag;
+
+  factory QuicEvent.fromJson(String raw) {
+    final map = jsonDecode(raw) as Map<String, dynamic>;
+    final connId = map['connection_id'] as String?;
+    final seq = map['seq'] as int? ?? 0;
+    final schema = map['schema'] as int? ?? 1;
+    final timestampUs = map['ts_us'] as int?;
+    final tag = map['tag'] as int?;
+    switch (map['type'] as String) {
+      case 'connected':
+        return QuicConnected(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          peerFingerprint: map['peer_fingerprint'] as String?,
+          peerCertificate: map['peer_cert_der_base64'] == null
+              ? null
+              : base64Decode(map['peer_cert_der_base64'] as String),
+          user: map['user'] as String?,
+          label: map['label'] as String?,
+          serverName: map['server_name'] as String?,
+        );
+      case 'message':
+        return QuicMessage(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          data: base64Decode(map['data_base64'] as String),
+          channel: map['channel'] as String?,
+          topic: map['topic'] as String?,
+        );
+      case 'media':
+        return QuicMedia(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          mediaSeq: map['media_seq'] as int,
+          timestamp: map['timestamp'] as int,
+          marker: map['marker'] as bool? ?? false,
+          data: base64Decode(map['data_base64'] as String),
+        );
+      case 'closed':
+        return QuicClosed(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          reason: map['reason'] as String?,
+        );
+      case 'stats':
+        final ecn = map['ecn'] as Map<String, dynamic>? ?? const {};
+        final dgram = map['dgram'] as Map<String, dynamic>? ?? const {};
+        final media = map['media'] as Map<String, dynamic>? ?? const {};
+        final control = map['control'] as Map<String, dynamic>? ?? const {};
+        final flow = map['flow'] as Map<String, dynamic>? ?? const {};
+        final sendLimit =
+            map['send_limit'] as Map<String, dynamic>? ?? const {};
+        final traffic = map['traffic'] as Map<String, dynamic>? ?? const {};
+        final blocklist = map['blocklist'] as Map<String, dynamic>?;
+        return QuicStats(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          rttMs: (map['rtt_ms'] as num).toDouble(),
+          cwnd: map['cwnd'] as int,
+          pmtu: map['pmtu'] as int? ?? 0,
+          sent: map['sent'] as int,
+          recv: map['recv'] as int,
+          lost: map['lost'] as int,
+          retrans: map['retrans'] as int,
+          sentBytes: map['sent_bytes'] as int,
+          recvBytes: map['recv_bytes'] as int,
+          ecnCe: ecn['ce'] as int? ?? 0,
+          ecnEct0: ecn['ect0'] as int? ?? 0,
+          ecnEct1: ecn['ect1'] as int? ?? 0,
+          dgramRecvQueueLen: dgram['recv_queue_len'] as int? ?? 0,
+          dgramRecvQueueBytes: dgram['recv_queue_bytes'] as int? ?? 0,
+          dgramRecvQueueMax: dgram['recv_queue_max'] as int? ?? 0,
+          dgramSendQueueLen: dgram['send_queue_len'] as int? ?? 0,
+          dgramDropped: dgram['dropped'] as int? ?? 0,
+          dgramDropPolicy: dgram['drop_policy'] == 'back'
+              ? QuicDgramDropPolicy.back
+              : QuicDgramDropPolicy.front,
+          media: QuicMediaStats.fromJson(media),
+          controlBacklogBytes: control['backlog_bytes'] as int? ?? 0,
+          controlBacklogMax: control['backlog_max'] as int? ?? 0,
+          controlOverflowed: control['overflowed'] as int? ?? 0,
+          recvRateBps: flow['recv_rate_bps'] as int? ?? 0,
+          bdpBytes: flow['bdp_bytes'] as int? ?? 0,
+          flowWindowMin: flow['window_min'] as int? ?? 0,
+          flowWindowMax: flow['window_max'] as int? ?? 0,
+          appLimited: sendLimit['app_limited'] as bool? ?? false,
+          sendBlockedPct: sendLimit['blocked_pct'] as int? ?? 0,
+          traffic: QuicTrafficStats.fromJson(traffic),
+          blocklist: blocklist == null
+              ? null
+              : QuicBlocklistStats.fromJson(blocklist),
+          paths: [
+            for (final path in map['paths'] as List<dynamic>? ?? const [])
+              QuicPathStats.fromJson(path as Map<String, dynamic>),
+          ],
+        );
+      case 'handshake_progress':
+        return QuicHandshakeProgress(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          stage: QuicHandshakeStage.fromWire(map['state'] as String),
+          elapsedMs: map['elapsed_ms'] as int,
+        );
+      case 'handshake_failed':
+        return QuicHandshakeFailed(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          cause: QuicHandshakeFailure.fromWire(map['cause'] as String),
+          alert: map['alert'] as int?,
+          alertName: map['alert_name'] as String?,
+          fromPeer: map['from_peer'] as bool?,
+          errorCode: map['error_code'] as int?,
+          detail: map['detail'] as String,
+          elapsedMs: map['elapsed_ms'] as int,
+        );
+      case 'transport_fallback':
+        return QuicTransportFallback(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          transport: map['transport'] as String,
+          address: map['addr'] as String,
+        );
+      case 'time_sync':
+        return QuicTimeSync(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          offsetUs: map['offset_us'] as int,
+          rttUs: map['rtt_us'] as int,
+          dispersionUs: map['dispersion_us'] as int,
+          samples: map['samples'] as int,
+        );
+      case 'message_too_large':
+        return QuicMessageTooLarge(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          streamId: map['stream_id'] as int,
+          scope: map['scope'] == 'connection'
+              ? QuicRecvLimitScope.connection
+              : QuicRecvLimitScope.message,
+          limit: map['limit'] as int,
+          size: map['size'] as int,
+        );
+      case 'events_dropped':
+        return QuicEventsDropped(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          count: map['count'] as int,
+        );
+      case 'events_suppressed':
+        final limited = map['limited'] as Map<String, dynamic>? ?? const {};
+        final repeated = map['repeated_errors'] as List<dynamic>? ?? const [];
+        return QuicEventsSuppressed(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          limited: limited.map((name, count) => MapEntry(name, count as int)),
+          repeatedErrors: repeated
+              .map(
+                (e) => QuicRepeatedError.fromJson(e as Map<String, dynamic>),
+              )
+              .toList(),
+        );
+      case 'worker_died':
+        return QuicWorkerDied(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          message: map['message'] as String? ?? '',
+        );
+      case 'worker_stalled':
+        return QuicWorkerStalled(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          lastProgressMs: map['last_progress_ms'] as int,
+        );
+      case 'listening':
+        return QuicListening(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          address: map['addr'] as String,
+          transport: map['transport'] as String? ?? 'udp',
+        );
+      case 'cert_expiring_soon':
+        return QuicCertExpiringSoon(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          which: map['which'] == 'peer'
+              ? QuicCertSide.peer
+              : QuicCertSide.local,
+          daysLeft: map['days_left'] as int,
+        );
+      case 'verification_skipped':
+        return QuicVerificationSkipped(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          peerFingerprint: map['peer_fingerprint'] as String,
+        );
+      case 'peer_identity_rotated':
+        return QuicPeerIdentityRotated(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          oldFingerprint: map['old_fingerprint'] as String,
+          newFingerprint: map['new_fingerprint'] as String,
+        );
+      case 'observed_address':
+        return QuicObservedAddress(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          address: map['addr'] as String,
+        );
+      case 'server_relocated':
+        return QuicServerRelocated(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          address: map['addr'] as String,
+        );
+      case 'reverse_connect':
+        return QuicReverseConnect(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          address: map['addr'] as String,
+          dialedHandle: map['dialed_handle'] as int?,
+          error: map['error'] as String?,
+        );
+      case 'channel_opened':
+        return QuicChannelOpened(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          channel: map['channel'] as String,
+          streamId: map['stream_id'] as int,
+          byPeer: map['by_peer'] as bool,
+        );
+      case 'channel_closed':
+        return QuicChannelClosed(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          channel: map['channel'] as String,
+        );
+      case 'stream_reset':
+        return QuicStreamReset(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          streamId: map['stream_id'] as int,
+          channel: map['channel'] as String?,
+          errorCode: map['error_code'] as int,
+        );
+      case 'stream_stopped':
+        return QuicStreamStopped(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          streamId: map['stream_id'] as int,
+          channel: map['channel'] as String,
+          errorCode: map['error_code'] as int,
+        );
+      case 'stream_writable':
+        return QuicStreamWritable(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          streamId: map['stream_id'] as int,
+          channel: map['channel'] as String,
+          capacity: map['capacity'] as int,
+        );
+      case 'roster_changed':
+        return QuicRosterChanged(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          peers: (map['peers'] as List<dynamic>)
+              .map((e) => QuicRosterPeer.fromJson(e as Map<String, dynamic>))
+              .toList(),
+        );
+      case 'subscription':
+        return QuicSubscription(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          topic: map['topic'] as String,
+          subscribed: map['subscribed'] as bool,
+        );
+      case 'auth_request':
+        return QuicAuthRequest(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          token: map['token'] as String,
+        );
+      case 'allowlist_reloaded':
+        return QuicAllowlistReloaded(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          entries: map['entries'] as int,
+        );
+      case 'capabilities_negotiated':
+        return QuicCapabilitiesNegotiated(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          version: map['version'] as int,
+          local: QuicCapability.fromWire(map['local'] as List<dynamic>),
+          peer: QuicCapability.fromWire(map['peer'] as List<dynamic>),
+          resolved: QuicCapability.fromWire(map['resolved'] as List<dynamic>),
+        );
+      case 'connection_pending':
+        return QuicConnectionPending(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          fingerprint: map['fingerprint'] as String?,
+          remoteAddress: map['remote_addr'] as String?,
+          serverName: map['server_name'] as String?,
+        );
+      case 'connection_reaped':
+        return QuicConnectionReaped(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int,
+          connectionId: connId,
+          reason: QuicReapReason.values.byName(map['reason'] as String),
+          age: Duration(milliseconds: map['age_ms'] as int),
+        );
+      case 'error':
+      default:
+        return QuicError(
+          seq: seq,
+          schema: schema,
+          timestampUs: timestampUs,
+          tag: tag,
+          handle: map['handle'] as int? ?? 0,
+          connectionId: connId,
+          message: map['message'] as String? ?? 'unknown error',
+        );
+    }
+  }
+}
+
+class QuicConnected extends QuicEvent {
+  const QuicConnected({
+    required this.handle,
+    this.peerFingerprint,
+    this.peerCertificate,
+    this.user,
+    this.label,
+    this.serverName,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+
+  /// Null for a client the server admitted without a certificate (see
+  /// [QuicConfigHandle.setClientCertOptional]).
+  final String? peerFingerprint;
+
+  /// The peer's certificate (DER) when
+  /// [QuicConfigHandle.setPeerCertExport] is on.
+  final Uint8List? peerCertificate;
+
+  /// Server side: the user the client authenticated as (see
+  /// [QuicConfigHandle.setAuthHmac]).
+  final String? user;
+
+  /// Server side: the label of the client's allowlist entry, e.g.
+  /// `fp;label=grandma-phone`.
+  final String? label;
+
+  /// Server side: the SNI the client asked for, which picks the identity
+  /// from [QuicConfigHandle.addSniIdentity].
+  final String? serverName;
+}
+
+class QuicMessage extends QuicEvent {
+  const QuicMessage({
+    required this.handle,
+    required this.data,
+    this.channel,
+    this.topic,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final Uint8List data;
+
+  /// Channel the data arrived on (see [QuicNativeConnection.openChannel]);
+  /// null for the connection's main stream.
+  final String? channel;
+
+  /// Topic of a server publication (see [QuicNativeConnection.subscribe]).
+  final String? topic;
+}
+
+/// A media datagram, released in sequence order by the native reorder window.
+class QuicMedia extends QuicEvent {
+  const QuicMedia({
+    required this.handle,
+    required this.mediaSeq,
+    required this.timestamp,
+    required this.marker,
+    required this.data,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+
+  /// Extended (non-wrapping) media sequence number.
+  final int mediaSeq;
+  final int timestamp;
+  final bool marker;
+  final Uint8List data;
+}
+
+class QuicClosed extends QuicEvent {
+  const QuicClosed({
+    required this.handle,
+    this.reason,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final String? reason;
+}
+
+class QuicStats extends QuicEvent {
+  const QuicStats({
+    required this.handle,
+    required this.rttMs,
+    required this.cwnd,
+    this.pmtu = 0,
+    required this.sent,
+    required this.recv,
+    required this.lost,
+    required this.retrans,
+    required this.sentBytes,
+    required this.recvBytes,
+    required this.ecnCe,
+    required this.ecnEct0,
+    required this.ecnEct1,
+    this.dgramRecvQueueLen = 0,
+    this.dgramRecvQueueBytes = 0,
+    this.dgramRecvQueueMax = 0,
+    this.dgramSendQueueLen = 0,
+    this.dgramDropped = 0,
+    this.dgramDropPolicy = QuicDgramDropPolicy.front,
+    this.media = const QuicMediaStats(),
+    this.controlBacklogBytes = 0,
+    this.controlBacklogMax = 0,
+    this.controlOverflowed = 0,
+    this.recvRateBps = 0,
+    this.bdpBytes = 0,
+    this.flowWindowMin = 0,
+    this.flowWindowMax = 0,
+    this.appLimited = false,
+    this.sendBlockedPct = 0,
+    this.traffic = const QuicTrafficStats(),
+    this.blocklist,
+    this.paths = const [],
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final double rttMs;
+  final int cwnd;
+
+  /// Current path MTU (UDP payload bytes).
+  final int pmtu;
+  final int sent;
+  final int recv;
+  final int lost;
+  final int retrans;
+  final int sentBytes;
+  final int recvBytes;
+  final int ecnCe;
+  final int ecnEct0;
+  final int ecnEct1;
+  final int dgramRecvQueueLen;
+  final int dgramRecvQueueBytes;
+  final int dgramRecvQueueMax;
+  final int dgramSendQueueLen;
+  final int dgramDropped;
+  final QuicDgramDropPolicy dgramDropPolicy;
+  final QuicMediaStats media;
+
+  /// Control bytes waiting for stream credit.
+  final int controlBacklogBytes;
+  final int controlBacklogMax;
+
+  /// Control messages refused because the backlog was full.
+  final int controlOverflowed;
+
+  /// Smoothed rate the peer delivered to us.
+  final int recvRateBps;
+
+  /// Receive-side bandwidth-delay product, within the window bounds.
+  final int bdpBytes;
+  final int flowWindowMin;
+  final int flowWindowMax;
+
+  /// Nothing waited on the transport this interval, so a low send rate
+  /// reflects how little there was to send, not the path. Adaptive bitrate
+  /// should not step down on it.
+  final bool appLimited;
+
+  /// Share of the interval with data waiting to be sent, in percent.
+  final int sendBlockedPct;
+
+  /// Payload bytes split by traffic class.
+  final QuicTrafficStats traffic;
+
+  /// Server handles only: the blocked ranges and what they sent.
+  final QuicBlocklistStats? blocklist;
+
+  /// Every path the connection has while it has more than one, as during
+  /// a migration or relocation; empty otherwise.
+  final List<QuicPathStats> paths;
+}
+
+/// One path of a connection from [QuicStats.paths].
+class QuicPathStats {
+  const QuicPathStats({
+    required this.localAddr,
+    required this.peerAddr,
+    required this.state,
+    this.active = false,
+    this.rttMs = 0,
+    this.cwnd = 0,
+    this.sentBytes = 0,
+    this.recvBytes = 0,
+    this.lostBytes = 0,
+  });
+
+  factory QuicPathStats.fromJson(Map<String, dynamic> map) => QuicPathStats(
+    localAddr: map['local_addr'] as String,
+    peerAddr: map['peer_addr'] as String,
+    state: map['state'] as String,
+    active: map['active'] as bool? ?? false,
+    rttMs: (map['rtt_ms'] as num?)?.toDouble() ?? 0,
+    cwnd: map['cwnd'] as int? ?? 0,
+    sentBytes: map['sent_bytes'] as int? ?? 0,
+    recvBytes: map['recv_bytes'] as int? ?? 0,
+    lostBytes: map['lost_bytes'] as int? ?? 0,
+  );
+
+  final String localAddr;
+  final String peerAddr;
+
+  /// `unknown`, `validating`, `validating_mtu`, `validated` or `failed`.
+  final String state;
+
+  /// Whether the connection's traffic uses this path.
+  final bool active;
+  final double rttMs;
+  final int cwnd;
+  final int sentBytes;
+  final int recvBytes;
+  final int lostBytes;
+}
+
+/// Server-wide counters for [QuicNativeConnection.blockAddress].
+class QuicBlocklistStats {
+  const QuicBlocklistStats({
+    this.rules = 0,
+    this.droppedDatagrams = 0,
+    this.droppedBytes = 0,
+  });
+
+  factory QuicBlocklistStats.fromJson(Map<String, dynamic> map) =>
+      QuicBlocklistStats(
+        rules: map['rules'] as int? ?? 0,
+        droppedDatagrams: map['dropped_datagrams'] as int? ?? 0,
+        droppedBytes: map['dropped_bytes'] as int? ?? 0,
+      );
+
+  final int rules;
+  final int droppedDatagrams;
+  final int droppedBytes;
+}
+
+/// Payload bytes per traffic class from a stats event: [control] is the
+/// control stream, [media] the datagram flow, [transfer] any other stream.
+/// QUIC overhead and internal streams make up the rest of
+/// [QuicStats.sentBytes].
+class QuicTrafficStats {
+  const QuicTrafficStats({
+    this.control = const QuicClassBytes(),
+    this.media = const QuicClassBytes(),
+    this.transfer = const QuicClassBytes(),
+  });
+
+  factory QuicTrafficStats.fromJson(Map<String, dynamic> map) =>
+      QuicTrafficStats(
+        control: QuicClassBytes.fromJson(
+          map['control'] as Map<String, dynamic>? ?? const {},
+        ),
+        media: QuicClassBytes.fromJson(
+          map['media'] as Map<String, dynamic>? ?? const {},
+        ),
+        transfer: QuicClassBytes.fromJson(
+          map['transfer'] as Map<String, dynamic>? ?? const {},
+        ),
+      );
+
+  final QuicClassBytes control;
+  final QuicClassBytes media;
+  final QuicClassBytes transfer;
+}
+
+class QuicClassBytes {
+  const QuicClassBytes({this.sentBytes = 0, this.recvBytes = 0});
+
+  factory QuicClassBytes.fromJson(Map<String, dynamic> map) => QuicClassBytes(
+    sentBytes: map['sent_bytes'] as int? ?? 0,
+    recvBytes: map['recv_bytes'] as int? ?? 0,
+  );
+
+  final int sentBytes;
+  final int recvBytes;
+}
+
+/// Media datagram counters from a stats event.
+class QuicMediaStats {
+  const QuicMediaStats({
+    this.sent = 0,
+    this.received = 0,
+    this.released = 0,
+    this.lost = 0,
+    this.late = 0,
+    this.duplicates = 0,
+    this.reordered = 0,
+    this.invalid = 0,
+    this.fecSent = 0,
+    this.fecReceived = 0,
+    this.recovered = 0,
+    this.redundantSent = 0,
+    this.pacedDropped = 0,
+    this.jitter = const QuicJitterStats(),
+  });
+
+  factory QuicMediaStats.fromJson(Map<String, dynamic> map) => QuicMediaStats(
+    sent: map['sent'] as int? ?? 0,
+    received: map['received'] as int? ?? 0,
+    released: map['released'] as int? ?? 0,
+    lost: map['lost'] as int? ?? 0,
+    late: map['late'] as int? ?? 0,
+    duplicates: map['duplicates'] as int? ?? 0,
+    reordered: map['reordered'] as int? ?? 0,
+    invalid: map['invalid'] as int? ?? 0,
+    fecSent: map['fec_sent'] as int? ?? 0,
+    fecReceived: map['fec_received'] as int? ?? 0,
+    recovered: map['recovered'] as int? ?? 0,
+    redundantSent: map['redundant_sent'] as int? ?? 0,
+    pacedDropped: map['paced_dropped'] as int? ?? 0,
+    jitter: QuicJitterStats.fromJson(
+      map['jitter'] as Map<String, dynamic>? ?? const {},
+    ),
+  );
+
+  final int sent;
+  final int received;
+  final int released;
+
+  /// Sequences skipped once the reorder window gave up on them.
+  final int lost;
+
+  /// Frames that arrived after their slot was released.
+  final int late;
+  final int duplicates;
+  final int reordered;
+  final int invalid;
+  final int fecSent;
+  final int fecReceived;
+
+  /// Frames rebuilt from FEC parity (also counted in [received]).
+  final int recovered;
+
+  /// Extra copies sent by the redundant mode.
+  final int redundantSent;
+
+  /// Frames dropped to stay within the dual-channel media budget.
+  final int pacedDropped;
+  final QuicJitterStats jitter;
+}
+
+/// Native jitter buffer state; all zero when the buffer is disabled.
+class QuicJitterStats {
+  const QuicJitterStats({
+    this.depth = 0,
+    this.depthMs = 0,
+    this.targetDelayMs = 0,
+    this.jitterMs = 0,
+    this.late = 0,
+  });
+
+  factory QuicJitterStats.fromJson(Map<String, dynamic> map) =>
+      QuicJitterStats(
+        depth: map['depth'] as int? ?? 0,
+        depthMs: (map['depth_ms'] as num? ?? 0).toDouble(),
+        targetDelayMs: (map['target_delay_ms'] as num? ?? 0).toDouble(),
+        jitterMs: (map['jitter_ms'] as num? ?? 0).toDouble(),
+        late: map['late'] as int? ?? 0,
+      );
+
+  /// Frames waiting for their playout time.
+  final int depth;
+  final double depthMs;
+  final double targetDelayMs;
+  final double jitterMs;
+
+  /// Frames that arrived after their playout time.
+  final int late;
+}
+
+/// The handshake reached [stage], [elapsedMs] after the connection started.
+/// The last one seen before a [QuicClosed] shows where a stuck handshake
+/// stopped; a client's [QuicHandshakeStage.confirmed] may follow
+/// [QuicConnected].
+class QuicHandshakeProgress extends QuicEvent {
+  const QuicHandshakeProgress({
+    required this.handle,
+    required this.stage,
+    required this.elapsedMs,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final QuicHandshakeStage stage;
+  final int elapsedMs;
+}
+
+/// The connection closed before [QuicConnected], for [cause]. [alert] and
+/// [alertName] give the TLS alert for [QuicHandshakeFailure.tlsAlert] and
+/// [QuicHandshakeFailure.certInvalid]; [fromPeer] tells whether the peer
+/// or this side closed it, when one did.
+class QuicHandshakeFailed extends QuicEvent {
+  const QuicHandshakeFailed({
+    required this.handle,
+    required this.cause,
+    required this.detail,
+    required this.elapsedMs,
+    this.alert,
+    this.alertName,
+    this.fromPeer,
+    this.errorCode,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final QuicHandshakeFailure cause;
+  final int? alert;
+  final String? alertName;
+  final bool? fromPeer;
+  final int? errorCode;
+  final String detail;
+  final int elapsedMs;
+}
+
+/// A client heard nothing over UDP, so it gave up on [connectionId] (its
+/// [QuicHandshakeFailed] came first) and dials again over [transport]
+/// (`tcp`) to [address]. Events of the next attempt carry a new
+/// connection id.
+class QuicTransportFallback extends QuicEvent {
+  const QuicTransportFallback({
+    required this.handle,
+    required this.transport,
+    required this.address,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final String transport;
+  final String address;
+}
+
+/// Clock estimate from a time sync probe. Adding [offsetUs] to a local
+/// wall-clock time in microseconds gives the peer's clock; [dispersionUs]
+/// bounds how far recent samples disagree.
+class QuicTimeSync extends QuicEvent {
+  const QuicTimeSync({
+    required this.handle,
+    required this.offsetUs,
+    required this.rttUs,
+    required this.dispersionUs,
+    required this.samples,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final int offsetUs;
+  final int rttUs;
+  final int dispersionUs;
+  final int samples;
+
+  /// Maps a peer wall-clock time onto this device's clock.
+  DateTime toLocal(DateTime peerTime) =>
+      peerTime.subtract(Duration(microseconds: offsetUs));
+}
+
+/// Stream data went over a receive cap. The stream was reset without
+/// forwarding the oversized message; a [QuicRecvLimitScope.connection] hit
+/// also closes the connection.
+class QuicMessageTooLarge extends QuicEvent {
+  const QuicMessageTooLarge({
+    required this.handle,
+    required this.streamId,
+    required this.scope,
+    required this.limit,
+    required this.size,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final int streamId;
+  final QuicRecvLimitScope scope;
+  final int limit;
+  final int size;
+}
+
+/// [count] earlier events never reached the isolate; state derived from the
+/// event stream may be stale.
+class QuicEventsDropped extends QuicEvent {
+  const QuicEventsDropped({
+    required this.handle,
+    required this.count,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(seq: seq, schema: schema, timestampUs: timestampUs, tag: tag);
+
+  final int handle;
+  final int count;
+}
+
+/// Events the config's rate limits or error coalescing held back since the
+/// last summary: [limited] counts them by class name, [repeatedErrors] the
+/// repeats of errors already posted.
+class QuicEventsSuppressed extends QuicEvent {
+  const QuicEventsSuppressed({
+    required this.handle,
+    required this.limited,
+    required this.repeatedErrors,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(seq: seq, schema: schema, timestampUs: timestampUs, tag: tag);
+
+  final int handle;
+  final Map<String, int> limited;
+  final List<QuicRepeatedError> repeatedErrors;
+}
+
+class QuicRepeatedError {
+  const QuicRepeatedError({
+    required this.connectionId,
+    required this.message,
+    required this.count,
+  });
+
+  factory QuicRepeatedError.fromJson(Map<String, dynamic> map) =>
+      QuicRepeatedError(
+        connectionId: map['connection_id'] as String?,
+        message: map['message'] as String,
+        count: map['count'] as int,
+      );
+
+  final String? connectionId;
+  final String message;
+  final int count;
+}
+
+/// The native worker panicked; a [QuicError] follows and the handle is gone.
+class QuicWorkerDied extends QuicEvent {
+  const QuicWorkerDied({
+    required this.handle,
+    required this.message,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(seq: seq, schema: schema, timestampUs: timestampUs, tag: tag);
+
+  final int handle;
+  final String message;
+}
+
+/// The native worker has not completed a loop pass for [lastProgressMs].
+class QuicWorkerStalled extends QuicEvent {
+  const QuicWorkerStalled({
+    required this.handle,
+    required this.lastProgressMs,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(seq: seq, schema: schema, timestampUs: timestampUs, tag: tag);
+
+  final int handle;
+  final int lastProgressMs;
+}
+
+/// A server socket is bound to [address] (`host:port`), with the port the OS
+/// assigned when port 0 was requested. Emitted once per listener before any
+/// connection event; advertise this port over mDNS/pairing. [transport] is
+/// `tcp` for the [QuicConfigHandle.setTcpFallback] listener.
+class QuicListening extends QuicEvent {
+  const QuicListening({
+    required this.handle,
+    required this.address,
+    this.transport = 'udp',
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(seq: seq, schema: schema, timestampUs: timestampUs, tag: tag);
+
+  final int handle;
+  final String address;
+  final String transport;
+
+  int get port => int.parse(address.substring(address.lastIndexOf(':') + 1));
+}
+
+/// A certificate expires within the warning window set by
+/// [QuicConfigHandle.setCertExpiryWarning]; [daysLeft] is negative once it
+/// has expired. Peer warnings carry the connection id.
+class QuicCertExpiringSoon extends QuicEvent {
+  const QuicCertExpiringSoon({
+    required this.handle,
+    required this.which,
+    required this.daysLeft,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final QuicCertSide which;
+  final int daysLeft;
+}
+
+/// The peer will present the certificate with [newFingerprint] from its
+/// next handshake. The announcement arrived over the session authenticated
+/// as [oldFingerprint]; replace that pin. A server whose allowlist holds
+/// [oldFingerprint] already accepts the new one.
+class QuicPeerIdentityRotated extends QuicEvent {
+  const QuicPeerIdentityRotated({
+    required this.handle,
+    required this.oldFingerprint,
+    required this.newFingerprint,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final String oldFingerprint;
+  final String newFingerprint;
+}
+
+/// A client started with `allowUnpinned` and no pin accepted the server on
+/// its CA alone; arrives just before [QuicConnected]. [peerFingerprint] is
+/// what the client could pin next time.
+class QuicVerificationSkipped extends QuicEvent {
+  const QuicVerificationSkipped({
+    required this.handle,
+    required this.peerFingerprint,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final String peerFingerprint;
+}
+
+/// The address the server sees this client's packets come from (its NAT
+/// mapping, if any), reported on connect and whenever the path changes.
+class QuicObservedAddress extends QuicEvent {
+  const QuicObservedAddress({
+    required this.handle,
+    required this.address,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+
+  /// `ip:port`, with IPv6 addresses in brackets.
+  final String address;
+}
+
+/// The client moved its connection to the server's preferred address (see
+/// [QuicNativeConnection.setPreferredAddress]).
+class QuicServerRelocated extends QuicEvent {
+  const QuicServerRelocated({
+    required this.handle,
+    required this.address,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+
+  /// `ip:port` the connection now talks to.
+  final String address;
+}
+
+/// The client dialed a connection back to [address] for the server's
+/// [QuicNativeConnection.requestConnect]; it arrives on
+/// [QuicNativeConnection.reverseConnections]. Without [dialedHandle],
+/// [error] says why there is none.
+class QuicReverseConnect extends QuicEvent {
+  const QuicReverseConnect({
+    required this.handle,
+    required this.address,
+    this.dialedHandle,
+    this.error,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  /// The signaling connection's handle.
+  final int handle;
+
+  /// `ip:port` dialed.
+  final String address;
+  final int? dialedHandle;
+  final String? error;
+}
+
+/// Channel [channel] is usable; [byPeer] when the peer opened it.
+class QuicChannelOpened extends QuicEvent {
+  const QuicChannelOpened({
+    required this.handle,
+    required this.channel,
+    required this.streamId,
+    required this.byPeer,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final String channel;
+  final int streamId;
+  final bool byPeer;
+}
+
+/// The peer closed channel [channel]; its name may be opened again.
+class QuicChannelClosed extends QuicEvent {
+  const QuicChannelClosed({
+    required this.handle,
+    required this.channel,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final String channel;
+}
+
+/// The peer reset stream [streamId], dropping what it had not delivered.
+/// A channel on it ends; a [QuicChannelClosed] follows.
+class QuicStreamReset extends QuicEvent {
+  const QuicStreamReset({
+    required this.handle,
+    required this.streamId,
+    required this.errorCode,
+    this.channel,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final int streamId;
+
+  /// Set when a channel was open on the stream.
+  final String? channel;
+  final int errorCode;
+}
+
+/// The peer asked us to stop sending on channel [channel]'s stream: what
+/// was still queued is dropped and sends on it fail, but what the peer
+/// sends still arrives until the channel is closed.
+class QuicStreamStopped extends QuicEvent {
+  const QuicStreamStopped({
+    required this.handle,
+    required this.streamId,
+    required this.channel,
+    required this.errorCode,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final int streamId;
+  final String channel;
+  final int errorCode;
+}
+
+/// Channel [channel]'s stream takes [capacity] more bytes right now. With
+/// [QuicConfigHandle.setWritableEvents]; another follows once a send is
+/// cut short or the peer extends the window.
+class QuicStreamWritable extends QuicEvent {
+  const QuicStreamWritable({
+    required this.handle,
+    required this.streamId,
+    required this.channel,
+    required this.capacity,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final int streamId;
+  final String channel;
+  final int capacity;
+}
+
+/// One fingerprint on a server's roster.
+class QuicRosterPeer {
+  const QuicRosterPeer({
+    required this.fingerprint,
+    required this.connections,
+    this.label,
+    this.you = false,
+  });
+
+  factory QuicRosterPeer.fromJson(Map<String, dynamic> map) => QuicRosterPeer(
+    fingerprint: map['fingerprint'] as String,
+    label: map['label'] as String?,
+    connections: map['connections'] as int,
+    you: map['you'] as bool? ?? false,
+  );
+
+  final String fingerprint;
+  final String? label;
+
+  /// Connections open with this fingerprint.
+  final int connections;
+
+  /// This client's own entry.
+  final bool you;
+}
+
+/// Who is connected to the server, after a peer joined or left or a label
+/// changed. Posted on the server (no [connectionId]) and on each client.
+class QuicRosterChanged extends QuicEvent {
+  const QuicRosterChanged({
+    required this.handle,
+    required this.peers,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final List<QuicRosterPeer> peers;
+}
+
+/// Server side: the client on [connectionId] subscribed to [topic], or
+/// unsubscribed when [subscribed] is false.
+class QuicSubscription extends QuicEvent {
+  const QuicSubscription({
+    required this.handle,
+    required this.topic,
+    required this.subscribed,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final String topic;
+  final bool subscribed;
+}
+
+/// Server side: the client on [connectionId] presented [token]; answer
+/// with [QuicNativeConnection.authVerdict] (see
+/// [QuicConfigHandle.setAuthCallback]).
+class QuicAuthRequest extends QuicEvent {
+  const QuicAuthRequest({
+    required this.handle,
+    required this.token,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final String token;
+}
+
+/// Server side: the allowlist file (see
+/// [QuicNativeConnection.setAllowlistFile]) changed and now lists
+/// [entries] fingerprints.
+class QuicAllowlistReloaded extends QuicEvent {
+  const QuicAllowlistReloaded({
+    required this.handle,
+    required this.entries,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(seq: seq, schema: schema, timestampUs: timestampUs, tag: tag);
+
+  final int handle;
+  final int entries;
+}
+
+/// Both sides of a connection exchanged the features they run (see
+/// [QuicConfigHandle.setCapabilities]). No event means the peer did not
+/// take part: an older build, or one with the exchange off.
+class QuicCapabilitiesNegotiated extends QuicEvent {
+  const QuicCapabilitiesNegotiated({
+    required this.handle,
+    required this.version,
+    required this.local,
+    required this.peer,
+    required this.resolved,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+
+  /// The lower of the two sides' exchange versions.
+  final int version;
+  final Set<QuicCapability> local;
+  final Set<QuicCapability> peer;
+
+  /// What both sides run: the features the connection can use.
+  final Set<QuicCapability> resolved;
+}
+
+/// Server side: a client with [fingerprint] wants in; answer with
+/// [QuicNativeConnection.decide] (see
+/// [QuicConfigHandle.setConnectionApproval]).
+class QuicConnectionPending extends QuicEvent {
+  const QuicConnectionPending({
+    required this.handle,
+    this.fingerprint,
+    this.remoteAddress,
+    this.serverName,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+
+  /// Null for a client without a certificate.
+  final String? fingerprint;
+
+  /// The client's `ip:port`.
+  final String? remoteAddress;
+
+  /// The SNI the client asked for.
+  final String? serverName;
+}
+
+/// The server closed a connection over a limit from
+/// [QuicConfigHandle.setConnectionLimits]; [QuicClosed] follows.
+class QuicConnectionReaped extends QuicEvent {
+  const QuicConnectionReaped({
+    required this.handle,
+    required this.reason,
+    required this.age,
+    String? connectionId,
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
+
+  final int handle;
+  final QuicReapReason reason;
+  final Duration age;
+}
 
 class QuicError extends QuicEvent {
   const QuicError({
     required this.handle,
     required this.message,
     String? connectionId,
-  }) : super(connectionId: connectionId);
+    int seq = 0,
+    int schema = 1,
+    int? timestampUs,
+    int? tag,
+  }) : super(
+         connectionId: connectionId,
+         seq: seq,
+         schema: schema,
+         timestampUs: timestampUs,
+         tag: tag,
+       );
 
   final int handle;
   final String message;
@@ -359,6 +3703,13 @@ class CcQuicStatus {
   static const socketError = CcQuicStatus._(5, 'socket_error');
   static const handshakeError = CcQuicStatus._(6, 'handshake_error');
   static const eventSendError = CcQuicStatus._(7, 'event_send_error');
+  static const permissionDenied = CcQuicStatus._(8, 'permission_denied');
+  static const unsupported = CcQuicStatus._(9, 'unsupported');
+  static const wrongRole = CcQuicStatus._(10, 'wrong_role');
+  static const unknownConnection = CcQuicStatus._(11, 'unknown_connection');
+  static const certExpired = CcQuicStatus._(12, 'cert_expired');
+  static const keyOpenFailed = CcQuicStatus._(13, 'key_open_failed');
+  static const staleHandle = CcQuicStatus._(14, 'stale_handle');
   static const internal = CcQuicStatus._(255, 'internal');
 
   static const values = [
@@ -370,6 +3721,13 @@ class CcQuicStatus {
     socketError,
     handshakeError,
     eventSendError,
+    permissionDenied,
+    unsupported,
+    wrongRole,
+    unknownConnection,
+    certExpired,
+    keyOpenFailed,
+    staleHandle,
     internal,
   ];
 
@@ -380,16 +3738,55 @@ class CcQuicStatus {
 }
 
 class CribcallQuicException implements Exception {
-  CribcallQuicException(this.operation, this.status);
+  CribcallQuicException(this.operation, this.status, [this.detail]);
 
   final String operation;
   final CcQuicStatus status;
 
+  /// The native library's explanation, where it gives one.
+  final String? detail;
+
   @override
-  String toString() =>
-      'CribcallQuicException($operation failed with ${status.label} [${status.code}])';
+  String toString() {
+    final suffix = detail == null ? '' : ': $detail';
+    return 'CribcallQuicException($operation failed with ${status.label} [${status.code}]$suffix)';
+  }
+}
+
+/// Raw id bytes for the hex `connection_id` events carry.
+Uint8List _connIdBytes(String hex) {
+  final bytes = Uint8List(hex.length ~/ 2);
+  for (var i = 0; i < bytes.length; i++) {
+    bytes[i] = int.parse(hex.substring(2 * i, 2 * i + 2), radix: 16);
+  }
+  return bytes;
 }
 
+/// The handle a raw event JSON names.
+int? _eventHandle(String raw) =>
+    (jsonDecode(raw) as Map<String, dynamic>)['handle'] as int?;
+
+typedef _KeyCryptNative =
+    Int32 Function(
+      Pointer<Uint8>,
+      UintPtr,
+      Pointer<Uint8>,
+      UintPtr,
+      Pointer<Uint8>,
+      UintPtr,
+      Pointer<UintPtr>,
+    );
+typedef _KeyCryptFn =
+    int Function(
+      Pointer<Uint8>,
+      int,
+      Pointer<Uint8>,
+      int,
+      Pointer<Uint8>,
+      int,
+      Pointer<UintPtr>,
+    );
+
 void _throwIfError(int status, String op) {
   final parsed = CcQuicStatus.fromCode(status);
   if (parsed != CcQuicStatus.ok) {
@@ -456,16 +3853,290 @@ class _NativeBindings {
           .lookupFunction<Pointer<Utf8> Function(), Pointer<Utf8> Function()>(
             'cc_quic_version',
           ),
+      abiVersion = lib.lookupFunction<Uint32 Function(), int Function()>(
+        'cc_quic_abi_version',
+      ),
+      configNewPreset = lib
+          .lookupFunction<
+            Int32 Function(Pointer<Utf8>, Pointer<Pointer<CcQuicConfig>>),
+            int Function(Pointer<Utf8>, Pointer<Pointer<CcQuicConfig>>)
+          >('cc_quic_config_new_preset'),
+      configFromJson = lib
+          .lookupFunction<
+            Int32 Function(Pointer<Utf8>, Pointer<Pointer<CcQuicConfig>>),
+            int Function(Pointer<Utf8>, Pointer<Pointer<CcQuicConfig>>)
+          >('cc_quic_config_from_json'),
+      lastError = lib
+          .lookupFunction<
+            Int32 Function(Pointer<Uint8>, UintPtr),
+            int Function(Pointer<Uint8>, int)
+          >('cc_quic_last_error'),
+      auditEnable = lib
+          .lookupFunction<
+            Int32 Function(Pointer<Utf8>, Uint64),
+            int Function(Pointer<Utf8>, int)
+          >('cc_quic_audit_enable'),
+      auditQuery = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Uint8>, UintPtr, Pointer<UintPtr>),
+            int Function(int, Pointer<Uint8>, int, Pointer<UintPtr>)
+          >('cc_quic_audit_query'),
+      keySeal = lib.lookupFunction<_KeyCryptNative, _KeyCryptFn>(
+        'cc_quic_key_seal',
+      ),
+      keyOpen = lib.lookupFunction<_KeyCryptNative, _KeyCryptFn>(
+        'cc_quic_key_open',
+      ),
       configNew = lib
           .lookupFunction<
             Int32 Function(Pointer<Pointer<CcQuicConfig>>),
             int Function(Pointer<Pointer<CcQuicConfig>>)
           >('cc_quic_config_new'),
+      configNewClient = lib
+          .lookupFunction<
+            Int32 Function(Pointer<Pointer<CcQuicConfig>>),
+            int Function(Pointer<Pointer<CcQuicConfig>>)
+          >('cc_quic_config_new_client'),
+      configNewServer = lib
+          .lookupFunction<
+            Int32 Function(Pointer<Pointer<CcQuicConfig>>),
+            int Function(Pointer<Pointer<CcQuicConfig>>)
+          >('cc_quic_config_new_server'),
       configFree = lib
           .lookupFunction<
             Void Function(Pointer<CcQuicConfig>),
             void Function(Pointer<CcQuicConfig>)
           >('cc_quic_config_free'),
+      configSetEcn = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Bool),
+            int Function(Pointer<CcQuicConfig>, bool)
+          >('cc_quic_config_set_ecn'),
+      configSetPeerCertExport = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Bool),
+            int Function(Pointer<CcQuicConfig>, bool)
+          >('cc_quic_config_set_peer_cert_export'),
+      configSetKeyingMaterialExport = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Bool),
+            int Function(Pointer<CcQuicConfig>, bool)
+          >('cc_quic_config_set_keying_material_export'),
+      configSetWritableEvents = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Bool),
+            int Function(Pointer<CcQuicConfig>, bool)
+          >('cc_quic_config_set_writable_events'),
+      configSetObservedAddress = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Bool),
+            int Function(Pointer<CcQuicConfig>, bool)
+          >('cc_quic_config_set_observed_address'),
+      configSetRoster = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Bool),
+            int Function(Pointer<CcQuicConfig>, bool)
+          >('cc_quic_config_set_roster'),
+      configSetCapabilities = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint32),
+            int Function(Pointer<CcQuicConfig>, int)
+          >('cc_quic_config_set_capabilities'),
+      configSetAuthToken = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Pointer<Utf8>),
+            int Function(Pointer<CcQuicConfig>, Pointer<Utf8>)
+          >('cc_quic_config_set_auth_token'),
+      configSetAuthHmac = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Pointer<Uint8>, UintPtr),
+            int Function(Pointer<CcQuicConfig>, Pointer<Uint8>, int)
+          >('cc_quic_config_set_auth_hmac'),
+      configSetAuthCallback = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Bool),
+            int Function(Pointer<CcQuicConfig>, bool)
+          >('cc_quic_config_set_auth_callback'),
+      configSetClientCertOptional = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Bool),
+            int Function(Pointer<CcQuicConfig>, bool)
+          >('cc_quic_config_set_client_cert_optional'),
+      configSetRequireClientCert = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Bool),
+            int Function(Pointer<CcQuicConfig>, bool)
+          >('cc_quic_config_set_require_client_cert'),
+      configSetConnectionApproval = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint64),
+            int Function(Pointer<CcQuicConfig>, int)
+          >('cc_quic_config_set_connection_approval'),
+      configAddSniIdentity = lib
+          .lookupFunction<
+            Int32 Function(
+              Pointer<CcQuicConfig>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+            ),
+            int Function(
+              Pointer<CcQuicConfig>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+            )
+          >('cc_quic_config_add_sni_identity'),
+      configSetCoalesce = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Bool),
+            int Function(Pointer<CcQuicConfig>, bool)
+          >('cc_quic_config_set_coalesce'),
+      configSetCwndResume = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint64),
+            int Function(Pointer<CcQuicConfig>, int)
+          >('cc_quic_config_set_cwnd_resume'),
+      configSetReverseConnect = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Bool, Pointer<Utf8>),
+            int Function(Pointer<CcQuicConfig>, bool, Pointer<Utf8>)
+          >('cc_quic_config_set_reverse_connect'),
+      configSetTcpFallback = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint16, Uint64),
+            int Function(Pointer<CcQuicConfig>, int, int)
+          >('cc_quic_config_set_tcp_fallback'),
+      configSetCaPath = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Pointer<Utf8>),
+            int Function(Pointer<CcQuicConfig>, Pointer<Utf8>)
+          >('cc_quic_config_set_ca_path'),
+      configSetCertExpiryWarning = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint32),
+            int Function(Pointer<CcQuicConfig>, int)
+          >('cc_quic_config_set_cert_expiry_warning'),
+      configSetPmtuDiscovery = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Bool, Uint32),
+            int Function(Pointer<CcQuicConfig>, bool, int)
+          >('cc_quic_config_set_pmtu_discovery'),
+      configSetUdpOffload = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Bool),
+            int Function(Pointer<CcQuicConfig>, bool)
+          >('cc_quic_config_set_udp_offload'),
+      configSetHystart = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Bool),
+            int Function(Pointer<CcQuicConfig>, bool)
+          >('cc_quic_config_set_hystart'),
+      configSetPacing = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Bool, Uint64),
+            int Function(Pointer<CcQuicConfig>, bool, int)
+          >('cc_quic_config_set_pacing'),
+      configSetAckDelay = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint64, Uint32),
+            int Function(Pointer<CcQuicConfig>, int, int)
+          >('cc_quic_config_set_ack_delay'),
+      configSetStatsInterval = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint64),
+            int Function(Pointer<CcQuicConfig>, int)
+          >('cc_quic_config_set_stats_interval'),
+      configSetSharedRuntime = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Bool),
+            int Function(Pointer<CcQuicConfig>, bool)
+          >('cc_quic_config_set_shared_runtime'),
+      configSetServerWorkers = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint32),
+            int Function(Pointer<CcQuicConfig>, int)
+          >('cc_quic_config_set_server_workers'),
+      configSetConnectionLimits = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint64, Uint64),
+            int Function(Pointer<CcQuicConfig>, int, int)
+          >('cc_quic_config_set_connection_limits'),
+      configSetWatchdog = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint64),
+            int Function(Pointer<CcQuicConfig>, int)
+          >('cc_quic_config_set_watchdog'),
+      configSetDgramQueues = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint32, Uint32),
+            int Function(Pointer<CcQuicConfig>, int, int)
+          >('cc_quic_config_set_dgram_queues'),
+      configSetDgramDropPolicy = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Int32),
+            int Function(Pointer<CcQuicConfig>, int)
+          >('cc_quic_config_set_dgram_drop_policy'),
+      configSetMediaReorderWindow = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint32),
+            int Function(Pointer<CcQuicConfig>, int)
+          >('cc_quic_config_set_media_reorder_window'),
+      configSetJitterBuffer = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint32, Uint32, Uint32),
+            int Function(Pointer<CcQuicConfig>, int, int, int)
+          >('cc_quic_config_set_jitter_buffer'),
+      configSetCompression = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Int32, Uint32),
+            int Function(Pointer<CcQuicConfig>, int, int)
+          >('cc_quic_config_set_compression'),
+      configSetFlowWindow = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint64, Uint64),
+            int Function(Pointer<CcQuicConfig>, int, int)
+          >('cc_quic_config_set_flow_window'),
+      configSetRecvLimits = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint64, Uint64),
+            int Function(Pointer<CcQuicConfig>, int, int)
+          >('cc_quic_config_set_recv_limits'),
+      configSetDualChannel = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint32, Uint32),
+            int Function(Pointer<CcQuicConfig>, int, int)
+          >('cc_quic_config_set_dual_channel'),
+      configSetDatagramsFirst = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Bool),
+            int Function(Pointer<CcQuicConfig>, bool)
+          >('cc_quic_config_set_datagrams_first'),
+      configSetEventSchema = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint32),
+            int Function(Pointer<CcQuicConfig>, int)
+          >('cc_quic_config_set_event_schema'),
+      configSetEventRateLimit = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint32, Uint32),
+            int Function(Pointer<CcQuicConfig>, int, int)
+          >('cc_quic_config_set_event_rate_limit'),
+      configSetErrorCoalescing = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Uint64),
+            int Function(Pointer<CcQuicConfig>, int)
+          >('cc_quic_config_set_error_coalescing'),
+      eventSchemaVersion = lib.lookupFunction<Uint32 Function(), int Function()>(
+        'cc_quic_event_schema_version',
+      ),
+      testLoopbackPair = lib
+          .lookupFunction<
+            Int32 Function(Pointer<CcQuicConfig>, Pointer<CcQuicConfig>),
+            int Function(Pointer<CcQuicConfig>, Pointer<CcQuicConfig>)
+          >('cc_quic_test_loopback_pair'),
       clientConnect = lib
           .lookupFunction<
             Int32 Function(
@@ -476,6 +4147,7 @@ class _NativeBindings {
               Pointer<Utf8>,
               Pointer<Utf8>,
               Pointer<Utf8>,
+              Uint32,
               Int64,
               Pointer<Uint64>,
             ),
@@ -488,9 +4160,43 @@ class _NativeBindings {
               Pointer<Utf8>,
               Pointer<Utf8>,
               int,
+              int,
               Pointer<Uint64>,
             )
           >('cc_quic_client_connect'),
+      clientConnectViaProxy = lib
+          .lookupFunction<
+            Int32 Function(
+              Pointer<CcQuicConfig>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Uint16,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Uint32,
+              Int64,
+              Pointer<Uint64>,
+            ),
+            int Function(
+              Pointer<CcQuicConfig>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              int,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              int,
+              int,
+              Pointer<Uint64>,
+            )
+          >('cc_quic_client_connect_via_proxy'),
       serverStart = lib
           .lookupFunction<
             Int32 Function(
@@ -514,6 +4220,31 @@ class _NativeBindings {
               Pointer<Uint64>,
             )
           >('cc_quic_server_start'),
+      serverStartMulti = lib
+          .lookupFunction<
+            Int32 Function(
+              Pointer<CcQuicConfig>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Int64,
+              Pointer<Uint64>,
+              Pointer<Utf8>,
+              UintPtr,
+            ),
+            int Function(
+              Pointer<CcQuicConfig>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+              int,
+              Pointer<Uint64>,
+              Pointer<Utf8>,
+              int,
+            )
+          >('cc_quic_server_start_multi'),
       send = lib
           .lookupFunction<
             Int32 Function(
@@ -525,15 +4256,378 @@ class _NativeBindings {
             ),
             int Function(int, Pointer<Uint8>, int, Pointer<Uint8>, int)
           >('cc_quic_conn_send'),
+      controlSend = lib
+          .lookupFunction<
+            Int32 Function(
+              Uint64,
+              Pointer<Uint8>,
+              IntPtr,
+              Pointer<Uint8>,
+              IntPtr,
+            ),
+            int Function(int, Pointer<Uint8>, int, Pointer<Uint8>, int)
+          >('cc_quic_control_send'),
+      mediaSend = lib
+          .lookupFunction<
+            Int32 Function(
+              Uint64,
+              Pointer<Uint8>,
+              IntPtr,
+              Uint32,
+              Bool,
+              Pointer<Uint8>,
+              IntPtr,
+            ),
+            int Function(int, Pointer<Uint8>, int, int, bool, Pointer<Uint8>, int)
+          >('cc_quic_media_send'),
+      mediaSetFec = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Uint8>, IntPtr, Uint32, Uint32),
+            int Function(int, Pointer<Uint8>, int, int, int)
+          >('cc_quic_media_set_fec'),
+      mediaSetRedundancy = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Uint8>, IntPtr, Uint32, Uint32),
+            int Function(int, Pointer<Uint8>, int, int, int)
+          >('cc_quic_media_set_redundancy'),
+      timesyncStart = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Uint8>, IntPtr, Uint32),
+            int Function(int, Pointer<Uint8>, int, int)
+          >('cc_quic_timesync_start'),
+      identityRotate = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Utf8>, Pointer<Utf8>),
+            int Function(int, Pointer<Utf8>, Pointer<Utf8>)
+          >('cc_quic_identity_rotate'),
+      serverBlockAddr = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Utf8>),
+            int Function(int, Pointer<Utf8>)
+          >('cc_quic_server_block_addr'),
+      serverUnblockAddr = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Utf8>),
+            int Function(int, Pointer<Utf8>)
+          >('cc_quic_server_unblock_addr'),
+      serverSetPreferredAddress = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Utf8>),
+            int Function(int, Pointer<Utf8>)
+          >('cc_quic_server_set_preferred_address'),
+      serverRequestConnect = lib
+          .lookupFunction<
+            Int32 Function(
+              Uint64,
+              Pointer<Uint8>,
+              UintPtr,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+            ),
+            int Function(
+              int,
+              Pointer<Uint8>,
+              int,
+              Pointer<Utf8>,
+              Pointer<Utf8>,
+            )
+          >('cc_quic_server_request_connect'),
+      usageStats = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Uint8>, UintPtr, Pointer<UintPtr>),
+            int Function(int, Pointer<Uint8>, int, Pointer<UintPtr>)
+          >('cc_quic_usage_stats'),
+      serverSetPeerLabel = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Utf8>, Pointer<Utf8>),
+            int Function(int, Pointer<Utf8>, Pointer<Utf8>)
+          >('cc_quic_server_set_peer_label'),
+      serverSetAllowlistFile = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Utf8>),
+            int Function(int, Pointer<Utf8>)
+          >('cc_quic_server_set_allowlist_file'),
+      diagnosticsDump = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Utf8>),
+            int Function(int, Pointer<Utf8>)
+          >('cc_quic_diagnostics_dump'),
+      metricsServe = lib.providesSymbol('cc_quic_metrics_serve')
+          ? lib.lookupFunction<
+              Int32 Function(Pointer<Utf8>),
+              int Function(Pointer<Utf8>)
+            >('cc_quic_metrics_serve')
+          : null,
+      serverReloadAllowlist = lib
+          .lookupFunction<Int32 Function(Uint64), int Function(int)>(
+            'cc_quic_server_reload_allowlist',
+          ),
+      authMintToken = lib
+          .lookupFunction<
+            Int32 Function(
+              Pointer<Uint8>,
+              UintPtr,
+              Pointer<Utf8>,
+              Uint64,
+              Pointer<Utf8>,
+              UintPtr,
+              Pointer<UintPtr>,
+            ),
+            int Function(
+              Pointer<Uint8>,
+              int,
+              Pointer<Utf8>,
+              int,
+              Pointer<Utf8>,
+              int,
+              Pointer<UintPtr>,
+            )
+          >('cc_quic_auth_mint_token'),
+      serverAuthVerdict = lib
+          .lookupFunction<
+            Int32 Function(
+              Uint64,
+              Pointer<Uint8>,
+              UintPtr,
+              Bool,
+              Pointer<Utf8>,
+            ),
+            int Function(int, Pointer<Uint8>, int, bool, Pointer<Utf8>)
+          >('cc_quic_server_auth_verdict'),
+      serverDecide = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Uint8>, UintPtr, Bool),
+            int Function(int, Pointer<Uint8>, int, bool)
+          >('cc_quic_server_decide'),
+      serverRevokeUser = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Utf8>, Bool),
+            int Function(int, Pointer<Utf8>, bool)
+          >('cc_quic_server_revoke_user'),
+      topicSubscribe = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Utf8>),
+            int Function(int, Pointer<Utf8>)
+          >('cc_quic_topic_subscribe'),
+      topicUnsubscribe = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Utf8>),
+            int Function(int, Pointer<Utf8>)
+          >('cc_quic_topic_unsubscribe'),
+      serverPublish = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Utf8>, Pointer<Uint8>, IntPtr),
+            int Function(int, Pointer<Utf8>, Pointer<Uint8>, int)
+          >('cc_quic_server_publish'),
+      channelOpen = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Uint8>, IntPtr, Pointer<Utf8>),
+            int Function(int, Pointer<Uint8>, int, Pointer<Utf8>)
+          >('cc_quic_channel_open'),
+      channelSend = lib
+          .lookupFunction<
+            Int32 Function(
+              Uint64,
+              Pointer<Uint8>,
+              IntPtr,
+              Pointer<Utf8>,
+              Pointer<Uint8>,
+              IntPtr,
+            ),
+            int Function(
+              int,
+              Pointer<Uint8>,
+              int,
+              Pointer<Utf8>,
+              Pointer<Uint8>,
+              int,
+            )
+          >('cc_quic_channel_send'),
+      channelClose = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Uint8>, IntPtr, Pointer<Utf8>),
+            int Function(int, Pointer<Uint8>, int, Pointer<Utf8>)
+          >('cc_quic_channel_close'),
+      streamReset = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Uint8>, IntPtr, Uint64, Uint64),
+            int Function(int, Pointer<Uint8>, int, int, int)
+          >('cc_quic_stream_reset'),
+      streamStopSending = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Uint8>, IntPtr, Uint64, Uint64),
+            int Function(int, Pointer<Uint8>, int, int, int)
+          >('cc_quic_stream_stop_sending'),
+      connSetRateLimit = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Uint8>, IntPtr, Uint64),
+            int Function(int, Pointer<Uint8>, int, int)
+          >('cc_quic_conn_set_rate_limit'),
+      connSetPacing = lib
+          .lookupFunction<
+            Int32 Function(
+              Uint64,
+              Pointer<Uint8>,
+              IntPtr,
+              Bool,
+              Uint32,
+              Uint32,
+            ),
+            int Function(int, Pointer<Uint8>, int, bool, int, int)
+          >('cc_quic_conn_set_pacing'),
+      connSetTag = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Pointer<Uint8>, IntPtr, Uint64),
+            int Function(int, Pointer<Uint8>, int, int)
+          >('cc_quic_conn_set_tag'),
+      connExportKeyingMaterial = lib
+          .lookupFunction<
+            Int32 Function(
+              Uint64,
+              Pointer<Uint8>,
+              IntPtr,
+              Pointer<Utf8>,
+              Pointer<Uint8>,
+              IntPtr,
+              Pointer<Uint8>,
+              IntPtr,
+            ),
+            int Function(
+              int,
+              Pointer<Uint8>,
+              int,
+              Pointer<Utf8>,
+              Pointer<Uint8>,
+              int,
+              Pointer<Uint8>,
+              int,
+            )
+          >('cc_quic_conn_export_keying_material'),
+      connCapture = lib
+          .lookupFunction<
+            Int32 Function(
+              Uint64,
+              Pointer<Uint8>,
+              IntPtr,
+              Pointer<Utf8>,
+              Uint64,
+            ),
+            int Function(int, Pointer<Uint8>, int, Pointer<Utf8>, int)
+          >('cc_quic_conn_capture'),
+      connSetImpairment = lib.providesSymbol('cc_quic_conn_set_impairment')
+          ? lib.lookupFunction<
+              Int32 Function(
+                Uint64,
+                Pointer<Uint8>,
+                IntPtr,
+                Float,
+                Float,
+                Uint32,
+                Uint32,
+                Uint32,
+                Uint64,
+              ),
+              int Function(
+                int,
+                Pointer<Uint8>,
+                int,
+                double,
+                double,
+                int,
+                int,
+                int,
+                int,
+              )
+            >('cc_quic_conn_set_impairment')
+          : null,
       close = lib.lookupFunction<Int32 Function(Uint64), int Function(int)>(
         'cc_quic_conn_close',
-      );
+      ),
+      setThreadPriority = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Int32),
+            int Function(int, int)
+          >('cc_quic_set_thread_priority'),
+      subscribe = lib
+          .lookupFunction<
+            Int32 Function(Uint64, Int64, Uint32),
+            int Function(int, int, int)
+          >('cc_quic_subscribe');
 
   final int Function(Pointer<Void>) initDartApi;
   final int Function() initLogging;
   final Pointer<Utf8> Function() version;
+  final int Function() abiVersion;
   final int Function(Pointer<Pointer<CcQuicConfig>>) configNew;
+  final int Function(Pointer<Pointer<CcQuicConfig>>) configNewClient;
+  final int Function(Pointer<Pointer<CcQuicConfig>>) configNewServer;
+  final int Function(Pointer<Utf8>, Pointer<Pointer<CcQuicConfig>>)
+  configNewPreset;
+  final int Function(Pointer<Utf8>, Pointer<Pointer<CcQuicConfig>>)
+  configFromJson;
+  final int Function(Pointer<Uint8>, int) lastError;
+  final int Function(Pointer<Utf8>, int) auditEnable;
+  final int Function(int, Pointer<Uint8>, int, Pointer<UintPtr>) auditQuery;
+  final _KeyCryptFn keySeal;
+  final _KeyCryptFn keyOpen;
   final void Function(Pointer<CcQuicConfig>) configFree;
+  final int Function(Pointer<CcQuicConfig>, bool) configSetEcn;
+  final int Function(Pointer<CcQuicConfig>, bool) configSetPeerCertExport;
+  final int Function(Pointer<CcQuicConfig>, bool)
+  configSetKeyingMaterialExport;
+  final int Function(Pointer<CcQuicConfig>, bool) configSetWritableEvents;
+  final int Function(Pointer<CcQuicConfig>, bool) configSetObservedAddress;
+  final int Function(Pointer<CcQuicConfig>, bool) configSetRoster;
+  final int Function(Pointer<CcQuicConfig>, int) configSetCapabilities;
+  final int Function(Pointer<CcQuicConfig>, Pointer<Utf8>) configSetAuthToken;
+  final int Function(Pointer<CcQuicConfig>, Pointer<Uint8>, int)
+  configSetAuthHmac;
+  final int Function(Pointer<CcQuicConfig>, bool) configSetAuthCallback;
+  final int Function(Pointer<CcQuicConfig>, bool) configSetClientCertOptional;
+  final int Function(Pointer<CcQuicConfig>, bool) configSetRequireClientCert;
+  final int Function(Pointer<CcQuicConfig>, int) configSetConnectionApproval;
+  final int Function(
+    Pointer<CcQuicConfig>,
+    Pointer<Utf8>,
+    Pointer<Utf8>,
+    Pointer<Utf8>,
+    Pointer<Utf8>,
+  )
+  configAddSniIdentity;
+  final int Function(Pointer<CcQuicConfig>, bool) configSetCoalesce;
+  final int Function(Pointer<CcQuicConfig>, int) configSetCwndResume;
+  final int Function(Pointer<CcQuicConfig>, bool, Pointer<Utf8>)
+  configSetReverseConnect;
+  final int Function(Pointer<CcQuicConfig>, int, int) configSetTcpFallback;
+  final int Function(Pointer<CcQuicConfig>, Pointer<Utf8>) configSetCaPath;
+  final int Function(Pointer<CcQuicConfig>, int) configSetCertExpiryWarning;
+  final int Function(Pointer<CcQuicConfig>, bool, int) configSetPmtuDiscovery;
+  final int Function(Pointer<CcQuicConfig>, bool) configSetUdpOffload;
+  final int Function(Pointer<CcQuicConfig>, bool) configSetHystart;
+  final int Function(Pointer<CcQuicConfig>, bool, int) configSetPacing;
+  final int Function(Pointer<CcQuicConfig>, int, int) configSetAckDelay;
+  final int Function(Pointer<CcQuicConfig>, int) configSetStatsInterval;
+  final int Function(Pointer<CcQuicConfig>, bool) configSetSharedRuntime;
+  final int Function(Pointer<CcQuicConfig>, int) configSetServerWorkers;
+  final int Function(Pointer<CcQuicConfig>, int, int)
+  configSetConnectionLimits;
+  final int Function(Pointer<CcQuicConfig>, int) configSetWatchdog;
+  final int Function(Pointer<CcQuicConfig>, int, int) configSetDgramQueues;
+  final int Function(Pointer<CcQuicConfig>, int) configSetDgramDropPolicy;
+  final int Function(Pointer<CcQuicConfig>, int) configSetMediaReorderWindow;
+  final int Function(Pointer<CcQuicConfig>, int, int, int)
+  configSetJitterBuffer;
+  final int Function(Pointer<CcQuicConfig>, int, int) configSetCompression;
+  final int Function(Pointer<CcQuicConfig>, int, int) configSetFlowWindow;
+  final int Function(Pointer<CcQuicConfig>, int, int) configSetRecvLimits;
+  final int Function(Pointer<CcQuicConfig>, int, int) configSetDualChannel;
+  final int Function(Pointer<CcQuicConfig>, bool) configSetDatagramsFirst;
+  final int Function(Pointer<CcQuicConfig>, int) configSetEventSchema;
+  final int Function(Pointer<CcQuicConfig>, int, int) configSetEventRateLimit;
+  final int Function(Pointer<CcQuicConfig>, int) configSetErrorCoalescing;
+  final int Function() eventSchemaVersion;
+  final int Function(Pointer<CcQuicConfig>, Pointer<CcQuicConfig>)
+  testLoopbackPair;
   final int Function(
     Pointer<CcQuicConfig>,
     Pointer<Utf8>,
@@ -543,9 +4637,26 @@ class _NativeBindings {
     Pointer<Utf8>,
     Pointer<Utf8>,
     int,
+    int,
     Pointer<Uint64>,
   )
   clientConnect;
+  final int Function(
+    Pointer<CcQuicConfig>,
+    Pointer<Utf8>,
+    Pointer<Utf8>,
+    Pointer<Utf8>,
+    Pointer<Utf8>,
+    int,
+    Pointer<Utf8>,
+    Pointer<Utf8>,
+    Pointer<Utf8>,
+    Pointer<Utf8>,
+    int,
+    int,
+    Pointer<Uint64>,
+  )
+  clientConnectViaProxy;
   final int Function(
     Pointer<CcQuicConfig>,
     Pointer<Utf8>,
@@ -557,6 +4668,18 @@ class _NativeBindings {
     Pointer<Uint64>,
   )
   serverStart;
+  final int Function(
+    Pointer<CcQuicConfig>,
+    Pointer<Utf8>,
+    Pointer<Utf8>,
+    Pointer<Utf8>,
+    Pointer<Utf8>,
+    int,
+    Pointer<Uint64>,
+    Pointer<Utf8>,
+    int,
+  )
+  serverStartMulti;
   final int Function(
     int,
     Pointer<Uint8>,
@@ -564,7 +4687,89 @@ class _NativeBindings {
     Pointer<Uint8>,
     int,
   ) send;
+  final int Function(int, Pointer<Uint8>, int, Pointer<Uint8>, int)
+  controlSend;
+  final int Function(int, Pointer<Uint8>, int, int, bool, Pointer<Uint8>, int)
+  mediaSend;
+  final int Function(int, Pointer<Uint8>, int, int, int) mediaSetFec;
+  final int Function(int, Pointer<Uint8>, int, int, int) mediaSetRedundancy;
+  final int Function(int, Pointer<Uint8>, int, int) timesyncStart;
+  final int Function(int, Pointer<Utf8>, Pointer<Utf8>) identityRotate;
+  final int Function(int, Pointer<Utf8>) serverBlockAddr;
+  final int Function(int, Pointer<Utf8>) serverUnblockAddr;
+  final int Function(int, Pointer<Utf8>) serverSetPreferredAddress;
+  final int Function(int, Pointer<Uint8>, int, Pointer<Utf8>, Pointer<Utf8>)
+  serverRequestConnect;
+  final int Function(int, Pointer<Uint8>, int, Pointer<UintPtr>) usageStats;
+  final int Function(int, Pointer<Utf8>, Pointer<Utf8>) serverSetPeerLabel;
+  final int Function(int, Pointer<Utf8>) serverSetAllowlistFile;
+  final int Function(int, Pointer<Utf8>) diagnosticsDump;
+
+  /// Null unless the native library was built with `metrics`.
+  final int Function(Pointer<Utf8>)? metricsServe;
+  final int Function(int) serverReloadAllowlist;
+  final int Function(
+    Pointer<Uint8>,
+    int,
+    Pointer<Utf8>,
+    int,
+    Pointer<Utf8>,
+    int,
+    Pointer<UintPtr>,
+  )
+  authMintToken;
+  final int Function(int, Pointer<Uint8>, int, bool, Pointer<Utf8>)
+  serverAuthVerdict;
+  final int Function(int, Pointer<Uint8>, int, bool) serverDecide;
+  final int Function(int, Pointer<Utf8>, bool) serverRevokeUser;
+  final int Function(int, Pointer<Utf8>) topicSubscribe;
+  final int Function(int, Pointer<Utf8>) topicUnsubscribe;
+  final int Function(int, Pointer<Utf8>, Pointer<Uint8>, int) serverPublish;
+  final int Function(int, Pointer<Uint8>, int, Pointer<Utf8>) channelOpen;
+  final int Function(
+    int,
+    Pointer<Uint8>,
+    int,
+    Pointer<Utf8>,
+    Pointer<Uint8>,
+    int,
+  )
+  channelSend;
+  final int Function(int, Pointer<Uint8>, int, Pointer<Utf8>) channelClose;
+  final int Function(int, Pointer<Uint8>, int, int, int) streamReset;
+  final int Function(int, Pointer<Uint8>, int, int, int) streamStopSending;
+  final int Function(int, Pointer<Uint8>, int, int) connSetRateLimit;
+  final int Function(int, Pointer<Uint8>, int, bool, int, int) connSetPacing;
+  final int Function(int, Pointer<Uint8>, int, int) connSetTag;
+  final int Function(
+    int,
+    Pointer<Uint8>,
+    int,
+    Pointer<Utf8>,
+    Pointer<Uint8>,
+    int,
+    Pointer<Uint8>,
+    int,
+  )
+  connExportKeyingMaterial;
+  final int Function(int, Pointer<Uint8>, int, Pointer<Utf8>, int) connCapture;
+
+  /// Null unless the native library was built with `impairment`.
+  final int Function(
+    int,
+    Pointer<Uint8>,
+    int,
+    double,
+    double,
+    int,
+    int,
+    int,
+    int,
+  )?
+  connSetImpairment;
   final int Function(int) close;
+  final int Function(int, int) setThreadPriority;
+  final int Function(int, int, int) subscribe;
 }
 
 final class CcQuicConfig extends Opaque {}
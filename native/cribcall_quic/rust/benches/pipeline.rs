@@ -0,0 +1,221 @@
+//! Benchmarks for the event pipeline's hot path: a client sends messages,
+//! the server's worker reads them off the control stream, frames and
+//! serializes each one as a `message` event and posts it through the Dart
+//! API, here a stand-in post function that counts the payload bytes. Each
+//! case reports the time `cc_quic_control_send` takes per call (the send
+//! path up to the worker's command queue) and the end-to-end rate until
+//! every byte has been posted.
+//!
+//! The harness is a small loop with warm-up and median reporting rather
+//! than criterion, so the crate still builds offline. Cases run over the
+//! in-memory loopback by default; set `CRIBCALL_BENCH_UDP=1` to go through
+//! 127.0.0.1 sockets instead, which is what UDP offload changes affect.
+//!
+//! ```sh
+//! CRIBCALL_BENCH_CERTS=dir SSL_CERT_FILE=dir/ca.pem cargo bench [filter]
+//! ```
+//!
+//! `dir` holds `srv.crt`, `srv.key`, `cli.crt` and `cli.key`, both signed
+//! by `ca.pem`; `CRIBCALL_BENCH_SERVER_NAME` names the server certificate's
+//! host (default `localhost`). Without `CRIBCALL_BENCH_CERTS` the
+//! benchmarks are skipped.
+
+use base64::Engine;
+use cribcall_quic::{
+    cc_quic_client_connect, cc_quic_config_new_client, cc_quic_config_new_server,
+    cc_quic_config_set_dual_channel, cc_quic_conn_close, cc_quic_control_send,
+    cc_quic_init_dart_api, cc_quic_server_start, cc_quic_test_loopback_pair, CcQuicConfig,
+    CC_QUIC_ALLOW_UNPINNED,
+};
+use serde_json::Value;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const SERVER_PORT: i64 = 1;
+const CLIENT_PORT: i64 = 2;
+const UDP_PORT: u16 = 4480;
+/// Message sizes, from per-event overhead to read-buffer sized chunks.
+const SIZES: [usize; 4] = [64, 1024, 16 * 1024, 64 * 1024];
+/// Payload bytes sent per sample.
+const SAMPLE_BYTES: usize = 4 * 1024 * 1024;
+const WARMUP: usize = 2;
+const SAMPLES: usize = 10;
+const SAMPLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `message` payload bytes the server has posted.
+static RECEIVED: AtomicU64 = AtomicU64::new(0);
+/// The client's connection id, once `connected` is posted.
+static CONN_ID: Mutex<Option<String>> = Mutex::new(None);
+
+/// The leading fields of `Dart_CObject` for a string message.
+#[repr(C)]
+struct CObject {
+    ty: i32,
+    string: *const c_char,
+}
+
+/// `Dart_CObject_kString`.
+const STRING_TYPE: i32 = 5;
+
+/// Stands in for `Dart_PostCObject`: decodes each event as Dart would.
+unsafe extern "C" fn post(port: i64, message: *mut CObject) -> bool {
+    let message = &*message;
+    if message.ty != STRING_TYPE {
+        return true;
+    }
+    let Ok(event) = serde_json::from_slice::<Value>(CStr::from_ptr(message.string).to_bytes())
+    else {
+        return true;
+    };
+    match (port, event["type"].as_str()) {
+        (SERVER_PORT, Some("message")) => {
+            let data = event["data_base64"].as_str().unwrap_or_default();
+            let len = base64::engine::general_purpose::STANDARD
+                .decode(data)
+                .map_or(0, |bytes| bytes.len());
+            RECEIVED.fetch_add(len as u64, Ordering::Relaxed);
+        }
+        (CLIENT_PORT, Some("connected")) => {
+            let id = event["connection_id"].as_str().map(str::to_string);
+            *CONN_ID.lock().unwrap() = id;
+        }
+        (_, Some("error" | "closed" | "worker_died")) => eprintln!("{event}"),
+        _ => {}
+    }
+    true
+}
+
+struct Bench {
+    client: u64,
+    conn_id: String,
+}
+
+impl Bench {
+    fn connect(certs: &Path, server_name: &CStr, udp: bool) -> Self {
+        let path = |name: &str| CString::new(certs.join(name).to_str().unwrap()).unwrap();
+        let mut server_config: *mut CcQuicConfig = std::ptr::null_mut();
+        let mut client_config: *mut CcQuicConfig = std::ptr::null_mut();
+        assert_eq!(cc_quic_config_new_server(&mut server_config), 0);
+        assert_eq!(cc_quic_config_new_client(&mut client_config), 0);
+        // A whole sample may wait for stream credit.
+        assert_eq!(
+            cc_quic_config_set_dual_channel(client_config, 0, 2 * SAMPLE_BYTES as u32),
+            0
+        );
+        if !udp {
+            assert_eq!(cc_quic_test_loopback_pair(server_config, client_config), 0);
+        }
+        let (bind, host) = (c"127.0.0.1", c"127.0.0.1");
+        let mut server = 0;
+        let mut client = 0;
+        assert_eq!(
+            cc_quic_server_start(
+                server_config,
+                bind.as_ptr(),
+                UDP_PORT,
+                path("srv.crt").as_ptr(),
+                path("srv.key").as_ptr(),
+                c"".as_ptr(),
+                SERVER_PORT,
+                &mut server,
+            ),
+            0,
+            "server_start"
+        );
+        assert_eq!(
+            cc_quic_client_connect(
+                client_config,
+                host.as_ptr(),
+                UDP_PORT,
+                server_name.as_ptr(),
+                c"".as_ptr(),
+                path("cli.crt").as_ptr(),
+                path("cli.key").as_ptr(),
+                CC_QUIC_ALLOW_UNPINNED,
+                CLIENT_PORT,
+                &mut client,
+            ),
+            0,
+            "client_connect"
+        );
+        let started = Instant::now();
+        let conn_id = loop {
+            if let Some(id) = CONN_ID.lock().unwrap().clone() {
+                break id;
+            }
+            assert!(started.elapsed() < SAMPLE_TIMEOUT, "not connected");
+            std::thread::sleep(Duration::from_millis(5));
+        };
+        Self { client, conn_id }
+    }
+
+    /// Sends `count` messages of `size` bytes; the time spent in the send
+    /// calls and the time until the server posted them all.
+    fn sample(&self, size: usize, count: usize) -> (Duration, Duration) {
+        let payload = vec![0x5a; size];
+        let target = RECEIVED.load(Ordering::Relaxed) + (size * count) as u64;
+        let started = Instant::now();
+        for _ in 0..count {
+            let status = cc_quic_control_send(
+                self.client,
+                self.conn_id.as_ptr(),
+                self.conn_id.len(),
+                payload.as_ptr(),
+                payload.len(),
+            );
+            assert_eq!(status, 0, "control_send");
+        }
+        let sent = started.elapsed();
+        while RECEIVED.load(Ordering::Relaxed) < target {
+            assert!(started.elapsed() < SAMPLE_TIMEOUT, "messages lost");
+            std::thread::yield_now();
+        }
+        (sent, started.elapsed())
+    }
+}
+
+fn median(mut samples: Vec<Duration>) -> Duration {
+    samples.sort();
+    samples[samples.len() / 2]
+}
+
+fn main() {
+    let Some(certs) = std::env::var_os("CRIBCALL_BENCH_CERTS") else {
+        eprintln!("CRIBCALL_BENCH_CERTS is not set; skipping the pipeline benchmarks");
+        return;
+    };
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let udp = std::env::var_os("CRIBCALL_BENCH_UDP").is_some_and(|value| value == "1");
+    assert_eq!(cc_quic_init_dart_api(post as *mut c_void), 0);
+    let server_name = std::env::var("CRIBCALL_BENCH_SERVER_NAME");
+    let server_name = CString::new(server_name.as_deref().unwrap_or("localhost")).unwrap();
+    let bench = Bench::connect(Path::new(&certs), &server_name, udp);
+
+    let transport = if udp { "udp" } else { "loopback" };
+    for size in SIZES {
+        let name = format!("pipeline/{transport}/{size}B");
+        if filter
+            .as_ref()
+            .is_some_and(|filter| !name.contains(filter.as_str()))
+        {
+            continue;
+        }
+        let count = SAMPLE_BYTES / size;
+        for _ in 0..WARMUP {
+            bench.sample(size, count);
+        }
+        let (sends, totals): (Vec<_>, Vec<_>) =
+            (0..SAMPLES).map(|_| bench.sample(size, count)).unzip();
+        let (send, total) = (median(sends), median(totals));
+        println!(
+            "{name:<28} send {:>8.0} ns/msg  end-to-end {:>8.0} ns/msg {:>9.1} MiB/s",
+            send.as_nanos() as f64 / count as f64,
+            total.as_nanos() as f64 / count as f64,
+            SAMPLE_BYTES as f64 / total.as_secs_f64() / (1024.0 * 1024.0),
+        );
+    }
+    cc_quic_conn_close(bench.client);
+}
@@ -0,0 +1,341 @@
+//! The fingerprints a server trusts, shared by its workers and the FFI.
+//! They come from the CSV given to `cc_quic_server_start` or, with
+//! `cc_quic_server_set_allowlist_file`, from a file other app components
+//! can manage: the lead worker checks its modification time and size every
+//! second and reloads it on change, and `cc_quic_server_reload_allowlist`
+//! reloads it at once. Connections whose fingerprint drops out are closed.
+//!
+//! Entries are separated by commas or newlines; `#` starts a comment. Each
+//! is a hex fingerprint, or `*` for any client, followed by optional
+//! `;`-separated attributes:
+//!
+//! ```text
+//! 3f0a…e1;label=grandma-phone;expires=2026-12-31
+//! *;label=pairing;expires=1767225600
+//! ```
+//!
+//! - `label`: shown in the server's `connected` event.
+//! - `expires`: Unix seconds, `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SSZ` (UTC);
+//!   checked when a client connects and when the list is reloaded, not
+//!   while a connection is open.
+//!
+//! An entry with an unknown attribute or a bad value is skipped with a
+//! warning. An empty CSV trusts every client, an empty file none, so a
+//! truncated file never opens the server up. Replace the file by renaming
+//! a new one over it; a reload mid-write would see a partial list.
+
+use crate::certexpiry::{days_from_civil, SECS_PER_DAY};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How often the lead worker looks at the file.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const WILDCARD: &str = "*";
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Entry {
+    pub label: Option<String>,
+    /// Unix seconds.
+    pub expires: Option<i64>,
+}
+
+impl Entry {
+    fn valid_at(&self, now: i64) -> bool {
+        self.expires.is_none_or(|expires| now < expires)
+    }
+}
+
+/// `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SSZ` in UTC, or Unix seconds.
+fn parse_expiry(text: &str) -> Option<i64> {
+    if let Ok(secs) = text.parse::<i64>() {
+        return Some(secs);
+    }
+    let (date, time) = match text.split_once('T') {
+        Some((date, time)) => (date, Some(time.strip_suffix('Z')?)),
+        None => (text, None),
+    };
+    let mut date = date.split('-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    if date.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let secs = match time {
+        None => 0,
+        Some(time) => {
+            let mut time = time.split(':').map(|part| part.parse::<i64>().ok());
+            let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+            if time.next().is_some() || hour > 23 || minute > 59 || second > 60 {
+                return None;
+            }
+            hour * 3600 + minute * 60 + second
+        }
+    };
+    Some(days_from_civil(year, month, day) * SECS_PER_DAY + secs)
+}
+
+fn parse_entry(text: &str) -> Option<(String, Entry)> {
+    let mut parts = text.split(';').map(str::trim);
+    let fingerprint = parts.next()?.to_lowercase();
+    let mut entry = Entry::default();
+    for attr in parts.filter(|attr| !attr.is_empty()) {
+        match attr
+            .split_once('=')
+            .map(|(key, value)| (key.trim(), value.trim()))
+        {
+            Some(("label", label)) if !label.is_empty() => entry.label = Some(label.to_string()),
+            Some(("expires", expires)) => entry.expires = Some(parse_expiry(expires)?),
+            _ => return None,
+        }
+    }
+    Some((fingerprint, entry))
+}
+
+/// Entries in a CSV or allowlist file, keyed by lowercase fingerprint.
+pub(crate) fn parse(text: &str) -> HashMap<String, Entry> {
+    text.lines()
+        .map(|line| line.split_once('#').map_or(line, |(entry, _)| entry))
+        .flat_map(|line| line.split(','))
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|text| {
+            let parsed = parse_entry(text);
+            if parsed.is_none() {
+                log::warn!("skipping bad allowlist entry {text:?}");
+            }
+            parsed
+        })
+        .collect()
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64)
+}
+
+struct Trusted {
+    entries: HashMap<String, Entry>,
+    /// No list at all: every client is trusted.
+    open: bool,
+}
+
+struct Watched {
+    path: PathBuf,
+    stamp: Option<(SystemTime, u64)>,
+    checked: Instant,
+    /// The last check failed and was reported; quiet until one succeeds.
+    failing: bool,
+}
+
+pub(crate) struct Allowlist {
+    trusted: Mutex<Trusted>,
+    /// Bumped on every change, so workers re-check their connections.
+    version: AtomicU64,
+    watched: Mutex<Option<Watched>>,
+}
+
+fn stamp(path: &PathBuf) -> io::Result<(SystemTime, u64)> {
+    let meta = std::fs::metadata(path)?;
+    Ok((meta.modified()?, meta.len()))
+}
+
+impl Allowlist {
+    pub(crate) fn new(entries: HashMap<String, Entry>) -> Self {
+        Self {
+            trusted: Mutex::new(Trusted {
+                open: entries.is_empty(),
+                entries,
+            }),
+            version: AtomicU64::new(0),
+            watched: Mutex::new(None),
+        }
+    }
+
+    fn trusted(&self) -> MutexGuard<'_, Trusted> {
+        self.trusted.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn watched(&self) -> MutexGuard<'_, Option<Watched>> {
+        self.watched.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn replace(&self, entries: HashMap<String, Entry>) -> usize {
+        let len = entries.len();
+        *self.trusted() = Trusted {
+            entries,
+            open: false,
+        };
+        self.version.fetch_add(1, Ordering::Relaxed);
+        len
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.trusted().entries.len()
+    }
+
+    pub(crate) fn version(&self) -> u64 {
+        self.version.load(Ordering::Relaxed)
+    }
+
+    /// Whether a client with `fingerprint` may connect now: its entry's
+    /// label if so, else why not.
+    pub(crate) fn admit(&self, fingerprint: &str) -> Result<Option<String>, &'static str> {
+        self.admit_at(fingerprint, unix_now())
+    }
+
+    fn admit_at(&self, fingerprint: &str, now: i64) -> Result<Option<String>, &'static str> {
+        let trusted = self.trusted();
+        if trusted.open {
+            return Ok(None);
+        }
+        let matches = [fingerprint, WILDCARD].map(|key| trusted.entries.get(key));
+        if let Some(entry) = matches.iter().flatten().find(|entry| entry.valid_at(now)) {
+            return Ok(entry.label.clone());
+        }
+        if matches[0].is_some() {
+            Err("allowlist entry expired")
+        } else {
+            Err("untrusted client")
+        }
+    }
+
+    /// Trusts `new` as well, with the same label and expiry, if `old` is
+    /// listed, so a peer that rotated its identity is still let in.
+    pub(crate) fn rotate(&self, old: &str, new: &str) {
+        let mut trusted = self.trusted();
+        if let Some(entry) = trusted.entries.get(old).cloned() {
+            trusted.entries.insert(new.to_string(), entry);
+        }
+    }
+
+    /// Loads `path` and watches it from now on; `None` stops watching and
+    /// keeps the current list. Returns the number of entries.
+    pub(crate) fn watch(&self, path: Option<PathBuf>) -> io::Result<usize> {
+        let mut watched = self.watched();
+        let Some(path) = path else {
+            *watched = None;
+            return Ok(self.len());
+        };
+        let stamp = stamp(&path)?;
+        let len = self.replace(parse(&std::fs::read_to_string(&path)?));
+        *watched = Some(Watched {
+            path,
+            stamp: Some(stamp),
+            checked: Instant::now(),
+            failing: false,
+        });
+        Ok(len)
+    }
+
+    /// Reloads the watched file now; `None` if there is none.
+    pub(crate) fn reload(&self) -> Option<io::Result<usize>> {
+        let mut watched = self.watched();
+        let watched = watched.as_mut()?;
+        Some(self.load(watched))
+    }
+
+    /// Reloads the watched file if it changed since the last look, at most
+    /// once per second. `Some` when it reloaded or the reload failed; a
+    /// failure is reported once until the file is readable again.
+    pub(crate) fn poll(&self) -> Option<io::Result<usize>> {
+        let mut watched = self.watched();
+        let watched = watched.as_mut()?;
+        if watched.checked.elapsed() < CHECK_INTERVAL {
+            return None;
+        }
+        watched.checked = Instant::now();
+        match stamp(&watched.path) {
+            Ok(stamp) if watched.stamp == Some(stamp) => None,
+            _ if watched.failing => self.load(watched).ok().map(Ok),
+            _ => Some(self.load(watched)),
+        }
+    }
+
+    fn load(&self, watched: &mut Watched) -> io::Result<usize> {
+        let loaded = stamp(&watched.path).and_then(|stamp| {
+            let text = std::fs::read_to_string(&watched.path)?;
+            Ok((stamp, text))
+        });
+        match loaded {
+            Ok((stamp, text)) => {
+                watched.stamp = Some(stamp);
+                watched.failing = false;
+                Ok(self.replace(parse(&text)))
+            }
+            Err(err) => {
+                watched.failing = true;
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reloads_the_file_only_when_it_changes() {
+        let path = std::env::temp_dir().join(format!("cc-allow-{}", std::process::id()));
+        std::fs::write(&path, "AA, bb # phone\n\n# tablet\ncc\n").unwrap();
+        let allowlist = Allowlist::new(HashMap::new());
+        assert!(allowlist.admit("zz").is_ok());
+        assert_eq!(allowlist.watch(Some(path.clone())).unwrap(), 3);
+        assert!(allowlist.admit("aa").is_ok() && allowlist.admit("cc").is_ok());
+        assert_eq!(allowlist.admit("zz"), Err("untrusted client"));
+
+        allowlist.watched().as_mut().unwrap().checked -= CHECK_INTERVAL;
+        assert!(allowlist.poll().is_none());
+
+        std::fs::write(&path, "").unwrap();
+        allowlist.watched().as_mut().unwrap().checked -= CHECK_INTERVAL;
+        assert_eq!(allowlist.poll().unwrap().unwrap(), 0);
+        assert!(allowlist.admit("aa").is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(allowlist.reload().unwrap().is_err());
+        allowlist.watched().as_mut().unwrap().checked -= CHECK_INTERVAL;
+        assert!(allowlist.poll().is_none());
+    }
+
+    #[test]
+    fn labels_expiry_and_wildcards() {
+        let entries = parse(
+            "AA;label=grandma-phone;expires=2026-12-31, bb;expires=2026-01-01T12:00:00Z\n\
+             cc;colour=red, dd;expires=soon, *;label=pairing;expires=1000",
+        );
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries["aa"],
+            Entry {
+                label: Some("grandma-phone".into()),
+                expires: Some(1_798_675_200),
+            }
+        );
+        assert_eq!(entries["bb"].expires, Some(1_767_268_800));
+
+        let allowlist = Allowlist::new(entries);
+        assert_eq!(
+            allowlist.admit_at("aa", 0),
+            Ok(Some("grandma-phone".to_string()))
+        );
+        assert_eq!(
+            allowlist.admit_at("zz", 999),
+            Ok(Some("pairing".to_string()))
+        );
+        assert_eq!(allowlist.admit_at("zz", 1000), Err("untrusted client"));
+        assert_eq!(
+            allowlist.admit_at("bb", 1_767_268_800),
+            Err("allowlist entry expired")
+        );
+        allowlist.rotate("aa", "ee");
+        assert_eq!(
+            allowlist.admit_at("ee", 0),
+            Ok(Some("grandma-phone".to_string()))
+        );
+    }
+}
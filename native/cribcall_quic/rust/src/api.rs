@@ -0,0 +1,621 @@
+//! Safe Rust API for in-process consumers (the relay server, tools, tests):
+//! the same workers the C ABI starts, without raw pointers. A [`Config`]
+//! owns its settings, [`Endpoint::connect`] and [`Endpoint::serve`] start
+//! a worker, and the workers hand the endpoint's events to a channel as
+//! [`Event`]s; only the C ABI serializes them. The `extern "C"` functions
+//! in `ffi.rs` wrap the same start functions and setters.
+//!
+//! Every `cc_quic_config_set_*` setting has a method of the same name,
+//! reached through `Config`'s `DerefMut` (see `settings.rs`).
+
+use std::net::SocketAddr;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use serde_json::{json, Map, Value};
+
+pub use crate::handshake::Cause as HandshakeCause;
+use crate::presets::Preset;
+pub use crate::Error;
+use crate::{CcQuicConfig, ClientTarget, ConfigRole, Proxy, QuicEvent, WorkerCommand};
+
+/// Installs the library's logger, as `cc_quic_init_logging`.
+pub fn init_logging() {
+    crate::diagnostics::install_logger();
+}
+
+/// Endpoint settings; each start takes a copy, so one config can start
+/// several endpoints.
+pub struct Config(pub(crate) Box<CcQuicConfig>);
+
+impl Config {
+    /// Defaults for a client, as `cc_quic_config_new_client`.
+    pub fn client() -> Self {
+        Self::for_role(ConfigRole::Client)
+    }
+
+    /// Defaults for a server, as `cc_quic_config_new_server`.
+    pub fn server() -> Self {
+        Self::for_role(ConfigRole::Server)
+    }
+
+    fn for_role(role: ConfigRole) -> Self {
+        Self(Box::new(CcQuicConfig {
+            role,
+            ..CcQuicConfig::default()
+        }))
+    }
+
+    /// A named preset for either side, as `cc_quic_config_new_preset`.
+    pub fn preset(name: &str) -> Result<Self, Error> {
+        let preset = Preset::from_name(name)
+            .ok_or_else(|| Error::Config(format!("unknown preset {name:?}")))?;
+        let mut config = Self::for_role(ConfigRole::Any);
+        preset.apply(&mut config.0)?;
+        Ok(config)
+    }
+
+    /// A config from a JSON document (see `jsonconfig.rs`).
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        crate::jsonconfig::parse(json)
+            .and_then(|doc| doc.build())
+            .map(|config| Self(Box::new(config)))
+            .map_err(Error::from)
+    }
+}
+
+impl Deref for Config {
+    type Target = CcQuicConfig;
+
+    fn deref(&self) -> &CcQuicConfig {
+        &self.0
+    }
+}
+
+impl DerefMut for Config {
+    fn deref_mut(&mut self) -> &mut CcQuicConfig {
+        &mut self.0
+    }
+}
+
+/// The certificate and key an endpoint presents, as PEM files.
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl Identity {
+    pub fn new(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        Self {
+            cert: cert.into(),
+            key: key.into(),
+        }
+    }
+
+    fn paths(&self) -> Result<(String, String), Error> {
+        let text = |path: &PathBuf| {
+            path.to_str()
+                .map(str::to_string)
+                .ok_or_else(|| Error::CertParse {
+                    path: path.display().to_string(),
+                    detail: "path is not UTF-8".into(),
+                })
+        };
+        Ok((text(&self.cert)?, text(&self.key)?))
+    }
+}
+
+/// How [`Endpoint::connect_with`] reaches and checks the server, beyond
+/// what [`Endpoint::connect`] does.
+#[derive(Clone, Debug, Default)]
+pub struct Dial {
+    /// With an empty pin, accept any server certificate the CA trusts, as
+    /// `CC_QUIC_ALLOW_UNPINNED`.
+    pub unpinned: bool,
+    /// A proxy URL, as `cc_quic_client_connect_via_proxy` takes it:
+    /// `https://` for MASQUE, `socks5://` for SOCKS5.
+    pub proxy: Option<String>,
+    /// `proxy-authorization` for MASQUE, `user:password` for SOCKS5.
+    pub proxy_auth: String,
+    /// A MASQUE proxy's CA file or directory.
+    pub proxy_ca: Option<PathBuf>,
+}
+
+/// An event from an endpoint's worker. The common ones are typed; every
+/// other one is the JSON object the C ABI posts for it.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Event {
+    Connected {
+        connection_id: String,
+        /// `None` for a client admitted without a certificate.
+        peer_fingerprint: Option<String>,
+        /// Server side: the SNI the client asked for.
+        server_name: Option<String>,
+    },
+    Message {
+        connection_id: String,
+        channel: Option<String>,
+        topic: Option<String>,
+        data: Vec<u8>,
+    },
+    Closed {
+        connection_id: String,
+        reason: Option<String>,
+    },
+    /// A connection ended before it was announced.
+    HandshakeFailed {
+        connection_id: String,
+        cause: HandshakeCause,
+        /// The QUIC error code of the close, when either side sent one.
+        error_code: Option<u64>,
+        detail: String,
+        /// Whether the peer closed the connection, when one side did.
+        from_peer: Option<bool>,
+    },
+    Error {
+        connection_id: Option<String>,
+        message: String,
+    },
+    /// The worker panicked; an `Error` follows and the endpoint is done.
+    WorkerDied {
+        message: String,
+    },
+    Other(Value),
+}
+
+impl Event {
+    /// What a worker's `event` is here; an event without a variant becomes
+    /// the JSON value of its fields, without the envelope's `seq`.
+    pub(crate) fn from_quic(event: &QuicEvent<'_>) -> Self {
+        match event {
+            QuicEvent::Connected {
+                connection_id,
+                peer_fingerprint,
+                server_name,
+                ..
+            } => Self::Connected {
+                connection_id: connection_id.clone(),
+                peer_fingerprint: peer_fingerprint.clone(),
+                server_name: server_name.clone(),
+            },
+            QuicEvent::Message {
+                connection_id,
+                channel,
+                topic,
+                data,
+                ..
+            } => Self::Message {
+                connection_id: connection_id.clone(),
+                channel: channel.map(str::to_string),
+                topic: topic.map(str::to_string),
+                data: data.to_vec(),
+            },
+            QuicEvent::Closed {
+                connection_id,
+                reason,
+                ..
+            } => Self::Closed {
+                connection_id: connection_id.clone(),
+                reason: reason.clone(),
+            },
+            QuicEvent::HandshakeFailed {
+                connection_id,
+                failure,
+                ..
+            } => Self::HandshakeFailed {
+                connection_id: connection_id.clone(),
+                cause: failure.cause,
+                error_code: failure.error_code,
+                detail: failure.detail.clone(),
+                from_peer: failure.from_peer,
+            },
+            QuicEvent::Error {
+                connection_id,
+                message,
+                ..
+            } => Self::Error {
+                connection_id: connection_id.clone(),
+                message: message.clone(),
+            },
+            QuicEvent::WorkerDied { message, .. } => Self::WorkerDied {
+                message: message.clone(),
+            },
+            other => Self::Other(serde_json::to_value(other).unwrap_or(Value::Null)),
+        }
+    }
+
+    /// The event as the JSON object the C ABI posts, with the fields its
+    /// variant keeps.
+    pub fn to_json(&self) -> Value {
+        // The fields the C ABI leaves out when unset.
+        let (kind, mut fields, optional): (_, _, &[&str]) = match self {
+            Self::Connected {
+                connection_id,
+                peer_fingerprint,
+                server_name,
+            } => (
+                "connected",
+                json!({
+                    "connection_id": connection_id,
+                    "peer_fingerprint": peer_fingerprint,
+                    "server_name": server_name,
+                }),
+                &["server_name"],
+            ),
+            Self::Message {
+                connection_id,
+                channel,
+                topic,
+                data,
+            } => {
+                use base64::Engine;
+                let data = base64::engine::general_purpose::STANDARD.encode(data);
+                (
+                    "message",
+                    json!({
+                        "connection_id": connection_id,
+                        "channel": channel,
+                        "topic": topic,
+                        "data_base64": data,
+                    }),
+                    &["channel", "topic"],
+                )
+            }
+            Self::Closed {
+                connection_id,
+                reason,
+            } => (
+                "closed",
+                json!({ "connection_id": connection_id, "reason": reason }),
+                &[],
+            ),
+            Self::HandshakeFailed {
+                connection_id,
+                cause,
+                error_code,
+                detail,
+                from_peer,
+            } => (
+                "handshake_failed",
+                json!({
+                    "connection_id": connection_id,
+                    "cause": cause,
+                    "error_code": error_code,
+                    "detail": detail,
+                    "from_peer": from_peer,
+                }),
+                &["error_code", "from_peer"],
+            ),
+            Self::Error {
+                connection_id,
+                message,
+            } => (
+                "error",
+                json!({ "connection_id": connection_id, "message": message }),
+                &[],
+            ),
+            Self::WorkerDied { message } => ("worker_died", json!({ "message": message }), &[]),
+            Self::Other(value) => return value.clone(),
+        };
+        let mut object = Map::new();
+        object.insert("type".into(), kind.into());
+        if let Some(fields) = fields.as_object_mut() {
+            fields.retain(|key, value| !(value.is_null() && optional.contains(&key.as_str())));
+            object.append(fields);
+        }
+        Value::Object(object)
+    }
+}
+
+/// A running client or server handle. Dropping it closes every
+/// connection and ends the worker.
+pub struct Endpoint {
+    handle: u64,
+    events: mpsc::Receiver<Event>,
+    local_addrs: Vec<SocketAddr>,
+}
+
+impl Endpoint {
+    /// Connects to the server at `addr`, checking its certificate against
+    /// `pin` (hex SHA-256). An empty `pin` is an `Error::Config` unless
+    /// [`Dial::unpinned`] is set, through [`Endpoint::connect_with`].
+    pub fn connect(
+        config: &mut Config,
+        addr: SocketAddr,
+        server_name: &str,
+        pin: &str,
+        identity: &Identity,
+    ) -> Result<Self, Error> {
+        Self::connect_with(config, addr, server_name, pin, identity, &Dial::default())
+    }
+
+    /// Like [`Endpoint::connect`], reaching the server as `dial` says.
+    pub fn connect_with(
+        config: &mut Config,
+        addr: SocketAddr,
+        server_name: &str,
+        pin: &str,
+        identity: &Identity,
+        dial: &Dial,
+    ) -> Result<Self, Error> {
+        let handle = start_client(config, addr, server_name, pin, identity, dial)?;
+        let (tx, events) = mpsc::sync_channel(crate::poll::QUEUE_CAPACITY);
+        crate::poll::attach(handle, move |event| tx.try_send(event).is_ok());
+        Ok(Self {
+            handle,
+            events,
+            local_addrs: Vec::new(),
+        })
+    }
+
+    /// Serves every address in `binds` (port 0 picks a free one), trusting
+    /// clients in `trust` (allowlist entries; empty trusts every client).
+    pub fn serve(
+        config: &mut Config,
+        binds: &[SocketAddr],
+        identity: &Identity,
+        trust: &[&str],
+    ) -> Result<Self, Error> {
+        let (handle, local_addrs) = start_server(config, binds, identity, trust)?;
+        let (tx, events) = mpsc::sync_channel(crate::poll::QUEUE_CAPACITY);
+        crate::poll::attach(handle, move |event| tx.try_send(event).is_ok());
+        Ok(Self {
+            handle,
+            events,
+            local_addrs,
+        })
+    }
+
+    /// The connection a reverse connect client dialed back with, from the
+    /// `dialed_handle` of its `reverse_connect` event. Its events wait,
+    /// typed like those of the client that dialed, until this takes them.
+    pub fn dialed_back(handle: u64) -> Result<Self, Error> {
+        crate::handle_entry(handle).map_err(|_| Error::StaleHandle)?;
+        let (tx, events) = mpsc::sync_channel(crate::poll::QUEUE_CAPACITY);
+        crate::poll::attach(handle, move |event| tx.try_send(event).is_ok());
+        Ok(Self {
+            handle,
+            events,
+            local_addrs: Vec::new(),
+        })
+    }
+
+    /// The handle the C ABI functions take.
+    pub fn handle(&self) -> u64 {
+        self.handle
+    }
+
+    /// Server: the addresses bound, in the order given.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// The next event, waiting for one to arrive.
+    pub fn recv(&self) -> Option<Event> {
+        self.events.recv().ok()
+    }
+
+    /// Like [`Endpoint::recv`], giving up after `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Event> {
+        self.events.recv_timeout(timeout).ok()
+    }
+
+    /// Events already waiting, without blocking.
+    pub fn try_iter(&self) -> impl Iterator<Item = Event> + '_ {
+        self.events.try_iter()
+    }
+
+    /// The connection `connection_id` (from [`Event::Connected`]).
+    pub fn connection(&self, connection_id: &str) -> Connection {
+        Connection::new(self.handle, connection_id.to_string())
+    }
+
+    /// Closes every connection and ends the worker, as dropping does, but
+    /// keeps the events posted until then.
+    pub fn close(&self) -> Result<(), Error> {
+        crate::close_handle(self.handle)
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        let _ = crate::close_handle(self.handle);
+        crate::poll::detach(self.handle);
+    }
+}
+
+/// One connection of an [`Endpoint`]; cheap to clone and to keep.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Connection {
+    pub(crate) handle: u64,
+    pub(crate) id: String,
+    pub(crate) raw_id: Vec<u8>,
+}
+
+impl Connection {
+    pub(crate) fn new(handle: u64, id: String) -> Self {
+        let raw_id = hex::decode(&id).unwrap_or_default();
+        Self { handle, id, raw_id }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Sends `data` on the control stream, as `cc_quic_conn_send`.
+    pub fn send(&self, data: &[u8]) -> Result<(), Error> {
+        let send = |conn_id, payload| WorkerCommand::Send { conn_id, payload };
+        self.command(data, send, "send")
+    }
+
+    /// Sends `data` reliably, as `cc_quic_control_send`.
+    pub fn control_send(&self, data: &[u8]) -> Result<(), Error> {
+        let send = |conn_id, payload| WorkerCommand::Control { conn_id, payload };
+        self.command(data, send, "control send")
+    }
+
+    /// Writes this connection's datagrams to `path` as pcapng, as
+    /// `cc_quic_conn_capture`; `None` stops the capture.
+    pub fn capture(&self, path: Option<&Path>, max_bytes: u64) -> Result<(), Error> {
+        crate::start_capture(self.handle, self.raw_id.clone(), path, max_bytes)
+    }
+
+    fn command(
+        &self,
+        data: &[u8],
+        send: impl FnOnce(Vec<u8>, Vec<u8>) -> WorkerCommand,
+        what: &str,
+    ) -> Result<(), Error> {
+        if data.is_empty() {
+            return Err(Error::Config(format!("{what}: nothing to send")));
+        }
+        crate::send_command(self.handle, send(self.raw_id.clone(), data.to_vec()))
+    }
+}
+
+/// Starts a client worker whose events are held, typed, in the poll queue
+/// until a sink is attached; returns its handle.
+pub(crate) fn start_client(
+    config: &mut Config,
+    addr: SocketAddr,
+    server_name: &str,
+    pin: &str,
+    identity: &Identity,
+    dial: &Dial,
+) -> Result<u64, Error> {
+    if config.0.options.coalesce {
+        // A coalesced handle posts to the Dart port that started it.
+        return Err(Error::Unsupported("coalescing needs a Dart port".into()));
+    }
+    let (cert_path, key_path) = identity.paths()?;
+    let proxy = dial
+        .proxy
+        .as_deref()
+        .map(|url| Proxy::parse(url, &dial.proxy_auth, dial.proxy_ca.clone()))
+        .transpose()?;
+    config.0.options.typed_events = true;
+    let target = ClientTarget {
+        host: crate::dial_host(addr),
+        port: addr.port(),
+        server_name: server_name.to_string(),
+        expected_fp: pin.to_lowercase(),
+        allow_unpinned: dial.unpinned,
+        cert_path,
+        key_path,
+    };
+    crate::connect_client(&mut config.0, target, crate::poll::POLL_PORT, proxy)
+}
+
+/// Starts a server worker whose events are held, typed, in the poll queue
+/// until a sink is attached; returns its handle and bound addresses.
+pub(crate) fn start_server(
+    config: &mut Config,
+    binds: &[SocketAddr],
+    identity: &Identity,
+    trust: &[&str],
+) -> Result<(u64, Vec<SocketAddr>), Error> {
+    if binds.is_empty() {
+        return Err(Error::Config("no bind addresses".into()));
+    }
+    let (cert_path, key_path) = identity.paths()?;
+    config.0.options.typed_events = true;
+    let allowlist = crate::allowlist::parse(&trust.join(","));
+    crate::start_server(
+        &mut config.0,
+        binds,
+        &cert_path,
+        &key_path,
+        allowlist,
+        crate::poll::POLL_PORT,
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CcQuicStatus;
+
+    #[test]
+    fn worker_events_convert_typed_or_as_json() {
+        let message = QuicEvent::Message {
+            handle: 7,
+            connection_id: "ab".into(),
+            channel: None,
+            topic: Some("t"),
+            data: b"hi",
+        };
+        assert_eq!(
+            Event::from_quic(&message),
+            Event::Message {
+                connection_id: "ab".into(),
+                channel: None,
+                topic: Some("t".into()),
+                data: b"hi".to_vec(),
+            }
+        );
+        let stalled = QuicEvent::WorkerStalled {
+            handle: 7,
+            last_progress_ms: 3,
+        };
+        match Event::from_quic(&stalled) {
+            Event::Other(value) => {
+                assert_eq!(value["type"], "worker_stalled");
+                assert_eq!(value["last_progress_ms"], 3);
+            }
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn config_setters_check_role_and_range() {
+        let mut config = Config::client();
+        assert!(config.set_pacing(true, 1000).is_ok());
+        let err = config.set_pacing(false, 1000).err().unwrap();
+        assert_eq!(err.status(), CcQuicStatus::ConfigError);
+        let err = config.set_roster(true).err().unwrap();
+        assert_eq!(err.status(), CcQuicStatus::WrongRole);
+        assert!(Config::preset("lan-low-latency")
+            .unwrap()
+            .set_roster(true)
+            .is_ok());
+    }
+
+    #[test]
+    fn ipv6_addresses_reach_the_worker() {
+        // No identity, so nothing fails before the address is parsed.
+        let identity = Identity::new("", "");
+        let pin = "ab".repeat(32);
+        let addr = "[::1]:9".parse().unwrap();
+        let connected = Endpoint::connect(&mut Config::client(), addr, "srv", &pin, &identity);
+        assert!(connected.is_ok(), "{:?}", connected.err());
+    }
+
+    #[test]
+    fn starts_check_the_config_role() {
+        let identity = Identity::new("/nonexistent.crt", "/nonexistent.key");
+        let addr = "127.0.0.1:4433".parse().unwrap();
+        let err = Endpoint::serve(&mut Config::client(), &[addr], &identity, &[]);
+        assert!(matches!(err, Err(Error::WrongRole(_))));
+        let err = Endpoint::connect(&mut Config::server(), addr, "srv", "", &identity);
+        assert!(matches!(err, Err(Error::WrongRole(_))));
+        let err = Endpoint::connect(&mut Config::client(), addr, "srv", "", &identity);
+        assert!(matches!(err, Err(Error::Config(_))));
+        match Endpoint::connect(&mut Config::client(), addr, "srv", "ab", &identity) {
+            Err(err @ Error::CertParse { .. }) => {
+                assert_eq!(err.status(), CcQuicStatus::CertLoadError);
+                assert!(err.to_string().starts_with("/nonexistent.crt: "), "{err}");
+            }
+            other => panic!("{:?}", other.err()),
+        }
+        let err = Config::from_json(r#"{ "server_workers": 0 }"#)
+            .err()
+            .unwrap();
+        assert_eq!(err.status(), CcQuicStatus::ConfigError);
+        assert!(err.to_string().starts_with("server_workers"), "{err}");
+    }
+}
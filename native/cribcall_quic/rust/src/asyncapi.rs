@@ -0,0 +1,346 @@
+//! Async Rust API (feature `async`) for embedders on an async runtime, such
+//! as the relay on tokio. The workers are the threads `api.rs` starts; only
+//! the hand-off differs. Events land in an inbox that wakes the tasks
+//! waiting on it, so no runtime thread blocks. The futures use nothing but
+//! `std::task` and run on any executor.
+//!
+//! [`connect`] resolves once the handshake is done. [`AsyncEndpoint::accept`]
+//! and [`AsyncConnection::recv`] take only the events they wait for; every
+//! other event stays queued for `next_event`, in order. When the inbox
+//! is full, the oldest event without a typed [`Event`] variant makes room.
+
+use std::collections::VecDeque;
+use std::future::{poll_fn, Future};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Poll, Waker};
+
+use crate::api::{self, Config, Error, Event, HandshakeCause, Identity};
+use crate::poll::QUEUE_CAPACITY;
+
+#[derive(Default)]
+struct Inbox {
+    events: VecDeque<Event>,
+    /// Tasks to wake on the next event; each checks again for its own.
+    wakers: Vec<Waker>,
+}
+
+impl Inbox {
+    /// False when the inbox is full of typed events.
+    fn push(&mut self, event: Event) -> bool {
+        if self.events.len() >= QUEUE_CAPACITY {
+            let Some(oldest) = self
+                .events
+                .iter()
+                .position(|event| matches!(event, Event::Other(_)))
+            else {
+                return false;
+            };
+            self.events.remove(oldest);
+        }
+        self.events.push_back(event);
+        true
+    }
+}
+
+fn lock(inbox: &Mutex<Inbox>) -> MutexGuard<'_, Inbox> {
+    inbox.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A started worker, shared by its endpoint and its connections; the last
+/// of them to drop closes it.
+struct Shared {
+    handle: u64,
+    inbox: Arc<Mutex<Inbox>>,
+}
+
+impl Shared {
+    fn attach(handle: u64) -> Arc<Self> {
+        let inbox = Arc::new(Mutex::new(Inbox::default()));
+        let sink = inbox.clone();
+        crate::poll::attach(handle, move |event| {
+            let mut inbox = lock(&sink);
+            let accepted = inbox.push(event);
+            let wakers = std::mem::take(&mut inbox.wakers);
+            drop(inbox);
+            wakers.into_iter().for_each(Waker::wake);
+            accepted
+        });
+        Arc::new(Self { handle, inbox })
+    }
+
+    /// Resolves with what `pick` first takes out of the queued events.
+    fn take<'a, T>(
+        &'a self,
+        mut pick: impl FnMut(&mut VecDeque<Event>) -> Option<T> + 'a,
+    ) -> impl Future<Output = T> + 'a {
+        poll_fn(move |cx| {
+            let mut inbox = lock(&self.inbox);
+            if let Some(value) = pick(&mut inbox.events) {
+                return Poll::Ready(value);
+            }
+            if !inbox.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                inbox.wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+    }
+
+    async fn next_event(&self) -> Event {
+        self.take(VecDeque::pop_front).await
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        let _ = crate::close_handle(self.handle);
+        crate::poll::detach(self.handle);
+    }
+}
+
+/// Connects as [`api::Endpoint::connect`] does and waits for the handshake.
+/// A failed one returns what its `handshake_failed` said:
+/// `FingerprintMismatch`, `Tls` for an alert, or else `Closed`. With a TCP
+/// fallback set, a silent UDP attempt is not a failure: the tunnel attempt
+/// follows under a new connection id.
+pub async fn connect(
+    config: &mut Config,
+    addr: SocketAddr,
+    server_name: &str,
+    pin: &str,
+    identity: &Identity,
+) -> Result<AsyncConnection, Error> {
+    let retries = config.0.options.tcp_fallback.is_some();
+    let handle = api::start_client(
+        config,
+        addr,
+        server_name,
+        pin,
+        identity,
+        &api::Dial::default(),
+    )?;
+    let shared = Shared::attach(handle);
+    let outcome = shared
+        .take(|events| {
+            let at = events.iter().position(|event| match event {
+                Event::Connected { .. } | Event::Closed { .. } => true,
+                Event::HandshakeFailed { cause, .. } => {
+                    !(retries && *cause == HandshakeCause::NoResponse)
+                }
+                _ => false,
+            })?;
+            events.remove(at)
+        })
+        .await;
+    match outcome {
+        Event::Connected { connection_id, .. } => Ok(AsyncConnection::new(shared, connection_id)),
+        Event::Closed { reason, .. } => Err(Error::Closed {
+            code: None,
+            reason: reason.unwrap_or_else(|| "closed".into()),
+        }),
+        failure @ Event::HandshakeFailed { .. } => Err(handshake_error(failure, pin)),
+        _ => unreachable!("only connected, closed and handshake_failed are taken"),
+    }
+}
+
+/// The error an [`Event::HandshakeFailed`] stands for.
+fn handshake_error(failure: Event, pin: &str) -> Error {
+    let Event::HandshakeFailed {
+        cause,
+        error_code,
+        detail,
+        from_peer,
+        ..
+    } = failure
+    else {
+        unreachable!("only handshake failures map to handshake errors")
+    };
+    match cause {
+        // From the peer, it is the server refusing this client.
+        HandshakeCause::FingerprintMismatch if from_peer == Some(false) => {
+            Error::FingerprintMismatch {
+                expected: pin.to_lowercase(),
+            }
+        }
+        HandshakeCause::TlsAlert | HandshakeCause::CertInvalid => Error::Tls(detail),
+        _ => Error::Closed {
+            code: error_code,
+            reason: detail,
+        },
+    }
+}
+
+/// A running server. The worker ends once the endpoint and every
+/// connection taken from it are dropped.
+pub struct AsyncEndpoint {
+    shared: Arc<Shared>,
+    local_addrs: Vec<SocketAddr>,
+}
+
+impl AsyncEndpoint {
+    /// Binds as [`api::Endpoint::serve`] does; binding does not wait on
+    /// the network, so this is not `async`.
+    pub fn serve(
+        config: &mut Config,
+        binds: &[SocketAddr],
+        identity: &Identity,
+        trust: &[&str],
+    ) -> Result<Self, Error> {
+        let (handle, local_addrs) = api::start_server(config, binds, identity, trust)?;
+        Ok(Self {
+            shared: Shared::attach(handle),
+            local_addrs,
+        })
+    }
+
+    /// The handle the C ABI functions take.
+    pub fn handle(&self) -> u64 {
+        self.shared.handle
+    }
+
+    /// The addresses bound, in the order given.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// The next client to finish its handshake.
+    pub async fn accept(&self) -> AsyncConnection {
+        let connection_id = self
+            .shared
+            .take(|events| {
+                let at = events
+                    .iter()
+                    .position(|event| matches!(event, Event::Connected { .. }))?;
+                match events.remove(at)? {
+                    Event::Connected { connection_id, .. } => Some(connection_id),
+                    _ => None,
+                }
+            })
+            .await;
+        AsyncConnection::new(self.shared.clone(), connection_id)
+    }
+
+    /// The next event not taken by `accept` or a connection's `recv`.
+    pub async fn next_event(&self) -> Event {
+        self.shared.next_event().await
+    }
+
+    /// The connection `connection_id` (from [`Event::Connected`]).
+    pub fn connection(&self, connection_id: &str) -> AsyncConnection {
+        AsyncConnection::new(self.shared.clone(), connection_id.to_string())
+    }
+}
+
+/// One connection and a share of its worker; cheap to clone.
+#[derive(Clone)]
+pub struct AsyncConnection {
+    shared: Arc<Shared>,
+    conn: api::Connection,
+}
+
+impl AsyncConnection {
+    fn new(shared: Arc<Shared>, id: String) -> Self {
+        let conn = api::Connection::new(shared.handle, id);
+        Self { shared, conn }
+    }
+
+    pub fn id(&self) -> &str {
+        self.conn.id()
+    }
+
+    /// Sends `data` on the control stream. Sends are queued for the worker
+    /// and never wait, so this is not `async`.
+    pub fn send(&self, data: &[u8]) -> Result<(), Error> {
+        self.conn.send(data)
+    }
+
+    /// Sends `data` reliably, as `cc_quic_control_send`.
+    pub fn control_send(&self, data: &[u8]) -> Result<(), Error> {
+        self.conn.control_send(data)
+    }
+
+    /// The next message on this connection, from any channel or topic
+    /// (`next_event` keeps those apart); `None` once it has closed.
+    pub async fn recv(&self) -> Option<Vec<u8>> {
+        let id = self.conn.id();
+        self.shared
+            .take(|events| {
+                let at = events.iter().position(|event| match event {
+                    Event::Message { connection_id, .. } | Event::Closed { connection_id, .. } => {
+                        connection_id == id
+                    }
+                    _ => false,
+                })?;
+                match &events[at] {
+                    // The close stays queued for `next_event`.
+                    Event::Closed { .. } => Some(None),
+                    _ => match events.remove(at)? {
+                        Event::Message { data, .. } => Some(Some(data)),
+                        _ => None,
+                    },
+                }
+            })
+            .await
+    }
+
+    /// The next event of this connection's worker not taken by `recv` or
+    /// `accept`; a server's events cover all of its connections.
+    pub async fn next_event(&self) -> Event {
+        self.shared.next_event().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::pin;
+    use std::task::Context;
+
+    #[test]
+    fn pending_takes_register_and_untyped_events_make_room() {
+        let handle = u64::MAX - 6;
+        let inbox = Arc::new(Mutex::new(Inbox::default()));
+        let shared = Shared {
+            handle,
+            inbox: inbox.clone(),
+        };
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut next = pin!(shared.next_event());
+        assert!(next.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(lock(&inbox).wakers.len(), 1);
+
+        let stats = Event::Other(serde_json::json!({ "type": "stats", "connection_id": "a" }));
+        for _ in 0..QUEUE_CAPACITY {
+            assert!(lock(&inbox).push(stats.clone()));
+        }
+        let closed = Event::Closed {
+            connection_id: "a".into(),
+            reason: None,
+        };
+        assert!(lock(&inbox).push(closed.clone()));
+        assert_eq!(lock(&inbox).events.back(), Some(&closed));
+        assert_eq!(next.as_mut().poll(&mut cx), Poll::Ready(stats));
+    }
+
+    #[test]
+    fn handshake_failures_map_to_errors() {
+        let failure = |from_peer, error_code| Event::HandshakeFailed {
+            connection_id: "a".into(),
+            cause: HandshakeCause::FingerprintMismatch,
+            error_code: Some(error_code),
+            detail: "d".into(),
+            from_peer: Some(from_peer),
+        };
+        assert!(matches!(
+            handshake_error(failure(false, 258), "AB"),
+            Error::FingerprintMismatch { expected } if expected == "ab"
+        ));
+        assert!(matches!(
+            handshake_error(failure(true, 259), "ab"),
+            Error::Closed {
+                code: Some(259),
+                ..
+            }
+        ));
+    }
+}
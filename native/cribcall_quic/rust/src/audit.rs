@@ -0,0 +1,187 @@
+//! Opt-in connection history (`cc_quic_audit_enable`): one JSON line per
+//! accepted, rejected or closed connection, across every handle, so an app
+//! can show which devices connected and when. The file is capped by
+//! rotating it to `<path>.1` once it would pass the limit, so at most two
+//! generations (about twice the cap) stay on disk.
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Cap applied when `cc_quic_audit_enable` is given 0.
+pub(crate) const DEFAULT_MAX_BYTES: u64 = 256 * 1024;
+
+static LOG: Lazy<Mutex<Option<AuditLog>>> = Lazy::new(|| Mutex::new(None));
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Outcome {
+    /// Handshake done and the peer passed pinning / the allowlist.
+    Accepted,
+    /// The peer's certificate failed pinning or the allowlist.
+    Rejected,
+    Closed,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Record<'a> {
+    pub ts_ms: u64,
+    pub handle: u64,
+    pub role: &'static str,
+    pub outcome: Outcome,
+    pub connection_id: &'a str,
+    pub remote: Option<SocketAddr>,
+    pub fingerprint: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'a str>,
+}
+
+struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    file: File,
+    len: u64,
+}
+
+impl AuditLog {
+    fn open(path: PathBuf, max_bytes: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            file,
+            len,
+        })
+    }
+
+    fn rotated(&self) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(".1");
+        name.into()
+    }
+
+    fn append(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.len > 0 && self.len + line.len() as u64 > self.max_bytes {
+            fs::rename(&self.path, self.rotated())?;
+            *self = Self::open(self.path.clone(), self.max_bytes)?;
+        }
+        self.file.write_all(line)?;
+        self.len += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Starts recording to `path` (created if missing, appended to otherwise),
+/// or stops with `None`.
+pub(crate) fn enable(path: Option<PathBuf>, max_bytes: u64) -> io::Result<()> {
+    let log = match path {
+        Some(path) => Some(AuditLog::open(path, max_bytes)?),
+        None => None,
+    };
+    *LOG.lock().unwrap_or_else(PoisonError::into_inner) = log;
+    Ok(())
+}
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Appends `record` when recording is on. Failures are logged, never
+/// surfaced: the audit trail must not take connections down.
+pub(crate) fn record(record: &Record<'_>) {
+    let mut log = LOG.lock().unwrap_or_else(PoisonError::into_inner);
+    let Some(log) = log.as_mut() else {
+        return;
+    };
+    let Ok(mut line) = serde_json::to_vec(record) else {
+        return;
+    };
+    line.push(b'\n');
+    if let Err(err) = log.append(&line) {
+        log::warn!("audit log write failed: {err}");
+    }
+}
+
+/// Records with `ts_ms >= since_ms`, oldest first, as JSON lines.
+pub(crate) fn query(since_ms: u64) -> io::Result<String> {
+    let log = LOG.lock().unwrap_or_else(PoisonError::into_inner);
+    let Some(log) = log.as_ref() else {
+        return Ok(String::new());
+    };
+    #[derive(serde::Deserialize)]
+    struct Stamp {
+        ts_ms: u64,
+    }
+    let mut out = String::new();
+    for path in [log.rotated(), log.path.clone()] {
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            // A torn last line (crash mid-write) is skipped, not fatal.
+            match serde_json::from_str::<Stamp>(&line) {
+                Ok(stamp) if stamp.ts_ms >= since_ms => {
+                    out.push_str(&line);
+                    out.push('\n');
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_at_the_cap_and_queries_both_generations() {
+        let dir = std::env::temp_dir().join(format!("cc-audit-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(dir.join("audit.jsonl.1"));
+        enable(Some(path.clone()), 400).unwrap();
+        for ts_ms in 1..=6 {
+            record(&Record {
+                ts_ms,
+                handle: 1,
+                role: "server",
+                outcome: Outcome::Accepted,
+                connection_id: "ab",
+                remote: "192.0.2.1:4433".parse().ok(),
+                fingerprint: "ff",
+                reason: None,
+            });
+        }
+        assert!(fs::metadata(&path).unwrap().len() <= 400);
+        assert!(dir.join("audit.jsonl.1").exists());
+
+        let lines = query(3).unwrap();
+        let stamps: Vec<u64> = lines
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<serde_json::Value>(line).unwrap()["ts_ms"]
+                    .as_u64()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(stamps, [3, 4, 5, 6]);
+        enable(None, 0).unwrap();
+        assert_eq!(query(0).unwrap(), "");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,426 @@
+//! Application authentication after the TLS handshake. Fingerprints
+//! identify devices; a bearer token identifies the user, so one member of a
+//! household can lose access without re-pairing the phone.
+//!
+//! A client with `cc_quic_config_set_auth_token` sends its token on a
+//! dedicated unidirectional stream (client id 10) once the server's pin
+//! checks out; a server with a verifier holds back `connected` until the
+//! token passes. The verdict comes back on server stream 19: one byte, `0`
+//! accepted or `1` rejected, after which the server closes with
+//! `AUTH_FAILED`. Verifiers:
+//!
+//! - HMAC (`cc_quic_config_set_auth_hmac`): tokens minted with
+//!   `cc_quic_auth_mint_token`, `user.expires.mac` where `mac` is the hex
+//!   HMAC-SHA256 of `user.expires` under the shared secret and `expires` is
+//!   Unix seconds (0 never expires).
+//! - callback (`cc_quic_config_set_auth_callback`): the server posts
+//!   `auth_request` and Dart answers with `cc_quic_server_auth_verdict`.
+//!
+//! Either way `cc_quic_server_revoke_user` refuses a user from then on and
+//! closes their open connections.
+//!
+//! Independently of tokens, `cc_quic_config_set_connection_approval` has the
+//! server post `connection_pending` for each client that passed the other
+//! checks and wait for `cc_quic_server_decide`, so the app can ask the user.
+//! Streams and datagrams from the client stay queued until then.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::os::raw::{c_int, c_uint, c_void};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const CLIENT_STREAM: u64 = 10;
+const SERVER_STREAM: u64 = 19;
+const ACCEPTED: u8 = 0;
+const REJECTED: u8 = 1;
+const MAC_LEN: usize = 32;
+/// Longest token the two-byte length prefix is allowed to announce.
+pub(crate) const MAX_TOKEN_LEN: usize = 4096;
+/// How long an established client has to present its token, or a Dart
+/// callback to answer.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Transport close code for a rejected or revoked token.
+pub(crate) const AUTH_FAILED: u64 = 0x106;
+/// Transport close code for a connection the app refused or left waiting.
+pub(crate) const APPROVAL_REFUSED: u64 = 0x107;
+
+#[repr(C)]
+struct EvpMd {
+    _private: [u8; 0],
+}
+
+extern "C" {
+    fn EVP_sha256() -> *const EvpMd;
+    fn HMAC(
+        evp_md: *const EvpMd,
+        key: *const c_void,
+        key_len: usize,
+        data: *const u8,
+        data_len: usize,
+        out: *mut u8,
+        out_len: *mut c_uint,
+    ) -> *mut u8;
+    fn CRYPTO_memcmp(a: *const c_void, b: *const c_void, len: usize) -> c_int;
+}
+
+pub(crate) fn is_auth_stream(stream_id: u64) -> bool {
+    stream_id == CLIENT_STREAM || stream_id == SERVER_STREAM
+}
+
+#[derive(Clone)]
+pub(crate) enum Verifier {
+    Hmac(Arc<[u8]>),
+    Callback,
+}
+
+// Keeps the secret out of logged options.
+impl fmt::Debug for Verifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Hmac(_) => f.write_str("Hmac(..)"),
+            Self::Callback => f.write_str("Callback"),
+        }
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; MAC_LEN] {
+    let mut out = [0u8; MAC_LEN];
+    let mut out_len: c_uint = 0;
+    // SHA-256 cannot fail for in-memory input; a null return leaves the
+    // zeroed output, which no real token matches.
+    unsafe {
+        HMAC(
+            EVP_sha256(),
+            key.as_ptr().cast(),
+            key.len(),
+            data.as_ptr(),
+            data.len(),
+            out.as_mut_ptr(),
+            &mut out_len,
+        );
+    }
+    out
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// A token for `user` that verifies under `secret` until `expires` (Unix
+/// seconds, 0 for never).
+pub(crate) fn mint(secret: &[u8], user: &str, expires: u64) -> String {
+    let payload = format!("{user}.{expires}");
+    let mac = hmac_sha256(secret, payload.as_bytes());
+    format!("{payload}.{}", hex::encode(mac))
+}
+
+/// The user `token` was minted for, or why it does not verify at `now`.
+fn verify(secret: &[u8], token: &str, now: u64) -> Result<String, &'static str> {
+    let (payload, mac) = token.rsplit_once('.').ok_or("malformed token")?;
+    let (user, expires) = payload.rsplit_once('.').ok_or("malformed token")?;
+    let mac = hex::decode(mac).map_err(|_| "malformed token")?;
+    let expires = expires.parse::<u64>().map_err(|_| "malformed token")?;
+    let expected = hmac_sha256(secret, payload.as_bytes());
+    let matches = mac.len() == MAC_LEN
+        && unsafe { CRYPTO_memcmp(mac.as_ptr().cast(), expected.as_ptr().cast(), MAC_LEN) } == 0;
+    if !matches {
+        return Err("bad token signature");
+    }
+    if expires != 0 && expires <= now {
+        return Err("token expired");
+    }
+    Ok(user.to_string())
+}
+
+/// Where one server connection's gate stands.
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum Step {
+    Pending,
+    /// The token is complete and Dart must judge it.
+    Ask(String),
+    Accepted(Option<String>),
+    Rejected(String),
+}
+
+#[derive(Default)]
+enum State {
+    #[default]
+    Reading,
+    Asked,
+    Accepted(Option<String>),
+    Rejected,
+}
+
+/// Server side of the gate for one connection.
+#[derive(Default)]
+pub(crate) struct ServerAuth {
+    state: State,
+    partial: Vec<u8>,
+    since: Option<Instant>,
+}
+
+impl ServerAuth {
+    /// The user the connection authenticated as.
+    pub(crate) fn user(&self) -> Option<&str> {
+        match &self.state {
+            State::Accepted(user) => user.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Advances the gate of an established connection: reads the token,
+    /// checks it and answers the client. Without a verifier every client is
+    /// accepted at once.
+    pub(crate) fn poll(
+        &mut self,
+        conn: &mut quiche::Connection,
+        verifier: Option<&Verifier>,
+        revoked: &HashSet<String>,
+    ) -> Step {
+        let Some(verifier) = verifier else {
+            return Step::Accepted(None);
+        };
+        match &self.state {
+            State::Accepted(user) => return Step::Accepted(user.clone()),
+            State::Rejected => return Step::Pending,
+            State::Asked | State::Reading => {}
+        }
+        let since = *self.since.get_or_insert_with(Instant::now);
+        if matches!(self.state, State::Reading) {
+            match self.read_token(conn) {
+                Ok(Some(token)) => {
+                    let Ok(token) = String::from_utf8(token) else {
+                        return self.reject(conn, "malformed token");
+                    };
+                    match verifier {
+                        Verifier::Hmac(secret) => {
+                            return match verify(secret, &token, unix_now()) {
+                                Ok(user) => self.accept(conn, Some(user), revoked),
+                                Err(reason) => self.reject(conn, reason),
+                            };
+                        }
+                        Verifier::Callback => {
+                            self.state = State::Asked;
+                            return Step::Ask(token);
+                        }
+                    }
+                }
+                Ok(None) => {}
+                Err(reason) => return self.reject(conn, reason),
+            }
+        }
+        if since.elapsed() >= AUTH_TIMEOUT {
+            return self.reject(conn, "authentication timed out");
+        }
+        Step::Pending
+    }
+
+    /// Applies Dart's verdict on the token it was asked about; `Pending` if
+    /// it was not asking (a late or repeated verdict).
+    pub(crate) fn decide(
+        &mut self,
+        conn: &mut quiche::Connection,
+        accept: bool,
+        user: Option<String>,
+        revoked: &HashSet<String>,
+    ) -> Step {
+        if !matches!(self.state, State::Asked) {
+            return Step::Pending;
+        }
+        if accept {
+            self.accept(conn, user, revoked)
+        } else {
+            self.reject(conn, "token refused")
+        }
+    }
+
+    /// The token once it has fully arrived.
+    fn read_token(
+        &mut self,
+        conn: &mut quiche::Connection,
+    ) -> Result<Option<Vec<u8>>, &'static str> {
+        let mut buf = [0u8; 1024];
+        while let Ok((read, _fin)) = conn.stream_recv(CLIENT_STREAM, &mut buf) {
+            self.partial.extend_from_slice(&buf[..read]);
+            if self.partial.len() > 2 + MAX_TOKEN_LEN {
+                break;
+            }
+        }
+        let [hi, lo, ..] = self.partial[..] else {
+            return Ok(None);
+        };
+        let len = usize::from(u16::from_be_bytes([hi, lo]));
+        if len == 0 || len > MAX_TOKEN_LEN {
+            return Err("malformed token");
+        }
+        let Some(token) = self.partial.get(2..2 + len) else {
+            return Ok(None);
+        };
+        let token = token.to_vec();
+        self.partial.clear();
+        Ok(Some(token))
+    }
+
+    fn accept(
+        &mut self,
+        conn: &mut quiche::Connection,
+        user: Option<String>,
+        revoked: &HashSet<String>,
+    ) -> Step {
+        if user.as_ref().is_some_and(|user| revoked.contains(user)) {
+            return self.reject(conn, "access revoked");
+        }
+        if let Err(err) = conn.stream_send(SERVER_STREAM, &[ACCEPTED], false) {
+            log::debug!("auth verdict send error: {err:?}");
+        }
+        self.state = State::Accepted(user.clone());
+        Step::Accepted(user)
+    }
+
+    fn reject(&mut self, conn: &mut quiche::Connection, reason: &str) -> Step {
+        self.state = State::Rejected;
+        let _ = conn.stream_send(SERVER_STREAM, &[REJECTED], true);
+        let _ = conn.close(false, AUTH_FAILED, b"authentication failed");
+        Step::Rejected(reason.to_string())
+    }
+}
+
+/// Where one connection's wait for `cc_quic_server_decide` stands.
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum Approval {
+    /// Just started waiting: post `connection_pending`.
+    Ask,
+    Pending,
+    Accepted,
+    /// Returned once; the connection must be closed.
+    Refused(&'static str),
+}
+
+/// The app's decision on one server connection.
+#[derive(Default)]
+pub(crate) struct Decision {
+    asked: Option<Instant>,
+    decided: Option<bool>,
+    refused: bool,
+}
+
+impl Decision {
+    pub(crate) fn poll(&mut self, timeout: Duration) -> Approval {
+        let Some(asked) = self.asked else {
+            self.asked = Some(Instant::now());
+            return Approval::Ask;
+        };
+        if self.refused {
+            return Approval::Pending;
+        }
+        let reason = match self.decided {
+            Some(true) => return Approval::Accepted,
+            Some(false) => "refused by the app",
+            None if asked.elapsed() >= timeout => "approval timed out",
+            None => return Approval::Pending,
+        };
+        self.refused = true;
+        Approval::Refused(reason)
+    }
+
+    /// Records the app's answer; ignored unless the connection is waiting
+    /// for one.
+    pub(crate) fn decide(&mut self, accept: bool) {
+        if self.asked.is_some() && self.decided.is_none() {
+            self.decided = Some(accept);
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum Verdict {
+    Pending,
+    Accepted,
+    Rejected,
+}
+
+/// Client side: presents the token and waits for the verdict.
+#[derive(Default)]
+pub(crate) struct ClientAuth {
+    pending: Option<Vec<u8>>,
+    verdict: Option<bool>,
+    refusal_seen: bool,
+}
+
+impl ClientAuth {
+    /// Sends the token once and checks for the server's answer. A
+    /// rejection is returned once; the server is closing by then.
+    pub(crate) fn poll(&mut self, conn: &mut quiche::Connection, token: &str) -> Verdict {
+        let pending = self.pending.get_or_insert_with(|| {
+            let mut record = (token.len() as u16).to_be_bytes().to_vec();
+            record.extend_from_slice(token.as_bytes());
+            record
+        });
+        if !pending.is_empty() {
+            if let Ok(written) = conn.stream_send(CLIENT_STREAM, pending, false) {
+                pending.drain(..written);
+            }
+        }
+        if self.verdict.is_none() {
+            let mut buf = [0u8; 16];
+            if let Ok((read, _fin)) = conn.stream_recv(SERVER_STREAM, &mut buf) {
+                if read > 0 {
+                    self.verdict = Some(buf[0] == ACCEPTED);
+                }
+            }
+        }
+        match self.verdict {
+            Some(true) => Verdict::Accepted,
+            Some(false) if !self.refusal_seen => {
+                self.refusal_seen = true;
+                Verdict::Rejected
+            }
+            _ => Verdict::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_minted_tokens_until_they_expire() {
+        let secret = b"household secret";
+        let token = mint(secret, "mum.phone", 2_000);
+        assert_eq!(verify(secret, &token, 1_999).as_deref(), Ok("mum.phone"));
+        assert_eq!(verify(secret, &token, 2_000), Err("token expired"));
+        assert_eq!(verify(b"other", &token, 0), Err("bad token signature"));
+
+        let forever = mint(secret, "dad", 0);
+        assert!(verify(secret, &forever, u64::MAX).is_ok());
+        let forged = forever.replace("dad.", "eve.");
+        assert_eq!(verify(secret, &forged, 0), Err("bad token signature"));
+        assert_eq!(verify(secret, "dad", 0), Err("malformed token"));
+    }
+
+    #[test]
+    fn a_decision_is_asked_for_once_and_applies_once() {
+        let mut decision = Decision::default();
+        decision.decide(true);
+        assert_eq!(decision.poll(AUTH_TIMEOUT), Approval::Ask);
+        assert_eq!(decision.poll(AUTH_TIMEOUT), Approval::Pending);
+        decision.decide(false);
+        decision.decide(true);
+        assert_eq!(
+            decision.poll(AUTH_TIMEOUT),
+            Approval::Refused("refused by the app")
+        );
+        assert_eq!(decision.poll(AUTH_TIMEOUT), Approval::Pending);
+
+        let mut silent = Decision::default();
+        silent.poll(Duration::ZERO);
+        assert_eq!(
+            silent.poll(Duration::ZERO),
+            Approval::Refused("approval timed out")
+        );
+    }
+}
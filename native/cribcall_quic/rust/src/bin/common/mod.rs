@@ -0,0 +1,136 @@
+//! Shared plumbing for the command-line client and server: flag parsing,
+//! config loading and the event loop. Both binaries drive the library
+//! through its safe Rust API (`api.rs`), printing every event as a JSON
+//! line on stdout.
+
+// Each binary uses only part of this module.
+#![allow(dead_code)]
+
+use cribcall_quic::api::{self, Config, Error, Event};
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// How long the loop waits for an event before checking its input.
+pub const IDLE: Duration = Duration::from_millis(10);
+
+/// `--name value` options and `--name` switches; a repeated option keeps
+/// every value.
+pub struct Args {
+    usage: &'static str,
+    values: HashMap<String, Vec<String>>,
+}
+
+impl Args {
+    /// Parses the process arguments; `switches` take no value. Prints
+    /// `usage` and exits on `--help` or a malformed command line.
+    pub fn parse(usage: &'static str, switches: &[&str]) -> Self {
+        let mut values: HashMap<String, Vec<String>> = HashMap::new();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let Some(name) = arg.strip_prefix("--") else {
+                fail(usage, &format!("unexpected argument {arg:?}"));
+            };
+            if name == "help" {
+                println!("{usage}");
+                std::process::exit(0);
+            }
+            let value = if switches.contains(&name) {
+                String::new()
+            } else {
+                args.next()
+                    .unwrap_or_else(|| fail(usage, &format!("--{name} needs a value")))
+            };
+            values.entry(name.to_string()).or_default().push(value);
+        }
+        Self { usage, values }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name)?.last().map(String::as_str)
+    }
+
+    pub fn all(&self, name: &str) -> &[String] {
+        self.values.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn switch(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
+
+    pub fn require(&self, name: &str) -> &str {
+        self.get(name)
+            .unwrap_or_else(|| fail(self.usage, &format!("--{name} is required")))
+    }
+
+    /// `name` parsed, or `default` when absent.
+    pub fn number<T: std::str::FromStr>(&self, name: &str, default: T) -> T {
+        match self.get(name) {
+            None => default,
+            Some(text) => text
+                .parse()
+                .unwrap_or_else(|_| fail(self.usage, &format!("--{name}: bad number {text:?}"))),
+        }
+    }
+}
+
+fn fail(usage: &str, message: &str) -> ! {
+    eprintln!("error: {message}\n\n{usage}");
+    std::process::exit(2);
+}
+
+/// The value of `result`, or exits with `what` and the error.
+pub fn check<T>(result: Result<T, Error>, what: &str) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("error: {what} failed: {err}");
+        std::process::exit(1);
+    })
+}
+
+/// A config from the `--config` JSON file (see `jsonconfig.rs`), or the
+/// role's defaults, with `--stats-ms` applied on top.
+pub fn load_config(args: &Args, server: bool) -> Config {
+    api::init_logging();
+    let mut config = match args.get("config") {
+        Some(path) => {
+            let json = std::fs::read_to_string(path).unwrap_or_else(|err| {
+                eprintln!("error: cannot read {path}: {err}");
+                std::process::exit(1);
+            });
+            check(Config::from_json(&json), "config_from_json")
+        }
+        None if server => Config::server(),
+        None => Config::client(),
+    };
+    if args.get("stats-ms").is_some() {
+        config.set_stats_interval(args.number("stats-ms", 0u64));
+    }
+    config
+}
+
+/// The next event within [`IDLE`], printed to stdout as it is read.
+pub fn next_event(endpoint: &api::Endpoint) -> Option<Event> {
+    let event = endpoint.recv_timeout(IDLE)?;
+    println!("{}", event.to_json());
+    Some(event)
+}
+
+pub fn send(connection: &api::Connection, data: &[u8]) {
+    if let Err(err) = connection.send(data) {
+        eprintln!("send on {} failed: {err}", connection.id());
+    }
+}
+
+/// Lines typed on stdin; the channel disconnects at end of input.
+pub fn stdin_lines() -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
@@ -0,0 +1,118 @@
+//! Standalone client for field debugging and interop tests: connects with
+//! the library's own worker, prints every event as a JSON line, sends the
+//! `--send` messages and then each stdin line, and closes once input ends.
+
+mod common;
+
+use common::Args;
+use cribcall_quic::api::{Dial, Endpoint, Event, Identity};
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::TryRecvError;
+use std::time::{Duration, Instant};
+
+/// How long to wait for `closed` after closing the connection.
+const CLOSE_GRACE: Duration = Duration::from_secs(2);
+
+const USAGE: &str = "\
+usage: cribcall-quic-client --host HOST --cert PEM --key PEM [options]
+
+  --port N          server UDP port (default 4433)
+  --server-name S   TLS server name (default: the host)
+  --pin FP          expected server certificate fingerprint (hex); required
+                    unless --unpinned
+  --unpinned        accept any server certificate the CA trusts
+  --config FILE     JSON config, as for cc_quic_config_from_json
+  --stats-ms N      post a stats event every N ms
+  --send TEXT       message to send once connected; may be repeated
+  --capture FILE    write the connection's datagrams to FILE as pcapng
+  --proxy URL       connect through this MASQUE (https://host[:port]) or
+                    SOCKS5 (socks5://host[:port]) proxy
+  --proxy-auth V    proxy-authorization value, or user:password for SOCKS5
+  --proxy-ca PATH   PEM file or directory of CAs for a MASQUE proxy
+                    (default: the system store, desktop Linux only)
+  --linger-ms N     keep printing events this long after input ends
+                    before closing (default 1000)
+  --timeout-ms N    give up if not connected in time (default 10000)
+
+Events are printed to stdout as JSON lines; each stdin line is sent as a
+message. Exits 0 after a clean close, 1 if the connection failed.";
+
+fn main() {
+    let args = Args::parse(USAGE, &["unpinned"]);
+    let host = args.require("host");
+    let identity = Identity::new(args.require("cert"), args.require("key"));
+    let server_name = args.get("server-name").unwrap_or(host);
+    let unpinned = args.switch("unpinned");
+    let pin = if unpinned { "" } else { args.require("pin") };
+    let port = args.number("port", 4433u16);
+    let linger = Duration::from_millis(args.number("linger-ms", 1000));
+    let timeout = Duration::from_millis(args.number("timeout-ms", 10_000));
+    let mut config = common::load_config(&args, false);
+    let capture = args.get("capture").map(Path::new);
+    let dial = Dial {
+        unpinned,
+        proxy: args.get("proxy").map(str::to_string),
+        proxy_auth: args.get("proxy-auth").unwrap_or("").to_string(),
+        proxy_ca: args.get("proxy-ca").map(PathBuf::from),
+    };
+    let addr = (host, port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .unwrap_or_else(|| {
+            eprintln!("error: cannot resolve {host}");
+            std::process::exit(1);
+        });
+
+    let endpoint = common::check(
+        Endpoint::connect_with(&mut config, addr, server_name, pin, &identity, &dial),
+        "client_connect",
+    );
+
+    let started = Instant::now();
+    let input = common::stdin_lines();
+    let mut connection = None;
+    let mut input_done: Option<Instant> = None;
+    let mut closing: Option<Instant> = None;
+    loop {
+        match common::next_event(&endpoint) {
+            Some(Event::Connected { connection_id, .. }) => {
+                let conn = endpoint.connection(&connection_id);
+                if let Some(path) = capture {
+                    common::check(conn.capture(Some(path), 0), "conn_capture");
+                }
+                for text in args.all("send") {
+                    common::send(&conn, text.as_bytes());
+                }
+                connection = Some(conn);
+            }
+            Some(Event::Closed { .. }) => {
+                std::process::exit(if connection.is_some() { 0 } else { 1 })
+            }
+            Some(Event::WorkerDied { .. }) => std::process::exit(1),
+            _ => {}
+        }
+
+        if let Some(conn) = &connection {
+            while input_done.is_none() {
+                match input.try_recv() {
+                    Ok(line) => common::send(conn, line.as_bytes()),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => input_done = Some(Instant::now()),
+                }
+            }
+        } else if started.elapsed() > timeout {
+            eprintln!("error: not connected after {timeout:?}");
+            std::process::exit(1);
+        }
+        if closing.is_none() && input_done.is_some_and(|done| done.elapsed() >= linger) {
+            closing = Some(Instant::now());
+            let _ = endpoint.close();
+        }
+        // A handle closed locally may be gone before its `closed` is read.
+        if closing.is_some_and(|at| at.elapsed() > CLOSE_GRACE) {
+            std::process::exit(0);
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! Standalone server for field debugging and interop tests: serves one
+//! address with the library's own workers, prints every event as a JSON
+//! line and sends each stdin line to every connected client.
+
+mod common;
+
+use common::Args;
+use cribcall_quic::api::{Endpoint, Event, Identity};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::mpsc::TryRecvError;
+
+const USAGE: &str = "\
+usage: cribcall-quic-server --cert PEM --key PEM [options]
+
+  --bind ADDR       address to listen on (default 0.0.0.0)
+  --port N          UDP port (default 4433)
+  --trust LIST      allowlist CSV of client fingerprints (default: any)
+  --config FILE     JSON config, as for cc_quic_config_from_json
+  --stats-ms N      post a stats event per connection every N ms
+  --echo            send every received message back to its sender
+
+Events are printed to stdout as JSON lines; each stdin line is sent to
+every connected client. RUST_LOG sets the log level.";
+
+fn main() {
+    let args = Args::parse(USAGE, &["echo"]);
+    let identity = Identity::new(args.require("cert"), args.require("key"));
+    let bind = args.get("bind").unwrap_or("0.0.0.0");
+    let trust: Vec<&str> = args
+        .get("trust")
+        .map_or(Vec::new(), |csv| csv.split(',').collect());
+    let port = args.number("port", 4433u16);
+    let echo = args.switch("echo");
+    let mut config = common::load_config(&args, true);
+    let ip: IpAddr = bind.trim_matches(['[', ']']).parse().unwrap_or_else(|_| {
+        eprintln!("error: --bind {bind:?} is not an IP address");
+        std::process::exit(2);
+    });
+
+    let endpoint = common::check(
+        Endpoint::serve(&mut config, &[SocketAddr::new(ip, port)], &identity, &trust),
+        "server_start",
+    );
+
+    let input = common::stdin_lines();
+    let mut input_open = true;
+    let mut connections = BTreeMap::new();
+    loop {
+        match common::next_event(&endpoint) {
+            Some(Event::Connected { connection_id, .. }) => {
+                let conn = endpoint.connection(&connection_id);
+                connections.insert(connection_id, conn);
+            }
+            Some(Event::Closed { connection_id, .. }) => {
+                connections.remove(&connection_id);
+            }
+            Some(Event::Message {
+                connection_id,
+                data,
+                ..
+            }) if echo => {
+                if let Some(conn) = connections.get(&connection_id) {
+                    common::send(conn, &data);
+                }
+            }
+            Some(Event::WorkerDied { .. }) => std::process::exit(1),
+            _ => {}
+        }
+        while input_open {
+            match input.try_recv() {
+                Ok(line) => {
+                    for conn in connections.values() {
+                        common::send(conn, line.as_bytes());
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => input_open = false,
+            }
+        }
+    }
+}
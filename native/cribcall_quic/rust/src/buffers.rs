@@ -0,0 +1,342 @@
+//! Reusable buffers for the stream receive → event post path, so sustained
+//! streaming does not allocate a read buffer, payload copy, base64 string and
+//! growing JSON string for every chunk.
+
+use allo_isolate::Isolate;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use super::{event_seq, poll, QuicEvent};
+use crate::api::Event;
+use crate::diagnostics::EventHistory;
+use crate::Error;
+
+/// Set once `cc_quic_init_dart_api` has stored Dart's post function;
+/// until then nothing posted to a Dart port arrives.
+static DART_API: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn dart_api_stored() {
+    DART_API.store(true, Ordering::Release);
+}
+
+/// Refuses a start whose events could never arrive: one posting to a Dart
+/// port before `cc_quic_init_dart_api`.
+pub(crate) fn check_port(port: i64) -> Result<(), Error> {
+    if port != poll::POLL_PORT && !DART_API.load(Ordering::Acquire) {
+        return Err(Error::EventSend);
+    }
+    Ok(())
+}
+
+/// Size of the buffers handed to `stream_recv`.
+pub(crate) const STREAM_READ_SIZE: usize = 65_535;
+/// Free buffers kept around per worker; anything beyond is dropped.
+const MAX_POOLED: usize = 4;
+
+/// Fixed-size receive buffers recycled across loop passes and connections.
+pub(crate) struct BufferPool {
+    size: usize,
+    free: Vec<Vec<u8>>,
+}
+
+impl BufferPool {
+    pub(crate) fn new(size: usize) -> Self {
+        Self {
+            size,
+            free: Vec::new(),
+        }
+    }
+
+    pub(crate) fn take(&mut self) -> Vec<u8> {
+        self.free.pop().unwrap_or_else(|| vec![0; self.size])
+    }
+
+    pub(crate) fn give(&mut self, buf: Vec<u8>) {
+        if self.free.len() < MAX_POOLED && buf.len() == self.size {
+            self.free.push(buf);
+        }
+    }
+}
+
+/// Event wire contract. v1 is the original JSON; v2 adds `ts_us`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub(crate) enum EventSchema {
+    #[default]
+    V1 = 1,
+    V2 = 2,
+}
+
+impl EventSchema {
+    pub(crate) const LATEST: Self = Self::V2;
+
+    pub(crate) fn from_version(version: u32) -> Option<Self> {
+        match version {
+            1 => Some(Self::V1),
+            2 => Some(Self::V2),
+            _ => None,
+        }
+    }
+}
+
+/// Origin of v2 `ts_us` timestamps: the first event the library posts.
+static EPOCH: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// Serializes events into one reused buffer. Posting hands ownership of the
+/// string to the Dart port, so each post still costs exactly one allocation,
+/// sized up front (including the NUL allo-isolate appends).
+#[derive(Default)]
+pub(crate) struct EventEncoder {
+    json: Vec<u8>,
+}
+
+impl EventEncoder {
+    pub(crate) fn encode<T: Serialize>(&mut self, event: &T) -> Option<String> {
+        self.json.clear();
+        serde_json::to_writer(&mut self.json, event).ok()?;
+        let json = std::str::from_utf8(&self.json).ok()?;
+        let mut out = String::with_capacity(json.len() + 1);
+        out.push_str(json);
+        Some(out)
+    }
+
+    /// Posts `event` stamped with its handle's next sequence number, or queues
+    /// it for `cc_quic_events_poll` when `port` is `POLL_PORT` (as it is,
+    /// unserialized, for a handle the Rust APIs started). Events the
+    /// port refuses are counted and reported by an `events_dropped`
+    /// marker ahead of the next event that gets through. Events the handle's
+    /// rate limits hold back are not posted at all; see `eventlimit.rs`.
+    pub(crate) fn post(&mut self, port: i64, event: &QuicEvent<'_>) {
+        self.post_with(port, event, true);
+    }
+
+    /// Like [`Self::post`], leaving out the handle's coalesced mirror ports.
+    pub(crate) fn post_direct(&mut self, port: i64, event: &QuicEvent<'_>) {
+        self.post_with(port, event, false);
+    }
+
+    fn post_with(&mut self, port: i64, event: &QuicEvent<'_>, mirrored: bool) {
+        let handle = event.handle();
+        let Some(seq) = event_seq(handle) else {
+            self.post_sequenced(port, 0, EventSchema::V1, event, None, None);
+            return;
+        };
+        let (admitted, suppressed) = seq.admit(event);
+        if !admitted {
+            return;
+        }
+        let schema = seq.schema;
+        if let Some(suppressed) = suppressed {
+            let marker = QuicEvent::EventsSuppressed { handle, suppressed };
+            if !self.post_sequenced(port, seq.next(), schema, &marker, None, Some(&seq.history)) {
+                seq.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let dropped = seq.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            let marker = QuicEvent::EventsDropped {
+                handle,
+                count: dropped,
+            };
+            if self.post_sequenced(port, seq.next(), schema, &marker, None, Some(&seq.history)) {
+                seq.dropped.fetch_sub(dropped, Ordering::Relaxed);
+            } else {
+                seq.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let number = seq.next();
+        let tag = seq.tag(event);
+        // Payload events stay out of the diagnostics history.
+        let history = (!matches!(event, QuicEvent::Message { .. } | QuicEvent::Media { .. }))
+            .then_some(&seq.history);
+        if !self.post_sequenced(port, number, schema, event, tag, history) {
+            seq.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        if mirrored {
+            seq.post_mirrors(event.class(), |mirror| {
+                self.post_sequenced(mirror, number, schema, event, tag, None)
+            });
+        }
+    }
+
+    fn post_sequenced(
+        &mut self,
+        port: i64,
+        seq: u64,
+        schema: EventSchema,
+        event: &QuicEvent<'_>,
+        tag: Option<u64>,
+        history: Option<&EventHistory>,
+    ) -> bool {
+        let ts_us = (schema >= EventSchema::V2).then(|| EPOCH.elapsed().as_micros() as u64);
+        let envelope = Sequenced {
+            schema: schema as u8,
+            seq,
+            ts_us,
+            tag,
+            event,
+        };
+        if port == poll::POLL_PORT {
+            if let Some(taken) = poll::push_typed(event.handle(), || Event::from_quic(event)) {
+                // The diagnostics history keeps JSON either way.
+                if let Some(history) = history {
+                    if let Some(json) = self.encode(&envelope) {
+                        history.record(&json);
+                    }
+                }
+                return taken;
+            }
+        }
+        let json = self.encode(&envelope);
+        if let (Some(history), Some(json)) = (history, &json) {
+            history.record(json);
+        }
+        match json {
+            Some(json) if port == poll::POLL_PORT => poll::push(event.handle(), json),
+            Some(json) => Isolate::new(port).post(json),
+            None => false,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Sequenced<'a, 'e> {
+    schema: u8,
+    seq: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ts_us: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<u64>,
+    #[serde(flatten)]
+    event: &'a QuicEvent<'e>,
+}
+
+/// Per-worker scratch space: receive buffers plus the event encoder.
+pub(crate) struct Scratch {
+    pub pool: BufferPool,
+    pub events: EventEncoder,
+}
+
+impl Scratch {
+    pub(crate) fn new() -> Self {
+        Self {
+            pool: BufferPool::new(STREAM_READ_SIZE),
+            events: EventEncoder::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recycles_buffers_up_to_cap() {
+        let mut pool = BufferPool::new(8);
+        let bufs: Vec<_> = (0..MAX_POOLED + 2).map(|_| pool.take()).collect();
+        for buf in bufs {
+            pool.give(buf);
+        }
+        assert_eq!(pool.free.len(), MAX_POOLED);
+        assert_eq!(pool.take().len(), 8);
+    }
+
+    #[test]
+    fn stamps_seq_ahead_of_event_fields() {
+        let mut encoder = EventEncoder::default();
+        let event = QuicEvent::EventsDropped {
+            handle: 3,
+            count: 2,
+        };
+        let json = encoder
+            .encode(&Sequenced {
+                schema: 2,
+                seq: 9,
+                ts_us: Some(5),
+                tag: None,
+                event: &event,
+            })
+            .unwrap();
+        assert_eq!(
+            json,
+            r#"{"schema":2,"seq":9,"ts_us":5,"type":"events_dropped","handle":3,"count":2}"#
+        );
+    }
+
+    /// Registers `handle` with `events` as a started handle would be.
+    fn register(handle: u64, events: std::sync::Arc<crate::EventSeq>) {
+        let (tx, _rx) = std::sync::mpsc::channel();
+        crate::CONNECTIONS.get_or_init(Default::default).insert(
+            handle,
+            crate::ConnectionHandle {
+                tx,
+                threads: Default::default(),
+                events,
+                live: Default::default(),
+                usage: Default::default(),
+                role: crate::ConfigRole::Client,
+                blocklist: None,
+                roster: None,
+                allowlist: None,
+            },
+        );
+    }
+
+    #[test]
+    fn counts_events_the_port_refuses() {
+        let handle = u64::MAX - 2;
+        let events = std::sync::Arc::new(crate::EventSeq::default());
+        register(handle, events.clone());
+        // No Dart API is initialised in tests, so every post is refused.
+        let mut encoder = EventEncoder::default();
+        let event = QuicEvent::WorkerStalled {
+            handle,
+            last_progress_ms: 1,
+        };
+        encoder.post(1, &event);
+        encoder.post(1, &event);
+        // Second post also tried (and lost) an events_dropped marker.
+        assert_eq!(events.dropped.load(Ordering::Relaxed), 3);
+        assert_eq!(events.next.load(Ordering::Relaxed), 3);
+        crate::CONNECTIONS.get().unwrap().remove(&handle);
+    }
+
+    #[test]
+    fn the_negotiated_schema_shapes_the_envelope() {
+        let handle = u64::MAX - 7;
+        let mut encoder = EventEncoder::default();
+        let event = QuicEvent::WorkerStalled {
+            handle,
+            last_progress_ms: 1,
+        };
+        let mut posted = |schema| {
+            let events = crate::EventSeq {
+                schema,
+                ..Default::default()
+            };
+            register(handle, std::sync::Arc::new(events));
+            encoder.post(poll::POLL_PORT, &event);
+            crate::CONNECTIONS.get().unwrap().remove(&handle);
+            let mut out = [0u8; 512];
+            assert_eq!(poll::drain(handle, &mut out, 1, false), Ok(1));
+            let end = out.iter().position(|b| *b == b'\n').unwrap();
+            serde_json::from_slice::<serde_json::Value>(&out[..end]).unwrap()
+        };
+        let v1 = posted(EventSchema::V1);
+        assert_eq!(v1["schema"], 1);
+        assert_eq!(v1.get("ts_us"), None);
+        let v2 = posted(EventSchema::V2);
+        assert_eq!(v2["schema"], 2);
+        assert!(v2["ts_us"].is_u64());
+        assert_eq!(v2["type"], "worker_stalled");
+    }
+
+    #[test]
+    fn encodes_with_room_for_nul() {
+        let mut encoder = EventEncoder::default();
+        let json = encoder.encode(&serde_json::json!({"a": 1})).unwrap();
+        assert_eq!(json, r#"{"a":1}"#);
+        assert!(json.capacity() > json.len());
+    }
+}
@@ -0,0 +1,172 @@
+//! Capability exchange (`cc_quic_config_set_capabilities`). Once a
+//! connection is announced each side sends the features it runs on it, on
+//! a dedicated unidirectional stream (client id 14, server id 23) so app
+//! framing on the control stream is untouched. When the peer's record is in
+//! both sides post `capabilities_negotiated` with the two sets and their
+//! intersection, which is what the connection can use.
+//!
+//! Record: `version u8 | length u8 | bitmap (length bytes, BE)`. A peer on
+//! a later version may send a longer bitmap; bits this build does not know
+//! are dropped, and the negotiated version is the lower of the two.
+
+const CLIENT_STREAM: u64 = 14;
+const SERVER_STREAM: u64 = 23;
+const VERSION: u8 = 1;
+
+pub(crate) const FRAMING_V2: u32 = 1 << 0;
+pub(crate) const COMPRESSION: u32 = 1 << 1;
+pub(crate) const MEDIA_DATAGRAMS: u32 = 1 << 2;
+pub(crate) const FEC: u32 = 1 << 3;
+/// Every feature this build knows.
+pub(crate) const KNOWN: u32 = FRAMING_V2 | COMPRESSION | MEDIA_DATAGRAMS | FEC;
+
+const NAMES: [(u32, &str); 4] = [
+    (FRAMING_V2, "framing_v2"),
+    (COMPRESSION, "compression"),
+    (MEDIA_DATAGRAMS, "media_datagrams"),
+    (FEC, "fec"),
+];
+
+pub(crate) fn is_capability_stream(stream_id: u64) -> bool {
+    stream_id == CLIENT_STREAM || stream_id == SERVER_STREAM
+}
+
+/// Wire names of the features in `bits`, lowest bit first.
+pub(crate) fn names(bits: u32) -> Vec<&'static str> {
+    NAMES
+        .iter()
+        .filter(|(bit, _)| bits & bit != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// The feature named `name`, as `names` spells it.
+pub(crate) fn bit(name: &str) -> Option<u32> {
+    NAMES
+        .iter()
+        .find(|(_, known)| *known == name)
+        .map(|(bit, _)| *bit)
+}
+
+/// What the two sides agreed on.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Negotiated {
+    pub version: u8,
+    pub local: u32,
+    pub peer: u32,
+}
+
+impl Negotiated {
+    pub(crate) fn resolved(&self) -> u32 {
+        self.local & self.peer
+    }
+}
+
+/// One connection's side of the exchange.
+#[derive(Default)]
+pub(crate) struct CapabilityExchange {
+    sent: Option<u32>,
+    partial: Vec<u8>,
+    /// The peer's version and known bits, once its record is complete.
+    peer: Option<(u8, u32)>,
+    posted: bool,
+}
+
+impl CapabilityExchange {
+    /// Feeds bytes read from the peer's capability stream. Anything past
+    /// the first record is ignored.
+    pub(crate) fn on_data(&mut self, data: &[u8]) {
+        if self.peer.is_some() {
+            return;
+        }
+        self.partial.extend_from_slice(data);
+        let [version, len, ..] = self.partial[..] else {
+            return;
+        };
+        let Some(bitmap) = self.partial.get(2..2 + usize::from(len)) else {
+            return;
+        };
+        // The low 32 bits are the last four bytes of the bitmap.
+        let bits = bitmap
+            .iter()
+            .rev()
+            .take(4)
+            .rev()
+            .fold(0u32, |bits, byte| bits << 8 | u32::from(*byte));
+        self.peer = Some((version, bits & KNOWN));
+        self.partial = Vec::new();
+    }
+
+    /// Sends `local` to the peer of an announced connection, once; returns
+    /// the outcome the first time the peer's record is also in.
+    pub(crate) fn poll(
+        &mut self,
+        conn: &mut quiche::Connection,
+        is_server: bool,
+        local: u32,
+    ) -> Option<Negotiated> {
+        if self.sent.is_none() {
+            let stream_id = if is_server {
+                SERVER_STREAM
+            } else {
+                CLIENT_STREAM
+            };
+            let mut record = vec![VERSION, 4];
+            record.extend_from_slice(&local.to_be_bytes());
+            match conn.stream_send(stream_id, &record, true) {
+                Ok(_) => self.sent = Some(local),
+                Err(err) => {
+                    log::warn!("{} capability send error: {err:?}", conn.trace_id());
+                    return None;
+                }
+            }
+        }
+        let (version, peer) = self.peer.filter(|_| !self.posted)?;
+        self.posted = true;
+        Some(Negotiated {
+            version: version.min(VERSION),
+            local: self.sent.unwrap_or(local),
+            peer,
+        })
+    }
+}
+
+/// The features in `wanted` that `conn` can run: compression needs the
+/// deflate ALPN to have been negotiated, media datagrams need both sides to
+/// have enabled datagrams.
+pub(crate) fn available(wanted: u32, conn: &quiche::Connection) -> u32 {
+    let mut bits = wanted & KNOWN;
+    if conn.application_proto() != crate::compress::DEFLATE_ALPN {
+        bits &= !COMPRESSION;
+    }
+    if conn.dgram_max_writable_len().is_none() {
+        bits &= !MEDIA_DATAGRAMS;
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_a_split_record_and_drops_unknown_bits() {
+        // A later version with a six-byte bitmap and a feature we lack.
+        let mut wire = vec![2, 6, 0xff, 0xff, 0, 0, 0];
+        wire.push(0x80 | (FEC | COMPRESSION) as u8);
+        wire.push(9);
+        let mut exchange = CapabilityExchange::default();
+        exchange.on_data(&wire[..4]);
+        assert_eq!(exchange.peer, None);
+        exchange.on_data(&wire[4..]);
+        assert_eq!(exchange.peer, Some((2, FEC | COMPRESSION)));
+
+        let negotiated = Negotiated {
+            version: 1,
+            local: FRAMING_V2 | FEC,
+            peer: FEC | COMPRESSION,
+        };
+        assert_eq!(names(negotiated.resolved()), vec!["fec"]);
+        assert_eq!(names(KNOWN).len(), NAMES.len());
+    }
+}
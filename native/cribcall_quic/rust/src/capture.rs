@@ -0,0 +1,265 @@
+//! Per-connection packet capture (`cc_quic_conn_capture`) for devices where
+//! tcpdump cannot run. Each datagram the connection sends or receives is
+//! written as it crosses the socket, still encrypted, to a pcapng file
+//! with its timestamp and addresses. Datagrams carry synthesized IPv4 or
+//! IPv6 and UDP headers on a raw-IP interface, so Wireshark dissects them
+//! as QUIC on the right ports (decrypting them needs the TLS keys). Sent
+//! datagrams are recorded as quiche produced them, before any impairment.
+//! The capture ends once the file would pass its byte cap.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Cap applied when `cc_quic_conn_capture` is given 0.
+pub(crate) const DEFAULT_MAX_BYTES: u64 = 16 * 1024 * 1024;
+
+const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const ENHANCED_PACKET: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+/// `LINKTYPE_RAW`: packets start with an IPv4 or IPv6 header.
+const LINKTYPE_RAW: u16 = 101;
+const UDP: u8 = 17;
+const OPT_END: u16 = 0;
+const SHB_USERAPPL: u16 = 4;
+
+pub(crate) struct PacketCapture {
+    out: BufWriter<File>,
+    written: u64,
+    max_bytes: u64,
+}
+
+impl fmt::Debug for PacketCapture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PacketCapture")
+            .field("written", &self.written)
+            .field("max_bytes", &self.max_bytes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PacketCapture {
+    /// Creates (or truncates) `path` and writes the pcapng headers.
+    pub(crate) fn create(path: &Path, max_bytes: u64) -> io::Result<Self> {
+        let mut capture = Self {
+            out: BufWriter::new(File::create(path)?),
+            written: 0,
+            max_bytes,
+        };
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        // Section length not given.
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        push_option(&mut shb, SHB_USERAPPL, b"cribcall_quic");
+        push_option(&mut shb, OPT_END, &[]);
+        capture.block(SECTION_HEADER, &shb)?;
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        // No snap length.
+        idb.extend_from_slice(&0u32.to_le_bytes());
+        capture.block(INTERFACE_DESCRIPTION, &idb)?;
+        Ok(capture)
+    }
+
+    /// Records one datagram; false once the capture is over, either at its
+    /// cap or after a write error.
+    pub(crate) fn record(&mut self, from: SocketAddr, to: SocketAddr, data: &[u8]) -> bool {
+        let packet = ip_udp_packet(from, to, data);
+        let block_len = 12 + 20 + padded(packet.len()) as u64;
+        if self.written + block_len > self.max_bytes {
+            self.finish();
+            return false;
+        }
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_micros() as u64);
+        let mut epb = Vec::with_capacity(20 + packet.len() + 3);
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(micros as u32).to_le_bytes());
+        epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&packet);
+        if let Err(err) = self.block(ENHANCED_PACKET, &epb) {
+            log::warn!("packet capture write failed: {err}");
+            return false;
+        }
+        true
+    }
+
+    fn finish(&mut self) {
+        log::info!("packet capture reached its {} byte cap", self.max_bytes);
+        if let Err(err) = self.out.flush() {
+            log::warn!("packet capture flush failed: {err}");
+        }
+    }
+
+    /// Writes a block around `body`, padding it to 32 bits.
+    fn block(&mut self, ty: u32, body: &[u8]) -> io::Result<()> {
+        let len = (12 + padded(body.len())) as u32;
+        self.out.write_all(&ty.to_le_bytes())?;
+        self.out.write_all(&len.to_le_bytes())?;
+        self.out.write_all(body)?;
+        self.out
+            .write_all(&[0; 3][..padded(body.len()) - body.len()])?;
+        self.out.write_all(&len.to_le_bytes())?;
+        self.written += u64::from(len);
+        Ok(())
+    }
+}
+
+/// Records a datagram into the capture in `slot`, if any, and clears the
+/// slot once that capture is over.
+pub(crate) fn record(
+    slot: &mut Option<PacketCapture>,
+    from: SocketAddr,
+    to: SocketAddr,
+    data: &[u8],
+) {
+    if slot
+        .as_mut()
+        .is_some_and(|capture| !capture.record(from, to, data))
+    {
+        *slot = None;
+    }
+}
+
+fn padded(len: usize) -> usize {
+    len.next_multiple_of(4)
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    body.resize(padded(body.len()), 0);
+}
+
+/// `data` behind IP and UDP headers from `from` to `to`. Mixed families
+/// (a dual-stack socket) are written as IPv6 with the IPv4 side mapped.
+fn ip_udp_packet(from: SocketAddr, to: SocketAddr, data: &[u8]) -> Vec<u8> {
+    let udp_len = 8 + data.len();
+    let mut udp = Vec::with_capacity(udp_len);
+    udp.extend_from_slice(&from.port().to_be_bytes());
+    udp.extend_from_slice(&to.port().to_be_bytes());
+    udp.extend_from_slice(&(udp_len as u16).to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(data);
+
+    let mut packet;
+    let pseudo_sum;
+    match (from.ip(), to.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            packet = Vec::with_capacity(20 + udp_len);
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&((20 + udp_len) as u16).to_be_bytes());
+            // Identification, then don't fragment.
+            packet.extend_from_slice(&[0, 0, 0x40, 0, 64, UDP, 0, 0]);
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+            let header_sum = !fold(sum(&packet));
+            packet[10..12].copy_from_slice(&header_sum.to_be_bytes());
+            pseudo_sum = sum(&src.octets()) + sum(&dst.octets()) + u32::from(UDP) + udp_len as u32;
+        }
+        (src, dst) => {
+            let (src, dst) = (v6(src), v6(dst));
+            packet = Vec::with_capacity(40 + udp_len);
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(udp_len as u16).to_be_bytes());
+            packet.extend_from_slice(&[UDP, 64]);
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+            pseudo_sum = sum(&src.octets()) + sum(&dst.octets()) + u32::from(UDP) + udp_len as u32;
+        }
+    }
+    // A computed zero is sent as all ones; zero means no checksum.
+    let checksum = match !fold(pseudo_sum + sum(&udp)) {
+        0 => 0xffff,
+        checksum => checksum,
+    };
+    udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+    packet.extend_from_slice(&udp);
+    packet
+}
+
+fn v6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// Ones' complement sum of `bytes` as 16-bit big-endian words, unfolded.
+fn sum(bytes: &[u8]) -> u32 {
+    bytes
+        .chunks(2)
+        .map(|word| u32::from(word[0]) << 8 | word.get(1).map_or(0, |low| u32::from(*low)))
+        .sum()
+}
+
+fn fold(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn writes_pcapng_blocks_until_the_cap() {
+        let path = std::env::temp_dir().join(format!("cc-capture-{}.pcapng", std::process::id()));
+        let from: SocketAddr = "192.0.2.1:50000".parse().unwrap();
+        let to: SocketAddr = "192.0.2.9:4433".parse().unwrap();
+        let mut capture = PacketCapture::create(&path, 300).unwrap();
+        assert!(capture.record(from, to, &[0xc0; 61]));
+        // The second one would pass the cap.
+        assert!(!capture.record(to, from, &[0x40; 61]));
+        drop(capture);
+
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(u32_at(&bytes, 0), SECTION_HEADER);
+        assert_eq!(u32_at(&bytes, 8), BYTE_ORDER_MAGIC);
+        let shb_len = u32_at(&bytes, 4) as usize;
+        assert_eq!(u32_at(&bytes, shb_len), INTERFACE_DESCRIPTION);
+        let idb_len = u32_at(&bytes, shb_len + 4) as usize;
+        let epb = &bytes[shb_len + idb_len..];
+        assert_eq!(u32_at(epb, 0), ENHANCED_PACKET);
+        assert_eq!(u32_at(epb, 4) as usize, epb.len());
+        // IPv4 + UDP + payload, padded.
+        assert_eq!(u32_at(epb, 20), 20 + 8 + 61);
+        assert_eq!(epb.len(), 32 + padded(89));
+        let packet = &epb[28..28 + 89];
+        assert_eq!(fold(sum(&packet[..20])), 0xffff, "IPv4 header checksum");
+        let pseudo = sum(&packet[12..20]) + u32::from(UDP) + 69;
+        assert_eq!(fold(pseudo + sum(&packet[20..])), 0xffff, "UDP checksum");
+        assert_eq!(&packet[20..24], &[0xc3, 0x50, 0x11, 0x51]);
+    }
+
+    #[test]
+    fn mixed_families_are_written_as_ipv6() {
+        let from: SocketAddr = "[2001:db8::1]:4433".parse().unwrap();
+        let to: SocketAddr = "192.0.2.9:50000".parse().unwrap();
+        let packet = ip_udp_packet(from, to, b"quic");
+        assert_eq!(packet[0] >> 4, 6);
+        assert_eq!(packet.len(), 40 + 8 + 4);
+        assert_eq!(&packet[24..40], &v6(to.ip()).octets());
+        let pseudo = sum(&packet[8..40]) + u32::from(UDP) + 12;
+        assert_eq!(fold(pseudo + sum(&packet[40..])), 0xffff);
+    }
+}
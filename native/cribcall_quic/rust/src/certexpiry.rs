@@ -0,0 +1,168 @@
+//! Certificate validity checks. Device certs are long-lived and self-signed,
+//! so nothing else notices them running out: the local cert is checked at
+//! load (an expired one fails with `cert_expired`) and both ends post
+//! `cert_expiring_soon` inside the configured warning window.
+//!
+//! Only the `notAfter` field is read, by walking the DER just far enough to
+//! reach it; a certificate this cannot parse is not checked.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Warn this many days ahead unless the config says otherwise.
+pub(crate) const DEFAULT_WARN_DAYS: u32 = 30;
+
+pub(crate) const SECS_PER_DAY: i64 = 86_400;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CertSide {
+    Local,
+    Peer,
+}
+
+/// `notAfter` of the first certificate in the PEM file at `path`, in Unix
+/// seconds.
+pub(crate) fn pem_not_after(path: &str) -> Option<i64> {
+    not_after(&pem_der(path)?)
+}
+
+/// DER of the first (leaf) certificate in the PEM file at `path`.
+pub(crate) fn pem_der(path: &str) -> Option<Vec<u8>> {
+    let pem = std::fs::read_to_string(path).ok()?;
+    let (_, body) = pem.split_once("-----BEGIN CERTIFICATE-----")?;
+    let (body, _) = body.split_once("-----END CERTIFICATE-----")?;
+    let b64: String = body.split_whitespace().collect();
+    BASE64.decode(b64).ok()
+}
+
+/// `notAfter` of a DER certificate, in Unix seconds.
+pub(crate) fn not_after(der: &[u8]) -> Option<i64> {
+    let (cert, _) = expect(0x30, der)?;
+    let (mut tbs, _) = expect(0x30, cert)?;
+    if tbs.first() == Some(&0xa0) {
+        tbs = tlv(tbs)?.2;
+    }
+    // serialNumber, signature, issuer.
+    for tag in [0x02, 0x30, 0x30] {
+        tbs = expect(tag, tbs)?.1;
+    }
+    let (validity, _) = expect(0x30, tbs)?;
+    let (_, _, validity) = tlv(validity)?;
+    let (tag, time, _) = tlv(validity)?;
+    parse_time(tag, time)
+}
+
+/// Whole days from `now` to `not_after`, rounded down: negative once the
+/// certificate has expired.
+pub(crate) fn days_left(not_after: i64, now: SystemTime) -> i64 {
+    let now = match now.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    };
+    (not_after - now).div_euclid(SECS_PER_DAY)
+}
+
+/// Days left when within `warn_days` (0 never warns).
+pub(crate) fn warning(not_after: Option<i64>, warn_days: u32) -> Option<i64> {
+    let days = days_left(not_after?, SystemTime::now());
+    (warn_days > 0 && days < i64::from(warn_days)).then_some(days)
+}
+
+/// Splits one DER element into (tag, contents, rest).
+fn tlv(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first, mut input) = input.split_first()?;
+    let len = if first < 0x80 {
+        usize::from(first)
+    } else {
+        let n = usize::from(first & 0x7f);
+        if n == 0 || n > 4 || input.len() < n {
+            return None;
+        }
+        let (len, rest) = input.split_at(n);
+        input = rest;
+        len.iter().fold(0, |acc, &b| (acc << 8) | usize::from(b))
+    };
+    (input.len() >= len).then(|| (tag, &input[..len], &input[len..]))
+}
+
+fn expect(tag: u8, input: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (found, contents, rest) = tlv(input)?;
+    (found == tag).then_some((contents, rest))
+}
+
+/// UTCTime (`YYMMDDHHMMSSZ`) or GeneralizedTime (`YYYYMMDDHHMMSSZ`), the
+/// only forms DER allows.
+fn parse_time(tag: u8, text: &[u8]) -> Option<i64> {
+    let text = std::str::from_utf8(text).ok()?.strip_suffix('Z')?;
+    let (year, rest) = match (tag, text.len()) {
+        (0x17, 12) => {
+            let yy: i64 = text[..2].parse().ok()?;
+            (if yy < 50 { 2000 + yy } else { 1900 + yy }, &text[2..])
+        }
+        (0x18, 14) => (text[..4].parse().ok()?, &text[4..]),
+        _ => return None,
+    };
+    if !rest.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let field = |i: usize| rest[i..i + 2].parse::<i64>().ok();
+    let (month, day) = (field(0)?, field(2)?);
+    let (hour, minute, second) = (field(4)?, field(6)?, field(8)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    Some(days * SECS_PER_DAY + hour * 3600 + minute * 60 + second)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        if contents.len() < 0x80 {
+            out.push(contents.len() as u8);
+        } else {
+            out.extend([0x82, (contents.len() >> 8) as u8, contents.len() as u8]);
+        }
+        out.extend_from_slice(contents);
+        out
+    }
+
+    #[test]
+    fn reads_not_after_from_a_certificate() {
+        let validity = [der(0x17, b"260101000000Z"), der(0x18, b"20500101000000Z")].concat();
+        let tbs = [
+            der(0xa0, &der(0x02, &[2])),
+            der(0x02, &[1]),
+            der(0x30, &[]),
+            der(0x30, &[0x20; 200]),
+            der(0x30, &validity),
+            der(0x30, &[]),
+        ]
+        .concat();
+        let cert = der(0x30, &[der(0x30, &tbs), der(0x30, &[])].concat());
+        assert_eq!(not_after(&cert), Some(2_524_608_000));
+        assert_eq!(not_after(&cert[..cert.len() - 4]), None);
+
+        let now = UNIX_EPOCH + Duration::from_secs(2_524_608_000 - 3 * SECS_PER_DAY as u64 + 1);
+        assert_eq!(days_left(2_524_608_000, now), 2);
+        let later = UNIX_EPOCH + Duration::from_secs(2_524_608_001);
+        assert_eq!(days_left(2_524_608_000, later), -1);
+    }
+}
@@ -0,0 +1,544 @@
+//! Named channels (`cc_quic_channel_open`): each name gets a bidirectional
+//! stream of its own, so "audio-meta" and "firmware" have independent flow
+//! control and head-of-line blocking. Client-opened channels use stream
+//! ids 8, 12, …; server-opened ones 9, 13, … (0, 4 and 5 belong to the
+//! control and timesync streams).
+//!
+//! Handshake: the opener starts the stream with `name length u8 | name`;
+//! the acceptor answers one byte, `0` accepted or `1` refused (the name is
+//! already open on the connection) followed by FIN. Both sides post
+//! `channel_opened` once the channel is usable, and data on it arrives as
+//! `message` events tagged with the channel name.
+//!
+//! Either side can also abort a channel stream by its id: a reset drops
+//! what it has not delivered yet, and stop-sending asks the peer to do the
+//! same, so a cancelled download stops using bandwidth at once instead of
+//! draining. The peer posts `stream_reset` or `stream_stopped`.
+//!
+//! With writable events on, an open channel whose stream gains send credit
+//! posts `stream_writable` with how many bytes it takes now, so a sender
+//! can pull: send up to `capacity`, then wait for the next event. quiche
+//! reports a stream again once a send is cut short or the peer extends its
+//! window.
+
+use std::collections::HashMap;
+
+const CLIENT_FIRST: u64 = 8;
+const SERVER_FIRST: u64 = 9;
+const ACCEPTED: u8 = 0;
+const REFUSED: u8 = 1;
+/// Longest name the one-byte length prefix can carry.
+pub(crate) const MAX_NAME_LEN: usize = 255;
+/// Largest application error code (a 62-bit varint).
+pub(crate) const MAX_ERROR_CODE: u64 = (1 << 62) - 1;
+
+/// Whether `stream_id` is a bidirectional stream channels may use.
+pub(crate) fn is_channel_stream(stream_id: u64) -> bool {
+    stream_id >= CLIENT_FIRST && stream_id % 4 < 2
+}
+
+#[derive(Debug)]
+pub(crate) enum ChannelOp {
+    Open,
+    Send(Vec<u8>),
+    Close,
+}
+
+/// Which half of a stream `cc_quic_stream_reset` and
+/// `cc_quic_stream_stop_sending` abort.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum Abort {
+    /// Ours: RESET_STREAM.
+    Reset,
+    /// The peer's: STOP_SENDING.
+    StopSending,
+}
+
+/// Something to post about a channel.
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum Notice {
+    Opened {
+        channel: String,
+        stream_id: u64,
+        by_peer: bool,
+    },
+    Closed {
+        channel: String,
+    },
+    Refused {
+        channel: String,
+    },
+    /// The peer reset `stream_id`; `channel` if one was open on it.
+    Reset {
+        channel: Option<String>,
+        stream_id: u64,
+        error_code: u64,
+    },
+    /// The peer asked us to stop sending on the channel's stream.
+    Stopped {
+        channel: String,
+        stream_id: u64,
+        error_code: u64,
+    },
+    /// The channel's stream takes `capacity` more bytes.
+    Writable {
+        channel: String,
+        stream_id: u64,
+        capacity: usize,
+    },
+}
+
+enum State {
+    /// Peer-opened, name still arriving.
+    Naming(Vec<u8>),
+    /// Opened here, waiting for the accept byte.
+    Offered,
+    Open,
+    /// Closed or refused here; the rest of the stream is discarded.
+    Closing,
+}
+
+struct Channel {
+    name: String,
+    state: State,
+    /// The peer sent STOP_SENDING: our half is reset, theirs still open.
+    stopped: bool,
+}
+
+pub(crate) struct Channels {
+    by_stream: HashMap<u64, Channel>,
+    by_name: HashMap<String, u64>,
+    next_local: u64,
+}
+
+impl Channels {
+    pub(crate) fn new(is_server: bool) -> Self {
+        Self {
+            by_stream: HashMap::new(),
+            by_name: HashMap::new(),
+            next_local: if is_server {
+                SERVER_FIRST
+            } else {
+                CLIENT_FIRST
+            },
+        }
+    }
+
+    /// Name of the open channel on `stream_id`.
+    pub(crate) fn name(&self, stream_id: u64) -> Option<&str> {
+        let channel = self.by_stream.get(&stream_id)?;
+        matches!(channel.state, State::Open).then_some(channel.name.as_str())
+    }
+
+    /// Runs a command from the FFI; returns the bytes queued, or the
+    /// message for an `error` event.
+    pub(crate) fn apply(
+        &mut self,
+        conn: &mut quiche::Connection,
+        name: &str,
+        op: ChannelOp,
+    ) -> Result<usize, String> {
+        match op {
+            ChannelOp::Open => self.open(conn, name).map(|()| 0),
+            ChannelOp::Send(data) => {
+                let stream_id = self.open_stream(name)?;
+                match conn.stream_send(stream_id, &data, false) {
+                    Ok(written) => Ok(written),
+                    Err(quiche::Error::Done) => Ok(0),
+                    Err(err) => Err(format!("channel {name}: send failed: {err:?}")),
+                }
+            }
+            ChannelOp::Close => {
+                let stream_id = self.open_stream(name)?;
+                self.wind_down(stream_id);
+                match conn.stream_send(stream_id, &[], true) {
+                    // A stopped half is already reset; nothing to finish.
+                    Ok(_) | Err(quiche::Error::StreamStopped(_)) => Ok(0),
+                    Err(err) => Err(format!("channel {name}: close failed: {err:?}")),
+                }
+            }
+        }
+    }
+
+    /// Aborts our (`Reset`) or the peer's (`StopSending`) half of
+    /// `stream_id`. A reset ends the channel on it as a close would; after
+    /// a stop-sending the channel stays open for our sends until it is
+    /// closed. A stream that is already finished or gone is left alone.
+    pub(crate) fn abort(
+        &mut self,
+        conn: &mut quiche::Connection,
+        stream_id: u64,
+        abort: Abort,
+        error_code: u64,
+    ) -> Result<(), String> {
+        let direction = match abort {
+            Abort::Reset => quiche::Shutdown::Write,
+            Abort::StopSending => quiche::Shutdown::Read,
+        };
+        match conn.stream_shutdown(stream_id, direction, error_code) {
+            Ok(()) | Err(quiche::Error::Done) => {}
+            Err(err) => return Err(format!("stream {stream_id}: {abort:?} failed: {err:?}")),
+        }
+        if let Abort::Reset = abort {
+            self.wind_down(stream_id);
+        }
+        Ok(())
+    }
+
+    fn open(&mut self, conn: &mut quiche::Connection, name: &str) -> Result<(), String> {
+        if self.by_name.contains_key(name) {
+            return Err(format!("channel {name}: already open"));
+        }
+        let stream_id = self.next_local;
+        let mut header = vec![name.len() as u8];
+        header.extend_from_slice(name.as_bytes());
+        match conn.stream_send(stream_id, &header, false) {
+            Ok(_) => {}
+            Err(quiche::Error::StreamLimit) => {
+                return Err(format!("channel {name}: peer allows no more streams"));
+            }
+            Err(err) => return Err(format!("channel {name}: open failed: {err:?}")),
+        }
+        self.next_local += 4;
+        self.by_name.insert(name.to_string(), stream_id);
+        self.by_stream.insert(
+            stream_id,
+            Channel {
+                name: name.to_string(),
+                state: State::Offered,
+                stopped: false,
+            },
+        );
+        Ok(())
+    }
+
+    fn open_stream(&self, name: &str) -> Result<u64, String> {
+        match self.by_name.get(name) {
+            Some(&stream_id) if self.name(stream_id).is_some() => Ok(stream_id),
+            Some(_) => Err(format!("channel {name}: not accepted yet")),
+            None => Err(format!("channel {name}: not open")),
+        }
+    }
+
+    /// Frees the channel's name and drops whatever else arrives on it.
+    fn wind_down(&mut self, stream_id: u64) {
+        let Some(channel) = self.by_stream.get_mut(&stream_id) else {
+            return;
+        };
+        channel.state = State::Closing;
+        if self.by_name.get(&channel.name) == Some(&stream_id) {
+            self.by_name.remove(&channel.name);
+        }
+    }
+
+    /// Feeds bytes read from channel stream `stream_id`; returns how many
+    /// leading bytes are not channel data (handshake, or all of them on a
+    /// channel that is not open).
+    pub(crate) fn on_data(
+        &mut self,
+        conn: &mut quiche::Connection,
+        stream_id: u64,
+        data: &[u8],
+        notices: &mut Vec<Notice>,
+    ) -> usize {
+        let channel = self.by_stream.entry(stream_id).or_insert(Channel {
+            name: String::new(),
+            state: State::Naming(Vec::new()),
+            stopped: false,
+        });
+        match &mut channel.state {
+            State::Open => 0,
+            State::Closing => data.len(),
+            State::Offered => match data.first() {
+                None => 0,
+                Some(&ACCEPTED) => {
+                    channel.state = State::Open;
+                    notices.push(Notice::Opened {
+                        channel: channel.name.clone(),
+                        stream_id,
+                        by_peer: false,
+                    });
+                    1
+                }
+                Some(_) => {
+                    notices.push(Notice::Refused {
+                        channel: channel.name.clone(),
+                    });
+                    self.wind_down(stream_id);
+                    data.len()
+                }
+            },
+            State::Naming(partial) => {
+                partial.extend_from_slice(data);
+                match partial.first().map(|&len| usize::from(len)) {
+                    Some(len) if partial.len() > len => {
+                        let name = String::from_utf8_lossy(&partial[1..=len]).into_owned();
+                        let used = data.len() - (partial.len() - 1 - len);
+                        self.accept(conn, stream_id, name, notices);
+                        used
+                    }
+                    _ => data.len(),
+                }
+            }
+        }
+    }
+
+    /// The peer finished `stream_id`: ends the channel on it.
+    pub(crate) fn on_fin(
+        &mut self,
+        conn: &mut quiche::Connection,
+        stream_id: u64,
+        notices: &mut Vec<Notice>,
+    ) {
+        if let Some(name) = self.name(stream_id) {
+            notices.push(Notice::Closed {
+                channel: name.to_string(),
+            });
+        }
+        self.wind_down(stream_id);
+        self.by_stream.remove(&stream_id);
+        // Finish our half too, so quiche can release the stream; it is
+        // already finished if we closed first.
+        let _ = conn.stream_send(stream_id, &[], true);
+    }
+
+    /// The peer reset `stream_id`; a channel on it ends as if finished.
+    pub(crate) fn on_reset(
+        &mut self,
+        conn: &mut quiche::Connection,
+        stream_id: u64,
+        error_code: u64,
+        notices: &mut Vec<Notice>,
+    ) {
+        notices.push(Notice::Reset {
+            channel: self.name(stream_id).map(str::to_string),
+            stream_id,
+            error_code,
+        });
+        if is_channel_stream(stream_id) {
+            self.on_fin(conn, stream_id, notices);
+        }
+    }
+
+    /// Posts STOP_SENDING once for each open channel the peer sent it on
+    /// (quiche has already reset our half, and the peer's half still
+    /// delivers), then drains quiche's writable queue, posting capacities
+    /// with `report`.
+    pub(crate) fn poll_writable(
+        &mut self,
+        conn: &mut quiche::Connection,
+        report: bool,
+        notices: &mut Vec<Notice>,
+    ) {
+        // quiche queues a stopped stream as writable only if it was not
+        // writable already, so the open channels are asked directly.
+        for (&stream_id, channel) in &mut self.by_stream {
+            if channel.stopped || !matches!(channel.state, State::Open) {
+                continue;
+            }
+            if let Err(quiche::Error::StreamStopped(error_code)) = conn.stream_capacity(stream_id) {
+                channel.stopped = true;
+                notices.push(Notice::Stopped {
+                    channel: channel.name.clone(),
+                    stream_id,
+                    error_code,
+                });
+            }
+        }
+        while let Some(stream_id) = conn.stream_writable_next() {
+            let Some(name) = self.name(stream_id) else {
+                continue;
+            };
+            match conn.stream_capacity(stream_id) {
+                Ok(capacity) if report => notices.push(Notice::Writable {
+                    channel: name.to_string(),
+                    stream_id,
+                    capacity,
+                }),
+                _ => {}
+            }
+        }
+    }
+
+    /// Answers a peer's open of `name` on `stream_id`.
+    fn accept(
+        &mut self,
+        conn: &mut quiche::Connection,
+        stream_id: u64,
+        name: String,
+        notices: &mut Vec<Notice>,
+    ) {
+        let taken = name.is_empty() || self.by_name.contains_key(&name);
+        let reply = if taken { REFUSED } else { ACCEPTED };
+        if let Err(err) = conn.stream_send(stream_id, &[reply], taken) {
+            log::debug!("channel {name}: handshake reply failed: {err:?}");
+        }
+        let state = if taken {
+            State::Closing
+        } else {
+            self.by_name.insert(name.clone(), stream_id);
+            notices.push(Notice::Opened {
+                channel: name.clone(),
+                stream_id,
+                by_peer: true,
+            });
+            State::Open
+        };
+        let channel = Channel {
+            name,
+            state,
+            stopped: false,
+        };
+        self.by_stream.insert(stream_id, channel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loopback::{self, abi};
+    use crate::{cc_quic_config_free, cc_quic_config_new_client, cc_quic_config_new_server};
+    use crate::{cc_quic_conn_close, CcQuicConfig, CcQuicStatus};
+    use std::time::Duration;
+
+    /// A loopback client and server with channel "dl" open from the client.
+    struct Pair {
+        server: u64,
+        client: u64,
+        client_conn: Vec<u8>,
+        stream_id: u64,
+    }
+
+    impl Pair {
+        fn open() -> Self {
+            Self::open_with(|_, _| {})
+        }
+
+        /// As `open`, with `tune` given the server and client configs first.
+        fn open_with(tune: impl FnOnce(*mut CcQuicConfig, *mut CcQuicConfig)) -> Self {
+            let server_config = abi::config(cc_quic_config_new_server);
+            let client_config = abi::config(cc_quic_config_new_client);
+            tune(server_config, client_config);
+            let (server, bound) = abi::serve(server_config, "127.0.0.1:0");
+            cc_quic_config_free(server_config);
+            let port = bound[0].port();
+            let client = abi::connect(client_config, port, loopback::SERVER_PIN, 0).unwrap();
+            cc_quic_config_free(client_config);
+            let client_conn = abi::connected(client);
+            abi::connected(server);
+            let (id, len) = (client_conn.as_ptr(), client_conn.len());
+            assert_eq!(
+                crate::cc_quic_channel_open(client, id, len, c"dl".as_ptr()),
+                0
+            );
+            let stream_id = abi::poll_for(client, |event| match event["type"].as_str() {
+                Some("channel_opened") => event["stream_id"].as_u64(),
+                _ => None,
+            });
+            Self {
+                server,
+                client,
+                client_conn,
+                stream_id,
+            }
+        }
+
+        fn server_event(&self, kind: &str) -> serde_json::Value {
+            abi::poll_for(self.server, |event| {
+                (event["type"] == kind).then(|| event.clone())
+            })
+        }
+    }
+
+    impl Drop for Pair {
+        fn drop(&mut self) {
+            cc_quic_conn_close(self.client);
+            cc_quic_conn_close(self.server);
+        }
+    }
+
+    #[test]
+    fn resets_reach_the_peer_and_end_the_channel() {
+        let pair = Pair::open();
+        let (id, len) = (pair.client_conn.as_ptr(), pair.client_conn.len());
+        let config_error = CcQuicStatus::ConfigError.code();
+        assert_eq!(
+            crate::cc_quic_stream_reset(pair.client, id, len, 0, 7),
+            config_error
+        );
+        assert_eq!(
+            crate::cc_quic_stream_reset(pair.client, id, len, pair.stream_id, 7),
+            0
+        );
+        let reset = pair.server_event("stream_reset");
+        assert_eq!(reset["error_code"], 7);
+        assert_eq!(reset["channel"], "dl");
+        assert_eq!(pair.server_event("channel_closed")["channel"], "dl");
+    }
+
+    #[test]
+    fn stop_sending_ends_only_the_peers_half() {
+        let pair = Pair::open();
+        let (id, len) = (pair.client_conn.as_ptr(), pair.client_conn.len());
+        let stream_id = pair.stream_id;
+        assert_eq!(
+            crate::cc_quic_stream_stop_sending(pair.client, id, len, stream_id, 9),
+            0
+        );
+        let stopped = pair.server_event("stream_stopped");
+        assert_eq!(stopped["error_code"], 9);
+        assert_eq!(stopped["channel"], "dl");
+        // The client's half is still open, and still delivers.
+        let data = b"still here";
+        let name = c"dl".as_ptr();
+        let sent = crate::cc_quic_channel_send(pair.client, id, len, name, data.as_ptr(), 10);
+        assert_eq!(sent, 0);
+        let message = pair.server_event("message");
+        assert_eq!(message["channel"], "dl");
+        assert_eq!(message["data_base64"], "c3RpbGwgaGVyZQ==");
+        assert_eq!(crate::cc_quic_channel_close(pair.client, id, len, name), 0);
+        pair.server_event("channel_closed");
+    }
+
+    #[test]
+    fn a_blocked_channel_posts_one_writable_once_credit_returns() {
+        for writable_events in [true, false] {
+            let pair = Pair::open_with(|server, client| {
+                assert_eq!(crate::cc_quic_config_set_flow_window(server, 1000, 1000), 0);
+                assert_eq!(
+                    crate::cc_quic_config_set_writable_events(client, writable_events),
+                    0
+                );
+            });
+            let (id, len) = (pair.client_conn.as_ptr(), pair.client_conn.len());
+            // Twice the window: the send is cut short and the stream blocks.
+            let data = [7u8; 2000];
+            let name = c"dl".as_ptr();
+            let sent = crate::cc_quic_channel_send(pair.client, id, len, name, data.as_ptr(), 2000);
+            assert_eq!(sent, 0);
+            pair.server_event("message");
+            let writable: Vec<_> = abi::drain(pair.client, Duration::from_millis(300))
+                .into_iter()
+                .filter(|event| event["type"] == "stream_writable")
+                .collect();
+            if !writable_events {
+                assert_eq!(writable, Vec::<serde_json::Value>::new());
+                continue;
+            }
+            assert_eq!(writable.len(), 1, "{writable:#?}");
+            assert_eq!(writable[0]["stream_id"], pair.stream_id);
+            assert_eq!(writable[0]["channel"], "dl");
+            assert!(writable[0]["capacity"].as_u64().unwrap() > 0);
+        }
+    }
+
+    #[test]
+    fn only_bidi_streams_past_the_reserved_ones_carry_channels() {
+        assert!(!is_channel_stream(0));
+        assert!(!is_channel_stream(5));
+        assert!(!is_channel_stream(7));
+        assert!(is_channel_stream(8));
+        assert!(is_channel_stream(13));
+        assert!(!is_channel_stream(14));
+    }
+}
@@ -0,0 +1,81 @@
+//! Connection-ID index for the server workers. A connection stays keyed by
+//! the SCID picked at accept time (the id Dart sees), but its packets may
+//! carry the client's original DCID until the client learns ours, or any
+//! spare SCID issued after the handshake for rotation and migration. The
+//! index follows quiche as it issues and retires those ids.
+
+use rand::{rngs::OsRng, RngCore};
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub(crate) struct CidIndex {
+    /// Every live connection id, mapped to its connection's key.
+    by_cid: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl CidIndex {
+    pub(crate) fn resolve(&self, dcid: &[u8]) -> Option<&Vec<u8>> {
+        self.by_cid.get(dcid)
+    }
+
+    pub(crate) fn insert(&mut self, cid: &[u8], key: &[u8]) {
+        self.by_cid.insert(cid.to_vec(), key.to_vec());
+    }
+
+    /// Forgets every id of the connection keyed `key`.
+    pub(crate) fn remove_conn(&mut self, key: &[u8]) {
+        self.by_cid.retain(|_, k| k != key);
+    }
+
+    /// Issues spare SCIDs up to the limit both ends allow and drops the ids
+    /// the peer retired. `stamp` goes in the first byte of each new id, as
+    /// for the primary SCID, so `SO_REUSEPORT` routing still finds the
+    /// owning worker.
+    pub(crate) fn refresh(&mut self, conn: &mut quiche::Connection, key: &[u8], stamp: Option<u8>) {
+        // The primary SCID can be retired too; the connection keeps its key.
+        while let Some(cid) = conn.retired_scid_next() {
+            self.by_cid.remove(cid.as_ref());
+        }
+        if !conn.is_established() {
+            return;
+        }
+        while conn.scids_left() > 0 {
+            let mut cid = [0u8; quiche::MAX_CONN_ID_LEN];
+            OsRng.fill_bytes(&mut cid);
+            if let Some(stamp) = stamp {
+                cid[0] = stamp;
+            }
+            let reset_token = u128::from_be_bytes({
+                let mut token = [0u8; 16];
+                OsRng.fill_bytes(&mut token);
+                token
+            });
+            let scid = quiche::ConnectionId::from_ref(&cid);
+            if let Err(err) = conn.new_scid(&scid, reset_token, false) {
+                log::debug!("spare scid not issued: {err:?}");
+                break;
+            }
+            self.insert(&cid, key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closing_a_connection_forgets_all_its_ids() {
+        let mut index = CidIndex::default();
+        for cid in [&b"primary-a"[..], b"client-odcid", b"spare-a"] {
+            index.insert(cid, b"primary-a");
+        }
+        index.insert(b"primary-b", b"primary-b");
+        assert_eq!(index.resolve(b"client-odcid"), Some(&b"primary-a".to_vec()));
+
+        index.remove_conn(b"primary-a");
+        assert!(index.resolve(b"spare-a").is_none());
+        assert!(index.resolve(b"client-odcid").is_none());
+        assert_eq!(index.resolve(b"primary-b"), Some(&b"primary-b".to_vec()));
+    }
+}
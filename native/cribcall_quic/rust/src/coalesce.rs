@@ -0,0 +1,112 @@
+//! Client connection coalescing (`cc_quic_config_set_coalesce`): a pinned
+//! `cc_quic_client_connect` to a fingerprint that already has an established
+//! connection joins it instead of dialling again. Joiners share the handle;
+//! each join takes a reference and `cc_quic_conn_close` only closes the
+//! connection once the last one is returned.
+
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+
+/// What a joiner needs to replay `connected` on its own port.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Shared {
+    pub handle: u64,
+    /// Port the connection's worker posts to.
+    pub dart_port: i64,
+    pub connection_id: String,
+    pub peer_fingerprint: String,
+}
+
+struct Entry {
+    shared: Shared,
+    refs: usize,
+}
+
+/// Established coalescable connections by pinned fingerprint.
+#[derive(Default)]
+pub(crate) struct Coalescer {
+    by_fp: HashMap<String, Entry>,
+}
+
+impl Coalescer {
+    /// Offers an established connection to later connects pinned to `fp`.
+    /// A fingerprint already served keeps its first connection.
+    pub(crate) fn publish(&mut self, fp: &str, shared: Shared) {
+        self.by_fp
+            .entry(fp.to_string())
+            .or_insert(Entry { shared, refs: 1 });
+    }
+
+    /// Takes a reference on the connection pinned to `fp`, if there is one.
+    pub(crate) fn join(&mut self, fp: &str) -> Option<Shared> {
+        let entry = self.by_fp.get_mut(fp)?;
+        entry.refs += 1;
+        Some(entry.shared.clone())
+    }
+
+    /// Returns one reference on `handle`; true while others still hold it,
+    /// in which case the connection must stay open. The last one withdraws
+    /// the connection, so nothing joins it while it closes.
+    pub(crate) fn release(&mut self, handle: u64) -> bool {
+        let Some((fp, entry)) = self
+            .by_fp
+            .iter_mut()
+            .find(|(_, e)| e.shared.handle == handle)
+        else {
+            return false;
+        };
+        entry.refs -= 1;
+        if entry.refs > 0 {
+            return true;
+        }
+        let fp = fp.clone();
+        self.by_fp.remove(&fp);
+        false
+    }
+
+    /// Forgets `handle` once its connection is closing.
+    pub(crate) fn withdraw(&mut self, handle: u64) {
+        self.by_fp.retain(|_, e| e.shared.handle != handle);
+    }
+}
+
+static COALESCER: Lazy<Mutex<Coalescer>> = Lazy::new(Mutex::default);
+
+pub(crate) fn registry() -> std::sync::MutexGuard<'static, Coalescer> {
+    COALESCER.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shared(handle: u64) -> Shared {
+        Shared {
+            handle,
+            dart_port: 1,
+            connection_id: format!("c{handle}"),
+            peer_fingerprint: "aa".into(),
+        }
+    }
+
+    #[test]
+    fn keeps_the_connection_until_every_holder_closes() {
+        let mut coalescer = Coalescer::default();
+        assert_eq!(coalescer.join("aa"), None);
+        coalescer.publish("aa", shared(1));
+        coalescer.publish("aa", shared(2));
+        assert_eq!(coalescer.join("aa"), Some(shared(1)));
+        assert_eq!(coalescer.join("aa").map(|s| s.handle), Some(1));
+
+        assert!(coalescer.release(1));
+        assert!(coalescer.release(1));
+        assert!(!coalescer.release(1));
+        assert_eq!(coalescer.join("aa"), None);
+        assert!(!coalescer.release(7));
+
+        coalescer.publish("aa", shared(2));
+        coalescer.withdraw(2);
+        assert_eq!(coalescer.join("aa"), None);
+    }
+}
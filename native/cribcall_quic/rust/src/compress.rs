@@ -0,0 +1,384 @@
+//! Transparent deflate compression of control-stream frames. Both ends opt
+//! in by offering an extra ALPN ahead of the plain one, so old peers never
+//! see compressed frames. A compressed frame sets the top bit of the 4-byte
+//! length header; its body is the original length followed by a zlib
+//! stream. The receiver restores the plain frame before Dart sees it.
+
+use std::borrow::Cow;
+
+/// Offered ahead of the plain control ALPN when deflate is enabled.
+pub(crate) const DEFLATE_ALPN: &[u8] = b"cribcall-ctrl+deflate";
+pub(crate) const COMPRESSED_FLAG: u32 = 1 << 31;
+const FRAME_HEADER_LEN: usize = 4;
+/// Largest frame inflated when no receive cap is configured.
+pub(crate) const MAX_INFLATED_FRAME: u64 = 16 * 1024 * 1024;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) enum CompressionMode {
+    #[default]
+    Off,
+    Deflate,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct CompressionConfig {
+    pub mode: CompressionMode,
+    /// Frames with smaller bodies go out as they are.
+    pub min_size: usize,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum InflateError {
+    /// The frame claims to inflate past the allowed size.
+    TooLarge(u64),
+    Corrupt,
+}
+
+/// Whether this build can deflate (it links the platform zlib).
+pub(crate) const fn deflate_available() -> bool {
+    cfg!(unix)
+}
+
+#[cfg(unix)]
+mod zlib {
+    use std::os::raw::{c_int, c_ulong};
+
+    const Z_OK: c_int = 0;
+    const Z_DEFAULT_COMPRESSION: c_int = -1;
+
+    #[link(name = "z")]
+    extern "C" {
+        fn compressBound(source_len: c_ulong) -> c_ulong;
+        fn compress2(
+            dest: *mut u8,
+            dest_len: *mut c_ulong,
+            source: *const u8,
+            source_len: c_ulong,
+            level: c_int,
+        ) -> c_int;
+        fn uncompress(
+            dest: *mut u8,
+            dest_len: *mut c_ulong,
+            source: *const u8,
+            source_len: c_ulong,
+        ) -> c_int;
+    }
+
+    pub(super) fn deflate(data: &[u8]) -> Option<Vec<u8>> {
+        let mut len = unsafe { compressBound(data.len() as c_ulong) };
+        let mut out = vec![0u8; len as usize];
+        let status = unsafe {
+            compress2(
+                out.as_mut_ptr(),
+                &mut len,
+                data.as_ptr(),
+                data.len() as c_ulong,
+                Z_DEFAULT_COMPRESSION,
+            )
+        };
+        (status == Z_OK).then(|| {
+            out.truncate(len as usize);
+            out
+        })
+    }
+
+    pub(super) fn inflate(data: &[u8], original_len: usize) -> Option<Vec<u8>> {
+        let mut out = vec![0u8; original_len];
+        let mut len = original_len as c_ulong;
+        let status = unsafe {
+            uncompress(
+                out.as_mut_ptr(),
+                &mut len,
+                data.as_ptr(),
+                data.len() as c_ulong,
+            )
+        };
+        (status == Z_OK && len as usize == original_len).then_some(out)
+    }
+}
+
+#[cfg(not(unix))]
+mod zlib {
+    pub(super) fn deflate(_data: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+
+    pub(super) fn inflate(_data: &[u8], _original_len: usize) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Where a byte stream is within its frames.
+#[derive(Default)]
+struct Framing {
+    header: [u8; FRAME_HEADER_LEN],
+    have: usize,
+    body_left: u64,
+}
+
+/// Per-connection codec for the control stream; does nothing until the
+/// handshake settled on [`DEFLATE_ALPN`].
+#[derive(Default)]
+pub(crate) struct ControlCodec {
+    negotiated: Option<bool>,
+    tx: Framing,
+    rx: Inflater,
+}
+
+impl ControlCodec {
+    /// Whether the handshake settled on [`DEFLATE_ALPN`]. The encoder then
+    /// tracks frame boundaries across sends, so everything it encodes must
+    /// reach the stream whole: callers queue it in the control backlog
+    /// rather than drop what the stream refuses.
+    pub(crate) fn compressing(&self, conn: &quiche::Connection) -> bool {
+        self.negotiated
+            .unwrap_or_else(|| conn.is_established() && conn.application_proto() == DEFLATE_ALPN)
+    }
+
+    fn active(&mut self, conn: &quiche::Connection) -> bool {
+        let active = self.compressing(conn);
+        if conn.is_established() {
+            self.negotiated = Some(active);
+        }
+        active
+    }
+
+    /// Compresses frames of at least `min_size` that lie wholly inside
+    /// `payload`; anything else, including frames split across sends, goes
+    /// out unchanged.
+    pub(crate) fn encode(
+        &mut self,
+        conn: &quiche::Connection,
+        payload: Vec<u8>,
+        min_size: usize,
+    ) -> Vec<u8> {
+        if !self.active(conn) {
+            return payload;
+        }
+        encode_frames(&mut self.tx, &payload, min_size)
+    }
+
+    /// Restores compressed frames in data read from `stream_id`; only the
+    /// control stream carries them. Frames may inflate to `max_len` bytes.
+    pub(crate) fn decode<'a>(
+        &mut self,
+        conn: &quiche::Connection,
+        stream_id: u64,
+        data: &'a [u8],
+        max_len: u64,
+    ) -> Result<Cow<'a, [u8]>, InflateError> {
+        if stream_id != crate::CONTROL_STREAM_ID || !self.active(conn) {
+            return Ok(Cow::Borrowed(data));
+        }
+        self.rx.feed(data, max_len).map(Cow::Owned)
+    }
+}
+
+fn encode_frames(tx: &mut Framing, payload: &[u8], min_size: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len());
+    let mut at = 0;
+    while at < payload.len() {
+        if tx.have == 0 && tx.body_left == 0 {
+            if let Some(frame) = compress_frame(&payload[at..], min_size) {
+                out.extend_from_slice(&frame.bytes);
+                at += frame.consumed;
+                continue;
+            }
+        }
+        at += advance(tx, &payload[at..], &mut out);
+    }
+    out
+}
+
+/// Receive side: passes plain frames through and holds a compressed frame
+/// until all of it is in.
+#[derive(Default)]
+struct Inflater {
+    framing: Framing,
+    /// Body of the compressed frame being received.
+    body: Option<Vec<u8>>,
+}
+
+impl Inflater {
+    fn feed(&mut self, data: &[u8], max_len: u64) -> Result<Vec<u8>, InflateError> {
+        let rx = &mut self.framing;
+        let mut out = Vec::with_capacity(data.len());
+        let mut at = 0;
+        while at < data.len() {
+            if let Some(body) = &mut self.body {
+                let take = rx.body_left.min((data.len() - at) as u64) as usize;
+                body.extend_from_slice(&data[at..at + take]);
+                rx.body_left -= take as u64;
+                at += take;
+                if rx.body_left == 0 {
+                    let body = self.body.take().unwrap_or_default();
+                    out.extend_from_slice(&inflate_frame(&body, max_len)?);
+                }
+                continue;
+            }
+            if rx.body_left > 0 {
+                at += advance(rx, &data[at..], &mut out);
+                continue;
+            }
+            // Header bytes are held back until the flag is known.
+            let take = (FRAME_HEADER_LEN - rx.have).min(data.len() - at);
+            rx.header[rx.have..rx.have + take].copy_from_slice(&data[at..at + take]);
+            rx.have += take;
+            at += take;
+            if rx.have < FRAME_HEADER_LEN {
+                break;
+            }
+            rx.have = 0;
+            let header = u32::from_be_bytes(rx.header);
+            rx.body_left = (header & !COMPRESSED_FLAG) as u64;
+            if header & COMPRESSED_FLAG == 0 {
+                out.extend_from_slice(&rx.header);
+            } else if rx.body_left == 0 {
+                return Err(InflateError::Corrupt);
+            } else if rx.body_left > max_len {
+                return Err(InflateError::TooLarge(rx.body_left));
+            } else {
+                self.body = Some(Vec::new());
+            }
+        }
+        Ok(out)
+    }
+}
+
+struct CompressedFrame {
+    bytes: Vec<u8>,
+    consumed: usize,
+}
+
+/// Compresses the frame at the start of `data` if it is complete, large
+/// enough and actually shrinks.
+fn compress_frame(data: &[u8], min_size: usize) -> Option<CompressedFrame> {
+    let header = u32::from_be_bytes(data.get(..FRAME_HEADER_LEN)?.try_into().ok()?);
+    let len = header as usize;
+    if header & COMPRESSED_FLAG != 0 || len < min_size {
+        return None;
+    }
+    let body = data.get(FRAME_HEADER_LEN..FRAME_HEADER_LEN + len)?;
+    let packed = zlib::deflate(body)?;
+    let wire_len = 4 + packed.len();
+    if wire_len >= len {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(FRAME_HEADER_LEN + wire_len);
+    bytes.extend_from_slice(&(wire_len as u32 | COMPRESSED_FLAG).to_be_bytes());
+    bytes.extend_from_slice(&header.to_be_bytes());
+    bytes.extend_from_slice(&packed);
+    Some(CompressedFrame {
+        bytes,
+        consumed: FRAME_HEADER_LEN + len,
+    })
+}
+
+fn inflate_frame(body: &[u8], max_len: u64) -> Result<Vec<u8>, InflateError> {
+    let Some((len, packed)) = body.split_first_chunk::<4>() else {
+        return Err(InflateError::Corrupt);
+    };
+    let len = u32::from_be_bytes(*len);
+    if len as u64 > max_len {
+        return Err(InflateError::TooLarge(len as u64));
+    }
+    let plain = zlib::inflate(packed, len as usize).ok_or(InflateError::Corrupt)?;
+    let mut out = Vec::with_capacity(FRAME_HEADER_LEN + plain.len());
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(&plain);
+    Ok(out)
+}
+
+/// Copies plain bytes through while tracking frame boundaries; returns how
+/// much of `data` was consumed.
+fn advance(state: &mut Framing, data: &[u8], out: &mut Vec<u8>) -> usize {
+    if state.body_left > 0 {
+        let take = state.body_left.min(data.len() as u64) as usize;
+        out.extend_from_slice(&data[..take]);
+        state.body_left -= take as u64;
+        return take;
+    }
+    let take = (FRAME_HEADER_LEN - state.have).min(data.len());
+    state.header[state.have..state.have + take].copy_from_slice(&data[..take]);
+    out.extend_from_slice(&data[..take]);
+    state.have += take;
+    if state.have == FRAME_HEADER_LEN {
+        state.have = 0;
+        state.body_left = (u32::from_be_bytes(state.header) & !COMPRESSED_FLAG) as u64;
+    }
+    take
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn frame(body: &[u8]) -> Vec<u8> {
+        let mut out = (body.len() as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(body);
+        out
+    }
+
+    #[test]
+    fn compressed_frame_round_trips_in_pieces() {
+        let status = br#"{"type":"status","level":0,"level":0,"level":0,"level":0}"#.repeat(8);
+        let mut payload = frame(b"tiny");
+        payload.extend_from_slice(&frame(&status));
+        let wire = encode_frames(&mut Framing::default(), &payload, 64);
+        assert!(wire.len() < payload.len() / 2);
+        assert_eq!(wire[8] & 0x80, 0x80);
+
+        let mut inflater = Inflater::default();
+        let mut restored = Vec::new();
+        for piece in wire.chunks(3) {
+            restored.extend(inflater.feed(piece, 1 << 20).unwrap());
+        }
+        assert_eq!(restored, payload);
+        // Small enough on the wire, too large once inflated.
+        let wire_body = (wire.len() - 12) as u64;
+        assert_eq!(
+            Inflater::default().feed(&wire[8..], wire_body),
+            Err(InflateError::TooLarge(status.len() as u64))
+        );
+    }
+
+    #[test]
+    fn compressed_sends_wait_for_stream_credit() {
+        use crate::api::{Config, Event};
+        use crate::loopback;
+
+        let configure = |mut config: Config| {
+            config.set_compression(1, 64).unwrap();
+            config.set_flow_window(16 * 1024, 16 * 1024).unwrap();
+            config
+        };
+        let server = loopback::serve(&mut configure(Config::server()));
+        let client = loopback::connect(&mut configure(Config::client()), &server);
+        let conn = client.connection(&loopback::connected(&client));
+        loopback::connected(&server);
+
+        // Random hex deflates to about half, still several windows' worth.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let body: String = (0..64 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                format!("{:02x}", state as u8)
+            })
+            .collect();
+        let mut sent = frame(body.as_bytes());
+        sent.extend_from_slice(&frame(&[b'x'; 100]));
+        conn.send(&sent).unwrap();
+
+        let mut received = Vec::new();
+        while received.len() < sent.len() {
+            received.extend(loopback::wait_for(&server, |event| match event {
+                Event::Message { data, .. } => Some(data.clone()),
+                _ => None,
+            }));
+        }
+        assert_eq!(received.len(), sent.len());
+        assert!(received == sent, "frames were cut or misframed");
+    }
+}
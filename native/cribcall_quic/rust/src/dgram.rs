@@ -0,0 +1,120 @@
+//! Received DATAGRAM frames, moved out of quiche every loop pass. quiche
+//! front-drops silently once its queue is full, so holding them here is what
+//! lets stats report queue depth and drops.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+
+pub(crate) const DEFAULT_DGRAM_QUEUE_LEN: usize = 1024;
+
+/// Which datagram goes when the receive queue is full.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DropPolicy {
+    /// Discard the oldest queued datagram (quiche's behaviour; suits audio).
+    #[default]
+    Front,
+    /// Discard the datagram that just arrived.
+    Back,
+}
+
+impl DropPolicy {
+    pub(crate) fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(Self::Front),
+            1 => Some(Self::Back),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Serialize)]
+pub(crate) struct DgramStats {
+    pub recv_queue_len: usize,
+    pub recv_queue_bytes: usize,
+    pub recv_queue_max: usize,
+    pub send_queue_len: usize,
+    pub dropped: u64,
+    pub drop_policy: DropPolicy,
+}
+
+#[derive(Default)]
+pub(crate) struct DgramInbox {
+    queue: VecDeque<Vec<u8>>,
+    bytes: usize,
+    dropped: u64,
+    /// Every datagram byte drained from quiche, dropped ones included.
+    received_bytes: u64,
+}
+
+impl DgramInbox {
+    /// Moves everything quiche has queued into the inbox, bounded by `max`.
+    pub(crate) fn pull(&mut self, conn: &mut quiche::Connection, max: usize, policy: DropPolicy) {
+        while let Ok(dgram) = conn.dgram_recv_vec() {
+            self.received_bytes += dgram.len() as u64;
+            self.push(dgram, max, policy);
+        }
+    }
+
+    pub(crate) fn received_bytes(&self) -> u64 {
+        self.received_bytes
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Vec<u8>> {
+        let dgram = self.queue.pop_front()?;
+        self.bytes -= dgram.len();
+        Some(dgram)
+    }
+
+    fn push(&mut self, dgram: Vec<u8>, max: usize, policy: DropPolicy) {
+        if self.queue.len() >= max {
+            self.dropped += 1;
+            match policy {
+                DropPolicy::Back => return,
+                DropPolicy::Front => {
+                    if let Some(old) = self.queue.pop_front() {
+                        self.bytes -= old.len();
+                    }
+                }
+            }
+        }
+        self.bytes += dgram.len();
+        self.queue.push_back(dgram);
+    }
+
+    pub(crate) fn stats(
+        &self,
+        conn: &quiche::Connection,
+        max: usize,
+        policy: DropPolicy,
+    ) -> DgramStats {
+        DgramStats {
+            recv_queue_len: self.queue.len(),
+            recv_queue_bytes: self.bytes,
+            recv_queue_max: max,
+            send_queue_len: conn.dgram_send_queue_len(),
+            dropped: self.dropped,
+            drop_policy: policy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_drops_under_either_policy() {
+        let mut front = DgramInbox::default();
+        let mut back = DgramInbox::default();
+        for i in 0..5u8 {
+            front.push(vec![i], 3, DropPolicy::Front);
+            back.push(vec![i], 3, DropPolicy::Back);
+        }
+        assert_eq!(front.dropped, 2);
+        assert_eq!(front.queue.front(), Some(&vec![2]));
+        assert_eq!(back.dropped, 2);
+        assert_eq!(back.queue.back(), Some(&vec![2]));
+        assert_eq!(front.bytes, 3);
+    }
+}
@@ -0,0 +1,239 @@
+//! `cc_quic_diagnostics_dump`: one JSON file per handle for bug reports,
+//! holding what otherwise has to be pieced together from the Dart event
+//! log, the native log and the app's config:
+//!
+//! - `workers`: each worker's options and a snapshot of its connections
+//!   (ids, addresses, handshake state and transport counters);
+//! - `usage`: bytes per peer, as `cc_quic_usage_stats` reports them;
+//! - `events`: the handle's most recent events as posted, leaving out
+//!   `message` and `media` payloads;
+//! - `logs`: the last native log lines, kept once `cc_quic_init_logging`
+//!   has installed the capturing logger.
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Recent events kept per handle.
+const EVENT_HISTORY: usize = 128;
+/// Recent log lines kept for the whole library.
+const LOG_HISTORY: usize = 512;
+/// How long a dump waits for the workers' snapshots.
+pub(crate) const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Last encoded events of one handle.
+#[derive(Default)]
+pub(crate) struct EventHistory {
+    events: Mutex<VecDeque<String>>,
+}
+
+impl EventHistory {
+    pub(crate) fn record(&self, json: &str) {
+        let mut events = self.events.lock().unwrap_or_else(PoisonError::into_inner);
+        if events.len() == EVENT_HISTORY {
+            events.pop_front();
+        }
+        events.push_back(json.to_string());
+    }
+
+    fn snapshot(&self) -> Vec<Value> {
+        let events = self.events.lock().unwrap_or_else(PoisonError::into_inner);
+        events
+            .iter()
+            .filter_map(|json| serde_json::from_str(json).ok())
+            .collect()
+    }
+}
+
+static LOG_LINES: Lazy<Mutex<VecDeque<String>>> = Lazy::new(Default::default);
+
+/// Forwards to the platform logger and keeps the lines it lets through.
+struct Capture<L> {
+    inner: L,
+}
+
+impl<L: log::Log> log::Log for Capture<L> {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "{} {} {}] {}",
+            unix_ms(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        let mut lines = LOG_LINES.lock().unwrap_or_else(PoisonError::into_inner);
+        if lines.len() == LOG_HISTORY {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+        drop(lines);
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Installs the platform logger, wrapped in [`Capture`]; later calls keep
+/// the first one.
+pub(crate) fn install_logger() {
+    #[cfg(target_os = "android")]
+    {
+        use android_logger::Config;
+        use log::LevelFilter;
+        let inner = android_logger::AndroidLogger::new(
+            Config::default()
+                .with_max_level(LevelFilter::Info)
+                .with_tag("cribcall_quic"),
+        );
+        if log::set_boxed_logger(Box::new(Capture { inner })).is_ok() {
+            log::set_max_level(LevelFilter::Info);
+        }
+    }
+
+    // Lines are also kept for `cc_quic_diagnostics_dump`.
+    #[cfg(not(target_os = "android"))]
+    {
+        let env = env_logger::Env::default().default_filter_or("info");
+        let inner = env_logger::Builder::from_env(env)
+            .format_timestamp_millis()
+            .build();
+        let max_level = inner.filter();
+        if log::set_boxed_logger(Box::new(Capture { inner })).is_ok() {
+            log::set_max_level(max_level);
+        }
+    }
+}
+
+/// One worker's part of a dump.
+#[derive(Debug, Serialize)]
+pub(crate) struct WorkerSnapshot {
+    /// The worker's options, as their debug rendering without the auth
+    /// token.
+    pub options: String,
+    pub connections: Vec<ConnectionSnapshot>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ConnectionSnapshot {
+    pub connection_id: String,
+    pub peer_addr: Option<String>,
+    pub local_addr: Option<String>,
+    pub established: bool,
+    pub closed: bool,
+    pub age_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_error: Option<String>,
+    pub rtt_ms: Option<f64>,
+    pub cwnd: Option<u64>,
+    pub pmtu: Option<u64>,
+    pub sent: u64,
+    pub recv: u64,
+    pub lost: u64,
+    pub retrans: u64,
+    pub sent_bytes: u64,
+    pub recv_bytes: u64,
+    pub lost_bytes: u64,
+}
+
+impl ConnectionSnapshot {
+    pub(crate) fn new(connection_id: String, conn: &quiche::Connection, age: Duration) -> Self {
+        let path = conn.path_stats().find(|path| path.active);
+        let stats = conn.stats();
+        Self {
+            connection_id,
+            peer_addr: path.as_ref().map(|path| path.peer_addr.to_string()),
+            local_addr: path.as_ref().map(|path| path.local_addr.to_string()),
+            established: conn.is_established(),
+            closed: conn.is_closed(),
+            age_ms: age.as_millis() as u64,
+            peer_error: conn.peer_error().map(|err| format!("{err:?}")),
+            local_error: conn.local_error().map(|err| format!("{err:?}")),
+            rtt_ms: path.as_ref().map(|path| path.rtt.as_secs_f64() * 1000.0),
+            cwnd: path.as_ref().map(|path| path.cwnd as u64),
+            pmtu: path.as_ref().map(|path| path.pmtu as u64),
+            sent: stats.sent as u64,
+            recv: stats.recv as u64,
+            lost: stats.lost as u64,
+            retrans: stats.retrans as u64,
+            sent_bytes: stats.sent_bytes,
+            recv_bytes: stats.recv_bytes,
+            lost_bytes: stats.lost_bytes,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct Bundle<U: Serialize> {
+    pub version: &'static str,
+    pub generated_unix_ms: u64,
+    pub handle: u64,
+    /// Events the Dart port refused and that are not yet reported.
+    pub events_dropped: u64,
+    pub workers: Vec<WorkerSnapshot>,
+    pub usage: U,
+    pub events: Vec<Value>,
+    pub logs: Vec<String>,
+}
+
+impl<U: Serialize> Bundle<U> {
+    pub(crate) fn new(
+        handle: u64,
+        events_dropped: u64,
+        workers: Vec<WorkerSnapshot>,
+        usage: U,
+        history: &EventHistory,
+    ) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            generated_unix_ms: unix_ms(),
+            handle,
+            events_dropped,
+            workers,
+            usage,
+            events: history.snapshot(),
+            logs: LOG_LINES
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .iter()
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_keeps_the_latest_events() {
+        let history = EventHistory::default();
+        for seq in 0..EVENT_HISTORY + 3 {
+            history.record(&format!(r#"{{"seq":{seq}}}"#));
+        }
+        let events = history.snapshot();
+        assert_eq!(events.len(), EVENT_HISTORY);
+        assert_eq!(events[0]["seq"], 3);
+        assert_eq!(events[EVENT_HISTORY - 1]["seq"], EVENT_HISTORY + 2);
+    }
+}
@@ -0,0 +1,175 @@
+//! Coordinated budgets for the two traffic classes on a connection: reliable
+//! control messages on stream 0 and unreliable media datagrams. Control
+//! writes queue in a bounded backlog that drains as flow control allows, so a
+//! full stream window never truncates a message; media is paced by a token
+//! bucket and yields to pending control so it cannot starve it of cwnd.
+//! With `datagrams_first` it goes the other way: media never yields, and
+//! waiting control bytes reach the stream only once the DATAGRAM queue is
+//! empty, so live audio is not held behind a large control flush. quiche
+//! still alternates the two by packet for data it already holds.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::throttle::TokenBucket;
+
+/// Burst the media pacer allows, as time at the configured rate.
+const MEDIA_BURST: Duration = Duration::from_millis(100);
+/// Smallest pacer burst, in datagrams of the largest size.
+const MIN_BURST_DGRAMS: usize = 4;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct DualChannelConfig {
+    /// Media budget in bits per second; 0 leaves media unpaced.
+    pub media_rate_bps: u64,
+    /// Control bytes allowed to wait for stream credit.
+    pub control_backlog_max: usize,
+    /// Queued datagrams go out before waiting control bytes.
+    pub datagrams_first: bool,
+}
+
+#[derive(Copy, Clone, Debug, Default, Serialize)]
+pub(crate) struct ControlStats {
+    pub backlog_bytes: usize,
+    pub backlog_max: usize,
+    /// Control messages refused because the backlog was full.
+    pub overflowed: u64,
+}
+
+pub(crate) struct DualChannel {
+    backlog: VecDeque<Vec<u8>>,
+    /// Bytes of the front message already written.
+    written: usize,
+    backlog_bytes: usize,
+    overflowed: u64,
+    pacer: Option<TokenBucket>,
+}
+
+impl DualChannel {
+    pub(crate) fn new() -> Self {
+        Self {
+            backlog: VecDeque::new(),
+            written: 0,
+            backlog_bytes: 0,
+            overflowed: 0,
+            pacer: None,
+        }
+    }
+
+    /// Queues a control message behind any already waiting and writes what
+    /// the stream accepts. Returns false if the backlog had no room.
+    pub(crate) fn send_control(
+        &mut self,
+        conn: &mut quiche::Connection,
+        stream_id: u64,
+        payload: Vec<u8>,
+        config: &DualChannelConfig,
+    ) -> bool {
+        if self.backlog_bytes + payload.len() > config.control_backlog_max {
+            self.overflowed += 1;
+            return false;
+        }
+        self.backlog_bytes += payload.len();
+        self.backlog.push_back(payload);
+        self.flush_control(conn, stream_id, config);
+        true
+    }
+
+    /// Writes waiting control bytes as stream credit allows; with
+    /// `datagrams_first`, only once no datagram is queued.
+    pub(crate) fn flush_control(
+        &mut self,
+        conn: &mut quiche::Connection,
+        stream_id: u64,
+        config: &DualChannelConfig,
+    ) {
+        if config.datagrams_first && conn.dgram_send_queue_len() > 0 {
+            return;
+        }
+        while let Some(front) = self.backlog.front() {
+            let written = match conn.stream_send(stream_id, &front[self.written..], false) {
+                Ok(written) => written,
+                Err(quiche::Error::Done) => 0,
+                Err(err) => {
+                    log::warn!("control send error: {err:?}");
+                    return;
+                }
+            };
+            self.written += written;
+            self.backlog_bytes -= written;
+            if self.written < front.len() {
+                return;
+            }
+            self.backlog.pop_front();
+            self.written = 0;
+        }
+    }
+
+    /// Whether control bytes are waiting for stream credit.
+    pub(crate) fn backlogged(&self) -> bool {
+        !self.backlog.is_empty()
+    }
+
+    /// Whether a media datagram of `len` bytes fits the budget at `now`.
+    /// While control is waiting, media only goes out onto an empty DATAGRAM
+    /// queue, unless datagrams go first.
+    pub(crate) fn admit_media(
+        &mut self,
+        conn: &quiche::Connection,
+        len: usize,
+        config: &DualChannelConfig,
+        now: Instant,
+    ) -> bool {
+        if !config.datagrams_first && !self.backlog.is_empty() && conn.dgram_send_queue_len() > 0 {
+            return false;
+        }
+        self.take_tokens(len, config, now)
+    }
+
+    fn take_tokens(&mut self, len: usize, config: &DualChannelConfig, now: Instant) -> bool {
+        if config.media_rate_bps == 0 {
+            return true;
+        }
+        self.pacer
+            .get_or_insert_with(|| {
+                TokenBucket::new(
+                    config.media_rate_bps,
+                    MEDIA_BURST,
+                    MIN_BURST_DGRAMS * crate::MAX_DATAGRAM_SIZE,
+                )
+            })
+            .take(len, now)
+    }
+
+    pub(crate) fn stats(&self, config: &DualChannelConfig) -> ControlStats {
+        ControlStats {
+            backlog_bytes: self.backlog_bytes,
+            backlog_max: config.control_backlog_max,
+            overflowed: self.overflowed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pacer_refills_at_the_media_rate() {
+        let config = DualChannelConfig {
+            media_rate_bps: 800_000,
+            control_backlog_max: 1024,
+            datagrams_first: false,
+        };
+        // 100 kB/s: a 10 kB burst, 1 kB back every 10 ms.
+        let mut dual = DualChannel::new();
+        let (mut tokens, start) = (0, Instant::now());
+        while dual.take_tokens(1000, &config, start) {
+            tokens += 1;
+        }
+        assert_eq!(tokens, 10);
+        assert!(!dual.take_tokens(1000, &config, start + Duration::from_millis(5)));
+        assert!(dual.take_tokens(1000, &config, start + Duration::from_millis(15)));
+    }
+}
@@ -0,0 +1,150 @@
+//! The crate's error type. The core and the Rust APIs (`api.rs`,
+//! `asyncapi.rs`) return it with what went wrong; only the `extern "C"`
+//! layer turns it into a `CcQuicStatus`, keeping its message for
+//! `cc_quic_last_error`.
+
+use std::io;
+
+use crate::CcQuicStatus;
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("null pointer")]
+    NullPointer,
+    /// A setting was rejected; the message names it.
+    #[error("{0}")]
+    Config(String),
+    #[error("{0}")]
+    InvalidAlpn(String),
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    /// A certificate or key file that could not be read or parsed.
+    #[error("{path}: {detail}")]
+    CertParse { path: String, detail: String },
+    #[error("certificate {path} has expired")]
+    CertExpired { path: String },
+    #[error("{0}")]
+    KeyOpen(String),
+    /// The handshake ended on a TLS alert, such as a certificate one side
+    /// could not verify.
+    #[error("TLS: {0}")]
+    Tls(String),
+    #[error("the server certificate does not match the pin {expected}")]
+    FingerprintMismatch { expected: String },
+    /// The connection closed before it was announced. `code` is the QUIC
+    /// error code, when either side sent one.
+    #[error("{reason}")]
+    Closed { code: Option<u64>, reason: String },
+    #[error("{0}")]
+    WrongRole(String),
+    #[error("no such connection")]
+    UnknownConnection,
+    #[error("the handle is closed or from an earlier load")]
+    StaleHandle,
+    /// A start asked for events on a Dart port before
+    /// `cc_quic_init_dart_api`, so none could arrive.
+    #[error("events cannot reach Dart before cc_quic_init_dart_api")]
+    EventSend,
+    #[error("{0}")]
+    PermissionDenied(String),
+    #[error("{0}")]
+    Unsupported(String),
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl Error {
+    pub(crate) fn io(context: impl Into<String>, source: io::Error) -> Self {
+        Self::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// The status the C ABI returns for this error.
+    pub fn status(&self) -> CcQuicStatus {
+        match self {
+            Self::NullPointer => CcQuicStatus::NullPointer,
+            Self::Config(_) => CcQuicStatus::ConfigError,
+            Self::InvalidAlpn(_) => CcQuicStatus::InvalidAlpn,
+            Self::Io { .. } => CcQuicStatus::SocketError,
+            Self::CertParse { .. } => CcQuicStatus::CertLoadError,
+            Self::CertExpired { .. } => CcQuicStatus::CertExpired,
+            Self::KeyOpen(_) => CcQuicStatus::KeyOpenFailed,
+            Self::Tls(_) | Self::FingerprintMismatch { .. } | Self::Closed { .. } => {
+                CcQuicStatus::HandshakeError
+            }
+            Self::WrongRole(_) => CcQuicStatus::WrongRole,
+            Self::UnknownConnection => CcQuicStatus::UnknownConnection,
+            Self::StaleHandle => CcQuicStatus::StaleHandle,
+            Self::EventSend => CcQuicStatus::EventSendError,
+            Self::PermissionDenied(_) => CcQuicStatus::PermissionDenied,
+            Self::Unsupported(_) => CcQuicStatus::Unsupported,
+            Self::Internal(_) => CcQuicStatus::Internal,
+        }
+    }
+
+    /// For a status returned by one of the C ABI functions, which carry
+    /// no detail; `message` says what was being done.
+    pub(crate) fn from_status(status: CcQuicStatus, message: impl Into<String>) -> Self {
+        let message = message.into();
+        match status {
+            CcQuicStatus::NullPointer => Self::NullPointer,
+            CcQuicStatus::ConfigError => Self::Config(message),
+            CcQuicStatus::InvalidAlpn => Self::InvalidAlpn(message),
+            CcQuicStatus::CertLoadError => {
+                let (path, detail) = message
+                    .split_once(": ")
+                    .unwrap_or((&message, "could not be loaded"));
+                Self::CertParse {
+                    path: path.into(),
+                    detail: detail.into(),
+                }
+            }
+            CcQuicStatus::SocketError => Self::io(message, io::Error::other("socket error")),
+            CcQuicStatus::HandshakeError => Self::Closed {
+                code: None,
+                reason: message,
+            },
+            CcQuicStatus::EventSendError => Self::EventSend,
+            CcQuicStatus::PermissionDenied => Self::PermissionDenied(message),
+            CcQuicStatus::Unsupported => Self::Unsupported(message),
+            CcQuicStatus::WrongRole => Self::WrongRole(message),
+            CcQuicStatus::UnknownConnection => Self::UnknownConnection,
+            CcQuicStatus::CertExpired => Self::CertExpired { path: message },
+            CcQuicStatus::KeyOpenFailed => Self::KeyOpen(message),
+            CcQuicStatus::StaleHandle => Self::StaleHandle,
+            // Only failures are converted; `Ok` cannot arrive here.
+            CcQuicStatus::Internal | CcQuicStatus::Ok => Self::Internal(message),
+        }
+    }
+}
+
+impl From<crate::jsonconfig::JsonConfigError> for Error {
+    fn from(err: crate::jsonconfig::JsonConfigError) -> Self {
+        Self::from_status(CcQuicStatus::from_code(err.code), err.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses_survive_the_round_trip() {
+        for code in (1..=14).chain([255]) {
+            let status = CcQuicStatus::from_code(code);
+            assert_eq!(status.code(), code);
+            assert_eq!(Error::from_status(status, "send").status(), status);
+        }
+        let err = Error::from_status(CcQuicStatus::CertLoadError, "/a.pem: unreadable");
+        assert_eq!(err.to_string(), "/a.pem: unreadable");
+        let err = Error::io("bind 0.0.0.0:1", io::ErrorKind::AddrInUse.into());
+        assert_eq!(err.to_string(), "bind 0.0.0.0:1: address in use");
+    }
+}
@@ -0,0 +1,229 @@
+//! Per-handle event throttling (`cc_quic_config_set_event_rate_limit` and
+//! `cc_quic_config_set_error_coalescing`). A flapping connection or a
+//! chatty peer can otherwise post thousands of events a second and leave
+//! the UI isolate seconds behind. Rate-limited classes take a budget of
+//! events per second (the same again as burst); an `error` repeating one
+//! already posted within the coalescing window is held back. What was held
+//! back is counted and reported by an `events_suppressed` summary, at most
+//! once a second, ahead of the next event that goes out.
+
+use crate::throttle::TokenBucket;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// `cc_quic_subscribe` classes, by bit index.
+pub(crate) const CLASS_NAMES: [&str; 6] = [
+    "connection",
+    "message",
+    "media",
+    "channel",
+    "stats",
+    "health",
+];
+/// Shortest gap between two `events_suppressed` summaries.
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(1);
+/// Distinct errors tracked for coalescing; the oldest is forgotten first.
+const MAX_TRACKED_ERRORS: usize = 16;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct EventLimits {
+    /// Events per second for each class, by bit index; 0 for no limit.
+    pub per_sec: [u32; CLASS_NAMES.len()],
+    /// Hold back an `error` identical to one posted this recently.
+    pub error_window: Option<Duration>,
+}
+
+impl EventLimits {
+    pub(crate) fn is_off(&self) -> bool {
+        self.per_sec.iter().all(|limit| *limit == 0) && self.error_window.is_none()
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct Suppressed {
+    /// Events each rate-limited class held back, by class name.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub limited: BTreeMap<&'static str, u64>,
+    /// Repeats of errors already posted.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub repeated_errors: Vec<RepeatedError>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub(crate) struct RepeatedError {
+    pub connection_id: Option<String>,
+    pub message: String,
+    pub count: u64,
+}
+
+struct RecentError {
+    connection_id: Option<String>,
+    message: String,
+    posted: Instant,
+    repeats: u64,
+}
+
+pub(crate) struct EventGate {
+    error_window: Option<Duration>,
+    buckets: [Option<TokenBucket>; CLASS_NAMES.len()],
+    limited: [u64; CLASS_NAMES.len()],
+    errors: Vec<RecentError>,
+    last_summary: Option<Instant>,
+}
+
+impl EventGate {
+    /// `None` when `limits` throttle nothing.
+    pub(crate) fn new(limits: EventLimits) -> Option<Self> {
+        if limits.is_off() {
+            return None;
+        }
+        Some(Self {
+            error_window: limits.error_window,
+            buckets: limits
+                .per_sec
+                .map(|limit| (limit > 0).then(|| TokenBucket::events(limit))),
+            limited: [0; CLASS_NAMES.len()],
+            errors: Vec::new(),
+            last_summary: None,
+        })
+    }
+
+    /// Whether an event of the class with bit index `class` goes out now;
+    /// `error` holds an `error` event's connection id and message.
+    pub(crate) fn admit(
+        &mut self,
+        class: usize,
+        error: Option<(Option<&str>, &str)>,
+        now: Instant,
+    ) -> bool {
+        if let (Some(window), Some((connection_id, message))) = (self.error_window, error) {
+            self.errors.retain(|seen| {
+                now.saturating_duration_since(seen.posted) < window || seen.repeats > 0
+            });
+            let seen = self.errors.iter_mut().find(|seen| {
+                seen.connection_id.as_deref() == connection_id && seen.message == message
+            });
+            match seen {
+                Some(seen) if now.saturating_duration_since(seen.posted) < window => {
+                    seen.repeats += 1;
+                    return false;
+                }
+                Some(seen) => seen.posted = now,
+                None => {
+                    if self.errors.len() == MAX_TRACKED_ERRORS {
+                        self.errors.remove(0);
+                    }
+                    self.errors.push(RecentError {
+                        connection_id: connection_id.map(str::to_string),
+                        message: message.to_string(),
+                        posted: now,
+                        repeats: 0,
+                    });
+                }
+            }
+        }
+        let Some(bucket) = self.buckets.get_mut(class).and_then(Option::as_mut) else {
+            return true;
+        };
+        if bucket.take(1, now) {
+            return true;
+        }
+        self.limited[class] += 1;
+        false
+    }
+
+    /// What was held back since the last summary, once one is due.
+    pub(crate) fn summary(&mut self, now: Instant) -> Option<Suppressed> {
+        if self
+            .last_summary
+            .is_some_and(|at| now.saturating_duration_since(at) < SUMMARY_INTERVAL)
+        {
+            return None;
+        }
+        let suppressed = Suppressed {
+            limited: CLASS_NAMES
+                .into_iter()
+                .zip(std::mem::take(&mut self.limited))
+                .filter(|(_, count)| *count > 0)
+                .collect(),
+            repeated_errors: self
+                .errors
+                .iter_mut()
+                .filter(|seen| seen.repeats > 0)
+                .map(|seen| RepeatedError {
+                    connection_id: seen.connection_id.clone(),
+                    message: seen.message.clone(),
+                    count: std::mem::take(&mut seen.repeats),
+                })
+                .collect(),
+        };
+        if suppressed == Suppressed::default() {
+            return None;
+        }
+        self.last_summary = Some(now);
+        Some(suppressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATS: usize = 4;
+    const HEALTH: usize = 5;
+
+    #[test]
+    fn limited_classes_spend_a_per_second_budget() {
+        let mut limits = EventLimits::default();
+        limits.per_sec[STATS] = 2;
+        let mut gate = EventGate::new(limits).unwrap();
+        let start = Instant::now();
+        let admitted = (0..5).filter(|_| gate.admit(STATS, None, start)).count();
+        assert_eq!(admitted, 2);
+        // Other classes are not limited.
+        assert!(gate.admit(HEALTH, Some((None, "boom")), start));
+        let summary = gate.summary(start).unwrap();
+        assert_eq!(summary.limited, BTreeMap::from([("stats", 3)]));
+        assert!(summary.repeated_errors.is_empty());
+        // Half a second refills one event.
+        let later = start + Duration::from_millis(500);
+        assert!(gate.admit(STATS, None, later));
+        assert!(!gate.admit(STATS, None, later));
+        // Summaries wait out their interval.
+        assert_eq!(gate.summary(later), None);
+        let summary = gate.summary(start + SUMMARY_INTERVAL).unwrap();
+        assert_eq!(summary.limited, BTreeMap::from([("stats", 1)]));
+        assert!(EventGate::new(EventLimits::default()).is_none());
+    }
+
+    #[test]
+    fn repeated_errors_coalesce_within_the_window() {
+        let limits = EventLimits {
+            error_window: Some(Duration::from_secs(1)),
+            ..EventLimits::default()
+        };
+        let mut gate = EventGate::new(limits).unwrap();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(gate.admit(HEALTH, Some((Some("ab"), "reset")), at(0)));
+        assert!(!gate.admit(HEALTH, Some((Some("ab"), "reset")), at(10)));
+        assert!(!gate.admit(HEALTH, Some((Some("ab"), "reset")), at(20)));
+        // Another connection or message is a different error.
+        assert!(gate.admit(HEALTH, Some((Some("cd"), "reset")), at(30)));
+        assert!(gate.admit(HEALTH, Some((Some("ab"), "timeout")), at(40)));
+        // Past the window it goes out again.
+        assert!(gate.admit(HEALTH, Some((Some("ab"), "reset")), at(1000)));
+        let summary = gate.summary(at(1000)).unwrap();
+        assert_eq!(
+            summary.repeated_errors,
+            [RepeatedError {
+                connection_id: Some("ab".to_string()),
+                message: "reset".to_string(),
+                count: 2,
+            }]
+        );
+        assert!(summary.limited.is_empty());
+        assert_eq!(gate.summary(at(3000)), None);
+    }
+}
@@ -0,0 +1,229 @@
+//! TLS exporters (RFC 8446 §7.5) for `cc_quic_conn_export_keying_material`,
+//! so an app can derive keys bound to one session, such as for the clips it
+//! caches encrypted, without a key exchange of its own. quiche 0.24 has no
+//! exporter call. With `cc_quic_config_set_keying_material_export` the
+//! config turns on quiche's key log, and each connection's sink keeps
+//! BoringSSL's `EXPORTER_SECRET` line and wipes the rest; exports are
+//! derived from that secret with the HKDF quiche links. Both ends derive
+//! the same bytes for the same label and context.
+
+use std::io;
+use std::os::raw::{c_int, c_uint, c_void};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::keyseal::wipe;
+
+const SECRET_LINE: &[u8] = b"EXPORTER_SECRET ";
+/// The longest export: 255 HKDF blocks of the smaller digest.
+pub(crate) const MAX_LEN: usize = 255 * 32;
+/// HkdfLabel holds `tls13 ` and the label behind a one-byte length.
+pub(crate) const MAX_LABEL_LEN: usize = 255 - 6;
+
+#[repr(C)]
+struct EvpMd {
+    _private: [u8; 0],
+}
+
+extern "C" {
+    fn EVP_sha256() -> *const EvpMd;
+    fn EVP_sha384() -> *const EvpMd;
+    fn EVP_Digest(
+        data: *const c_void,
+        len: usize,
+        md_out: *mut u8,
+        md_out_size: *mut c_uint,
+        md: *const EvpMd,
+        engine: *mut c_void,
+    ) -> c_int;
+    fn HKDF_expand(
+        out_key: *mut u8,
+        out_len: usize,
+        digest: *const EvpMd,
+        prk: *const u8,
+        prk_len: usize,
+        info: *const u8,
+        info_len: usize,
+    ) -> c_int;
+}
+
+/// A connection's exporter secret, empty until its handshake derives one
+/// (or for good, with export off); wiped on drop.
+#[derive(Default)]
+pub(crate) struct Secret(Mutex<Vec<u8>>);
+
+impl Secret {
+    /// `len` bytes of keying material for `label` and `context`; `None`
+    /// while there is no secret.
+    pub(crate) fn export(&self, label: &[u8], context: &[u8], len: usize) -> Option<Vec<u8>> {
+        let secret = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let md = digest(secret.len())?;
+        let empty = hash(md, b"", secret.len())?;
+        let mut derived = expand_label(md, &secret, label, &empty, secret.len())?;
+        let context = hash(md, context, secret.len())?;
+        let out = expand_label(md, &derived, b"exporter", &context, len);
+        wipe(&mut derived);
+        out
+    }
+
+    fn set(&self, mut secret: Vec<u8>) {
+        let mut held = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        wipe(&mut held);
+        std::mem::swap(&mut *held, &mut secret);
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        wipe(self.0.get_mut().unwrap_or_else(PoisonError::into_inner));
+    }
+}
+
+/// The suite's digest, told apart by its secret length: SHA-384 for
+/// AES-256-GCM, SHA-256 for the others.
+fn digest(secret_len: usize) -> Option<*const EvpMd> {
+    match secret_len {
+        32 => Some(unsafe { EVP_sha256() }),
+        48 => Some(unsafe { EVP_sha384() }),
+        _ => None,
+    }
+}
+
+fn hash(md: *const EvpMd, data: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut out = vec![0u8; len];
+    let mut written: c_uint = 0;
+    let ok = unsafe {
+        EVP_Digest(
+            data.as_ptr().cast(),
+            data.len(),
+            out.as_mut_ptr(),
+            &mut written,
+            md,
+            std::ptr::null_mut(),
+        )
+    };
+    (ok == 1 && written as usize == len).then_some(out)
+}
+
+/// HKDF-Expand-Label (RFC 8446 §7.1).
+fn expand_label(
+    md: *const EvpMd,
+    secret: &[u8],
+    label: &[u8],
+    context: &[u8],
+    len: usize,
+) -> Option<Vec<u8>> {
+    if label.len() > MAX_LABEL_LEN || context.len() > 255 || len > usize::from(u16::MAX) {
+        return None;
+    }
+    let mut info = Vec::with_capacity(10 + label.len() + context.len());
+    info.extend_from_slice(&(len as u16).to_be_bytes());
+    info.push((6 + label.len()) as u8);
+    info.extend_from_slice(b"tls13 ");
+    info.extend_from_slice(label);
+    info.push(context.len() as u8);
+    info.extend_from_slice(context);
+    let mut out = vec![0u8; len];
+    let ok = unsafe {
+        HKDF_expand(
+            out.as_mut_ptr(),
+            len,
+            md,
+            secret.as_ptr(),
+            secret.len(),
+            info.as_ptr(),
+            info.len(),
+        )
+    };
+    (ok == 1).then_some(out)
+}
+
+/// quiche's key log sink for one connection.
+struct KeyLog {
+    secret: Arc<Secret>,
+    pending: Vec<u8>,
+}
+
+impl io::Write for KeyLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        while let Some(end) = self.pending.iter().position(|&byte| byte == b'\n') {
+            let mut line: Vec<u8> = self.pending.drain(..=end).collect();
+            if let Some(secret) = parse(&line) {
+                self.secret.set(secret);
+            }
+            wipe(&mut line);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for KeyLog {
+    fn drop(&mut self) {
+        wipe(&mut self.pending);
+    }
+}
+
+/// The secret of an `EXPORTER_SECRET <client random> <secret>` line.
+fn parse(line: &[u8]) -> Option<Vec<u8>> {
+    let rest = line.strip_prefix(SECRET_LINE)?;
+    let secret = rest.split(|&byte| byte == b' ').nth(1)?;
+    hex::decode(secret.trim_ascii_end()).ok()
+}
+
+/// Gives `conn` a key log feeding the returned secret. quiche only calls
+/// it for configs with `log_keys`, so without export it stays empty.
+pub(crate) fn attach(conn: &mut quiche::Connection) -> Arc<Secret> {
+    let secret = Arc::new(Secret::default());
+    conn.set_keylog(Box::new(KeyLog {
+        secret: secret.clone(),
+        pending: Vec::new(),
+    }));
+    secret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn exports_follow_rfc_8446_from_the_logged_secret() {
+        let secret = Arc::new(Secret::default());
+        let mut log = KeyLog {
+            secret: secret.clone(),
+            pending: Vec::new(),
+        };
+        assert_eq!(secret.export(b"EXPORTER-cribcall-clip", b"", 16), None);
+
+        let line = format!(
+            "EXPORTER_SECRET {} {}\n",
+            "ab".repeat(32),
+            hex::encode([7; 32])
+        );
+        log.write_all(b"CLIENT_TRAFFIC_SECRET_0 aa bb\n").unwrap();
+        let (start, end) = line.as_bytes().split_at(40);
+        log.write_all(start).unwrap();
+        assert_eq!(secret.export(b"EXPORTER-cribcall-clip", b"", 16), None);
+        log.write_all(end).unwrap();
+        assert!(log.pending.is_empty());
+        assert!(secret.export(b"EXPORTER-cribcall-clip", b"", 16).is_some());
+
+        // Checked against an independent HKDF-Expand-Label implementation.
+        secret.set((0..32).collect());
+        let clip = secret.export(b"EXPORTER-cribcall-clip", b"clip-42", 32);
+        assert_eq!(
+            clip.map(hex::encode).as_deref(),
+            Some("bbccb45d3322d671dd1cfc711d89a1b3580cbf1221ae5081d74e8061290b22c5")
+        );
+        secret.set((0..48).collect());
+        let sha384 = secret.export(b"EXPORTER-cribcall-clip", b"", 16);
+        assert_eq!(
+            sha384.map(hex::encode).as_deref(),
+            Some("e5da29c40e7fb40a4e1799b21346c553")
+        );
+    }
+}
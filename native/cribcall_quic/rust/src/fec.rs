@@ -0,0 +1,336 @@
+//! Forward error correction over groups of media datagrams. Every `k` source
+//! datagrams the sender adds `n - k` parity datagrams: a plain XOR for a
+//! single parity, otherwise a systematic Reed-Solomon code over GF(256) with
+//! Cauchy coefficients, so any `k` of the `n` datagrams rebuild the group.
+
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+
+/// First byte of a parity datagram: RTP version bits of 1, so media parsing
+/// never mistakes one for a source frame.
+const FEC_TAG: u8 = 0x40;
+const FEC_HEADER_LEN: usize = 6;
+/// Largest group the codec accepts (`n`); keeps decode matrices small.
+pub(crate) const MAX_FEC_GROUP: u8 = 64;
+/// Source datagrams remembered for recovery.
+const SOURCE_HISTORY: usize = 512;
+/// Groups with outstanding parity remembered for recovery.
+const GROUP_HISTORY: usize = 64;
+
+struct Gf {
+    exp: [u8; 512],
+    log: [u8; 256],
+}
+
+static GF: Lazy<Gf> = Lazy::new(|| {
+    let mut gf = Gf {
+        exp: [0; 512],
+        log: [0; 256],
+    };
+    let mut x: u16 = 1;
+    for i in 0..255 {
+        gf.exp[i] = x as u8;
+        gf.log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= 0x11d;
+        }
+    }
+    for i in 255..512 {
+        gf.exp[i] = gf.exp[i - 255];
+    }
+    gf
+});
+
+fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    let gf = &*GF;
+    gf.exp[gf.log[a as usize] as usize + gf.log[b as usize] as usize]
+}
+
+fn inv(a: u8) -> u8 {
+    let gf = &*GF;
+    gf.exp[255 - gf.log[a as usize] as usize]
+}
+
+/// Coefficient of source `i` in parity row `j`.
+fn coef(k: u8, n: u8, j: u8, i: u8) -> u8 {
+    if n - k == 1 {
+        1
+    } else {
+        // Cauchy: 1 / (x_j + y_i) with x_j = k + j and y_i = i, disjoint sets.
+        inv((k + j) ^ i)
+    }
+}
+
+/// `dst += c * src`, byte-wise over GF(256).
+fn mul_add(dst: &mut [u8], c: u8, src: &[u8]) {
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d ^= mul(c, *s);
+    }
+}
+
+/// A source datagram prefixed with its length so padded units round-trip.
+fn unit(dgram: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(dgram.len() + 2);
+    out.extend_from_slice(&(dgram.len() as u16).to_be_bytes());
+    out.extend_from_slice(dgram);
+    out
+}
+
+fn from_unit(unit: &[u8]) -> Option<Vec<u8>> {
+    let len = u16::from_be_bytes([*unit.first()?, *unit.get(1)?]) as usize;
+    unit.get(2..2 + len).map(<[u8]>::to_vec)
+}
+
+pub(crate) fn is_parity(dgram: &[u8]) -> bool {
+    dgram.len() > FEC_HEADER_LEN && dgram[0] == FEC_TAG
+}
+
+/// Sender side: collects each group's source datagrams and emits parity.
+pub(crate) struct FecEncoder {
+    k: u8,
+    n: u8,
+    base_seq: u16,
+    group: Vec<Vec<u8>>,
+}
+
+impl FecEncoder {
+    pub(crate) fn new(k: u8, n: u8) -> Self {
+        Self {
+            k,
+            n,
+            base_seq: 0,
+            group: Vec::with_capacity(k as usize),
+        }
+    }
+
+    /// Records source datagram `seq`; returns the parity datagrams once its
+    /// group is complete.
+    pub(crate) fn protect(&mut self, seq: u16, dgram: &[u8]) -> Vec<Vec<u8>> {
+        if self.group.is_empty() {
+            self.base_seq = seq;
+        }
+        self.group.push(unit(dgram));
+        if self.group.len() < self.k as usize {
+            return Vec::new();
+        }
+        let len = self.group.iter().map(Vec::len).max().unwrap_or(0);
+        let parity = (0..self.n - self.k)
+            .map(|j| {
+                let mut out = Vec::with_capacity(FEC_HEADER_LEN + len);
+                out.extend_from_slice(&[FEC_TAG, j, self.k, self.n]);
+                out.extend_from_slice(&self.base_seq.to_be_bytes());
+                out.resize(FEC_HEADER_LEN + len, 0);
+                for (i, src) in self.group.iter().enumerate() {
+                    mul_add(
+                        &mut out[FEC_HEADER_LEN..],
+                        coef(self.k, self.n, j, i as u8),
+                        src,
+                    );
+                }
+                out
+            })
+            .collect();
+        self.group.clear();
+        parity
+    }
+}
+
+struct Group {
+    k: u8,
+    n: u8,
+    parity: Vec<(u8, Vec<u8>)>,
+}
+
+/// Receiver side: remembers recent source datagrams and parity, rebuilding
+/// missing sources once any `k` of a group's `n` datagrams are in.
+#[derive(Default)]
+pub(crate) struct FecDecoder {
+    sources: HashMap<u16, Vec<u8>>,
+    source_order: VecDeque<u16>,
+    groups: HashMap<u16, Group>,
+    group_order: VecDeque<u16>,
+}
+
+impl FecDecoder {
+    pub(crate) fn on_source(&mut self, seq: u16, dgram: &[u8]) -> Vec<Vec<u8>> {
+        if self.sources.insert(seq, dgram.to_vec()).is_none() {
+            self.source_order.push_back(seq);
+            if self.source_order.len() > SOURCE_HISTORY {
+                if let Some(old) = self.source_order.pop_front() {
+                    self.sources.remove(&old);
+                }
+            }
+        }
+        // A late source may complete a group whose parity is already in.
+        let base = self
+            .groups
+            .iter()
+            .find(|(base, group)| seq.wrapping_sub(**base) < group.k as u16)
+            .map(|(base, _)| *base);
+        match base {
+            Some(base) => self.try_recover(base),
+            None => Vec::new(),
+        }
+    }
+
+    /// Accepts a parity datagram; returns any sources it lets us rebuild.
+    pub(crate) fn on_parity(&mut self, dgram: &[u8]) -> Vec<Vec<u8>> {
+        let (j, k, n) = (dgram[1], dgram[2], dgram[3]);
+        if k == 0 || n <= k || n > MAX_FEC_GROUP || j >= n - k {
+            return Vec::new();
+        }
+        let base = u16::from_be_bytes([dgram[4], dgram[5]]);
+        let group = self.groups.entry(base).or_insert_with(|| {
+            self.group_order.push_back(base);
+            Group {
+                k,
+                n,
+                parity: Vec::new(),
+            }
+        });
+        if group.k != k || group.n != n || group.parity.iter().any(|(row, _)| *row == j) {
+            return Vec::new();
+        }
+        group.parity.push((j, dgram[FEC_HEADER_LEN..].to_vec()));
+        if self.group_order.len() > GROUP_HISTORY {
+            if let Some(old) = self.group_order.pop_front() {
+                self.groups.remove(&old);
+            }
+        }
+        self.try_recover(base)
+    }
+
+    fn try_recover(&mut self, base: u16) -> Vec<Vec<u8>> {
+        let Some(group) = self.groups.get(&base) else {
+            return Vec::new();
+        };
+        let seqs: Vec<u16> = (0..group.k as u16).map(|i| base.wrapping_add(i)).collect();
+        let missing: Vec<u8> = (0..group.k)
+            .filter(|i| !self.sources.contains_key(&seqs[*i as usize]))
+            .collect();
+        if missing.is_empty() {
+            self.forget(base);
+            return Vec::new();
+        }
+        if missing.len() > group.parity.len() {
+            return Vec::new();
+        }
+        let rows = &group.parity[..missing.len()];
+        let len = rows.iter().map(|(_, p)| p.len()).max().unwrap_or(0);
+
+        // Move known sources to the right-hand side of each parity equation.
+        let mut rhs: Vec<Vec<u8>> = rows
+            .iter()
+            .map(|(j, parity)| {
+                let mut acc = parity.clone();
+                acc.resize(len, 0);
+                for i in 0..group.k {
+                    if let Some(src) = self.sources.get(&seqs[i as usize]) {
+                        mul_add(&mut acc, coef(group.k, group.n, *j, i), &unit(src));
+                    }
+                }
+                acc
+            })
+            .collect();
+        let mut matrix: Vec<Vec<u8>> = rows
+            .iter()
+            .map(|(j, _)| {
+                missing
+                    .iter()
+                    .map(|i| coef(group.k, group.n, *j, *i))
+                    .collect()
+            })
+            .collect();
+        if !solve(&mut matrix, &mut rhs) {
+            return Vec::new();
+        }
+
+        let mut recovered = Vec::with_capacity(missing.len());
+        for (i, unit) in missing.iter().zip(rhs) {
+            if let Some(dgram) = from_unit(&unit) {
+                let seq = seqs[*i as usize];
+                self.sources.insert(seq, dgram.clone());
+                self.source_order.push_back(seq);
+                recovered.push(dgram);
+            }
+        }
+        self.forget(base);
+        recovered
+    }
+
+    fn forget(&mut self, base: u16) {
+        self.groups.remove(&base);
+        self.group_order.retain(|b| *b != base);
+    }
+}
+
+/// Gauss-Jordan elimination over GF(256); leaves the solution in `rhs`.
+fn solve(matrix: &mut [Vec<u8>], rhs: &mut [Vec<u8>]) -> bool {
+    let size = matrix.len();
+    for col in 0..size {
+        let Some(pivot) = (col..size).find(|row| matrix[*row][col] != 0) else {
+            return false;
+        };
+        matrix.swap(col, pivot);
+        rhs.swap(col, pivot);
+        let scale = inv(matrix[col][col]);
+        for value in matrix[col].iter_mut() {
+            *value = mul(*value, scale);
+        }
+        for value in rhs[col].iter_mut() {
+            *value = mul(*value, scale);
+        }
+        for row in 0..size {
+            let factor = matrix[row][col];
+            if row == col || factor == 0 {
+                continue;
+            }
+            let (pivot_row, pivot_rhs) = (matrix[col].clone(), rhs[col].clone());
+            mul_add(&mut matrix[row], factor, &pivot_row);
+            mul_add(&mut rhs[row], factor, &pivot_rhs);
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(k: u8, n: u8) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+        let mut encoder = FecEncoder::new(k, n);
+        let sources: Vec<Vec<u8>> = (0..k).map(|i| vec![i; 10 + i as usize]).collect();
+        let mut parity = Vec::new();
+        for (i, src) in sources.iter().enumerate() {
+            parity = encoder.protect((u16::MAX - 1).wrapping_add(i as u16), src);
+        }
+        assert_eq!(parity.len(), (n - k) as usize);
+        (sources, parity)
+    }
+
+    #[test]
+    fn xor_parity_rebuilds_one_loss() {
+        let (sources, parity) = group(4, 5);
+        let mut decoder = FecDecoder::default();
+        for (i, src) in sources.iter().enumerate().filter(|(i, _)| *i != 2) {
+            decoder.on_source((u16::MAX - 1).wrapping_add(i as u16), src);
+        }
+        assert_eq!(decoder.on_parity(&parity[0]), vec![sources[2].clone()]);
+    }
+
+    #[test]
+    fn reed_solomon_rebuilds_burst_with_parity_first() {
+        let (sources, parity) = group(5, 8);
+        let mut decoder = FecDecoder::default();
+        assert!(decoder.on_parity(&parity[2]).is_empty());
+        assert!(decoder.on_parity(&parity[0]).is_empty());
+        assert!(decoder.on_parity(&parity[1]).is_empty());
+        assert!(decoder.on_source(u16::MAX - 1, &sources[0]).is_empty());
+        let recovered = decoder.on_source((u16::MAX - 1).wrapping_add(4), &sources[4]);
+        assert_eq!(recovered, sources[1..4].to_vec());
+    }
+}
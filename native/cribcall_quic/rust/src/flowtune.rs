@@ -0,0 +1,131 @@
+//! Receive-window bounds and the bandwidth-delay estimate that explains
+//! them. quiche grows a stream or connection window whenever the peer uses
+//! one up within two RTTs, i.e. whenever delivery rate times RTT outruns
+//! it; the bounds here are the credit a connection starts with and the
+//! ceiling it may grow to. The receive-side BDP is tracked per connection
+//! so stats show what the path actually needs.
+
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Credit each stream and the connection start with.
+pub(crate) const DEFAULT_MIN_WINDOW: u64 = crate::DEFAULT_STREAM_WINDOW;
+/// quiche's own stream-window ceiling.
+pub(crate) const DEFAULT_MAX_WINDOW: u64 = 16 * 1_048_576;
+/// Weight of a new delivery-rate sample in the smoothed rate.
+const RATE_GAIN: f64 = 0.25;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct FlowWindow {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl Default for FlowWindow {
+    fn default() -> Self {
+        Self {
+            min: DEFAULT_MIN_WINDOW,
+            max: DEFAULT_MAX_WINDOW,
+        }
+    }
+}
+
+impl FlowWindow {
+    /// Applies the bounds to a quiche config. The connection window may
+    /// grow to 1.5x the stream ceiling, the ratio quiche uses by default.
+    pub(crate) fn apply(&self, config: &mut quiche::Config) {
+        config.set_initial_max_data(self.min);
+        config.set_initial_max_stream_data_bidi_local(self.min);
+        config.set_initial_max_stream_data_bidi_remote(self.min);
+        config.set_initial_max_stream_data_uni(self.min);
+        config.set_max_stream_window(self.max);
+        config.set_max_connection_window(self.max / 2 * 3);
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Serialize)]
+pub(crate) struct FlowStats {
+    /// Smoothed rate the peer delivered to us, in bits per second.
+    pub recv_rate_bps: u64,
+    /// That rate over the minimum RTT, clamped to the window bounds.
+    pub bdp_bytes: u64,
+    pub window_min: u64,
+    pub window_max: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct BdpEstimator {
+    last: Option<(Instant, u64)>,
+    /// Bytes per second.
+    rate: f64,
+}
+
+impl BdpEstimator {
+    /// Samples the connection's received bytes and returns the estimate.
+    pub(crate) fn stats(&mut self, conn: &quiche::Connection, window: &FlowWindow) -> FlowStats {
+        let min_rtt = conn
+            .path_stats()
+            .find(|path| path.active)
+            .and_then(|p| p.min_rtt)
+            .unwrap_or_default();
+        self.sample(Instant::now(), conn.stats().recv_bytes, min_rtt, window)
+    }
+
+    fn sample(
+        &mut self,
+        now: Instant,
+        recv_bytes: u64,
+        min_rtt: Duration,
+        window: &FlowWindow,
+    ) -> FlowStats {
+        if let Some((at, bytes)) = self.last {
+            let elapsed = now.saturating_duration_since(at).as_secs_f64();
+            if elapsed > 0.0 {
+                let rate = recv_bytes.saturating_sub(bytes) as f64 / elapsed;
+                self.rate = if self.rate == 0.0 {
+                    rate
+                } else {
+                    self.rate + RATE_GAIN * (rate - self.rate)
+                };
+            }
+        }
+        self.last = Some((now, recv_bytes));
+        let bdp = (self.rate * min_rtt.as_secs_f64()) as u64;
+        FlowStats {
+            recv_rate_bps: (self.rate * 8.0) as u64,
+            bdp_bytes: bdp.clamp(window.min, window.max),
+            window_min: window.min,
+            window_max: window.max,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bdp_follows_the_smoothed_rate_within_bounds() {
+        let window = FlowWindow {
+            min: 64 * 1024,
+            max: 4 * 1_048_576,
+        };
+        let rtt = Duration::from_millis(80);
+        let (mut est, start) = (BdpEstimator::default(), Instant::now());
+        est.sample(start, 0, rtt, &window);
+        // 10 MB/s over 80 ms: 800 kB in flight.
+        let stats = est.sample(start + Duration::from_secs(1), 10_000_000, rtt, &window);
+        assert_eq!(stats.recv_rate_bps, 80_000_000);
+        assert_eq!(stats.bdp_bytes, 800_000);
+        // A near-idle second only pulls the average down by the gain.
+        let stats = est.sample(start + Duration::from_secs(2), 10_000_000, rtt, &window);
+        assert_eq!(stats.bdp_bytes, 600_000);
+        let stats = est.sample(
+            start + Duration::from_secs(3),
+            10_000_000,
+            Duration::ZERO,
+            &window,
+        );
+        assert_eq!(stats.bdp_bytes, window.min);
+    }
+}
@@ -0,0 +1,468 @@
+//! Handshake progress for `handshake_progress` events, so a pairing UI can
+//! show where a connection is and a stuck handshake shows where it stopped
+//! instead of a silence until `closed`. quiche does not expose its key
+//! epochs, so the stages are read off what crosses the wire:
+//!
+//! - `initial_sent`: our first packet went out;
+//! - `handshake_keys`: on a client, a Handshake packet arrived, so the
+//!   server answered its Initial; a server derives them from the
+//!   ClientHello, so it reaches this with its first flight;
+//! - `one_rtt_keys`: the TLS handshake completed here;
+//! - `confirmed`: on a server as soon as it completes; on a client, the
+//!   first 1-RTT packet from the server after that, which is the flight
+//!   carrying HANDSHAKE_DONE.
+//!
+//! A connection that closes before it is announced gets one
+//! `handshake_failed` with a [`Cause`] read off quiche's close errors, the
+//! packets seen and whether the idle timer fired (see [`Cause`]).
+
+use serde::Serialize;
+use std::time::Instant;
+
+#[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Stage {
+    InitialSent,
+    HandshakeKeys,
+    OneRttKeys,
+    Confirmed,
+}
+
+const STAGES: [Stage; 4] = [
+    Stage::InitialSent,
+    Stage::HandshakeKeys,
+    Stage::OneRttKeys,
+    Stage::Confirmed,
+];
+
+impl Stage {
+    fn name(self) -> &'static str {
+        match self {
+            Stage::InitialSent => "initial_sent",
+            Stage::HandshakeKeys => "handshake_keys",
+            Stage::OneRttKeys => "one_rtt_keys",
+            Stage::Confirmed => "confirmed",
+        }
+    }
+}
+
+/// RFC 8446 names of the alerts a QUIC handshake can end with.
+fn alert_name(alert: u8) -> Option<&'static str> {
+    Some(match alert {
+        10 => "unexpected_message",
+        20 => "bad_record_mac",
+        40 => "handshake_failure",
+        42 => "bad_certificate",
+        43 => "unsupported_certificate",
+        44 => "certificate_revoked",
+        45 => "certificate_expired",
+        46 => "certificate_unknown",
+        47 => "illegal_parameter",
+        48 => "unknown_ca",
+        49 => "access_denied",
+        50 => "decode_error",
+        51 => "decrypt_error",
+        70 => "protocol_version",
+        71 => "insufficient_security",
+        80 => "internal_error",
+        109 => "missing_extension",
+        110 => "unsupported_extension",
+        112 => "unrecognized_name",
+        116 => "certificate_required",
+        120 => "no_application_protocol",
+        _ => return None,
+    })
+}
+
+/// QUIC v1 long-header packet types.
+const INITIAL: u8 = 0;
+const HANDSHAKE: u8 = 2;
+const RETRY: u8 = 3;
+
+/// Close code of a client whose pinned server fingerprint did not match.
+pub(crate) const PIN_MISMATCH: u64 = 0x102;
+/// Close code of a server turning away a client off its allowlist.
+pub(crate) const UNTRUSTED_PEER: u64 = 0x103;
+/// quiche's CRYPTO_ERROR range: 0x100 plus the TLS alert.
+const TLS_ALERT_BASE: u64 = 0x100;
+/// Close code of a server requiring a certificate the client did not send:
+/// the `certificate_required` alert.
+pub(crate) const CERTIFICATE_REQUIRED: u64 = TLS_ALERT_BASE + 116;
+/// Alerts about a certificate: the side sending one could not parse or
+/// did not accept the other's certificate.
+const CERT_ALERTS: [u8; 6] = [42, 43, 44, 45, 46, 48];
+/// Room for the last datagram a server sends up to its amplification limit.
+const AMPLIFICATION_SLACK: u64 = 1500;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cause {
+    /// A client heard nothing back: likely a firewall, a wrong address or
+    /// port, or the server is not running.
+    NoResponse,
+    /// The server offered no QUIC version this side speaks.
+    VersionMismatch,
+    /// A TLS alert other than a certificate one; see `alert`.
+    TlsAlert,
+    /// A certificate alert: one side could not parse or verify the other's
+    /// certificate.
+    CertInvalid,
+    /// The certificate was valid but not the pinned or allowlisted one.
+    FingerprintMismatch,
+    /// A server that never saw the client's address validated used up its
+    /// 3x anti-amplification allowance and the client went quiet.
+    AmplificationLimited,
+    /// The idle timeout fired with packets flowing; `detail` names the
+    /// last stage reached.
+    Timeout,
+    /// Any other close before the connection was announced: a transport
+    /// error, or an application close such as a failed token.
+    Other,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub(crate) struct Failure {
+    pub cause: Cause,
+    /// The TLS alert, for `tls_alert` and `cert_invalid`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alert: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alert_name: Option<&'static str>,
+    /// Whether the peer closed the connection, when one side did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_peer: Option<bool>,
+    /// The QUIC error code of the close, when either side sent one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<u64>,
+    pub detail: String,
+    /// Since the connection started.
+    pub elapsed_ms: u64,
+}
+
+pub(crate) struct HandshakeProgress {
+    started: Instant,
+    /// The last stage posted.
+    reached: Option<Stage>,
+    saw_handshake: bool,
+    /// A 1-RTT packet arrived after the handshake completed here.
+    saw_one_rtt: bool,
+    /// The first error `conn.recv` returned.
+    recv_error: Option<quiche::Error>,
+    /// The socket reported the server port unreachable (ICMP).
+    unreachable: bool,
+}
+
+impl HandshakeProgress {
+    pub(crate) fn new(started: Instant) -> Self {
+        Self {
+            started,
+            reached: None,
+            saw_handshake: false,
+            saw_one_rtt: false,
+            recv_error: None,
+            unreachable: false,
+        }
+    }
+
+    /// Notes that the socket got `ECONNREFUSED`: nothing listens there.
+    pub(crate) fn on_unreachable(&mut self) {
+        self.unreachable = true;
+    }
+
+    pub(crate) fn unreachable(&self) -> bool {
+        self.unreachable
+    }
+
+    /// Notes an error `conn.recv` returned other than `Done`.
+    pub(crate) fn on_recv_error(&mut self, err: quiche::Error) {
+        self.recv_error.get_or_insert(err);
+    }
+
+    /// Why `conn`, closed before it was announced, did not make it.
+    pub(crate) fn failure(&self, conn: &quiche::Connection) -> Failure {
+        let stats = conn.stats();
+        let closed_by = conn
+            .peer_error()
+            .map(|err| (err, true))
+            .or_else(|| conn.local_error().map(|err| (err, false)));
+        let reached = match self.reached {
+            Some(stage) => format!("after {}", stage.name()),
+            None => "before any stage".to_string(),
+        };
+        let timed_out = Failure {
+            cause: Cause::Timeout,
+            alert: None,
+            alert_name: None,
+            from_peer: None,
+            error_code: None,
+            detail: format!("idle timeout {reached}"),
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+        };
+        if self.recv_error == Some(quiche::Error::UnknownVersion) {
+            return Failure {
+                cause: Cause::VersionMismatch,
+                detail: "the server offered no supported QUIC version".to_string(),
+                ..timed_out
+            };
+        }
+        if let Some((err, from_peer)) = closed_by {
+            let reason = String::from_utf8_lossy(&err.reason);
+            let failure = Failure {
+                cause: Cause::Other,
+                from_peer: Some(from_peer),
+                error_code: Some(err.error_code),
+                detail: if reason.is_empty() {
+                    format!("closed with code {:#x}", err.error_code)
+                } else {
+                    format!("closed with code {:#x}: {reason}", err.error_code)
+                },
+                ..timed_out
+            };
+            if err.is_app {
+                return failure;
+            }
+            return match err.error_code {
+                PIN_MISMATCH | UNTRUSTED_PEER => Failure {
+                    cause: Cause::FingerprintMismatch,
+                    ..failure
+                },
+                code @ TLS_ALERT_BASE..=0x1ff => {
+                    let alert = (code - TLS_ALERT_BASE) as u8;
+                    let cause = if CERT_ALERTS.contains(&alert) {
+                        Cause::CertInvalid
+                    } else {
+                        Cause::TlsAlert
+                    };
+                    let name = alert_name(alert);
+                    let by = if from_peer { "from the peer" } else { "sent" };
+                    Failure {
+                        cause,
+                        alert: Some(alert),
+                        alert_name: name,
+                        detail: format!("TLS alert {} {by}", name.unwrap_or("unknown")),
+                        ..failure
+                    }
+                }
+                _ => failure,
+            };
+        }
+        if !conn.is_server() && stats.recv == 0 {
+            return Failure {
+                cause: Cause::NoResponse,
+                detail: if self.unreachable {
+                    "the server port is unreachable".to_string()
+                } else {
+                    format!("no reply to {} packets", stats.sent)
+                },
+                ..timed_out
+            };
+        }
+        if conn.is_server()
+            && !conn.is_established()
+            && stats.sent_bytes + AMPLIFICATION_SLACK >= 3 * stats.recv_bytes
+        {
+            return Failure {
+                cause: Cause::AmplificationLimited,
+                detail: format!(
+                    "sent {} bytes for {} received from an unvalidated address",
+                    stats.sent_bytes, stats.recv_bytes
+                ),
+                ..timed_out
+            };
+        }
+        timed_out
+    }
+
+    /// Notes the packet types in a datagram about to go to `conn.recv`.
+    pub(crate) fn on_datagram(&mut self, conn: &quiche::Connection, datagram: &[u8]) {
+        if self.reached == Some(Stage::Confirmed) {
+            return;
+        }
+        let (handshake, one_rtt) = packet_kinds(datagram);
+        self.saw_handshake |= handshake;
+        self.saw_one_rtt |= one_rtt && conn.is_established();
+    }
+
+    /// Stages reached since the last call, in order, with the milliseconds
+    /// since the connection started.
+    pub(crate) fn advance(&mut self, conn: &quiche::Connection) -> Vec<(Stage, u64)> {
+        if self.reached == Some(Stage::Confirmed) {
+            return Vec::new();
+        }
+        let Some(current) = self.current(conn) else {
+            return Vec::new();
+        };
+        if self.reached >= Some(current) {
+            return Vec::new();
+        }
+        let elapsed_ms = self.started.elapsed().as_millis() as u64;
+        let new = STAGES
+            .into_iter()
+            .filter(|stage| Some(*stage) > self.reached && *stage <= current)
+            .map(|stage| (stage, elapsed_ms))
+            .collect();
+        self.reached = Some(current);
+        new
+    }
+
+    fn current(&self, conn: &quiche::Connection) -> Option<Stage> {
+        let confirmed = conn.is_server() || self.saw_one_rtt;
+        if conn.is_established() && confirmed {
+            Some(Stage::Confirmed)
+        } else if conn.is_established() {
+            Some(Stage::OneRttKeys)
+        } else if conn.stats().sent == 0 {
+            None
+        } else if self.saw_handshake || conn.is_server() {
+            Some(Stage::HandshakeKeys)
+        } else {
+            Some(Stage::InitialSent)
+        }
+    }
+}
+
+/// Whether `datagram` holds a Handshake packet and whether it ends in a
+/// short-header (1-RTT) one, walking coalesced long-header packets by
+/// their length fields. Only the unprotected header bits are read.
+fn packet_kinds(datagram: &[u8]) -> (bool, bool) {
+    let mut handshake = false;
+    let mut rest = datagram;
+    while let Some(&first) = rest.first() {
+        if first & 0x80 == 0 {
+            return (handshake, true);
+        }
+        let ty = (first >> 4) & 0x03;
+        handshake |= ty == HANDSHAKE;
+        match long_packet_len(rest, ty) {
+            Some(len) if ty != RETRY => rest = &rest[len..],
+            _ => break,
+        }
+    }
+    (handshake, false)
+}
+
+/// Total length of the long-header packet at the start of `packet`.
+fn long_packet_len(packet: &[u8], ty: u8) -> Option<usize> {
+    // Type byte and version; version 0 is a version negotiation packet.
+    let version = packet.get(1..5)?;
+    if version == [0; 4] {
+        return None;
+    }
+    let mut at = 5;
+    for _ in 0..2 {
+        let cid_len = usize::from(*packet.get(at)?);
+        at += 1 + cid_len;
+    }
+    if ty == INITIAL {
+        let (token_len, used) = varint(packet.get(at..)?)?;
+        at += used + usize::try_from(token_len).ok()?;
+    }
+    let (len, used) = varint(packet.get(at..)?)?;
+    let end = at + used + usize::try_from(len).ok()?;
+    (end <= packet.len()).then_some(end)
+}
+
+/// A QUIC variable-length integer and the bytes it took.
+pub(crate) fn varint(data: &[u8]) -> Option<(u64, usize)> {
+    let first = *data.first()?;
+    let len = 1usize << (first >> 6);
+    let bytes = data.get(..len)?;
+    let value = bytes[1..]
+        .iter()
+        .fold(u64::from(first & 0x3f), |value, byte| {
+            (value << 8) | u64::from(*byte)
+        });
+    Some((value, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn long_packet(ty: u8, payload_len: usize) -> Vec<u8> {
+        let mut packet = vec![0xc0 | (ty << 4), 0, 0, 0, 1, 2, 0xaa, 0xbb, 1, 0xcc];
+        if ty == INITIAL {
+            packet.push(0); // no token
+        }
+        // Two-byte varint length.
+        packet.extend_from_slice(&[0x40 | (payload_len >> 8) as u8, payload_len as u8]);
+        packet.resize(packet.len() + payload_len, 0);
+        packet
+    }
+
+    #[test]
+    fn coalesced_packets_are_walked_by_length() {
+        let initial = long_packet(INITIAL, 300);
+        assert_eq!(packet_kinds(&initial), (false, false));
+
+        let mut flight = initial.clone();
+        flight.extend(long_packet(HANDSHAKE, 40));
+        assert_eq!(packet_kinds(&flight), (true, false));
+
+        flight.extend_from_slice(&[0x40, 1, 2, 3]);
+        assert_eq!(packet_kinds(&flight), (true, true));
+
+        // A length running past the datagram stops the walk.
+        let mut cut = initial;
+        cut.truncate(100);
+        cut.extend(long_packet(HANDSHAKE, 40));
+        assert_eq!(packet_kinds(&cut), (false, false));
+    }
+
+    fn client() -> quiche::Connection {
+        let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+        config.set_application_protos(&[b"test"]).unwrap();
+        let scid = quiche::ConnectionId::from_ref(&[7; 16]);
+        let local = "127.0.0.1:50000".parse().unwrap();
+        let peer = "127.0.0.1:4433".parse().unwrap();
+        let mut conn = quiche::connect(None, &scid, local, peer, &mut config).unwrap();
+        let mut out = [0; 1500];
+        conn.send(&mut out).unwrap();
+        conn
+    }
+
+    #[test]
+    fn failures_are_classified_from_close_errors_and_traffic() {
+        let progress = HandshakeProgress::new(Instant::now());
+        let silent = client();
+        assert_eq!(progress.failure(&silent).cause, Cause::NoResponse);
+
+        let mut rejected = client();
+        rejected.close(false, TLS_ALERT_BASE + 45, b"").unwrap();
+        let failure = progress.failure(&rejected);
+        assert_eq!(failure.cause, Cause::CertInvalid);
+        assert_eq!(failure.alert_name, Some("certificate_expired"));
+        assert_eq!(failure.from_peer, Some(false));
+
+        let mut alert = client();
+        alert.close(false, TLS_ALERT_BASE + 120, b"").unwrap();
+        assert_eq!(progress.failure(&alert).cause, Cause::TlsAlert);
+
+        let mut required = client();
+        required.close(false, CERTIFICATE_REQUIRED, b"").unwrap();
+        let failure = progress.failure(&required);
+        assert_eq!(failure.cause, Cause::TlsAlert);
+        assert_eq!(failure.alert_name, Some("certificate_required"));
+
+        let mut pinned = client();
+        pinned
+            .close(false, PIN_MISMATCH, b"fingerprint mismatch")
+            .unwrap();
+        let failure = progress.failure(&pinned);
+        assert_eq!(failure.cause, Cause::FingerprintMismatch);
+        assert!(failure.detail.ends_with("fingerprint mismatch"));
+
+        let mut negotiated = HandshakeProgress::new(Instant::now());
+        negotiated.on_recv_error(quiche::Error::UnknownVersion);
+        negotiated.on_recv_error(quiche::Error::InvalidPacket);
+        assert_eq!(negotiated.failure(&silent).cause, Cause::VersionMismatch);
+    }
+
+    #[test]
+    fn varints_take_their_prefixed_length() {
+        assert_eq!(varint(&[0x25]), Some((37, 1)));
+        assert_eq!(varint(&[0x7b, 0xbd]), Some((15_293, 2)));
+        assert_eq!(varint(&[0x9d, 0x7f, 0x3e, 0x7d]), Some((494_878_333, 4)));
+        assert_eq!(varint(&[0x7b]), None);
+    }
+}
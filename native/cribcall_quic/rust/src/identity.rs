@@ -0,0 +1,78 @@
+//! Identity rotation announcements. After `cc_quic_identity_rotate` each
+//! established peer is told the SHA-256 fingerprint of the next certificate
+//! on a dedicated unidirectional stream (client id 2, server id 3), so app
+//! framing on the control stream is untouched. The record carries no
+//! signature of its own: it arrives inside a TLS session the peer already
+//! authenticated against the current certificate.
+
+const CLIENT_STREAM: u64 = 2;
+const SERVER_STREAM: u64 = 3;
+const ANNOUNCE: u8 = 1;
+const RECORD_LEN: usize = 33;
+
+pub(crate) type Fingerprint = [u8; 32];
+
+pub(crate) fn is_identity_stream(stream_id: u64) -> bool {
+    stream_id == CLIENT_STREAM || stream_id == SERVER_STREAM
+}
+
+/// Queues the announcement of `next` to the peer of `conn`.
+pub(crate) fn announce(
+    conn: &mut quiche::Connection,
+    is_server: bool,
+    next: &Fingerprint,
+) -> quiche::Result<()> {
+    let stream_id = if is_server {
+        SERVER_STREAM
+    } else {
+        CLIENT_STREAM
+    };
+    let mut record = [0u8; RECORD_LEN];
+    record[0] = ANNOUNCE;
+    record[1..].copy_from_slice(next);
+    conn.stream_send(stream_id, &record, false).map(|_| ())
+}
+
+/// Reassembles the peer's announcements across stream reads.
+#[derive(Default)]
+pub(crate) struct IdentityInbox {
+    partial: Vec<u8>,
+}
+
+impl IdentityInbox {
+    /// Feeds bytes read from the peer's identity stream; pushes the hex
+    /// fingerprint of each complete announcement.
+    pub(crate) fn on_data(&mut self, data: &[u8], out: &mut Vec<String>) {
+        self.partial.extend_from_slice(data);
+        let mut records = self.partial.chunks_exact(RECORD_LEN);
+        for rec in &mut records {
+            if rec[0] == ANNOUNCE {
+                out.push(hex::encode(&rec[1..]));
+            }
+        }
+        self.partial = records.remainder().to_vec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reassembles_announcements_split_across_reads() {
+        let mut record = vec![ANNOUNCE];
+        record.extend_from_slice(&[0xab; 32]);
+        let mut wire = record.clone();
+        wire.push(9);
+        wire.extend_from_slice(&[0; 32]);
+        wire.extend_from_slice(&record);
+
+        let mut inbox = IdentityInbox::default();
+        let mut out = Vec::new();
+        inbox.on_data(&wire[..40], &mut out);
+        assert_eq!(out, vec!["ab".repeat(32)]);
+        inbox.on_data(&wire[40..], &mut out);
+        assert_eq!(out.len(), 2);
+        assert!(inbox.partial.is_empty());
+    }
+}
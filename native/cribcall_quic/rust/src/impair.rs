@@ -0,0 +1,231 @@
+//! Network impairment for tests, built only with the `impairment` feature:
+//! per-connection packet loss, reordering, added latency with jitter and a
+//! bandwidth cap, applied to the datagrams a worker sends. It reproduces
+//! poor links (congested hotel Wi-Fi, a lossy cellular hop) in automated
+//! tests, over UDP or the in-memory loopback.
+//!
+//! Each side impairs only what it sends; impair both ends to degrade both
+//! directions. Held datagrams go out on the worker's next pass, so delays
+//! have the workers' few-millisecond resolution. Without the feature
+//! [`Impairment`] is uninhabited and the send path never holds anything.
+
+#[cfg(feature = "impairment")]
+pub(crate) use enabled::{ImpairConfig, Impairment};
+
+#[cfg(not(feature = "impairment"))]
+pub(crate) enum Impairment {}
+
+#[cfg(not(feature = "impairment"))]
+impl Impairment {
+    pub(crate) fn submit(
+        &mut self,
+        _data: &[u8],
+        _from: Option<std::net::SocketAddr>,
+        _to: std::net::SocketAddr,
+        _now: std::time::Instant,
+    ) {
+        match *self {}
+    }
+
+    pub(crate) fn due(
+        &mut self,
+        _now: std::time::Instant,
+        _from: Option<std::net::SocketAddr>,
+    ) -> Option<(Vec<u8>, std::net::SocketAddr)> {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "impairment")]
+mod enabled {
+    use rand::rngs::{OsRng, StdRng};
+    use rand::{Rng, RngCore, SeedableRng};
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    /// Queue the bandwidth cap builds up before it drops, as time at the cap.
+    const LINK_BUFFER: Duration = Duration::from_millis(200);
+    /// Extra hold for a reordered datagram, so later ones overtake it.
+    const REORDER_HOLD: Duration = Duration::from_millis(10);
+
+    #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    pub(crate) struct ImpairConfig {
+        pub loss_pct: f32,
+        pub reorder_pct: f32,
+        pub latency: Duration,
+        /// Each datagram's delay varies by up to this much either way.
+        pub jitter: Duration,
+        /// Bits per second; 0 for no cap.
+        pub bandwidth_bps: u64,
+        /// Seeds the random choices so a run can be repeated; 0 picks one.
+        pub seed: u64,
+    }
+
+    impl ImpairConfig {
+        pub(crate) fn is_noop(&self) -> bool {
+            self.loss_pct <= 0.0
+                && self.reorder_pct <= 0.0
+                && self.latency.is_zero()
+                && self.jitter.is_zero()
+                && self.bandwidth_bps == 0
+        }
+    }
+
+    struct Held {
+        at: Instant,
+        from: Option<SocketAddr>,
+        to: SocketAddr,
+        data: Vec<u8>,
+    }
+
+    pub(crate) struct Impairment {
+        config: ImpairConfig,
+        rng: StdRng,
+        /// Ordered by release time; ties keep submission order.
+        held: Vec<Held>,
+        /// When the capped link finishes sending what it already holds.
+        link_free: Option<Instant>,
+    }
+
+    impl Impairment {
+        pub(crate) fn new(config: ImpairConfig) -> Self {
+            let seed = match config.seed {
+                0 => OsRng.next_u64(),
+                seed => seed,
+            };
+            Self {
+                config,
+                rng: StdRng::seed_from_u64(seed),
+                held: Vec::new(),
+                link_free: None,
+            }
+        }
+
+        fn chance(&mut self, pct: f32) -> bool {
+            pct > 0.0 && self.rng.gen_range(0.0..100.0) < pct
+        }
+
+        /// Takes a datagram quiche built for `to`: drops it, or holds it
+        /// until [`Self::due`] hands it back.
+        pub(crate) fn submit(
+            &mut self,
+            data: &[u8],
+            from: Option<SocketAddr>,
+            to: SocketAddr,
+            now: Instant,
+        ) {
+            if self.chance(self.config.loss_pct) {
+                return;
+            }
+            let mut at = now;
+            if self.config.bandwidth_bps > 0 {
+                let start = self.link_free.map_or(now, |free| free.max(now));
+                if start - now > LINK_BUFFER {
+                    // Tail drop, like a full router queue.
+                    return;
+                }
+                let serialize = data.len() as f64 * 8.0 / self.config.bandwidth_bps as f64;
+                at = start + Duration::from_secs_f64(serialize);
+                self.link_free = Some(at);
+            }
+            at += self.config.latency;
+            let jitter = self.config.jitter.as_secs_f64();
+            if jitter > 0.0 {
+                let offset = self.rng.gen_range(-jitter..=jitter);
+                at = if offset >= 0.0 {
+                    at + Duration::from_secs_f64(offset)
+                } else {
+                    at.checked_sub(Duration::from_secs_f64(-offset))
+                        .map_or(now, |earlier| earlier.max(now))
+                };
+            }
+            if self.chance(self.config.reorder_pct) {
+                at += REORDER_HOLD;
+            }
+            let index = self.held.partition_point(|held| held.at <= at);
+            self.held.insert(
+                index,
+                Held {
+                    at,
+                    from,
+                    to,
+                    data: data.to_vec(),
+                },
+            );
+        }
+
+        /// The next held datagram from `from` whose time has come.
+        pub(crate) fn due(
+            &mut self,
+            now: Instant,
+            from: Option<SocketAddr>,
+        ) -> Option<(Vec<u8>, SocketAddr)> {
+            let index = self
+                .held
+                .iter()
+                .take_while(|held| held.at <= now)
+                .position(|held| held.from == from)?;
+            let held = self.held.remove(index);
+            Some((held.data, held.to))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn drain(impair: &mut Impairment, now: Instant) -> Vec<u8> {
+            std::iter::from_fn(|| impair.due(now, None))
+                .map(|(data, _)| data[0])
+                .collect()
+        }
+
+        #[test]
+        fn delays_drops_and_reorders_reproducibly() {
+            let to: SocketAddr = "192.0.2.1:4433".parse().unwrap();
+            let start = Instant::now();
+            let config = ImpairConfig {
+                loss_pct: 20.0,
+                reorder_pct: 20.0,
+                latency: Duration::from_millis(50),
+                seed: 7,
+                ..ImpairConfig::default()
+            };
+            let run = || {
+                let mut impair = Impairment::new(config);
+                for i in 0..100u8 {
+                    impair.submit(&[i], None, to, start);
+                }
+                assert!(drain(&mut impair, start + Duration::from_millis(49)).is_empty());
+                drain(&mut impair, start + Duration::from_secs(1))
+            };
+            let delivered = run();
+            assert_eq!(delivered, run());
+            assert!((60..95).contains(&delivered.len()), "{}", delivered.len());
+            assert!(delivered.windows(2).any(|pair| pair[0] > pair[1]));
+        }
+
+        #[test]
+        fn bandwidth_cap_paces_and_tail_drops() {
+            let to: SocketAddr = "192.0.2.1:4433".parse().unwrap();
+            let start = Instant::now();
+            // 1000-byte datagrams at 80 kbit/s: one every 100 ms.
+            let mut impair = Impairment::new(ImpairConfig {
+                bandwidth_bps: 80_000,
+                ..ImpairConfig::default()
+            });
+            for i in 0..5u8 {
+                impair.submit(&[i; 1000], None, to, start);
+            }
+            assert!(impair
+                .due(start + Duration::from_millis(99), None)
+                .is_none());
+            assert_eq!(
+                drain(&mut impair, start + Duration::from_millis(200)),
+                [0, 1]
+            );
+            // The fourth and fifth waited more than the link buffer.
+            assert_eq!(drain(&mut impair, start + Duration::from_secs(5)), [2]);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Receive-side adaptive jitter buffer for media frames. Frames leave at
+//! their playout time (arrival of the talkspurt's first frame plus the
+//! media-clock offset plus a target delay), so Dart gets a steady cadence
+//! instead of raw network arrival times.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::media::MediaFrame;
+
+pub(crate) const DEFAULT_JITTER_MIN_MS: u32 = 20;
+pub(crate) const DEFAULT_JITTER_MAX_MS: u32 = 200;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct JitterConfig {
+    /// Media clock of the frame timestamps, e.g. 48000 for Opus.
+    pub clock_rate: u32,
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+}
+
+#[derive(Copy, Clone, Debug, Default, Serialize)]
+pub(crate) struct JitterStats {
+    /// Frames waiting for their playout time.
+    pub depth: usize,
+    /// Media time spanned by the waiting frames.
+    pub depth_ms: f64,
+    pub target_delay_ms: f64,
+    /// Interarrival jitter estimate (RFC 3550).
+    pub jitter_ms: f64,
+    /// Frames that arrived after their playout time and went out at once.
+    pub late: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct JitterBuffer {
+    frames: VecDeque<(Instant, MediaFrame)>,
+    /// Arrival instant and media timestamp playout times are measured from.
+    anchor: Option<(Instant, u32)>,
+    delay: Duration,
+    jitter_s: f64,
+    prev_transit: Option<f64>,
+    clock_rate: u32,
+    late: u64,
+}
+
+impl JitterBuffer {
+    /// Queues in-order frames that arrived at `now`.
+    pub(crate) fn push(&mut self, frames: Vec<MediaFrame>, now: Instant, config: &JitterConfig) {
+        self.clock_rate = config.clock_rate.max(1);
+        for frame in frames {
+            let restart = match self.anchor {
+                None => true,
+                // A talkspurt starting on an empty buffer picks up the
+                // latest delay target.
+                Some(_) => frame.marker && self.frames.is_empty(),
+            };
+            if restart {
+                self.anchor = Some((now, frame.timestamp));
+                self.delay = self.target(config);
+                self.prev_transit = None;
+            }
+            let transit = self.transit(now, frame.timestamp);
+            if let Some(prev) = self.prev_transit {
+                self.jitter_s += ((transit - prev).abs() - self.jitter_s) / 16.0;
+            }
+            self.prev_transit = Some(transit);
+
+            let playout = self.playout(frame.timestamp);
+            if playout + config.max_delay < now {
+                // The sender's clock jumped; start over from this frame.
+                self.anchor = Some((now, frame.timestamp));
+                self.prev_transit = None;
+            } else if playout < now {
+                self.late += 1;
+            }
+            self.frames
+                .push_back((self.playout(frame.timestamp), frame));
+        }
+    }
+
+    /// Releases frames whose playout time has come.
+    pub(crate) fn pop_due(&mut self, now: Instant) -> Vec<MediaFrame> {
+        let mut due = Vec::new();
+        while self.frames.front().is_some_and(|(at, _)| *at <= now) {
+            if let Some((_, frame)) = self.frames.pop_front() {
+                due.push(frame);
+            }
+        }
+        due
+    }
+
+    pub(crate) fn stats(&self) -> JitterStats {
+        let rate = self.clock_rate.max(1) as f64;
+        let depth_ms = match (self.frames.front(), self.frames.back()) {
+            (Some((_, first)), Some((_, last))) => {
+                last.timestamp.wrapping_sub(first.timestamp) as f64 * 1000.0 / rate
+            }
+            _ => 0.0,
+        };
+        JitterStats {
+            depth: self.frames.len(),
+            depth_ms,
+            target_delay_ms: self.delay.as_secs_f64() * 1000.0,
+            jitter_ms: self.jitter_s * 1000.0,
+            late: self.late,
+        }
+    }
+
+    fn target(&self, config: &JitterConfig) -> Duration {
+        Duration::from_secs_f64(self.jitter_s * 3.0).clamp(config.min_delay, config.max_delay)
+    }
+
+    /// Media time of `timestamp` relative to the anchor, in seconds.
+    fn media_offset(&self, timestamp: u32) -> f64 {
+        let Some((_, base)) = self.anchor else {
+            return 0.0;
+        };
+        timestamp.wrapping_sub(base) as i32 as f64 / self.clock_rate as f64
+    }
+
+    fn transit(&self, now: Instant, timestamp: u32) -> f64 {
+        let arrival = match self.anchor {
+            Some((at, _)) => now.saturating_duration_since(at).as_secs_f64(),
+            None => 0.0,
+        };
+        arrival - self.media_offset(timestamp)
+    }
+
+    fn playout(&self, timestamp: u32) -> Instant {
+        let (at, _) = self.anchor.expect("anchored before playout");
+        let offset = self.media_offset(timestamp);
+        if offset >= 0.0 {
+            at + Duration::from_secs_f64(offset) + self.delay
+        } else {
+            (at + self.delay)
+                .checked_sub(Duration::from_secs_f64(-offset))
+                .unwrap_or(at)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: JitterConfig = JitterConfig {
+        clock_rate: 1000,
+        min_delay: Duration::from_millis(20),
+        max_delay: Duration::from_millis(200),
+    };
+
+    fn frame(timestamp: u32) -> MediaFrame {
+        MediaFrame {
+            seq: timestamp as u64,
+            timestamp,
+            marker: false,
+            payload: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn releases_on_media_clock_after_target_delay() {
+        let start = Instant::now();
+        let mut buffer = JitterBuffer::default();
+        // Two 10 ms frames arriving together.
+        buffer.push(vec![frame(0), frame(10)], start, &CONFIG);
+        assert_eq!(buffer.stats().depth, 2);
+        assert_eq!(buffer.stats().depth_ms, 10.0);
+        assert!(buffer.pop_due(start + Duration::from_millis(19)).is_empty());
+        assert_eq!(buffer.pop_due(start + Duration::from_millis(20)).len(), 1);
+        assert_eq!(buffer.pop_due(start + Duration::from_millis(30)).len(), 1);
+        assert_eq!(buffer.stats().target_delay_ms, 20.0);
+    }
+
+    #[test]
+    fn counts_frames_past_their_playout_time() {
+        let start = Instant::now();
+        let mut buffer = JitterBuffer::default();
+        buffer.push(vec![frame(0)], start, &CONFIG);
+        buffer.push(vec![frame(10)], start + Duration::from_millis(45), &CONFIG);
+        assert_eq!(buffer.stats().late, 1);
+        assert!(buffer.stats().jitter_ms > 0.0);
+        assert_eq!(buffer.pop_due(start + Duration::from_millis(45)).len(), 2);
+    }
+}
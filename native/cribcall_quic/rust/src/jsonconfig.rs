@@ -0,0 +1,720 @@
+//! `cc_quic_config_from_json`: every tunable in one document. Sections map
+//! one-to-one onto the `cc_quic_config_set_*` functions and are applied
+//! through them, so defaults (0 = default, as for the setters) and
+//! validation match; a rejected value is reported by field name through
+//! `cc_quic_last_error`.
+//!
+//! ```json
+//! {
+//!   "role": "client",
+//!   "preset": "cellular-remote",
+//!   "idle_timeout_ms": 30000,
+//!   "cc_algorithm": "cubic",
+//!   "hystart": true,
+//!   "pacing": { "enabled": true, "max_rate_kbps": 0 },
+//!   "ack_delay": { "max_ack_delay_ms": 25, "exponent": 3 },
+//!   "keepalive_ms": 0,
+//!   "ecn": true,
+//!   "udp_offload": true,
+//!   "peer_cert_export": false,
+//!   "keying_material_export": false,
+//!   "writable_events": false,
+//!   "observed_address": false,
+//!   "coalesce": false,
+//!   "cwnd_resume_ms": 0,
+//!   "reverse_connect": { "enabled": false, "pins": [] },
+//!   "roster": false,
+//!   "capabilities": ["framing_v2", "compression", "media_datagrams", "fec"],
+//!   "auth_callback": false,
+//!   "connection_approval_ms": 0,
+//!   "ca_path": "/etc/cribcall/ca.pem",
+//!   "cert_expiry_warn_days": 30,
+//!   "pmtu": { "discovery": false, "max_udp_payload": 1350 },
+//!   "stats_interval_ms": 0,
+//!   "shared_runtime": false,
+//!   "server_workers": 1,
+//!   "watchdog_ms": 2000,
+//!   "connection_limits": { "max_lifetime_ms": 0, "max_handshake_ms": 0 },
+//!   "tcp_fallback": { "port": 443, "udp_timeout_ms": 3000 },
+//!   "event_schema": 1,
+//!   "event_limits": { "stats": 0, "health": 0, "error_coalesce_ms": 0 },
+//!   "dgram": { "recv_queue_len": 128, "send_queue_len": 128, "drop_policy": "front" },
+//!   "media": {
+//!     "reorder_window": 4,
+//!     "jitter": { "clock_rate": 48000, "min_delay_ms": 20, "max_delay_ms": 200 }
+//!   },
+//!   "flow_window": { "min_bytes": 1048576, "max_bytes": 16777216 },
+//!   "recv_limits": { "max_message_bytes": 0, "max_connection_bytes": 0 },
+//!   "dual_channel": { "media_kbps": 0, "control_backlog_bytes": 0, "datagrams_first": false },
+//!   "compression": { "mode": "off", "min_size": 0 }
+//! }
+//! ```
+//!
+//! Every key is optional; unknown keys are rejected. `role` ("client" or
+//! "server") makes the config as `cc_quic_config_new_client` / `_server`
+//! would; without it the config fits either side. `preset` is applied
+//! first, as with `cc_quic_config_new_preset`, and the other keys adjust it.
+//! Leaving out one of the `dgram` queue lengths, `flow_window` bounds or
+//! `ack_delay` values keeps its current value. Auth tokens and secrets stay out of the
+//! document (`cc_quic_config_set_auth_token` / `_auth_hmac`), so it can be
+//! logged or shipped with the app.
+
+use crate::presets::Preset;
+use crate::{CcQuicConfig, CcQuicStatus, ConfigRole};
+use serde::Deserialize;
+use std::ffi::CString;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ConfigDoc {
+    role: Option<RoleDoc>,
+    preset: Option<String>,
+    idle_timeout_ms: Option<u64>,
+    cc_algorithm: Option<String>,
+    hystart: Option<bool>,
+    pacing: Option<PacingDoc>,
+    ack_delay: Option<AckDelayDoc>,
+    keepalive_ms: Option<u64>,
+    ecn: Option<bool>,
+    udp_offload: Option<bool>,
+    peer_cert_export: Option<bool>,
+    keying_material_export: Option<bool>,
+    writable_events: Option<bool>,
+    observed_address: Option<bool>,
+    coalesce: Option<bool>,
+    cwnd_resume_ms: Option<u64>,
+    reverse_connect: Option<ReverseConnectDoc>,
+    roster: Option<bool>,
+    capabilities: Option<Vec<String>>,
+    auth_callback: Option<bool>,
+    connection_approval_ms: Option<u64>,
+    ca_path: Option<String>,
+    cert_expiry_warn_days: Option<u32>,
+    pmtu: Option<PmtuDoc>,
+    stats_interval_ms: Option<u64>,
+    shared_runtime: Option<bool>,
+    server_workers: Option<u32>,
+    watchdog_ms: Option<u64>,
+    connection_limits: Option<ConnectionLimitsDoc>,
+    tcp_fallback: Option<TcpFallbackDoc>,
+    event_schema: Option<u32>,
+    event_limits: Option<EventLimitsDoc>,
+    dgram: Option<DgramDoc>,
+    media: Option<MediaDoc>,
+    flow_window: Option<FlowWindowDoc>,
+    recv_limits: Option<RecvLimitsDoc>,
+    dual_channel: Option<DualChannelDoc>,
+    compression: Option<CompressionDoc>,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RoleDoc {
+    Client,
+    Server,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PmtuDoc {
+    discovery: bool,
+    #[serde(default)]
+    max_udp_payload: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PacingDoc {
+    enabled: bool,
+    #[serde(default)]
+    max_rate_kbps: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AckDelayDoc {
+    max_ack_delay_ms: Option<u64>,
+    exponent: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConnectionLimitsDoc {
+    #[serde(default)]
+    max_lifetime_ms: u64,
+    #[serde(default)]
+    max_handshake_ms: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TcpFallbackDoc {
+    port: u16,
+    #[serde(default)]
+    udp_timeout_ms: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReverseConnectDoc {
+    enabled: bool,
+    #[serde(default)]
+    pins: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DgramDoc {
+    recv_queue_len: Option<u32>,
+    send_queue_len: Option<u32>,
+    drop_policy: Option<DropPolicyDoc>,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DropPolicyDoc {
+    Front,
+    Back,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MediaDoc {
+    reorder_window: Option<u32>,
+    jitter: Option<JitterDoc>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JitterDoc {
+    clock_rate: u32,
+    #[serde(default)]
+    min_delay_ms: u32,
+    #[serde(default)]
+    max_delay_ms: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FlowWindowDoc {
+    min_bytes: Option<u64>,
+    max_bytes: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RecvLimitsDoc {
+    #[serde(default)]
+    max_message_bytes: u64,
+    #[serde(default)]
+    max_connection_bytes: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DualChannelDoc {
+    #[serde(default)]
+    media_kbps: u32,
+    #[serde(default)]
+    control_backlog_bytes: u32,
+    #[serde(default)]
+    datagrams_first: bool,
+}
+
+/// Events per second by `cc_quic_subscribe` class; 0 lifts a cap.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EventLimitsDoc {
+    connection: Option<u32>,
+    message: Option<u32>,
+    media: Option<u32>,
+    channel: Option<u32>,
+    stats: Option<u32>,
+    health: Option<u32>,
+    error_coalesce_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CompressionDoc {
+    mode: CompressionModeDoc,
+    #[serde(default)]
+    min_size: u32,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CompressionModeDoc {
+    Off,
+    Deflate,
+    Zstd,
+}
+
+/// A rejected document: the status code to return and the message for
+/// `cc_quic_last_error`.
+#[derive(Debug)]
+pub(crate) struct JsonConfigError {
+    pub code: i32,
+    pub message: String,
+}
+
+impl JsonConfigError {
+    fn new(status: CcQuicStatus, message: impl Into<String>) -> Self {
+        Self {
+            code: status.code(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Maps a setter's status to an error naming the field and what it expects.
+fn check(code: i32, field: &str, expects: &str) -> Result<(), JsonConfigError> {
+    if code == CcQuicStatus::Ok.code() {
+        return Ok(());
+    }
+    let message = if code == CcQuicStatus::WrongRole.code() {
+        format!("{field}: does not apply to this role")
+    } else {
+        format!("{field}: {expects}")
+    };
+    Err(JsonConfigError { code, message })
+}
+
+/// For setters that only fail on a null config.
+fn applied(code: i32) {
+    debug_assert_eq!(code, CcQuicStatus::Ok.code());
+}
+
+pub(crate) fn parse(json: &str) -> Result<ConfigDoc, JsonConfigError> {
+    serde_json::from_str(json).map_err(|err| {
+        JsonConfigError::new(
+            CcQuicStatus::ConfigError,
+            format!("invalid config JSON: {err}"),
+        )
+    })
+}
+
+impl ConfigDoc {
+    pub(crate) fn build(&self) -> Result<CcQuicConfig, JsonConfigError> {
+        let mut config = CcQuicConfig {
+            role: match self.role {
+                None => ConfigRole::Any,
+                Some(RoleDoc::Client) => ConfigRole::Client,
+                Some(RoleDoc::Server) => ConfigRole::Server,
+            },
+            ..CcQuicConfig::default()
+        };
+        if let Some(name) = &self.preset {
+            let preset = Preset::from_name(name).ok_or_else(|| {
+                JsonConfigError::new(
+                    CcQuicStatus::ConfigError,
+                    format!(
+                        "preset: unknown preset {name:?} (expected \"lan-low-latency\", \
+                         \"cellular-remote\" or \"bulk-transfer\")"
+                    ),
+                )
+            })?;
+            preset.apply(&mut config).map_err(|err| {
+                JsonConfigError::new(err.status(), format!("preset: {name:?} failed: {err}"))
+            })?;
+        }
+        self.apply(&mut config)?;
+        Ok(config)
+    }
+
+    fn apply(&self, config: &mut CcQuicConfig) -> Result<(), JsonConfigError> {
+        if let Some(ms) = self.idle_timeout_ms {
+            config.quic.idle_timeout_ms = ms;
+        }
+        if let Some(name) = &self.cc_algorithm {
+            config.quic.cc_algorithm = name.parse().map_err(|_| {
+                JsonConfigError::new(
+                    CcQuicStatus::ConfigError,
+                    format!("cc_algorithm: unknown algorithm {name:?}"),
+                )
+            })?;
+        }
+        if let Some(ms) = self.keepalive_ms {
+            config.options.keepalive_ms = ms;
+        }
+
+        // A queue length or window bound left out keeps the current one.
+        let dgram_queue_len = config.options.dgram_recv_queue_len as u32;
+        let flow_window = config.options.flow_window;
+        let (ack_delay_ms, ack_exponent) =
+            (config.quic.max_ack_delay_ms, config.quic.ack_delay_exponent);
+        let config: *mut CcQuicConfig = config;
+        if let Some(enabled) = self.hystart {
+            applied(crate::cc_quic_config_set_hystart(config, enabled));
+        }
+        if let Some(pacing) = &self.pacing {
+            check(
+                crate::cc_quic_config_set_pacing(config, pacing.enabled, pacing.max_rate_kbps),
+                "pacing.max_rate_kbps",
+                "needs pacing enabled",
+            )?;
+        }
+        if let Some(ack) = &self.ack_delay {
+            check(
+                crate::cc_quic_config_set_ack_delay(
+                    config,
+                    ack.max_ack_delay_ms.unwrap_or(ack_delay_ms),
+                    ack.exponent.unwrap_or(ack_exponent as u32),
+                ),
+                "ack_delay",
+                "max_ack_delay_ms must be below 16384 and exponent at most 20",
+            )?;
+        }
+        if let Some(enabled) = self.ecn {
+            applied(crate::cc_quic_config_set_ecn(config, enabled));
+        }
+        if let Some(enabled) = self.udp_offload {
+            applied(crate::cc_quic_config_set_udp_offload(config, enabled));
+        }
+        if let Some(enabled) = self.peer_cert_export {
+            applied(crate::cc_quic_config_set_peer_cert_export(config, enabled));
+        }
+        if let Some(enabled) = self.keying_material_export {
+            applied(crate::cc_quic_config_set_keying_material_export(
+                config, enabled,
+            ));
+        }
+        if let Some(enabled) = self.writable_events {
+            applied(crate::cc_quic_config_set_writable_events(config, enabled));
+        }
+        if let Some(enabled) = self.observed_address {
+            check(
+                crate::cc_quic_config_set_observed_address(config, enabled),
+                "observed_address",
+                "invalid value",
+            )?;
+        }
+        if let Some(enabled) = self.coalesce {
+            check(
+                crate::cc_quic_config_set_coalesce(config, enabled),
+                "coalesce",
+                "invalid value",
+            )?;
+        }
+        if let Some(max_age_ms) = self.cwnd_resume_ms {
+            check(
+                crate::cc_quic_config_set_cwnd_resume(config, max_age_ms),
+                "cwnd_resume_ms",
+                "invalid value",
+            )?;
+        }
+        if let Some(reverse) = &self.reverse_connect {
+            const EXPECTS: &str = "pins must be hex SHA-256 fingerprints";
+            let pins = CString::new(reverse.pins.join(",")).map_err(|_| {
+                JsonConfigError::new(
+                    CcQuicStatus::ConfigError,
+                    format!("reverse_connect: {EXPECTS}"),
+                )
+            })?;
+            check(
+                crate::cc_quic_config_set_reverse_connect(config, reverse.enabled, pins.as_ptr()),
+                "reverse_connect",
+                EXPECTS,
+            )?;
+        }
+        if let Some(enabled) = self.roster {
+            check(
+                crate::cc_quic_config_set_roster(config, enabled),
+                "roster",
+                "invalid value",
+            )?;
+        }
+        if let Some(names) = &self.capabilities {
+            let mut features = 0;
+            for name in names {
+                features |= crate::capabilities::bit(name).ok_or_else(|| {
+                    JsonConfigError::new(
+                        CcQuicStatus::ConfigError,
+                        format!("capabilities: unknown feature {name:?}"),
+                    )
+                })?;
+            }
+            applied(crate::cc_quic_config_set_capabilities(config, features));
+        }
+        if let Some(enabled) = self.auth_callback {
+            check(
+                crate::cc_quic_config_set_auth_callback(config, enabled),
+                "auth_callback",
+                "invalid value",
+            )?;
+        }
+        if let Some(timeout_ms) = self.connection_approval_ms {
+            check(
+                crate::cc_quic_config_set_connection_approval(config, timeout_ms),
+                "connection_approval_ms",
+                "invalid value",
+            )?;
+        }
+        if let Some(path) = &self.ca_path {
+            const EXPECTS: &str = "must be an existing file or directory";
+            let path = CString::new(path.as_str()).map_err(|_| {
+                JsonConfigError::new(CcQuicStatus::ConfigError, format!("ca_path: {EXPECTS}"))
+            })?;
+            check(
+                crate::cc_quic_config_set_ca_path(config, path.as_ptr()),
+                "ca_path",
+                EXPECTS,
+            )?;
+        }
+        if let Some(days) = self.cert_expiry_warn_days {
+            applied(crate::cc_quic_config_set_cert_expiry_warning(config, days));
+        }
+        if let Some(pmtu) = &self.pmtu {
+            check(
+                crate::cc_quic_config_set_pmtu_discovery(
+                    config,
+                    pmtu.discovery,
+                    pmtu.max_udp_payload,
+                ),
+                "pmtu.max_udp_payload",
+                "must be 0 or between 1200 and 65527",
+            )?;
+        }
+        if let Some(ms) = self.stats_interval_ms {
+            applied(crate::cc_quic_config_set_stats_interval(config, ms));
+        }
+        if let Some(enabled) = self.shared_runtime {
+            applied(crate::cc_quic_config_set_shared_runtime(config, enabled));
+        }
+        if let Some(workers) = self.server_workers {
+            check(
+                crate::cc_quic_config_set_server_workers(config, workers),
+                "server_workers",
+                &format!("must be between 1 and {}", crate::MAX_SERVER_WORKERS),
+            )?;
+        }
+        if let Some(ms) = self.watchdog_ms {
+            applied(crate::cc_quic_config_set_watchdog(config, ms));
+        }
+        if let Some(limits) = &self.connection_limits {
+            check(
+                crate::cc_quic_config_set_connection_limits(
+                    config,
+                    limits.max_lifetime_ms,
+                    limits.max_handshake_ms,
+                ),
+                "connection_limits",
+                "invalid limits",
+            )?;
+        }
+        if let Some(fallback) = &self.tcp_fallback {
+            applied(crate::cc_quic_config_set_tcp_fallback(
+                config,
+                fallback.port,
+                fallback.udp_timeout_ms,
+            ));
+        }
+        if let Some(version) = self.event_schema {
+            check(
+                crate::cc_quic_config_set_event_schema(config, version),
+                "event_schema",
+                &format!(
+                    "must be between 1 and {}",
+                    crate::cc_quic_event_schema_version()
+                ),
+            )?;
+        }
+        if let Some(limits) = &self.event_limits {
+            let classes = [
+                (crate::EVENTS_CONNECTION, limits.connection),
+                (crate::EVENTS_MESSAGE, limits.message),
+                (crate::EVENTS_MEDIA, limits.media),
+                (crate::EVENTS_CHANNEL, limits.channel),
+                (crate::EVENTS_STATS, limits.stats),
+                (crate::EVENTS_HEALTH, limits.health),
+            ];
+            for (class, limit) in classes {
+                if let Some(per_sec) = limit {
+                    applied(crate::cc_quic_config_set_event_rate_limit(
+                        config, class, per_sec,
+                    ));
+                }
+            }
+            if let Some(window_ms) = limits.error_coalesce_ms {
+                applied(crate::cc_quic_config_set_error_coalescing(
+                    config, window_ms,
+                ));
+            }
+        }
+        if let Some(dgram) = &self.dgram {
+            if dgram.recv_queue_len.is_some() || dgram.send_queue_len.is_some() {
+                check(
+                    crate::cc_quic_config_set_dgram_queues(
+                        config,
+                        dgram.recv_queue_len.unwrap_or(dgram_queue_len),
+                        dgram.send_queue_len.unwrap_or(dgram_queue_len),
+                    ),
+                    "dgram",
+                    "recv_queue_len and send_queue_len must be non-zero",
+                )?;
+            }
+            if let Some(policy) = dgram.drop_policy {
+                check(
+                    crate::cc_quic_config_set_dgram_drop_policy(config, policy as i32),
+                    "dgram.drop_policy",
+                    "must be \"front\" or \"back\"",
+                )?;
+            }
+        }
+        if let Some(media) = &self.media {
+            if let Some(packets) = media.reorder_window {
+                applied(crate::cc_quic_config_set_media_reorder_window(
+                    config, packets,
+                ));
+            }
+            if let Some(jitter) = &media.jitter {
+                check(
+                    crate::cc_quic_config_set_jitter_buffer(
+                        config,
+                        jitter.clock_rate,
+                        jitter.min_delay_ms,
+                        jitter.max_delay_ms,
+                    ),
+                    "media.jitter",
+                    "min_delay_ms must not exceed max_delay_ms",
+                )?;
+            }
+        }
+        if let Some(window) = &self.flow_window {
+            check(
+                crate::cc_quic_config_set_flow_window(
+                    config,
+                    window.min_bytes.unwrap_or(flow_window.min),
+                    window.max_bytes.unwrap_or(flow_window.max),
+                ),
+                "flow_window",
+                "min_bytes must not exceed max_bytes",
+            )?;
+        }
+        if let Some(limits) = &self.recv_limits {
+            applied(crate::cc_quic_config_set_recv_limits(
+                config,
+                limits.max_message_bytes,
+                limits.max_connection_bytes,
+            ));
+        }
+        if let Some(dual) = &self.dual_channel {
+            applied(crate::cc_quic_config_set_dual_channel(
+                config,
+                dual.media_kbps,
+                dual.control_backlog_bytes,
+            ));
+            applied(crate::cc_quic_config_set_datagrams_first(
+                config,
+                dual.datagrams_first,
+            ));
+        }
+        if let Some(compression) = &self.compression {
+            let mode = match compression.mode {
+                CompressionModeDoc::Off => 0,
+                CompressionModeDoc::Deflate => 1,
+                CompressionModeDoc::Zstd => 2,
+            };
+            check(
+                crate::cc_quic_config_set_compression(config, mode, compression.min_size),
+                "compression.mode",
+                "not available in this build",
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(json: &str) -> Result<CcQuicConfig, String> {
+        parse(json)
+            .and_then(|doc| doc.build())
+            .map_err(|err| err.message)
+    }
+
+    fn rejection(json: &str) -> String {
+        match build(json) {
+            Ok(_) => panic!("accepted {json}"),
+            Err(message) => message,
+        }
+    }
+
+    #[test]
+    fn sections_apply_on_top_of_the_preset() {
+        let config = build(
+            r#"{
+                "preset": "lan-low-latency",
+                "server_workers": 4,
+                "hystart": false,
+                "pacing": { "enabled": true, "max_rate_kbps": 2000 },
+                "dgram": { "drop_policy": "back" },
+                "flow_window": { "max_bytes": 8388608 },
+                "event_limits": { "stats": 5, "error_coalesce_ms": 1000 },
+                "tcp_fallback": { "port": 443 },
+                "capabilities": ["fec", "framing_v2"],
+                "dual_channel": { "media_kbps": 64, "datagrams_first": true },
+                "ack_delay": { "max_ack_delay_ms": 100 },
+                "reverse_connect": { "enabled": true, "pins": ["ABABABABABABABABABABABABABABABABABABABABABABABABABABABABABABABAB"] }
+            }"#,
+        )
+        .unwrap_or_else(|message| panic!("{message}"));
+        assert_eq!(config.options.server_workers, 4);
+        assert!(!config.quic.hystart);
+        assert_eq!(config.quic.max_pacing_rate, Some(250_000));
+        assert_eq!(config.options.dgram_drop_policy, crate::DropPolicy::Back);
+        assert_eq!(config.options.flow_window.max, 8_388_608);
+        let limits = config.options.event_limits;
+        assert_eq!(limits.per_sec, [0, 0, 0, 0, 5, 0]);
+        assert_eq!(limits.error_window, Some(std::time::Duration::from_secs(1)));
+        let fallback = config.options.tcp_fallback.unwrap();
+        assert_eq!(fallback.port, 443);
+        assert_eq!(fallback.udp_timeout, crate::tunnel::DEFAULT_UDP_TIMEOUT);
+        assert_eq!(config.options.capabilities, 0b1001);
+        assert_eq!(config.options.dual.media_rate_bps, 64_000);
+        assert!(config.options.dual.datagrams_first);
+        assert_eq!(config.quic.max_ack_delay_ms, 100);
+        assert_eq!(config.quic.ack_delay_exponent, 3);
+        let reverse = config.options.reverse_connect.unwrap();
+        assert_eq!(reverse.pins, ["ab".repeat(32)]);
+        // Keys the document leaves out keep the preset's values.
+        assert_eq!(config.options.flow_window.min, 256 * 1024);
+        assert_eq!(config.options.dgram_recv_queue_len, 64);
+        assert_eq!(config.options.keepalive_ms, 0);
+    }
+
+    #[test]
+    fn rejections_name_the_field() {
+        let err = rejection(r#"{ "flow_window": { "min_bytes": 9, "max_bytes": 8 } }"#);
+        assert_eq!(err, "flow_window: min_bytes must not exceed max_bytes");
+        let err = rejection(r#"{ "server_workers": 0 }"#);
+        assert_eq!(err, "server_workers: must be between 1 and 64");
+        let err = rejection(r#"{ "role": "client", "server_workers": 2 }"#);
+        assert_eq!(err, "server_workers: does not apply to this role");
+        let err = rejection(r#"{ "role": "client", "connection_limits": {} }"#);
+        assert_eq!(err, "connection_limits: does not apply to this role");
+        let err = rejection(r#"{ "role": "server", "cwnd_resume_ms": 60000 }"#);
+        assert_eq!(err, "cwnd_resume_ms: does not apply to this role");
+        let err = rejection(r#"{ "reverse_connect": { "enabled": true, "pins": ["ab"] } }"#);
+        assert_eq!(
+            err,
+            "reverse_connect: pins must be hex SHA-256 fingerprints"
+        );
+        let err = rejection(r#"{ "pacing": { "enabled": false, "max_rate_kbps": 8 } }"#);
+        assert_eq!(err, "pacing.max_rate_kbps: needs pacing enabled");
+        let err = rejection(r#"{ "ack_delay": { "max_ack_delay_ms": 16384 } }"#);
+        assert!(err.starts_with("ack_delay: "), "{err}");
+        let err = rejection(r#"{ "preset": "lan" }"#);
+        assert!(err.starts_with("preset: unknown preset \"lan\""), "{err}");
+        let err = rejection(r#"{ "capabilities": ["zstd"] }"#);
+        assert_eq!(err, "capabilities: unknown feature \"zstd\"");
+        let err = rejection(r#"{ "ecn": true, "idle": 5 }"#);
+        assert!(
+            err.starts_with("invalid config JSON: unknown field `idle`"),
+            "{err}"
+        );
+        assert!(err.ends_with("line 1 column 21"), "{err}");
+    }
+}
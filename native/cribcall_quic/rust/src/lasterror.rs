@@ -0,0 +1,37 @@
+//! Per-thread message for the last failed call that had more to say than
+//! its status code, read back with `cc_quic_last_error`.
+
+use std::cell::RefCell;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+pub(crate) fn set(message: String) {
+    LAST_ERROR.with(|slot| *slot.borrow_mut() = Some(message));
+}
+
+pub(crate) fn clear() {
+    LAST_ERROR.with(|slot| *slot.borrow_mut() = None);
+}
+
+/// Writes the message and a NUL into `out`; returns the message length,
+/// `Ok(0)` if there is none, or `Err(needed)` if `out` is too small.
+pub(crate) fn copy_to(out: &mut [u8]) -> Result<usize, usize> {
+    LAST_ERROR.with(|slot| {
+        let slot = slot.borrow();
+        let Some(message) = slot.as_deref() else {
+            if let Some(b) = out.first_mut() {
+                *b = 0;
+            }
+            return Ok(0);
+        };
+        let bytes = message.as_bytes();
+        if bytes.len() + 1 > out.len() {
+            return Err(bytes.len() + 1);
+        }
+        out[..bytes.len()].copy_from_slice(bytes);
+        out[bytes.len()] = 0;
+        Ok(bytes.len())
+    })
+}
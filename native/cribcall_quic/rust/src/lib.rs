@@ -1,981 +1,5014 @@
-use allo_isolate::Isolate;
-use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+mod allowlist;
+pub mod api;
+#[cfg(feature = "async")]
+pub mod asyncapi;
+mod audit;
+mod auth;
+mod blocklist;
+mod buffers;
+mod capabilities;
+mod capture;
+mod certexpiry;
+mod channels;
+mod cids;
+mod coalesce;
+mod compress;
+mod dgram;
+mod diagnostics;
+mod dual;
+mod error;
+mod eventlimit;
+mod exporter;
+mod fec;
+mod ffi;
+mod flowtune;
+mod handshake;
+mod identity;
+mod impair;
+mod jitter;
+mod jsonconfig;
+mod keyseal;
+mod lasterror;
+#[cfg(test)]
+mod loopback;
+mod masque;
+mod media;
+mod metrics;
+mod observed;
+mod poll;
+mod presets;
+mod reaper;
+mod recvguard;
+mod relocate;
+#[cfg(test)]
+mod replay;
+mod reverse;
+mod roster;
+mod runtime;
+mod sendlimit;
+mod settings;
+mod sni;
+mod socket;
+mod socks;
+mod threads;
+mod throttle;
+mod timesync;
+mod topics;
+mod traffic;
+mod transport;
+mod tunnel;
+mod usage;
+mod watchdog;
+
+pub use error::Error;
+pub use ffi::*;
+
+use allowlist::Allowlist;
+use audit::Outcome;
+use auth::{Approval, ClientAuth, Decision, ServerAuth, Step, Verdict, Verifier};
+use base64::{display::Base64Display, engine::general_purpose::STANDARD as BASE64, Engine};
+use blocklist::{Blocklist, BlocklistStats, Cidr};
+use buffers::{EventEncoder, EventSchema, Scratch};
+use capabilities::CapabilityExchange;
+use capture::PacketCapture;
+use certexpiry::CertSide;
+use channels::{Abort, ChannelOp, Channels, Notice};
+use cids::CidIndex;
+use compress::{
+    CompressionConfig, CompressionMode, ControlCodec, InflateError, DEFLATE_ALPN,
+    MAX_INFLATED_FRAME,
+};
 use dashmap::DashMap;
-use log::{error, info, warn};
+use dgram::{DgramInbox, DgramStats, DropPolicy, DEFAULT_DGRAM_QUEUE_LEN};
+use diagnostics::{ConnectionSnapshot, EventHistory, WorkerSnapshot};
+use dual::{ControlStats, DualChannel, DualChannelConfig};
+use eventlimit::{EventGate, EventLimits, Suppressed};
+use flowtune::{BdpEstimator, FlowStats, FlowWindow};
+use handshake::{Failure, HandshakeProgress, Stage};
+use identity::{Fingerprint, IdentityInbox};
+#[cfg(feature = "impairment")]
+use impair::ImpairConfig;
+use impair::Impairment;
+use jitter::{JitterConfig, DEFAULT_JITTER_MAX_MS, DEFAULT_JITTER_MIN_MS};
+use log::{debug, error, info, warn};
+use masque::{MasqueTunnel, ProxyRoute};
+use media::{MediaChannel, MediaFrame, MediaStats, DEFAULT_REORDER_WINDOW};
+use observed::{AddressRecord, ObservedInbox};
 use once_cell::sync::OnceCell;
+use presets::Preset;
 use rand::{rngs::OsRng, RngCore};
+use reaper::{ReapPolicy, ReapReason};
+use recvguard::{LimitScope, RecvGuard, RecvLimits, Violation};
+use relocate::Relocation;
+use reverse::{DialBack, ReverseConnect};
+use roster::{Roster, RosterFeed, RosterInbox, RosterPeer};
+use sendlimit::{SendLimit, SendLimitStats};
 use serde::Serialize;
 use sha2::{Digest, Sha256};
+use socket::{EcnCounts, QuicSocket, RecvMeta, SendBatch, SocketOptions};
+use socks::{SocksRoute, SocksTunnel};
+use std::any::Any;
 use std::collections::{HashMap, HashSet};
 use std::ffi::{CStr, CString};
-use std::net::{SocketAddr, UdpSocket};
+use std::net::SocketAddr;
 use std::os::raw::{c_char, c_void};
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::mpsc;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, PoisonError, RwLock};
 use std::thread;
 use std::time::{Duration, Instant};
+use threads::{ThreadPriority, WorkerThreads};
+use throttle::{Pacer, SendPacing, TokenBucket};
+use timesync::{Estimate, TimeSync};
+use topics::{Subscriber, Subscriptions};
+use traffic::{TrafficClass, TrafficMeter, TrafficStats};
+use transport::MemoryEnd;
+use tunnel::{Fallback, TunnelListener, TunnelStream};
+use usage::UsageBook;
+use watchdog::Heartbeat;
 
 const CONTROL_ALPN: &[u8] = b"cribcall-ctrl";
 const DEFAULT_IDLE_TIMEOUT_MS: u64 = 30_000;
 const DEFAULT_MAX_UDP_PAYLOAD: usize = 1350;
+/// quiche never sends less; the largest is the UDP limit over IPv6.
+const UDP_PAYLOAD_RANGE: std::ops::RangeInclusive<u32> = 1200..=65_527;
 const DEFAULT_STREAM_WINDOW: u64 = 1_048_576; // 1 MiB baseline until tuned.
+/// quiche's and RFC 9000's defaults; a peer assumes them when omitted.
+const DEFAULT_MAX_ACK_DELAY_MS: u64 = 25;
+const DEFAULT_ACK_DELAY_EXPONENT: u64 = 3;
+/// RFC 9000 §18.2 bounds; quiche refuses a peer's parameters beyond them.
+const MAX_ACK_DELAY_LIMIT_MS: u64 = (1 << 14) - 1;
+const ACK_DELAY_EXPONENT_LIMIT: u32 = 20;
 const CONTROL_STREAM_ID: u64 = 0;
 const MAX_DATAGRAM_SIZE: usize = 1350;
+const CONTROL_BACKLOG_FULL: &str = "control backlog full";
+/// Application error code for streams and connections over a receive cap.
+const RECV_LIMIT_ERROR: u64 = 0x104;
+const DEFAULT_WATCHDOG_MS: u64 = 2_000;
+/// Worker indexes are stamped into one SCID byte.
+const MAX_SERVER_WORKERS: u32 = 64;
 
 #[repr(C)]
+#[derive(Default)]
 pub struct CcQuicConfig {
-    inner: quiche::Config,
+    quic: QuicSettings,
+    options: WorkerOptions,
+    role: ConfigRole,
+    /// Set by `cc_quic_test_loopback_pair`; taken by the next start or
+    /// connect, which then uses it instead of a UDP socket.
+    loopback: Option<MemoryEnd>,
+    /// Identities a server picks by SNI (`cc_quic_config_add_sni_identity`).
+    sni: Vec<sni::SniIdentity>,
 }
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub enum CcQuicStatus {
-    Ok = 0,
-    NullPointer = 1,
-    ConfigError = 2,
-    InvalidAlpn = 3,
-    CertLoadError = 4,
-    SocketError = 5,
-    HandshakeError = 6,
-    EventSendError = 7,
-    Internal = 255,
+impl CcQuicConfig {
+    /// Builds the quiche config for one connect or server start. quiche
+    /// configs cannot be cloned and take the certificate, so each use gets
+    /// its own and the `CcQuicConfig` stays with the caller for reuse.
+    fn quiche_config(&self) -> Result<quiche::Config, Error> {
+        let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)
+            .map_err(|err| Error::Config(format!("quiche config: {err}")))?;
+        config
+            .set_application_protos(self.quic.alpns)
+            .map_err(|err| Error::InvalidAlpn(format!("ALPN list refused: {err}")))?;
+
+        config.verify_peer(true);
+        if let Some(ca) = &self.quic.ca_path {
+            load_ca(&mut config, ca).map_err(|err| Error::Config(err.to_string()))?;
+        }
+        config.set_max_idle_timeout(self.quic.idle_timeout_ms);
+        config.set_max_recv_udp_payload_size(self.options.max_udp_payload);
+        config.set_max_send_udp_payload_size(self.options.max_udp_payload);
+        config.discover_pmtu(self.quic.pmtu_discovery);
+        self.options.flow_window.apply(&mut config);
+        config.set_initial_max_streams_bidi(8);
+        config.set_initial_max_streams_uni(8);
+        config.enable_dgram(
+            true,
+            self.options.dgram_recv_queue_len,
+            self.quic.dgram_send_queue_len,
+        );
+        config.enable_pacing(self.quic.pacing);
+        if let Some(rate) = self.quic.max_pacing_rate {
+            config.set_max_pacing_rate(rate);
+        }
+        config.enable_hystart(self.quic.hystart);
+        config.set_cc_algorithm(self.quic.cc_algorithm);
+        config.set_max_ack_delay(self.quic.max_ack_delay_ms);
+        config.set_ack_delay_exponent(self.quic.ack_delay_exponent);
+        if self.options.keying_material_export {
+            config.log_keys();
+        }
+        Ok(config)
+    }
+}
+
+/// Settings applied to each connection's quiche config.
+#[derive(Clone, Debug)]
+struct QuicSettings {
+    alpns: &'static [&'static [u8]],
+    idle_timeout_ms: u64,
+    pmtu_discovery: bool,
+    dgram_send_queue_len: usize,
+    cc_algorithm: quiche::CongestionControlAlgorithm,
+    hystart: bool,
+    pacing: bool,
+    /// Bytes per second; `None` leaves pacing uncapped.
+    max_pacing_rate: Option<u64>,
+    /// The `max_ack_delay` and `ack_delay_exponent` transport parameters.
+    max_ack_delay_ms: u64,
+    ack_delay_exponent: u64,
+    /// Extra CAs to verify the peer with, on top of the system store.
+    ca_path: Option<std::path::PathBuf>,
+}
+
+impl Default for QuicSettings {
+    fn default() -> Self {
+        Self {
+            alpns: &[CONTROL_ALPN],
+            idle_timeout_ms: DEFAULT_IDLE_TIMEOUT_MS,
+            pmtu_discovery: false,
+            dgram_send_queue_len: DEFAULT_DGRAM_QUEUE_LEN,
+            cc_algorithm: quiche::CongestionControlAlgorithm::CUBIC,
+            hystart: true,
+            pacing: true,
+            max_pacing_rate: None,
+            max_ack_delay_ms: DEFAULT_MAX_ACK_DELAY_MS,
+            ack_delay_exponent: DEFAULT_ACK_DELAY_EXPONENT,
+            ca_path: None,
+        }
+    }
+}
+
+/// Which entry point a config was made for. Configs from
+/// `cc_quic_config_new` are `Any` and accept every setter.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+enum ConfigRole {
+    #[default]
+    Any,
+    Client,
+    Server,
+}
+
+impl ConfigRole {
+    fn allows(self, role: ConfigRole) -> bool {
+        self == ConfigRole::Any || self == role
+    }
+}
+
+/// Settings that live outside quiche and are applied by the workers.
+#[derive(Clone, Debug)]
+struct WorkerOptions {
+    ecn: bool,
+    udp_offload: bool,
+    /// Include the peer's DER certificate in `connected`.
+    peer_cert: bool,
+    /// Keep each connection's TLS exporter secret for
+    /// `cc_quic_conn_export_keying_material`.
+    keying_material_export: bool,
+    /// Post `cert_expiring_soon` this many days ahead; 0 never does.
+    cert_warn_days: u32,
+    stats_interval_ms: u64,
+    shared_runtime: bool,
+    server_workers: usize,
+    watchdog_ms: u64,
+    event_schema: EventSchema,
+    event_limits: EventLimits,
+    dgram_recv_queue_len: usize,
+    dgram_drop_policy: DropPolicy,
+    media_reorder_window: usize,
+    jitter: Option<JitterConfig>,
+    dual: DualChannelConfig,
+    recv_limits: RecvLimits,
+    compression: CompressionConfig,
+    flow_window: FlowWindow,
+    max_udp_payload: usize,
+    /// PING interval for established connections; 0 sends none.
+    keepalive_ms: u64,
+    /// Server-side lifetime and handshake limits.
+    reap: ReapPolicy,
+    /// Server: report each client's source address to it.
+    observed_addr: bool,
+    /// Client: join an established connection to the pinned fingerprint.
+    coalesce: bool,
+    /// Client: open with the last window to a server reconnected to within
+    /// this long.
+    cwnd_resume: Option<Duration>,
+    /// Client: dial back to the endpoints the server asks for.
+    reverse_connect: Option<ReverseConnect>,
+    /// Server: keep clients up to date with who else is connected.
+    roster: bool,
+    /// Features offered in the capability exchange; 0 sends no record.
+    capabilities: u32,
+    /// Post `stream_writable` as channel streams gain send credit.
+    writable_events: bool,
+    /// Client: bearer token presented after the handshake.
+    auth_token: Option<String>,
+    /// Server: how tokens are checked; `None` admits without one.
+    auth: Option<Verifier>,
+    /// Server: admit clients without a certificate on their token alone.
+    client_cert_optional: bool,
+    /// Server: fail the handshake of a client without a certificate before
+    /// the allowlist or token is looked at.
+    require_client_cert: bool,
+    /// Server: how long a connection waits for `cc_quic_server_decide`;
+    /// `None` admits without asking.
+    approval_timeout: Option<Duration>,
+    /// QUIC over TCP: the server also listens there, the client dials it
+    /// when UDP stays silent.
+    tcp_fallback: Option<Fallback>,
+    /// Started by the Rust APIs: events are kept as `api::Event`s in the
+    /// poll queue instead of serialized (see `poll.rs`).
+    typed_events: bool,
+}
+
+impl Default for WorkerOptions {
+    fn default() -> Self {
+        Self {
+            ecn: false,
+            udp_offload: true,
+            peer_cert: false,
+            keying_material_export: false,
+            cert_warn_days: certexpiry::DEFAULT_WARN_DAYS,
+            stats_interval_ms: 0,
+            shared_runtime: false,
+            server_workers: 1,
+            watchdog_ms: DEFAULT_WATCHDOG_MS,
+            event_schema: EventSchema::V1,
+            event_limits: EventLimits::default(),
+            dgram_recv_queue_len: DEFAULT_DGRAM_QUEUE_LEN,
+            dgram_drop_policy: DropPolicy::Front,
+            media_reorder_window: DEFAULT_REORDER_WINDOW,
+            jitter: None,
+            dual: DualChannelConfig {
+                media_rate_bps: 0,
+                control_backlog_max: DEFAULT_STREAM_WINDOW as usize,
+                datagrams_first: false,
+            },
+            recv_limits: RecvLimits::default(),
+            compression: CompressionConfig::default(),
+            flow_window: FlowWindow::default(),
+            max_udp_payload: DEFAULT_MAX_UDP_PAYLOAD,
+            keepalive_ms: 0,
+            reap: ReapPolicy::default(),
+            observed_addr: false,
+            coalesce: false,
+            cwnd_resume: None,
+            reverse_connect: None,
+            roster: false,
+            capabilities: 0,
+            writable_events: false,
+            auth_token: None,
+            auth: None,
+            client_cert_optional: false,
+            require_client_cert: false,
+            approval_timeout: None,
+            tcp_fallback: None,
+            typed_events: false,
+        }
+    }
 }
 
-impl CcQuicStatus {
-    const fn code(self) -> i32 {
-        self as i32
+impl WorkerOptions {
+    /// The options for a diagnostics bundle, without the auth token.
+    fn describe(&self) -> String {
+        let mut shown = self.clone();
+        if shown.auth_token.is_some() {
+            shown.auth_token = Some("..".to_string());
+        }
+        format!("{shown:?}")
+    }
+
+    fn socket_options(&self) -> SocketOptions {
+        SocketOptions {
+            ecn: self.ecn,
+            offload: self.udp_offload,
+            max_payload: self.max_udp_payload,
+        }
     }
 }
 
+// Events are built on the stack and serialized straight away, so the wide
+// `stats` variant is not worth boxing.
+#[allow(clippy::large_enum_variant)]
 #[derive(Serialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
-enum QuicEvent {
+enum QuicEvent<'a> {
     Connected {
         handle: u64,
         connection_id: String,
-        peer_fingerprint: String,
+        /// Null for a client admitted without a certificate.
+        peer_fingerprint: Option<String>,
+        /// Base64 DER, only with `cc_quic_config_set_peer_cert_export`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        peer_cert_der_base64: Option<String>,
+        /// Server side: the user the client's token authenticated.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        user: Option<String>,
+        /// Server side: the label of the client's allowlist entry.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        /// Server side: the SNI the client asked for.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        server_name: Option<String>,
     },
     Message {
         handle: u64,
         connection_id: String,
-        data_base64: String,
+        /// Set for data read on a named channel.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        channel: Option<&'a str>,
+        /// Set for a publication on a subscribed topic.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        topic: Option<&'a str>,
+        #[serde(rename = "data_base64", serialize_with = "serialize_base64")]
+        data: &'a [u8],
     },
     Closed {
         handle: u64,
         connection_id: String,
         reason: Option<String>,
     },
+    /// A media datagram released in sequence order by the reorder window.
+    Media {
+        handle: u64,
+        connection_id: String,
+        media_seq: u64,
+        timestamp: u32,
+        marker: bool,
+        #[serde(rename = "data_base64", serialize_with = "serialize_base64")]
+        data: &'a [u8],
+    },
     Error {
         handle: u64,
         connection_id: Option<String>,
         message: String,
     },
+    Stats {
+        handle: u64,
+        connection_id: String,
+        rtt_ms: f64,
+        cwnd: u64,
+        /// Current path MTU: the discovered size with PMTUD on, else the
+        /// configured ceiling once the peer accepts it.
+        pmtu: u64,
+        sent: u64,
+        recv: u64,
+        lost: u64,
+        retrans: u64,
+        sent_bytes: u64,
+        recv_bytes: u64,
+        ecn: EcnCounts,
+        dgram: DgramStats,
+        media: MediaStats,
+        control: ControlStats,
+        flow: FlowStats,
+        /// Whether sending was held back by the transport or by the app.
+        send_limit: SendLimitStats,
+        /// Payload bytes per traffic class (control, media, transfer).
+        traffic: TrafficStats,
+        /// Server only: the handle's blocked source ranges and what they
+        /// sent.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        blocklist: Option<BlocklistStats>,
+        /// Every path quiche tracks, while it tracks more than one.
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        paths: Vec<PathReport>,
+    },
+    /// The handshake reached `state` (see `handshake.rs`), `elapsed_ms`
+    /// after the connection started; stages can arrive together, and a
+    /// client's `confirmed` may follow `connected`.
+    HandshakeProgress {
+        handle: u64,
+        connection_id: String,
+        state: Stage,
+        elapsed_ms: u64,
+    },
+    /// The connection closed before it was announced; `cause` says why
+    /// (see `handshake.rs`). `closed` follows, if the worker keeps the
+    /// connection until then.
+    HandshakeFailed {
+        handle: u64,
+        connection_id: String,
+        #[serde(flatten)]
+        failure: Failure,
+    },
+    /// A client heard nothing back over UDP, so `connection_id` was given
+    /// up (its `handshake_failed` came first) and the next attempt goes
+    /// over `transport` to `addr`, with events under a new connection id.
+    TransportFallback {
+        handle: u64,
+        connection_id: String,
+        transport: &'static str,
+        addr: String,
+    },
+    /// Clock estimate after a timesync probe: `offset_us` is the peer's
+    /// wall clock minus ours, taken from the lowest-delay recent sample.
+    TimeSync {
+        handle: u64,
+        connection_id: String,
+        offset_us: i64,
+        rtt_us: u64,
+        dispersion_us: u64,
+        samples: usize,
+    },
+    /// Stream data went over a receive cap: the stream was reset (and, for
+    /// the connection cap, the connection closed) without forwarding it.
+    MessageTooLarge {
+        handle: u64,
+        connection_id: String,
+        stream_id: u64,
+        scope: LimitScope,
+        limit: u64,
+        size: u64,
+    },
+    /// `count` earlier events could not be posted to the Dart port.
+    EventsDropped { handle: u64, count: u64 },
+    /// Events held back by the handle's rate limits or error coalescing
+    /// since the last summary.
+    EventsSuppressed {
+        handle: u64,
+        #[serde(flatten)]
+        suppressed: Suppressed,
+    },
+    /// The worker driving `handle` panicked; an `error` event follows.
+    WorkerDied { handle: u64, message: String },
+    /// A worker has not finished a loop pass for `last_progress_ms`.
+    WorkerStalled { handle: u64, last_progress_ms: u64 },
+    /// A server socket of `handle` is bound to `addr`, with the port the OS
+    /// picked when port 0 was asked for. Posted once per listener, before
+    /// any connection event.
+    Listening {
+        handle: u64,
+        addr: String,
+        /// `tcp` for the TCP fallback listener; UDP otherwise.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        transport: Option<&'static str>,
+    },
+    /// The `which` certificate expires in under the configured warning
+    /// window; `days_left` goes negative once it has expired. Peer
+    /// warnings follow that connection's `connected`.
+    CertExpiringSoon {
+        handle: u64,
+        connection_id: Option<String>,
+        which: CertSide,
+        days_left: i64,
+    },
+    /// The peer of `connection_id` announced the certificate it presents
+    /// from its next handshake on. The announcement came over the session
+    /// authenticated as `old_fingerprint`; a server allowlisting that one
+    /// now accepts `new_fingerprint` too.
+    PeerIdentityRotated {
+        handle: u64,
+        connection_id: String,
+        old_fingerprint: String,
+        new_fingerprint: String,
+    },
+    /// A client started with an empty pin and `CC_QUIC_ALLOW_UNPINNED`
+    /// accepted the server on its CA alone; posted just before its
+    /// `connected`, with the fingerprint it could have pinned.
+    VerificationSkipped {
+        handle: u64,
+        connection_id: String,
+        peer_fingerprint: String,
+    },
+    /// The server reported the address it sees this client's packets come
+    /// from: the client's public NAT mapping when there is one.
+    ObservedAddress {
+        handle: u64,
+        connection_id: String,
+        addr: SocketAddr,
+    },
+    /// A named channel is ready on the connection; `by_peer` when the peer
+    /// opened it.
+    ChannelOpened {
+        handle: u64,
+        connection_id: String,
+        channel: String,
+        stream_id: u64,
+        by_peer: bool,
+    },
+    /// The peer closed a named channel.
+    ChannelClosed {
+        handle: u64,
+        connection_id: String,
+        channel: String,
+    },
+    /// The peer reset a stream, dropping what it had not delivered; a
+    /// channel on it ends, with `channel_closed` to follow.
+    StreamReset {
+        handle: u64,
+        connection_id: String,
+        stream_id: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        channel: Option<String>,
+        error_code: u64,
+    },
+    /// The peer asked us to stop sending on a channel's stream: what was
+    /// still queued is dropped and our sends on it fail, but what the peer
+    /// sends still arrives until the channel is closed.
+    StreamStopped {
+        handle: u64,
+        connection_id: String,
+        stream_id: u64,
+        channel: String,
+        error_code: u64,
+    },
+    /// A channel's stream takes `capacity` more bytes right now; posted
+    /// again once a send is cut short or the peer extends the window.
+    StreamWritable {
+        handle: u64,
+        connection_id: String,
+        stream_id: u64,
+        channel: String,
+        capacity: usize,
+    },
+    /// Who is connected to the server, posted on the server (without
+    /// `connection_id`) and on each client whenever it changes.
+    RosterChanged {
+        handle: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        connection_id: Option<String>,
+        peers: Vec<RosterPeer>,
+    },
+    /// Server side, callback verifier: judge `token` with
+    /// `cc_quic_server_auth_verdict`; the client waits for the answer.
+    AuthRequest {
+        handle: u64,
+        connection_id: String,
+        token: String,
+    },
+    /// Server side: the allowlist file changed and was read again.
+    AllowlistReloaded { handle: u64, entries: usize },
+    /// Both sides of `connection_id` exchanged the features they run;
+    /// `resolved` is what both have, for the lower of the two `version`s.
+    CapabilitiesNegotiated {
+        handle: u64,
+        connection_id: String,
+        version: u8,
+        local: Vec<&'static str>,
+        peer: Vec<&'static str>,
+        resolved: Vec<&'static str>,
+    },
+    /// Server side, with connection approval: accept or refuse the client
+    /// with `cc_quic_server_decide`.
+    ConnectionPending {
+        handle: u64,
+        connection_id: String,
+        fingerprint: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        remote_addr: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        server_name: Option<String>,
+    },
+    /// Server side: a client subscribed to (or unsubscribed from) a topic.
+    Subscription {
+        handle: u64,
+        connection_id: String,
+        topic: String,
+        subscribed: bool,
+    },
+    /// The client moved the connection to the endpoint the server named
+    /// with `cc_quic_server_set_preferred_address`.
+    ServerRelocated {
+        handle: u64,
+        connection_id: String,
+        addr: SocketAddr,
+    },
+    /// Client side: the connection dialed back for the server's
+    /// `cc_quic_server_request_connect`, or why there is none.
+    ReverseConnect {
+        handle: u64,
+        connection_id: String,
+        addr: SocketAddr,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dialed_handle: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// The server closed a connection over its configured limit: a
+    /// handshake older than `max_handshake_ms` or an established connection
+    /// older than `max_lifetime_ms`. `closed` follows once it drains.
+    ConnectionReaped {
+        handle: u64,
+        connection_id: String,
+        reason: ReapReason,
+        age_ms: u64,
+    },
 }
 
 #[derive(Debug)]
 enum WorkerCommand {
-    Send { conn_id: Vec<u8>, payload: Vec<u8> },
-    Close { conn_id: Option<Vec<u8>> },
+    Send {
+        conn_id: Vec<u8>,
+        payload: Vec<u8>,
+    },
+    /// Install the new certificate for later handshakes and announce its
+    /// fingerprint to every established peer.
+    RotateIdentity {
+        cert_path: String,
+        key_path: String,
+        fingerprint: Fingerprint,
+    },
+    Close {
+        conn_id: Option<Vec<u8>>,
+    },
+    Control {
+        conn_id: Vec<u8>,
+        payload: Vec<u8>,
+    },
+    Media {
+        conn_id: Vec<u8>,
+        timestamp: u32,
+        marker: bool,
+        payload: Vec<u8>,
+    },
+    MediaFec {
+        conn_id: Vec<u8>,
+        group: Option<(u8, u8)>,
+    },
+    MediaRedundancy {
+        conn_id: Vec<u8>,
+        copies: u32,
+        spread: Duration,
+    },
+    TimeSync {
+        conn_id: Vec<u8>,
+        interval: Option<Duration>,
+    },
+    RateLimit {
+        conn_id: Vec<u8>,
+        max_bps: Option<u64>,
+    },
+    Pacing {
+        conn_id: Vec<u8>,
+        pacing: SendPacing,
+    },
+    #[cfg(feature = "impairment")]
+    Impair {
+        conn_id: Vec<u8>,
+        config: Option<ImpairConfig>,
+    },
+    /// Start writing the connection's datagrams to `capture`, or stop.
+    Capture {
+        conn_id: Vec<u8>,
+        capture: Option<PacketCapture>,
+    },
+    Channel {
+        conn_id: Vec<u8>,
+        name: String,
+        op: ChannelOp,
+    },
+    StreamAbort {
+        conn_id: Vec<u8>,
+        stream_id: u64,
+        abort: Abort,
+        error_code: u64,
+    },
+    /// Steer clients to `addr`, one of the worker's bound addresses; `None`
+    /// stops announcing.
+    PreferredAddress {
+        addr: Option<SocketAddr>,
+    },
+    /// Server side: ask a reverse connect client to dial back.
+    ConnectRequest {
+        conn_id: Vec<u8>,
+        addr: Option<SocketAddr>,
+        pin: Option<[u8; 32]>,
+    },
+    /// Server side: Dart's answer to an `auth_request`.
+    AuthVerdict {
+        conn_id: Vec<u8>,
+        accept: bool,
+        user: Option<String>,
+    },
+    /// Server side: the app's answer to a `connection_pending`.
+    Decide {
+        conn_id: Vec<u8>,
+        accept: bool,
+    },
+    /// Server side: refuse `user` (or admit them again) and close their
+    /// open connections.
+    RevokeUser {
+        user: String,
+        revoked: bool,
+    },
+    /// Snapshot the worker's options and connections for
+    /// `cc_quic_diagnostics_dump`.
+    Diagnostics {
+        reply: mpsc::Sender<WorkerSnapshot>,
+    },
+    /// Client side: subscribe to (or unsubscribe from) a server topic.
+    Subscribe {
+        topic: String,
+        subscribe: bool,
+    },
+    /// Server side: queue `data` on every connection subscribed to `topic`.
+    Publish {
+        topic: String,
+        data: Arc<[u8]>,
+    },
+}
+
+impl WorkerCommand {
+    /// The connection the command is for, if it names one.
+    fn conn_id(&self) -> Option<&[u8]> {
+        match self {
+            WorkerCommand::Send { conn_id, .. }
+            | WorkerCommand::Control { conn_id, .. }
+            | WorkerCommand::Media { conn_id, .. }
+            | WorkerCommand::MediaFec { conn_id, .. }
+            | WorkerCommand::MediaRedundancy { conn_id, .. }
+            | WorkerCommand::TimeSync { conn_id, .. }
+            | WorkerCommand::RateLimit { conn_id, .. }
+            | WorkerCommand::Pacing { conn_id, .. }
+            | WorkerCommand::Capture { conn_id, .. }
+            | WorkerCommand::Channel { conn_id, .. }
+            | WorkerCommand::StreamAbort { conn_id, .. }
+            | WorkerCommand::AuthVerdict { conn_id, .. }
+            | WorkerCommand::Decide { conn_id, .. }
+            | WorkerCommand::ConnectRequest { conn_id, .. }
+            | WorkerCommand::Close {
+                conn_id: Some(conn_id),
+            } => Some(conn_id),
+            #[cfg(feature = "impairment")]
+            WorkerCommand::Impair { conn_id, .. } => Some(conn_id),
+            WorkerCommand::Close { conn_id: None }
+            | WorkerCommand::RotateIdentity { .. }
+            | WorkerCommand::PreferredAddress { .. }
+            | WorkerCommand::RevokeUser { .. }
+            | WorkerCommand::Diagnostics { .. }
+            | WorkerCommand::Subscribe { .. }
+            | WorkerCommand::Publish { .. } => None,
+        }
+    }
 }
 
 struct ConnectionHandle {
     tx: mpsc::Sender<WorkerCommand>,
+    threads: WorkerThreads,
+    events: Arc<EventSeq>,
+    /// Ids of the connections announced with `connected` and not yet
+    /// `closed`, with their exporter secrets; commands for any other id are
+    /// refused.
+    live: Mutex<HashMap<Vec<u8>, Arc<exporter::Secret>>>,
+    /// Bytes moved per peer fingerprint, for `cc_quic_usage_stats`.
+    usage: Mutex<UsageBook>,
+    /// `Client` or `Server`, for the calls only one side takes; never `Any`.
+    role: ConfigRole,
+    /// Source ranges a server drops unread; `None` for clients.
+    blocklist: Option<Arc<Blocklist>>,
+    /// Connected peers and their labels; `None` for clients.
+    roster: Option<Arc<Roster>>,
+    /// Trusted client fingerprints; `None` for clients.
+    allowlist: Option<Arc<Allowlist>>,
 }
 
-static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
-static CONNECTIONS: OnceCell<DashMap<u64, ConnectionHandle>> = OnceCell::new();
+/// Per-handle event sequence (starting at 1; 0 marks events for handles
+/// that are already gone), count of events the port refused, and the schema
+/// the embedder negotiated.
+#[derive(Default)]
+struct EventSeq {
+    next: AtomicU64,
+    dropped: AtomicU64,
+    schema: EventSchema,
+    /// Extra ports, each with the event classes it takes, sent matching
+    /// events after the worker's own: coalesced connects take all of them,
+    /// `cc_quic_subscribe` sinks what they asked for. One that refuses an
+    /// event is dropped.
+    mirrors: RwLock<Vec<(i64, u32)>>,
+    /// Rate limits and error coalescing, when configured.
+    gate: Option<Mutex<EventGate>>,
+    /// Recent events for `cc_quic_diagnostics_dump`.
+    history: EventHistory,
+    /// `cc_quic_conn_set_tag` tags by connection id; the one under `""`
+    /// tags the handle's events matching no other.
+    tags: RwLock<HashMap<String, u64>>,
+}
 
-#[no_mangle]
-pub extern "C" fn cc_quic_init_logging() -> i32 {
-    #[cfg(target_os = "android")]
-    {
-        use android_logger::Config;
-        use log::LevelFilter;
-        android_logger::init_once(
-            Config::default()
-                .with_max_level(LevelFilter::Info)
-                .with_tag("cribcall_quic"),
-        );
-        return CcQuicStatus::Ok.code();
+/// Event classes for `cc_quic_subscribe` masks.
+const EVENTS_CONNECTION: u32 = 1 << 0;
+const EVENTS_MESSAGE: u32 = 1 << 1;
+const EVENTS_MEDIA: u32 = 1 << 2;
+const EVENTS_CHANNEL: u32 = 1 << 3;
+const EVENTS_STATS: u32 = 1 << 4;
+const EVENTS_HEALTH: u32 = 1 << 5;
+const EVENTS_ALL: u32 = (1 << 6) - 1;
+
+impl EventSeq {
+    fn new(schema: EventSchema, limits: EventLimits) -> Self {
+        Self {
+            schema,
+            gate: EventGate::new(limits).map(Mutex::new),
+            ..Self::default()
+        }
     }
 
-    #[cfg(not(target_os = "android"))]
-    {
-        let env = env_logger::Env::default().default_filter_or("info");
-        let _ = env_logger::Builder::from_env(env)
-            .format_timestamp_millis()
-            .try_init();
-        return CcQuicStatus::Ok.code();
+    /// Whether `event` gets past the rate limits and error coalescing, and
+    /// the summary of what did not when one is due.
+    fn admit(&self, event: &QuicEvent<'_>) -> (bool, Option<Suppressed>) {
+        let Some(gate) = &self.gate else {
+            return (true, None);
+        };
+        // Lifecycle events and the markers are never held back.
+        let error = match event {
+            QuicEvent::Connected { .. }
+            | QuicEvent::Closed { .. }
+            | QuicEvent::WorkerDied { .. }
+            | QuicEvent::EventsDropped { .. }
+            | QuicEvent::EventsSuppressed { .. } => return (true, None),
+            QuicEvent::Error {
+                connection_id,
+                message,
+                ..
+            } => Some((connection_id.as_deref(), message.as_str())),
+            _ => None,
+        };
+        let class = event.class().trailing_zeros() as usize;
+        let now = Instant::now();
+        let mut gate = gate.lock().unwrap_or_else(PoisonError::into_inner);
+        if !gate.admit(class, error, now) {
+            return (false, None);
+        }
+        (true, gate.summary(now))
     }
-}
 
-#[no_mangle]
-pub extern "C" fn cc_quic_init_dart_api(post_cobject: *mut c_void) -> i32 {
-    if post_cobject.is_null() {
-        return CcQuicStatus::NullPointer.code();
+    fn next(&self) -> u64 {
+        self.next.fetch_add(1, Ordering::Relaxed) + 1
     }
-    // Safety: the pointer comes from Dart's NativeApi.postCObject.
-    unsafe {
-        allo_isolate::store_dart_post_cobject(std::mem::transmute(post_cobject));
+
+    fn set_tag(&self, connection_id: String, tag: u64) {
+        let mut tags = self.tags.write().unwrap_or_else(PoisonError::into_inner);
+        if tag == 0 {
+            tags.remove(&connection_id);
+        } else {
+            tags.insert(connection_id, tag);
+        }
     }
-    CcQuicStatus::Ok.code()
-}
 
-#[no_mangle]
-pub extern "C" fn cc_quic_version() -> *const c_char {
-    static VERSION: OnceCell<CString> = OnceCell::new();
-    VERSION
-        .get_or_init(|| {
-            CString::new(format!("cribcall-quic-rs/{}", env!("CARGO_PKG_VERSION")))
-                .expect("static version string")
-        })
-        .as_ptr()
-}
+    /// The tag `event` carries; a connection's is forgotten after its
+    /// `closed`.
+    fn tag(&self, event: &QuicEvent<'_>) -> Option<u64> {
+        let tags = self.tags.read().unwrap_or_else(PoisonError::into_inner);
+        if tags.is_empty() {
+            return None;
+        }
+        let own = event.connection_id().and_then(|id| tags.get(id));
+        let tag = own.or_else(|| tags.get("")).copied();
+        if let (QuicEvent::Closed { connection_id, .. }, Some(_)) = (event, own) {
+            let connection_id = connection_id.clone();
+            drop(tags);
+            self.set_tag(connection_id, 0);
+        }
+        tag
+    }
 
-#[no_mangle]
-pub extern "C" fn cc_quic_config_new(out_config: *mut *mut CcQuicConfig) -> i32 {
-    if out_config.is_null() {
-        return CcQuicStatus::NullPointer.code();
+    fn mirror_to(&self, port: i64) {
+        self.subscribe(port, EVENTS_ALL);
     }
 
-    let mut config = match quiche::Config::new(quiche::PROTOCOL_VERSION) {
-        Ok(cfg) => cfg,
-        Err(_) => return CcQuicStatus::ConfigError.code(),
-    };
+    /// Sends `port` the events in `mask` from now on, replacing its earlier
+    /// mask; an empty mask removes it.
+    fn subscribe(&self, port: i64, mask: u32) {
+        let mut mirrors = self.mirrors.write().unwrap_or_else(PoisonError::into_inner);
+        mirrors.retain(|(mirror, _)| *mirror != port);
+        if mask != 0 {
+            mirrors.push((port, mask));
+        }
+    }
 
-    if config.set_application_protos(&[CONTROL_ALPN]).is_err() {
-        return CcQuicStatus::InvalidAlpn.code();
+    /// Hands each mirror port taking `class` to `post`, forgetting those it
+    /// fails on.
+    fn post_mirrors(&self, class: u32, mut post: impl FnMut(i64) -> bool) {
+        let failed: Vec<i64> = {
+            let mirrors = self.mirrors.read().unwrap_or_else(PoisonError::into_inner);
+            mirrors
+                .iter()
+                .filter(|(_, mask)| mask & class != 0)
+                .map(|(port, _)| *port)
+                .filter(|port| !post(*port))
+                .collect()
+        };
+        if !failed.is_empty() {
+            self.mirrors
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .retain(|(port, _)| !failed.contains(port));
+        }
+    }
+}
+
+impl QuicEvent<'_> {
+    /// The connection this event is about, if any.
+    fn connection_id(&self) -> Option<&str> {
+        match self {
+            Self::Connected { connection_id, .. }
+            | Self::Message { connection_id, .. }
+            | Self::Media { connection_id, .. }
+            | Self::Closed { connection_id, .. }
+            | Self::Stats { connection_id, .. }
+            | Self::TimeSync { connection_id, .. }
+            | Self::HandshakeProgress { connection_id, .. }
+            | Self::HandshakeFailed { connection_id, .. }
+            | Self::TransportFallback { connection_id, .. }
+            | Self::MessageTooLarge { connection_id, .. }
+            | Self::PeerIdentityRotated { connection_id, .. }
+            | Self::VerificationSkipped { connection_id, .. }
+            | Self::ConnectionReaped { connection_id, .. }
+            | Self::ObservedAddress { connection_id, .. }
+            | Self::ServerRelocated { connection_id, .. }
+            | Self::ReverseConnect { connection_id, .. }
+            | Self::ChannelOpened { connection_id, .. }
+            | Self::ChannelClosed { connection_id, .. }
+            | Self::StreamReset { connection_id, .. }
+            | Self::StreamStopped { connection_id, .. }
+            | Self::StreamWritable { connection_id, .. }
+            | Self::Subscription { connection_id, .. }
+            | Self::AuthRequest { connection_id, .. }
+            | Self::ConnectionPending { connection_id, .. }
+            | Self::CapabilitiesNegotiated { connection_id, .. } => Some(connection_id),
+            Self::Error { connection_id, .. }
+            | Self::CertExpiringSoon { connection_id, .. }
+            | Self::RosterChanged { connection_id, .. } => connection_id.as_deref(),
+            Self::EventsDropped { .. }
+            | Self::EventsSuppressed { .. }
+            | Self::WorkerDied { .. }
+            | Self::WorkerStalled { .. }
+            | Self::Listening { .. }
+            | Self::AllowlistReloaded { .. } => None,
+        }
     }
 
-    config.verify_peer(true);
-    config.set_max_idle_timeout(DEFAULT_IDLE_TIMEOUT_MS);
-    config.set_max_recv_udp_payload_size(DEFAULT_MAX_UDP_PAYLOAD);
-    config.set_max_send_udp_payload_size(DEFAULT_MAX_UDP_PAYLOAD);
-    config.set_initial_max_data(DEFAULT_STREAM_WINDOW);
-    config.set_initial_max_stream_data_bidi_local(DEFAULT_STREAM_WINDOW);
-    config.set_initial_max_stream_data_bidi_remote(DEFAULT_STREAM_WINDOW);
-    config.set_initial_max_stream_data_uni(DEFAULT_STREAM_WINDOW);
-    config.set_initial_max_streams_bidi(8);
-    config.set_initial_max_streams_uni(4);
-    config.enable_dgram(true, 1024, 1024);
-    config.enable_pacing(true);
+    fn handle(&self) -> u64 {
+        match self {
+            Self::Connected { handle, .. }
+            | Self::Message { handle, .. }
+            | Self::Media { handle, .. }
+            | Self::Closed { handle, .. }
+            | Self::Error { handle, .. }
+            | Self::Stats { handle, .. }
+            | Self::TimeSync { handle, .. }
+            | Self::HandshakeProgress { handle, .. }
+            | Self::HandshakeFailed { handle, .. }
+            | Self::TransportFallback { handle, .. }
+            | Self::MessageTooLarge { handle, .. }
+            | Self::EventsDropped { handle, .. }
+            | Self::EventsSuppressed { handle, .. }
+            | Self::WorkerDied { handle, .. }
+            | Self::WorkerStalled { handle, .. }
+            | Self::Listening { handle, .. }
+            | Self::CertExpiringSoon { handle, .. }
+            | Self::PeerIdentityRotated { handle, .. }
+            | Self::VerificationSkipped { handle, .. }
+            | Self::ConnectionReaped { handle, .. }
+            | Self::ObservedAddress { handle, .. }
+            | Self::ServerRelocated { handle, .. }
+            | Self::ReverseConnect { handle, .. }
+            | Self::ChannelOpened { handle, .. }
+            | Self::ChannelClosed { handle, .. }
+            | Self::StreamReset { handle, .. }
+            | Self::StreamStopped { handle, .. }
+            | Self::StreamWritable { handle, .. }
+            | Self::Subscription { handle, .. }
+            | Self::RosterChanged { handle, .. }
+            | Self::AuthRequest { handle, .. }
+            | Self::ConnectionPending { handle, .. }
+            | Self::AllowlistReloaded { handle, .. }
+            | Self::CapabilitiesNegotiated { handle, .. } => *handle,
+        }
+    }
 
-    let handle = Box::new(CcQuicConfig { inner: config });
-    unsafe {
-        *out_config = Box::into_raw(handle);
+    /// The `cc_quic_subscribe` class this event belongs to.
+    fn class(&self) -> u32 {
+        match self {
+            Self::Connected { .. }
+            | Self::Closed { .. }
+            | Self::HandshakeProgress { .. }
+            | Self::HandshakeFailed { .. }
+            | Self::TransportFallback { .. }
+            | Self::Listening { .. }
+            | Self::PeerIdentityRotated { .. }
+            | Self::VerificationSkipped { .. }
+            | Self::ConnectionReaped { .. }
+            | Self::ObservedAddress { .. }
+            | Self::ServerRelocated { .. }
+            | Self::ReverseConnect { .. }
+            | Self::RosterChanged { .. }
+            | Self::AuthRequest { .. }
+            | Self::ConnectionPending { .. }
+            | Self::AllowlistReloaded { .. }
+            | Self::CapabilitiesNegotiated { .. } => EVENTS_CONNECTION,
+            Self::Message { .. } | Self::MessageTooLarge { .. } | Self::Subscription { .. } => {
+                EVENTS_MESSAGE
+            }
+            Self::Media { .. } => EVENTS_MEDIA,
+            Self::ChannelOpened { .. }
+            | Self::ChannelClosed { .. }
+            | Self::StreamReset { .. }
+            | Self::StreamStopped { .. }
+            | Self::StreamWritable { .. } => EVENTS_CHANNEL,
+            Self::Stats { .. } | Self::TimeSync { .. } => EVENTS_STATS,
+            Self::Error { .. }
+            | Self::EventsDropped { .. }
+            | Self::EventsSuppressed { .. }
+            | Self::WorkerDied { .. }
+            | Self::WorkerStalled { .. }
+            | Self::CertExpiringSoon { .. } => EVENTS_HEALTH,
+        }
     }
+}
 
-    CcQuicStatus::Ok.code()
+struct WorkerContext {
+    handle_id: u64,
+    dart_port: i64,
+    options: WorkerOptions,
+    rx: mpsc::Receiver<WorkerCommand>,
 }
 
-#[no_mangle]
-pub extern "C" fn cc_quic_config_free(config: *mut CcQuicConfig) {
-    if config.is_null() {
-        return;
+static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
+static CONNECTIONS: OnceCell<DashMap<u64, ConnectionHandle>> = OnceCell::new();
+/// Random per load of the library and carried above the counter in every
+/// handle, so a handle kept from an earlier load (the counter starts over
+/// after an app restart) is refused instead of reaching whichever worker
+/// got its number this time.
+static HANDLE_GENERATION: OnceCell<u64> = OnceCell::new();
+/// Handle bits below the generation.
+const HANDLE_COUNTER_BITS: u32 = 32;
+/// Generation bits; handles stay below 2^47, exact in JSON and in Dart
+/// compiled to JavaScript.
+const HANDLE_GENERATION_BITS: u32 = 15;
+
+fn handle_generation() -> u64 {
+    *HANDLE_GENERATION.get_or_init(|| OsRng.next_u64() % ((1 << HANDLE_GENERATION_BITS) - 1) + 1)
+}
+
+fn next_handle() -> u64 {
+    let counter = NEXT_HANDLE.fetch_add(1, Ordering::SeqCst) & u64::from(u32::MAX);
+    handle_generation() << HANDLE_COUNTER_BITS | counter
+}
+
+fn is_current_handle(handle: u64) -> bool {
+    handle >> HANDLE_COUNTER_BITS == handle_generation()
+}
+
+/// The live entry for `handle`, or `StaleHandle`.
+fn handle_entry(
+    handle: u64,
+) -> Result<dashmap::mapref::one::Ref<'static, u64, ConnectionHandle>, CcQuicStatus> {
+    if !is_current_handle(handle) {
+        return Err(CcQuicStatus::StaleHandle);
     }
+    CONNECTIONS
+        .get()
+        .and_then(|map| map.get(&handle))
+        .ok_or(CcQuicStatus::StaleHandle)
+}
 
-    unsafe {
-        drop(Box::from_raw(config));
+/// Hands `cmd` to `handle`'s worker. A command for one connection needs
+/// it announced and not yet closed.
+fn send_command(handle: u64, cmd: WorkerCommand) -> Result<(), Error> {
+    let entry = handle_entry(handle).map_err(|_| Error::StaleHandle)?;
+    if let Some(conn_id) = cmd.conn_id() {
+        let live = entry.live.lock().unwrap_or_else(PoisonError::into_inner);
+        if !live.contains_key(conn_id) {
+            return Err(Error::UnknownConnection);
+        }
     }
+    entry
+        .tx
+        .send(cmd)
+        .map_err(|_| Error::Internal("the worker has stopped".into()))
 }
 
-#[no_mangle]
-pub extern "C" fn cc_quic_client_connect(
-    config: *mut CcQuicConfig,
-    host: *const c_char,
-    port: u16,
-    server_name: *const c_char,
-    expected_server_fingerprint_hex: *const c_char,
-    cert_pem_path: *const c_char,
-    key_pem_path: *const c_char,
-    dart_port: i64,
-    out_handle: *mut u64,
-) -> i32 {
-    if config.is_null()
-        || host.is_null()
-        || server_name.is_null()
-        || expected_server_fingerprint_hex.is_null()
-        || cert_pem_path.is_null()
-        || key_pem_path.is_null()
-        || out_handle.is_null()
-    {
-        return CcQuicStatus::NullPointer.code();
+/// Closes every connection of `handle` and ends its worker. A coalesced
+/// connection stays up for its other holders.
+fn close_handle(handle: u64) -> Result<(), Error> {
+    if !is_current_handle(handle) {
+        return Err(Error::StaleHandle);
     }
+    let map = CONNECTIONS
+        .get()
+        .ok_or_else(|| Error::Internal("no handle was ever started".into()))?;
+    if coalesce::registry().release(handle) {
+        return Ok(());
+    }
+    if let Some(entry) = map.get(&handle) {
+        let _ = entry.tx.send(WorkerCommand::Close { conn_id: None });
+    }
+    Ok(())
+}
 
-    let host = match cstr_to_string(host) {
-        Ok(s) => s,
-        Err(code) => return code.code(),
-    };
-    let server_name = match cstr_to_string(server_name) {
-        Ok(s) => s,
-        Err(code) => return code.code(),
-    };
-    let expected_fp = match cstr_to_string(expected_server_fingerprint_hex) {
-        Ok(s) => s.to_lowercase(),
-        Err(code) => return code.code(),
-    };
-    let cert_path = match cstr_to_string(cert_pem_path) {
-        Ok(s) => s,
-        Err(code) => return code.code(),
+/// Starts writing `conn_id`'s datagrams to a pcapng file at `path`, up to
+/// `max_bytes` (0 for the default); `None` stops a running capture.
+fn start_capture(
+    handle: u64,
+    conn_id: Vec<u8>,
+    path: Option<&std::path::Path>,
+    max_bytes: u64,
+) -> Result<(), Error> {
+    let Some(path) = path else {
+        return send_command(
+            handle,
+            WorkerCommand::Capture {
+                conn_id,
+                capture: None,
+            },
+        );
     };
-    let key_path = match cstr_to_string(key_pem_path) {
-        Ok(s) => s,
-        Err(code) => return code.code(),
+    let max_bytes = if max_bytes == 0 {
+        capture::DEFAULT_MAX_BYTES
+    } else {
+        max_bytes
     };
+    let capture = PacketCapture::create(path, max_bytes)
+        .map_err(|err| Error::Config(format!("capture {}: {err}", path.display())))?;
+    let capture = Some(capture);
+    let sent = send_command(handle, WorkerCommand::Capture { conn_id, capture });
+    if sent.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    sent
+}
+
+/// Where `connect_client` dials and with which identity.
+struct ClientTarget {
+    host: String,
+    port: u16,
+    server_name: String,
+    /// Lowercase hex; empty accepts any server certificate the CA trusts,
+    /// which needs `allow_unpinned`.
+    expected_fp: String,
+    allow_unpinned: bool,
+    cert_path: String,
+    key_path: String,
+}
+
+/// Addresses as the C ABI lists them: `192.168.1.10:4433,[fe80::1%2]:4433`.
+fn addrs_csv(addrs: &[SocketAddr]) -> String {
+    addrs
+        .iter()
+        .map(SocketAddr::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The `ClientTarget::host` that dials `addr`, IPv6 in brackets.
+fn dial_host(addr: SocketAddr) -> String {
+    match addr {
+        SocketAddr::V4(v4) => v4.ip().to_string(),
+        SocketAddr::V6(v6) => format!("[{}]", v6.ip()),
+    }
+}
+
+/// Starts a client handle for `target`, from a copy of `template`.
+fn connect_client(
+    template: &mut CcQuicConfig,
+    target: ClientTarget,
+    dart_port: i64,
+    proxy: Option<Proxy>,
+) -> Result<u64, Error> {
+    let dial_back = template
+        .options
+        .reverse_connect
+        .is_some()
+        .then(|| Arc::new(DialBack::new(template, &target)));
+    let ClientTarget {
+        host,
+        port,
+        server_name,
+        expected_fp,
+        allow_unpinned,
+        cert_path,
+        key_path,
+    } = target;
     info!(
         "client connect host={host}:{port} server_name={server_name} expected_fp={}",
         short_hex(&expected_fp)
     );
 
-    let mut config = unsafe { Box::from_raw(config) }.inner;
-    if let Err(err) = config.load_cert_chain_from_pem_file(&cert_path) {
-        error!("load cert error: {err}");
-        return CcQuicStatus::CertLoadError.code();
+    if !template.role.allows(ConfigRole::Client) {
+        error!("server config passed to cc_quic_client_connect");
+        return Err(Error::WrongRole("a server config cannot connect".into()));
     }
-    if let Err(err) = config.load_priv_key_from_pem_file(&key_path) {
-        error!("load key error: {err}");
-        return CcQuicStatus::CertLoadError.code();
+    buffers::check_port(dart_port)?;
+    if expected_fp.is_empty() && !allow_unpinned {
+        return Err(Error::Config(
+            "an empty server fingerprint needs CC_QUIC_ALLOW_UNPINNED".into(),
+        ));
     }
-
-    let peer: SocketAddr = match format!("{host}:{port}").parse() {
-        Ok(addr) => addr,
-        Err(err) => {
-            error!("invalid peer addr: {err}");
-            return CcQuicStatus::SocketError.code();
+    // A proxied connection must not ride on a direct one.
+    if template.options.coalesce && !expected_fp.is_empty() && proxy.is_none() {
+        if let Some(shared) = join_shared(&expected_fp, dart_port) {
+            info!(
+                "client connect coalesced onto handle={} conn_id={}",
+                shared.handle, shared.connection_id
+            );
+            return Ok(shared.handle);
+        }
+    }
+    let mut config = template.quiche_config()?;
+    let mut options = template.options.clone();
+    if let Some(reverse) = options.reverse_connect.as_mut() {
+        reverse.dial = dial_back;
+    }
+    // Empty paths connect without a client certificate.
+    let local_not_after = match (cert_path.is_empty(), key_path.is_empty()) {
+        (true, true) => None,
+        (false, false) => {
+            load_identity(&mut config, &cert_path, &key_path)?;
+            check_local_cert(&cert_path)?
+        }
+        _ => {
+            return Err(Error::Config(
+                "a client certificate needs both a cert and a key path".into(),
+            ))
         }
     };
 
-    let socket = match UdpSocket::bind("0.0.0.0:0") {
-        Ok(s) => s,
-        Err(err) => {
-            error!("bind failed: {err}");
-            return CcQuicStatus::SocketError.code();
-        }
+    let loopback = template
+        .loopback
+        .take()
+        .map(|end| QuicSocket::memory(end, options.max_udp_payload));
+    let peer: SocketAddr = match loopback.as_ref().and_then(QuicSocket::memory_peer) {
+        Some(addr) => addr,
+        None => match format!("{host}:{port}").parse() {
+            Ok(addr) => addr,
+            Err(err) => {
+                error!("invalid peer addr: {err}");
+                return Err(Error::Config(format!(
+                    "peer address {host}:{port} is not ip:port"
+                )));
+            }
+        },
     };
-    socket
-        .connect(peer)
-        .map_err(|err| error!("connect error: {err}"))
-        .ok();
-    if socket.set_nonblocking(true).is_err() {
-        warn!("failed to set nonblocking on client socket");
-    }
 
     let (tx, rx) = mpsc::channel();
-    let handle_id = NEXT_HANDLE.fetch_add(1, Ordering::SeqCst);
+    let handle_id = next_handle();
 
-    CONNECTIONS
-        .get_or_init(DashMap::new)
-        .insert(handle_id, ConnectionHandle { tx });
+    let threads = WorkerThreads::default();
+    CONNECTIONS.get_or_init(DashMap::new).insert(
+        handle_id,
+        ConnectionHandle {
+            tx,
+            threads: Arc::clone(&threads),
+            events: Arc::new(EventSeq::new(options.event_schema, options.event_limits)),
+            live: Mutex::default(),
+            usage: Mutex::default(),
+            role: ConfigRole::Client,
+            blocklist: None,
+            roster: None,
+            allowlist: None,
+        },
+    );
+    if options.typed_events {
+        poll::hold_typed(handle_id);
+    }
+    post_cert_expiry(
+        handle_id,
+        dart_port,
+        None,
+        CertSide::Local,
+        local_not_after,
+        options.cert_warn_days,
+    );
 
-    thread::spawn(move || {
-        run_client_worker(
-            handle_id,
+    let socket_options = options.socket_options();
+    let shared_runtime = options.shared_runtime;
+    let ctx = WorkerContext {
+        handle_id,
+        dart_port,
+        options,
+        rx,
+    };
+
+    let direct = loopback.is_none() && proxy.is_none();
+    if shared_runtime && direct && ctx.options.tcp_fallback.is_none() {
+        let spec = runtime::ClientSpec {
+            ctx,
             config,
-            socket,
+            socket_options,
             peer,
             server_name,
             expected_fp,
-            dart_port,
-            rx,
-        );
-        if let Some(map) = CONNECTIONS.get() {
-            map.remove(&handle_id);
+        };
+        match runtime::register(spec) {
+            Ok(tid) => threads
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(tid),
+            Err(err) => {
+                error!("client runtime bind failed: {err}");
+                abandon_handle(handle_id);
+                return Err(Error::io("client runtime bind", err));
+            }
         }
-    });
+    } else {
+        let link = match (loopback, proxy) {
+            (Some(socket), _) => ClientLink::Socket(socket),
+            (None, Some(route)) => ClientLink::Proxy(route),
+            (None, None) => match QuicSocket::bind("0.0.0.0:0", socket_options) {
+                Ok(socket) => {
+                    socket
+                        .connect(peer)
+                        .map_err(|err| error!("connect error: {err}"))
+                        .ok();
+                    ClientLink::Socket(socket)
+                }
+                Err(err) => {
+                    error!("bind failed: {err}");
+                    abandon_handle(handle_id);
+                    return Err(Error::io("client bind", err));
+                }
+            },
+        };
 
-    unsafe {
-        *out_handle = handle_id;
+        let spawned =
+            threads::spawn_worker(format!("cc-quic-cli-{handle_id}"), &threads, move || {
+                run_guarded(handle_id, dart_port, || {
+                    run_client_worker(ctx, config, link, peer, server_name, expected_fp)
+                });
+                remove_handle(handle_id);
+            });
+        if let Err(err) = spawned {
+            error!("client worker spawn failed: {err}");
+            abandon_handle(handle_id);
+            return Err(Error::Internal(format!("client worker spawn: {err}")));
+        }
     }
 
-    CcQuicStatus::Ok.code()
+    Ok(handle_id)
 }
 
-#[no_mangle]
-pub extern "C" fn cc_quic_server_start(
-    config: *mut CcQuicConfig,
-    bind_addr: *const c_char,
-    port: u16,
-    cert_pem_path: *const c_char,
-    key_pem_path: *const c_char,
-    trusted_fingerprints_csv: *const c_char,
-    dart_port: i64,
-    out_handle: *mut u64,
-) -> i32 {
-    if config.is_null()
-        || bind_addr.is_null()
-        || cert_pem_path.is_null()
-        || key_pem_path.is_null()
-        || trusted_fingerprints_csv.is_null()
-        || out_handle.is_null()
-    {
-        return CcQuicStatus::NullPointer.code();
-    }
-
-    let bind_host = match cstr_to_string(bind_addr) {
-        Ok(s) => s,
-        Err(code) => return code.code(),
-    };
-    let cert_path = match cstr_to_string(cert_pem_path) {
-        Ok(s) => s,
-        Err(code) => return code.code(),
-    };
-    let key_path = match cstr_to_string(key_pem_path) {
-        Ok(s) => s,
-        Err(code) => return code.code(),
-    };
-
-    let trusted_allowlist = match cstr_to_string(trusted_fingerprints_csv) {
-        Ok(s) => parse_allowlist(&s),
-        Err(code) => return code.code(),
-    };
-
-    let local: SocketAddr = match format!("{bind_host}:{port}").parse() {
-        Ok(addr) => addr,
-        Err(err) => {
-            error!("invalid bind addr: {err}");
-            return CcQuicStatus::SocketError.code();
-        }
+/// Joins the established connection pinned to `fp`, if any: subscribes
+/// `dart_port` to its events and replays `connected` there.
+fn join_shared(fp: &str, dart_port: i64) -> Option<coalesce::Shared> {
+    let shared = coalesce::registry().join(fp)?;
+    let Some(events) = event_seq(shared.handle) else {
+        coalesce::registry().release(shared.handle);
+        return None;
     };
+    // A joiner on the worker's own port (both polling) already sees it all.
+    if dart_port != shared.dart_port {
+        EventEncoder::default().post_direct(
+            dart_port,
+            &QuicEvent::Connected {
+                handle: shared.handle,
+                connection_id: shared.connection_id.clone(),
+                peer_fingerprint: Some(shared.peer_fingerprint.clone()),
+                peer_cert_der_base64: None,
+                user: None,
+                label: None,
+                server_name: None,
+            },
+        );
+        events.mirror_to(dart_port);
+    }
+    Some(shared)
+}
 
-    let mut config = unsafe { Box::from_raw(config) }.inner;
-    if let Err(err) = config.load_cert_chain_from_pem_file(&cert_path) {
-        error!("load cert error: {err}");
-        return CcQuicStatus::CertLoadError.code();
+/// Starts a server handle listening on `locals`: one address is served by
+/// `server_workers` `SO_REUSEPORT` workers, several by one worker. Returns
+/// the handle and the addresses bound. `bound_room` is the space a caller
+/// has to write those back as `addrs_csv` plus a NUL; when it is too small
+/// the start fails before any worker runs.
+#[allow(clippy::too_many_arguments)]
+fn start_server(
+    template: &mut CcQuicConfig,
+    locals: &[SocketAddr],
+    cert_path: &str,
+    key_path: &str,
+    trusted_allowlist: HashMap<String, allowlist::Entry>,
+    dart_port: i64,
+    bound_room: Option<usize>,
+) -> Result<(u64, Vec<SocketAddr>), Error> {
+    if !template.role.allows(ConfigRole::Server) {
+        error!("client config passed to cc_quic_server_start");
+        return Err(Error::WrongRole("a client config cannot serve".into()));
+    }
+    buffers::check_port(dart_port)?;
+    if template.options.client_cert_optional && template.options.auth.is_none() {
+        return Err(Error::Config(
+            "optional client certificates need a token check".into(),
+        ));
     }
-    if let Err(err) = config.load_priv_key_from_pem_file(&key_path) {
-        error!("load key error: {err}");
-        return CcQuicStatus::CertLoadError.code();
+    if template.options.client_cert_optional && template.options.require_client_cert {
+        return Err(Error::Config(
+            "client certificates cannot be both optional and required".into(),
+        ));
+    }
+    let mut config = template.quiche_config()?;
+    let options = template.options.clone();
+    load_identity(&mut config, cert_path, key_path)?;
+    let local_not_after = check_local_cert(cert_path)?;
+    let mut routes = Vec::with_capacity(template.sni.len());
+    for identity in &template.sni {
+        let mut route_config = template.quiche_config()?;
+        load_identity(&mut route_config, &identity.cert_path, &identity.key_path)?;
+        check_local_cert(&identity.cert_path)?;
+        routes.push(sni::SniRoute {
+            server_name: identity.server_name.clone(),
+            config: Mutex::new(route_config),
+            allowlist: Allowlist::new(identity.trusted.clone()),
+        });
     }
 
-    let socket = match UdpSocket::bind(local) {
-        Ok(s) => s,
+    if locals.len() > 1 && options.server_workers > 1 {
+        error!("server_workers does not combine with several bind addresses");
+        return Err(Error::Config(
+            "server_workers does not combine with several bind addresses".into(),
+        ));
+    }
+    let socket_options = options.socket_options();
+    let tunnel_addr = options
+        .tcp_fallback
+        .filter(|_| template.loopback.is_none())
+        .map(|fallback| SocketAddr::new(locals[0].ip(), fallback.port));
+    let mut listeners: Vec<Vec<QuicSocket>> = match (locals, template.loopback.take()) {
+        (_, Some(end)) => vec![vec![QuicSocket::memory(end, options.max_udp_payload)]],
+        ([local], None) => {
+            match bind_server_sockets(*local, socket_options, options.server_workers) {
+                Ok(sockets) => sockets.into_iter().map(|socket| vec![socket]).collect(),
+                Err(err) => {
+                    error!("server bind failed: {err}");
+                    return Err(Error::io(format!("server bind {local}"), err));
+                }
+            }
+        }
+        (_, None) => match locals
+            .iter()
+            .map(|local| QuicSocket::bind(*local, socket_options))
+            .collect::<std::io::Result<Vec<_>>>()
+        {
+            Ok(sockets) => vec![sockets],
+            Err(err) => {
+                error!("server bind failed: {err}");
+                return Err(Error::io("server bind", err));
+            }
+        },
+    };
+    let bound = listeners[0]
+        .iter()
+        .map(QuicSocket::local_addr)
+        .collect::<std::io::Result<Vec<_>>>();
+    let bound = match bound {
+        Ok(bound) => bound,
         Err(err) => {
             error!("server bind failed: {err}");
-            return CcQuicStatus::SocketError.code();
+            return Err(Error::io("server local address", err));
         }
     };
+    let mut tunnel_bound = None;
+    if let Some(addr) = tunnel_addr {
+        let bound = TunnelListener::bind(addr).and_then(|listener| {
+            let local = listener.local_addr()?;
+            Ok((listener, local))
+        });
+        match bound {
+            Ok((listener, local)) => {
+                tunnel_bound = Some(local);
+                listeners[0].push(QuicSocket::tunnel_listener(
+                    listener,
+                    options.max_udp_payload,
+                ));
+            }
+            Err(err) => {
+                error!("server tcp fallback bind failed on {addr}: {err}");
+                return Err(Error::io(format!("server tcp fallback bind {addr}"), err));
+            }
+        }
+    }
+    let bound_csv = addrs_csv(&bound);
+    if let Some(room) = bound_room.filter(|room| bound_csv.len() >= *room) {
+        return Err(Error::Config(format!(
+            "bound addresses {bound_csv} do not fit in {room} bytes"
+        )));
+    }
     info!(
-        "server start bind={bind_host}:{port} trusted_allowlist={}",
+        "server start bind={bound_csv} tcp_fallback={tunnel_bound:?} workers={} trusted_allowlist={}",
+        listeners.len(),
         trusted_allowlist.len()
     );
-    if socket.set_nonblocking(true).is_err() {
-        warn!("failed to set nonblocking on server socket");
-    }
 
     let (tx, rx) = mpsc::channel();
-    let handle_id = NEXT_HANDLE.fetch_add(1, Ordering::SeqCst);
-
-    CONNECTIONS
-        .get_or_init(DashMap::new)
-        .insert(handle_id, ConnectionHandle { tx });
+    let handle_id = next_handle();
 
-    thread::spawn(move || {
-        run_server_worker(
-            handle_id,
-            config,
-            socket,
+    let threads = WorkerThreads::default();
+    // Shared so a rotation announced on one worker is trusted by all.
+    let trusted_allowlist = Arc::new(Allowlist::new(trusted_allowlist));
+    let blocklist = Arc::new(Blocklist::default());
+    let roster = Arc::new(Roster::default());
+    CONNECTIONS.get_or_init(DashMap::new).insert(
+        handle_id,
+        ConnectionHandle {
+            tx,
+            threads: Arc::clone(&threads),
+            events: Arc::new(EventSeq::new(options.event_schema, options.event_limits)),
+            live: Mutex::default(),
+            usage: Mutex::default(),
+            role: ConfigRole::Server,
+            blocklist: Some(Arc::clone(&blocklist)),
+            roster: Some(Arc::clone(&roster)),
+            allowlist: Some(Arc::clone(&trusted_allowlist)),
+        },
+    );
+    if options.typed_events {
+        poll::hold_typed(handle_id);
+    }
+    // Posted before the workers start so it precedes every connection event.
+    for addr in &bound {
+        post_event(
             dart_port,
-            trusted_allowlist,
-            rx,
+            QuicEvent::Listening {
+                handle: handle_id,
+                addr: addr.to_string(),
+                transport: None,
+            },
         );
-        if let Some(map) = CONNECTIONS.get() {
-            map.remove(&handle_id);
-        }
-    });
-
-    unsafe {
-        *out_handle = handle_id;
     }
+    if let Some(addr) = tunnel_bound {
+        post_event(
+            dart_port,
+            QuicEvent::Listening {
+                handle: handle_id,
+                addr: addr.to_string(),
+                transport: Some("tcp"),
+            },
+        );
+    }
+    post_cert_expiry(
+        handle_id,
+        dart_port,
+        None,
+        CertSide::Local,
+        local_not_after,
+        options.cert_warn_days,
+    );
 
-    CcQuicStatus::Ok.code()
-}
-
-#[no_mangle]
-pub extern "C" fn cc_quic_conn_send(
-    handle: u64,
-    conn_id_ptr: *const u8,
-    conn_id_len: usize,
-    data: *const u8,
-    data_len: usize,
-) -> i32 {
-    if data.is_null() || data_len == 0 {
-        return CcQuicStatus::NullPointer.code();
-    }
-    if conn_id_ptr.is_null() || conn_id_len == 0 {
-        return CcQuicStatus::NullPointer.code();
-    }
-    let slice = unsafe { std::slice::from_raw_parts(data, data_len) };
-    let payload = slice.to_vec();
-    let conn_id_raw = unsafe { std::slice::from_raw_parts(conn_id_ptr, conn_id_len) }.to_vec();
-    let conn_id_str = match String::from_utf8(conn_id_raw) {
-        Ok(s) => s,
-        Err(_) => return CcQuicStatus::Internal.code(),
-    };
-    let conn_id = match hex::decode(conn_id_str.trim()) {
-        Ok(bytes) => bytes,
-        Err(_) => return CcQuicStatus::Internal.code(),
+    let workers: Vec<(mpsc::Receiver<WorkerCommand>, Option<ServerRoute>)> = if listeners.len() == 1
+    {
+        vec![(rx, None)]
+    } else {
+        let (worker_txs, worker_rxs): (Vec<_>, Vec<_>) =
+            (0..listeners.len()).map(|_| mpsc::channel()).unzip();
+        let spawned = thread::Builder::new()
+            .name(format!("cc-quic-rte-{handle_id}"))
+            .spawn(move || route_server_commands(rx, worker_txs));
+        if let Err(err) = spawned {
+            error!("server router spawn failed: {err}");
+            abandon_handle(handle_id);
+            return Err(Error::Internal(format!("server router spawn: {err}")));
+        }
+        worker_rxs
+            .into_iter()
+            .zip(
+                ServerRoute::for_workers(listeners.len())
+                    .into_iter()
+                    .map(Some),
+            )
+            .collect()
     };
 
-    let map = match CONNECTIONS.get() {
-        Some(map) => map,
-        None => return CcQuicStatus::Internal.code(),
-    };
-    match map.get(&handle) {
-        Some(entry) => {
-            if entry
-                .tx
-                .send(WorkerCommand::Send {
-                    conn_id,
-                    payload,
-                })
-                .is_err()
-            {
-                return CcQuicStatus::Internal.code();
-            }
+    let identities = Arc::new(sni::ServerIdentities {
+        default: Mutex::new(config),
+        routes,
+    });
+    let remaining = Arc::new(AtomicUsize::new(listeners.len()));
+    for (sockets, (rx, route)) in listeners.into_iter().zip(workers) {
+        let ctx = WorkerContext {
+            handle_id,
+            dart_port,
+            options: options.clone(),
+            rx,
+        };
+        let identities = Arc::clone(&identities);
+        let trusted_allowlist = Arc::clone(&trusted_allowlist);
+        let blocklist = Arc::clone(&blocklist);
+        let roster = Arc::clone(&roster);
+        let remaining = Arc::clone(&remaining);
+        let spawned =
+            threads::spawn_worker(format!("cc-quic-srv-{handle_id}"), &threads, move || {
+                let survived = run_guarded(handle_id, dart_port, || {
+                    run_server_worker(
+                        ctx,
+                        identities,
+                        sockets,
+                        trusted_allowlist,
+                        blocklist,
+                        roster,
+                        route,
+                    )
+                });
+                // A dead worker takes the whole handle down; its siblings stop
+                // once their command channel disconnects.
+                if remaining.fetch_sub(1, Ordering::SeqCst) == 1 || !survived {
+                    remove_handle(handle_id);
+                }
+            });
+        if let Err(err) = spawned {
+            // Workers already running keep serving but lose their handle.
+            error!("server worker spawn failed: {err}");
+            abandon_handle(handle_id);
+            return Err(Error::Internal(format!("server worker spawn: {err}")));
         }
-        None => return CcQuicStatus::Internal.code(),
     }
 
-    CcQuicStatus::Ok.code()
-}
-
-#[no_mangle]
-pub extern "C" fn cc_quic_conn_close(handle: u64) -> i32 {
-    let map = match CONNECTIONS.get() {
-        Some(map) => map,
-        None => return CcQuicStatus::Internal.code(),
-    };
-    if let Some(entry) = map.get(&handle) {
-        let _ = entry.tx.send(WorkerCommand::Close { conn_id: None });
-    }
-    CcQuicStatus::Ok.code()
+    Ok((handle_id, bound))
 }
 
-fn run_client_worker(
+/// Per-connection client state, driven either by a dedicated worker thread or
+/// by the shared client runtime.
+struct ClientConnection {
     handle_id: u64,
-    mut config: quiche::Config,
-    socket: UdpSocket,
-    peer: SocketAddr,
-    server_name: String,
-    expected_fp: String,
     dart_port: i64,
+    options: WorkerOptions,
     rx: mpsc::Receiver<WorkerCommand>,
-) {
-    let start = Instant::now();
-    let local_addr = match socket.local_addr() {
-        Ok(addr) => addr,
-        Err(err) => {
-            post_event(
-                dart_port,
-                QuicEvent::Error {
-                    handle: handle_id,
-                    connection_id: None,
-                    message: format!("socket addr error: {err}"),
-                },
-            );
-            return;
-        }
-    };
-
-    let mut scid = [0u8; quiche::MAX_CONN_ID_LEN];
-    OsRng.fill_bytes(&mut scid);
-    let scid = quiche::ConnectionId::from_ref(&scid);
-    let conn_id_hex = hex_string(scid.as_ref());
-    info!(
-        "client {} connecting from {} to {} (server_name={} expected_fp={})",
-        handle_id,
-        local_addr,
-        peer,
-        server_name,
-        short_hex(&expected_fp)
-    );
-
-    let mut conn = match quiche::connect(
-        Some(&server_name),
-        &scid,
-        local_addr,
-        peer,
-        &mut config,
-    ) {
-        Ok(c) => c,
-        Err(err) => {
-            post_event(
-                dart_port,
-                QuicEvent::Error {
-                    handle: handle_id,
-                    connection_id: Some(conn_id_hex.clone()),
-                    message: format!("connect error: {err}"),
-                },
-            );
-            return;
-        }
-    };
+    conn: quiche::Connection,
+    scid: Vec<u8>,
+    conn_id_hex: String,
+    expected_fp: String,
+    exporter: Arc<exporter::Secret>,
+    started: Instant,
+    announced: bool,
+    ecn: EcnCounts,
+    dgrams: DgramInbox,
+    media: MediaChannel,
+    dual: DualChannel,
+    timesync: TimeSync,
+    identity: IdentityInbox,
+    capabilities: CapabilityExchange,
+    channels: Channels,
+    topics: Subscriber,
+    roster: RosterInbox,
+    auth: ClientAuth,
+    observed: ObservedInbox,
+    /// Last address the server reported, to post only changes.
+    observed_addr: Option<SocketAddr>,
+    relocation: Relocation,
+    send_cap: Option<TokenBucket>,
+    /// The `cc_quic_conn_set_rate_limit` rate and `_set_pacing` overrides
+    /// `send_cap` is built from.
+    send_rate: Option<u64>,
+    send_pacing: SendPacing,
+    impair: Option<Impairment>,
+    /// Packets held for quiche's pacing release time.
+    pacer: Pacer,
+    capture: Option<PacketCapture>,
+    recv_guard: RecvGuard,
+    control_codec: ControlCodec,
+    bdp: BdpEstimator,
+    send_limit: SendLimit,
+    handshake: HandshakeProgress,
+    traffic: TrafficMeter,
+    last_stats: Instant,
+    last_usage: Instant,
+    last_keepalive: Instant,
+}
 
-    let mut out = [0u8; MAX_DATAGRAM_SIZE];
-    let mut buf = [0u8; 65_536];
-    let mut announced = false;
+impl ClientConnection {
+    /// Starts the handshake; posts an `error` event and returns `None` on failure.
+    fn connect(
+        ctx: WorkerContext,
+        config: &mut quiche::Config,
+        local_addr: SocketAddr,
+        peer: SocketAddr,
+        server_name: &str,
+        expected_fp: String,
+    ) -> Option<Self> {
+        let WorkerContext {
+            handle_id,
+            dart_port,
+            options,
+            rx,
+        } = ctx;
+        let mut scid = [0u8; quiche::MAX_CONN_ID_LEN];
+        OsRng.fill_bytes(&mut scid);
+        let scid = quiche::ConnectionId::from_ref(&scid);
+        let conn_id_hex = hex_string(scid.as_ref());
+        info!(
+            "client {} connecting from {} to {} (server_name={} expected_fp={})",
+            handle_id,
+            local_addr,
+            peer,
+            server_name,
+            short_hex(&expected_fp)
+        );
 
-    loop {
-        while let Ok(cmd) = rx.try_recv() {
-            match cmd {
-                WorkerCommand::Send { conn_id, payload } => {
-                    if conn.is_established() && conn_id == scid.as_ref() {
-                        if let Err(err) = conn.stream_send(CONTROL_STREAM_ID, &payload, false) {
-                            if err != quiche::Error::Done {
-                                warn!("send error: {err:?}");
-                            }
-                        }
-                    }
-                }
-                WorkerCommand::Close { conn_id } => {
-                    if conn_id.is_none() || conn_id.as_deref() == Some(scid.as_ref()) {
-                        let _ = conn.close(false, 0x100, b"app close");
-                    }
-                }
+        if let Some(max_age) = options.cwnd_resume {
+            if let Some(packets) = sendlimit::resume_window(peer, max_age, options.max_udp_payload)
+            {
+                info!("client {handle_id} resuming a {packets}-packet window to {peer}");
+                config.set_initial_congestion_window_packets(packets);
             }
         }
-
-        match conn.send(&mut out) {
-            Ok((len, send_info)) => {
-                if let Err(err) = socket.send_to(&out[..len], send_info.to) {
-                    warn!("udp send error: {err}");
-                }
+        let mut conn = match quiche::connect(Some(server_name), &scid, local_addr, peer, config) {
+            Ok(c) => {
+                // `connection_id` in events is quiche's trace id, which
+                // prefixes its own log lines for the connection.
+                debug_assert_eq!(c.trace_id(), conn_id_hex);
+                c
             }
-            Err(quiche::Error::Done) => {}
             Err(err) => {
-                warn!(
-                    "client {} send loop error (established={}): {err}",
-                    conn_id_hex,
-                    conn.is_established()
-                );
                 post_event(
                     dart_port,
                     QuicEvent::Error {
                         handle: handle_id,
                         connection_id: Some(conn_id_hex.clone()),
-                        message: format!("quic send error: {err}"),
+                        message: format!("connect error: {err}"),
                     },
                 );
-                break;
+                return None;
             }
-        }
+        };
+        let exporter = exporter::attach(&mut conn);
+
+        Some(Self {
+            handle_id,
+            dart_port,
+            options,
+            rx,
+            conn,
+            scid: scid.to_vec(),
+            conn_id_hex,
+            expected_fp,
+            exporter,
+            started: Instant::now(),
+            announced: false,
+            ecn: EcnCounts::default(),
+            dgrams: DgramInbox::default(),
+            media: MediaChannel::new(),
+            dual: DualChannel::new(),
+            timesync: TimeSync::new(false),
+            identity: IdentityInbox::default(),
+            capabilities: CapabilityExchange::default(),
+            channels: Channels::new(false),
+            topics: Subscriber::default(),
+            roster: RosterInbox::default(),
+            auth: ClientAuth::default(),
+            observed: ObservedInbox::default(),
+            observed_addr: None,
+            relocation: Relocation::default(),
+            send_cap: None,
+            send_rate: None,
+            send_pacing: SendPacing::default(),
+            impair: None,
+            pacer: Pacer::default(),
+            capture: None,
+            recv_guard: RecvGuard::default(),
+            control_codec: ControlCodec::default(),
+            bdp: BdpEstimator::default(),
+            send_limit: SendLimit::new(Instant::now()),
+            handshake: HandshakeProgress::new(Instant::now()),
+            traffic: TrafficMeter::default(),
+            last_stats: Instant::now(),
+            last_usage: Instant::now(),
+            last_keepalive: Instant::now(),
+        })
+    }
 
-        match socket.recv_from(&mut buf) {
-            Ok((len, from)) => {
-                let recv_info = quiche::RecvInfo { from, to: local_addr };
-                if let Err(err) = conn.recv(&mut buf[..len], recv_info) {
-                    if err != quiche::Error::Done {
-                        warn!("recv error: {err:?}");
+    fn apply_commands(&mut self) {
+        while let Ok(cmd) = self.rx.try_recv() {
+            match cmd {
+                WorkerCommand::RotateIdentity { fingerprint, .. } => {
+                    if self.conn.is_established() {
+                        if let Err(err) = identity::announce(&mut self.conn, false, &fingerprint) {
+                            let trace_id = self.conn.trace_id();
+                            warn!("{trace_id} identity announce error: {err:?}");
+                        }
+                    }
+                }
+                // Compressed sends take the backlog below: a dropped tail
+                // would leave the encoder's framing ahead of the peer's.
+                WorkerCommand::Send { conn_id, payload }
+                    if !self.control_codec.compressing(&self.conn) =>
+                {
+                    if self.conn.is_established() && conn_id == self.scid {
+                        match self.conn.stream_send(CONTROL_STREAM_ID, &payload, false) {
+                            Ok(written) => self.traffic.sent(TrafficClass::Control, written),
+                            Err(quiche::Error::Done) => {}
+                            Err(err) => warn!("{} send error: {err:?}", self.conn.trace_id()),
+                        }
+                    }
+                }
+                WorkerCommand::Close { conn_id } => {
+                    if conn_id.is_none() || conn_id.as_deref() == Some(self.scid.as_slice()) {
+                        let _ = self.conn.close(false, 0x100, b"app close");
+                    }
+                }
+                WorkerCommand::Send { conn_id, payload }
+                | WorkerCommand::Control { conn_id, payload } => {
+                    if !self.conn.is_established() || conn_id != self.scid {
+                        continue;
+                    }
+                    let min_size = self.options.compression.min_size;
+                    let payload = self.control_codec.encode(&self.conn, payload, min_size);
+                    let len = payload.len();
+                    if self.dual.send_control(
+                        &mut self.conn,
+                        CONTROL_STREAM_ID,
+                        payload,
+                        &self.options.dual,
+                    ) {
+                        self.traffic.sent(TrafficClass::Control, len);
+                    } else {
+                        post_event(
+                            self.dart_port,
+                            QuicEvent::Error {
+                                handle: self.handle_id,
+                                connection_id: Some(self.conn_id_hex.clone()),
+                                message: CONTROL_BACKLOG_FULL.to_string(),
+                            },
+                        );
+                    }
+                }
+                WorkerCommand::Media {
+                    conn_id,
+                    timestamp,
+                    marker,
+                    payload,
+                } => {
+                    if self.conn.is_established() && conn_id == self.scid {
+                        let len = payload.len() + media::MEDIA_HEADER_LEN;
+                        if !self.dual.admit_media(
+                            &self.conn,
+                            len,
+                            &self.options.dual,
+                            Instant::now(),
+                        ) {
+                            self.media.paced_drop();
+                        } else if let Err(err) =
+                            self.media.send(&mut self.conn, timestamp, marker, &payload)
+                        {
+                            if err != quiche::Error::Done {
+                                warn!("{} media send error: {err:?}", self.conn.trace_id());
+                            }
+                        }
+                    }
+                }
+                WorkerCommand::MediaFec { conn_id, group } => {
+                    if conn_id == self.scid {
+                        self.media.set_fec(group);
+                    }
+                }
+                WorkerCommand::MediaRedundancy {
+                    conn_id,
+                    copies,
+                    spread,
+                } => {
+                    if conn_id == self.scid {
+                        self.media.set_redundancy(copies, spread);
+                    }
+                }
+                WorkerCommand::TimeSync { conn_id, interval } => {
+                    if conn_id == self.scid {
+                        self.timesync.set_interval(interval);
+                    }
+                }
+                WorkerCommand::RateLimit { conn_id, max_bps } => {
+                    if conn_id == self.scid {
+                        self.send_rate = max_bps;
+                        self.send_cap = self.send_pacing.cap(max_bps);
+                    }
+                }
+                WorkerCommand::Pacing { conn_id, pacing } => {
+                    if conn_id == self.scid {
+                        self.send_pacing = pacing;
+                        self.send_cap = pacing.cap(self.send_rate);
+                    }
+                }
+                #[cfg(feature = "impairment")]
+                WorkerCommand::Impair { conn_id, config } => {
+                    if conn_id == self.scid {
+                        self.impair = config.map(Impairment::new);
+                    }
+                }
+                WorkerCommand::Capture { conn_id, capture } => {
+                    if conn_id == self.scid {
+                        self.capture = capture;
+                    }
+                }
+                WorkerCommand::Channel { conn_id, name, op } => {
+                    if conn_id != self.scid || !self.conn.is_established() {
+                        continue;
+                    }
+                    match self.channels.apply(&mut self.conn, &name, op) {
+                        Ok(written) => self.traffic.sent(TrafficClass::Transfer, written),
+                        Err(message) => post_event(
+                            self.dart_port,
+                            QuicEvent::Error {
+                                handle: self.handle_id,
+                                connection_id: Some(self.conn_id_hex.clone()),
+                                message,
+                            },
+                        ),
+                    }
+                }
+                WorkerCommand::StreamAbort {
+                    conn_id,
+                    stream_id,
+                    abort,
+                    error_code,
+                } => {
+                    if conn_id != self.scid {
+                        continue;
+                    }
+                    if let Err(message) =
+                        self.channels
+                            .abort(&mut self.conn, stream_id, abort, error_code)
+                    {
+                        post_event(
+                            self.dart_port,
+                            QuicEvent::Error {
+                                handle: self.handle_id,
+                                connection_id: Some(self.conn_id_hex.clone()),
+                                message,
+                            },
+                        );
                     }
                 }
+                WorkerCommand::Subscribe { topic, subscribe } => {
+                    self.topics.change(&topic, subscribe);
+                }
+                WorkerCommand::PreferredAddress { .. }
+                | WorkerCommand::ConnectRequest { .. }
+                | WorkerCommand::Publish { .. }
+                | WorkerCommand::AuthVerdict { .. }
+                | WorkerCommand::Decide { .. }
+                | WorkerCommand::RevokeUser { .. } => {}
+                WorkerCommand::Diagnostics { reply } => {
+                    let _ = reply.send(WorkerSnapshot {
+                        options: self.options.describe(),
+                        connections: vec![ConnectionSnapshot::new(
+                            self.conn_id_hex.clone(),
+                            &self.conn,
+                            self.started.elapsed(),
+                        )],
+                    });
+                }
             }
-            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {}
-            Err(err) => {
-                warn!("client udp recv error: {err}");
-                break;
+        }
+    }
+
+    /// The server address datagrams are expected from: the relocation
+    /// target while one is probed, else the active path's peer.
+    fn socket_peer(&self) -> Option<SocketAddr> {
+        self.relocation.target().or_else(|| {
+            self.conn
+                .path_stats()
+                .find(|path| path.active)
+                .map(|path| path.peer_addr)
+        })
+    }
+
+    /// Queues outgoing packets into `batch`; posts an `error` event and
+    /// returns false if quiche reports a fatal send error.
+    fn flush(&mut self, socket: &QuicSocket, batch: &mut SendBatch) -> bool {
+        let mut sent = self.drain(socket, batch);
+        // Control held back for queued datagrams goes out once they have.
+        if sent.is_ok() && self.options.dual.datagrams_first && self.dual.backlogged() {
+            self.dual
+                .flush_control(&mut self.conn, CONTROL_STREAM_ID, &self.options.dual);
+            sent = self.drain(socket, batch);
+        }
+        let Err(err) = sent else {
+            let blocked = sendlimit::blocked(&self.conn, self.dual.backlogged());
+            self.send_limit.sample(Instant::now(), blocked);
+            return true;
+        };
+        warn!(
+            "client {} send loop error (established={}): {err}",
+            self.conn_id_hex,
+            self.conn.is_established()
+        );
+        post_event(
+            self.dart_port,
+            QuicEvent::Error {
+                handle: self.handle_id,
+                connection_id: Some(self.conn_id_hex.clone()),
+                message: format!("quic send error: {err}"),
+            },
+        );
+        false
+    }
+
+    fn drain(&mut self, socket: &QuicSocket, batch: &mut SendBatch) -> Result<(), quiche::Error> {
+        drain_send(
+            &mut self.conn,
+            socket,
+            batch,
+            None,
+            self.send_cap.as_mut(),
+            self.impair.as_mut(),
+            &mut self.pacer,
+            &mut self.capture,
+        )
+    }
+
+    fn recv(&mut self, data: &mut [u8], meta: RecvMeta, local_addr: SocketAddr) {
+        self.ecn.record(meta.ecn);
+        let recv_info = quiche::RecvInfo {
+            from: meta.from,
+            to: local_addr,
+        };
+        self.handshake.on_datagram(&self.conn, data);
+        capture::record(&mut self.capture, meta.from, local_addr, data);
+        if let Err(err) = self.conn.recv(data, recv_info) {
+            if err != quiche::Error::Done {
+                warn!("{} recv error: {err:?}", self.conn.trace_id());
+                self.handshake.on_recv_error(err);
             }
         }
+    }
 
-        if conn.is_established() && !announced {
-            announced = true;
-            let peer_fp = match conn.peer_cert() {
+    /// Announces the handshake, forwards stream data and stats, and reports
+    /// closure. Returns false once the connection is finished.
+    fn poll(&mut self, scratch: &mut Scratch) -> bool {
+        post_handshake_progress(
+            self.handle_id,
+            self.dart_port,
+            &self.conn_id_hex,
+            self.handshake.advance(&self.conn),
+        );
+        let admitted = if self.conn.is_established() && !self.announced {
+            let peer_fp = match self.conn.peer_cert() {
                 Some(cert) => sha256_hex(cert),
                 None => String::new(),
             };
-            if !expected_fp.is_empty() && peer_fp.to_lowercase() != expected_fp {
+            if !self.expected_fp.is_empty() && peer_fp.to_lowercase() != self.expected_fp {
                 warn!(
                     "client {} fingerprint mismatch: expected {} got {}",
-                    conn_id_hex,
-                    short_hex(&expected_fp),
+                    self.conn_id_hex,
+                    short_hex(&self.expected_fp),
                     short_hex(&peer_fp)
                 );
-                let _ = conn.close(false, 0x102, b"fingerprint mismatch");
+                let _ = self
+                    .conn
+                    .close(false, handshake::PIN_MISMATCH, b"fingerprint mismatch");
+                audit_conn(
+                    self.handle_id,
+                    "client",
+                    Outcome::Rejected,
+                    &self.conn_id_hex,
+                    &self.conn,
+                    Some("fingerprint mismatch"),
+                );
+                post_handshake_failed(
+                    self.handle_id,
+                    self.dart_port,
+                    &self.conn_id_hex,
+                    &self.handshake,
+                    &self.conn,
+                );
                 post_event(
-                    dart_port,
+                    self.dart_port,
                     QuicEvent::Error {
-                        handle: handle_id,
-                        connection_id: Some(conn_id_hex.clone()),
+                        handle: self.handle_id,
+                        connection_id: Some(self.conn_id_hex.clone()),
                         message: "server fingerprint mismatch".to_string(),
                     },
                 );
-                break;
+                return false;
+            }
+            // The token only goes out once the server's pin checked out.
+            let verdict = match &self.options.auth_token {
+                Some(token) => self.auth.poll(&mut self.conn, token),
+                None => Verdict::Accepted,
+            };
+            match verdict {
+                Verdict::Accepted => Some(peer_fp),
+                Verdict::Pending => None,
+                Verdict::Rejected => {
+                    warn!("client {} token rejected", self.conn_id_hex);
+                    post_event(
+                        self.dart_port,
+                        QuicEvent::Error {
+                            handle: self.handle_id,
+                            connection_id: Some(self.conn_id_hex.clone()),
+                            message: "authentication rejected by server".to_string(),
+                        },
+                    );
+                    None
+                }
             }
+        } else {
+            None
+        };
+        if let Some(peer_fp) = admitted {
+            self.announced = true;
             info!(
                 "client connected conn_id={} peer_fp={}",
-                conn_id_hex,
+                self.conn_id_hex,
                 short_hex(&peer_fp)
             );
+            set_conn_live(self.handle_id, &self.scid, Some(&self.exporter));
+            record_usage(self.handle_id, &self.scid, &self.conn, false);
+            audit_conn(
+                self.handle_id,
+                "client",
+                Outcome::Accepted,
+                &self.conn_id_hex,
+                &self.conn,
+                None,
+            );
+            if self.expected_fp.is_empty() {
+                warn!(
+                    "client {} connected without a pin: fp={}",
+                    self.conn_id_hex,
+                    short_hex(&peer_fp)
+                );
+                post_event(
+                    self.dart_port,
+                    QuicEvent::VerificationSkipped {
+                        handle: self.handle_id,
+                        connection_id: self.conn_id_hex.clone(),
+                        peer_fingerprint: peer_fp.clone(),
+                    },
+                );
+            }
             post_event(
-                dart_port,
+                self.dart_port,
                 QuicEvent::Connected {
-                    handle: handle_id,
-                    connection_id: conn_id_hex.clone(),
-                    peer_fingerprint: peer_fp,
+                    handle: self.handle_id,
+                    connection_id: self.conn_id_hex.clone(),
+                    peer_fingerprint: Some(peer_fp.clone()),
+                    peer_cert_der_base64: exported_cert(&self.conn, &self.options),
+                    user: None,
+                    label: None,
+                    server_name: None,
                 },
             );
+            if self.options.coalesce && !self.expected_fp.is_empty() {
+                coalesce::registry().publish(
+                    &self.expected_fp,
+                    coalesce::Shared {
+                        handle: self.handle_id,
+                        dart_port: self.dart_port,
+                        connection_id: self.conn_id_hex.clone(),
+                        peer_fingerprint: peer_fp,
+                    },
+                );
+            }
+            post_cert_expiry(
+                self.handle_id,
+                self.dart_port,
+                Some(self.conn_id_hex.clone()),
+                CertSide::Peer,
+                self.conn.peer_cert().and_then(certexpiry::not_after),
+                self.options.cert_warn_days,
+            );
         }
 
-        for stream_id in conn.readable() {
+        let mut app_buf = scratch.pool.take();
+        let mut clock = Vec::new();
+        let mut rotated = Vec::new();
+        let mut reports = Vec::new();
+        let mut notices = Vec::new();
+        let mut publications = Vec::new();
+        let mut roster_update = None;
+        // Before reading, so a FIN behind the STOP_SENDING is not taken
+        // for a plain close.
+        self.channels
+            .poll_writable(&mut self.conn, self.options.writable_events, &mut notices);
+        for stream_id in self.conn.readable() {
             loop {
-                let mut app_buf = [0u8; 65535];
-                match conn.stream_recv(stream_id, &mut app_buf) {
-                    Ok((read, _fin)) => {
-                        let data = &app_buf[..read];
-                        post_event(
-                            dart_port,
-                            QuicEvent::Message {
-                                handle: handle_id,
-                                connection_id: conn_id_hex.clone(),
-                                data_base64: BASE64.encode(data),
-                            },
+                match self.conn.stream_recv(stream_id, &mut app_buf) {
+                    Ok((read, _fin)) if timesync::is_timesync_stream(stream_id) => {
+                        self.timesync.on_data(
+                            &mut self.conn,
+                            stream_id,
+                            &app_buf[..read],
+                            &mut clock,
                         );
                     }
-                    Err(quiche::Error::Done) => break,
-                    Err(err) => {
-                        warn!("stream read error: {err:?}");
-                        break;
+                    Ok((read, _fin)) if identity::is_identity_stream(stream_id) => {
+                        self.identity.on_data(&app_buf[..read], &mut rotated);
                     }
-                }
-            }
-        }
-
-        if conn.is_closed() {
-            let reason = conn.peer_error().map(|err| format!("{err:?}"));
+                    Ok((read, _fin)) if observed::is_observed_stream(stream_id) => {
+                        self.observed.on_data(&app_buf[..read], &mut reports);
+                    }
+                    Ok((read, _fin)) if capabilities::is_capability_stream(stream_id) => {
+                        self.capabilities.on_data(&app_buf[..read]);
+                    }
+                    Ok((read, _fin)) if topics::is_topic_stream(stream_id) => {
+                        self.traffic.received(TrafficClass::Transfer, read);
+                        self.topics.on_data(&app_buf[..read], &mut publications);
+                    }
+                    // Read by the gate before the connection is announced.
+                    Ok(_) if auth::is_auth_stream(stream_id) => {}
+                    Ok((read, _fin)) if roster::is_roster_stream(stream_id) => {
+                        if let Some(peers) = self.roster.on_data(&app_buf[..read]) {
+                            roster_update = Some(peers);
+                        }
+                    }
+                    Ok((read, fin)) => {
+                        self.traffic
+                            .received(TrafficClass::of_stream(stream_id, CONTROL_STREAM_ID), read);
+                        let skip = if channels::is_channel_stream(stream_id) {
+                            self.channels.on_data(
+                                &mut self.conn,
+                                stream_id,
+                                &app_buf[..read],
+                                &mut notices,
+                            )
+                        } else {
+                            0
+                        };
+                        let body = &app_buf[skip..read];
+                        let (raw, violation) = match self.recv_guard.check(
+                            stream_id,
+                            body,
+                            fin,
+                            &self.options.recv_limits,
+                        ) {
+                            Ok(()) => (body, None),
+                            Err(v) => (&body[..v.forward], Some(v)),
+                        };
+                        let data = match self.control_codec.decode(
+                            &self.conn,
+                            stream_id,
+                            raw,
+                            inflate_limit(&self.options),
+                        ) {
+                            Ok(data) => data,
+                            Err(err) => {
+                                reject_inflate(
+                                    self.handle_id,
+                                    self.dart_port,
+                                    &self.conn_id_hex,
+                                    &mut self.conn,
+                                    stream_id,
+                                    err,
+                                    inflate_limit(&self.options),
+                                );
+                                break;
+                            }
+                        };
+                        if !data.is_empty() {
+                            scratch.events.post(
+                                self.dart_port,
+                                &QuicEvent::Message {
+                                    handle: self.handle_id,
+                                    connection_id: self.conn_id_hex.clone(),
+                                    channel: self.channels.name(stream_id),
+                                    topic: None,
+                                    data: &data,
+                                },
+                            );
+                        }
+                        if fin && channels::is_channel_stream(stream_id) {
+                            self.channels
+                                .on_fin(&mut self.conn, stream_id, &mut notices);
+                        }
+                        if let Some(violation) = violation {
+                            reject_oversized(
+                                self.handle_id,
+                                self.dart_port,
+                                &self.conn_id_hex,
+                                &mut self.conn,
+                                stream_id,
+                                violation,
+                            );
+                            break;
+                        }
+                    }
+                    Err(quiche::Error::Done) => break,
+                    Err(quiche::Error::StreamReset(error_code)) => {
+                        self.channels
+                            .on_reset(&mut self.conn, stream_id, error_code, &mut notices);
+                        break;
+                    }
+                    Err(err) => {
+                        warn!("{} stream read error: {err:?}", self.conn.trace_id());
+                        break;
+                    }
+                }
+            }
+        }
+        scratch.pool.give(app_buf);
+        post_timesync(self.handle_id, self.dart_port, &self.conn_id_hex, &clock);
+        post_rotations(
+            self.handle_id,
+            self.dart_port,
+            &self.conn_id_hex,
+            &self.conn,
+            rotated,
+            None,
+        );
+        post_channel_notices(self.handle_id, self.dart_port, &self.conn_id_hex, notices);
+        if self.options.capabilities != 0 && self.announced {
+            let local = capabilities::available(self.options.capabilities, &self.conn);
+            if let Some(negotiated) = self.capabilities.poll(&mut self.conn, false, local) {
+                post_capabilities(
+                    self.handle_id,
+                    self.dart_port,
+                    &self.conn_id_hex,
+                    negotiated,
+                );
+            }
+        }
+        for publication in &publications {
+            scratch.events.post(
+                self.dart_port,
+                &QuicEvent::Message {
+                    handle: self.handle_id,
+                    connection_id: self.conn_id_hex.clone(),
+                    channel: None,
+                    topic: Some(&publication.topic),
+                    data: &publication.data,
+                },
+            );
+        }
+        let sent = self.topics.flush(&mut self.conn);
+        self.traffic.sent(TrafficClass::Transfer, sent);
+        if let Some(peers) = roster_update {
+            post_event(
+                self.dart_port,
+                QuicEvent::RosterChanged {
+                    handle: self.handle_id,
+                    connection_id: Some(self.conn_id_hex.clone()),
+                    peers,
+                },
+            );
+        }
+        let observed = reports.iter().rev().find_map(|record| match record {
+            AddressRecord::Observed(addr) => Some(*addr),
+            AddressRecord::Preferred(_) | AddressRecord::Connect { .. } => None,
+        });
+        if let Some(addr) = observed {
+            if self.observed_addr != Some(addr) {
+                self.observed_addr = Some(addr);
+                post_event(
+                    self.dart_port,
+                    QuicEvent::ObservedAddress {
+                        handle: self.handle_id,
+                        connection_id: self.conn_id_hex.clone(),
+                        addr,
+                    },
+                );
+            }
+        }
+        let peer = self
+            .conn
+            .path_stats()
+            .find(|path| path.active)
+            .map(|path| path.peer_addr);
+        for record in &reports {
+            match *record {
+                AddressRecord::Preferred(addr) => self.relocation.prefer(addr, peer),
+                AddressRecord::Connect { addr, pin } => self.dial_back(addr, pin, peer),
+                AddressRecord::Observed(_) => {}
+            }
+        }
+        match self.relocation.drive(&mut self.conn) {
+            Some(relocate::Outcome::Moved(addr)) => {
+                info!("client {} relocated to {addr}", self.conn_id_hex);
+                post_event(
+                    self.dart_port,
+                    QuicEvent::ServerRelocated {
+                        handle: self.handle_id,
+                        connection_id: self.conn_id_hex.clone(),
+                        addr,
+                    },
+                );
+            }
+            Some(relocate::Outcome::Failed(addr, message)) => {
+                warn!(
+                    "client {} relocation to {addr}: {message}",
+                    self.conn_id_hex
+                );
+                post_event(
+                    self.dart_port,
+                    QuicEvent::Error {
+                        handle: self.handle_id,
+                        connection_id: Some(self.conn_id_hex.clone()),
+                        message: format!("relocation to {addr}: {message}"),
+                    },
+                );
+            }
+            None => {}
+        }
+        self.dual
+            .flush_control(&mut self.conn, CONTROL_STREAM_ID, &self.options.dual);
+        self.timesync.on_timer(&mut self.conn, Instant::now());
+        keepalive(&self.options, &mut self.conn, &mut self.last_keepalive);
+
+        let (dgram_max, dgram_policy) = (
+            self.options.dgram_recv_queue_len,
+            self.options.dgram_drop_policy,
+        );
+        self.dgrams.pull(&mut self.conn, dgram_max, dgram_policy);
+        self.media.send_due(&mut self.conn, Instant::now());
+        deliver_media(
+            self.handle_id,
+            self.dart_port,
+            &self.conn_id_hex,
+            &mut self.dgrams,
+            &mut self.media,
+            &self.options,
+            &mut scratch.events,
+        );
+
+        if stats_due(&self.options, self.announced, &mut self.last_stats) {
+            let dgram = self.dgrams.stats(&self.conn, dgram_max, dgram_policy);
+            let local = LocalStats {
+                ecn: self.ecn,
+                dgram,
+                media: self.media.stats(),
+                control: self.dual.stats(&self.options.dual),
+                flow: self.bdp.stats(&self.conn, &self.options.flow_window),
+                send_limit: self.send_limit.stats(Instant::now()),
+                traffic: self
+                    .traffic
+                    .stats(self.media.sent_bytes(), self.dgrams.received_bytes()),
+                blocklist: None,
+            };
+            let event = stats_event(self.handle_id, &self.conn_id_hex, &self.conn, local);
+            post_event(self.dart_port, event);
+        }
+        if self.announced && self.last_usage.elapsed() >= usage::PUBLISH_INTERVAL {
+            self.last_usage = Instant::now();
+            record_usage(self.handle_id, &self.scid, &self.conn, false);
+        }
+
+        if self.conn.is_closed() {
+            let reason = self.conn.peer_error().map(|err| format!("{err:?}"));
             info!(
                 "client connection {} closed established={} ({:?}) stats={}",
-                conn_id_hex,
-                conn.is_established(),
+                self.conn_id_hex,
+                self.conn.is_established(),
                 reason,
-                format_stats(&conn.stats())
+                format_stats(&self.conn.stats())
+            );
+            set_conn_live(self.handle_id, &self.scid, None);
+            if !self.announced {
+                post_handshake_failed(
+                    self.handle_id,
+                    self.dart_port,
+                    &self.conn_id_hex,
+                    &self.handshake,
+                    &self.conn,
+                );
+            }
+            coalesce::registry().withdraw(self.handle_id);
+            if self.options.cwnd_resume.is_some() {
+                sendlimit::remember(&self.conn);
+            }
+            record_usage(self.handle_id, &self.scid, &self.conn, true);
+            audit_conn(
+                self.handle_id,
+                "client",
+                Outcome::Closed,
+                &self.conn_id_hex,
+                &self.conn,
+                reason.as_deref(),
             );
             post_event(
-                dart_port,
+                self.dart_port,
                 QuicEvent::Closed {
-                    handle: handle_id,
-                    connection_id: conn_id_hex.clone(),
+                    handle: self.handle_id,
+                    connection_id: self.conn_id_hex.clone(),
                     reason,
                 },
             );
-            break;
+            return false;
         }
+        true
+    }
 
-        if let Some(timeout) = conn.timeout() {
-            if timeout.is_zero() {
-                conn.on_timeout();
-            } else {
-                let wait = timeout.min(Duration::from_millis(5));
-                thread::sleep(wait);
-                if wait >= timeout {
-                    if !conn.is_established() {
-                        warn!(
-                            "client {} handshake timeout fired after {:?} stats={}",
-                            conn_id_hex,
-                            start.elapsed(),
-                            format_stats(&conn.stats())
-                        );
-                    }
-                    conn.on_timeout();
-                }
-            }
-        } else {
-            thread::sleep(Duration::from_millis(2));
+    /// Fires quiche's timers after the worker slept through `conn.timeout()`.
+    fn on_timeout(&mut self) {
+        if !self.conn.is_established() {
+            warn!(
+                "client {} handshake timeout fired after {:?} stats={}",
+                self.conn_id_hex,
+                self.started.elapsed(),
+                format_stats(&self.conn.stats())
+            );
         }
+        self.conn.on_timeout();
     }
-}
 
-fn run_server_worker(
-    handle_id: u64,
-    mut config: quiche::Config,
-    socket: UdpSocket,
-    dart_port: i64,
-    trusted_allowlist: HashSet<String>,
-    rx: mpsc::Receiver<WorkerCommand>,
-) {
-    let local_addr = match socket.local_addr() {
-        Ok(addr) => addr,
-        Err(err) => {
-            post_event(
-                dart_port,
-                QuicEvent::Error {
-                    handle: handle_id,
-                    connection_id: None,
-                    message: format!("socket addr error: {err}"),
-                },
+    /// Whether UDP looks blocked: not one packet back from the server
+    /// within `timeout`, before the connection closed, or its port is
+    /// unreachable.
+    fn udp_silent(&self, timeout: Duration) -> bool {
+        !self.conn.is_established()
+            && self.conn.stats().recv == 0
+            && (self.handshake.unreachable()
+                || self.conn.is_closed()
+                || self.started.elapsed() >= timeout)
+    }
+
+    /// Answers the server's connect request: dials the connection it asks
+    /// for, reached from `peer`'s side, and posts `reverse_connect`.
+    fn dial_back(
+        &mut self,
+        addr: Option<SocketAddr>,
+        pin: Option<[u8; 32]>,
+        peer: Option<SocketAddr>,
+    ) {
+        let Some(reverse) = &self.options.reverse_connect else {
+            debug!(
+                "client {} ignored a connect request: reverse connect is off",
+                self.conn_id_hex
             );
             return;
+        };
+        let Some(reached) = peer else {
+            return;
+        };
+        let (target, dialed) = reverse.dial(reached, addr, pin, self.dart_port);
+        match &dialed {
+            Ok(handle) => info!(
+                "client {} dialed back to {target} as handle {handle}",
+                self.conn_id_hex
+            ),
+            Err(message) => warn!(
+                "client {} reverse connect to {target}: {message}",
+                self.conn_id_hex
+            ),
         }
-    };
-
-    let mut buf = [0u8; 65_536];
-    let mut out = [0u8; MAX_DATAGRAM_SIZE];
-    let mut conns: HashMap<Vec<u8>, quiche::Connection> = HashMap::new();
-    let mut announced: HashSet<Vec<u8>> = HashSet::new();
-    let mut start_times: HashMap<Vec<u8>, Instant> = HashMap::new();
+        post_event(
+            self.dart_port,
+            QuicEvent::ReverseConnect {
+                handle: self.handle_id,
+                connection_id: self.conn_id_hex.clone(),
+                addr: target,
+                dialed_handle: dialed.as_ref().ok().copied(),
+                error: dialed.err(),
+            },
+        );
+    }
 
-    loop {
-        while let Ok(cmd) = rx.try_recv() {
-            match cmd {
-                WorkerCommand::Send { conn_id, payload } => {
-                    if let Some(connection) = conns.get_mut(&conn_id) {
-                        if connection.is_established() {
-                            if let Err(err) =
-                                connection.stream_send(CONTROL_STREAM_ID, &payload, false)
-                            {
-                                if err != quiche::Error::Done {
-                                    warn!("server send error: {err:?}");
-                                }
-                            }
-                        }
-                    }
-                }
-                WorkerCommand::Close { conn_id } => {
-                    if let Some(id) = conn_id {
-                        if let Some(conn) = conns.get_mut(&id) {
-                            let _ = conn.close(false, 0x101, b"server close");
-                        }
-                    } else {
-                        for connection in conns.values_mut() {
-                            let _ = connection.close(false, 0x101, b"server close");
-                        }
-                    }
-                }
-            }
+    /// Gives up on the connection without closing it (the server never
+    /// heard from it), handing back what the next attempt needs.
+    fn into_context(self) -> WorkerContext {
+        WorkerContext {
+            handle_id: self.handle_id,
+            dart_port: self.dart_port,
+            options: self.options,
+            rx: self.rx,
         }
+    }
+}
 
-        match socket.recv_from(&mut buf) {
-            Ok((len, from)) => {
-                let hdr = match quiche::Header::from_slice(&mut buf[..len], quiche::MAX_CONN_ID_LEN)
-                {
-                    Ok(h) => h,
-                    Err(err) => {
-                        warn!("header parse error: {err:?}");
-                        continue;
-                    }
-                };
+/// How a client worker's drive loop ended.
+enum ClientEnd {
+    Finished,
+    /// UDP stayed silent and the TCP fallback is set.
+    UdpSilent,
+}
 
-                if !conns.contains_key(hdr.dcid.as_ref()) {
-                    let mut scid = [0u8; quiche::MAX_CONN_ID_LEN];
-                    OsRng.fill_bytes(&mut scid);
-                    let scid = quiche::ConnectionId::from_ref(&scid);
-                    match quiche::accept(&scid, Some(&hdr.scid), local_addr, from, &mut config) {
-                        Ok(c) => {
-                            info!(
-                                "server accepted conn_id={} from {}",
-                                hex_string(scid.as_ref()),
-                                from
-                            );
-                            conns.insert(scid.to_vec(), c);
-                            start_times.insert(scid.to_vec(), Instant::now());
-                        }
-                        Err(err) => {
-                            warn!("accept error: {err}");
-                            continue;
-                        }
-                    }
-                }
+/// How a client worker reaches its server.
+enum ClientLink {
+    Socket(QuicSocket),
+    /// Dialed by the worker, which blocks on the proxy handshake.
+    Proxy(Proxy),
+}
 
-                if let Some(connection) = conns.get_mut(hdr.dcid.as_ref()) {
-                    let recv_info = quiche::RecvInfo { from, to: local_addr };
-                    if let Err(err) = connection.recv(&mut buf[..len], recv_info) {
-                        if err != quiche::Error::Done {
-                            warn!("server recv error: {err:?}");
-                        }
-                    }
-                }
+/// A proxy from `cc_quic_client_connect_via_proxy`.
+enum Proxy {
+    Masque(ProxyRoute),
+    Socks(SocksRoute),
+}
+
+impl Proxy {
+    /// The proxy `url` names: `https://` for MASQUE, `socks5://` for
+    /// SOCKS5. `ca` is a MASQUE proxy's CA file or directory, and must
+    /// exist; SOCKS5 ignores it.
+    fn parse(url: &str, auth: &str, ca: Option<std::path::PathBuf>) -> Result<Self, Error> {
+        if let Some(ca) = ca.as_ref().filter(|ca| !ca.exists()) {
+            return Err(Error::CertParse {
+                path: ca.display().to_string(),
+                detail: "proxy CA does not exist".into(),
+            });
+        }
+        let route = if url.starts_with("socks5://") {
+            SocksRoute::parse(url, auth).map(Proxy::Socks)
+        } else {
+            ProxyRoute::parse(url, auth).map(|route| Proxy::Masque(route.trusting(ca)))
+        };
+        route.map_err(Error::Config)
+    }
+
+    /// Opens the tunnel to `peer`, and gives the largest packet that fits
+    /// through it.
+    fn dial(
+        &self,
+        peer: SocketAddr,
+        max_udp_payload: usize,
+    ) -> std::io::Result<(QuicSocket, usize)> {
+        match self {
+            Proxy::Masque(route) => {
+                let tunnel =
+                    MasqueTunnel::connect(route, peer, max_udp_payload, masque::CONNECT_TIMEOUT)?;
+                let max = tunnel.max_payload();
+                Ok((QuicSocket::masque(tunnel, max_udp_payload), max))
             }
-            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {}
-            Err(err) => {
-                warn!("server udp recv error: {err}");
-                break;
+            Proxy::Socks(route) => {
+                let tunnel = SocksTunnel::connect(route, peer, socks::CONNECT_TIMEOUT)?;
+                let max = max_udp_payload - tunnel.overhead();
+                Ok((QuicSocket::socks(tunnel, max_udp_payload), max))
             }
         }
+    }
+}
 
-        let mut to_close: Vec<Vec<u8>> = Vec::new();
-
-        for (id, connection) in conns.iter_mut() {
-            let id_hex = hex_string(id);
-            match connection.send(&mut out) {
-                Ok((len, send_info)) => {
-                    if let Err(err) = socket.send_to(&out[..len], send_info.to) {
-                        warn!("server udp send error: {err}");
-                    }
+fn run_client_worker(
+    mut ctx: WorkerContext,
+    mut config: quiche::Config,
+    link: ClientLink,
+    mut peer: SocketAddr,
+    server_name: String,
+    expected_fp: String,
+) {
+    let heartbeat = Heartbeat::new();
+    watchdog::watch(
+        &heartbeat,
+        ctx.handle_id,
+        ctx.dart_port,
+        ctx.options.watchdog_ms,
+    );
+    let mut socket = match link {
+        ClientLink::Socket(socket) => socket,
+        ClientLink::Proxy(proxy) => {
+            heartbeat.park();
+            let dialed = proxy.dial(peer, ctx.options.max_udp_payload);
+            heartbeat.beat();
+            match dialed {
+                Ok((socket, max_payload)) => {
+                    // Each packet has to fit in one proxy datagram.
+                    config.set_max_send_udp_payload_size(max_payload);
+                    socket
                 }
-                Err(quiche::Error::Done) => {}
                 Err(err) => {
-                    warn!(
-                        "server send error conn_id={} established={} err={err}",
-                        id_hex,
-                        connection.is_established()
-                    );
+                    warn!("client proxy to {peer} failed: {err}");
                     post_event(
-                        dart_port,
+                        ctx.dart_port,
                         QuicEvent::Error {
-                            handle: handle_id,
-                            connection_id: Some(id_hex.clone()),
-                            message: format!("server send error: {err}"),
+                            handle: ctx.handle_id,
+                            connection_id: None,
+                            message: format!("proxy connect failed: {err}"),
                         },
                     );
-                    to_close.push(id.clone());
-                    continue;
+                    return;
                 }
             }
-
-            if connection.is_established() && !announced.contains(id) {
-                let peer_fp = match connection.peer_cert() {
-                    Some(cert) => sha256_hex(cert),
-                    None => String::new(),
-                };
-
-                if !trusted_allowlist.is_empty() && !trusted_allowlist.contains(&peer_fp) {
-                    warn!(
-                        "rejecting untrusted client conn={} fp={}",
-                        id_hex,
-                        short_hex(&peer_fp)
-                    );
-                    let _ = connection.close(false, 0x103, b"untrusted client");
-                    to_close.push(id.clone());
-                    continue;
-                }
-
-                info!(
-                    "server connection established conn_id={} peer_fp={}",
-                    id_hex,
-                    short_hex(&peer_fp)
-                );
-                announced.insert(id.clone());
+        }
+    };
+    // An in-memory pair has nothing to fall back to, and a proxy is the
+    // only way out.
+    let mut fallback = ctx
+        .options
+        .tcp_fallback
+        .filter(|_| socket.memory_peer().is_none() && !socket.is_proxied());
+    loop {
+        let local_addr = match socket.local_addr() {
+            Ok(addr) => addr,
+            Err(err) => {
                 post_event(
-                    dart_port,
-                    QuicEvent::Connected {
-                        handle: handle_id,
-                        connection_id: id_hex.clone(),
-                        peer_fingerprint: peer_fp,
+                    ctx.dart_port,
+                    QuicEvent::Error {
+                        handle: ctx.handle_id,
+                        connection_id: None,
+                        message: format!("socket addr error: {err}"),
                     },
                 );
+                return;
             }
+        };
+        let Some(mut client) = ClientConnection::connect(
+            ctx,
+            &mut config,
+            local_addr,
+            peer,
+            &server_name,
+            expected_fp.clone(),
+        ) else {
+            return;
+        };
+        let udp_timeout = fallback.map(|fallback| fallback.udp_timeout);
+        match drive_client(
+            &mut client,
+            &socket,
+            peer,
+            local_addr,
+            &heartbeat,
+            udp_timeout,
+        ) {
+            ClientEnd::Finished => return,
+            ClientEnd::UdpSilent => {}
+        }
 
-            for stream_id in connection.readable() {
-                loop {
-                    let mut app_buf = [0u8; 65535];
-                    match connection.stream_recv(stream_id, &mut app_buf) {
-                        Ok((read, _fin)) => {
-                            let data = &app_buf[..read];
-                            post_event(
-                                dart_port,
-                                QuicEvent::Message {
-                                    handle: handle_id,
-                                    connection_id: id_hex.clone(),
-                                    data_base64: BASE64.encode(data),
-                                },
-                            );
-                        }
-                        Err(quiche::Error::Done) => break,
-                        Err(err) => {
-                            warn!("server stream read error: {err:?}");
-                            break;
-                        }
-                    }
-                }
+        // Only one fallback: the tunnel is the last thing to try.
+        let Some(Fallback { port, .. }) = fallback.take() else {
+            return;
+        };
+        let tunnel_addr = SocketAddr::new(peer.ip(), port);
+        info!(
+            "client {} heard nothing over UDP; trying tcp {tunnel_addr}",
+            client.conn_id_hex
+        );
+        post_event(
+            client.dart_port,
+            QuicEvent::TransportFallback {
+                handle: client.handle_id,
+                connection_id: client.conn_id_hex.clone(),
+                transport: "tcp",
+                addr: tunnel_addr.to_string(),
+            },
+        );
+        // The connect blocks for up to its timeout with nothing to drive.
+        heartbeat.park();
+        let dialed = TunnelStream::connect(tunnel_addr);
+        heartbeat.beat();
+        match dialed {
+            Ok(stream) => {
+                socket = QuicSocket::tunnel(stream, client.options.max_udp_payload);
+                peer = tunnel_addr;
+                ctx = client.into_context();
             }
-
-            if connection.is_closed() {
-                let reason = connection.peer_error().map(|err| format!("{err:?}"));
-                info!(
-                    "server connection {} closed established={} ({:?}) stats={}",
-                    id_hex,
-                    connection.is_established(),
-                    reason,
-                    format_stats(&connection.stats())
+            Err(err) => {
+                warn!("client {} tcp fallback failed: {err}", client.conn_id_hex);
+                audit_conn(
+                    client.handle_id,
+                    "client",
+                    Outcome::Closed,
+                    &client.conn_id_hex,
+                    &client.conn,
+                    None,
                 );
                 post_event(
-                    dart_port,
+                    client.dart_port,
                     QuicEvent::Closed {
-                        handle: handle_id,
-                        connection_id: id_hex.clone(),
-                        reason,
+                        handle: client.handle_id,
+                        connection_id: client.conn_id_hex.clone(),
+                        reason: Some(format!("tcp fallback failed: {err}")),
                     },
                 );
-                to_close.push(id.clone());
-                continue;
+                return;
             }
+        }
+    }
+}
 
-            if let Some(timeout) = connection.timeout() {
-                if timeout.is_zero() {
-                    connection.on_timeout();
-                } else {
-                    let wait = timeout.min(Duration::from_millis(5));
-                    thread::sleep(wait);
-                    if wait >= timeout {
-                        if !connection.is_established() {
-                            let elapsed = start_times
-                                .get(id)
-                                .map(|s| s.elapsed())
-                                .unwrap_or_default();
-                            warn!(
-                                "server conn {} handshake timeout fired after {:?} stats={}",
-                                id_hex,
-                                elapsed,
-                                format_stats(&connection.stats())
-                            );
-                        }
-                        connection.on_timeout();
+/// Runs `client` until it finishes, or until UDP has stayed silent for
+/// `udp_timeout` when a TCP fallback is set; that attempt has then had its
+/// `handshake_failed` but no `closed`.
+fn drive_client(
+    client: &mut ClientConnection,
+    socket: &QuicSocket,
+    peer: SocketAddr,
+    local_addr: SocketAddr,
+    heartbeat: &Heartbeat,
+    udp_timeout: Option<Duration>,
+) -> ClientEnd {
+    let mut tx_batch = socket.new_send_batch();
+    let mut rx_batch = socket.new_recv_batch();
+    let mut scratch = Scratch::new();
+    let mut connected = peer;
+
+    loop {
+        heartbeat.beat();
+        client.apply_commands();
+
+        let sent = client.flush(socket, &mut tx_batch);
+        if let Err(err) = socket.send_batch(&mut tx_batch) {
+            warn!("udp send error: {err}");
+        }
+        if !sent {
+            return ClientEnd::Finished;
+        }
+
+        loop {
+            match socket.recv_batch(&mut rx_batch) {
+                Ok(count) => {
+                    for i in 0..count {
+                        let (data, meta) = rx_batch.get_mut(i);
+                        client.recv(data, meta, local_addr);
                     }
                 }
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+                // An ICMP port unreachable; quiche retries until it times out.
+                Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => {
+                    client.handshake.on_unreachable();
+                    break;
+                }
+                Err(err) => {
+                    warn!("client udp recv error: {err}");
+                    return ClientEnd::Finished;
+                }
             }
         }
 
-        for id in to_close {
-            conns.remove(&id);
-            announced.remove(&id);
-            start_times.remove(&id);
+        if udp_timeout.is_some_and(|timeout| client.udp_silent(timeout)) {
+            post_handshake_failed(
+                client.handle_id,
+                client.dart_port,
+                &client.conn_id_hex,
+                &client.handshake,
+                &client.conn,
+            );
+            return ClientEnd::UdpSilent;
+        }
+        if !client.poll(&mut scratch) {
+            return ClientEnd::Finished;
+        }
+        // The kernel only passes datagrams from the connected address, so
+        // the socket follows a relocation probe and, if it fails, returns.
+        if let Some(server) = client.socket_peer().filter(|server| *server != connected) {
+            connected = server;
+            if let Err(err) = socket.connect(server) {
+                warn!("client socket connect error: {err}");
+            }
         }
 
+        let release = client.pacer.next_release(Instant::now());
+        if let Some(timeout) = client.conn.timeout() {
+            if timeout.is_zero() {
+                client.conn.on_timeout();
+            } else {
+                let wait = timeout.min(Duration::from_millis(5));
+                let wait = release.map_or(wait, |release| wait.min(release));
+                thread::sleep(wait);
+                if wait >= timeout {
+                    client.on_timeout();
+                }
+            }
+        } else {
+            thread::sleep(release.map_or(Duration::from_millis(2), |release| {
+                release.min(Duration::from_millis(2))
+            }));
+        }
     }
 }
 
-fn cstr_to_string(ptr: *const c_char) -> Result<String, CcQuicStatus> {
-    if ptr.is_null() {
-        return Err(CcQuicStatus::NullPointer);
+struct ServerConnection {
+    conn: quiche::Connection,
+    exporter: Arc<exporter::Secret>,
+    announced: bool,
+    started: Instant,
+    ecn: EcnCounts,
+    dgrams: DgramInbox,
+    media: MediaChannel,
+    dual: DualChannel,
+    timesync: TimeSync,
+    identity: IdentityInbox,
+    capabilities: CapabilityExchange,
+    channels: Channels,
+    topics: Subscriptions,
+    roster: RosterFeed,
+    auth: ServerAuth,
+    approval: Decision,
+    /// Source address last reported to the client.
+    reported_addr: Option<SocketAddr>,
+    /// Preferred address last announced to the client.
+    told_preferred: Option<SocketAddr>,
+    send_cap: Option<TokenBucket>,
+    /// The `cc_quic_conn_set_rate_limit` rate and `_set_pacing` overrides
+    /// `send_cap` is built from.
+    send_rate: Option<u64>,
+    send_pacing: SendPacing,
+    impair: Option<Impairment>,
+    /// Packets held for quiche's pacing release time.
+    pacer: Pacer,
+    capture: Option<PacketCapture>,
+    recv_guard: RecvGuard,
+    control_codec: ControlCodec,
+    bdp: BdpEstimator,
+    send_limit: SendLimit,
+    handshake: HandshakeProgress,
+    traffic: TrafficMeter,
+    last_stats: Instant,
+    last_usage: Instant,
+    last_keepalive: Instant,
+    /// Closed by the reaper; waiting to drain.
+    reaped: bool,
+    /// Reached through the TCP fallback listener, which then carries all
+    /// of its packets.
+    tunneled: bool,
+    /// The SNI identity it was accepted with; the default one when `None`.
+    sni_route: Option<usize>,
+}
+
+impl ServerConnection {
+    fn new(mut conn: quiche::Connection) -> Self {
+        let now = Instant::now();
+        Self {
+            exporter: exporter::attach(&mut conn),
+            conn,
+            announced: false,
+            started: now,
+            ecn: EcnCounts::default(),
+            dgrams: DgramInbox::default(),
+            media: MediaChannel::new(),
+            dual: DualChannel::new(),
+            timesync: TimeSync::new(true),
+            identity: IdentityInbox::default(),
+            capabilities: CapabilityExchange::default(),
+            channels: Channels::new(true),
+            topics: Subscriptions::default(),
+            roster: RosterFeed::default(),
+            auth: ServerAuth::default(),
+            approval: Decision::default(),
+            reported_addr: None,
+            told_preferred: None,
+            send_cap: None,
+            send_rate: None,
+            send_pacing: SendPacing::default(),
+            impair: None,
+            pacer: Pacer::default(),
+            capture: None,
+            recv_guard: RecvGuard::default(),
+            control_codec: ControlCodec::default(),
+            bdp: BdpEstimator::default(),
+            send_limit: SendLimit::new(now),
+            handshake: HandshakeProgress::new(now),
+            traffic: TrafficMeter::default(),
+            last_stats: now,
+            last_usage: now,
+            last_keepalive: now,
+            reaped: false,
+            tunneled: false,
+            sni_route: None,
+        }
     }
-    unsafe {
-        CStr::from_ptr(ptr)
+}
+
+/// Binds the server socket, or `workers` sockets sharing the port via
+/// `SO_REUSEPORT`. Later sockets reuse the first one's port so port 0 works.
+fn bind_server_sockets(
+    local: SocketAddr,
+    options: SocketOptions,
+    workers: usize,
+) -> std::io::Result<Vec<QuicSocket>> {
+    if workers <= 1 {
+        return Ok(vec![QuicSocket::bind(local, options)?]);
+    }
+    let first = QuicSocket::bind_reuseport(local, options)?;
+    let local = first.local_addr()?;
+    let mut sockets = vec![first];
+    for _ in 1..workers {
+        sockets.push(QuicSocket::bind_reuseport(local, options)?);
+    }
+    Ok(sockets)
+}
+
+/// A datagram the kernel delivered to the wrong `SO_REUSEPORT` sibling.
+struct ForwardedDatagram {
+    data: Vec<u8>,
+    meta: RecvMeta,
+}
+
+/// Where a server worker sits among its `SO_REUSEPORT` siblings. Each worker
+/// stamps its index into the first byte of the SCIDs it issues, so packets
+/// for a connection it does not own can be handed to the worker that does.
+struct ServerRoute {
+    index: u8,
+    peers: Vec<mpsc::Sender<ForwardedDatagram>>,
+    inbox: mpsc::Receiver<ForwardedDatagram>,
+}
+
+impl ServerRoute {
+    fn for_workers(count: usize) -> Vec<Self> {
+        let (peers, inboxes): (Vec<_>, Vec<_>) = (0..count).map(|_| mpsc::channel()).unzip();
+        inboxes
+            .into_iter()
+            .enumerate()
+            .map(|(index, inbox)| Self {
+                index: index as u8,
+                peers: peers.clone(),
+                inbox,
+            })
+            .collect()
+    }
+
+    fn owner(&self, hdr: &quiche::Header) -> Option<usize> {
+        owning_worker(self.index, self.peers.len(), hdr.ty, &hdr.dcid)
+    }
+}
+
+/// Sibling of worker `index` (of `workers`) owning a packet's DCID, when
+/// that is not `index` itself. Initials carry a client-chosen DCID and are
+/// accepted wherever they land.
+fn owning_worker(index: u8, workers: usize, ty: quiche::Type, dcid: &[u8]) -> Option<usize> {
+    if ty == quiche::Type::Initial || dcid.len() != quiche::MAX_CONN_ID_LEN {
+        return None;
+    }
+    let owner = dcid[0] as usize;
+    (owner != index as usize && owner < workers).then_some(owner)
+}
+
+/// What a server worker does with a datagram, decided from its type, DCID
+/// and length alone; `replay.rs` runs recorded traces through the same
+/// decision.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Inbound {
+    /// For the connection with this key.
+    Conn(Vec<u8>),
+    /// For a connection the sibling worker at this index owns.
+    Forward(usize),
+    /// A padded Initial for an unknown DCID: accept a new connection.
+    Accept,
+    /// Anything else for an unknown DCID.
+    Drop,
+}
+
+fn classify_inbound(
+    cids: &CidIndex,
+    ty: quiche::Type,
+    dcid: &[u8],
+    len: usize,
+    owner: Option<usize>,
+) -> Inbound {
+    if let Some(key) = cids.resolve(dcid) {
+        return Inbound::Conn(key.clone());
+    }
+    if let Some(owner) = owner {
+        return Inbound::Forward(owner);
+    }
+    // Only a padded Initial may create state, so a spoofed source can't
+    // get a connection (and its replies) out of an arbitrary packet.
+    if ty != quiche::Type::Initial || len < quiche::MIN_CLIENT_INITIAL_LEN {
+        return Inbound::Drop;
+    }
+    Inbound::Accept
+}
+
+/// Fans handle commands out to the `SO_REUSEPORT` workers by the index
+/// stamped into each connection id.
+fn route_server_commands(
+    rx: mpsc::Receiver<WorkerCommand>,
+    workers: Vec<mpsc::Sender<WorkerCommand>>,
+) {
+    for cmd in rx {
+        let owner = match &cmd {
+            WorkerCommand::Send { conn_id, .. }
+            | WorkerCommand::Control { conn_id, .. }
+            | WorkerCommand::Media { conn_id, .. }
+            | WorkerCommand::MediaFec { conn_id, .. }
+            | WorkerCommand::MediaRedundancy { conn_id, .. }
+            | WorkerCommand::TimeSync { conn_id, .. }
+            | WorkerCommand::RateLimit { conn_id, .. }
+            | WorkerCommand::Pacing { conn_id, .. }
+            | WorkerCommand::Capture { conn_id, .. }
+            | WorkerCommand::Channel { conn_id, .. }
+            | WorkerCommand::StreamAbort { conn_id, .. }
+            | WorkerCommand::AuthVerdict { conn_id, .. }
+            | WorkerCommand::Decide { conn_id, .. }
+            | WorkerCommand::ConnectRequest { conn_id, .. }
+            | WorkerCommand::Close {
+                conn_id: Some(conn_id),
+            } => conn_id.first().map(|index| *index as usize),
+            #[cfg(feature = "impairment")]
+            WorkerCommand::Impair { conn_id, .. } => conn_id.first().map(|index| *index as usize),
+            WorkerCommand::Close { conn_id: None } => {
+                for worker in &workers {
+                    let _ = worker.send(WorkerCommand::Close { conn_id: None });
+                }
+                continue;
+            }
+            WorkerCommand::PreferredAddress { addr } => {
+                for worker in &workers {
+                    let _ = worker.send(WorkerCommand::PreferredAddress { addr: *addr });
+                }
+                continue;
+            }
+            WorkerCommand::RevokeUser { user, revoked } => {
+                for worker in &workers {
+                    let _ = worker.send(WorkerCommand::RevokeUser {
+                        user: user.clone(),
+                        revoked: *revoked,
+                    });
+                }
+                continue;
+            }
+            // Subscribers may sit on any worker.
+            WorkerCommand::Publish { topic, data } => {
+                for worker in &workers {
+                    let _ = worker.send(WorkerCommand::Publish {
+                        topic: topic.clone(),
+                        data: Arc::clone(data),
+                    });
+                }
+                continue;
+            }
+            WorkerCommand::Diagnostics { reply } => {
+                for worker in &workers {
+                    let reply = reply.clone();
+                    let _ = worker.send(WorkerCommand::Diagnostics { reply });
+                }
+                continue;
+            }
+            WorkerCommand::Subscribe { .. } => None,
+            WorkerCommand::RotateIdentity {
+                cert_path,
+                key_path,
+                fingerprint,
+            } => {
+                // Each worker announces on its own connections; the shared
+                // quiche config is reloaded once per worker, which is harmless.
+                for worker in &workers {
+                    let _ = worker.send(WorkerCommand::RotateIdentity {
+                        cert_path: cert_path.clone(),
+                        key_path: key_path.clone(),
+                        fingerprint: *fingerprint,
+                    });
+                }
+                continue;
+            }
+        };
+        if let Some(worker) = owner.and_then(|index| workers.get(index)) {
+            let _ = worker.send(cmd);
+        }
+    }
+}
+
+/// Feeds one datagram to its connection, accepting a new one for an
+/// Initial; the key of the connection it went to, if any.
+fn handle_server_datagram(
+    conns: &mut HashMap<Vec<u8>, ServerConnection>,
+    cids: &mut CidIndex,
+    identities: &sni::ServerIdentities,
+    local_addr: SocketAddr,
+    route: Option<&ServerRoute>,
+    data: &mut [u8],
+    meta: RecvMeta,
+) -> Option<Vec<u8>> {
+    let hdr = match quiche::Header::from_slice(data, quiche::MAX_CONN_ID_LEN) {
+        Ok(h) => h,
+        Err(err) => {
+            warn!("header parse error: {err:?}");
+            return None;
+        }
+    };
+
+    let owner = route.and_then(|route| route.owner(&hdr));
+    let conn_key = match classify_inbound(cids, hdr.ty, &hdr.dcid, data.len(), owner) {
+        Inbound::Conn(key) => Some(key),
+        Inbound::Forward(owner) => {
+            if let Some(route) = route {
+                let _ = route.peers[owner].send(ForwardedDatagram {
+                    data: data.to_vec(),
+                    meta,
+                });
+            }
+            return None;
+        }
+        Inbound::Drop => {
+            log::debug!(
+                "dropping {:?} packet of {} bytes for unknown dcid from {}",
+                hdr.ty,
+                data.len(),
+                meta.from
+            );
+            return None;
+        }
+        Inbound::Accept => {
+            let (config, sni_route) = identities.for_initial(data);
+            let key = accept_server_conn(conns, cids, config, local_addr, route, &hdr, meta);
+            if let Some(entry) = key.as_ref().and_then(|key| conns.get_mut(key)) {
+                entry.sni_route = sni_route;
+            }
+            key
+        }
+    };
+    let conn_key = conn_key?;
+
+    if let Some(entry) = conns.get_mut(&conn_key) {
+        entry.ecn.record(meta.ecn);
+        let recv_info = quiche::RecvInfo {
+            from: meta.from,
+            to: local_addr,
+        };
+        entry.handshake.on_datagram(&entry.conn, data);
+        capture::record(&mut entry.capture, meta.from, local_addr, data);
+        if let Err(err) = entry.conn.recv(data, recv_info) {
+            if err != quiche::Error::Done {
+                warn!("{} server recv error: {err:?}", entry.conn.trace_id());
+                entry.handshake.on_recv_error(err);
+            }
+        }
+    }
+    Some(conn_key)
+}
+
+/// Accepts a new connection for `hdr`'s Initial; its key, or `None` if
+/// quiche refused it.
+fn accept_server_conn(
+    conns: &mut HashMap<Vec<u8>, ServerConnection>,
+    cids: &mut CidIndex,
+    config: &Mutex<quiche::Config>,
+    local_addr: SocketAddr,
+    route: Option<&ServerRoute>,
+    hdr: &quiche::Header,
+    meta: RecvMeta,
+) -> Option<Vec<u8>> {
+    let mut scid = [0u8; quiche::MAX_CONN_ID_LEN];
+    OsRng.fill_bytes(&mut scid);
+    if let Some(route) = route {
+        scid[0] = route.index;
+    }
+    let scid = quiche::ConnectionId::from_ref(&scid);
+    let mut config = config.lock().unwrap_or_else(PoisonError::into_inner);
+    match quiche::accept(&scid, None, local_addr, meta.from, &mut config) {
+        Ok(c) => {
+            debug_assert_eq!(c.trace_id(), hex_string(scid.as_ref()));
+            info!(
+                "server accepted conn_id={} from {}",
+                hex_string(scid.as_ref()),
+                meta.from
+            );
+            // The client's first DCID is its own random pick and stays in
+            // use until our SCID reaches it; both lead to the connection
+            // keyed by our SCID.
+            let key = scid.to_vec();
+            cids.insert(&key, &key);
+            cids.insert(&hdr.dcid, &key);
+            conns.insert(key.clone(), ServerConnection::new(c));
+            Some(key)
+        }
+        Err(err) => {
+            warn!("accept error: {err}");
+            None
+        }
+    }
+}
+
+fn run_server_worker(
+    ctx: WorkerContext,
+    identities: Arc<sni::ServerIdentities>,
+    mut sockets: Vec<QuicSocket>,
+    trusted_allowlist: Arc<Allowlist>,
+    blocklist: Arc<Blocklist>,
+    roster: Arc<Roster>,
+    route: Option<ServerRoute>,
+) {
+    let WorkerContext {
+        handle_id,
+        dart_port,
+        options,
+        rx,
+    } = ctx;
+    // One worker posts the server's own `roster_changed` events and watches
+    // the allowlist file.
+    let lead = route.as_ref().is_none_or(|route| route.index == 0);
+    let mut roster_posted = roster.version();
+    let mut allowlist_seen = trusted_allowlist.version();
+    let mut revoked_users: HashSet<String> = HashSet::new();
+    // A TCP fallback listener comes last; its connections keep to it.
+    let tunnel = match sockets.last() {
+        Some(last) if last.is_tunnel_listener() => sockets.pop(),
+        _ => None,
+    };
+    let local_addrs = match sockets
+        .iter()
+        .chain(&tunnel)
+        .map(QuicSocket::local_addr)
+        .collect::<std::io::Result<Vec<_>>>()
+    {
+        Ok(addrs) => addrs,
+        Err(err) => {
+            post_event(
+                dart_port,
+                QuicEvent::Error {
+                    handle: handle_id,
+                    connection_id: None,
+                    message: format!("socket addr error: {err}"),
+                },
+            );
+            return;
+        }
+    };
+
+    // Every socket shares the handle's options, so one receive batch fits all.
+    let mut rx_batch = sockets[0].new_recv_batch();
+    let mut tx_batches: Vec<SendBatch> = sockets
+        .iter()
+        .chain(&tunnel)
+        .map(QuicSocket::new_send_batch)
+        .collect();
+    let mut scratch = Scratch::new();
+    let mut conns: HashMap<Vec<u8>, ServerConnection> = HashMap::new();
+    let mut cids = CidIndex::default();
+    let mut preferred: Option<SocketAddr> = None;
+    let heartbeat = Heartbeat::new();
+    watchdog::watch(&heartbeat, handle_id, dart_port, options.watchdog_ms);
+
+    'worker: loop {
+        heartbeat.beat();
+        loop {
+            match rx.try_recv() {
+                Ok(cmd) => match cmd {
+                    // Compressed sends take the backlog, as for the client.
+                    WorkerCommand::Send { conn_id, payload }
+                        if !conns
+                            .get(&conn_id)
+                            .is_some_and(|entry| entry.control_codec.compressing(&entry.conn)) =>
+                    {
+                        if let Some(entry) = conns.get_mut(&conn_id) {
+                            if entry.conn.is_established() {
+                                match entry.conn.stream_send(CONTROL_STREAM_ID, &payload, false) {
+                                    Ok(written) => {
+                                        entry.traffic.sent(TrafficClass::Control, written)
+                                    }
+                                    Err(quiche::Error::Done) => {}
+                                    Err(err) => {
+                                        let trace_id = entry.conn.trace_id();
+                                        warn!("{trace_id} server send error: {err:?}")
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    WorkerCommand::Close { conn_id } => {
+                        if let Some(id) = conn_id {
+                            if let Some(entry) = conns.get_mut(&id) {
+                                let _ = entry.conn.close(false, 0x101, b"server close");
+                            }
+                        } else {
+                            for entry in conns.values_mut() {
+                                let _ = entry.conn.close(false, 0x101, b"server close");
+                            }
+                        }
+                    }
+                    WorkerCommand::RotateIdentity {
+                        cert_path,
+                        key_path,
+                        fingerprint,
+                    } => {
+                        let loaded = {
+                            let mut config = identities
+                                .default
+                                .lock()
+                                .unwrap_or_else(PoisonError::into_inner);
+                            config
+                                .load_cert_chain_from_pem_file(&cert_path)
+                                .and_then(|()| config.load_priv_key_from_pem_file(&key_path))
+                        };
+                        if let Err(err) = loaded {
+                            error!("identity rotate: reload failed: {err}");
+                            post_event(
+                                dart_port,
+                                QuicEvent::Error {
+                                    handle: handle_id,
+                                    connection_id: None,
+                                    message: format!("identity rotate failed: {err}"),
+                                },
+                            );
+                            continue;
+                        }
+                        for entry in conns.values_mut().filter(|entry| entry.announced) {
+                            if let Err(err) =
+                                identity::announce(&mut entry.conn, true, &fingerprint)
+                            {
+                                let trace_id = entry.conn.trace_id();
+                                warn!("{trace_id} identity announce error: {err:?}");
+                            }
+                        }
+                    }
+                    WorkerCommand::Send { conn_id, payload }
+                    | WorkerCommand::Control { conn_id, payload } => {
+                        let Some(entry) = conns.get_mut(&conn_id) else {
+                            continue;
+                        };
+                        if !entry.conn.is_established() {
+                            continue;
+                        }
+                        let payload = entry.control_codec.encode(
+                            &entry.conn,
+                            payload,
+                            options.compression.min_size,
+                        );
+                        let len = payload.len();
+                        if entry.dual.send_control(
+                            &mut entry.conn,
+                            CONTROL_STREAM_ID,
+                            payload,
+                            &options.dual,
+                        ) {
+                            entry.traffic.sent(TrafficClass::Control, len);
+                        } else {
+                            post_event(
+                                dart_port,
+                                QuicEvent::Error {
+                                    handle: handle_id,
+                                    connection_id: Some(hex_string(&conn_id)),
+                                    message: CONTROL_BACKLOG_FULL.to_string(),
+                                },
+                            );
+                        }
+                    }
+                    WorkerCommand::Media {
+                        conn_id,
+                        timestamp,
+                        marker,
+                        payload,
+                    } => {
+                        let Some(entry) = conns.get_mut(&conn_id) else {
+                            continue;
+                        };
+                        if !entry.conn.is_established() {
+                            continue;
+                        }
+                        let len = payload.len() + media::MEDIA_HEADER_LEN;
+                        if !entry
+                            .dual
+                            .admit_media(&entry.conn, len, &options.dual, Instant::now())
+                        {
+                            entry.media.paced_drop();
+                        } else if let Err(err) =
+                            entry
+                                .media
+                                .send(&mut entry.conn, timestamp, marker, &payload)
+                        {
+                            if err != quiche::Error::Done {
+                                let trace_id = entry.conn.trace_id();
+                                warn!("{trace_id} server media send error: {err:?}");
+                            }
+                        }
+                    }
+                    WorkerCommand::MediaFec { conn_id, group } => {
+                        if let Some(entry) = conns.get_mut(&conn_id) {
+                            entry.media.set_fec(group);
+                        }
+                    }
+                    WorkerCommand::MediaRedundancy {
+                        conn_id,
+                        copies,
+                        spread,
+                    } => {
+                        if let Some(entry) = conns.get_mut(&conn_id) {
+                            entry.media.set_redundancy(copies, spread);
+                        }
+                    }
+                    WorkerCommand::TimeSync { conn_id, interval } => {
+                        if let Some(entry) = conns.get_mut(&conn_id) {
+                            entry.timesync.set_interval(interval);
+                        }
+                    }
+                    WorkerCommand::RateLimit { conn_id, max_bps } => {
+                        if let Some(entry) = conns.get_mut(&conn_id) {
+                            entry.send_rate = max_bps;
+                            entry.send_cap = entry.send_pacing.cap(max_bps);
+                        }
+                    }
+                    WorkerCommand::Pacing { conn_id, pacing } => {
+                        if let Some(entry) = conns.get_mut(&conn_id) {
+                            entry.send_pacing = pacing;
+                            entry.send_cap = pacing.cap(entry.send_rate);
+                        }
+                    }
+                    #[cfg(feature = "impairment")]
+                    WorkerCommand::Impair { conn_id, config } => {
+                        if let Some(entry) = conns.get_mut(&conn_id) {
+                            entry.impair = config.map(Impairment::new);
+                        }
+                    }
+                    WorkerCommand::Capture { conn_id, capture } => {
+                        if let Some(entry) = conns.get_mut(&conn_id) {
+                            entry.capture = capture;
+                        }
+                    }
+                    WorkerCommand::Channel { conn_id, name, op } => {
+                        let Some(entry) = conns.get_mut(&conn_id) else {
+                            continue;
+                        };
+                        if !entry.conn.is_established() {
+                            continue;
+                        }
+                        match entry.channels.apply(&mut entry.conn, &name, op) {
+                            Ok(written) => entry.traffic.sent(TrafficClass::Transfer, written),
+                            Err(message) => post_event(
+                                dart_port,
+                                QuicEvent::Error {
+                                    handle: handle_id,
+                                    connection_id: Some(hex_string(&conn_id)),
+                                    message,
+                                },
+                            ),
+                        }
+                    }
+                    WorkerCommand::StreamAbort {
+                        conn_id,
+                        stream_id,
+                        abort,
+                        error_code,
+                    } => {
+                        let Some(entry) = conns.get_mut(&conn_id) else {
+                            continue;
+                        };
+                        if let Err(message) =
+                            entry
+                                .channels
+                                .abort(&mut entry.conn, stream_id, abort, error_code)
+                        {
+                            post_event(
+                                dart_port,
+                                QuicEvent::Error {
+                                    handle: handle_id,
+                                    connection_id: Some(hex_string(&conn_id)),
+                                    message,
+                                },
+                            );
+                        }
+                    }
+                    WorkerCommand::PreferredAddress { addr } => {
+                        // Clients can only be moved between sockets this
+                        // worker reads.
+                        if let Some(unbound) = addr.filter(|addr| !local_addrs.contains(addr)) {
+                            post_event(
+                                dart_port,
+                                QuicEvent::Error {
+                                    handle: handle_id,
+                                    connection_id: None,
+                                    message: format!(
+                                        "preferred address {unbound} is not a bound address"
+                                    ),
+                                },
+                            );
+                            continue;
+                        }
+                        preferred = addr;
+                    }
+                    WorkerCommand::ConnectRequest { conn_id, addr, pin } => {
+                        let Some(entry) = conns.get_mut(&conn_id) else {
+                            continue;
+                        };
+                        let record = AddressRecord::Connect { addr, pin };
+                        if let Err(err) = observed::send(&mut entry.conn, record) {
+                            post_event(
+                                dart_port,
+                                QuicEvent::Error {
+                                    handle: handle_id,
+                                    connection_id: Some(hex_string(&conn_id)),
+                                    message: format!("connect request not sent: {err:?}"),
+                                },
+                            );
+                        }
+                    }
+                    WorkerCommand::Publish { topic, data } => {
+                        for (conn_id, entry) in conns
+                            .iter_mut()
+                            .filter(|(_, entry)| entry.topics.subscribed(&topic))
+                        {
+                            if !entry.topics.publish(&topic, &data) {
+                                post_event(
+                                    dart_port,
+                                    QuicEvent::Error {
+                                        handle: handle_id,
+                                        connection_id: Some(hex_string(conn_id)),
+                                        message: format!(
+                                            "topic {topic}: subscriber backlog full, dropped"
+                                        ),
+                                    },
+                                );
+                            }
+                        }
+                    }
+                    WorkerCommand::AuthVerdict {
+                        conn_id,
+                        accept,
+                        user,
+                    } => {
+                        let Some(entry) = conns.get_mut(&conn_id) else {
+                            continue;
+                        };
+                        let step = entry
+                            .auth
+                            .decide(&mut entry.conn, accept, user, &revoked_users);
+                        if let Step::Rejected(reason) = step {
+                            let id_hex = hex_string(&conn_id);
+                            auth_rejected(handle_id, dart_port, &id_hex, &entry.conn, &reason);
+                        }
+                    }
+                    WorkerCommand::Decide { conn_id, accept } => {
+                        if let Some(entry) = conns.get_mut(&conn_id) {
+                            entry.approval.decide(accept);
+                        }
+                    }
+                    WorkerCommand::RevokeUser { user, revoked } => {
+                        if !revoked {
+                            revoked_users.remove(&user);
+                            continue;
+                        }
+                        for (conn_id, entry) in conns
+                            .iter_mut()
+                            .filter(|(_, entry)| entry.auth.user() == Some(user.as_str()))
+                        {
+                            info!(
+                                "closing conn {} of revoked user {user}",
+                                hex_string(conn_id)
+                            );
+                            let _ = entry
+                                .conn
+                                .close(false, auth::AUTH_FAILED, b"access revoked");
+                        }
+                        revoked_users.insert(user);
+                    }
+                    WorkerCommand::Diagnostics { reply } => {
+                        let connections = conns
+                            .iter()
+                            .map(|(conn_id, entry)| {
+                                ConnectionSnapshot::new(
+                                    hex_string(conn_id),
+                                    &entry.conn,
+                                    entry.started.elapsed(),
+                                )
+                            })
+                            .collect();
+                        let _ = reply.send(WorkerSnapshot {
+                            options: options.describe(),
+                            connections,
+                        });
+                    }
+                    WorkerCommand::Subscribe { .. } => {}
+                },
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    // The handle was removed (e.g. a sibling worker died).
+                    break 'worker;
+                }
+            }
+        }
+
+        for (listener, socket) in sockets.iter().chain(&tunnel).enumerate() {
+            let tunneled = listener == sockets.len();
+            loop {
+                match socket.recv_batch(&mut rx_batch) {
+                    Ok(count) => {
+                        for i in 0..count {
+                            let (data, meta) = rx_batch.get_mut(i);
+                            if blocklist.drops(meta.from.ip(), data.len()) {
+                                continue;
+                            }
+                            let conn = handle_server_datagram(
+                                &mut conns,
+                                &mut cids,
+                                &identities,
+                                local_addrs[listener],
+                                route.as_ref(),
+                                data,
+                                meta,
+                            );
+                            if let Some(entry) = conn
+                                .filter(|_| tunneled)
+                                .and_then(|key| conns.get_mut(&key))
+                            {
+                                entry.tunneled = true;
+                            }
+                        }
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+                    Err(err) => {
+                        warn!("server udp recv error: {err}");
+                        break 'worker;
+                    }
+                }
+            }
+        }
+
+        if let Some(route) = &route {
+            while let Ok(mut forwarded) = route.inbox.try_recv() {
+                // Workers with siblings own exactly one socket.
+                handle_server_datagram(
+                    &mut conns,
+                    &mut cids,
+                    &identities,
+                    local_addrs[0],
+                    Some(route),
+                    &mut forwarded.data,
+                    forwarded.meta,
+                );
+            }
+        }
+
+        let mut to_close: Vec<Vec<u8>> = Vec::new();
+
+        for (id, entry) in conns.iter_mut() {
+            let id_hex = hex_string(id);
+            reap(handle_id, dart_port, &id_hex, entry, &options.reap);
+            let allowlist = identities.allowlist(entry.sni_route, &trusted_allowlist);
+            let connection = &mut entry.conn;
+            // Each packet leaves from the socket bound to its path's local
+            // address; a relocated connection has moved off the socket it
+            // was accepted on, and a probe arrives on the one it moves to.
+            let sent = if let Some(tunnel) = tunnel.as_ref().filter(|_| entry.tunneled) {
+                drain_send(
+                    connection,
+                    tunnel,
+                    &mut tx_batches[sockets.len()],
+                    None,
+                    entry.send_cap.as_mut(),
+                    entry.impair.as_mut(),
+                    &mut entry.pacer,
+                    &mut entry.capture,
+                )
+            } else if sockets.len() == 1 {
+                drain_send(
+                    connection,
+                    &sockets[0],
+                    &mut tx_batches[0],
+                    None,
+                    entry.send_cap.as_mut(),
+                    entry.impair.as_mut(),
+                    &mut entry.pacer,
+                    &mut entry.capture,
+                )
+            } else {
+                sockets
+                    .iter()
+                    .zip(tx_batches.iter_mut())
+                    .zip(&local_addrs)
+                    .try_for_each(|((socket, tx_batch), local)| {
+                        drain_send(
+                            connection,
+                            socket,
+                            tx_batch,
+                            Some(*local),
+                            entry.send_cap.as_mut(),
+                            entry.impair.as_mut(),
+                            &mut entry.pacer,
+                            &mut entry.capture,
+                        )
+                    })
+            };
+            if let Err(err) = sent {
+                warn!(
+                    "server send error conn_id={} established={} err={err}",
+                    id_hex,
+                    connection.is_established()
+                );
+                post_event(
+                    dart_port,
+                    QuicEvent::Error {
+                        handle: handle_id,
+                        connection_id: Some(id_hex.clone()),
+                        message: format!("server send error: {err}"),
+                    },
+                );
+                to_close.push(id.clone());
+                continue;
+            }
+            let blocked = sendlimit::blocked(connection, entry.dual.backlogged());
+            entry.send_limit.sample(Instant::now(), blocked);
+
+            post_handshake_progress(
+                handle_id,
+                dart_port,
+                &id_hex,
+                entry.handshake.advance(connection),
+            );
+            let admitted = if connection.is_established()
+                && !entry.announced
+                && connection.local_error().is_none()
+            {
+                let peer_fp = connection.peer_cert().map(sha256_hex);
+                // Without a certificate only the token check can admit it.
+                let trusted = match &peer_fp {
+                    Some(fp) => allowlist.admit(fp),
+                    None if options.require_client_cert => Err("client certificate required"),
+                    None if options.client_cert_optional => Ok(None),
+                    None => Err("no client certificate"),
+                };
+                let label = match trusted {
+                    Ok(label) => label,
+                    Err(reason) => {
+                        warn!(
+                            "rejecting client conn={} fp={}: {reason}",
+                            id_hex,
+                            peer_fp.as_deref().map_or("-".into(), short_hex)
+                        );
+                        let _ = if peer_fp.is_none() && options.require_client_cert {
+                            connection.close(false, handshake::CERTIFICATE_REQUIRED, b"")
+                        } else {
+                            connection.close(false, handshake::UNTRUSTED_PEER, b"untrusted client")
+                        };
+                        audit_conn(
+                            handle_id,
+                            "server",
+                            Outcome::Rejected,
+                            &id_hex,
+                            connection,
+                            Some(reason),
+                        );
+                        // Kept until the close is sent; its handshake_failed
+                        // follows once it has drained.
+                        continue;
+                    }
+                };
+
+                match entry
+                    .auth
+                    .poll(connection, options.auth.as_ref(), &revoked_users)
+                {
+                    Step::Accepted(user) => match options.approval_timeout {
+                        None => Some((peer_fp, user, label)),
+                        Some(timeout) => match entry.approval.poll(timeout) {
+                            Approval::Accepted => Some((peer_fp, user, label)),
+                            Approval::Pending => None,
+                            Approval::Ask => {
+                                post_event(
+                                    dart_port,
+                                    QuicEvent::ConnectionPending {
+                                        handle: handle_id,
+                                        connection_id: id_hex.clone(),
+                                        fingerprint: peer_fp,
+                                        remote_addr: connection
+                                            .path_stats()
+                                            .find(|path| path.active)
+                                            .map(|path| path.peer_addr.to_string()),
+                                        server_name: connection.server_name().map(str::to_string),
+                                    },
+                                );
+                                None
+                            }
+                            Approval::Refused(reason) => {
+                                warn!("conn {id_hex} not approved: {reason}");
+                                let _ = connection.close(
+                                    false,
+                                    auth::APPROVAL_REFUSED,
+                                    b"connection refused",
+                                );
+                                audit_conn(
+                                    handle_id,
+                                    "server",
+                                    Outcome::Rejected,
+                                    &id_hex,
+                                    connection,
+                                    Some(reason),
+                                );
+                                post_event(
+                                    dart_port,
+                                    QuicEvent::Error {
+                                        handle: handle_id,
+                                        connection_id: Some(id_hex.clone()),
+                                        message: format!("connection not approved: {reason}"),
+                                    },
+                                );
+                                None
+                            }
+                        },
+                    },
+                    Step::Pending => None,
+                    Step::Ask(token) => {
+                        post_event(
+                            dart_port,
+                            QuicEvent::AuthRequest {
+                                handle: handle_id,
+                                connection_id: id_hex.clone(),
+                                token,
+                            },
+                        );
+                        None
+                    }
+                    Step::Rejected(reason) => {
+                        auth_rejected(handle_id, dart_port, &id_hex, connection, &reason);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            if let Some((peer_fp, user, label)) = admitted {
+                info!(
+                    "server connection established conn_id={} peer_fp={} user={}",
+                    id_hex,
+                    peer_fp.as_deref().map_or("-".into(), short_hex),
+                    user.as_deref().unwrap_or("-")
+                );
+                entry.announced = true;
+                set_conn_live(handle_id, id, Some(&entry.exporter));
+                if let Some(fp) = peer_fp.as_ref().filter(|_| options.roster) {
+                    roster.join(fp);
+                    entry.roster.joined = Some(fp.clone());
+                }
+                record_usage(handle_id, id, connection, false);
+                audit_conn(
+                    handle_id,
+                    "server",
+                    Outcome::Accepted,
+                    &id_hex,
+                    connection,
+                    None,
+                );
+                post_event(
+                    dart_port,
+                    QuicEvent::Connected {
+                        handle: handle_id,
+                        connection_id: id_hex.clone(),
+                        peer_fingerprint: peer_fp,
+                        peer_cert_der_base64: exported_cert(connection, &options),
+                        user,
+                        label,
+                        server_name: connection.server_name().map(str::to_string),
+                    },
+                );
+                post_cert_expiry(
+                    handle_id,
+                    dart_port,
+                    Some(id_hex.clone()),
+                    CertSide::Peer,
+                    connection.peer_cert().and_then(certexpiry::not_after),
+                    options.cert_warn_days,
+                );
+            }
+
+            let mut app_buf = scratch.pool.take();
+            let mut clock = Vec::new();
+            let mut rotated = Vec::new();
+            let mut notices = Vec::new();
+            let mut subscriptions = Vec::new();
+            // Streams from a client not yet admitted stay queued in quiche,
+            // held back by flow control, until it is.
+            let admitted = entry.announced;
+            entry
+                .channels
+                .poll_writable(connection, options.writable_events, &mut notices);
+            for stream_id in connection.readable().filter(|_| admitted) {
+                loop {
+                    match connection.stream_recv(stream_id, &mut app_buf) {
+                        Ok((read, _fin)) if timesync::is_timesync_stream(stream_id) => {
+                            entry.timesync.on_data(
+                                connection,
+                                stream_id,
+                                &app_buf[..read],
+                                &mut clock,
+                            );
+                        }
+                        Ok((read, _fin)) if identity::is_identity_stream(stream_id) => {
+                            entry.identity.on_data(&app_buf[..read], &mut rotated);
+                        }
+                        Ok((read, _fin)) if capabilities::is_capability_stream(stream_id) => {
+                            entry.capabilities.on_data(&app_buf[..read]);
+                        }
+                        Ok((read, _fin)) if topics::is_topic_stream(stream_id) => {
+                            entry.traffic.received(TrafficClass::Transfer, read);
+                            entry.topics.on_data(&app_buf[..read], &mut subscriptions);
+                        }
+                        // The gate reads the token itself.
+                        Ok(_) if auth::is_auth_stream(stream_id) => {}
+                        Ok((read, fin)) => {
+                            entry.traffic.received(
+                                TrafficClass::of_stream(stream_id, CONTROL_STREAM_ID),
+                                read,
+                            );
+                            let skip = if channels::is_channel_stream(stream_id) {
+                                entry.channels.on_data(
+                                    connection,
+                                    stream_id,
+                                    &app_buf[..read],
+                                    &mut notices,
+                                )
+                            } else {
+                                0
+                            };
+                            let body = &app_buf[skip..read];
+                            let (raw, violation) = match entry.recv_guard.check(
+                                stream_id,
+                                body,
+                                fin,
+                                &options.recv_limits,
+                            ) {
+                                Ok(()) => (body, None),
+                                Err(v) => (&body[..v.forward], Some(v)),
+                            };
+                            let limit = inflate_limit(&options);
+                            let data = match entry
+                                .control_codec
+                                .decode(connection, stream_id, raw, limit)
+                            {
+                                Ok(data) => data,
+                                Err(err) => {
+                                    reject_inflate(
+                                        handle_id, dart_port, &id_hex, connection, stream_id, err,
+                                        limit,
+                                    );
+                                    break;
+                                }
+                            };
+                            if !data.is_empty() {
+                                scratch.events.post(
+                                    dart_port,
+                                    &QuicEvent::Message {
+                                        handle: handle_id,
+                                        connection_id: id_hex.clone(),
+                                        channel: entry.channels.name(stream_id),
+                                        topic: None,
+                                        data: &data,
+                                    },
+                                );
+                            }
+                            if fin && channels::is_channel_stream(stream_id) {
+                                entry.channels.on_fin(connection, stream_id, &mut notices);
+                            }
+                            if let Some(violation) = violation {
+                                reject_oversized(
+                                    handle_id, dart_port, &id_hex, connection, stream_id, violation,
+                                );
+                                break;
+                            }
+                        }
+                        Err(quiche::Error::Done) => break,
+                        Err(quiche::Error::StreamReset(error_code)) => {
+                            entry.channels.on_reset(
+                                connection,
+                                stream_id,
+                                error_code,
+                                &mut notices,
+                            );
+                            break;
+                        }
+                        Err(err) => {
+                            let trace_id = connection.trace_id();
+                            warn!("{trace_id} server stream read error: {err:?}");
+                            break;
+                        }
+                    }
+                }
+            }
+            scratch.pool.give(app_buf);
+            post_timesync(handle_id, dart_port, &id_hex, &clock);
+            post_channel_notices(handle_id, dart_port, &id_hex, notices);
+            for (topic, subscribed) in subscriptions {
+                post_event(
+                    dart_port,
+                    QuicEvent::Subscription {
+                        handle: handle_id,
+                        connection_id: id_hex.clone(),
+                        topic,
+                        subscribed,
+                    },
+                );
+            }
+            let sent = entry.topics.flush(connection);
+            entry.traffic.sent(TrafficClass::Transfer, sent);
+            if options.roster && entry.announced {
+                entry.roster.update(connection, &roster);
+            }
+            post_rotations(
+                handle_id,
+                dart_port,
+                &id_hex,
+                connection,
+                rotated,
+                Some(allowlist),
+            );
+            if options.observed_addr && entry.announced {
+                report_observed(connection, &mut entry.reported_addr);
+            }
+            if options.capabilities != 0 && entry.announced {
+                let local = capabilities::available(options.capabilities, connection);
+                if let Some(negotiated) = entry.capabilities.poll(connection, true, local) {
+                    post_capabilities(handle_id, dart_port, &id_hex, negotiated);
+                }
+            }
+            if let Some(addr) = preferred.filter(|_| entry.announced) {
+                announce_preferred(connection, addr, &mut entry.told_preferred);
+            }
+            cids.refresh(connection, id, route.as_ref().map(|r| r.index));
+            entry
+                .dual
+                .flush_control(connection, CONTROL_STREAM_ID, &options.dual);
+            entry.timesync.on_timer(connection, Instant::now());
+            keepalive(&options, connection, &mut entry.last_keepalive);
+
+            let (dgram_max, dgram_policy) =
+                (options.dgram_recv_queue_len, options.dgram_drop_policy);
+            entry.dgrams.pull(connection, dgram_max, dgram_policy);
+            entry.media.send_due(connection, Instant::now());
+            // Datagrams wait in the receive queue until the client is admitted.
+            if entry.announced {
+                deliver_media(
+                    handle_id,
+                    dart_port,
+                    &id_hex,
+                    &mut entry.dgrams,
+                    &mut entry.media,
+                    &options,
+                    &mut scratch.events,
+                );
+            }
+
+            if stats_due(&options, entry.announced, &mut entry.last_stats) {
+                let dgram = entry.dgrams.stats(connection, dgram_max, dgram_policy);
+                let local = LocalStats {
+                    ecn: entry.ecn,
+                    dgram,
+                    media: entry.media.stats(),
+                    control: entry.dual.stats(&options.dual),
+                    flow: entry.bdp.stats(connection, &options.flow_window),
+                    send_limit: entry.send_limit.stats(Instant::now()),
+                    traffic: entry
+                        .traffic
+                        .stats(entry.media.sent_bytes(), entry.dgrams.received_bytes()),
+                    blocklist: Some(blocklist.stats()),
+                };
+                let event = stats_event(handle_id, &id_hex, connection, local);
+                post_event(dart_port, event);
+            }
+            if entry.announced && entry.last_usage.elapsed() >= usage::PUBLISH_INTERVAL {
+                entry.last_usage = Instant::now();
+                record_usage(handle_id, id, connection, false);
+            }
+
+            if connection.is_closed() {
+                let reason = connection.peer_error().map(|err| format!("{err:?}"));
+                info!(
+                    "server connection {} closed established={} ({:?}) stats={}",
+                    id_hex,
+                    connection.is_established(),
+                    reason,
+                    format_stats(&connection.stats())
+                );
+                set_conn_live(handle_id, id, None);
+                if !entry.announced {
+                    post_handshake_failed(
+                        handle_id,
+                        dart_port,
+                        &id_hex,
+                        &entry.handshake,
+                        connection,
+                    );
+                }
+                record_usage(handle_id, id, connection, true);
+                audit_conn(
+                    handle_id,
+                    "server",
+                    Outcome::Closed,
+                    &id_hex,
+                    connection,
+                    reason.as_deref(),
+                );
+                post_event(
+                    dart_port,
+                    QuicEvent::Closed {
+                        handle: handle_id,
+                        connection_id: id_hex.clone(),
+                        reason,
+                    },
+                );
+                to_close.push(id.clone());
+                continue;
+            }
+        }
+
+        for (socket, tx_batch) in sockets.iter().chain(&tunnel).zip(&mut tx_batches) {
+            if let Err(err) = socket.send_batch(tx_batch) {
+                warn!("server udp send error: {err}");
+            }
+        }
+
+        for id in to_close {
+            if let Some(fp) = conns.remove(&id).and_then(|entry| entry.roster.joined) {
+                roster.leave(&fp);
+            }
+            cids.remove_conn(&id);
+        }
+        if options.roster && lead && roster.version() != roster_posted {
+            roster_posted = roster.version();
+            post_event(
+                dart_port,
+                QuicEvent::RosterChanged {
+                    handle: handle_id,
+                    connection_id: None,
+                    peers: roster.snapshot(None),
+                },
+            );
+        }
+        if lead {
+            if let Some(reloaded) = trusted_allowlist.poll() {
+                post_allowlist_reload(handle_id, dart_port, reloaded);
+            }
+        }
+        if trusted_allowlist.version() != allowlist_seen {
+            allowlist_seen = trusted_allowlist.version();
+            // Route allowlists do not change.
+            let default_routed = |entry: &ServerConnection| entry.sni_route.is_none();
+            for (id, entry) in conns
+                .iter_mut()
+                .filter(|(_, entry)| entry.announced && default_routed(entry))
+            {
+                // Certificate-less clients never went through the list.
+                let Some(fp) = entry.conn.peer_cert().map(sha256_hex) else {
+                    continue;
+                };
+                if trusted_allowlist.admit(&fp).is_err() {
+                    info!(
+                        "closing conn {} no longer on the allowlist fp={}",
+                        hex_string(id),
+                        short_hex(&fp)
+                    );
+                    let _ = entry
+                        .conn
+                        .close(false, handshake::UNTRUSTED_PEER, b"untrusted client");
+                }
+            }
+        }
+
+        wait_server_timers(&sockets, tunnel.as_ref(), &mut conns);
+    }
+}
+
+/// Closes `entry` once it is over `policy`'s limit for its state.
+fn reap(
+    handle_id: u64,
+    dart_port: i64,
+    id_hex: &str,
+    entry: &mut ServerConnection,
+    policy: &ReapPolicy,
+) {
+    if entry.reaped || entry.conn.is_closed() {
+        return;
+    }
+    let age = entry.started.elapsed();
+    let Some(reason) = policy.due(entry.conn.is_established(), age) else {
+        return;
+    };
+    info!("reaping server conn {id_hex} ({reason:?}) after {age:?}");
+    entry.reaped = true;
+    let (code, phrase) = reason.close_frame();
+    let _ = entry.conn.close(false, code, phrase);
+    post_event(
+        dart_port,
+        QuicEvent::ConnectionReaped {
+            handle: handle_id,
+            connection_id: id_hex.to_string(),
+            reason,
+            age_ms: age.as_millis() as u64,
+        },
+    );
+}
+
+/// Waits once for the earliest quiche timer or paced packet across the
+/// worker's connections (capped like the client workers), waking early when a
+/// datagram arrives, then fires only the timers that expired, so one idle
+/// connection never delays the others.
+fn wait_server_timers(
+    sockets: &[QuicSocket],
+    tunnel: Option<&QuicSocket>,
+    conns: &mut HashMap<Vec<u8>, ServerConnection>,
+) {
+    let now = Instant::now();
+    let next = conns
+        .values()
+        .flat_map(|entry| [entry.conn.timeout(), entry.pacer.next_release(now)])
+        .flatten()
+        .min()
+        .unwrap_or(Duration::from_millis(2));
+    let wait = next.min(Duration::from_millis(5));
+    if !wait.is_zero() {
+        socket::wait_readable(sockets.iter().chain(tunnel), wait);
+    }
+    for (id, entry) in conns.iter_mut() {
+        if !entry.conn.timeout().is_some_and(|t| t.is_zero()) {
+            continue;
+        }
+        if !entry.conn.is_established() {
+            warn!(
+                "server conn {} handshake timeout fired after {:?} stats={}",
+                hex_string(id),
+                entry.started.elapsed(),
+                format_stats(&entry.conn.stats())
+            );
+        }
+        entry.conn.on_timeout();
+    }
+}
+
+fn cstr_to_string(ptr: *const c_char) -> Result<String, CcQuicStatus> {
+    if ptr.is_null() {
+        return Err(CcQuicStatus::NullPointer);
+    }
+    unsafe {
+        CStr::from_ptr(ptr)
             .to_str()
             .map(|s| s.to_string())
             .map_err(|_| CcQuicStatus::Internal)
     }
 }
 
-fn parse_allowlist(csv: &str) -> HashSet<String> {
-    csv.split(',')
-        .filter_map(|s| {
-            let trimmed = s.trim();
-            if trimmed.is_empty() {
-                None
-            } else {
-                Some(trimmed.to_lowercase())
+fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    let digest = hasher.finalize();
+    digest
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>()
+}
+
+fn hex_string(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn format_stats(stats: &quiche::Stats) -> String {
+    format!(
+        "tx {} pkts ({} retrans) rx {} pkts lost {} spurious {}",
+        stats.sent, stats.retrans, stats.recv, stats.lost, stats.spurious_lost
+    )
+}
+
+/// Pulls every packet quiche has ready for `conn` into `batch`, flushing to
+/// the socket whenever the batch fills; the tail is left for the caller.
+/// With `from` set only paths on that local address are served.
+/// Stops early once the connection's send cap is spent. Until a path is
+/// validated quiche holds it to 3x the bytes received on it, so `Done` may
+/// mean amplification-limited; that connection then waits for more client
+/// bytes or its timer without holding up the others. An impaired
+/// connection's packets go through `impair` instead, which releases them
+/// here once they are due. A packet quiche paces for later (`at`) waits in
+/// `pacer`, and nothing more is built for `from` until it has gone. Each
+/// packet is also written to `capture`, if one is running.
+#[allow(clippy::too_many_arguments)]
+fn drain_send(
+    conn: &mut quiche::Connection,
+    socket: &QuicSocket,
+    batch: &mut SendBatch,
+    from: Option<SocketAddr>,
+    mut cap: Option<&mut TokenBucket>,
+    mut impair: Option<&mut Impairment>,
+    pacer: &mut Pacer,
+    capture: &mut Option<PacketCapture>,
+) -> Result<(), quiche::Error> {
+    let now = Instant::now();
+    if let Some(impair) = impair.as_deref_mut() {
+        while let Some((data, to)) = impair.due(now, from) {
+            batch.slot_mut()[..data.len()].copy_from_slice(&data);
+            batch.push(data.len(), to);
+            if batch.is_full() {
+                if let Err(err) = socket.send_batch(batch) {
+                    warn!("udp send error: {err}");
+                }
+            }
+        }
+    }
+    if let Some((data, to)) = pacer.take_due(from, now) {
+        if let Some(impair) = impair.as_deref_mut() {
+            impair.submit(&data, from, to, now);
+        } else {
+            batch.slot_mut()[..data.len()].copy_from_slice(&data);
+            batch.push(data.len(), to);
+            if batch.is_full() {
+                if let Err(err) = socket.send_batch(batch) {
+                    warn!("udp send error: {err}");
+                }
+            }
+        }
+    }
+    if pacer.waiting(from) {
+        return Ok(());
+    }
+    loop {
+        if let Some(cap) = cap.as_deref_mut() {
+            if !cap.ready(now) {
+                return Ok(());
+            }
+        }
+        match conn.send_on_path(batch.slot_mut(), from, None) {
+            Ok((len, send_info)) => {
+                if let Some(cap) = cap.as_deref_mut() {
+                    cap.consume(len);
+                }
+                capture::record(
+                    capture,
+                    send_info.from,
+                    send_info.to,
+                    &batch.slot_mut()[..len],
+                );
+                if Pacer::early(send_info.at, now) {
+                    pacer.hold(from, send_info.to, send_info.at, &batch.slot_mut()[..len]);
+                    return Ok(());
+                }
+                if let Some(impair) = impair.as_deref_mut() {
+                    impair.submit(&batch.slot_mut()[..len], from, send_info.to, now);
+                    continue;
+                }
+                batch.push(len, send_info.to);
+                if batch.is_full() {
+                    if let Err(err) = socket.send_batch(batch) {
+                        warn!("udp send error: {err}");
+                    }
+                }
+            }
+            Err(quiche::Error::Done) => return Ok(()),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Sends a PING every `keepalive_ms` so NAT bindings on the path stay open
+/// through quiet periods.
+fn keepalive(options: &WorkerOptions, conn: &mut quiche::Connection, last: &mut Instant) {
+    if options.keepalive_ms == 0 || !conn.is_established() {
+        return;
+    }
+    if last.elapsed() < Duration::from_millis(options.keepalive_ms) {
+        return;
+    }
+    *last = Instant::now();
+    if let Err(err) = conn.send_ack_eliciting() {
+        log::debug!("keepalive not sent: {err:?}");
+    }
+}
+
+fn stats_due(options: &WorkerOptions, established: bool, last: &mut Instant) -> bool {
+    if options.stats_interval_ms == 0 || !established {
+        return false;
+    }
+    if last.elapsed() < Duration::from_millis(options.stats_interval_ms) {
+        return false;
+    }
+    *last = Instant::now();
+    true
+}
+
+/// Counters the worker keeps itself, next to quiche's own.
+struct LocalStats {
+    ecn: EcnCounts,
+    dgram: DgramStats,
+    media: MediaStats,
+    control: ControlStats,
+    flow: FlowStats,
+    send_limit: SendLimitStats,
+    traffic: TrafficStats,
+    blocklist: Option<BlocklistStats>,
+}
+
+/// One path of a connection for `stats`. quiche 0.24 has no multipath, so
+/// a second path only lives while a migration, relocation or rebinding is
+/// probed or the old path lingers.
+#[derive(Debug, Serialize)]
+struct PathReport {
+    local_addr: String,
+    peer_addr: String,
+    state: &'static str,
+    /// The path carrying the connection's traffic.
+    active: bool,
+    rtt_ms: f64,
+    cwnd: u64,
+    sent_bytes: u64,
+    recv_bytes: u64,
+    lost_bytes: u64,
+}
+
+impl PathReport {
+    fn new(path: &quiche::PathStats) -> Self {
+        Self {
+            local_addr: path.local_addr.to_string(),
+            peer_addr: path.peer_addr.to_string(),
+            // quiche does not export `PathState`, only its Debug names.
+            state: match format!("{:?}", path.validation_state).as_str() {
+                "Failed" => "failed",
+                "Validating" => "validating",
+                "ValidatingMTU" => "validating_mtu",
+                "Validated" => "validated",
+                _ => "unknown",
+            },
+            active: path.active,
+            rtt_ms: path.rtt.as_secs_f64() * 1000.0,
+            cwnd: path.cwnd as u64,
+            sent_bytes: path.sent_bytes,
+            recv_bytes: path.recv_bytes,
+            lost_bytes: path.lost_bytes,
+        }
+    }
+}
+
+fn stats_event(
+    handle: u64,
+    conn_id_hex: &str,
+    conn: &quiche::Connection,
+    local: LocalStats,
+) -> QuicEvent<'static> {
+    let stats = conn.stats();
+    let path = conn.path_stats().find(|path| path.active);
+    let mut paths: Vec<PathReport> = conn
+        .path_stats()
+        .map(|path| PathReport::new(&path))
+        .collect();
+    if paths.len() < 2 {
+        paths.clear();
+    }
+    QuicEvent::Stats {
+        handle,
+        connection_id: conn_id_hex.to_string(),
+        rtt_ms: path
+            .as_ref()
+            .map(|p| p.rtt.as_secs_f64() * 1000.0)
+            .unwrap_or_default(),
+        cwnd: path.as_ref().map(|p| p.cwnd as u64).unwrap_or_default(),
+        pmtu: path.as_ref().map(|p| p.pmtu as u64).unwrap_or_default(),
+        sent: stats.sent as u64,
+        recv: stats.recv as u64,
+        lost: stats.lost as u64,
+        retrans: stats.retrans as u64,
+        sent_bytes: stats.sent_bytes,
+        recv_bytes: stats.recv_bytes,
+        ecn: local.ecn,
+        dgram: local.dgram,
+        media: local.media,
+        control: local.control,
+        flow: local.flow,
+        send_limit: local.send_limit,
+        traffic: local.traffic,
+        blocklist: local.blocklist,
+        paths,
+    }
+}
+
+/// Runs received datagrams through the media reorder window (and jitter
+/// buffer, if configured) and posts the frames due for playout.
+fn deliver_media(
+    handle_id: u64,
+    dart_port: i64,
+    conn_id_hex: &str,
+    dgrams: &mut DgramInbox,
+    media: &mut MediaChannel,
+    options: &WorkerOptions,
+    events: &mut EventEncoder,
+) {
+    let mut released: Vec<MediaFrame> = Vec::new();
+    while let Some(dgram) = dgrams.pop() {
+        media.receive(&dgram, options.media_reorder_window, &mut released);
+    }
+    let due = media.playout(released, Instant::now(), options.jitter.as_ref());
+    for frame in due {
+        events.post(
+            dart_port,
+            &QuicEvent::Media {
+                handle: handle_id,
+                connection_id: conn_id_hex.to_string(),
+                media_seq: frame.seq,
+                timestamp: frame.timestamp,
+                marker: frame.marker,
+                data: &frame.payload,
+            },
+        );
+    }
+}
+
+/// Records a client the auth gate turned away; the gate already told it
+/// and started the close.
+fn auth_rejected(
+    handle_id: u64,
+    dart_port: i64,
+    conn_id_hex: &str,
+    conn: &quiche::Connection,
+    reason: &str,
+) {
+    warn!("conn {conn_id_hex} authentication failed: {reason}");
+    audit_conn(
+        handle_id,
+        "server",
+        Outcome::Rejected,
+        conn_id_hex,
+        conn,
+        Some(reason),
+    );
+    post_event(
+        dart_port,
+        QuicEvent::Error {
+            handle: handle_id,
+            connection_id: Some(conn_id_hex.to_string()),
+            message: format!("authentication failed: {reason}"),
+        },
+    );
+}
+
+/// Posts `allowlist_reloaded` after the lead worker reloaded the allowlist
+/// file, or an error if it could not be read (the old list stays).
+fn post_allowlist_reload(handle_id: u64, dart_port: i64, reloaded: std::io::Result<usize>) {
+    let event = match reloaded {
+        Ok(entries) => {
+            info!("allowlist reloaded entries={entries}");
+            QuicEvent::AllowlistReloaded {
+                handle: handle_id,
+                entries,
             }
-        })
-        .collect()
+        }
+        Err(err) => {
+            warn!("allowlist reload failed: {err}");
+            QuicEvent::Error {
+                handle: handle_id,
+                connection_id: None,
+                message: format!("allowlist reload failed: {err}"),
+            }
+        }
+    };
+    post_event(dart_port, event);
 }
 
-fn sha256_hex(data: &[u8]) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(data);
-    let digest = hasher.finalize();
-    digest
-        .iter()
-        .map(|b| format!("{b:02x}"))
-        .collect::<String>()
+/// Reports a receive-cap violation and resets the stream, closing the
+/// connection when its own cap was hit.
+fn reject_oversized(
+    handle_id: u64,
+    dart_port: i64,
+    conn_id_hex: &str,
+    conn: &mut quiche::Connection,
+    stream_id: u64,
+    violation: Violation,
+) {
+    warn!(
+        "conn {conn_id_hex} stream {stream_id} over {:?} receive limit ({} > {})",
+        violation.scope, violation.size, violation.limit
+    );
+    let _ = conn.stream_shutdown(stream_id, quiche::Shutdown::Read, RECV_LIMIT_ERROR);
+    let _ = conn.stream_shutdown(stream_id, quiche::Shutdown::Write, RECV_LIMIT_ERROR);
+    if violation.scope == LimitScope::Connection {
+        let _ = conn.close(false, RECV_LIMIT_ERROR, b"receive limit");
+    }
+    post_event(
+        dart_port,
+        QuicEvent::MessageTooLarge {
+            handle: handle_id,
+            connection_id: conn_id_hex.to_string(),
+            stream_id,
+            scope: violation.scope,
+            limit: violation.limit,
+            size: violation.size,
+        },
+    );
 }
 
-fn hex_string(data: &[u8]) -> String {
-    data.iter().map(|b| format!("{b:02x}")).collect()
+/// Largest control frame the codec may inflate.
+fn inflate_limit(options: &WorkerOptions) -> u64 {
+    options
+        .recv_limits
+        .max_message
+        .unwrap_or(MAX_INFLATED_FRAME)
 }
 
-fn format_stats(stats: &quiche::Stats) -> String {
-    format!(
-        "tx {} pkts ({} retrans) rx {} pkts lost {} spurious {}",
-        stats.sent, stats.retrans, stats.recv, stats.lost, stats.spurious_lost
-    )
+/// An oversized compressed frame is a receive-cap violation; one that does
+/// not inflate ends the connection, as the peer broke the negotiated framing.
+fn reject_inflate(
+    handle_id: u64,
+    dart_port: i64,
+    conn_id_hex: &str,
+    conn: &mut quiche::Connection,
+    stream_id: u64,
+    err: InflateError,
+    limit: u64,
+) {
+    match err {
+        InflateError::TooLarge(size) => reject_oversized(
+            handle_id,
+            dart_port,
+            conn_id_hex,
+            conn,
+            stream_id,
+            Violation {
+                scope: LimitScope::Message,
+                limit,
+                size,
+                forward: 0,
+            },
+        ),
+        InflateError::Corrupt => {
+            warn!("conn {conn_id_hex} sent a corrupt compressed frame");
+            let _ = conn.close(false, RECV_LIMIT_ERROR, b"corrupt compressed frame");
+            post_event(
+                dart_port,
+                QuicEvent::Error {
+                    handle: handle_id,
+                    connection_id: Some(conn_id_hex.to_string()),
+                    message: "corrupt compressed control frame".to_string(),
+                },
+            );
+        }
+    }
+}
+
+/// Posts `peer_identity_rotated` for each fingerprint the peer of `conn`
+/// announced, adding it to a non-empty `allowlist` that trusts the peer's
+/// current certificate.
+fn post_rotations(
+    handle_id: u64,
+    dart_port: i64,
+    conn_id_hex: &str,
+    conn: &quiche::Connection,
+    rotated: Vec<String>,
+    allowlist: Option<&Allowlist>,
+) {
+    if rotated.is_empty() {
+        return;
+    }
+    let old_fingerprint = conn.peer_cert().map(sha256_hex).unwrap_or_default();
+    for new_fingerprint in rotated {
+        info!(
+            "peer identity rotated conn_id={conn_id_hex} {} -> {}",
+            short_hex(&old_fingerprint),
+            short_hex(&new_fingerprint)
+        );
+        if let Some(allowlist) = allowlist {
+            allowlist.rotate(&old_fingerprint, &new_fingerprint);
+        }
+        post_event(
+            dart_port,
+            QuicEvent::PeerIdentityRotated {
+                handle: handle_id,
+                connection_id: conn_id_hex.to_string(),
+                old_fingerprint: old_fingerprint.clone(),
+                new_fingerprint,
+            },
+        );
+    }
+}
+
+/// Reports the peer's address on the active path to it when it differs
+/// from the last report.
+fn report_observed(conn: &mut quiche::Connection, reported: &mut Option<SocketAddr>) {
+    let Some(peer) = conn
+        .path_stats()
+        .find(|path| path.active)
+        .map(|path| path.peer_addr)
+    else {
+        return;
+    };
+    if *reported == Some(peer) {
+        return;
+    }
+    match observed::send(conn, AddressRecord::Observed(peer)) {
+        Ok(()) => *reported = Some(peer),
+        // Out of stream credit; retried on the next pass.
+        Err(quiche::Error::Done) => {}
+        Err(err) => {
+            warn!("{} observed address report error: {err:?}", conn.trace_id());
+            *reported = Some(peer);
+        }
+    }
+}
+
+/// Names `preferred` to the client when it has not been told yet and is
+/// not already talking to it.
+fn announce_preferred(
+    conn: &mut quiche::Connection,
+    preferred: SocketAddr,
+    told: &mut Option<SocketAddr>,
+) {
+    if *told == Some(preferred) {
+        return;
+    }
+    if conn
+        .path_stats()
+        .find(|path| path.active)
+        .map(|path| path.local_addr)
+        == Some(preferred)
+    {
+        *told = Some(preferred);
+        return;
+    }
+    match observed::send(conn, AddressRecord::Preferred(preferred)) {
+        Ok(()) => *told = Some(preferred),
+        // Out of stream credit; retried on the next pass.
+        Err(quiche::Error::Done) => {}
+        Err(err) => {
+            warn!(
+                "{} preferred address announce error: {err:?}",
+                conn.trace_id()
+            );
+            *told = Some(preferred);
+        }
+    }
+}
+
+fn post_channel_notices(handle_id: u64, dart_port: i64, conn_id_hex: &str, notices: Vec<Notice>) {
+    for notice in notices {
+        let event = match notice {
+            Notice::Opened {
+                channel,
+                stream_id,
+                by_peer,
+            } => QuicEvent::ChannelOpened {
+                handle: handle_id,
+                connection_id: conn_id_hex.to_string(),
+                channel,
+                stream_id,
+                by_peer,
+            },
+            Notice::Closed { channel } => QuicEvent::ChannelClosed {
+                handle: handle_id,
+                connection_id: conn_id_hex.to_string(),
+                channel,
+            },
+            Notice::Refused { channel } => QuicEvent::Error {
+                handle: handle_id,
+                connection_id: Some(conn_id_hex.to_string()),
+                message: format!("channel {channel}: refused by peer"),
+            },
+            Notice::Reset {
+                channel,
+                stream_id,
+                error_code,
+            } => QuicEvent::StreamReset {
+                handle: handle_id,
+                connection_id: conn_id_hex.to_string(),
+                stream_id,
+                channel,
+                error_code,
+            },
+            Notice::Stopped {
+                channel,
+                stream_id,
+                error_code,
+            } => QuicEvent::StreamStopped {
+                handle: handle_id,
+                connection_id: conn_id_hex.to_string(),
+                stream_id,
+                channel,
+                error_code,
+            },
+            Notice::Writable {
+                channel,
+                stream_id,
+                capacity,
+            } => QuicEvent::StreamWritable {
+                handle: handle_id,
+                connection_id: conn_id_hex.to_string(),
+                stream_id,
+                channel,
+                capacity,
+            },
+        };
+        post_event(dart_port, event);
+    }
+}
+
+fn post_handshake_progress(
+    handle_id: u64,
+    dart_port: i64,
+    conn_id_hex: &str,
+    stages: Vec<(Stage, u64)>,
+) {
+    for (state, elapsed_ms) in stages {
+        post_event(
+            dart_port,
+            QuicEvent::HandshakeProgress {
+                handle: handle_id,
+                connection_id: conn_id_hex.to_string(),
+                state,
+                elapsed_ms,
+            },
+        );
+    }
+}
+
+fn post_handshake_failed(
+    handle_id: u64,
+    dart_port: i64,
+    conn_id_hex: &str,
+    progress: &HandshakeProgress,
+    conn: &quiche::Connection,
+) {
+    let failure = progress.failure(conn);
+    info!(
+        "{conn_id_hex} handshake failed: {:?}, {}",
+        failure.cause, failure.detail
+    );
+    post_event(
+        dart_port,
+        QuicEvent::HandshakeFailed {
+            handle: handle_id,
+            connection_id: conn_id_hex.to_string(),
+            failure,
+        },
+    );
+}
+
+fn post_timesync(handle_id: u64, dart_port: i64, conn_id_hex: &str, estimates: &[Estimate]) {
+    for estimate in estimates {
+        post_event(
+            dart_port,
+            QuicEvent::TimeSync {
+                handle: handle_id,
+                connection_id: conn_id_hex.to_string(),
+                offset_us: estimate.offset_us,
+                rtt_us: estimate.rtt_us,
+                dispersion_us: estimate.dispersion_us,
+                samples: estimate.samples,
+            },
+        );
+    }
+}
+
+fn post_capabilities(
+    handle_id: u64,
+    dart_port: i64,
+    conn_id_hex: &str,
+    negotiated: capabilities::Negotiated,
+) {
+    post_event(
+        dart_port,
+        QuicEvent::CapabilitiesNegotiated {
+            handle: handle_id,
+            connection_id: conn_id_hex.to_string(),
+            version: negotiated.version,
+            local: capabilities::names(negotiated.local),
+            peer: capabilities::names(negotiated.peer),
+            resolved: capabilities::names(negotiated.resolved()),
+        },
+    );
 }
 
 fn short_hex(hex: &str) -> String {
@@ -986,17 +5019,230 @@ fn short_hex(hex: &str) -> String {
     let prefix_len = 6.min(trimmed.len());
     let suffix_len = 4.min(trimmed.len().saturating_sub(prefix_len));
     let suffix_start = trimmed.len().saturating_sub(suffix_len);
-    format!(
-        "{}...{}",
-        &trimmed[..prefix_len],
-        &trimmed[suffix_start..]
-    )
+    format!("{}...{}", &trimmed[..prefix_len], &trimmed[suffix_start..])
+}
+
+/// Runs a worker body, reporting a panic as `worker_died` followed by
+/// `error` so the Dart side does not wait on a dead handle. Returns false if
+/// the body panicked.
+fn run_guarded(handle_id: u64, dart_port: i64, body: impl FnOnce()) -> bool {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(()) => true,
+        Err(payload) => {
+            report_worker_death(handle_id, dart_port, &panic_message(payload.as_ref()));
+            false
+        }
+    }
+}
+
+fn report_worker_death(handle_id: u64, dart_port: i64, message: &str) {
+    error!("worker for handle {handle_id} died: {message}");
+    post_event(
+        dart_port,
+        QuicEvent::WorkerDied {
+            handle: handle_id,
+            message: message.to_string(),
+        },
+    );
+    post_event(
+        dart_port,
+        QuicEvent::Error {
+            handle: handle_id,
+            connection_id: None,
+            message: format!("worker died: {message}"),
+        },
+    );
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+fn remove_handle(handle_id: u64) {
+    coalesce::registry().withdraw(handle_id);
+    if let Some(map) = CONNECTIONS.get() {
+        map.remove(&handle_id);
+    }
+}
+
+/// Undoes a start that failed after its handle was made: the caller never
+/// sees the handle, so its queued events go too.
+fn abandon_handle(handle_id: u64) {
+    remove_handle(handle_id);
+    poll::detach(handle_id);
+}
+
+/// Loads the pair into a scratch config, so a mismatched key or unreadable
+/// file fails the rotation instead of the next handshake.
+fn check_identity(cert_path: &str, key_path: &str) -> Result<(), Error> {
+    let mut scratch = quiche::Config::new(quiche::PROTOCOL_VERSION)
+        .map_err(|err| Error::Internal(format!("quiche config: {err}")))?;
+    load_identity(&mut scratch, cert_path, key_path)?;
+    check_local_cert(cert_path).map(|_| ())
+}
+
+/// Loads the certificate chain and private key into `config`.
+fn load_identity(
+    config: &mut quiche::Config,
+    cert_path: &str,
+    key_path: &str,
+) -> Result<(), Error> {
+    let failed = |path: &str, what: &str, err: quiche::Error| {
+        error!("load {what} error: {err}");
+        Error::CertParse {
+            path: path.to_string(),
+            detail: format!("{what} did not load: {err}"),
+        }
+    };
+    config
+        .load_cert_chain_from_pem_file(cert_path)
+        .map_err(|err| failed(cert_path, "certificate", err))?;
+    config
+        .load_priv_key_from_pem_file(key_path)
+        .map_err(|err| failed(key_path, "key", err))
+}
+
+/// Adds the CAs in `ca` (a PEM file, or a directory of hashed PEM names)
+/// to the ones `config` verifies the peer with.
+fn load_ca(config: &mut quiche::Config, ca: &std::path::Path) -> std::io::Result<()> {
+    let path = ca
+        .to_str()
+        .ok_or_else(|| std::io::Error::other(format!("CA {ca:?} is not UTF-8")))?;
+    let loaded = if ca.is_dir() {
+        config.load_verify_locations_from_directory(path)
+    } else {
+        config.load_verify_locations_from_file(path)
+    };
+    loaded.map_err(|err| std::io::Error::other(format!("CA {path}: {err:?}")))
+}
+
+/// Refuses an expired certificate at `cert_path` and returns its
+/// `notAfter` for `post_cert_expiry`; one it cannot parse passes unchecked.
+fn check_local_cert(cert_path: &str) -> Result<Option<i64>, Error> {
+    let not_after = certexpiry::pem_not_after(cert_path);
+    if let Some(expiry) = not_after {
+        if certexpiry::days_left(expiry, std::time::SystemTime::now()) < 0 {
+            error!("local certificate {cert_path} has expired");
+            return Err(Error::CertExpired {
+                path: cert_path.to_string(),
+            });
+        }
+    }
+    Ok(not_after)
 }
 
-fn post_event(port: i64, event: QuicEvent) {
-    if let Ok(json) = serde_json::to_string(&event) {
-        let _ = Isolate::new(port).post(json);
+fn post_cert_expiry(
+    handle: u64,
+    dart_port: i64,
+    connection_id: Option<String>,
+    which: CertSide,
+    not_after: Option<i64>,
+    warn_days: u32,
+) {
+    if let Some(days_left) = certexpiry::warning(not_after, warn_days) {
+        warn!("{which:?} certificate expires in {days_left} days");
+        post_event(
+            dart_port,
+            QuicEvent::CertExpiringSoon {
+                handle,
+                connection_id,
+                which,
+                days_left,
+            },
+        );
+    }
+}
+
+fn exported_cert(conn: &quiche::Connection, options: &WorkerOptions) -> Option<String> {
+    let cert = conn.peer_cert().filter(|_| options.peer_cert)?;
+    Some(BASE64.encode(cert))
+}
+
+fn serialize_base64<S: serde::Serializer>(data: &&[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&Base64Display::new(data, &BASE64))
+}
+
+fn post_event(port: i64, event: QuicEvent<'_>) {
+    EventEncoder::default().post(port, &event);
+}
+
+/// Appends a connection record to the audit log, if one is enabled.
+fn audit_conn(
+    handle: u64,
+    role: &'static str,
+    outcome: Outcome,
+    conn_id_hex: &str,
+    conn: &quiche::Connection,
+    reason: Option<&str>,
+) {
+    let server = role == "server";
+    match outcome {
+        Outcome::Accepted => metrics::connection_accepted(server),
+        Outcome::Rejected => metrics::handshake_failed(server, true),
+        Outcome::Closed if !conn.is_established() => metrics::handshake_failed(server, false),
+        Outcome::Closed => {}
     }
+    let fingerprint = conn.peer_cert().map(sha256_hex).unwrap_or_default();
+    audit::record(&audit::Record {
+        ts_ms: audit::now_ms(),
+        handle,
+        role,
+        outcome,
+        connection_id: conn_id_hex,
+        remote: conn
+            .path_stats()
+            .find(|path| path.active)
+            .map(|path| path.peer_addr),
+        fingerprint: &fingerprint,
+        reason,
+    });
+}
+
+/// Records a connection of `handle` as announced, with its exporter secret,
+/// or as closed (`None`), for the check in `send_command`.
+fn set_conn_live(handle: u64, conn_id: &[u8], exporter: Option<&Arc<exporter::Secret>>) {
+    let Some(entry) = CONNECTIONS.get().and_then(|map| map.get(&handle)) else {
+        return;
+    };
+    let mut ids = entry.live.lock().unwrap_or_else(PoisonError::into_inner);
+    match exporter {
+        Some(secret) => ids.insert(conn_id.to_vec(), secret.clone()),
+        None => ids.remove(conn_id),
+    };
+}
+
+/// Publishes the byte counts of an announced connection of `handle` to its
+/// usage book; `closed` folds them into the peer's totals for good.
+fn record_usage(handle: u64, conn_id: &[u8], conn: &quiche::Connection, closed: bool) {
+    let Some(entry) = CONNECTIONS.get().and_then(|map| map.get(&handle)) else {
+        return;
+    };
+    let stats = conn.stats();
+    let server = entry.role == ConfigRole::Server;
+    let mut book = entry.usage.lock().unwrap_or_else(PoisonError::into_inner);
+    let (sent, recv) = if closed {
+        book.close(conn_id, stats.sent_bytes, stats.recv_bytes)
+    } else {
+        let fingerprint = conn.peer_cert().map(sha256_hex).unwrap_or_default();
+        if let Some(path) = conn.path_stats().find(|path| path.active) {
+            metrics::observe_rtt(server, path.rtt);
+        }
+        book.update(conn_id, &fingerprint, stats.sent_bytes, stats.recv_bytes)
+    };
+    metrics::add_bytes(server, sent, recv);
+}
+
+fn event_seq(handle: u64) -> Option<Arc<EventSeq>> {
+    CONNECTIONS
+        .get()?
+        .get(&handle)
+        .map(|entry| Arc::clone(&entry.events))
 }
 
 #[cfg(test)]
@@ -1004,11 +5250,257 @@ mod tests {
     use super::*;
 
     #[test]
-    fn builds_default_config() {
-        let mut ptr: *mut CcQuicConfig = std::ptr::null_mut();
-        let status = cc_quic_config_new(&mut ptr);
-        assert_eq!(status, CcQuicStatus::Ok.code());
-        assert!(!ptr.is_null());
-        cc_quic_config_free(ptr);
+    fn subscribed_ports_get_only_their_classes() {
+        let seq = EventSeq::default();
+        seq.mirror_to(7);
+        seq.subscribe(8, EVENTS_MESSAGE | EVENTS_MEDIA);
+        seq.subscribe(9, EVENTS_CONNECTION);
+        let posted = |class| {
+            let mut ports = Vec::new();
+            seq.post_mirrors(class, |port| {
+                ports.push(port);
+                port != 9
+            });
+            ports
+        };
+        assert_eq!(posted(EVENTS_MEDIA), [7, 8]);
+        assert_eq!(posted(EVENTS_STATS), [7]);
+        // 9 refused its event, so it is gone.
+        assert_eq!(posted(EVENTS_CONNECTION), [7, 9]);
+        assert_eq!(posted(EVENTS_CONNECTION), [7]);
+        seq.subscribe(8, EVENTS_STATS);
+        seq.subscribe(7, 0);
+        assert_eq!(posted(EVENTS_STATS), [8]);
+        assert_eq!(posted(EVENTS_MESSAGE), Vec::<i64>::new());
+
+        let handle = next_handle();
+        assert_eq!(
+            cc_quic_subscribe(handle, poll::POLL_PORT, EVENTS_ALL),
+            CcQuicStatus::ConfigError.code()
+        );
+        assert_eq!(
+            cc_quic_subscribe(handle, 5, EVENTS_ALL + 1),
+            CcQuicStatus::ConfigError.code()
+        );
+        assert_eq!(
+            cc_quic_subscribe(handle, 5, EVENTS_ALL),
+            CcQuicStatus::StaleHandle.code()
+        );
+    }
+
+    #[test]
+    fn tags_follow_their_connection_until_it_closes() {
+        let seq = EventSeq::default();
+        let closed = |id: &str| QuicEvent::Closed {
+            handle: 1,
+            connection_id: id.into(),
+            reason: None,
+        };
+        let listening = QuicEvent::Listening {
+            handle: 1,
+            addr: "0.0.0.0:1".into(),
+            transport: None,
+        };
+        assert_eq!(seq.tag(&closed("ab")), None);
+        seq.set_tag("ab".into(), 7);
+        seq.set_tag(String::new(), 3);
+        assert_eq!(seq.tag(&listening), Some(3));
+        assert_eq!(seq.tag(&closed("cd")), Some(3));
+        assert_eq!(seq.tag(&closed("ab")), Some(7));
+        assert_eq!(seq.tag(&closed("ab")), Some(3));
+        seq.set_tag(String::new(), 0);
+        assert_eq!(seq.tag(&listening), None);
+
+        assert_eq!(
+            cc_quic_conn_set_tag(next_handle(), std::ptr::null(), 0, 1),
+            CcQuicStatus::StaleHandle.code()
+        );
+    }
+
+    #[test]
+    fn message_event_keeps_base64_wire_field() {
+        let event = QuicEvent::Message {
+            handle: 7,
+            connection_id: "ab".to_string(),
+            channel: None,
+            topic: None,
+            data: b"hi",
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"type":"message","handle":7,"connection_id":"ab","data_base64":"aGk="}"#
+        );
+    }
+
+    #[test]
+    fn connected_event_carries_cert_only_when_exported() {
+        let event = |cert: Option<&str>| QuicEvent::Connected {
+            handle: 1,
+            connection_id: "ab".to_string(),
+            peer_fingerprint: cert.map(|_| "ff".to_string()),
+            peer_cert_der_base64: cert.map(str::to_string),
+            user: None,
+            label: None,
+            server_name: None,
+        };
+        // A client without a certificate has an explicit null fingerprint.
+        let bare = serde_json::to_string(&event(None)).unwrap();
+        assert!(!bare.contains("peer_cert"));
+        assert!(bare.contains(r#""peer_fingerprint":null"#));
+        assert!(serde_json::to_string(&event(Some("MAo=")))
+            .unwrap()
+            .ends_with(r#""peer_cert_der_base64":"MAo="}"#));
+    }
+
+    #[test]
+    fn guarded_worker_reports_panic_message() {
+        assert!(run_guarded(0, 0, || {}));
+        assert!(!run_guarded(0, 0, || panic!("boom {}", 7)));
+        let payload = panic::catch_unwind(|| panic!("static")).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "static");
+    }
+
+    #[test]
+    fn routes_short_headers_to_owning_worker() {
+        let routes = ServerRoute::for_workers(3);
+        let owner_of = |first: u8| {
+            let mut packet = vec![0x40, first];
+            packet.extend_from_slice(&[0xaa; quiche::MAX_CONN_ID_LEN - 1]);
+            packet.extend_from_slice(&[0; 16]);
+            let hdr = quiche::Header::from_slice(&mut packet, quiche::MAX_CONN_ID_LEN).unwrap();
+            routes[1].owner(&hdr)
+        };
+        assert_eq!(owner_of(2), Some(2));
+        assert_eq!(owner_of(1), None);
+        assert_eq!(owner_of(7), None);
+    }
+
+    #[test]
+    fn paced_packets_leave_at_their_release_time() {
+        use crate::api::{Config, Event};
+
+        let mut client_config = Config::client();
+        // 1 Mbit/s: 64 KiB takes about half a second past the first flight.
+        client_config.set_pacing(true, 1000).unwrap();
+        let server = loopback::serve(&mut Config::server());
+        let client = loopback::connect(&mut client_config, &server);
+        let conn = client.connection(&loopback::connected(&client));
+        loopback::connected(&server);
+
+        let sent = vec![b'p'; 64 * 1024];
+        let started = Instant::now();
+        conn.control_send(&sent).unwrap();
+        let mut received = 0;
+        while received < sent.len() {
+            received += loopback::wait_for(&server, |event| match event {
+                Event::Message { data, .. } => Some(data.len()),
+                _ => None,
+            });
+        }
+        let elapsed = started.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(300),
+            "paced send took {elapsed:?}"
+        );
+    }
+
+    #[test]
+    fn certless_clients_need_the_optional_mode_and_a_token() {
+        use crate::api::{Config, Event, Identity};
+
+        let no_cert = Identity::new("", "");
+        let dial = |config: &mut Config, server| loopback::connect_as(config, server, &no_cert);
+        let server = loopback::serve(&mut Config::server());
+        let _refused = dial(&mut Config::client(), &server);
+        let code = loopback::wait_for(&server, |event| match event {
+            Event::Connected { .. } => panic!("admitted without a certificate"),
+            Event::HandshakeFailed { error_code, .. } => Some(*error_code),
+            _ => None,
+        });
+        assert_eq!(code, Some(handshake::UNTRUSTED_PEER));
+
+        let secret = b"optional-cert-secret";
+        let mut server_config = Config::server();
+        server_config.set_auth_hmac(Some(secret)).unwrap();
+        server_config.set_client_cert_optional(true).unwrap();
+        let server = loopback::serve(&mut server_config);
+        let mut client_config = Config::client();
+        let token = auth::mint(secret, "nursery", 0);
+        client_config.set_auth_token(Some(&token)).unwrap();
+        let _admitted = dial(&mut client_config, &server);
+        let fingerprint = loopback::wait_for(&server, |event| match event {
+            Event::Connected {
+                peer_fingerprint, ..
+            } => Some(peer_fingerprint.clone()),
+            _ => None,
+        });
+        assert_eq!(fingerprint, None);
+    }
+
+    #[test]
+    fn required_client_certs_fail_with_certificate_required() {
+        use crate::api::{Config, Endpoint, Event, HandshakeCause, Identity};
+
+        let mut both = Config::server();
+        both.set_auth_callback(true).unwrap();
+        both.set_client_cert_optional(true).unwrap();
+        both.set_require_client_cert(true).unwrap();
+        let bind = "127.0.0.1:0".parse().unwrap();
+        let started = Endpoint::serve(&mut both, &[bind], &loopback::server_identity(), &[]);
+        assert!(matches!(started, Err(Error::Config(_))));
+
+        let mut required = Config::server();
+        required.set_require_client_cert(true).unwrap();
+        let server = loopback::serve(&mut required);
+        let no_cert = Identity::new("", "");
+        let _client = loopback::connect_as(&mut Config::client(), &server, &no_cert);
+        let (cause, code) = loopback::wait_for(&server, |event| match event {
+            Event::HandshakeFailed {
+                cause, error_code, ..
+            } => Some((*cause, *error_code)),
+            _ => None,
+        });
+        assert_eq!(cause, HandshakeCause::TlsAlert);
+        assert_eq!(code, Some(handshake::CERTIFICATE_REQUIRED));
+    }
+
+    #[test]
+    fn cas_load_from_a_file_or_a_directory() {
+        let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+        let ca = std::path::Path::new(loopback::CA);
+        load_ca(&mut config, ca).unwrap();
+        load_ca(&mut config, ca.parent().unwrap()).unwrap();
+        assert!(load_ca(&mut config, &ca.with_extension("missing")).is_err());
+    }
+
+    #[test]
+    fn rejected_clients_receive_the_close() {
+        use crate::api::{Config, Event, Identity};
+
+        let server = loopback::serve(&mut Config::server());
+        let no_cert = Identity::new("", "");
+        let client = loopback::connect_as(&mut Config::client(), &server, &no_cert);
+        // Without the close the client would only notice on its idle timeout,
+        // with no peer error to report.
+        let reason = loopback::wait_for(&client, |event| match event {
+            Event::Closed { reason, .. } => Some(reason.clone()),
+            _ => None,
+        });
+        let code = format!("error_code: {}", handshake::UNTRUSTED_PEER);
+        assert!(reason.is_some_and(|reason| reason.contains(&code)));
+        loopback::wait_for(&server, |event| match event {
+            Event::HandshakeFailed { .. } => Some(()),
+            _ => None,
+        });
+    }
+
+    #[test]
+    fn reuseport_workers_share_one_port() {
+        let local: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let sockets = bind_server_sockets(local, SocketOptions::default(), 3).unwrap();
+        let port = sockets[0].local_addr().unwrap().port();
+        assert!(sockets
+            .iter()
+            .all(|s| s.local_addr().unwrap().port() == port));
     }
 }
@@ -0,0 +1,223 @@
+//! Fixtures for the unit tests that drive real connections over 127.0.0.1:
+//! the certificates in `testdata/` and helpers that wait on an endpoint's
+//! events. Both leaf certificates are signed by `testdata/ca.pem`, which
+//! `serve` and `connect` hand each config as its CA path, and are valid
+//! until 2126. Their pins are the hex SHA-256 of each leaf's DER.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::api::{Config, Endpoint, Event, Identity};
+
+pub(crate) const CA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/ca.pem");
+pub(crate) const SERVER_NAME: &str = "srv";
+pub(crate) const SERVER_PIN: &str =
+    "7abba7b93f4c21e6c0a6f925ad55ec6c548a5381c542ca82f4027c0065362785";
+/// How long a test waits for an event before failing.
+const EVENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn identity(name: &str) -> Identity {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata");
+    Identity::new(format!("{dir}/{name}.crt"), format!("{dir}/{name}.key"))
+}
+
+pub(crate) fn server_identity() -> Identity {
+    identity("srv")
+}
+
+pub(crate) fn client_identity() -> Identity {
+    identity("cli")
+}
+
+/// Makes `config` verify peers against the fixture CA.
+pub(crate) fn trust_ca(config: &mut Config) {
+    config.set_ca_path(Some(Path::new(CA))).unwrap();
+}
+
+/// A server on a free loopback port, trusting every client.
+pub(crate) fn serve(config: &mut Config) -> Endpoint {
+    trust_ca(config);
+    let bind = "127.0.0.1:0".parse().unwrap();
+    Endpoint::serve(config, &[bind], &server_identity(), &[]).unwrap()
+}
+
+/// A client of `server`, pinned to the fixture server certificate.
+pub(crate) fn connect(config: &mut Config, server: &Endpoint) -> Endpoint {
+    connect_as(config, server, &client_identity())
+}
+
+/// Like [`connect`], presenting `identity` instead of the client fixture.
+pub(crate) fn connect_as(config: &mut Config, server: &Endpoint, identity: &Identity) -> Endpoint {
+    trust_ca(config);
+    let addr = server.local_addrs()[0];
+    Endpoint::connect(config, addr, SERVER_NAME, SERVER_PIN, identity).unwrap()
+}
+
+/// Waits for the first event `pick` takes, failing the test with the
+/// events seen so far once [`EVENT_TIMEOUT`] passes.
+pub(crate) fn wait_for<T>(endpoint: &Endpoint, mut pick: impl FnMut(&Event) -> Option<T>) -> T {
+    let deadline = Instant::now() + EVENT_TIMEOUT;
+    let mut seen = Vec::new();
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let Some(event) = endpoint.recv_timeout(left) else {
+            panic!("no matching event; saw {seen:#?}");
+        };
+        if let Some(found) = pick(&event) {
+            return found;
+        }
+        seen.push(event);
+    }
+}
+
+/// The id of the next connection `endpoint` announces.
+pub(crate) fn connected(endpoint: &Endpoint) -> String {
+    wait_for(endpoint, |event| match event {
+        Event::Connected { connection_id, .. } => Some(connection_id.clone()),
+        _ => None,
+    })
+}
+
+/// The same fixtures for tests of the `cc_quic_*` exports, whose handles
+/// are polled for their events.
+pub(crate) mod abi {
+    use std::ffi::CString;
+    use std::net::SocketAddr;
+    use std::ptr;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use serde_json::Value;
+
+    use super::{CA, EVENT_TIMEOUT, SERVER_NAME};
+    use crate::{poll, CcQuicConfig};
+
+    /// A config from `new` (`cc_quic_config_new_client` or `_server`)
+    /// trusting the fixture CA.
+    pub(crate) fn config(new: extern "C" fn(*mut *mut CcQuicConfig) -> i32) -> *mut CcQuicConfig {
+        let mut config = ptr::null_mut();
+        assert_eq!(new(&mut config), 0);
+        let ca = CString::new(CA).unwrap();
+        assert_eq!(crate::cc_quic_config_set_ca_path(config, ca.as_ptr()), 0);
+        config
+    }
+
+    /// The certificate and key paths of fixture `name`.
+    pub(crate) fn identity(name: &str) -> (CString, CString) {
+        let identity = super::identity(name);
+        let path = |path: &std::path::Path| CString::new(path.to_str().unwrap()).unwrap();
+        (path(&identity.cert), path(&identity.key))
+    }
+
+    /// `cc_quic_server_start_multi` on `binds` as the fixture server,
+    /// trusting every client; the handle and the addresses bound.
+    pub(crate) fn serve(config: *mut CcQuicConfig, binds: &str) -> (u64, Vec<SocketAddr>) {
+        let (cert, key) = identity("srv");
+        let binds = CString::new(binds).unwrap();
+        let mut handle = 0;
+        let mut bound = [0u8; 256];
+        let status = crate::cc_quic_server_start_multi(
+            config,
+            binds.as_ptr(),
+            cert.as_ptr(),
+            key.as_ptr(),
+            c"".as_ptr(),
+            poll::POLL_PORT,
+            &mut handle,
+            bound.as_mut_ptr().cast(),
+            bound.len(),
+        );
+        assert_eq!(status, 0);
+        let end = bound.iter().position(|b| *b == 0).unwrap();
+        let bound = std::str::from_utf8(&bound[..end]).unwrap();
+        (
+            handle,
+            bound.split(',').map(|addr| addr.parse().unwrap()).collect(),
+        )
+    }
+
+    /// `cc_quic_client_connect` to 127.0.0.1:`port` as the fixture client,
+    /// checking the server against `pin`; the handle, or the status.
+    pub(crate) fn connect(
+        config: *mut CcQuicConfig,
+        port: u16,
+        pin: &str,
+        flags: u32,
+    ) -> Result<u64, i32> {
+        let (cert, key) = identity("cli");
+        let (name, pin) = (CString::new(SERVER_NAME), CString::new(pin));
+        let mut handle = 0;
+        let status = crate::cc_quic_client_connect(
+            config,
+            c"127.0.0.1".as_ptr(),
+            port,
+            name.unwrap().as_ptr(),
+            pin.unwrap().as_ptr(),
+            cert.as_ptr(),
+            key.as_ptr(),
+            flags,
+            poll::POLL_PORT,
+            &mut handle,
+        );
+        match status {
+            0 => Ok(handle),
+            status => Err(status),
+        }
+    }
+
+    /// The raw id of `handle`'s next `connected` connection.
+    pub(crate) fn connected(handle: u64) -> Vec<u8> {
+        let id = poll_for(handle, |event| match event["type"].as_str() {
+            Some("connected") => event["connection_id"].as_str().map(str::to_string),
+            _ => None,
+        });
+        hex::decode(id).unwrap()
+    }
+
+    /// Polls `handle` for the first event `pick` takes, failing the test
+    /// with the events seen so far once [`EVENT_TIMEOUT`] passes. Events
+    /// are taken one at a time, so those after it stay queued.
+    pub(crate) fn poll_for<T>(handle: u64, mut pick: impl FnMut(&Value) -> Option<T>) -> T {
+        let deadline = Instant::now() + EVENT_TIMEOUT;
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut seen = Vec::new();
+        loop {
+            assert!(
+                Instant::now() < deadline,
+                "no matching event; saw {seen:#?}"
+            );
+            let polled = crate::cc_quic_events_poll(handle, buf.as_mut_ptr(), buf.len(), 1);
+            assert!(polled >= 0, "poll failed with {polled}");
+            let end = buf.iter().position(|b| *b == 0).unwrap();
+            for line in std::str::from_utf8(&buf[..end]).unwrap().lines() {
+                let event: Value = serde_json::from_str(line).unwrap();
+                if let Some(found) = pick(&event) {
+                    return found;
+                }
+                seen.push(event);
+            }
+            if polled == 0 {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    /// Every event `handle` posts within `period`.
+    pub(crate) fn drain(handle: u64, period: Duration) -> Vec<Value> {
+        let deadline = Instant::now() + period;
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut seen = Vec::new();
+        while Instant::now() < deadline {
+            let polled = crate::cc_quic_events_poll(handle, buf.as_mut_ptr(), buf.len(), 1);
+            assert!(polled >= 0, "poll failed with {polled}");
+            let end = buf.iter().position(|b| *b == 0).unwrap();
+            for line in std::str::from_utf8(&buf[..end]).unwrap().lines() {
+                seen.push(serde_json::from_str(line).unwrap());
+            }
+            if polled == 0 {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+        seen
+    }
+}
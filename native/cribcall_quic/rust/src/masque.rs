@@ -0,0 +1,592 @@
+//! QUIC through a MASQUE proxy (`cc_quic_client_connect_via_proxy`), for
+//! networks where UDP only leaves through an approved egress proxy. The
+//! client opens an HTTP/3 connection of its own to the proxy and asks it,
+//! with an extended CONNECT for `connect-udp` (RFC 9298), to relay UDP to
+//! the server. The connection to the server then runs inside that one
+//! unchanged: each of its datagrams is sent as an HTTP datagram (RFC 9297)
+//! on the request stream, so pinning, the allowlist and every event work as
+//! they do over plain UDP. The server sees the proxy's address.
+//!
+//! The proxy's certificate is checked for the host in the proxy URL against
+//! the CA file or directory given with the route, and the trust store
+//! BoringSSL finds by default. That default is only populated on desktop
+//! Linux, so Android, iOS, macOS and Windows callers have to pass a CA.
+//! Inner packets have to fit in one outer datagram, so the tunnel reports
+//! how large they may be.
+
+use std::cell::RefCell;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::handshake::varint;
+use quiche::h3::{self, NameValue};
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+/// How long the proxy handshake and CONNECT may take.
+pub(crate) const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// RFC 9298's default URI template, used when the proxy URL has no path.
+const DEFAULT_TEMPLATE: &str = "/.well-known/masque/udp/{target_host}/{target_port}/";
+const IDLE_TIMEOUT_MS: u64 = 60_000;
+/// Context id 0 carries UDP payloads (RFC 9298 §5).
+const UDP_CONTEXT: u64 = 0;
+/// QUIC never sends less, so a proxy path that can't carry it is useless.
+const MIN_INNER_PAYLOAD: usize = 1200;
+const RECV_BUF: usize = 65_535;
+
+/// Where `cc_quic_client_connect_via_proxy` sends the connection.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct ProxyRoute {
+    host: String,
+    port: u16,
+    /// Path with `{target_host}` and `{target_port}` still in it.
+    template: String,
+    /// `proxy-authorization` value, if any.
+    auth: Option<String>,
+    /// PEM file or directory of CAs for the proxy's certificate.
+    ca: Option<PathBuf>,
+}
+
+impl ProxyRoute {
+    /// Parses `https://host[:port][/template]`; the port defaults to 443
+    /// and the template to RFC 9298's well-known one.
+    pub(crate) fn parse(url: &str, auth: &str) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("https://")
+            .ok_or_else(|| format!("proxy url {url:?} is not https"))?;
+        let (authority, template) = match rest.find('/') {
+            Some(at) => (&rest[..at], &rest[at..]),
+            None => (rest, ""),
+        };
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(v6) => {
+                let (host, after) = v6
+                    .split_once(']')
+                    .ok_or_else(|| format!("proxy url {url:?} has an unclosed ["))?;
+                (host, after.strip_prefix(':'))
+            }
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(format!("proxy url {url:?} has no host"));
+        }
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| format!("proxy url {url:?} has a bad port"))?,
+            None => 443,
+        };
+        let template = match template {
+            "" | "/" => DEFAULT_TEMPLATE.to_string(),
+            template
+                if template.contains("{target_host}") && template.contains("{target_port}") =>
+            {
+                template.to_string()
+            }
+            _ => {
+                return Err(format!(
+                    "proxy url {url:?} lacks {{target_host}}/{{target_port}}"
+                ))
+            }
+        };
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            template,
+            auth: (!auth.is_empty()).then(|| auth.to_string()),
+            ca: None,
+        })
+    }
+
+    /// Also trusts the CAs in `ca`, a PEM file or a directory of them.
+    pub(crate) fn trusting(mut self, ca: Option<PathBuf>) -> Self {
+        self.ca = ca;
+        self
+    }
+
+    fn authority(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    /// The request path asking for UDP to `target`; an IPv6 host has its
+    /// colons percent-encoded.
+    fn path(&self, target: SocketAddr) -> String {
+        let host = match target.ip() {
+            IpAddr::V4(ip) => ip.to_string(),
+            IpAddr::V6(ip) => ip.to_string().replace(':', "%3A"),
+        };
+        self.template
+            .replace("{target_host}", &host)
+            .replace("{target_port}", &target.port().to_string())
+    }
+}
+
+/// The connection to the proxy, carrying one UDP flow to the server.
+pub(crate) struct MasqueTunnel {
+    /// Boxed: a quiche connection is large for a socket variant.
+    inner: RefCell<Box<Outer>>,
+    target: SocketAddr,
+    local: SocketAddr,
+    max_payload: usize,
+}
+
+struct Outer {
+    udp: UdpSocket,
+    proxy: SocketAddr,
+    conn: quiche::Connection,
+    h3: h3::Connection,
+    /// The CONNECT request stream the datagrams belong to.
+    stream_id: u64,
+    /// Whether the proxy ended the request or the connection.
+    closed: bool,
+    buf: Vec<u8>,
+}
+
+impl Outer {
+    /// Sends what quiche has queued; a full socket buffer drops the rest,
+    /// which loss recovery resends.
+    fn flush(&mut self) {
+        loop {
+            match self.conn.send(&mut self.buf) {
+                Ok((len, _)) => match self.udp.send(&self.buf[..len]) {
+                    Ok(_) => {}
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) => {
+                        log::debug!("masque proxy send failed: {err}");
+                        break;
+                    }
+                },
+                Err(quiche::Error::Done) => break,
+                Err(err) => {
+                    log::warn!("masque proxy send error: {err:?}");
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Feeds everything the socket holds to quiche and fires its timer.
+    fn pump(&mut self, local: SocketAddr) {
+        loop {
+            match self.udp.recv(&mut self.buf) {
+                Ok(len) => {
+                    let info = quiche::RecvInfo {
+                        from: self.proxy,
+                        to: local,
+                    };
+                    if let Err(err) = self.conn.recv(&mut self.buf[..len], info) {
+                        log::debug!("masque proxy recv error: {err:?}");
+                    }
+                }
+                Err(err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut =>
+                {
+                    break
+                }
+                Err(err) => {
+                    log::debug!("masque proxy recv failed: {err}");
+                    break;
+                }
+            }
+        }
+        if self.conn.timeout().is_some_and(|left| left.is_zero()) {
+            self.conn.on_timeout();
+        }
+    }
+
+    /// Handles HTTP/3 events; the request stream's own frames are read and
+    /// dropped, as no capsules are used.
+    fn poll_h3(&mut self) {
+        loop {
+            match self.h3.poll(&mut self.conn) {
+                Ok((stream_id, h3::Event::Data)) => {
+                    while self
+                        .h3
+                        .recv_body(&mut self.conn, stream_id, &mut self.buf)
+                        .is_ok()
+                    {}
+                }
+                Ok((stream_id, h3::Event::Finished | h3::Event::Reset(_)))
+                    if stream_id == self.stream_id =>
+                {
+                    self.close("proxy ended the CONNECT-UDP request")
+                }
+                Ok(_) => {}
+                Err(h3::Error::Done) => break,
+                Err(err) => {
+                    self.close(&format!("proxy HTTP/3 error: {err:?}"));
+                    break;
+                }
+            }
+        }
+        if self.conn.is_closed() {
+            self.close("proxy connection closed");
+        }
+    }
+
+    fn close(&mut self, why: &str) {
+        if !self.closed {
+            log::warn!("masque tunnel down: {why}");
+            self.closed = true;
+        }
+    }
+}
+
+impl MasqueTunnel {
+    /// Handshakes with the proxy and asks it for UDP to `target`, blocking
+    /// for up to `timeout`. `max_payload` caps the proxy connection's own
+    /// packets.
+    pub(crate) fn connect(
+        route: &ProxyRoute,
+        target: SocketAddr,
+        max_payload: usize,
+        timeout: Duration,
+    ) -> io::Result<Self> {
+        let deadline = Instant::now() + timeout;
+        let proxy = (route.host.as_str(), route.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::other(format!("proxy {} did not resolve", route.host)))?;
+        let bind: SocketAddr = match proxy {
+            SocketAddr::V4(_) => "0.0.0.0:0".parse().unwrap(),
+            SocketAddr::V6(_) => "[::]:0".parse().unwrap(),
+        };
+        let udp = UdpSocket::bind(bind)?;
+        udp.connect(proxy)?;
+        let local = udp.local_addr()?;
+
+        let quic_err = |err: quiche::Error| io::Error::other(format!("proxy quic: {err:?}"));
+        let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).map_err(quic_err)?;
+        config
+            .set_application_protos(h3::APPLICATION_PROTOCOL)
+            .map_err(quic_err)?;
+        config.verify_peer(true);
+        if let Some(ca) = &route.ca {
+            crate::load_ca(&mut config, ca)?;
+        }
+        config.set_max_idle_timeout(IDLE_TIMEOUT_MS);
+        config.set_max_recv_udp_payload_size(max_payload);
+        config.set_max_send_udp_payload_size(max_payload);
+        config.set_initial_max_data(4 * 1024 * 1024);
+        config.set_initial_max_stream_data_bidi_local(1024 * 1024);
+        config.set_initial_max_stream_data_bidi_remote(1024 * 1024);
+        config.set_initial_max_stream_data_uni(1024 * 1024);
+        config.set_initial_max_streams_bidi(16);
+        config.set_initial_max_streams_uni(16);
+        config.enable_dgram(true, 1024, 1024);
+        let mut scid = [0u8; quiche::MAX_CONN_ID_LEN];
+        OsRng.fill_bytes(&mut scid);
+        let scid = quiche::ConnectionId::from_ref(&scid);
+        let conn = quiche::connect(Some(&route.host), &scid, local, proxy, &mut config)
+            .map_err(quic_err)?;
+        let h3_config = h3::Config::new().map_err(|err| io::Error::other(format!("{err:?}")))?;
+
+        let mut buf = vec![0; RECV_BUF];
+        let mut conn = conn;
+        let mut h3_conn = None;
+        let mut request = None;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "proxy did not open a CONNECT-UDP tunnel in time",
+                ));
+            }
+            loop {
+                match conn.send(&mut buf) {
+                    Ok((len, _)) => {
+                        udp.send(&buf[..len])?;
+                    }
+                    Err(quiche::Error::Done) => break,
+                    Err(err) => return Err(quic_err(err)),
+                }
+            }
+            if conn.is_closed() {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    format!(
+                        "proxy connection closed: {:?}",
+                        conn.peer_error().or(conn.local_error())
+                    ),
+                ));
+            }
+            let wait = conn
+                .timeout()
+                .unwrap_or(Duration::from_millis(50))
+                .min(deadline - now)
+                .max(Duration::from_millis(1));
+            udp.set_read_timeout(Some(wait))?;
+            match udp.recv(&mut buf) {
+                Ok(len) => {
+                    let info = quiche::RecvInfo {
+                        from: proxy,
+                        to: local,
+                    };
+                    if let Err(err) = conn.recv(&mut buf[..len], info) {
+                        log::debug!("masque proxy recv error: {err:?}");
+                    }
+                }
+                Err(err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut =>
+                {
+                    if conn.timeout().is_some_and(|left| left.is_zero()) {
+                        conn.on_timeout();
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+
+            if conn.is_established() && h3_conn.is_none() {
+                h3_conn = Some(
+                    h3::Connection::with_transport(&mut conn, &h3_config)
+                        .map_err(|err| io::Error::other(format!("proxy h3: {err:?}")))?,
+                );
+            }
+            let Some(h3) = h3_conn.as_mut() else {
+                continue;
+            };
+            loop {
+                match h3.poll(&mut conn) {
+                    Ok((stream_id, h3::Event::Headers { list, .. }))
+                        if Some(stream_id) == request =>
+                    {
+                        let status = list
+                            .iter()
+                            .find(|header| header.name() == b":status")
+                            .map(|header| String::from_utf8_lossy(header.value()).into_owned())
+                            .unwrap_or_default();
+                        if !status.starts_with('2') {
+                            return Err(io::Error::new(
+                                io::ErrorKind::PermissionDenied,
+                                format!("proxy refused CONNECT-UDP with status {status:?}"),
+                            ));
+                        }
+                        let room = conn
+                            .dgram_max_writable_len()
+                            .unwrap_or(0)
+                            .saturating_sub(prefix_len(stream_id));
+                        if room < MIN_INNER_PAYLOAD {
+                            return Err(io::Error::other(format!(
+                                "proxy datagrams carry {room} bytes, QUIC needs {MIN_INNER_PAYLOAD}"
+                            )));
+                        }
+                        udp.set_read_timeout(None)?;
+                        udp.set_nonblocking(true)?;
+                        log::info!("masque tunnel to {target} open via {}", route.authority());
+                        return Ok(Self {
+                            inner: RefCell::new(Box::new(Outer {
+                                udp,
+                                proxy,
+                                conn,
+                                h3: h3_conn.take().unwrap(),
+                                stream_id,
+                                closed: false,
+                                buf,
+                            })),
+                            target,
+                            local,
+                            max_payload: room,
+                        });
+                    }
+                    Ok((stream_id, h3::Event::Finished | h3::Event::Reset(_)))
+                        if Some(stream_id) == request =>
+                    {
+                        return Err(io::Error::new(
+                            io::ErrorKind::ConnectionRefused,
+                            "proxy ended the CONNECT-UDP request",
+                        ));
+                    }
+                    Ok(_) => {}
+                    Err(h3::Error::Done) => break,
+                    Err(err) => return Err(io::Error::other(format!("proxy h3: {err:?}"))),
+                }
+            }
+            // Extended CONNECT waits for the proxy's SETTINGS to allow it.
+            if request.is_none() && h3.peer_settings_raw().is_some() {
+                if !h3.extended_connect_enabled_by_peer() || !h3.dgram_enabled_by_peer(&conn) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "proxy does not offer extended CONNECT with HTTP datagrams",
+                    ));
+                }
+                let path = route.path(target);
+                let authority = route.authority();
+                let mut headers = vec![
+                    h3::Header::new(b":method", b"CONNECT"),
+                    h3::Header::new(b":protocol", b"connect-udp"),
+                    h3::Header::new(b":scheme", b"https"),
+                    h3::Header::new(b":authority", authority.as_bytes()),
+                    h3::Header::new(b":path", path.as_bytes()),
+                    h3::Header::new(b"capsule-protocol", b"?1"),
+                ];
+                if let Some(auth) = &route.auth {
+                    headers.push(h3::Header::new(b"proxy-authorization", auth.as_bytes()));
+                }
+                request = Some(
+                    h3.send_request(&mut conn, &headers, false)
+                        .map_err(|err| io::Error::other(format!("proxy request: {err:?}")))?,
+                );
+            }
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.local
+    }
+
+    /// Largest inner datagram one HTTP datagram carries.
+    pub(crate) fn max_payload(&self) -> usize {
+        self.max_payload
+    }
+
+    /// Relays one inner datagram; dropped if the proxy path is down or
+    /// its datagram queue is full.
+    pub(crate) fn send(&self, data: &[u8]) {
+        let mut outer = self.inner.borrow_mut();
+        if outer.closed {
+            return;
+        }
+        let framed = wrap(outer.stream_id, data);
+        if let Err(err) = outer.conn.dgram_send(&framed) {
+            log::debug!("masque datagram dropped: {err:?}");
+        }
+        outer.flush();
+    }
+
+    /// Hands up to `max` inner datagrams to `each` (index, payload, the
+    /// server's address), after driving the proxy connection.
+    pub(crate) fn recv(&self, max: usize, each: &mut dyn FnMut(usize, &[u8], SocketAddr)) -> usize {
+        let mut outer = self.inner.borrow_mut();
+        outer.pump(self.local);
+        outer.poll_h3();
+        let mut received = 0;
+        let mut dgram = vec![0; RECV_BUF];
+        while received < max {
+            let Ok(len) = outer.conn.dgram_recv(&mut dgram) else {
+                break;
+            };
+            if let Some(payload) = unwrap(outer.stream_id, &dgram[..len]) {
+                each(received, payload, self.target);
+                received += 1;
+            }
+        }
+        outer.flush();
+        received
+    }
+
+    /// Whether datagrams are waiting in quiche, past the socket.
+    pub(crate) fn has_pending(&self) -> bool {
+        self.inner.borrow().conn.dgram_recv_queue_len() > 0
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) fn push_fds(&self, fds: &mut Vec<std::os::fd::RawFd>) {
+        fds.push(std::os::fd::AsRawFd::as_raw_fd(&self.inner.borrow().udp));
+    }
+}
+
+impl Drop for MasqueTunnel {
+    fn drop(&mut self) {
+        let outer = self.inner.get_mut();
+        let _ = outer.conn.close(true, 0x100, b"");
+        outer.flush();
+    }
+}
+
+fn prefix_len(stream_id: u64) -> usize {
+    varint_len(stream_id / 4) + varint_len(UDP_CONTEXT)
+}
+
+/// `data` behind its quarter stream id and the UDP context id.
+fn wrap(stream_id: u64, data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(prefix_len(stream_id) + data.len());
+    put_varint(&mut out, stream_id / 4);
+    put_varint(&mut out, UDP_CONTEXT);
+    out.extend_from_slice(data);
+    out
+}
+
+/// The UDP payload of an HTTP datagram for `stream_id`; `None` for other
+/// streams and contexts.
+fn unwrap(stream_id: u64, data: &[u8]) -> Option<&[u8]> {
+    let (quarter, used) = varint(data)?;
+    let (context, more) = varint(data.get(used..)?)?;
+    (quarter == stream_id / 4 && context == UDP_CONTEXT).then(|| &data[used + more..])
+}
+
+fn varint_len(value: u64) -> usize {
+    match value {
+        0..=63 => 1,
+        64..=16_383 => 2,
+        16_384..=1_073_741_823 => 4,
+        _ => 8,
+    }
+}
+
+fn put_varint(out: &mut Vec<u8>, value: u64) {
+    let len = varint_len(value);
+    let tag = (len.trailing_zeros() as u64) << ((len * 8) - 2);
+    out.extend_from_slice(&(value | tag).to_be_bytes()[8 - len..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proxy_urls_fill_in_the_template() {
+        let route = ProxyRoute::parse("https://proxy.example", "").unwrap();
+        assert_eq!(route.authority(), "proxy.example:443");
+        assert_eq!(route.auth, None);
+        let target: SocketAddr = "192.0.2.9:4433".parse().unwrap();
+        assert_eq!(
+            route.path(target),
+            "/.well-known/masque/udp/192.0.2.9/4433/"
+        );
+
+        let route = ProxyRoute::parse(
+            "https://[2001:db8::1]:8443/udp?h={target_host}&p={target_port}",
+            "Bearer abc",
+        )
+        .unwrap();
+        assert_eq!(route.authority(), "[2001:db8::1]:8443");
+        assert_eq!(route.auth.as_deref(), Some("Bearer abc"));
+        let target: SocketAddr = "[2001:db8::9]:4433".parse().unwrap();
+        assert_eq!(route.path(target), "/udp?h=2001%3Adb8%3A%3A9&p=4433");
+
+        assert!(ProxyRoute::parse("http://proxy.example", "").is_err());
+        assert!(ProxyRoute::parse("https://proxy.example:x", "").is_err());
+        assert!(ProxyRoute::parse("https://proxy.example/udp", "").is_err());
+    }
+
+    #[test]
+    fn datagrams_carry_the_quarter_stream_id_and_context() {
+        let framed = wrap(0, b"quic");
+        assert_eq!(framed, b"\x00\x00quic");
+        assert_eq!(unwrap(0, &framed), Some(&b"quic"[..]));
+        assert_eq!(unwrap(4, &framed), None);
+
+        // Quarter stream id 100 takes two bytes.
+        let framed = wrap(400, b"x");
+        assert_eq!(&framed[..2], &[0x40, 100]);
+        assert_eq!(prefix_len(400), 3);
+        assert_eq!(unwrap(400, &framed), Some(&b"x"[..]));
+
+        let mut other_context = vec![0];
+        put_varint(&mut other_context, 2);
+        other_context.push(1);
+        assert_eq!(unwrap(0, &other_context), None);
+    }
+}
@@ -0,0 +1,522 @@
+//! Partially reliable media over QUIC DATAGRAM frames. Each datagram carries
+//! the 12-byte RTP fixed header (version, marker, sequence, timestamp, SSRC)
+//! so receivers can reorder within a small window and account for gaps
+//! without an RTP stack on the Dart side.
+
+use crate::fec::{self, FecDecoder, FecEncoder};
+use crate::jitter::{JitterBuffer, JitterConfig, JitterStats};
+use rand::{rngs::OsRng, RngCore};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+pub(crate) const MEDIA_HEADER_LEN: usize = 12;
+/// Frames held back waiting for a missing sequence before it is declared lost.
+pub(crate) const DEFAULT_REORDER_WINDOW: usize = 4;
+const RTP_VERSION: u8 = 2;
+/// First dynamic RTP payload type; receivers do not interpret it.
+const PAYLOAD_TYPE: u8 = 96;
+/// Extended sequences start one cycle up so early reordering cannot underflow.
+const SEQ_CYCLE: u64 = 1 << 16;
+/// Most copies of one frame the redundant mode will send.
+pub(crate) const MAX_MEDIA_COPIES: u32 = 8;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct MediaHeader {
+    marker: bool,
+    seq: u16,
+    timestamp: u32,
+    ssrc: u32,
+}
+
+impl MediaHeader {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.push(RTP_VERSION << 6);
+        out.push(((self.marker as u8) << 7) | PAYLOAD_TYPE);
+        out.extend_from_slice(&self.seq.to_be_bytes());
+        out.extend_from_slice(&self.timestamp.to_be_bytes());
+        out.extend_from_slice(&self.ssrc.to_be_bytes());
+    }
+
+    fn parse(data: &[u8]) -> Option<(Self, &[u8])> {
+        if data.len() < MEDIA_HEADER_LEN || data[0] >> 6 != RTP_VERSION {
+            return None;
+        }
+        let header = Self {
+            marker: data[1] & 0x80 != 0,
+            seq: u16::from_be_bytes([data[2], data[3]]),
+            timestamp: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            ssrc: u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
+        };
+        Some((header, &data[MEDIA_HEADER_LEN..]))
+    }
+}
+
+/// A received frame released in sequence order.
+#[derive(Debug)]
+pub(crate) struct MediaFrame {
+    pub seq: u64,
+    pub timestamp: u32,
+    pub marker: bool,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Copy, Clone, Debug, Default, Serialize)]
+pub(crate) struct MediaStats {
+    pub sent: u64,
+    pub received: u64,
+    pub released: u64,
+    /// Sequences skipped after the reorder window gave up on them.
+    pub lost: u64,
+    /// Frames that arrived after their slot was released or skipped.
+    pub late: u64,
+    pub duplicates: u64,
+    /// Frames that arrived behind a higher sequence but were still in time.
+    pub reordered: u64,
+    /// Datagrams without a media header.
+    pub invalid: u64,
+    pub fec_sent: u64,
+    pub fec_received: u64,
+    /// Frames rebuilt from FEC parity (also counted in `received`).
+    pub recovered: u64,
+    /// Extra copies sent by the redundant mode (not counted in `sent`).
+    pub redundant_sent: u64,
+    /// Frames dropped to keep within the dual-channel media budget.
+    pub paced_dropped: u64,
+    pub jitter: JitterStats,
+}
+
+/// Per-connection media state: outgoing sequence numbering plus the receive
+/// reorder buffer for the peer's stream.
+pub(crate) struct MediaChannel {
+    ssrc: u32,
+    next_send_seq: u16,
+    peer_ssrc: Option<u32>,
+    next: Option<u64>,
+    highest: Option<u64>,
+    /// Bit `i` set when sequence `next - 1 - i` was released, to tell
+    /// redundant copies apart from frames that missed their slot.
+    released_mask: u64,
+    pending: BTreeMap<u64, MediaFrame>,
+    copies: Option<(u32, Duration)>,
+    resend: VecDeque<(Instant, Vec<u8>)>,
+    fec_tx: Option<FecEncoder>,
+    fec_rx: FecDecoder,
+    jitter: JitterBuffer,
+    stats: MediaStats,
+    /// Datagram bytes queued, parity and redundant copies included.
+    sent_bytes: u64,
+}
+
+impl MediaChannel {
+    pub(crate) fn new() -> Self {
+        Self {
+            ssrc: OsRng.next_u32(),
+            next_send_seq: OsRng.next_u32() as u16,
+            peer_ssrc: None,
+            next: None,
+            highest: None,
+            released_mask: 0,
+            pending: BTreeMap::new(),
+            copies: None,
+            resend: VecDeque::new(),
+            fec_tx: None,
+            fec_rx: FecDecoder::default(),
+            jitter: JitterBuffer::default(),
+            stats: MediaStats::default(),
+            sent_bytes: 0,
+        }
+    }
+
+    pub(crate) fn stats(&self) -> MediaStats {
+        MediaStats {
+            jitter: self.jitter.stats(),
+            ..self.stats
+        }
+    }
+
+    pub(crate) fn sent_bytes(&self) -> u64 {
+        self.sent_bytes
+    }
+
+    /// Holds in-order frames in the jitter buffer when one is configured and
+    /// returns those due for playout at `now`.
+    pub(crate) fn playout(
+        &mut self,
+        frames: Vec<MediaFrame>,
+        now: Instant,
+        config: Option<&JitterConfig>,
+    ) -> Vec<MediaFrame> {
+        let Some(config) = config else {
+            return frames;
+        };
+        self.jitter.push(frames, now, config);
+        self.jitter.pop_due(now)
+    }
+
+    /// Protects every `k` outgoing frames with `n - k` parity datagrams;
+    /// `None` turns FEC off. Takes effect from the next frame.
+    pub(crate) fn set_fec(&mut self, group: Option<(u8, u8)>) {
+        self.fec_tx = group.map(|(k, n)| FecEncoder::new(k, n));
+    }
+
+    /// Sends every frame `copies` times spread evenly over `spread`; one copy
+    /// turns the redundant mode off. Receivers drop the extras by sequence.
+    pub(crate) fn set_redundancy(&mut self, copies: u32, spread: Duration) {
+        self.copies = (copies > 1).then_some((copies, spread));
+        if self.copies.is_none() {
+            self.resend.clear();
+        }
+    }
+
+    /// Counts a frame refused by the dual-channel media budget.
+    pub(crate) fn paced_drop(&mut self) {
+        self.stats.paced_dropped += 1;
+    }
+
+    /// Sends redundant copies that have come due.
+    pub(crate) fn send_due(&mut self, conn: &mut quiche::Connection, now: Instant) {
+        while let Some(dgram) = self.take_due(now) {
+            let len = dgram.len() as u64;
+            match conn.dgram_send_vec(dgram) {
+                Ok(()) => {
+                    self.stats.redundant_sent += 1;
+                    self.sent_bytes += len;
+                }
+                Err(quiche::Error::Done) => {}
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// The next redundant copy due by `now`.
+    fn take_due(&mut self, now: Instant) -> Option<Vec<u8>> {
+        if self.resend.front()?.0 > now {
+            return None;
+        }
+        self.resend.pop_front().map(|(_, dgram)| dgram)
+    }
+
+    /// Queues the extra copies of `dgram`, sent at `now`, spread evenly
+    /// so the last goes out `spread` later.
+    fn schedule_copies(&mut self, dgram: &[u8], now: Instant) {
+        let Some((copies, spread)) = self.copies else {
+            return;
+        };
+        let step = spread / (copies - 1);
+        for i in 1..copies {
+            self.resend.push_back((now + step * i, dgram.to_vec()));
+        }
+    }
+
+    /// Frames `payload` and queues it as a DATAGRAM, followed by parity
+    /// when it completes an FEC group.
+    pub(crate) fn send(
+        &mut self,
+        conn: &mut quiche::Connection,
+        timestamp: u32,
+        marker: bool,
+        payload: &[u8],
+    ) -> quiche::Result<()> {
+        let seq = self.next_send_seq;
+        let dgram = self.frame(timestamp, marker, payload);
+        let parity = match &mut self.fec_tx {
+            Some(fec) => fec.protect(seq, &dgram),
+            None => Vec::new(),
+        };
+        self.schedule_copies(&dgram, Instant::now());
+        let len = dgram.len() as u64;
+        conn.dgram_send_vec(dgram)?;
+        self.stats.sent += 1;
+        self.sent_bytes += len;
+        for dgram in parity {
+            let len = dgram.len() as u64;
+            conn.dgram_send_vec(dgram)?;
+            self.stats.fec_sent += 1;
+            self.sent_bytes += len;
+        }
+        Ok(())
+    }
+
+    fn frame(&mut self, timestamp: u32, marker: bool, payload: &[u8]) -> Vec<u8> {
+        let header = MediaHeader {
+            marker,
+            seq: self.next_send_seq,
+            timestamp,
+            ssrc: self.ssrc,
+        };
+        self.next_send_seq = self.next_send_seq.wrapping_add(1);
+        let mut out = Vec::with_capacity(MEDIA_HEADER_LEN + payload.len());
+        header.write(&mut out);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    /// Accepts one received datagram, appending any frames now in order to
+    /// `out`. At most `window` frames wait behind a missing sequence.
+    pub(crate) fn receive(&mut self, dgram: &[u8], window: usize, out: &mut Vec<MediaFrame>) {
+        let recovered = if fec::is_parity(dgram) {
+            self.stats.fec_received += 1;
+            self.fec_rx.on_parity(dgram)
+        } else {
+            let recovered = match MediaHeader::parse(dgram) {
+                Some((header, _)) => self.fec_rx.on_source(header.seq, dgram),
+                None => Vec::new(),
+            };
+            self.accept(dgram, window, out);
+            recovered
+        };
+        for dgram in recovered {
+            self.stats.recovered += 1;
+            self.accept(&dgram, window, out);
+        }
+    }
+
+    fn accept(&mut self, dgram: &[u8], window: usize, out: &mut Vec<MediaFrame>) {
+        let Some((header, payload)) = MediaHeader::parse(dgram) else {
+            self.stats.invalid += 1;
+            return;
+        };
+        if self.peer_ssrc != Some(header.ssrc) {
+            // A new sender (or restarted stream): flush and start over.
+            self.flush_pending(out);
+            self.peer_ssrc = Some(header.ssrc);
+            self.next = None;
+            self.highest = None;
+            self.released_mask = 0;
+        }
+        self.stats.received += 1;
+
+        let seq = self.extend(header.seq);
+        let next = *self.next.get_or_insert(seq);
+        if seq < next {
+            let age = next - 1 - seq;
+            if age < 64 && self.released_mask >> age & 1 == 1 {
+                self.stats.duplicates += 1;
+            } else {
+                self.stats.late += 1;
+            }
+            return;
+        }
+        if self.pending.contains_key(&seq) {
+            self.stats.duplicates += 1;
+            return;
+        }
+        match self.highest {
+            Some(highest) if seq < highest => self.stats.reordered += 1,
+            _ => self.highest = Some(seq),
+        }
+        self.pending.insert(
+            seq,
+            MediaFrame {
+                seq,
+                timestamp: header.timestamp,
+                marker: header.marker,
+                payload: payload.to_vec(),
+            },
+        );
+        self.release(window, out);
+    }
+
+    /// Maps a 16-bit wire sequence to the extended sequence nearest the
+    /// highest seen so far.
+    fn extend(&self, seq: u16) -> u64 {
+        match self.highest {
+            None => SEQ_CYCLE + seq as u64,
+            Some(highest) => {
+                let delta = seq.wrapping_sub(highest as u16) as i16;
+                highest.saturating_add_signed(delta as i64)
+            }
+        }
+    }
+
+    fn release(&mut self, window: usize, out: &mut Vec<MediaFrame>) {
+        let Some(mut next) = self.next else {
+            return;
+        };
+        loop {
+            while let Some(frame) = self.pending.remove(&next) {
+                out.push(frame);
+                self.stats.released += 1;
+                self.released_mask = self.released_mask << 1 | 1;
+                next += 1;
+            }
+            if self.pending.len() <= window {
+                break;
+            }
+            // The window is full behind a gap: give up on the missing ones.
+            let first = *self.pending.keys().next().expect("pending is non-empty");
+            self.stats.lost += first - next;
+            self.released_mask = self
+                .released_mask
+                .checked_shl((first - next) as u32)
+                .unwrap_or(0);
+            next = first;
+        }
+        self.next = Some(next);
+    }
+
+    /// Releases everything pending (counting gaps as lost), e.g. when the
+    /// peer's stream restarts.
+    fn flush_pending(&mut self, out: &mut Vec<MediaFrame>) {
+        let Some(mut next) = self.next else {
+            return;
+        };
+        for (seq, frame) in std::mem::take(&mut self.pending) {
+            self.stats.lost += seq - next;
+            next = seq + 1;
+            out.push(frame);
+            self.stats.released += 1;
+        }
+        self.next = Some(next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sender() -> MediaChannel {
+        let mut channel = MediaChannel::new();
+        channel.next_send_seq = u16::MAX - 1;
+        channel
+    }
+
+    fn seqs(frames: &[MediaFrame]) -> Vec<u64> {
+        frames.iter().map(|f| f.seq - SEQ_CYCLE).collect()
+    }
+
+    #[test]
+    fn header_round_trips_as_rtp() {
+        let mut tx = sender();
+        let dgram = tx.frame(0xdead_beef, true, b"opus");
+        assert_eq!(dgram.len(), MEDIA_HEADER_LEN + 4);
+        assert_eq!(dgram[0], 0x80);
+        let (header, payload) = MediaHeader::parse(&dgram).unwrap();
+        assert!(header.marker);
+        assert_eq!(header.seq, u16::MAX - 1);
+        assert_eq!(header.timestamp, 0xdead_beef);
+        assert_eq!(header.ssrc, tx.ssrc);
+        assert_eq!(payload, b"opus");
+    }
+
+    #[test]
+    fn reorders_within_window_across_wrap() {
+        let mut tx = sender();
+        let dgrams: Vec<_> = (0..4).map(|i| tx.frame(i, false, &[])).collect();
+        let mut rx = MediaChannel::new();
+        let mut out = Vec::new();
+        for i in [0, 2, 1, 3, 1] {
+            rx.receive(&dgrams[i], 2, &mut out);
+        }
+        let base = (u16::MAX - 1) as u64;
+        assert_eq!(seqs(&out), vec![base, base + 1, base + 2, base + 3]);
+        let stats = rx.stats();
+        assert_eq!((stats.reordered, stats.duplicates, stats.lost), (1, 1, 0));
+    }
+
+    #[test]
+    fn declares_loss_once_window_overflows() {
+        let mut tx = sender();
+        let dgrams: Vec<_> = (0..5).map(|i| tx.frame(i, false, &[])).collect();
+        let mut rx = MediaChannel::new();
+        let mut out = Vec::new();
+        for i in [0, 2, 3] {
+            rx.receive(&dgrams[i], 2, &mut out);
+        }
+        assert_eq!(out.len(), 1);
+        rx.receive(&dgrams[4], 2, &mut out);
+        assert_eq!(out.len(), 4);
+        assert_eq!(rx.stats().lost, 1);
+        rx.receive(&dgrams[1], 2, &mut out);
+        assert_eq!(rx.stats().late, 1);
+        rx.receive(&dgrams[3], 2, &mut out);
+        assert_eq!(rx.stats().duplicates, 1);
+        rx.receive(b"not media", 2, &mut out);
+        assert_eq!(rx.stats().invalid, 1);
+    }
+
+    #[test]
+    fn parity_fills_gap_before_window_gives_up() {
+        let mut tx = sender();
+        tx.set_fec(Some((3, 4)));
+        let mut dgrams = Vec::new();
+        for i in 0..3 {
+            let seq = tx.next_send_seq;
+            let dgram = tx.frame(i, false, &[i as u8]);
+            dgrams.extend(tx.fec_tx.as_mut().unwrap().protect(seq, &dgram));
+            dgrams.insert(i as usize, dgram);
+        }
+        assert_eq!(dgrams.len(), 4);
+        let mut rx = MediaChannel::new();
+        let mut out = Vec::new();
+        for i in [0, 2, 3] {
+            rx.receive(&dgrams[i], 2, &mut out);
+        }
+        assert_eq!(out.len(), 3);
+        let stats = rx.stats();
+        assert_eq!((stats.recovered, stats.lost), (1, 0));
+    }
+
+    #[test]
+    fn copies_spread_over_the_window() {
+        let mut tx = sender();
+        tx.set_redundancy(3, Duration::from_millis(40));
+        let start = Instant::now();
+        let dgram = tx.frame(0, false, b"pcm");
+        tx.schedule_copies(&dgram, start);
+        assert_eq!(tx.take_due(start), None);
+        assert_eq!(tx.take_due(start + Duration::from_millis(19)), None);
+        let due = start + Duration::from_millis(20);
+        assert_eq!(tx.take_due(due).as_deref(), Some(&dgram[..]));
+        assert_eq!(tx.take_due(due), None);
+        let last = start + Duration::from_millis(40);
+        assert_eq!(tx.take_due(last).as_deref(), Some(&dgram[..]));
+        assert_eq!(tx.take_due(last + Duration::from_secs(1)), None);
+
+        tx.schedule_copies(&dgram, start);
+        tx.set_redundancy(1, Duration::ZERO);
+        assert_eq!(tx.take_due(last), None);
+        tx.schedule_copies(&dgram, start);
+        assert!(tx.resend.is_empty());
+    }
+
+    #[test]
+    fn redundant_copies_count_as_duplicates() {
+        let mut tx = sender();
+        let dgrams: Vec<_> = (0..5).map(|i| tx.frame(i, false, &[])).collect();
+        let mut rx = MediaChannel::new();
+        let mut out = Vec::new();
+        for i in [0, 0, 1, 0, 1, 3, 4, 3] {
+            rx.receive(&dgrams[i], 1, &mut out);
+        }
+        // 2 never came: skipped once 3 and 4 filled the window.
+        assert_eq!(out.len(), 4);
+        let stats = rx.stats();
+        assert_eq!((stats.duplicates, stats.late, stats.lost), (4, 0, 1));
+        rx.receive(&dgrams[2], 1, &mut out);
+        assert_eq!(rx.stats().late, 1);
+        assert_eq!(out.len(), 4);
+    }
+
+    #[test]
+    fn copies_past_the_released_mask_count_as_late() {
+        let mut tx = sender();
+        let dgrams: Vec<_> = (0..70).map(|i| tx.frame(i, false, &[])).collect();
+        let mut rx = MediaChannel::new();
+        let mut out = Vec::new();
+        for dgram in &dgrams {
+            rx.receive(dgram, 4, &mut out);
+        }
+        assert_eq!(out.len(), 70);
+        // Sequence 6 is 63 behind the next one expected, the last the
+        // mask remembers; sequence 5 has aged out of it.
+        rx.receive(&dgrams[6], 4, &mut out);
+        assert_eq!(rx.stats().duplicates, 1);
+        rx.receive(&dgrams[5], 4, &mut out);
+        rx.receive(&dgrams[0], 4, &mut out);
+        let stats = rx.stats();
+        assert_eq!((stats.duplicates, stats.late, stats.released), (1, 2, 70));
+        assert_eq!(out.len(), 70);
+    }
+}
@@ -0,0 +1,314 @@
+//! Prometheus text-format metrics (`cc_quic_metrics_serve`), built only with
+//! the `metrics` feature, for a server role running on a hub that is
+//! already scraped. Counters cover every handle in the process:
+//!
+//! - `cribcall_quic_connections_accepted_total` and, at scrape time, the
+//!   `cribcall_quic_connections` gauge of announced connections;
+//! - `cribcall_quic_handshake_failures_total`, by `reason`: `incomplete`
+//!   for a connection that closed before its handshake finished,
+//!   `rejected` for a peer that failed pinning or the allowlist;
+//! - `cribcall_quic_{sent,recv}_bytes_total` of announced connections, as
+//!   published to the usage books about once a second;
+//! - `cribcall_quic_rtt_seconds`, a histogram sampled with those same
+//!   publications, so once a second per connection;
+//! - `cribcall_quic_event_queue_depth`, events waiting in polling queues.
+//!
+//! Each series but the queue depth carries a `role` label. The listener
+//! answers `GET /metrics` (any other path gets 404), one request per TCP
+//! connection, on a thread of its own. Without the feature the recording
+//! calls compile to nothing.
+
+#[cfg(feature = "metrics")]
+pub(crate) use enabled::{
+    add_bytes, connection_accepted, handshake_failed, observe_rtt, serve, Gauges,
+};
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn connection_accepted(_server: bool) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn handshake_failed(_server: bool, _rejected: bool) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn add_bytes(_server: bool, _sent: u64, _recv: u64) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn observe_rtt(_server: bool, _rtt: std::time::Duration) {}
+
+#[cfg(feature = "metrics")]
+mod enabled {
+    use once_cell::sync::{Lazy, OnceCell};
+    use std::fmt::Write as _;
+    use std::io::{self, BufRead, BufReader, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    const ROLES: [&str; 2] = ["client", "server"];
+    /// RTT histogram bucket bounds, in seconds.
+    const RTT_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+    /// How long one scrape may take to send its request.
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+    #[derive(Default)]
+    struct Histogram {
+        /// Per bucket, not cumulative; the last one is `+Inf`.
+        buckets: [AtomicU64; RTT_BUCKETS.len() + 1],
+        sum_us: AtomicU64,
+    }
+
+    #[derive(Default)]
+    struct RoleCounters {
+        accepted: AtomicU64,
+        incomplete: AtomicU64,
+        rejected: AtomicU64,
+        sent_bytes: AtomicU64,
+        recv_bytes: AtomicU64,
+        rtt: Histogram,
+    }
+
+    static COUNTERS: Lazy<[RoleCounters; 2]> = Lazy::new(Default::default);
+    /// The address being served, once `serve` succeeded.
+    static LISTENING: OnceCell<SocketAddr> = OnceCell::new();
+
+    fn role(server: bool) -> &'static RoleCounters {
+        &COUNTERS[usize::from(server)]
+    }
+
+    pub(crate) fn connection_accepted(server: bool) {
+        role(server).accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn handshake_failed(server: bool, rejected: bool) {
+        let counters = role(server);
+        let counter = if rejected {
+            &counters.rejected
+        } else {
+            &counters.incomplete
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_bytes(server: bool, sent: u64, recv: u64) {
+        let counters = role(server);
+        counters.sent_bytes.fetch_add(sent, Ordering::Relaxed);
+        counters.recv_bytes.fetch_add(recv, Ordering::Relaxed);
+    }
+
+    pub(crate) fn observe_rtt(server: bool, rtt: Duration) {
+        let histogram = &role(server).rtt;
+        let secs = rtt.as_secs_f64();
+        let bucket = RTT_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(RTT_BUCKETS.len());
+        histogram.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        histogram
+            .sum_us
+            .fetch_add(rtt.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Values read off the live handles at scrape time.
+    #[derive(Copy, Clone, Debug, Default)]
+    pub(crate) struct Gauges {
+        /// Announced connections, by role.
+        pub connections: [u64; 2],
+        pub event_queue_depth: u64,
+    }
+
+    /// Binds `addr` and answers scrapes from a background thread, reading
+    /// gauges from `gauges`. One listener per process; a second call fails
+    /// with `AlreadyExists`.
+    pub(crate) fn serve(addr: SocketAddr, gauges: fn() -> Gauges) -> io::Result<SocketAddr> {
+        if let Some(serving) = LISTENING.get() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("metrics are already served on {serving}"),
+            ));
+        }
+        let listener = TcpListener::bind(addr)?;
+        let local = listener.local_addr()?;
+        if LISTENING.set(local).is_err() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "metrics are already served",
+            ));
+        }
+        thread::Builder::new()
+            .name("cc-quic-metrics".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let result = stream.and_then(|stream| answer(stream, gauges));
+                    if let Err(err) = result {
+                        log::debug!("metrics scrape failed: {err}");
+                    }
+                }
+            })?;
+        Ok(local)
+    }
+
+    fn answer(stream: TcpStream, gauges: fn() -> Gauges) -> io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // Headers are read and ignored, so the client sees a clean close.
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+        let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+        let (status, body) = if request_line.starts_with("GET ")
+            && (path == "/metrics" || path.starts_with("/metrics?"))
+        {
+            ("200 OK", render(&gauges()))
+        } else {
+            ("404 Not Found", "not found\n".to_string())
+        };
+        let mut stream = &stream;
+        write!(
+            stream,
+            "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        stream.flush()
+    }
+
+    fn render(gauges: &Gauges) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut out = String::new();
+        // Every sample of a family goes right under its TYPE line.
+        let mut family = |name: &str, kind: &str, help: &str, value: &dyn Fn(usize) -> u64| {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+            for (index, role) in ROLES.into_iter().enumerate() {
+                let _ = writeln!(out, "{name}{{role=\"{role}\"}} {}", value(index));
+            }
+        };
+        family(
+            "cribcall_quic_connections",
+            "gauge",
+            "Connections announced and not yet closed.",
+            &|index| gauges.connections[index],
+        );
+        family(
+            "cribcall_quic_connections_accepted_total",
+            "counter",
+            "Connections that completed their handshake and passed pinning.",
+            &|index| load(&COUNTERS[index].accepted),
+        );
+        family(
+            "cribcall_quic_sent_bytes_total",
+            "counter",
+            "Bytes sent on announced connections.",
+            &|index| load(&COUNTERS[index].sent_bytes),
+        );
+        family(
+            "cribcall_quic_recv_bytes_total",
+            "counter",
+            "Bytes received on announced connections.",
+            &|index| load(&COUNTERS[index].recv_bytes),
+        );
+
+        let name = "cribcall_quic_handshake_failures_total";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Connections closed before their handshake finished, \
+             or rejected after it.\n# TYPE {name} counter"
+        );
+        for (counters, role) in COUNTERS.iter().zip(ROLES) {
+            for (reason, count) in [
+                ("incomplete", &counters.incomplete),
+                ("rejected", &counters.rejected),
+            ] {
+                let _ = writeln!(
+                    out,
+                    "{name}{{role=\"{role}\",reason=\"{reason}\"}} {}",
+                    load(count)
+                );
+            }
+        }
+
+        let name = "cribcall_quic_rtt_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Smoothed RTT, sampled once a second per connection.\n\
+             # TYPE {name} histogram"
+        );
+        for (counters, role) in COUNTERS.iter().zip(ROLES) {
+            let mut cumulative = 0;
+            for (bucket, count) in counters.rtt.buckets.iter().enumerate() {
+                cumulative += load(count);
+                let le = RTT_BUCKETS
+                    .get(bucket)
+                    .map_or("+Inf".to_string(), f64::to_string);
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{{role=\"{role}\",le=\"{le}\"}} {cumulative}"
+                );
+            }
+            let sum = load(&counters.rtt.sum_us) as f64 / 1e6;
+            let _ = writeln!(
+                out,
+                "{name}_sum{{role=\"{role}\"}} {sum}\n{name}_count{{role=\"{role}\"}} {cumulative}"
+            );
+        }
+
+        let name = "cribcall_quic_event_queue_depth";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Events waiting in polling queues.\n# TYPE {name} gauge\n{name} {}",
+            gauges.event_queue_depth
+        );
+        out
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// The value of `series` in `text`; other tests' connections feed the
+        /// same process-wide counters, so assertions compare before and after.
+        fn value(text: &str, series: &str) -> u64 {
+            text.lines()
+                .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0)
+        }
+
+        #[test]
+        fn renders_counters_and_cumulative_buckets() {
+            let gauges = Gauges {
+                connections: [0, 2],
+                event_queue_depth: 7,
+            };
+            let rtt = |le: &str| {
+                format!("cribcall_quic_rtt_seconds_bucket{{role=\"server\",le=\"{le}\"}}")
+            };
+            let series = [
+                "cribcall_quic_handshake_failures_total{role=\"server\",reason=\"rejected\"}"
+                    .to_string(),
+                rtt("0.005"),
+                rtt("0.05"),
+                rtt("+Inf"),
+                "cribcall_quic_rtt_seconds_count{role=\"server\"}".to_string(),
+            ];
+            let before = render(&gauges);
+            observe_rtt(true, Duration::from_millis(3));
+            observe_rtt(true, Duration::from_millis(40));
+            observe_rtt(true, Duration::from_secs(9));
+            connection_accepted(true);
+            handshake_failed(true, true);
+            let text = render(&gauges);
+            assert!(text.contains("# TYPE cribcall_quic_rtt_seconds histogram\n"));
+            assert!(text.contains("cribcall_quic_connections{role=\"server\"} 2\n"));
+            assert!(text.contains("cribcall_quic_event_queue_depth 7\n"));
+            let grown: Vec<u64> = series
+                .iter()
+                .map(|series| value(&text, series) - value(&before, series))
+                .collect();
+            assert_eq!(grown, [1, 1, 2, 3, 3]);
+        }
+    }
+}
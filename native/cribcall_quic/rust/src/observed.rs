@@ -0,0 +1,205 @@
+//! Server-to-client address records, on a dedicated unidirectional stream
+//! (server id 7):
+//!
+//! - observed (`cc_quic_config_set_observed_address`): the source address
+//!   the client's packets arrive from, sent once the connection is accepted
+//!   and again whenever the path changes. The client posts
+//!   `observed_address`, which gives it its NAT mapping without a STUN round.
+//! - preferred (`cc_quic_server_set_preferred_address`): the server
+//!   endpoint the client should move to. quiche does not carry the
+//!   `preferred_address` transport parameter, so this stands in for it.
+//! - connect (`cc_quic_server_request_connect`): asks a client in reverse
+//!   connect mode to dial a new connection back to an endpoint (see
+//!   `reverse.rs`). The unspecified address with port 0 means the endpoint
+//!   the client reached, and an optional pin names the certificate to
+//!   expect there.
+//!
+//! Record: `kind (1 observed, 2 preferred, 3 connect) | family (4 or 6) |
+//! address (4 or 16 bytes) | port u16 BE`; a connect record goes on with
+//! `pin length u8 (0 or 32) | pin`.
+
+use std::net::{IpAddr, SocketAddr};
+
+const SERVER_STREAM: u64 = 7;
+const OBSERVED: u8 = 1;
+const PREFERRED: u8 = 2;
+const CONNECT: u8 = 3;
+const PIN_LEN: usize = 32;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum AddressRecord {
+    Observed(SocketAddr),
+    Preferred(SocketAddr),
+    /// `addr` `None` is the endpoint the client reached; `pin` `None` is
+    /// the certificate it pinned there.
+    Connect {
+        addr: Option<SocketAddr>,
+        pin: Option<[u8; PIN_LEN]>,
+    },
+}
+
+pub(crate) fn is_observed_stream(stream_id: u64) -> bool {
+    stream_id == SERVER_STREAM
+}
+
+/// Queues `record` to the client of `conn`.
+pub(crate) fn send(conn: &mut quiche::Connection, record: AddressRecord) -> quiche::Result<()> {
+    conn.stream_send(SERVER_STREAM, &encode(record), false)
+        .map(|_| ())
+}
+
+fn encode(record: AddressRecord) -> Vec<u8> {
+    let (kind, addr) = match record {
+        AddressRecord::Observed(addr) => (OBSERVED, addr),
+        AddressRecord::Preferred(addr) => (PREFERRED, addr),
+        AddressRecord::Connect { addr, .. } => (
+            CONNECT,
+            addr.unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0))),
+        ),
+    };
+    let mut out = vec![kind];
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            out.push(4);
+            out.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            out.push(6);
+            out.extend_from_slice(&ip.octets());
+        }
+    }
+    out.extend_from_slice(&addr.port().to_be_bytes());
+    if let AddressRecord::Connect { pin, .. } = record {
+        match pin {
+            Some(pin) => {
+                out.push(PIN_LEN as u8);
+                out.extend_from_slice(&pin);
+            }
+            None => out.push(0),
+        }
+    }
+    out
+}
+
+/// Address and port of one record, past its kind and family bytes.
+fn decode(body: &[u8]) -> SocketAddr {
+    let (ip, port) = body.split_at(body.len() - 2);
+    let ip = if ip.len() == 4 {
+        let mut octets = [0; 4];
+        octets.copy_from_slice(ip);
+        IpAddr::from(octets)
+    } else {
+        let mut octets = [0; 16];
+        octets.copy_from_slice(ip);
+        IpAddr::from(octets)
+    };
+    SocketAddr::new(ip, u16::from_be_bytes([port[0], port[1]]))
+}
+
+/// Reassembles the server's records across stream reads.
+#[derive(Default)]
+pub(crate) struct ObservedInbox {
+    partial: Vec<u8>,
+    /// Set once the stream carried something this version cannot parse;
+    /// the rest of it is ignored.
+    broken: bool,
+}
+
+impl ObservedInbox {
+    /// Feeds bytes read from the address stream; pushes each complete
+    /// record.
+    pub(crate) fn on_data(&mut self, data: &[u8], out: &mut Vec<AddressRecord>) {
+        if self.broken {
+            return;
+        }
+        self.partial.extend_from_slice(data);
+        let mut used = 0;
+        while let Some(rest) = self.partial.get(used..) {
+            let ip_len = match rest {
+                [OBSERVED | PREFERRED | CONNECT, 4, ..] => 4,
+                [OBSERVED | PREFERRED | CONNECT, 6, ..] => 16,
+                [] | [OBSERVED | PREFERRED | CONNECT] => break,
+                _ => {
+                    self.broken = true;
+                    self.partial.clear();
+                    return;
+                }
+            };
+            let addr_len = 2 + ip_len + 2;
+            let pin_len = match (rest[0], rest.get(addr_len)) {
+                (CONNECT, None) => break,
+                (CONNECT, Some(0)) => Some(0),
+                (CONNECT, Some(&len)) if usize::from(len) == PIN_LEN => Some(PIN_LEN),
+                (CONNECT, Some(_)) => {
+                    self.broken = true;
+                    self.partial.clear();
+                    return;
+                }
+                _ => None,
+            };
+            let record_len = addr_len + pin_len.map_or(0, |len| 1 + len);
+            let Some(record) = rest.get(..record_len) else {
+                break;
+            };
+            let addr = decode(&record[2..addr_len]);
+            out.push(match record[0] {
+                OBSERVED => AddressRecord::Observed(addr),
+                PREFERRED => AddressRecord::Preferred(addr),
+                _ => AddressRecord::Connect {
+                    addr: (addr.port() != 0 || !addr.ip().is_unspecified()).then_some(addr),
+                    pin: record[addr_len + 1..].try_into().ok(),
+                },
+            });
+            used += record_len;
+        }
+        self.partial.drain(..used);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reassembles_records_of_both_families() {
+        let v4 = AddressRecord::Observed("203.0.113.7:40123".parse().unwrap());
+        let v6 = AddressRecord::Preferred("[2001:db8::9]:443".parse().unwrap());
+        let wire = [encode(v4), encode(v6)].concat();
+
+        let mut inbox = ObservedInbox::default();
+        let mut out = Vec::new();
+        inbox.on_data(&wire[..5], &mut out);
+        assert!(out.is_empty());
+        inbox.on_data(&wire[5..12], &mut out);
+        assert_eq!(out, [v4]);
+        inbox.on_data(&wire[12..], &mut out);
+        assert_eq!(out, [v4, v6]);
+
+        inbox.on_data(&[9, 9, 9], &mut out);
+        inbox.on_data(&encode(v4), &mut out);
+        assert_eq!(out.len(), 2);
+    }
+
+    #[test]
+    fn connect_records_carry_an_optional_pin() {
+        let back = AddressRecord::Connect {
+            addr: None,
+            pin: None,
+        };
+        let pinned = AddressRecord::Connect {
+            addr: Some("[2001:db8::5]:4433".parse().unwrap()),
+            pin: Some([0xab; PIN_LEN]),
+        };
+        let wire = [encode(back), encode(pinned)].concat();
+        assert_eq!(wire.len(), 9 + 21 + PIN_LEN);
+
+        let mut inbox = ObservedInbox::default();
+        let mut out = Vec::new();
+        inbox.on_data(&wire[..8], &mut out);
+        assert!(out.is_empty());
+        inbox.on_data(&wire[8..30], &mut out);
+        assert_eq!(out, [back]);
+        inbox.on_data(&wire[30..], &mut out);
+        assert_eq!(out, [back, pinned]);
+    }
+}
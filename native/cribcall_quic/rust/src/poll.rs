@@ -0,0 +1,200 @@
+//! Event queues for embedders without a Dart isolate. Handles started with
+//! `dart_port == POLL_PORT` buffer their event JSON here until drained by
+//! `cc_quic_events_poll`. Handles the Rust APIs (`api.rs`, `asyncapi.rs`)
+//! start are typed instead: their events are held as [`Event`]s, never
+//! serialized, until `attach` hands them to a sink.
+
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use std::collections::VecDeque;
+
+use crate::api::Event;
+
+/// Dart's `ILLEGAL_PORT`; no isolate can own it, so it selects polling.
+pub(crate) const POLL_PORT: i64 = 0;
+/// Events buffered per handle; further events are refused (and reported
+/// through `events_dropped`) until the embedder polls.
+pub(crate) const QUEUE_CAPACITY: usize = 1024;
+
+/// Takes each event of a typed handle; false refuses it.
+type Sink = Box<dyn FnMut(Event) -> bool + Send + Sync>;
+
+#[derive(Default)]
+struct Queue {
+    events: VecDeque<String>,
+    /// Set by `hold_typed`; events then go here instead.
+    typed: Option<Typed>,
+}
+
+#[derive(Default)]
+struct Typed {
+    /// Events posted before `attach`.
+    held: VecDeque<Event>,
+    sink: Option<Sink>,
+}
+
+static QUEUES: Lazy<DashMap<u64, Queue>> = Lazy::new(DashMap::new);
+
+/// Queues one serialized event; false when the handle's ring is full.
+pub(crate) fn push(handle: u64, json: String) -> bool {
+    let mut queue = QUEUES.entry(handle).or_default();
+    if queue.events.len() >= QUEUE_CAPACITY {
+        return false;
+    }
+    queue.events.push_back(json);
+    true
+}
+
+/// Keeps `handle`'s events typed; called before its first event.
+pub(crate) fn hold_typed(handle: u64) {
+    QUEUES
+        .entry(handle)
+        .or_default()
+        .typed
+        .get_or_insert_with(Typed::default);
+}
+
+/// Hands the event `make` builds to `handle`'s sink, or holds it until one
+/// is attached; false when either refuses it. `None`, without building
+/// the event, when the handle's events are JSON.
+pub(crate) fn push_typed(handle: u64, make: impl FnOnce() -> Event) -> Option<bool> {
+    let mut queue = QUEUES.get_mut(&handle)?;
+    let typed = queue.typed.as_mut()?;
+    let event = make();
+    Some(match &mut typed.sink {
+        Some(sink) => sink(event),
+        None if typed.held.len() >= QUEUE_CAPACITY => false,
+        None => {
+            typed.held.push_back(event);
+            true
+        }
+    })
+}
+
+/// Hands `handle`'s events to `sink` from now on, starting with those
+/// already held. A sink should hold at least `QUEUE_CAPACITY` events so
+/// none of those is refused.
+pub(crate) fn attach(handle: u64, mut sink: impl FnMut(Event) -> bool + Send + Sync + 'static) {
+    let mut queue = QUEUES.entry(handle).or_default();
+    let typed = queue.typed.get_or_insert_with(Typed::default);
+    for event in typed.held.drain(..) {
+        sink(event);
+    }
+    typed.sink = Some(Box::new(sink));
+}
+
+/// Forgets `handle`'s queue and sink.
+pub(crate) fn detach(handle: u64) {
+    QUEUES.remove(&handle);
+}
+
+/// Why a drain stopped before writing anything.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum DrainError {
+    /// The oldest queued event is larger than the whole buffer.
+    TooSmall,
+}
+
+/// Moves up to `max_events` events into `out` as newline-terminated JSON
+/// followed by a NUL, returning how many were written. Events that do not
+/// fit stay queued. Queues of handles that are gone are freed once empty.
+pub(crate) fn drain(
+    handle: u64,
+    out: &mut [u8],
+    max_events: usize,
+    live: bool,
+) -> Result<usize, DrainError> {
+    let Some(mut queue) = QUEUES.get_mut(&handle) else {
+        if let Some(b) = out.first_mut() {
+            *b = 0;
+        }
+        return Ok(0);
+    };
+    let mut written = 0;
+    let mut used = 0;
+    while written < max_events {
+        let Some(next) = queue.events.front() else {
+            break;
+        };
+        // Room for the event, its newline and the trailing NUL.
+        let end = used + next.len() + 1;
+        if end + 1 > out.len() {
+            if written == 0 {
+                return Err(DrainError::TooSmall);
+            }
+            break;
+        }
+        out[used..end - 1].copy_from_slice(next.as_bytes());
+        out[end - 1] = b'\n';
+        used = end;
+        written += 1;
+        queue.events.pop_front();
+    }
+    if let Some(b) = out.get_mut(used) {
+        *b = 0;
+    }
+    let empty = queue.events.is_empty() && queue.typed.is_none();
+    drop(queue);
+    if empty && !live {
+        QUEUES.remove_if(&handle, |_, queue| queue.events.is_empty());
+    }
+    Ok(written)
+}
+
+/// Events waiting across every handle's queue.
+#[cfg(feature = "metrics")]
+pub(crate) fn depth() -> usize {
+    QUEUES.iter().map(|queue| queue.events.len()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drains_what_fits_and_keeps_the_rest() {
+        let handle = u64::MAX - 3;
+        assert!(push(handle, r#"{"seq":1}"#.into()));
+        assert!(push(handle, r#"{"seq":2}"#.into()));
+
+        let mut tiny = [0u8; 4];
+        assert_eq!(drain(handle, &mut tiny, 8, true), Err(DrainError::TooSmall));
+
+        let mut buf = [0u8; 12];
+        assert_eq!(drain(handle, &mut buf, 8, true), Ok(1));
+        assert_eq!(&buf[..11], b"{\"seq\":1}\n\0");
+
+        let mut buf = [0u8; 64];
+        assert_eq!(drain(handle, &mut buf, 8, false), Ok(1));
+        assert!(!QUEUES.contains_key(&handle));
+    }
+
+    #[test]
+    fn refuses_events_past_capacity() {
+        let handle = u64::MAX - 4;
+        for _ in 0..QUEUE_CAPACITY {
+            assert!(push(handle, String::new()));
+        }
+        assert!(!push(handle, String::new()));
+        QUEUES.remove(&handle);
+    }
+
+    #[test]
+    fn typed_handles_hold_events_until_attached() {
+        let handle = u64::MAX - 5;
+        let event = |seq: u64| Event::Other(serde_json::json!({ "seq": seq }));
+        assert_eq!(push_typed(handle, || event(0)), None);
+        hold_typed(handle);
+        assert_eq!(push_typed(handle, || event(1)), Some(true));
+        let (tx, rx) = std::sync::mpsc::sync_channel(QUEUE_CAPACITY);
+        attach(handle, move |event| tx.try_send(event).is_ok());
+        assert_eq!(push_typed(handle, || event(2)), Some(true));
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [event(1), event(2)]);
+        let mut buf = [0u8; 8];
+        assert_eq!(drain(handle, &mut buf, 8, true), Ok(0));
+        drop(rx);
+        assert_eq!(push_typed(handle, || event(3)), Some(false));
+        detach(handle);
+        assert!(!QUEUES.contains_key(&handle));
+    }
+}
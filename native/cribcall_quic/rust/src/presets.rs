@@ -0,0 +1,111 @@
+//! Named profiles for `cc_quic_config_new_preset`. Each starts from the
+//! `cc_quic_config_new` defaults and sets the knobs that matter for one
+//! deployment shape; the individual setters can still adjust the result.
+
+use crate::flowtune::FlowWindow;
+use crate::{CcQuicConfig, Error};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Preset {
+    /// Same-LAN monitor: short idle timeout, small windows, shallow
+    /// DATAGRAM queues so stale media is dropped rather than queued.
+    LanLowLatency,
+    /// Remote monitoring over LTE/5G: BBR, large windows for the BDP,
+    /// keepalives under typical carrier NAT timeouts, and PMTUD from 1200 so
+    /// tunnels with small MTUs still work.
+    CellularRemote,
+    /// Recordings and firmware: the largest windows, deep queues, no
+    /// keepalive.
+    BulkTransfer,
+}
+
+struct Profile {
+    idle_timeout_ms: u64,
+    window: FlowWindow,
+    cc_algorithm: &'static str,
+    dgram_queue_len: usize,
+    keepalive_ms: u64,
+    pmtu_discovery: bool,
+}
+
+impl Preset {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "lan-low-latency" => Some(Self::LanLowLatency),
+            "cellular-remote" => Some(Self::CellularRemote),
+            "bulk-transfer" => Some(Self::BulkTransfer),
+            _ => None,
+        }
+    }
+
+    fn profile(self) -> Profile {
+        match self {
+            Self::LanLowLatency => Profile {
+                idle_timeout_ms: 10_000,
+                window: FlowWindow {
+                    min: 256 * 1024,
+                    max: 4 * 1024 * 1024,
+                },
+                cc_algorithm: "cubic",
+                dgram_queue_len: 64,
+                keepalive_ms: 0,
+                pmtu_discovery: false,
+            },
+            Self::CellularRemote => Profile {
+                idle_timeout_ms: 60_000,
+                window: FlowWindow::default(),
+                cc_algorithm: "bbr2",
+                dgram_queue_len: 256,
+                keepalive_ms: 15_000,
+                pmtu_discovery: true,
+            },
+            Self::BulkTransfer => Profile {
+                idle_timeout_ms: 30_000,
+                window: FlowWindow {
+                    min: 4 * 1024 * 1024,
+                    max: 32 * 1024 * 1024,
+                },
+                cc_algorithm: "cubic",
+                dgram_queue_len: crate::DEFAULT_DGRAM_QUEUE_LEN,
+                keepalive_ms: 0,
+                pmtu_discovery: false,
+            },
+        }
+    }
+
+    pub(crate) fn apply(self, config: &mut CcQuicConfig) -> Result<(), Error> {
+        let profile = self.profile();
+        config.quic.idle_timeout_ms = profile.idle_timeout_ms;
+        config.quic.cc_algorithm = profile.cc_algorithm.parse().map_err(|_| {
+            Error::Config(format!(
+                "unknown congestion control {}",
+                profile.cc_algorithm
+            ))
+        })?;
+        config.quic.dgram_send_queue_len = profile.dgram_queue_len;
+        config.quic.pmtu_discovery = profile.pmtu_discovery;
+        config.options.flow_window = profile.window;
+        config.options.dgram_recv_queue_len = profile.dgram_queue_len;
+        config.options.keepalive_ms = profile.keepalive_ms;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_named_preset_is_consistent() {
+        for name in ["lan-low-latency", "cellular-remote", "bulk-transfer"] {
+            let profile = Preset::from_name(name).unwrap().profile();
+            assert!(profile.window.min <= profile.window.max, "{name}");
+            assert!(profile.keepalive_ms < profile.idle_timeout_ms, "{name}");
+            assert!(profile
+                .cc_algorithm
+                .parse::<quiche::CongestionControlAlgorithm>()
+                .is_ok());
+        }
+        assert_eq!(Preset::from_name("LAN"), None);
+    }
+}
@@ -0,0 +1,69 @@
+//! Server-side connection reaping (`cc_quic_config_set_connection_limits`).
+//! Without it a handshake a scanner abandons sits in the worker until
+//! quiche's idle timeout, and an established connection lives forever; the
+//! worker closes offenders itself and posts `connection_reaped`.
+
+use serde::Serialize;
+use std::time::Duration;
+
+/// Application close code for a handshake that ran out of time.
+pub(crate) const HANDSHAKE_CLOSE_CODE: u64 = 0x104;
+/// Application close code for a connection past its maximum lifetime.
+pub(crate) const LIFETIME_CLOSE_CODE: u64 = 0x105;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct ReapPolicy {
+    /// Close established connections this long after they were accepted.
+    pub max_lifetime: Option<Duration>,
+    /// Close connections still handshaking this long after the first
+    /// Initial arrived.
+    pub max_handshake: Option<Duration>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ReapReason {
+    Handshake,
+    Lifetime,
+}
+
+impl ReapReason {
+    /// Code and reason phrase sent in the CONNECTION_CLOSE.
+    pub(crate) fn close_frame(self) -> (u64, &'static [u8]) {
+        match self {
+            Self::Handshake => (HANDSHAKE_CLOSE_CODE, b"handshake timeout"),
+            Self::Lifetime => (LIFETIME_CLOSE_CODE, b"max lifetime"),
+        }
+    }
+}
+
+impl ReapPolicy {
+    /// Whether a connection `age` old is over its limit.
+    pub(crate) fn due(&self, established: bool, age: Duration) -> Option<ReapReason> {
+        let (limit, reason) = if established {
+            (self.max_lifetime, ReapReason::Lifetime)
+        } else {
+            (self.max_handshake, ReapReason::Handshake)
+        };
+        limit.is_some_and(|limit| age >= limit).then_some(reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_the_limit_for_the_connection_state() {
+        let policy = ReapPolicy {
+            max_lifetime: Some(Duration::from_secs(60)),
+            max_handshake: Some(Duration::from_secs(5)),
+        };
+        let secs = Duration::from_secs;
+        assert_eq!(policy.due(false, secs(4)), None);
+        assert_eq!(policy.due(false, secs(5)), Some(ReapReason::Handshake));
+        assert_eq!(policy.due(true, secs(30)), None);
+        assert_eq!(policy.due(true, secs(61)), Some(ReapReason::Lifetime));
+        assert_eq!(ReapPolicy::default().due(false, secs(3600)), None);
+    }
+}
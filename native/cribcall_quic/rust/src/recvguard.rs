@@ -0,0 +1,202 @@
+//! Receive-size caps on stream data, checked before bytes become `message`
+//! events. On the control stream a message is one length-prefixed frame
+//! (4-byte big-endian length, as the Dart control codec writes them); on
+//! any other stream it is the whole stream. A per-connection cap bounds all
+//! stream bytes over the connection's life.
+
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::compress::COMPRESSED_FLAG;
+
+const FRAME_HEADER_LEN: usize = 4;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct RecvLimits {
+    pub max_message: Option<u64>,
+    pub max_connection: Option<u64>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LimitScope {
+    Message,
+    Connection,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Violation {
+    pub scope: LimitScope,
+    pub limit: u64,
+    /// Size that broke the limit: the declared frame length, the stream's
+    /// length so far, or the connection total.
+    pub size: u64,
+    /// Leading bytes of the chunk that are still within limits.
+    pub forward: usize,
+}
+
+enum StreamState {
+    /// Control stream: header bytes gathered so far, then body bytes left.
+    Framed {
+        header: [u8; FRAME_HEADER_LEN],
+        have: usize,
+        body_left: u64,
+    },
+    Whole {
+        seen: u64,
+    },
+}
+
+#[derive(Default)]
+pub(crate) struct RecvGuard {
+    total: u64,
+    streams: HashMap<u64, StreamState>,
+}
+
+impl RecvGuard {
+    /// Accounts for `data` read from `stream_id`; `fin` ends the stream.
+    pub(crate) fn check(
+        &mut self,
+        stream_id: u64,
+        data: &[u8],
+        fin: bool,
+        limits: &RecvLimits,
+    ) -> Result<(), Violation> {
+        if limits.max_message.is_none() && limits.max_connection.is_none() {
+            return Ok(());
+        }
+        let mut forward = data.len();
+        let mut violation = None;
+        if let Some(limit) = limits.max_connection {
+            let room = limit.saturating_sub(self.total);
+            if data.len() as u64 > room {
+                forward = room as usize;
+                violation = Some(Violation {
+                    scope: LimitScope::Connection,
+                    limit,
+                    size: self.total + data.len() as u64,
+                    forward,
+                });
+            }
+        }
+        if let Some(limit) = limits.max_message {
+            if let Err(v) = self.check_message(stream_id, &data[..forward], limit) {
+                violation = Some(v);
+            }
+        }
+        self.total += violation.map_or(data.len(), |v| v.forward) as u64;
+        if fin || violation.is_some() {
+            self.streams.remove(&stream_id);
+        }
+        violation.map_or(Ok(()), Err)
+    }
+
+    fn check_message(&mut self, stream_id: u64, data: &[u8], limit: u64) -> Result<(), Violation> {
+        let state = self.streams.entry(stream_id).or_insert_with(|| {
+            if stream_id == crate::CONTROL_STREAM_ID {
+                StreamState::Framed {
+                    header: [0; FRAME_HEADER_LEN],
+                    have: 0,
+                    body_left: 0,
+                }
+            } else {
+                StreamState::Whole { seen: 0 }
+            }
+        });
+        match state {
+            StreamState::Whole { seen } => {
+                *seen += data.len() as u64;
+                if *seen > limit {
+                    let over = (*seen - limit) as usize;
+                    return Err(Violation {
+                        scope: LimitScope::Message,
+                        limit,
+                        size: *seen,
+                        forward: data.len() - over,
+                    });
+                }
+            }
+            StreamState::Framed {
+                header,
+                have,
+                body_left,
+            } => {
+                let mut at = 0;
+                while at < data.len() {
+                    if *body_left > 0 {
+                        let take = (*body_left).min((data.len() - at) as u64);
+                        *body_left -= take;
+                        at += take as usize;
+                        continue;
+                    }
+                    // A header split across reads started in an earlier chunk.
+                    let frame_start = at.saturating_sub(*have);
+                    let take = (FRAME_HEADER_LEN - *have).min(data.len() - at);
+                    header[*have..*have + take].copy_from_slice(&data[at..at + take]);
+                    *have += take;
+                    at += take;
+                    if *have < FRAME_HEADER_LEN {
+                        break;
+                    }
+                    *have = 0;
+                    // Compressed frames are bounded again once inflated.
+                    let declared = (u32::from_be_bytes(*header) & !COMPRESSED_FLAG) as u64;
+                    if declared > limit {
+                        return Err(Violation {
+                            scope: LimitScope::Message,
+                            limit,
+                            size: declared,
+                            forward: frame_start,
+                        });
+                    }
+                    *body_left = declared;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(len: u32) -> Vec<u8> {
+        let mut out = len.to_be_bytes().to_vec();
+        out.resize(FRAME_HEADER_LEN + len as usize, 7);
+        out
+    }
+
+    #[test]
+    fn oversized_control_frame_stops_at_its_header() {
+        let limits = RecvLimits {
+            max_message: Some(16),
+            max_connection: None,
+        };
+        let mut guard = RecvGuard::default();
+        let mut chunk = frame(16);
+        chunk.extend_from_slice(&frame(17)[..6]);
+        let violation = guard.check(0, &chunk, false, &limits).unwrap_err();
+        assert_eq!(violation.scope, LimitScope::Message);
+        assert_eq!((violation.size, violation.forward), (17, 20));
+        // Other streams count the whole stream as one message.
+        assert!(guard.check(8, &[0; 15], false, &limits).is_ok());
+        assert_eq!(
+            guard.check(8, &[0; 4], false, &limits).unwrap_err().forward,
+            1
+        );
+    }
+
+    #[test]
+    fn connection_cap_counts_every_stream() {
+        let limits = RecvLimits {
+            max_message: None,
+            max_connection: Some(10),
+        };
+        let mut guard = RecvGuard::default();
+        assert!(guard.check(0, &[0; 6], false, &limits).is_ok());
+        let violation = guard.check(12, &[0; 6], true, &limits).unwrap_err();
+        assert_eq!(violation.scope, LimitScope::Connection);
+        assert_eq!((violation.size, violation.forward), (12, 4));
+    }
+}
@@ -0,0 +1,119 @@
+//! Client side of server relocation (`cc_quic_server_set_preferred_address`).
+//! When the server names a preferred endpoint on its address stream the
+//! client validates a path to it with its current local address, migrates
+//! once the path answers and posts `server_relocated`; the connection, its
+//! streams and its keys carry over without a new handshake.
+
+use rand::{rngs::OsRng, RngCore};
+use std::net::SocketAddr;
+
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum Outcome {
+    Moved(SocketAddr),
+    Failed(SocketAddr, String),
+}
+
+#[derive(Default)]
+pub(crate) struct Relocation {
+    target: Option<SocketAddr>,
+    probing: bool,
+    spare_issued: bool,
+}
+
+impl Relocation {
+    /// Records the server's latest preferred endpoint; the one it is
+    /// already reached on needs no move.
+    pub(crate) fn prefer(&mut self, addr: SocketAddr, current_peer: Option<SocketAddr>) {
+        if current_peer == Some(addr) {
+            self.target = None;
+        } else if self.target != Some(addr) {
+            self.target = Some(addr);
+            self.probing = false;
+        }
+    }
+
+    /// The endpoint a move is under way to.
+    pub(crate) fn target(&self) -> Option<SocketAddr> {
+        self.target
+    }
+
+    /// Steps the move along once per worker pass: drains path events and
+    /// starts the probe when none is out.
+    pub(crate) fn drive(&mut self, conn: &mut quiche::Connection) -> Option<Outcome> {
+        let mut outcome = None;
+        while let Some(event) = conn.path_event_next() {
+            match event {
+                quiche::PathEvent::Validated(local, peer) if self.target == Some(peer) => {
+                    self.target = None;
+                    outcome = Some(match conn.migrate(local, peer) {
+                        Ok(_) => Outcome::Moved(peer),
+                        Err(err) => Outcome::Failed(peer, format!("migrate failed: {err:?}")),
+                    });
+                }
+                quiche::PathEvent::FailedValidation(_, peer) if self.target == Some(peer) => {
+                    self.target = None;
+                    outcome = Some(Outcome::Failed(peer, "path validation failed".into()));
+                }
+                _ => {}
+            }
+        }
+
+        let Some(target) = self.target.filter(|_| !self.probing) else {
+            return outcome;
+        };
+        if !conn.is_established() {
+            return outcome;
+        }
+        let Some(local) = conn
+            .path_stats()
+            .find(|path| path.active)
+            .map(|path| path.local_addr)
+        else {
+            return outcome;
+        };
+        // The probe needs a connection id of ours the server has not seen
+        // used yet; it may take a round trip to be acknowledged.
+        if !self.spare_issued && conn.scids_left() > 0 {
+            self.spare_issued = true;
+            let mut cid = [0u8; quiche::MAX_CONN_ID_LEN];
+            OsRng.fill_bytes(&mut cid);
+            let mut token = [0u8; 16];
+            OsRng.fill_bytes(&mut token);
+            let scid = quiche::ConnectionId::from_ref(&cid);
+            if let Err(err) = conn.new_scid(&scid, u128::from_be_bytes(token), false) {
+                log::debug!("relocation scid not issued: {err:?}");
+            }
+        }
+        match conn.probe_path(local, target) {
+            Ok(_) => self.probing = true,
+            // Waiting on connection ids; retried on the next pass.
+            Err(quiche::Error::OutOfIdentifiers) => {}
+            Err(err) => {
+                self.target = None;
+                outcome = Some(Outcome::Failed(target, format!("probe failed: {err:?}")));
+            }
+        }
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignores_the_endpoint_already_in_use() {
+        let a: SocketAddr = "192.0.2.1:4433".parse().unwrap();
+        let b: SocketAddr = "192.0.2.2:4433".parse().unwrap();
+        let mut relocation = Relocation::default();
+        relocation.prefer(a, Some(a));
+        assert_eq!(relocation.target, None);
+
+        relocation.prefer(b, Some(a));
+        relocation.probing = true;
+        relocation.prefer(b, Some(a));
+        assert!(relocation.probing);
+        relocation.prefer(a, Some(a));
+        assert_eq!(relocation.target, None);
+    }
+}
@@ -0,0 +1,535 @@
+//! Deterministic replay of recorded server traffic, for regression tests of
+//! connection routing. A trace read from a pcap capture or a qlog file is
+//! fed, packet by packet, through the decision the server workers make on
+//! every datagram (`classify_inbound` and `owning_worker` in `lib.rs`), and
+//! the resulting steps are compared with what the recording should produce:
+//! which datagrams open a connection, which reach one, which are handed to
+//! a sibling `SO_REUSEPORT` worker and which are dropped.
+//!
+//! Only headers are replayed. Captured payloads are encrypted with keys we
+//! do not have, so quiche cannot decrypt them again and stream framing is
+//! out of reach; it is covered by the unit tests that drive real
+//! connections. For the same reason a replay never sees a connection
+//! close, so connection ids stay live to the end of the trace. The server's
+//! SCID for a connection is learned from the first packet the server sent
+//! to the client's SCID, where a real worker would have picked it.
+
+use crate::cids::CidIndex;
+use crate::{classify_inbound, owning_worker, Inbound};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// One recorded QUIC packet, seen from the server.
+#[derive(Clone, Debug)]
+pub(crate) struct Packet {
+    /// Capture time, in microseconds from the start of the trace.
+    pub at_us: u64,
+    /// Sent by the client; otherwise sent by the server.
+    pub inbound: bool,
+    /// `None` when the header does not parse.
+    pub header: Option<Header>,
+    /// Datagram bytes.
+    pub len: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Header {
+    pub ty: quiche::Type,
+    pub dcid: Vec<u8>,
+    pub scid: Vec<u8>,
+}
+
+impl Packet {
+    /// `data` parsed the way a server worker parses a received datagram.
+    pub(crate) fn from_datagram(at_us: u64, inbound: bool, data: &[u8]) -> Self {
+        let mut buf = data.to_vec();
+        let header = quiche::Header::from_slice(&mut buf, quiche::MAX_CONN_ID_LEN)
+            .ok()
+            .map(|hdr| Header {
+                ty: hdr.ty,
+                dcid: hdr.dcid.to_vec(),
+                scid: hdr.scid.to_vec(),
+            });
+        Self {
+            at_us,
+            inbound,
+            header,
+            len: data.len(),
+        }
+    }
+}
+
+/// What replaying one inbound packet did. Connections are numbered in the
+/// order they were accepted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Step {
+    Accepted { conn: usize },
+    Delivered { conn: usize },
+    Forwarded { worker: usize },
+    Dropped,
+    Malformed,
+}
+
+/// The routing state of a server with `workers` workers.
+pub(crate) struct Replay {
+    /// One index per worker, as each worker keeps its own.
+    cids: Vec<CidIndex>,
+    /// Connection keys in accept order; a key's position is its number.
+    conns: Vec<Vec<u8>>,
+    /// Client SCID of each connection whose server SCID is still unknown,
+    /// with the accepting worker and the connection's key.
+    unlearned: HashMap<Vec<u8>, (usize, Vec<u8>)>,
+}
+
+impl Replay {
+    pub(crate) fn new(workers: usize) -> Self {
+        Self {
+            cids: (0..workers.max(1)).map(|_| CidIndex::default()).collect(),
+            conns: Vec::new(),
+            unlearned: HashMap::new(),
+        }
+    }
+
+    fn conn_number(&self, key: &[u8]) -> usize {
+        self.conns
+            .iter()
+            .position(|known| known == key)
+            .expect("indexed key of an accepted connection")
+    }
+
+    /// Hands an inbound packet to `worker`, as the kernel would; learns
+    /// from an outbound one and returns `None`.
+    pub(crate) fn feed(&mut self, worker: usize, packet: &Packet) -> Option<Step> {
+        let Some(hdr) = &packet.header else {
+            return packet.inbound.then_some(Step::Malformed);
+        };
+        if !packet.inbound {
+            self.learn(hdr);
+            return None;
+        }
+        let owner = owning_worker(worker as u8, self.cids.len(), hdr.ty, &hdr.dcid);
+        let cids = &mut self.cids[worker];
+        Some(
+            match classify_inbound(cids, hdr.ty, &hdr.dcid, packet.len, owner) {
+                Inbound::Conn(key) => Step::Delivered {
+                    conn: self.conn_number(&key),
+                },
+                Inbound::Forward(worker) => Step::Forwarded { worker },
+                Inbound::Drop => Step::Dropped,
+                Inbound::Accept => {
+                    // Keyed by the client's DCID until the server's SCID
+                    // shows up; both then lead to the same number.
+                    let key = hdr.dcid.clone();
+                    cids.insert(&key, &key);
+                    self.unlearned
+                        .insert(hdr.scid.clone(), (worker, key.clone()));
+                    self.conns.push(key);
+                    Step::Accepted {
+                        conn: self.conns.len() - 1,
+                    }
+                }
+            },
+        )
+    }
+
+    fn learn(&mut self, hdr: &Header) {
+        if hdr.scid.is_empty() {
+            return;
+        }
+        if let Some((worker, key)) = self.unlearned.remove(&hdr.dcid) {
+            self.cids[worker].insert(&hdr.scid, &key);
+        }
+    }
+
+    /// Replays `packets` in order, `land` picking the worker each inbound
+    /// packet reaches. A forwarded packet is replayed again on the worker
+    /// it was handed to, so its steps are the forward and then its outcome.
+    pub(crate) fn run(
+        &mut self,
+        packets: &[Packet],
+        mut land: impl FnMut(&Packet) -> usize,
+    ) -> Vec<Step> {
+        let mut steps = Vec::new();
+        for packet in packets {
+            let worker = if packet.inbound { land(packet) } else { 0 };
+            let mut step = self.feed(worker, packet);
+            while let Some(next) = step {
+                let forward = match next {
+                    Step::Forwarded { worker } => Some(worker),
+                    _ => None,
+                };
+                steps.push(next);
+                step = forward.and_then(|worker| self.feed(worker, packet));
+            }
+        }
+        steps
+    }
+}
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+/// The UDP datagrams of a classic (not pcapng) capture, in either byte
+/// order and with micro- or nanosecond timestamps. A datagram is inbound
+/// when sent to `server_port` and outbound when sent from it; anything
+/// else, fragments and non-UDP traffic are skipped.
+pub(crate) fn read_pcap(capture: &[u8], server_port: u16) -> Result<Vec<Packet>, String> {
+    let magic = capture
+        .get(..4)
+        .ok_or("capture is shorter than its header")?;
+    let (big_endian, nanos) = match magic {
+        [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+        [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+        [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+        [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+        [0x0a, 0x0d, 0x0d, 0x0a] => return Err("pcapng captures are not supported".into()),
+        _ => return Err("not a pcap capture".into()),
+    };
+    let u32_at = |at: usize| -> Option<u32> {
+        let bytes: [u8; 4] = capture.get(at..at + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+    let link = u32_at(20).ok_or("capture is shorter than its header")?;
+
+    let mut packets = Vec::new();
+    let mut start = None;
+    let mut at = 24;
+    while at < capture.len() {
+        let (Some(secs), Some(frac), Some(len)) = (u32_at(at), u32_at(at + 4), u32_at(at + 8))
+        else {
+            return Err(format!("truncated record header at byte {at}"));
+        };
+        let frame = capture
+            .get(at + 16..at + 16 + len as usize)
+            .ok_or_else(|| format!("truncated record at byte {at}"))?;
+        at += 16 + len as usize;
+
+        let micros = secs as u64 * 1_000_000 + if nanos { frac / 1000 } else { frac } as u64;
+        let start = *start.get_or_insert(micros);
+        let Some((src, dst, payload)) = link_udp(link, frame)? else {
+            continue;
+        };
+        let inbound = match (src, dst) {
+            (_, dst) if dst == server_port => true,
+            (src, _) if src == server_port => false,
+            _ => continue,
+        };
+        packets.push(Packet::from_datagram(
+            micros.saturating_sub(start),
+            inbound,
+            payload,
+        ));
+    }
+    Ok(packets)
+}
+
+/// Source port, destination port and payload of a UDP datagram.
+type Udp<'a> = (u16, u16, &'a [u8]);
+
+/// The UDP datagram in a captured frame, if it holds one.
+fn link_udp(link: u32, frame: &[u8]) -> Result<Option<Udp<'_>>, String> {
+    let ip = match link {
+        // BSD loopback, raw IP, raw IPv4 and raw IPv6: the IP version
+        // nibble tells the family.
+        0 => frame.get(4..),
+        101 | 228 | 229 => Some(frame),
+        // Ethernet, optionally with one 802.1Q tag.
+        1 => match u16_at(frame, 12) {
+            Some(0x8100) => frame.get(18..),
+            _ => frame.get(14..),
+        },
+        // Linux cooked capture v1 and v2.
+        113 => frame.get(16..),
+        276 => frame.get(20..),
+        other => return Err(format!("unsupported link type {other}")),
+    };
+    Ok(ip.and_then(ip_udp))
+}
+
+fn ip_udp(ip: &[u8]) -> Option<Udp<'_>> {
+    let udp = match ip.first()? >> 4 {
+        4 => {
+            let header = (ip[0] & 0x0f) as usize * 4;
+            let fragment = u16_at(ip, 6)? & 0x3fff;
+            if *ip.get(9)? != 17 || fragment != 0 {
+                return None;
+            }
+            let total = (u16_at(ip, 2)? as usize).min(ip.len());
+            ip.get(header..total)?
+        }
+        // Extension headers are not followed.
+        6 if *ip.get(6)? == 17 => ip.get(40..)?,
+        _ => return None,
+    };
+    let len = (u16_at(udp, 4)? as usize).min(udp.len());
+    Some((u16_at(udp, 0)?, u16_at(udp, 2)?, udp.get(8..len)?))
+}
+
+/// The packets of a streamed qlog trace (JSON-SEQ or one JSON object per
+/// line), as a server writes it: received packets are inbound, sent ones
+/// outbound. A packet without a usable header, such as a 1-RTT packet
+/// logged without its DCID, replays as malformed.
+pub(crate) fn read_qlog(trace: &str) -> Result<Vec<Packet>, String> {
+    let mut packets = Vec::new();
+    for record in trace.split(['\u{1e}', '\n']).map(str::trim) {
+        if record.is_empty() {
+            continue;
+        }
+        let event: Value =
+            serde_json::from_str(record).map_err(|err| format!("bad qlog record: {err}"))?;
+        let name = event["name"].as_str().unwrap_or_default();
+        let inbound = match name.split_once(':').map_or(name, |(_, name)| name) {
+            "packet_received" => true,
+            "packet_sent" => false,
+            _ => continue,
+        };
+        let data = &event["data"];
+        let len = data["raw"]["length"]
+            .as_u64()
+            .or_else(|| data["header"]["length"].as_u64())
+            .unwrap_or_default() as usize;
+        packets.push(Packet {
+            at_us: (event["time"].as_f64().unwrap_or_default() * 1000.0) as u64,
+            inbound,
+            header: qlog_header(&data["header"]),
+            len,
+        });
+    }
+    Ok(packets)
+}
+
+fn qlog_header(header: &Value) -> Option<Header> {
+    let ty = match header["packet_type"].as_str()? {
+        "initial" => quiche::Type::Initial,
+        "0RTT" => quiche::Type::ZeroRTT,
+        "handshake" => quiche::Type::Handshake,
+        "retry" => quiche::Type::Retry,
+        "1RTT" => quiche::Type::Short,
+        "version_negotiation" => quiche::Type::VersionNegotiation,
+        _ => return None,
+    };
+    let cid = |field: &str| match header[field].as_str() {
+        Some(text) => hex::decode(text).ok(),
+        None => Some(Vec::new()),
+    };
+    let dcid = cid("dcid")?;
+    if dcid.is_empty() {
+        return None;
+    }
+    Some(Header {
+        ty,
+        dcid,
+        scid: cid("scid")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER_PORT: u16 = 4433;
+
+    /// A padded client Initial from quiche, and the client's SCID.
+    fn client_initial() -> (Vec<u8>, Vec<u8>) {
+        let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+        config.set_application_protos(&[b"cribcall"]).unwrap();
+        let scid = [7u8; quiche::MAX_CONN_ID_LEN];
+        let mut conn = quiche::connect(
+            Some("localhost"),
+            &quiche::ConnectionId::from_ref(&scid),
+            "192.0.2.2:40000".parse().unwrap(),
+            "192.0.2.1:4433".parse().unwrap(),
+            &mut config,
+        )
+        .unwrap();
+        let mut out = [0u8; 1500];
+        let (len, _) = conn.send(&mut out).unwrap();
+        (out[..len].to_vec(), scid.to_vec())
+    }
+
+    /// A server Handshake packet header from `scid` to `dcid`.
+    fn server_handshake(dcid: &[u8], scid: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0xe0, 0, 0, 0, 1, dcid.len() as u8];
+        packet.extend_from_slice(dcid);
+        packet.push(scid.len() as u8);
+        packet.extend_from_slice(scid);
+        packet.resize(packet.len() + 40, 0);
+        packet
+    }
+
+    fn short(dcid: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x40];
+        packet.extend_from_slice(dcid);
+        packet.resize(packet.len() + 40, 0);
+        packet
+    }
+
+    /// A server SCID stamped for `worker`.
+    fn server_cid(worker: u8, fill: u8) -> Vec<u8> {
+        let mut cid = vec![fill; quiche::MAX_CONN_ID_LEN];
+        cid[0] = worker;
+        cid
+    }
+
+    /// `datagrams` as an Ethernet/IPv4 pcap, client on port 40000.
+    fn write_pcap(datagrams: &[(bool, Vec<u8>)]) -> Vec<u8> {
+        let mut pcap = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        pcap.extend_from_slice(&[0; 8]);
+        pcap.extend_from_slice(&65535u32.to_le_bytes());
+        pcap.extend_from_slice(&1u32.to_le_bytes());
+        for (i, (inbound, payload)) in datagrams.iter().enumerate() {
+            let (src, dst) = if *inbound {
+                (40000u16, SERVER_PORT)
+            } else {
+                (SERVER_PORT, 40000)
+            };
+            let mut frame = vec![0u8; 12];
+            frame.extend_from_slice(&0x0800u16.to_be_bytes());
+            let total = 20 + 8 + payload.len();
+            frame.extend_from_slice(&[0x45, 0]);
+            frame.extend_from_slice(&(total as u16).to_be_bytes());
+            frame.extend_from_slice(&[0, 0, 0x40, 0, 64, 17, 0, 0]);
+            frame.extend_from_slice(&[192, 0, 2, 2, 192, 0, 2, 1]);
+            frame.extend_from_slice(&src.to_be_bytes());
+            frame.extend_from_slice(&dst.to_be_bytes());
+            frame.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+            frame.extend_from_slice(&[0, 0]);
+            frame.extend_from_slice(payload);
+
+            pcap.extend_from_slice(&1_700_000_000u32.to_le_bytes());
+            pcap.extend_from_slice(&(i as u32 * 1000).to_le_bytes());
+            pcap.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            pcap.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            pcap.extend_from_slice(&frame);
+        }
+        pcap
+    }
+
+    #[test]
+    fn capture_replays_to_the_recorded_routing() {
+        let (initial, client_scid) = client_initial();
+        let odcid = Packet::from_datagram(0, true, &initial)
+            .header
+            .unwrap()
+            .dcid;
+        let server_scid = server_cid(0, 9);
+        let mut unpadded = initial.clone();
+        unpadded.truncate(600);
+        unpadded[6] ^= 0xff;
+        let pcap = write_pcap(&[
+            (true, initial.clone()),
+            (false, server_handshake(&client_scid, &server_scid)),
+            // A retransmitted Initial still carries the client's DCID.
+            (true, initial),
+            (true, short(&server_scid)),
+            (true, short(&server_cid(0, 3))),
+            (true, unpadded),
+            (true, vec![0x80, 0, 0]),
+        ]);
+
+        let packets = read_pcap(&pcap, SERVER_PORT).unwrap();
+        assert_eq!(packets.len(), 7);
+        assert_eq!(packets[3].at_us, 3000);
+        assert_eq!(packets[0].header.as_ref().unwrap().dcid, odcid);
+        assert_eq!(
+            Replay::new(1).run(&packets, |_| 0),
+            [
+                Step::Accepted { conn: 0 },
+                Step::Delivered { conn: 0 },
+                Step::Delivered { conn: 0 },
+                Step::Dropped,
+                Step::Dropped,
+                Step::Malformed,
+            ]
+        );
+        assert!(read_pcap(&[0x0a, 0x0d, 0x0d, 0x0a], SERVER_PORT).is_err());
+    }
+
+    #[test]
+    fn packets_landing_on_a_sibling_are_forwarded_to_the_owner() {
+        let (initial, client_scid) = client_initial();
+        let first = server_cid(0, 1);
+        let second = server_cid(1, 2);
+        let mut packets: Vec<Packet> = [
+            (true, initial.clone()),
+            (false, server_handshake(&client_scid, &first)),
+            (true, short(&first)),
+            (true, initial),
+            (false, server_handshake(&client_scid, &second)),
+            (true, short(&second)),
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, (inbound, data))| Packet::from_datagram(i as u64, *inbound, data))
+        .collect();
+
+        // The second Initial would reuse the first's ids and reach the first
+        // connection; re-key it so it opens its own.
+        let header = packets[3].header.as_mut().unwrap();
+        header.dcid[0] ^= 0xff;
+        header.scid[0] ^= 0xff;
+        packets[4].header.as_mut().unwrap().dcid[0] ^= 0xff;
+
+        let mut lands = [0, 1, 1, 0].into_iter();
+        assert_eq!(
+            Replay::new(2).run(&packets, |_| lands.next().unwrap()),
+            [
+                Step::Accepted { conn: 0 },
+                Step::Forwarded { worker: 0 },
+                Step::Delivered { conn: 0 },
+                Step::Accepted { conn: 1 },
+                Step::Forwarded { worker: 1 },
+                Step::Delivered { conn: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn qlog_streams_replay_like_captures() {
+        let (initial, _) = client_initial();
+        let odcid = hex::encode(
+            Packet::from_datagram(0, true, &initial)
+                .header
+                .unwrap()
+                .dcid,
+        );
+        let scid = hex::encode(server_cid(0, 5));
+        let records = [
+            r#"{"qlog_version":"0.3","qlog_format":"JSON-SEQ","trace":{}}"#.to_string(),
+            format!(
+                r#"{{"time":0.5,"name":"transport:packet_received","data":{{"header":{{"packet_type":"initial","dcid":"{odcid}","scid":"0707"}},"raw":{{"length":1200}}}}}}"#
+            ),
+            format!(
+                r#"{{"time":1.0,"name":"transport:packet_sent","data":{{"header":{{"packet_type":"handshake","dcid":"0707","scid":"{scid}"}},"raw":{{"length":1200}}}}}}"#
+            ),
+            format!(
+                r#"{{"time":2.0,"name":"quic:packet_received","data":{{"header":{{"packet_type":"1RTT","dcid":"{scid}"}},"raw":{{"length":60}}}}}}"#
+            ),
+            r#"{"time":3.0,"name":"quic:packet_received","data":{"header":{"packet_type":"1RTT"}}}"#
+                .to_string(),
+            r#"{"time":4.0,"name":"recovery:metrics_updated","data":{}}"#.to_string(),
+        ];
+        let expected = [
+            Step::Accepted { conn: 0 },
+            Step::Delivered { conn: 0 },
+            Step::Malformed,
+        ];
+
+        let seq: String = records.iter().map(|r| format!("\u{1e}{r}\n")).collect();
+        let packets = read_qlog(&seq).unwrap();
+        assert_eq!(packets[0].at_us, 500);
+        assert_eq!(Replay::new(1).run(&packets, |_| 0), expected);
+
+        let lines = records.join("\n");
+        assert_eq!(
+            Replay::new(1).run(&read_qlog(&lines).unwrap(), |_| 0),
+            expected
+        );
+        assert!(read_qlog("{not json").is_err());
+    }
+}
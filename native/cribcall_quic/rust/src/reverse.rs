@@ -0,0 +1,155 @@
+//! Reverse connect (`cc_quic_config_set_reverse_connect`), for an always-on
+//! unit nothing can dial: it keeps an ordinary client connection to the
+//! parent's listener or a relay as its signaling connection. When that
+//! server sends a connect request (`cc_quic_server_request_connect`), the
+//! unit dials a new connection back to the endpoint it names. Every packet
+//! the unit sends is outbound, so its router needs no port forwarding.
+//!
+//! The dial-back is a client handle of its own, started from the signaling
+//! connection's settings, server name and identity, and posting to the same
+//! event port. It pins the signaling server's certificate unless the
+//! request names one of the configured pins, so a relay can send the unit
+//! on to the parent but never to a certificate the unit was not given.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::{dial_host, CcQuicConfig, ClientTarget, ConfigRole, QuicSettings, WorkerOptions};
+
+/// A client config's reverse connect mode.
+#[derive(Clone, Default)]
+pub(crate) struct ReverseConnect {
+    /// Lowercase hex fingerprints a request may name besides the signaling
+    /// server's own.
+    pub pins: Vec<String>,
+    /// Filled in when the signaling connection starts.
+    pub dial: Option<Arc<DialBack>>,
+}
+
+// Keeps the identity paths out of logged options.
+impl fmt::Debug for ReverseConnect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReverseConnect")
+            .field("pins", &self.pins)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What a signaling connection dials back with.
+pub(crate) struct DialBack {
+    quic: QuicSettings,
+    options: WorkerOptions,
+    server_name: String,
+    /// The signaling server's pin; empty when it was unpinned.
+    expected_fp: String,
+    allow_unpinned: bool,
+    cert_path: String,
+    key_path: String,
+}
+
+impl DialBack {
+    pub(crate) fn new(template: &CcQuicConfig, target: &ClientTarget) -> Self {
+        let mut options = template.options.clone();
+        // A dial-back takes no requests of its own, and must not coalesce
+        // onto the signaling connection it shares a pin with.
+        options.reverse_connect = None;
+        options.coalesce = false;
+        Self {
+            quic: template.quic.clone(),
+            options,
+            server_name: target.server_name.clone(),
+            expected_fp: target.expected_fp.clone(),
+            allow_unpinned: target.allow_unpinned,
+            cert_path: target.cert_path.clone(),
+            key_path: target.key_path.clone(),
+        }
+    }
+}
+
+impl ReverseConnect {
+    /// The pin a request resolves to: the signaling server's without one,
+    /// else one of `pins`.
+    fn pin(&self, dial: &DialBack, pin: Option<[u8; 32]>) -> Result<String, String> {
+        let Some(pin) = pin else {
+            return Ok(dial.expected_fp.clone());
+        };
+        let pin = crate::hex_string(&pin);
+        if pin == dial.expected_fp || self.pins.contains(&pin) {
+            Ok(pin)
+        } else {
+            Err(format!(
+                "pin {} is not one of the reverse connect pins",
+                crate::short_hex(&pin)
+            ))
+        }
+    }
+
+    /// Dials the connection a request asks for, to `addr` or else to
+    /// `reached`, the server the request came from. Gives the endpoint and
+    /// the new handle, which posts its events to `dart_port`.
+    pub(crate) fn dial(
+        &self,
+        reached: SocketAddr,
+        addr: Option<SocketAddr>,
+        pin: Option<[u8; 32]>,
+        dart_port: i64,
+    ) -> (SocketAddr, Result<u64, String>) {
+        let target = addr.unwrap_or(reached);
+        let Some(dial) = &self.dial else {
+            return (target, Err("reverse connect is not set up".into()));
+        };
+        let expected_fp = match self.pin(dial, pin) {
+            Ok(pin) => pin,
+            Err(message) => return (target, Err(message)),
+        };
+        let mut template = CcQuicConfig {
+            quic: dial.quic.clone(),
+            options: dial.options.clone(),
+            role: ConfigRole::Client,
+            ..CcQuicConfig::default()
+        };
+        let client = ClientTarget {
+            host: dial_host(target),
+            port: target.port(),
+            server_name: dial.server_name.clone(),
+            allow_unpinned: dial.allow_unpinned && expected_fp.is_empty(),
+            expected_fp,
+            cert_path: dial.cert_path.clone(),
+            key_path: dial.key_path.clone(),
+        };
+        let dialed = crate::connect_client(&mut template, client, dart_port, None);
+        (target, dialed.map_err(|err| err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_only_name_known_pins() {
+        let dial = DialBack {
+            quic: QuicSettings::default(),
+            options: WorkerOptions::default(),
+            server_name: "relay".into(),
+            expected_fp: "11".repeat(32),
+            allow_unpinned: false,
+            cert_path: String::new(),
+            key_path: String::new(),
+        };
+        let reverse = ReverseConnect {
+            pins: vec!["22".repeat(32)],
+            dial: None,
+        };
+        assert_eq!(reverse.pin(&dial, None), Ok("11".repeat(32)));
+        assert_eq!(reverse.pin(&dial, Some([0x11; 32])), Ok("11".repeat(32)));
+        assert_eq!(reverse.pin(&dial, Some([0x22; 32])), Ok("22".repeat(32)));
+        assert!(reverse.pin(&dial, Some([0x33; 32])).is_err());
+
+        let reached = "198.51.100.4:4433".parse().unwrap();
+        let (target, dialed) = reverse.dial(reached, None, None, 0);
+        assert_eq!(target, reached);
+        assert!(dialed.is_err());
+    }
+}
@@ -0,0 +1,284 @@
+//! Presence (`cc_quic_config_set_roster`): a server keeps the roster of
+//! peer fingerprints connected to it, shared by its workers, with labels
+//! the app assigns through `cc_quic_server_set_peer_label` ("Mum's phone").
+//! Every change is posted as `roster_changed` on the server and sent to
+//! each connected client, which posts it too, so every parent app can show
+//! who else is listening.
+//!
+//! Snapshots travel on a dedicated unidirectional stream (server id 15):
+//! `length u32 BE | count u16 BE | entries`, each entry
+//! `flags u8 (1 = the recipient) | connections u16 BE | fingerprint 32 bytes |
+//! label length u8 | label`. A client keeps only the newest snapshot.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+const SERVER_STREAM: u64 = 15;
+const YOU: u8 = 1;
+const FINGERPRINT_LEN: usize = 32;
+/// Longest label the one-byte length prefix can carry.
+pub(crate) const MAX_LABEL_LEN: usize = 255;
+
+pub(crate) fn is_roster_stream(stream_id: u64) -> bool {
+    stream_id == SERVER_STREAM
+}
+
+/// One fingerprint on the roster, as events carry it.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub(crate) struct RosterPeer {
+    pub fingerprint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Connections open with this fingerprint.
+    pub connections: u16,
+    /// Set on the client's own entry.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub you: bool,
+}
+
+#[derive(Default)]
+struct Members {
+    /// Connection count per connected fingerprint (lowercase hex).
+    present: BTreeMap<String, u16>,
+    labels: HashMap<String, String>,
+}
+
+/// One server's roster, shared by its workers and the FFI.
+pub(crate) struct Roster {
+    members: Mutex<Members>,
+    /// Bumped on every change, so each worker can tell its clients are
+    /// behind without a lock.
+    version: AtomicU64,
+}
+
+impl Default for Roster {
+    fn default() -> Self {
+        Self {
+            members: Mutex::default(),
+            version: AtomicU64::new(1),
+        }
+    }
+}
+
+impl Roster {
+    fn members(&self) -> std::sync::MutexGuard<'_, Members> {
+        self.members.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn bump(&self) {
+        self.version.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn version(&self) -> u64 {
+        self.version.load(Ordering::Relaxed)
+    }
+
+    /// Counts a newly accepted connection from `fingerprint`.
+    pub(crate) fn join(&self, fingerprint: &str) {
+        *self
+            .members()
+            .present
+            .entry(fingerprint.to_string())
+            .or_default() += 1;
+        self.bump();
+    }
+
+    /// Drops one connection from `fingerprint`.
+    pub(crate) fn leave(&self, fingerprint: &str) {
+        let mut members = self.members();
+        if let Some(count) = members.present.get_mut(fingerprint) {
+            *count -= 1;
+            if *count == 0 {
+                members.present.remove(fingerprint);
+            }
+        }
+        drop(members);
+        self.bump();
+    }
+
+    /// Sets or (with `None`) clears the label of `fingerprint`, connected
+    /// or not.
+    pub(crate) fn label(&self, fingerprint: &str, label: Option<String>) {
+        let mut members = self.members();
+        let changed = match label {
+            Some(label) => {
+                members
+                    .labels
+                    .insert(fingerprint.to_string(), label.clone())
+                    != Some(label)
+            }
+            None => members.labels.remove(fingerprint).is_some(),
+        };
+        let connected = members.present.contains_key(fingerprint);
+        drop(members);
+        if changed && connected {
+            self.bump();
+        }
+    }
+
+    /// The connected fingerprints, `recipient`'s entry flagged.
+    pub(crate) fn snapshot(&self, recipient: Option<&str>) -> Vec<RosterPeer> {
+        let members = self.members();
+        members
+            .present
+            .iter()
+            .map(|(fingerprint, &connections)| RosterPeer {
+                fingerprint: fingerprint.clone(),
+                label: members.labels.get(fingerprint).cloned(),
+                connections,
+                you: recipient == Some(fingerprint.as_str()),
+            })
+            .collect()
+    }
+}
+
+fn encode(peers: &[RosterPeer]) -> Vec<u8> {
+    let mut body = (peers.len() as u16).to_be_bytes().to_vec();
+    for peer in peers {
+        body.push(if peer.you { YOU } else { 0 });
+        body.extend_from_slice(&peer.connections.to_be_bytes());
+        let mut fingerprint = [0u8; FINGERPRINT_LEN];
+        if let Ok(raw) = hex::decode(&peer.fingerprint) {
+            if raw.len() == FINGERPRINT_LEN {
+                fingerprint.copy_from_slice(&raw);
+            }
+        }
+        body.extend_from_slice(&fingerprint);
+        let label = peer.label.as_deref().unwrap_or("");
+        body.push(label.len() as u8);
+        body.extend_from_slice(label.as_bytes());
+    }
+    let mut out = (body.len() as u32).to_be_bytes().to_vec();
+    out.extend_from_slice(&body);
+    out
+}
+
+fn decode(mut body: &[u8]) -> Option<Vec<RosterPeer>> {
+    let take = |body: &mut &[u8], len: usize| -> Option<Vec<u8>> {
+        let (head, rest) = (body.get(..len)?, body.get(len..)?);
+        *body = rest;
+        Some(head.to_vec())
+    };
+    let count = u16::from_be_bytes(take(&mut body, 2)?.try_into().ok()?);
+    let mut peers = Vec::with_capacity(usize::from(count));
+    for _ in 0..count {
+        let flags = take(&mut body, 1)?[0];
+        let connections = u16::from_be_bytes(take(&mut body, 2)?.try_into().ok()?);
+        let fingerprint = hex::encode(take(&mut body, FINGERPRINT_LEN)?);
+        let label_len = usize::from(take(&mut body, 1)?[0]);
+        let label = String::from_utf8_lossy(&take(&mut body, label_len)?).into_owned();
+        peers.push(RosterPeer {
+            fingerprint,
+            label: (!label.is_empty()).then_some(label),
+            connections,
+            you: flags & YOU != 0,
+        });
+    }
+    Some(peers)
+}
+
+/// Server side: keeps one client up to date with the roster. A snapshot
+/// being written is finished before a newer one is queued, so a short
+/// write never splits the framing.
+#[derive(Default)]
+pub(crate) struct RosterFeed {
+    /// Fingerprint this connection was counted under.
+    pub joined: Option<String>,
+    sent_version: u64,
+    pending: Vec<u8>,
+}
+
+impl RosterFeed {
+    /// Queues the newest snapshot if the client is behind and writes what
+    /// the stream takes.
+    pub(crate) fn update(&mut self, conn: &mut quiche::Connection, roster: &Roster) {
+        let version = roster.version();
+        if self.pending.is_empty() && self.sent_version != version {
+            self.sent_version = version;
+            self.pending = encode(&roster.snapshot(self.joined.as_deref()));
+        }
+        if self.pending.is_empty() {
+            return;
+        }
+        match conn.stream_send(SERVER_STREAM, &self.pending, false) {
+            Ok(written) => {
+                self.pending.drain(..written);
+            }
+            Err(quiche::Error::Done) => {}
+            Err(err) => log::debug!("roster send error: {err:?}"),
+        }
+    }
+}
+
+/// Client side: reassembles the server's snapshots across stream reads.
+#[derive(Default)]
+pub(crate) struct RosterInbox {
+    partial: Vec<u8>,
+    broken: bool,
+}
+
+impl RosterInbox {
+    /// Feeds bytes read from the roster stream; returns the newest complete
+    /// snapshot among them.
+    pub(crate) fn on_data(&mut self, data: &[u8]) -> Option<Vec<RosterPeer>> {
+        if self.broken {
+            return None;
+        }
+        self.partial.extend_from_slice(data);
+        let mut newest = None;
+        let mut used = 0;
+        while let Some(header) = self.partial.get(used..used + 4) {
+            let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+            let Some(body) = self.partial.get(used + 4..used + 4 + len) else {
+                break;
+            };
+            match decode(body) {
+                Some(peers) => newest = Some(peers),
+                None => {
+                    self.broken = true;
+                    self.partial.clear();
+                    return newest;
+                }
+            }
+            used += 4 + len;
+        }
+        self.partial.drain(..used);
+        newest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_connections_and_round_trips_snapshots() {
+        let (a, b) = ("aa".repeat(32), "bb".repeat(32));
+        let roster = Roster::default();
+        let start = roster.version();
+        roster.label(&a, Some("Mum's phone".into()));
+        assert_eq!(roster.version(), start);
+        roster.join(&a);
+        roster.join(&b);
+        roster.join(&b);
+        roster.label(&b, Some("Dad".into()));
+        roster.label(&b, Some("Dad".into()));
+        assert_eq!(roster.version(), start + 4);
+
+        let peers = roster.snapshot(Some(&b));
+        assert_eq!(peers[0].label.as_deref(), Some("Mum's phone"));
+        assert_eq!((peers[1].connections, peers[1].you), (2, true));
+
+        let wire = [encode(&[]), encode(&peers)].concat();
+        let mut inbox = RosterInbox::default();
+        assert_eq!(inbox.on_data(&wire[..10]), Some(Vec::new()));
+        assert_eq!(inbox.on_data(&wire[10..]), Some(peers));
+
+        roster.leave(&b);
+        roster.leave(&a);
+        let peers = roster.snapshot(None);
+        assert_eq!((peers.len(), peers[0].connections), (1, 1));
+    }
+}
@@ -0,0 +1,289 @@
+//! Shared client runtime: a single poller thread drives every client
+//! connection created with `cc_quic_config_set_shared_runtime`. Connections
+//! share one UDP socket per distinct socket option set and address family,
+//! and incoming packets are routed by their DCID, which is the SCID we
+//! picked for the connection.
+
+use super::{panic_message, remove_handle, report_worker_death, ClientConnection, WorkerContext};
+use crate::buffers::Scratch;
+use crate::socket::{self, QuicSocket, RecvBatch, SendBatch, SocketOptions};
+use crate::threads::{spawn_worker, WorkerThreads};
+use crate::watchdog::{self, Heartbeat};
+use log::{info, warn};
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+/// Everything the runtime needs to start one client connection.
+pub(crate) struct ClientSpec {
+    pub ctx: WorkerContext,
+    pub config: quiche::Config,
+    pub socket_options: SocketOptions,
+    pub peer: SocketAddr,
+    pub server_name: String,
+    pub expected_fp: String,
+}
+
+type Registration = (ClientSpec, mpsc::Sender<io::Result<()>>);
+
+/// Registration channel plus the poller's OS thread id.
+static RUNTIME: OnceCell<(mpsc::Sender<Registration>, i32)> = OnceCell::new();
+
+/// Hands a connection to the shared poller, starting it on first use. Returns
+/// the poller's thread id once the connection has a socket, so bind errors
+/// surface to the caller.
+pub(crate) fn register(spec: ClientSpec) -> io::Result<i32> {
+    let (runtime, tid) = RUNTIME.get_or_try_init(|| {
+        let (tx, rx) = mpsc::channel();
+        let threads = WorkerThreads::default();
+        spawn_worker("cc-quic-runtime".to_string(), &threads, move || run(rx))?;
+        let tid = threads
+            .lock()
+            .ok()
+            .and_then(|tids| tids.first().copied())
+            .unwrap_or_default();
+        Ok::<_, io::Error>((tx, tid))
+    })?;
+    let stopped = || io::Error::other("client runtime stopped");
+    let (bound_tx, bound_rx) = mpsc::channel();
+    runtime.send((spec, bound_tx)).map_err(|_| stopped())?;
+    bound_rx.recv().map_err(|_| stopped())??;
+    Ok(*tid)
+}
+
+struct Endpoint {
+    options: SocketOptions,
+    v6: bool,
+    socket: QuicSocket,
+    local_addr: SocketAddr,
+    rx_batch: RecvBatch,
+    tx_batch: SendBatch,
+}
+
+impl Endpoint {
+    /// Binds the unspecified address of the family `v6` picks; each family
+    /// gets its own socket, so IPv6 servers work next to IPv4 ones.
+    fn bind(options: SocketOptions, v6: bool) -> io::Result<Self> {
+        let socket = QuicSocket::bind(if v6 { "[::]:0" } else { "0.0.0.0:0" }, options)?;
+        let local_addr = socket.local_addr()?;
+        info!("client runtime bound {local_addr} ({options:?})");
+        Ok(Self {
+            options,
+            v6,
+            rx_batch: socket.new_recv_batch(),
+            tx_batch: socket.new_send_batch(),
+            socket,
+            local_addr,
+        })
+    }
+}
+
+struct Runtime {
+    /// Sockets are kept for the life of the runtime; there is at most one
+    /// per option combination.
+    endpoints: Vec<Endpoint>,
+    /// Keyed by our SCID, with the index of the endpoint the connection uses.
+    clients: HashMap<Vec<u8>, (usize, ClientConnection)>,
+    scratch: Scratch,
+    heartbeat: Arc<Heartbeat>,
+}
+
+impl Runtime {
+    fn admit(&mut self, (spec, bound): Registration) {
+        let ClientSpec {
+            ctx,
+            mut config,
+            socket_options,
+            peer,
+            server_name,
+            expected_fp,
+        } = spec;
+        let v6 = peer.is_ipv6();
+        let index = match self
+            .endpoints
+            .iter()
+            .position(|e| e.options == socket_options && e.v6 == v6)
+        {
+            Some(index) => index,
+            None => match Endpoint::bind(socket_options, v6) {
+                Ok(endpoint) => {
+                    self.endpoints.push(endpoint);
+                    self.endpoints.len() - 1
+                }
+                Err(err) => {
+                    let _ = bound.send(Err(err));
+                    return;
+                }
+            },
+        };
+        let _ = bound.send(Ok(()));
+
+        let handle_id = ctx.handle_id;
+        watchdog::watch(
+            &self.heartbeat,
+            handle_id,
+            ctx.dart_port,
+            ctx.options.watchdog_ms,
+        );
+        let local_addr = self.endpoints[index].local_addr;
+        match ClientConnection::connect(
+            ctx,
+            &mut config,
+            local_addr,
+            peer,
+            &server_name,
+            expected_fp,
+        ) {
+            Some(client) => {
+                self.clients.insert(client.scid.clone(), (index, client));
+            }
+            None => remove_handle(handle_id),
+        }
+    }
+
+    fn retire(&mut self, finished: Vec<Vec<u8>>) {
+        for scid in finished {
+            if let Some((_, client)) = self.clients.remove(&scid) {
+                remove_handle(client.handle_id);
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        let mut finished = Vec::new();
+        for (scid, (index, client)) in self.clients.iter_mut() {
+            client.apply_commands();
+            let endpoint = &mut self.endpoints[*index];
+            if !client.flush(&endpoint.socket, &mut endpoint.tx_batch) {
+                finished.push(scid.clone());
+            }
+        }
+        for endpoint in self.endpoints.iter_mut() {
+            if let Err(err) = endpoint.socket.send_batch(&mut endpoint.tx_batch) {
+                warn!("client runtime udp send error: {err}");
+            }
+        }
+        self.retire(finished);
+    }
+
+    fn receive(&mut self) {
+        for endpoint in self.endpoints.iter_mut() {
+            loop {
+                match endpoint.socket.recv_batch(&mut endpoint.rx_batch) {
+                    Ok(count) => {
+                        for i in 0..count {
+                            let (data, meta) = endpoint.rx_batch.get_mut(i);
+                            let dcid =
+                                match quiche::Header::from_slice(data, quiche::MAX_CONN_ID_LEN) {
+                                    Ok(hdr) => hdr.dcid.to_vec(),
+                                    Err(err) => {
+                                        warn!("client runtime header parse error: {err:?}");
+                                        continue;
+                                    }
+                                };
+                            if let Some((_, client)) = self.clients.get_mut(&dcid) {
+                                client.recv(data, meta, endpoint.local_addr);
+                            }
+                        }
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) => {
+                        warn!("client runtime udp recv error: {err}");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn poll(&mut self) {
+        let scratch = &mut self.scratch;
+        let finished = self
+            .clients
+            .iter_mut()
+            .filter_map(|(scid, (_, client))| (!client.poll(scratch)).then(|| scid.clone()))
+            .collect();
+        self.retire(finished);
+    }
+
+    /// Waits for a datagram on any endpoint, the earliest quiche timer or a
+    /// paced packet, capped like the server worker so queued commands are
+    /// not held up, and fires the timers that expired.
+    fn wait(&mut self) {
+        let now = Instant::now();
+        let next = self
+            .clients
+            .values()
+            .flat_map(|(_, client)| [client.conn.timeout(), client.pacer.next_release(now)])
+            .flatten()
+            .min()
+            .unwrap_or(Duration::from_millis(2));
+        let wait = next.min(Duration::from_millis(5));
+        if !wait.is_zero() {
+            socket::wait_readable(self.endpoints.iter().map(|e| &e.socket), wait);
+        }
+        for (_, client) in self.clients.values_mut() {
+            if client.conn.timeout().is_some_and(|t| t.is_zero()) {
+                client.on_timeout();
+            }
+        }
+    }
+}
+
+fn run(rx: mpsc::Receiver<Registration>) {
+    let mut runtime = Runtime {
+        endpoints: Vec::new(),
+        clients: HashMap::new(),
+        scratch: Scratch::new(),
+        heartbeat: Heartbeat::new(),
+    };
+    loop {
+        runtime.heartbeat.beat();
+        if runtime.clients.is_empty() {
+            // Nothing to drive; park until the next connect.
+            runtime.heartbeat.park();
+            let registration = rx.recv();
+            runtime.heartbeat.beat();
+            match registration {
+                Ok(registration) => runtime.admit(registration),
+                Err(_) => return,
+            }
+        }
+        while let Ok(registration) = rx.try_recv() {
+            runtime.admit(registration);
+        }
+
+        let pass = panic::catch_unwind(AssertUnwindSafe(|| {
+            runtime.flush();
+            runtime.receive();
+            runtime.poll();
+            runtime.wait();
+        }));
+        if let Err(payload) = pass {
+            // Connection state is suspect after a panic; drop every client but
+            // keep the poller alive for future connects.
+            let message = panic_message(payload.as_ref());
+            for (_, client) in runtime.clients.drain().map(|(_, entry)| entry) {
+                report_worker_death(client.handle_id, client.dart_port, &message);
+                remove_handle(client.handle_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoints_bind_per_address_family() {
+        let v4 = Endpoint::bind(SocketOptions::default(), false).unwrap();
+        let v6 = Endpoint::bind(SocketOptions::default(), true).unwrap();
+        assert!(v4.local_addr.is_ipv4());
+        assert!(v6.local_addr.is_ipv6());
+    }
+}
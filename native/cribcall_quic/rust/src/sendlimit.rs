@@ -0,0 +1,161 @@
+//! Send-side hints for adaptive bitrate. A connection's send rate is held
+//! down either by the path (congestion window, pacing, peer credit) or by
+//! the app having little to send; only the first says anything about the
+//! path. After every send pass the worker notes whether data was still
+//! waiting to go out, and [`SendLimit`] turns the share of time spent that
+//! way over a stats interval into `app_limited` for the `stats` event.
+//!
+//! quiche keeps a live connection's window through idle periods (there is
+//! no slow-start restart), but a client that reconnects after an idle close
+//! starts again from the initial window. With window resume on, the client
+//! remembers the last window per server address and opens the next
+//! connection to it with that window, if it comes within the allowed age.
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Below this share of blocked time a connection counts as app-limited.
+const APP_LIMITED_BELOW_PCT: u8 = 10;
+/// quiche's own initial window, in packets.
+const INITIAL_WINDOW_PACKETS: usize = 10;
+/// Largest resumed window, so a stale estimate cannot burst a whole BDP
+/// onto a path that has since changed.
+const MAX_RESUME_PACKETS: usize = 64;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct SendLimitStats {
+    /// Nothing (or nearly nothing) waited on the transport this interval,
+    /// so a low send rate reflects the app, not the path.
+    pub app_limited: bool,
+    /// Share of the interval with data waiting to be sent, in percent.
+    pub blocked_pct: u8,
+}
+
+pub(crate) struct SendLimit {
+    since: Instant,
+    last: Instant,
+    /// Whether data was waiting at the last sample.
+    blocked: bool,
+    blocked_time: Duration,
+}
+
+impl SendLimit {
+    pub(crate) fn new(now: Instant) -> Self {
+        Self {
+            since: now,
+            last: now,
+            blocked: false,
+            blocked_time: Duration::ZERO,
+        }
+    }
+
+    /// Records the end of a send pass; `blocked` when data is still waiting.
+    pub(crate) fn sample(&mut self, now: Instant, blocked: bool) {
+        if self.blocked {
+            self.blocked_time += now.saturating_duration_since(self.last);
+        }
+        self.last = now;
+        self.blocked = blocked;
+    }
+
+    /// The interval since the last call, which starts a new one.
+    pub(crate) fn stats(&mut self, now: Instant) -> SendLimitStats {
+        self.sample(now, self.blocked);
+        let total = now.saturating_duration_since(self.since);
+        let blocked_pct = if total.is_zero() {
+            0
+        } else {
+            (self.blocked_time.as_secs_f64() / total.as_secs_f64() * 100.0).round() as u8
+        };
+        self.since = now;
+        self.blocked_time = Duration::ZERO;
+        SendLimitStats {
+            app_limited: blocked_pct < APP_LIMITED_BELOW_PCT,
+            blocked_pct,
+        }
+    }
+}
+
+/// Whether a pass left data waiting on `conn`: control bytes without stream
+/// credit, or datagrams quiche could not fit into its window.
+pub(crate) fn blocked(conn: &quiche::Connection, control_backlogged: bool) -> bool {
+    control_backlogged || conn.dgram_send_queue_len() > 0
+}
+
+/// Last window per server address, with when it was seen.
+static WINDOWS: Lazy<Mutex<HashMap<SocketAddr, (usize, Instant)>>> = Lazy::new(Default::default);
+
+/// Remembers `conn`'s current window toward its server.
+pub(crate) fn remember(conn: &quiche::Connection) {
+    let Some(path) = conn.path_stats().find(|path| path.active) else {
+        return;
+    };
+    WINDOWS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(path.peer_addr, (path.cwnd, Instant::now()));
+}
+
+/// Initial window in packets for a new connection to `peer`, if one was
+/// remembered within `max_age` and is larger than quiche's default.
+pub(crate) fn resume_window(peer: SocketAddr, max_age: Duration, mtu: usize) -> Option<usize> {
+    let mut windows = WINDOWS.lock().unwrap_or_else(PoisonError::into_inner);
+    windows.retain(|_, (_, at)| at.elapsed() <= max_age);
+    let (cwnd, _) = windows.get(&peer)?;
+    let packets = (cwnd / mtu.max(1)).min(MAX_RESUME_PACKETS);
+    (packets > INITIAL_WINDOW_PACKETS).then_some(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocked_share_decides_app_limited() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut limit = SendLimit::new(start);
+        limit.sample(at(100), true);
+        limit.sample(at(400), false);
+        limit.sample(at(900), true);
+        assert_eq!(
+            limit.stats(at(1000)),
+            SendLimitStats {
+                app_limited: false,
+                blocked_pct: 40,
+            }
+        );
+        // Blocked from the last pass until it clears.
+        limit.sample(at(1020), false);
+        limit.sample(at(1500), false);
+        assert_eq!(
+            limit.stats(at(2000)),
+            SendLimitStats {
+                app_limited: true,
+                blocked_pct: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn resumed_windows_are_capped_and_expire() {
+        let peer: SocketAddr = "192.0.2.7:4433".parse().unwrap();
+        let insert = |cwnd, age_ms| {
+            let at = Instant::now() - Duration::from_millis(age_ms);
+            WINDOWS.lock().unwrap().insert(peer, (cwnd, at));
+        };
+        let max_age = Duration::from_secs(60);
+        insert(30 * 1350, 0);
+        assert_eq!(resume_window(peer, max_age, 1350), Some(30));
+        insert(1_000_000, 0);
+        assert_eq!(resume_window(peer, max_age, 1350), Some(MAX_RESUME_PACKETS));
+        insert(5 * 1350, 0);
+        assert_eq!(resume_window(peer, max_age, 1350), None);
+        insert(30 * 1350, 61_000);
+        assert_eq!(resume_window(peer, max_age, 1350), None);
+    }
+}
@@ -0,0 +1,500 @@
+//! Typed setters behind the `cc_quic_config_set_*` exports: each export
+//! checks its pointers and calls the method of the same name here, and
+//! `api::Config` reaches them directly. Arguments keep the C meaning (0
+//! picks the default or turns a setting off) except that strings and byte
+//! buffers are borrowed and NULL is `None`. Role mismatches are
+//! `Error::WrongRole`, out-of-range values `Error::Config`.
+
+use super::*;
+
+impl CcQuicConfig {
+    fn check_role(&self, role: ConfigRole, setting: &str) -> Result<(), Error> {
+        if self.role.allows(role) {
+            return Ok(());
+        }
+        let side = match role {
+            ConfigRole::Server => "server",
+            _ => "client",
+        };
+        Err(Error::WrongRole(format!("{setting} is for {side} configs")))
+    }
+
+    /// As `cc_quic_config_set_ecn`.
+    pub fn set_ecn(&mut self, enabled: bool) -> &mut Self {
+        self.options.ecn = enabled;
+        self
+    }
+
+    /// As `cc_quic_config_set_peer_cert_export`.
+    pub fn set_peer_cert_export(&mut self, enabled: bool) -> &mut Self {
+        self.options.peer_cert = enabled;
+        self
+    }
+
+    /// As `cc_quic_config_set_keying_material_export`.
+    pub fn set_keying_material_export(&mut self, enabled: bool) -> &mut Self {
+        self.options.keying_material_export = enabled;
+        self
+    }
+
+    /// As `cc_quic_config_set_writable_events`.
+    pub fn set_writable_events(&mut self, enabled: bool) -> &mut Self {
+        self.options.writable_events = enabled;
+        self
+    }
+
+    /// As `cc_quic_config_set_observed_address`.
+    pub fn set_observed_address(&mut self, enabled: bool) -> Result<&mut Self, Error> {
+        self.check_role(ConfigRole::Server, "observed address reports")?;
+        self.options.observed_addr = enabled;
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_capabilities`.
+    pub fn set_capabilities(&mut self, features: u32) -> Result<&mut Self, Error> {
+        if features & !capabilities::KNOWN != 0 {
+            return Err(Error::Config(format!(
+                "unknown capability bits {:#x}",
+                features & !capabilities::KNOWN
+            )));
+        }
+        self.options.capabilities = features;
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_roster`.
+    pub fn set_roster(&mut self, enabled: bool) -> Result<&mut Self, Error> {
+        self.check_role(ConfigRole::Server, "the roster")?;
+        self.options.roster = enabled;
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_auth_token`; `None` clears it.
+    pub fn set_auth_token(&mut self, token: Option<&str>) -> Result<&mut Self, Error> {
+        self.check_role(ConfigRole::Client, "an auth token")?;
+        if let Some(token) = token {
+            if token.is_empty() || token.len() > auth::MAX_TOKEN_LEN {
+                return Err(Error::Config(format!(
+                    "auth tokens are 1 to {} bytes",
+                    auth::MAX_TOKEN_LEN
+                )));
+            }
+        }
+        self.options.auth_token = token.map(str::to_string);
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_auth_hmac`; `None` or an empty secret turns
+    /// the check off.
+    pub fn set_auth_hmac(&mut self, secret: Option<&[u8]>) -> Result<&mut Self, Error> {
+        self.check_role(ConfigRole::Server, "token checks")?;
+        self.options.auth = secret
+            .filter(|secret| !secret.is_empty())
+            .map(|secret| Verifier::Hmac(Arc::from(secret)));
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_auth_callback`.
+    pub fn set_auth_callback(&mut self, enabled: bool) -> Result<&mut Self, Error> {
+        self.check_role(ConfigRole::Server, "token checks")?;
+        self.options.auth = enabled.then_some(Verifier::Callback);
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_client_cert_optional`.
+    pub fn set_client_cert_optional(&mut self, enabled: bool) -> Result<&mut Self, Error> {
+        self.check_role(ConfigRole::Server, "optional client certificates")?;
+        self.options.client_cert_optional = enabled;
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_require_client_cert`.
+    pub fn set_require_client_cert(&mut self, enabled: bool) -> Result<&mut Self, Error> {
+        self.check_role(ConfigRole::Server, "required client certificates")?;
+        self.options.require_client_cert = enabled;
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_connection_approval`.
+    pub fn set_connection_approval(&mut self, timeout_ms: u64) -> Result<&mut Self, Error> {
+        self.check_role(ConfigRole::Server, "connection approval")?;
+        self.options.approval_timeout = (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms));
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_add_sni_identity`, with the allowlist entries as
+    /// a list.
+    pub fn add_sni_identity(
+        &mut self,
+        server_name: &str,
+        cert_path: &str,
+        key_path: &str,
+        trust: &[&str],
+    ) -> Result<&mut Self, Error> {
+        self.check_role(ConfigRole::Server, "SNI identities")?;
+        let server_name = server_name.trim().to_ascii_lowercase();
+        if server_name.is_empty() {
+            return Err(Error::Config("an SNI identity needs a server name".into()));
+        }
+        self.sni
+            .retain(|identity| identity.server_name != server_name);
+        self.sni.push(sni::SniIdentity {
+            server_name,
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+            trusted: allowlist::parse(&trust.join(",")),
+        });
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_coalesce`. The Rust APIs refuse to connect
+    /// with it, as a joined connection posts to a Dart port.
+    pub fn set_coalesce(&mut self, enabled: bool) -> Result<&mut Self, Error> {
+        self.check_role(ConfigRole::Client, "coalescing")?;
+        self.options.coalesce = enabled;
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_cwnd_resume`.
+    pub fn set_cwnd_resume(&mut self, max_age_ms: u64) -> Result<&mut Self, Error> {
+        self.check_role(ConfigRole::Client, "window resumption")?;
+        self.options.cwnd_resume = (max_age_ms > 0).then(|| Duration::from_millis(max_age_ms));
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_reverse_connect`, with the pins as a list.
+    pub fn set_reverse_connect(
+        &mut self,
+        enabled: bool,
+        pins: &[&str],
+    ) -> Result<&mut Self, Error> {
+        self.check_role(ConfigRole::Client, "reverse connect")?;
+        let mut reverse = ReverseConnect::default();
+        for pin in pins
+            .iter()
+            .map(|pin| pin.trim())
+            .filter(|pin| !pin.is_empty())
+        {
+            if pin.len() != 64 || !pin.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(Error::Config(format!(
+                    "reverse connect pin {pin:?} is not a hex SHA-256 fingerprint"
+                )));
+            }
+            reverse.pins.push(pin.to_lowercase());
+        }
+        self.options.reverse_connect = enabled.then_some(reverse);
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_tcp_fallback`.
+    pub fn set_tcp_fallback(&mut self, port: u16, udp_timeout_ms: u64) -> &mut Self {
+        self.options.tcp_fallback = (port > 0).then(|| Fallback {
+            port,
+            udp_timeout: match udp_timeout_ms {
+                0 => tunnel::DEFAULT_UDP_TIMEOUT,
+                ms => Duration::from_millis(ms),
+            },
+        });
+        self
+    }
+
+    /// As `cc_quic_config_set_cert_expiry_warning`.
+    pub fn set_cert_expiry_warning(&mut self, days: u32) -> &mut Self {
+        self.options.cert_warn_days = days;
+        self
+    }
+
+    /// As `cc_quic_config_set_ca_path`; `None` trusts the system store only.
+    pub fn set_ca_path(&mut self, path: Option<&std::path::Path>) -> Result<&mut Self, Error> {
+        if let Some(path) = path.filter(|path| !path.exists()) {
+            return Err(Error::Config(format!(
+                "CA path {} does not exist",
+                path.display()
+            )));
+        }
+        self.quic.ca_path = path.map(std::path::Path::to_path_buf);
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_udp_offload`.
+    pub fn set_udp_offload(&mut self, enabled: bool) -> &mut Self {
+        self.options.udp_offload = enabled;
+        self
+    }
+
+    /// As `cc_quic_config_set_pmtu_discovery`.
+    pub fn set_pmtu_discovery(
+        &mut self,
+        enabled: bool,
+        max_udp_payload: u32,
+    ) -> Result<&mut Self, Error> {
+        let max = match max_udp_payload {
+            0 => DEFAULT_MAX_UDP_PAYLOAD,
+            v if UDP_PAYLOAD_RANGE.contains(&v) => v as usize,
+            v => {
+                return Err(Error::Config(format!(
+                    "max UDP payload {v} is outside {UDP_PAYLOAD_RANGE:?}"
+                )))
+            }
+        };
+        self.quic.pmtu_discovery = enabled;
+        self.options.max_udp_payload = max;
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_hystart`.
+    pub fn set_hystart(&mut self, enabled: bool) -> &mut Self {
+        self.quic.hystart = enabled;
+        self
+    }
+
+    /// As `cc_quic_config_set_pacing`.
+    pub fn set_pacing(&mut self, enabled: bool, max_rate_kbps: u64) -> Result<&mut Self, Error> {
+        if !enabled && max_rate_kbps > 0 {
+            return Err(Error::Config("a pacing rate cap needs pacing".into()));
+        }
+        self.quic.pacing = enabled;
+        self.quic.max_pacing_rate = (max_rate_kbps > 0).then(|| max_rate_kbps.saturating_mul(125));
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_ack_delay`.
+    pub fn set_ack_delay(
+        &mut self,
+        max_ack_delay_ms: u64,
+        ack_delay_exponent: u32,
+    ) -> Result<&mut Self, Error> {
+        if max_ack_delay_ms > MAX_ACK_DELAY_LIMIT_MS
+            || ack_delay_exponent > ACK_DELAY_EXPONENT_LIMIT
+        {
+            return Err(Error::Config(format!(
+                "ACK delay is up to {MAX_ACK_DELAY_LIMIT_MS} ms, its exponent up to \
+                 {ACK_DELAY_EXPONENT_LIMIT}"
+            )));
+        }
+        self.quic.max_ack_delay_ms = match max_ack_delay_ms {
+            0 => DEFAULT_MAX_ACK_DELAY_MS,
+            ms => ms,
+        };
+        self.quic.ack_delay_exponent = match ack_delay_exponent {
+            0 => DEFAULT_ACK_DELAY_EXPONENT,
+            exponent => u64::from(exponent),
+        };
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_stats_interval`.
+    pub fn set_stats_interval(&mut self, interval_ms: u64) -> &mut Self {
+        self.options.stats_interval_ms = interval_ms;
+        self
+    }
+
+    /// As `cc_quic_config_set_shared_runtime`.
+    pub fn set_shared_runtime(&mut self, enabled: bool) -> Result<&mut Self, Error> {
+        self.check_role(ConfigRole::Client, "the shared runtime")?;
+        self.options.shared_runtime = enabled;
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_server_workers`.
+    pub fn set_server_workers(&mut self, workers: u32) -> Result<&mut Self, Error> {
+        self.check_role(ConfigRole::Server, "server workers")?;
+        if workers == 0 || workers > MAX_SERVER_WORKERS {
+            return Err(Error::Config(format!(
+                "server workers are 1 to {MAX_SERVER_WORKERS}"
+            )));
+        }
+        self.options.server_workers = workers as usize;
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_connection_limits`.
+    pub fn set_connection_limits(
+        &mut self,
+        max_lifetime_ms: u64,
+        max_handshake_ms: u64,
+    ) -> Result<&mut Self, Error> {
+        self.check_role(ConfigRole::Server, "connection limits")?;
+        let limit = |ms: u64| (ms > 0).then(|| Duration::from_millis(ms));
+        self.options.reap = ReapPolicy {
+            max_lifetime: limit(max_lifetime_ms),
+            max_handshake: limit(max_handshake_ms),
+        };
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_watchdog`.
+    pub fn set_watchdog(&mut self, threshold_ms: u64) -> &mut Self {
+        self.options.watchdog_ms = threshold_ms;
+        self
+    }
+
+    /// As `cc_quic_config_set_dgram_queues`.
+    pub fn set_dgram_queues(&mut self, recv_len: u32, send_len: u32) -> Result<&mut Self, Error> {
+        if recv_len == 0 || send_len == 0 {
+            return Err(Error::Config("datagram queues cannot be empty".into()));
+        }
+        self.options.dgram_recv_queue_len = recv_len as usize;
+        self.quic.dgram_send_queue_len = send_len as usize;
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_dgram_drop_policy`.
+    pub fn set_dgram_drop_policy(&mut self, policy: i32) -> Result<&mut Self, Error> {
+        let Some(policy) = DropPolicy::from_code(policy) else {
+            return Err(Error::Config(format!("unknown drop policy {policy}")));
+        };
+        self.options.dgram_drop_policy = policy;
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_media_reorder_window`.
+    pub fn set_media_reorder_window(&mut self, packets: u32) -> &mut Self {
+        self.options.media_reorder_window = packets as usize;
+        self
+    }
+
+    /// As `cc_quic_config_set_jitter_buffer`.
+    pub fn set_jitter_buffer(
+        &mut self,
+        clock_rate: u32,
+        min_delay_ms: u32,
+        max_delay_ms: u32,
+    ) -> Result<&mut Self, Error> {
+        if clock_rate == 0 {
+            self.options.jitter = None;
+            return Ok(self);
+        }
+        let or_default = |ms: u32, default: u32| if ms == 0 { default } else { ms };
+        let min = or_default(min_delay_ms, DEFAULT_JITTER_MIN_MS);
+        let max = or_default(max_delay_ms, DEFAULT_JITTER_MAX_MS);
+        if min > max {
+            return Err(Error::Config(format!(
+                "jitter delay {min} ms is above its maximum {max} ms"
+            )));
+        }
+        self.options.jitter = Some(JitterConfig {
+            clock_rate,
+            min_delay: Duration::from_millis(min as u64),
+            max_delay: Duration::from_millis(max as u64),
+        });
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_compression`.
+    pub fn set_compression(&mut self, mode: i32, min_size: u32) -> Result<&mut Self, Error> {
+        let mode = match mode {
+            0 => CompressionMode::Off,
+            1 if compress::deflate_available() => CompressionMode::Deflate,
+            1 | 2 => {
+                return Err(Error::Unsupported(format!(
+                    "compression mode {mode} is not in this build"
+                )))
+            }
+            _ => return Err(Error::Config(format!("unknown compression mode {mode}"))),
+        };
+        self.quic.alpns = match mode {
+            CompressionMode::Off => &[CONTROL_ALPN],
+            CompressionMode::Deflate => &[DEFLATE_ALPN, CONTROL_ALPN],
+        };
+        self.options.compression = CompressionConfig {
+            mode,
+            min_size: min_size as usize,
+        };
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_flow_window`.
+    pub fn set_flow_window(&mut self, min_bytes: u64, max_bytes: u64) -> Result<&mut Self, Error> {
+        let defaults = FlowWindow::default();
+        let window = FlowWindow {
+            min: if min_bytes == 0 {
+                defaults.min
+            } else {
+                min_bytes
+            },
+            max: if max_bytes == 0 {
+                defaults.max
+            } else {
+                max_bytes
+            },
+        };
+        if window.min > window.max {
+            return Err(Error::Config(format!(
+                "flow window {} is above its maximum {}",
+                window.min, window.max
+            )));
+        }
+        self.options.flow_window = window;
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_recv_limits`.
+    pub fn set_recv_limits(
+        &mut self,
+        max_message_bytes: u64,
+        max_connection_bytes: u64,
+    ) -> &mut Self {
+        self.options.recv_limits = RecvLimits {
+            max_message: (max_message_bytes > 0).then_some(max_message_bytes),
+            max_connection: (max_connection_bytes > 0).then_some(max_connection_bytes),
+        };
+        self
+    }
+
+    /// As `cc_quic_config_set_dual_channel`.
+    pub fn set_dual_channel(&mut self, media_kbps: u32, control_backlog_bytes: u32) -> &mut Self {
+        self.options.dual = DualChannelConfig {
+            media_rate_bps: media_kbps as u64 * 1000,
+            control_backlog_max: if control_backlog_bytes == 0 {
+                DEFAULT_STREAM_WINDOW as usize
+            } else {
+                control_backlog_bytes as usize
+            },
+            ..self.options.dual
+        };
+        self
+    }
+
+    /// As `cc_quic_config_set_datagrams_first`.
+    pub fn set_datagrams_first(&mut self, enabled: bool) -> &mut Self {
+        self.options.dual.datagrams_first = enabled;
+        self
+    }
+
+    /// As `cc_quic_config_set_event_schema`.
+    pub fn set_event_schema(&mut self, version: u32) -> Result<&mut Self, Error> {
+        let Some(schema) = EventSchema::from_version(version) else {
+            return Err(Error::Config(format!("unknown event schema {version}")));
+        };
+        self.options.event_schema = schema;
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_event_rate_limit`.
+    pub fn set_event_rate_limit(
+        &mut self,
+        event_mask: u32,
+        max_per_sec: u32,
+    ) -> Result<&mut Self, Error> {
+        if event_mask == 0 || event_mask & !EVENTS_ALL != 0 {
+            return Err(Error::Config(format!(
+                "event mask {event_mask:#x} names no event class, or an unknown one"
+            )));
+        }
+        let per_sec = &mut self.options.event_limits.per_sec;
+        for (class, limit) in per_sec.iter_mut().enumerate() {
+            if event_mask & 1 << class != 0 {
+                *limit = max_per_sec;
+            }
+        }
+        Ok(self)
+    }
+
+    /// As `cc_quic_config_set_error_coalescing`.
+    pub fn set_error_coalescing(&mut self, window_ms: u64) -> &mut Self {
+        self.options.event_limits.error_window =
+            (window_ms > 0).then(|| Duration::from_millis(window_ms));
+        self
+    }
+}
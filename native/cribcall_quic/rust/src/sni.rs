@@ -0,0 +1,444 @@
+//! Accept-time SNI routing (`cc_quic_config_add_sni_identity`): one
+//! listener presents a different certificate, and trusts a different
+//! allowlist, for each server name clients ask for. quiche takes the
+//! certificate from the config a connection is accepted with, before it
+//! has read the ClientHello, so the worker opens the client's first Initial
+//! itself (RFC 9001 §5: its keys derive from the destination connection id
+//! alone) with the BoringSSL quiche links, and reads the SNI from there.
+//!
+//! A client with no SNI, a name without an identity, or a ClientHello that
+//! does not fit in its first packet gets the server's default identity.
+//! Route allowlists are fixed at start; the file and reload calls apply to
+//! the default one.
+
+use std::collections::HashMap;
+use std::os::raw::{c_int, c_uint};
+use std::sync::Mutex;
+
+use crate::allowlist::{self, Allowlist};
+
+/// RFC 9001 §5.2.
+const INITIAL_SALT_V1: [u8; 20] = [
+    0x38, 0x76, 0x2c, 0xf7, 0xf5, 0x59, 0x34, 0xb3, 0x4d, 0x17, 0x9a, 0xe6, 0xa4, 0xc8, 0x0c, 0xad,
+    0xcc, 0xbb, 0x7f, 0x0a,
+];
+const TAG_LEN: usize = 16;
+/// Header protection samples the 16 bytes 4 past the packet number.
+const SAMPLE_OFFSET: usize = 4;
+const SAMPLE_LEN: usize = 16;
+
+#[repr(C)]
+struct EvpMd {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct EvpAead {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct EvpAeadCtx {
+    _private: [u8; 0],
+}
+
+/// BoringSSL's `AES_KEY`.
+#[repr(C, align(16))]
+struct AesKey {
+    rd_key: [u32; 60],
+    rounds: c_uint,
+}
+
+extern "C" {
+    fn EVP_sha256() -> *const EvpMd;
+    fn HKDF_extract(
+        out_key: *mut u8,
+        out_len: *mut usize,
+        digest: *const EvpMd,
+        secret: *const u8,
+        secret_len: usize,
+        salt: *const u8,
+        salt_len: usize,
+    ) -> c_int;
+    fn HKDF_expand(
+        out_key: *mut u8,
+        out_len: usize,
+        digest: *const EvpMd,
+        prk: *const u8,
+        prk_len: usize,
+        info: *const u8,
+        info_len: usize,
+    ) -> c_int;
+    fn AES_set_encrypt_key(key: *const u8, bits: c_uint, aes_key: *mut AesKey) -> c_int;
+    fn AES_encrypt(input: *const u8, out: *mut u8, key: *const AesKey);
+    fn EVP_aead_aes_128_gcm() -> *const EvpAead;
+    fn EVP_AEAD_CTX_new(
+        aead: *const EvpAead,
+        key: *const u8,
+        key_len: usize,
+        tag_len: usize,
+    ) -> *mut EvpAeadCtx;
+    fn EVP_AEAD_CTX_free(ctx: *mut EvpAeadCtx);
+    fn EVP_AEAD_CTX_open(
+        ctx: *const EvpAeadCtx,
+        out: *mut u8,
+        out_len: *mut usize,
+        max_out_len: usize,
+        nonce: *const u8,
+        nonce_len: usize,
+        input: *const u8,
+        in_len: usize,
+        ad: *const u8,
+        ad_len: usize,
+    ) -> c_int;
+}
+
+/// One `cc_quic_config_add_sni_identity`, kept on the config until a
+/// server starts with it.
+#[derive(Clone, Debug)]
+pub(crate) struct SniIdentity {
+    /// Lowercase.
+    pub server_name: String,
+    pub cert_path: String,
+    pub key_path: String,
+    pub trusted: HashMap<String, allowlist::Entry>,
+}
+
+/// An identity a started server picks by SNI.
+pub(crate) struct SniRoute {
+    pub server_name: String,
+    pub config: Mutex<quiche::Config>,
+    pub allowlist: Allowlist,
+}
+
+/// A started server's identities, shared by its workers.
+pub(crate) struct ServerIdentities {
+    /// The one quiche loads at start and `cc_quic_identity_rotate` replaces.
+    pub default: Mutex<quiche::Config>,
+    pub routes: Vec<SniRoute>,
+}
+
+impl ServerIdentities {
+    /// The config to accept `datagram`, a client's first Initial, with,
+    /// and the route whose name its ClientHello asks for.
+    pub(crate) fn for_initial(&self, datagram: &[u8]) -> (&Mutex<quiche::Config>, Option<usize>) {
+        match pick(&self.routes, datagram) {
+            Some(route) => (&self.routes[route].config, Some(route)),
+            None => (&self.default, None),
+        }
+    }
+
+    /// What a connection accepted for `route` is admitted by.
+    pub(crate) fn allowlist<'a>(
+        &'a self,
+        route: Option<usize>,
+        default: &'a Allowlist,
+    ) -> &'a Allowlist {
+        route.map_or(default, |route| &self.routes[route].allowlist)
+    }
+}
+
+fn pick(routes: &[SniRoute], datagram: &[u8]) -> Option<usize> {
+    if routes.is_empty() {
+        return None;
+    }
+    let name = client_hello_server_name(datagram)?;
+    routes.iter().position(|route| route.server_name == name)
+}
+
+/// The SNI in the ClientHello of a client Initial, lowercased.
+fn client_hello_server_name(datagram: &[u8]) -> Option<String> {
+    let payload = open_initial(datagram)?;
+    server_name(&crypto_stream(&payload))
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let (head, rest) = self.0.split_at_checked(len)?;
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn vec8(&mut self) -> Option<&'a [u8]> {
+        let len = self.u8()?;
+        self.take(len.into())
+    }
+
+    fn vec16(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()?;
+        self.take(len.into())
+    }
+
+    /// A QUIC variable-length integer (RFC 9000 §16).
+    fn varint(&mut self) -> Option<u64> {
+        let first = self.u8()?;
+        let rest = self.take((1 << (first >> 6)) - 1)?;
+        Some(rest.iter().fold(u64::from(first & 0x3f), |value, byte| {
+            value << 8 | u64::from(*byte)
+        }))
+    }
+
+    fn varint_len(&mut self) -> Option<usize> {
+        usize::try_from(self.varint()?).ok()
+    }
+}
+
+/// The decrypted payload of the Initial packet that starts `datagram`.
+fn open_initial(datagram: &[u8]) -> Option<Vec<u8>> {
+    let mut reader = Reader(datagram);
+    // Long header and fixed bit, Initial type; the low bits are protected.
+    if reader.u8()? & 0xf0 != 0xc0 || reader.take(4)? != [0, 0, 0, 1] {
+        return None;
+    }
+    let dcid = reader.vec8()?;
+    reader.vec8()?;
+    let token_len = reader.varint_len()?;
+    reader.take(token_len)?;
+    let length = reader.varint_len()?;
+    let pn_offset = datagram.len() - reader.0.len();
+    let end = pn_offset.checked_add(length)?;
+    if end > datagram.len() {
+        return None;
+    }
+    let sample_at = pn_offset + SAMPLE_OFFSET;
+    let sample = datagram.get(sample_at..sample_at + SAMPLE_LEN)?;
+
+    let keys = InitialKeys::client(dcid)?;
+    let mask = keys.mask(sample)?;
+    let mut header = datagram[..pn_offset].to_vec();
+    header[0] ^= mask[0] & 0x0f;
+    let pn_len = usize::from(header[0] & 0x03) + 1;
+    let mut pn = 0u64;
+    for (byte, mask) in datagram[pn_offset..pn_offset + pn_len]
+        .iter()
+        .zip(&mask[1..])
+    {
+        header.push(byte ^ mask);
+        pn = pn << 8 | u64::from(byte ^ mask);
+    }
+    keys.open(pn, &header, &datagram[pn_offset + pn_len..end])
+}
+
+/// The client's Initial keys for a connection (RFC 9001 §5.2).
+struct InitialKeys {
+    key: [u8; 16],
+    iv: [u8; 12],
+    hp: [u8; 16],
+}
+
+impl InitialKeys {
+    fn client(dcid: &[u8]) -> Option<Self> {
+        let mut initial = [0u8; 32];
+        let mut initial_len = 0;
+        let ok = unsafe {
+            HKDF_extract(
+                initial.as_mut_ptr(),
+                &mut initial_len,
+                EVP_sha256(),
+                dcid.as_ptr(),
+                dcid.len(),
+                INITIAL_SALT_V1.as_ptr(),
+                INITIAL_SALT_V1.len(),
+            )
+        };
+        if ok != 1 {
+            return None;
+        }
+        let secret: [u8; 32] = expand_label(&initial[..initial_len], b"client in")?;
+        Some(Self {
+            key: expand_label(&secret, b"quic key")?,
+            iv: expand_label(&secret, b"quic iv")?,
+            hp: expand_label(&secret, b"quic hp")?,
+        })
+    }
+
+    /// The header protection mask for `sample` (AES-128-ECB).
+    fn mask(&self, sample: &[u8]) -> Option<[u8; 16]> {
+        let mut key = AesKey {
+            rd_key: [0; 60],
+            rounds: 0,
+        };
+        let mut mask = [0u8; 16];
+        unsafe {
+            if AES_set_encrypt_key(self.hp.as_ptr(), 128, &mut key) != 0 {
+                return None;
+            }
+            AES_encrypt(sample.as_ptr(), mask.as_mut_ptr(), &key);
+        }
+        Some(mask)
+    }
+
+    fn open(&self, pn: u64, header: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        let mut nonce = self.iv;
+        for (byte, pn) in nonce[4..].iter_mut().zip(pn.to_be_bytes()) {
+            *byte ^= pn;
+        }
+        let ctx =
+            unsafe { EVP_AEAD_CTX_new(EVP_aead_aes_128_gcm(), self.key.as_ptr(), 16, TAG_LEN) };
+        if ctx.is_null() {
+            return None;
+        }
+        let mut out = vec![0u8; sealed.len()];
+        let mut out_len = 0;
+        let ok = unsafe {
+            let ok = EVP_AEAD_CTX_open(
+                ctx,
+                out.as_mut_ptr(),
+                &mut out_len,
+                out.len(),
+                nonce.as_ptr(),
+                nonce.len(),
+                sealed.as_ptr(),
+                sealed.len(),
+                header.as_ptr(),
+                header.len(),
+            );
+            EVP_AEAD_CTX_free(ctx);
+            ok
+        };
+        out.truncate(out_len);
+        (ok == 1).then_some(out)
+    }
+}
+
+/// TLS 1.3 `HKDF-Expand-Label` with an empty context.
+fn expand_label<const N: usize>(secret: &[u8], label: &[u8]) -> Option<[u8; N]> {
+    let mut info = Vec::with_capacity(10 + label.len());
+    info.extend_from_slice(&(N as u16).to_be_bytes());
+    info.push((6 + label.len()) as u8);
+    info.extend_from_slice(b"tls13 ");
+    info.extend_from_slice(label);
+    info.push(0);
+    let mut out = [0u8; N];
+    let ok = unsafe {
+        HKDF_expand(
+            out.as_mut_ptr(),
+            N,
+            EVP_sha256(),
+            secret.as_ptr(),
+            secret.len(),
+            info.as_ptr(),
+            info.len(),
+        )
+    };
+    (ok == 1).then_some(out)
+}
+
+/// The CRYPTO frames of an Initial payload put together from offset 0, as
+/// far as they are contiguous.
+fn crypto_stream(payload: &[u8]) -> Vec<u8> {
+    let mut pieces = Vec::new();
+    let mut reader = Reader(payload);
+    while let Some(frame) = reader.varint() {
+        match frame {
+            // PADDING, PING.
+            0x00 | 0x01 => {}
+            0x06 => {
+                let (Some(offset), Some(len)) = (reader.varint_len(), reader.varint_len()) else {
+                    break;
+                };
+                let Some(data) = reader.take(len) else {
+                    break;
+                };
+                pieces.push((offset, data));
+            }
+            // Nothing else comes before a client's first CRYPTO data.
+            _ => break,
+        }
+    }
+    pieces.sort_by_key(|(offset, _)| *offset);
+    let mut stream = Vec::new();
+    for (offset, data) in pieces {
+        let Some(overlap) = stream.len().checked_sub(offset) else {
+            break;
+        };
+        if let Some(fresh) = data.get(overlap..) {
+            stream.extend_from_slice(fresh);
+        }
+    }
+    stream
+}
+
+/// The `host_name` of the `server_name` extension (RFC 6066 §3) in a
+/// ClientHello handshake message, lowercased.
+fn server_name(hello: &[u8]) -> Option<String> {
+    let mut reader = Reader(hello);
+    if reader.u8()? != 1 {
+        return None;
+    }
+    // Length, legacy_version, random.
+    reader.take(3 + 2 + 32)?;
+    reader.vec8()?;
+    reader.vec16()?;
+    reader.vec8()?;
+    let mut extensions = Reader(reader.vec16()?);
+    while !extensions.0.is_empty() {
+        let kind = extensions.u16()?;
+        let body = extensions.vec16()?;
+        if kind != 0 {
+            continue;
+        }
+        let mut names = Reader(Reader(body).vec16()?);
+        while !names.0.is_empty() {
+            let name_type = names.u8()?;
+            let name = names.vec16()?;
+            if name_type == 0 {
+                return std::str::from_utf8(name).ok().map(str::to_ascii_lowercase);
+            }
+        }
+        return None;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_the_rfc_9001_client_keys() {
+        let keys = InitialKeys::client(&hex::decode("8394c8f03e515708").unwrap()).unwrap();
+        assert_eq!(hex::encode(keys.key), "1f369613dd76d5467730efcbe3b1a22d");
+        assert_eq!(hex::encode(keys.iv), "fa044b2f42a3fd3b46fb255c");
+        assert_eq!(hex::encode(keys.hp), "9f50449e04a0e810283a1e9933adedd2");
+    }
+
+    #[test]
+    fn reads_the_sni_of_a_quiche_client_initial() {
+        let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+        config.set_application_protos(&[b"cribcall"]).unwrap();
+        let scid = [7u8; quiche::MAX_CONN_ID_LEN];
+        let addr = "127.0.0.1:4433".parse().unwrap();
+        let mut conn = quiche::connect(
+            Some("CribCall-Admin"),
+            &quiche::ConnectionId::from_ref(&scid),
+            addr,
+            addr,
+            &mut config,
+        )
+        .unwrap();
+        let mut datagram = [0u8; 1350];
+        let (len, _) = conn.send(&mut datagram).unwrap();
+        let datagram = &datagram[..len];
+        assert_eq!(
+            client_hello_server_name(datagram).as_deref(),
+            Some("cribcall-admin")
+        );
+
+        let mut tampered = datagram.to_vec();
+        tampered[100] ^= 1;
+        assert_eq!(client_hello_server_name(&tampered), None);
+        assert_eq!(pick(&[], datagram), None);
+    }
+}
@@ -0,0 +1,1070 @@
+use crate::masque::MasqueTunnel;
+use crate::socks::SocksTunnel;
+use crate::transport::MemoryEnd;
+use crate::tunnel::{TunnelListener, TunnelStream};
+use serde::Serialize;
+use std::cell::Cell;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+/// ECN codepoint carried in the low two bits of the IP TOS / traffic class.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Ecn {
+    NotEct,
+    Ect1,
+    Ect0,
+    Ce,
+}
+
+impl Ecn {
+    pub(crate) fn from_tos(tos: u8) -> Self {
+        match tos & 0b11 {
+            0b01 => Ecn::Ect1,
+            0b10 => Ecn::Ect0,
+            0b11 => Ecn::Ce,
+            _ => Ecn::NotEct,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Serialize)]
+pub(crate) struct EcnCounts {
+    pub ect0: u64,
+    pub ect1: u64,
+    pub ce: u64,
+}
+
+impl EcnCounts {
+    pub(crate) fn record(&mut self, ecn: Ecn) {
+        match ecn {
+            Ecn::Ect0 => self.ect0 += 1,
+            Ecn::Ect1 => self.ect1 += 1,
+            Ecn::Ce => self.ce += 1,
+            Ecn::NotEct => {}
+        }
+    }
+}
+
+/// Number of datagrams moved per `recvmmsg`/`sendmmsg` call.
+pub(crate) const BATCH_SIZE: usize = 32;
+/// Smallest receive slot without GRO, else slots match the largest payload
+/// we advertise (`max_recv_udp_payload_size`). The floor covers a peer that
+/// pads its first Initial past our limit before it has learned it.
+const RECV_SLOT_SIZE: usize = 2048;
+/// With GRO the kernel may hand back up to 64 KiB of coalesced datagrams
+/// per slot, so fewer, larger slots are used.
+const GRO_SLOT_SIZE: usize = 65_535;
+const GRO_SLOTS: usize = 8;
+/// Socket buffer asked for in each direction. Workers drain the socket
+/// every pass, but a burst of handshakes can still outrun the default
+/// (~208 KiB) between passes; the kernel caps this at `rmem_max`/`wmem_max`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const SOCKET_BUFFER_BYTES: libc::c_int = 4 * 1024 * 1024;
+/// The kernel rejects a GSO send whose segments add up to more than one
+/// IPv4 UDP datagram could carry.
+const GSO_MAX_BYTES: usize = 65_507;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const UDP_SEGMENT: libc::c_int = 103;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const UDP_GRO: libc::c_int = 104;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct SocketOptions {
+    pub ecn: bool,
+    /// Use UDP GSO for runs of equal-size datagrams and GRO on receive.
+    pub offload: bool,
+    /// Largest UDP payload sent or received; sizes the batch slots.
+    pub max_payload: usize,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct RecvMeta {
+    pub len: usize,
+    pub from: SocketAddr,
+    pub ecn: Ecn,
+}
+
+/// Fixed set of receive slots filled by one `recv_batch` call. GRO slots are
+/// split back into individual datagrams, so callers only ever see those.
+pub(crate) struct RecvBatch {
+    buf: Vec<u8>,
+    slot_size: usize,
+    slots: usize,
+    datagrams: Vec<(usize, RecvMeta)>,
+}
+
+impl RecvBatch {
+    pub(crate) fn new(slot_size: usize, slots: usize) -> Self {
+        let slots = slots.min(BATCH_SIZE);
+        Self {
+            buf: vec![0; slot_size * slots],
+            slot_size,
+            slots,
+            datagrams: Vec::with_capacity(BATCH_SIZE),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.datagrams.len()
+    }
+
+    pub(crate) fn get_mut(&mut self, index: usize) -> (&mut [u8], RecvMeta) {
+        let (start, meta) = self.datagrams[index];
+        (&mut self.buf[start..start + meta.len], meta)
+    }
+
+    fn slot_mut(&mut self, index: usize) -> &mut [u8] {
+        let start = index * self.slot_size;
+        &mut self.buf[start..start + self.slot_size]
+    }
+
+    /// Records `len` bytes received into `slot`, split into `segment`-sized
+    /// datagrams when the kernel coalesced them.
+    fn push_slot(&mut self, slot: usize, len: usize, segment: usize, from: SocketAddr, ecn: Ecn) {
+        let base = slot * self.slot_size;
+        let segment = if segment == 0 { len } else { segment };
+        let mut offset = 0;
+        while offset < len {
+            let seg_len = segment.min(len - offset);
+            self.datagrams.push((
+                base + offset,
+                RecvMeta {
+                    len: seg_len,
+                    from,
+                    ecn,
+                },
+            ));
+            offset += seg_len;
+        }
+    }
+}
+
+/// Outgoing datagrams queued by the worker and flushed with one `send_batch`.
+pub(crate) struct SendBatch {
+    buf: Vec<u8>,
+    slot_size: usize,
+    lens: Vec<usize>,
+    dests: Vec<SocketAddr>,
+}
+
+impl SendBatch {
+    pub(crate) fn new(slot_size: usize) -> Self {
+        Self {
+            buf: vec![0; slot_size * BATCH_SIZE],
+            slot_size,
+            lens: Vec::with_capacity(BATCH_SIZE),
+            dests: Vec::with_capacity(BATCH_SIZE),
+        }
+    }
+
+    /// Next free slot; only valid while `!is_full()`.
+    pub(crate) fn slot_mut(&mut self) -> &mut [u8] {
+        let start = self.lens.len() * self.slot_size;
+        &mut self.buf[start..start + self.slot_size]
+    }
+
+    pub(crate) fn push(&mut self, len: usize, to: SocketAddr) {
+        self.lens.push(len);
+        self.dests.push(to);
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.lens.len() == BATCH_SIZE
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.lens.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.lens.clear();
+        self.dests.clear();
+    }
+
+    fn datagram(&self, index: usize) -> (&[u8], SocketAddr) {
+        let start = index * self.slot_size;
+        (
+            &self.buf[start..start + self.lens[index]],
+            self.dests[index],
+        )
+    }
+
+    /// End (exclusive) of the GSO run starting at `start`: same destination,
+    /// every datagram but the last exactly as long as the first, and no
+    /// more than [`GSO_MAX_BYTES`] in all.
+    fn gso_run_end(&self, start: usize) -> usize {
+        let segment = self.lens[start];
+        let mut end = start + 1;
+        while end < self.lens.len()
+            && self.dests[end] == self.dests[start]
+            && self.lens[end - 1] == segment
+            && self.lens[end] <= segment
+            && (end + 1 - start) * segment <= GSO_MAX_BYTES
+        {
+            end += 1;
+        }
+        end
+    }
+}
+
+/// Nonblocking UDP socket used by the workers.
+///
+/// quiche has no ECN support of its own: it neither echoes ECN counts in
+/// its ACKs nor backs off on CE. So the socket never marks what it sends
+/// (RFC 9000 §13.4 only allows ECT with a congestion response), and with
+/// ECN enabled it only reports the codepoint of each received datagram back
+/// to the worker for accounting. On Linux/Android
+/// datagrams move in batches via `recvmmsg`/`sendmmsg`, optionally with UDP
+/// GSO/GRO; elsewhere the batch calls loop over plain `recv_from`/`send_to`.
+/// Integration tests swap the UDP socket for one end of an in-memory pair,
+/// the TCP fallback for a tunnel stream or listener, and a proxied client
+/// for its MASQUE tunnel or SOCKS5 association.
+pub(crate) struct QuicSocket {
+    inner: Transport,
+    ecn: bool,
+    gso: Cell<bool>,
+    gro: bool,
+    max_payload: usize,
+    /// Datagrams dropped for not fitting a receive slot.
+    truncated: Cell<u64>,
+}
+
+enum Transport {
+    Udp(UdpSocket),
+    Memory(MemoryEnd),
+    Tunnel(TunnelStream),
+    TunnelListener(TunnelListener),
+    Masque(MasqueTunnel),
+    Socks(SocksTunnel),
+}
+
+impl QuicSocket {
+    pub(crate) fn bind<A: ToSocketAddrs>(addr: A, options: SocketOptions) -> io::Result<Self> {
+        Self::from_udp(UdpSocket::bind(addr)?, options)
+    }
+
+    /// Binds with `SO_REUSEPORT` so several workers can share `addr`; the
+    /// kernel then spreads incoming flows across them by 4-tuple hash.
+    pub(crate) fn bind_reuseport(addr: SocketAddr, options: SocketOptions) -> io::Result<Self> {
+        Self::from_udp(bind_reuseport(addr)?, options)
+    }
+
+    fn from_udp(inner: UdpSocket, options: SocketOptions) -> io::Result<Self> {
+        inner.set_nonblocking(true)?;
+        grow_buffers(&inner);
+        let ecn = options.ecn && enable_ecn(&inner);
+        let (gso, gro) = if options.offload {
+            enable_offload(&inner)
+        } else {
+            (false, false)
+        };
+        Ok(Self {
+            inner: Transport::Udp(inner),
+            ecn,
+            gso: Cell::new(gso),
+            gro,
+            max_payload: options.max_payload,
+            truncated: Cell::new(0),
+        })
+    }
+
+    /// Wraps one end of a [`crate::transport::pair`]; ECN and offload do
+    /// not apply.
+    pub(crate) fn memory(end: MemoryEnd, max_payload: usize) -> Self {
+        Self::without_offload(Transport::Memory(end), max_payload)
+    }
+
+    /// Wraps a client's TCP tunnel; ECN and offload do not apply.
+    pub(crate) fn tunnel(stream: TunnelStream, max_payload: usize) -> Self {
+        Self::without_offload(Transport::Tunnel(stream), max_payload)
+    }
+
+    /// Wraps a server's TCP tunnel listener; ECN and offload do not apply.
+    pub(crate) fn tunnel_listener(listener: TunnelListener, max_payload: usize) -> Self {
+        Self::without_offload(Transport::TunnelListener(listener), max_payload)
+    }
+
+    /// Wraps a client's MASQUE tunnel; ECN and offload do not apply.
+    pub(crate) fn masque(tunnel: MasqueTunnel, max_payload: usize) -> Self {
+        Self::without_offload(Transport::Masque(tunnel), max_payload)
+    }
+
+    /// Wraps a client's SOCKS5 association; ECN and offload do not apply.
+    pub(crate) fn socks(tunnel: SocksTunnel, max_payload: usize) -> Self {
+        Self::without_offload(Transport::Socks(tunnel), max_payload)
+    }
+
+    fn without_offload(inner: Transport, max_payload: usize) -> Self {
+        Self {
+            inner,
+            ecn: false,
+            gso: Cell::new(false),
+            gro: false,
+            max_payload,
+            truncated: Cell::new(0),
+        }
+    }
+
+    /// Whether this is a server's TCP fallback listener.
+    pub(crate) fn is_tunnel_listener(&self) -> bool {
+        matches!(self.inner, Transport::TunnelListener(_))
+    }
+
+    /// Whether this is a client's MASQUE tunnel or SOCKS5 association.
+    pub(crate) fn is_proxied(&self) -> bool {
+        matches!(self.inner, Transport::Masque(_) | Transport::Socks(_))
+    }
+
+    /// The address the in-memory peer sends from, if this is a memory end.
+    pub(crate) fn memory_peer(&self) -> Option<SocketAddr> {
+        match &self.inner {
+            Transport::Memory(end) => Some(end.peer_addr()),
+            _ => None,
+        }
+    }
+
+    /// A memory end or tunnel only ever reaches its peer, so connecting is
+    /// a no-op.
+    pub(crate) fn connect(&self, peer: SocketAddr) -> io::Result<()> {
+        match &self.inner {
+            Transport::Udp(udp) => udp.connect(peer),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        match &self.inner {
+            Transport::Udp(udp) => udp.local_addr(),
+            Transport::Memory(end) => Ok(end.local_addr()),
+            Transport::Tunnel(stream) => Ok(stream.local_addr()),
+            Transport::TunnelListener(listener) => listener.local_addr(),
+            Transport::Masque(tunnel) => Ok(tunnel.local_addr()),
+            Transport::Socks(tunnel) => Ok(tunnel.local_addr()),
+        }
+    }
+
+    /// Datagrams dropped so far because they were larger than a receive
+    /// slot; quiche never sees a cut-short packet. The running count is in
+    /// each drop's log line.
+    #[cfg(test)]
+    pub(crate) fn truncated(&self) -> u64 {
+        self.truncated.get()
+    }
+
+    fn drop_truncated(&self, from: SocketAddr, len: usize) {
+        let dropped = self.truncated.get() + 1;
+        self.truncated.set(dropped);
+        log::warn!("dropped a truncated datagram from {from} ({len} bytes read, {dropped} so far)");
+    }
+
+    /// Receive buffer sized for this socket's GRO setting.
+    pub(crate) fn new_recv_batch(&self) -> RecvBatch {
+        if self.gro {
+            RecvBatch::new(GRO_SLOT_SIZE, GRO_SLOTS)
+        } else {
+            RecvBatch::new(RECV_SLOT_SIZE.max(self.max_payload), BATCH_SIZE)
+        }
+    }
+
+    /// Send buffer with a slot per datagram of the largest payload.
+    pub(crate) fn new_send_batch(&self) -> SendBatch {
+        SendBatch::new(self.max_payload)
+    }
+
+    /// Reads a batch of datagrams; `WouldBlock` when none are pending.
+    pub(crate) fn recv_batch(&self, batch: &mut RecvBatch) -> io::Result<usize> {
+        batch.datagrams.clear();
+        match &self.inner {
+            Transport::Udp(udp) => recv_batch(self, udp, batch)?,
+            Transport::Memory(end) => {
+                let from = end.peer_addr();
+                let received = end.recv(batch.slots, |i, data| {
+                    if data.len() > batch.slot_size {
+                        return self.drop_truncated(from, data.len());
+                    }
+                    batch.slot_mut(i)[..data.len()].copy_from_slice(data);
+                    batch.push_slot(i, data.len(), 0, from, Ecn::NotEct);
+                });
+                if received == 0 {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+            }
+            Transport::Tunnel(stream) => {
+                tunnel_batch(self, batch, |slots, each| stream.recv(slots, each))?
+            }
+            Transport::TunnelListener(listener) => {
+                tunnel_batch(self, batch, |slots, each| listener.recv(slots, each))?
+            }
+            Transport::Masque(tunnel) => {
+                tunnel_batch(self, batch, |slots, each| tunnel.recv(slots, each))?
+            }
+            Transport::Socks(tunnel) => {
+                tunnel_batch(self, batch, |slots, each| tunnel.recv(slots, each))?
+            }
+        }
+        Ok(batch.len())
+    }
+
+    /// Sends every queued datagram and empties the batch, even on error
+    /// (quiche's loss recovery covers anything that did not go out).
+    pub(crate) fn send_batch(&self, batch: &mut SendBatch) -> io::Result<usize> {
+        if batch.is_empty() {
+            return Ok(0);
+        }
+        let result = match &self.inner {
+            Transport::Udp(udp) => send_batch(self, udp, batch),
+            Transport::Memory(end) => {
+                for i in 0..batch.lens.len() {
+                    let (data, to) = batch.datagram(i);
+                    end.send(data, to);
+                }
+                Ok(batch.lens.len())
+            }
+            Transport::Tunnel(stream) => {
+                for i in 0..batch.lens.len() {
+                    stream.send(batch.datagram(i).0);
+                }
+                Ok(batch.lens.len())
+            }
+            Transport::TunnelListener(listener) => {
+                for i in 0..batch.lens.len() {
+                    let (data, to) = batch.datagram(i);
+                    listener.send(data, to);
+                }
+                Ok(batch.lens.len())
+            }
+            Transport::Masque(tunnel) => {
+                for i in 0..batch.lens.len() {
+                    tunnel.send(batch.datagram(i).0);
+                }
+                Ok(batch.lens.len())
+            }
+            Transport::Socks(tunnel) => {
+                for i in 0..batch.lens.len() {
+                    tunnel.send(batch.datagram(i).0);
+                }
+                Ok(batch.lens.len())
+            }
+        };
+        batch.clear();
+        result
+    }
+}
+
+/// Fills `batch` from a tunnel's `recv`; `WouldBlock` when nothing was read.
+fn tunnel_batch(
+    socket: &QuicSocket,
+    batch: &mut RecvBatch,
+    recv: impl FnOnce(usize, &mut dyn FnMut(usize, &[u8], SocketAddr)) -> usize,
+) -> io::Result<()> {
+    let received = recv(batch.slots, &mut |i, data, from| {
+        if data.len() > batch.slot_size {
+            return socket.drop_truncated(from, data.len());
+        }
+        batch.slot_mut(i)[..data.len()].copy_from_slice(data);
+        batch.push_slot(i, data.len(), 0, from, Ecn::NotEct);
+    });
+    if received == 0 {
+        return Err(io::ErrorKind::WouldBlock.into());
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn recv_batch(socket: &QuicSocket, udp: &UdpSocket, batch: &mut RecvBatch) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let slots = batch.slots;
+    let mut addrs: [libc::sockaddr_storage; BATCH_SIZE] = unsafe { std::mem::zeroed() };
+    let mut iovs: [libc::iovec; BATCH_SIZE] = unsafe { std::mem::zeroed() };
+    let mut controls = [[0u64; 8]; BATCH_SIZE];
+    let mut msgs: [libc::mmsghdr; BATCH_SIZE] = unsafe { std::mem::zeroed() };
+    let want_cmsgs = socket.ecn || socket.gro;
+    for i in 0..slots {
+        let slot = batch.slot_mut(i);
+        iovs[i] = libc::iovec {
+            iov_base: slot.as_mut_ptr() as *mut libc::c_void,
+            iov_len: slot.len(),
+        };
+        let hdr = &mut msgs[i].msg_hdr;
+        hdr.msg_name = &mut addrs[i] as *mut libc::sockaddr_storage as *mut libc::c_void;
+        hdr.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        hdr.msg_iov = &mut iovs[i];
+        hdr.msg_iovlen = 1;
+        if want_cmsgs {
+            hdr.msg_control = controls[i].as_mut_ptr() as *mut libc::c_void;
+            hdr.msg_controllen = std::mem::size_of_val(&controls[i]) as _;
+        }
+    }
+
+    let count = unsafe {
+        libc::recvmmsg(
+            udp.as_raw_fd(),
+            msgs.as_mut_ptr(),
+            slots as _,
+            0,
+            std::ptr::null_mut(),
+        )
+    };
+    if count < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    for i in 0..count as usize {
+        let (tos, segment) = if want_cmsgs {
+            unsafe { read_cmsgs(&msgs[i].msg_hdr) }
+        } else {
+            (0, 0)
+        };
+        let from = sockaddr_to_std(&addrs[i])?;
+        if msgs[i].msg_hdr.msg_flags & libc::MSG_TRUNC != 0 {
+            socket.drop_truncated(from, msgs[i].msg_len as usize);
+            continue;
+        }
+        batch.push_slot(
+            i,
+            msgs[i].msg_len as usize,
+            segment,
+            from,
+            Ecn::from_tos(tos),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn recv_batch(socket: &QuicSocket, udp: &UdpSocket, batch: &mut RecvBatch) -> io::Result<()> {
+    for i in 0..batch.slots {
+        match udp.recv_from(batch.slot_mut(i)) {
+            // `recv_from` cuts a datagram to the slot without saying so, and
+            // slots are larger than any payload we accept, so a full one was
+            // probably cut.
+            Ok((len, from)) if len == batch.slot_size => socket.drop_truncated(from, len),
+            Ok((len, from)) => batch.push_slot(i, len, 0, from, Ecn::NotEct),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock && i > 0 => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn send_batch(socket: &QuicSocket, udp: &UdpSocket, batch: &SendBatch) -> io::Result<usize> {
+    match send_batch_from(udp, batch, 0, socket.gso.get()) {
+        Err((err, unsent)) if err.raw_os_error() == Some(libc::EIO) && socket.gso.get() => {
+            // EIO means the device cannot segment for us; stop asking.
+            log::warn!("UDP GSO rejected by the kernel, falling back: {err}");
+            socket.gso.set(false);
+            send_batch_from(udp, batch, unsent, false).map_err(|(err, _)| err)
+        }
+        result => result.map_err(|(err, _)| err),
+    }
+}
+
+/// Sends datagrams `first..` as one `sendmmsg` sequence. On error returns the
+/// index of the first datagram that was not handed to the kernel.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn send_batch_from(
+    udp: &UdpSocket,
+    batch: &SendBatch,
+    first: usize,
+    gso: bool,
+) -> Result<usize, (io::Error, usize)> {
+    use std::os::fd::AsRawFd;
+
+    let count = batch.lens.len();
+    let mut addrs: [libc::sockaddr_storage; BATCH_SIZE] = unsafe { std::mem::zeroed() };
+    let mut iovs: [libc::iovec; BATCH_SIZE] = unsafe { std::mem::zeroed() };
+    let mut controls = [[0u64; 4]; BATCH_SIZE];
+    let mut msgs: [libc::mmsghdr; BATCH_SIZE] = unsafe { std::mem::zeroed() };
+    let mut msg_starts = [0usize; BATCH_SIZE + 1];
+
+    for (i, iov) in iovs.iter_mut().enumerate().take(count).skip(first) {
+        let (data, _) = batch.datagram(i);
+        *iov = libc::iovec {
+            iov_base: data.as_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+    }
+
+    let mut msg_count = 0;
+    let mut start = first;
+    while start < count {
+        let end = if gso {
+            batch.gso_run_end(start)
+        } else {
+            start + 1
+        };
+        let (_, to) = batch.datagram(start);
+        let hdr = &mut msgs[msg_count].msg_hdr;
+        hdr.msg_namelen = std_to_sockaddr(to, &mut addrs[msg_count]);
+        hdr.msg_name = &mut addrs[msg_count] as *mut libc::sockaddr_storage as *mut libc::c_void;
+        hdr.msg_iov = &mut iovs[start];
+        hdr.msg_iovlen = (end - start) as _;
+        if end - start > 1 {
+            hdr.msg_control = controls[msg_count].as_mut_ptr() as *mut libc::c_void;
+            hdr.msg_controllen = unsafe { libc::CMSG_SPACE(2) } as _;
+            unsafe {
+                let cmsg = libc::CMSG_FIRSTHDR(hdr);
+                (*cmsg).cmsg_level = libc::SOL_UDP;
+                (*cmsg).cmsg_type = UDP_SEGMENT;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(2) as _;
+                std::ptr::write_unaligned(
+                    libc::CMSG_DATA(cmsg) as *mut u16,
+                    batch.lens[start] as u16,
+                );
+            }
+        }
+        msg_starts[msg_count] = start;
+        msg_count += 1;
+        start = end;
+    }
+    msg_starts[msg_count] = count;
+
+    let fd = udp.as_raw_fd();
+    let mut sent = 0;
+    while sent < msg_count {
+        let rc =
+            unsafe { libc::sendmmsg(fd, msgs[sent..].as_mut_ptr(), (msg_count - sent) as _, 0) };
+        if rc < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err((err, msg_starts[sent]));
+        }
+        sent += rc as usize;
+    }
+    Ok(count - first)
+}
+
+/// Blocks until a datagram is pending on any of `sockets` or `timeout` has
+/// passed. A memory end is waited on alone; workers never mix one with UDP
+/// sockets. A tunnel holding a datagram it already read returns at once.
+pub(crate) fn wait_readable<'a>(
+    sockets: impl IntoIterator<Item = &'a QuicSocket>,
+    timeout: Duration,
+) {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let mut fds = Vec::new();
+    for socket in sockets {
+        match &socket.inner {
+            Transport::Memory(end) => return end.wait_readable(timeout),
+            Transport::Tunnel(stream) if stream.has_pending() => return,
+            Transport::TunnelListener(listener) if listener.has_pending() => return,
+            Transport::Masque(tunnel) if tunnel.has_pending() => return,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Transport::Udp(udp) => fds.push(std::os::fd::AsRawFd::as_raw_fd(udp)),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Transport::Tunnel(stream) => stream.push_fds(&mut fds),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Transport::TunnelListener(listener) => listener.push_fds(&mut fds),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Transport::Masque(tunnel) => tunnel.push_fds(&mut fds),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Transport::Socks(tunnel) => tunnel.push_fds(&mut fds),
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            _ => {}
+        }
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    wait_fds(&fds, timeout);
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    std::thread::sleep(timeout);
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn wait_fds(fds: &[std::os::fd::RawFd], timeout: Duration) {
+    let mut fds: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|fd| libc::pollfd {
+            fd: *fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    // Round up so a sub-millisecond timer doesn't turn into a busy loop.
+    let ms = timeout.as_micros().div_ceil(1000).min(i32::MAX as u128) as libc::c_int;
+    // EINTR and friends just end the wait early; the caller loops anyway.
+    unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, ms) };
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn send_batch(_socket: &QuicSocket, udp: &UdpSocket, batch: &SendBatch) -> io::Result<usize> {
+    for i in 0..batch.lens.len() {
+        let (data, to) = batch.datagram(i);
+        udp.send_to(data, to)?;
+    }
+    Ok(batch.lens.len())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_reuseport(addr: SocketAddr) -> io::Result<UdpSocket> {
+    use std::os::fd::FromRawFd;
+
+    let family = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe { libc::socket(family, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Owning the fd first closes it on every early return below.
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+    if !set_int_opt(fd, libc::SOL_SOCKET, libc::SO_REUSEPORT, 1) {
+        return Err(io::Error::last_os_error());
+    }
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let len = std_to_sockaddr(addr, &mut storage);
+    let rc = unsafe {
+        libc::bind(
+            fd,
+            &storage as *const libc::sockaddr_storage as *const libc::sockaddr,
+            len,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn bind_reuseport(_addr: SocketAddr) -> io::Result<UdpSocket> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SO_REUSEPORT workers are only supported on Linux/Android",
+    ))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn grow_buffers(socket: &UdpSocket) {
+    use std::os::fd::AsRawFd;
+
+    let fd = socket.as_raw_fd();
+    for name in [libc::SO_RCVBUF, libc::SO_SNDBUF] {
+        if !set_int_opt(fd, libc::SOL_SOCKET, name, SOCKET_BUFFER_BYTES) {
+            log::debug!(
+                "socket buffer {name} not grown: {}",
+                io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn grow_buffers(_socket: &UdpSocket) {}
+
+/// Probes UDP_SEGMENT and turns on UDP_GRO; returns (gso, gro).
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn enable_offload(socket: &UdpSocket) -> (bool, bool) {
+    use std::os::fd::AsRawFd;
+
+    let fd = socket.as_raw_fd();
+    let mut segment: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let gso = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_UDP,
+            UDP_SEGMENT,
+            &mut segment as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    } == 0;
+    let gro = set_int_opt(fd, libc::SOL_UDP, UDP_GRO, 1);
+    if !gso || !gro {
+        log::info!("UDP offload partially unavailable (gso={gso} gro={gro})");
+    }
+    (gso, gro)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn enable_offload(_socket: &UdpSocket) -> (bool, bool) {
+    (false, false)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn enable_ecn(socket: &UdpSocket) -> bool {
+    use std::os::fd::AsRawFd;
+
+    // Receive side only: sends stay Not-ECT until quiche can respond to CE.
+    let fd = socket.as_raw_fd();
+    let v6 = matches!(socket.local_addr(), Ok(SocketAddr::V6(_)));
+    let (level, recv) = if v6 {
+        (libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS)
+    } else {
+        (libc::IPPROTO_IP, libc::IP_RECVTOS)
+    };
+    let ok = set_int_opt(fd, level, recv, 1);
+    if !ok {
+        log::warn!("ECN unavailable: {}", io::Error::last_os_error());
+    }
+    ok
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn enable_ecn(_socket: &UdpSocket) -> bool {
+    log::warn!("ECN is not supported on this platform");
+    false
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn set_int_opt(
+    fd: libc::c_int,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> bool {
+    let rc = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    rc == 0
+}
+
+/// Extracts the TOS / traffic class byte and the GRO segment size (0 when
+/// not coalesced) from a received message's cmsgs.
+///
+/// # Safety
+/// `msg` must describe a control buffer filled in by `recvmmsg`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn read_cmsgs(msg: &libc::msghdr) -> (u8, usize) {
+    let mut tos = 0u8;
+    let mut segment = 0usize;
+    let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+    while !cmsg.is_null() {
+        let hdr = &*cmsg;
+        let data = libc::CMSG_DATA(cmsg);
+        if hdr.cmsg_level == libc::IPPROTO_IP && hdr.cmsg_type == libc::IP_TOS {
+            tos = *data;
+        } else if hdr.cmsg_level == libc::IPPROTO_IPV6 && hdr.cmsg_type == libc::IPV6_TCLASS {
+            tos = std::ptr::read_unaligned(data as *const libc::c_int) as u8;
+        } else if hdr.cmsg_level == libc::SOL_UDP && hdr.cmsg_type == UDP_GRO {
+            segment = std::ptr::read_unaligned(data as *const libc::c_int) as usize;
+        }
+        cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+    }
+    (tos, segment)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn sockaddr_to_std(addr: &libc::sockaddr_storage) -> io::Result<SocketAddr> {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+
+    match addr.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let sin = unsafe { &*(addr as *const _ as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+            Ok(SocketAddr::V4(SocketAddrV4::new(
+                ip,
+                u16::from_be(sin.sin_port),
+            )))
+        }
+        libc::AF_INET6 => {
+            let sin6 = unsafe { &*(addr as *const _ as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+            Ok(SocketAddr::V6(SocketAddrV6::new(
+                ip,
+                u16::from_be(sin6.sin6_port),
+                sin6.sin6_flowinfo,
+                sin6.sin6_scope_id,
+            )))
+        }
+        family => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported address family {family}"),
+        )),
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn std_to_sockaddr(addr: SocketAddr, out: &mut libc::sockaddr_storage) -> libc::socklen_t {
+    match addr {
+        SocketAddr::V4(v4) => {
+            let sin = unsafe { &mut *(out as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = v4.port().to_be();
+            sin.sin_addr = libc::in_addr {
+                s_addr: u32::from(*v4.ip()).to_be(),
+            };
+            std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t
+        }
+        SocketAddr::V6(v6) => {
+            let sin6 = unsafe { &mut *(out as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = v6.port().to_be();
+            sin6.sin6_flowinfo = v6.flowinfo();
+            sin6.sin6_addr = libc::in6_addr {
+                s6_addr: v6.ip().octets(),
+            };
+            sin6.sin6_scope_id = v6.scope_id();
+            std::mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_ecn_codepoints_from_tos() {
+        let mut counts = EcnCounts::default();
+        for tos in [0x00, 0x02, 0x01, 0x03, 0xb8 | 0x03] {
+            counts.record(Ecn::from_tos(tos));
+        }
+        assert_eq!((counts.ect0, counts.ect1, counts.ce), (1, 1, 2));
+    }
+
+    #[test]
+    fn groups_equal_size_runs_per_destination() {
+        let a: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let b: SocketAddr = "127.0.0.1:2".parse().unwrap();
+        let mut batch = SendBatch::new(16);
+        for (len, to) in [(8, a), (8, a), (5, a), (8, a), (8, b)] {
+            batch.push(len, to);
+        }
+        assert_eq!(batch.gso_run_end(0), 3);
+        assert_eq!(batch.gso_run_end(3), 4);
+        assert_eq!(batch.gso_run_end(4), 5);
+
+        // Jumbo segments: seven fit under the GSO byte limit.
+        let mut jumbo = SendBatch::new(9000);
+        for _ in 0..10 {
+            jumbo.push(9000, a);
+        }
+        assert_eq!(jumbo.gso_run_end(0), 7);
+    }
+
+    #[test]
+    fn batches_datagrams_over_loopback() {
+        let options = SocketOptions {
+            ecn: true,
+            offload: true,
+            max_payload: 64,
+        };
+        let a = QuicSocket::bind("127.0.0.1:0", options).unwrap();
+        let b = QuicSocket::bind("127.0.0.1:0", options).unwrap();
+        let to = b.local_addr().unwrap();
+
+        let mut tx = SendBatch::new(64);
+        for i in 0..3u8 {
+            let len = if i == 2 { 2 } else { 4 };
+            tx.slot_mut()[..len].fill(i);
+            tx.push(len, to);
+        }
+        assert_eq!(a.send_batch(&mut tx).unwrap(), 3);
+        assert!(tx.is_empty());
+
+        let mut rx = b.new_recv_batch();
+        let mut seen = Vec::new();
+        for _ in 0..100 {
+            match b.recv_batch(&mut rx) {
+                Ok(count) => {
+                    for i in 0..count {
+                        let (data, meta) = rx.get_mut(i);
+                        assert_eq!(meta.from, a.local_addr().unwrap());
+                        // Sends are never marked, with ECN on or not.
+                        assert_eq!(meta.ecn, Ecn::NotEct);
+                        seen.push(data.to_vec());
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => panic!("recv failed: {err}"),
+            }
+            if seen.len() == 3 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert_eq!(seen, vec![vec![0; 4], vec![1; 4], vec![2; 2]]);
+    }
+
+    #[test]
+    fn drops_datagrams_larger_than_a_slot() {
+        let options = SocketOptions {
+            max_payload: 64,
+            ..SocketOptions::default()
+        };
+        let socket = QuicSocket::bind("127.0.0.1:0", options).unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let to = socket.local_addr().unwrap();
+        sender.send_to(&[1; RECV_SLOT_SIZE + 100], to).unwrap();
+        sender.send_to(&[2; 10], to).unwrap();
+        wait_readable(std::slice::from_ref(&socket), Duration::from_secs(5));
+
+        let mut rx = socket.new_recv_batch();
+        let mut seen = Vec::new();
+        for _ in 0..100 {
+            match socket.recv_batch(&mut rx) {
+                Ok(count) => (0..count).for_each(|i| seen.push(rx.get_mut(i).0.to_vec())),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => panic!("recv failed: {err}"),
+            }
+            if !seen.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(seen, vec![vec![2; 10]]);
+        assert_eq!(socket.truncated(), 1);
+    }
+
+    #[test]
+    fn memory_ends_batch_like_sockets() {
+        let (server, client) = crate::transport::pair();
+        let (server, client) = (
+            QuicSocket::memory(server, 64),
+            QuicSocket::memory(client, 64),
+        );
+        let to = server.local_addr().unwrap();
+        assert_eq!(client.memory_peer(), Some(to));
+
+        let mut tx = client.new_send_batch();
+        for i in 0..3u8 {
+            tx.slot_mut()[..2].fill(i);
+            tx.push(2, to);
+        }
+        assert_eq!(client.send_batch(&mut tx).unwrap(), 3);
+        wait_readable(std::slice::from_ref(&server), Duration::from_secs(5));
+
+        let mut rx = server.new_recv_batch();
+        assert_eq!(server.recv_batch(&mut rx).unwrap(), 3);
+        let (data, meta) = rx.get_mut(2);
+        assert_eq!(
+            (&*data, meta.from),
+            (&[2u8, 2][..], client.local_addr().unwrap())
+        );
+        let err = server.recv_batch(&mut rx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn wait_readable_wakes_on_a_datagram() {
+        let options = SocketOptions {
+            max_payload: 64,
+            ..SocketOptions::default()
+        };
+        let a = QuicSocket::bind("127.0.0.1:0", options).unwrap();
+        let b = QuicSocket::bind("127.0.0.1:0", options).unwrap();
+        let c = QuicSocket::bind("127.0.0.1:0", options).unwrap();
+        let mut tx = SendBatch::new(64);
+        tx.push(1, c.local_addr().unwrap());
+        a.send_batch(&mut tx).unwrap();
+
+        let listeners = [b, c];
+        let start = std::time::Instant::now();
+        wait_readable(&listeners, Duration::from_secs(5));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        let c = &listeners[1];
+        assert_eq!(c.recv_batch(&mut c.new_recv_batch()).unwrap(), 1);
+    }
+}
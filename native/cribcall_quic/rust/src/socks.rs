@@ -0,0 +1,488 @@
+//! QUIC through a SOCKS5 proxy's UDP relay (`cc_quic_client_connect_via_proxy`
+//! with a `socks5://` URL), for a parent app routed through a home VPN
+//! gateway. The client asks the proxy for a UDP ASSOCIATE (RFC 1928) over
+//! a TCP control connection, with username/password auth (RFC 1929) when
+//! given, then sends each datagram to the relay behind a SOCKS UDP header
+//! naming the server. The association lasts as long as the control
+//! connection, so that is kept open with TCP keepalives and watched for
+//! the proxy closing it.
+
+use std::cell::Cell;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// How long the control connection and its handshake may take.
+pub(crate) const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const USER_PASS: u8 = 2;
+const NO_ACCEPTABLE: u8 = 0xff;
+const UDP_ASSOCIATE: u8 = 3;
+const ATYP_V4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_V6: u8 = 4;
+
+/// Where a `socks5://` connect sends the connection.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct SocksRoute {
+    host: String,
+    port: u16,
+    /// Username and password, if any.
+    auth: Option<(String, String)>,
+}
+
+impl SocksRoute {
+    /// Parses `socks5://host[:port]` (port 1080 by default); `auth` is
+    /// `user:password`, or empty.
+    pub(crate) fn parse(url: &str, auth: &str) -> Result<Self, String> {
+        let authority = url
+            .strip_prefix("socks5://")
+            .ok_or_else(|| format!("proxy url {url:?} is not socks5"))?
+            .trim_end_matches('/');
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(v6) => {
+                let (host, after) = v6
+                    .split_once(']')
+                    .ok_or_else(|| format!("proxy url {url:?} has an unclosed ["))?;
+                (host, after.strip_prefix(':'))
+            }
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        if host.is_empty() || host.contains('/') {
+            return Err(format!("proxy url {url:?} has no host"));
+        }
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| format!("proxy url {url:?} has a bad port"))?,
+            None => 1080,
+        };
+        let auth = match auth.split_once(':') {
+            _ if auth.is_empty() => None,
+            Some((user, pass)) if user.len() <= 255 && pass.len() <= 255 => {
+                Some((user.to_string(), pass.to_string()))
+            }
+            _ => return Err("socks5 auth must be user:password, each up to 255 bytes".into()),
+        };
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            auth,
+        })
+    }
+}
+
+/// A UDP association through the proxy to one server.
+pub(crate) struct SocksTunnel {
+    /// Holds the association open; read only to notice it closing.
+    control: TcpStream,
+    udp: UdpSocket,
+    target: SocketAddr,
+    local: SocketAddr,
+    header: Vec<u8>,
+    closed: Cell<bool>,
+}
+
+impl SocksTunnel {
+    /// Opens the control connection and the association for `target`,
+    /// blocking for up to `timeout`.
+    pub(crate) fn connect(
+        route: &SocksRoute,
+        target: SocketAddr,
+        timeout: Duration,
+    ) -> io::Result<Self> {
+        let deadline = Instant::now() + timeout;
+        let proxy = (route.host.as_str(), route.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::other(format!("proxy {} did not resolve", route.host)))?;
+        let mut control = TcpStream::connect_timeout(&proxy, timeout)?;
+        control.set_nodelay(true)?;
+        keepalive(&control);
+        let left = || {
+            deadline
+                .checked_duration_since(Instant::now())
+                .filter(|left| !left.is_zero())
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::TimedOut, "socks5 handshake timed out")
+                })
+        };
+        control.set_read_timeout(Some(left()?))?;
+
+        let methods: &[u8] = match route.auth {
+            Some(_) => &[NO_AUTH, USER_PASS],
+            None => &[NO_AUTH],
+        };
+        let mut greeting = vec![VERSION, methods.len() as u8];
+        greeting.extend_from_slice(methods);
+        control.write_all(&greeting)?;
+        let mut choice = [0u8; 2];
+        control.read_exact(&mut choice)?;
+        match (choice, &route.auth) {
+            ([VERSION, NO_AUTH], _) => {}
+            ([VERSION, USER_PASS], Some((user, pass))) => {
+                let mut request = vec![1, user.len() as u8];
+                request.extend_from_slice(user.as_bytes());
+                request.push(pass.len() as u8);
+                request.extend_from_slice(pass.as_bytes());
+                control.write_all(&request)?;
+                let mut status = [0u8; 2];
+                control.set_read_timeout(Some(left()?))?;
+                control.read_exact(&mut status)?;
+                if status[1] != 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "socks5 proxy refused the username and password",
+                    ));
+                }
+            }
+            ([VERSION, NO_ACCEPTABLE], _) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "socks5 proxy accepts none of the offered auth methods",
+                ))
+            }
+            (reply, _) => {
+                return Err(io::Error::other(format!(
+                    "socks5 proxy chose an unexpected method {reply:?}"
+                )))
+            }
+        }
+
+        // The relay is told the address datagrams will come from; 0.0.0.0:0
+        // lets it take whatever arrives first, which NAT would rewrite anyway.
+        let unspecified = unspecified_like(proxy.is_ipv6());
+        let mut request = vec![VERSION, UDP_ASSOCIATE, 0];
+        put_addr(&mut request, unspecified);
+        control.write_all(&request)?;
+        control.set_read_timeout(Some(left()?))?;
+        let mut reply = [0u8; 3];
+        control.read_exact(&mut reply)?;
+        if reply[1] != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!(
+                    "socks5 proxy refused UDP ASSOCIATE: {}",
+                    reply_text(reply[1])
+                ),
+            ));
+        }
+        let mut relay = read_addr(&mut control)?;
+        // A relay on the unspecified address is on the proxy's own host.
+        if relay.ip().is_unspecified() {
+            relay.set_ip(proxy.ip());
+        }
+
+        let udp = UdpSocket::bind(unspecified_like(relay.is_ipv6()))?;
+        udp.connect(relay)?;
+        udp.set_nonblocking(true)?;
+        control.set_nonblocking(true)?;
+        let local = udp.local_addr()?;
+        let mut header = vec![0, 0, 0];
+        put_addr(&mut header, target);
+        log::info!("socks5 association to {target} open via relay {relay}");
+        Ok(Self {
+            control,
+            udp,
+            target,
+            local,
+            header,
+            closed: Cell::new(false),
+        })
+    }
+
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.local
+    }
+
+    /// Bytes each datagram gains on its way to the relay.
+    pub(crate) fn overhead(&self) -> usize {
+        self.header.len()
+    }
+
+    /// Relays one datagram; a full socket buffer drops it.
+    pub(crate) fn send(&self, data: &[u8]) {
+        if self.closed.get() {
+            return;
+        }
+        let mut framed = Vec::with_capacity(self.header.len() + data.len());
+        framed.extend_from_slice(&self.header);
+        framed.extend_from_slice(data);
+        if let Err(err) = self.udp.send(&framed) {
+            if err.kind() != io::ErrorKind::WouldBlock {
+                log::debug!("socks5 relay send failed: {err}");
+            }
+        }
+    }
+
+    /// Hands up to `max` datagrams from the server to `each`; fragments
+    /// and datagrams from anyone else are dropped.
+    pub(crate) fn recv(&self, max: usize, each: &mut dyn FnMut(usize, &[u8], SocketAddr)) -> usize {
+        self.watch_control();
+        let mut buf = [0u8; 65_535];
+        let mut received = 0;
+        while received < max {
+            let len = match self.udp.recv(&mut buf) {
+                Ok(len) => len,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    log::debug!("socks5 relay recv failed: {err}");
+                    break;
+                }
+            };
+            match unwrap(&buf[..len]) {
+                Some((from, payload)) if from == self.target => {
+                    each(received, payload, self.target);
+                    received += 1;
+                }
+                _ => log::debug!("socks5 relay sent {len} bytes not from the server"),
+            }
+        }
+        received
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) fn push_fds(&self, fds: &mut Vec<std::os::fd::RawFd>) {
+        fds.push(std::os::fd::AsRawFd::as_raw_fd(&self.udp));
+    }
+
+    /// Notes the proxy closing the control connection, which ends the
+    /// association; the connection then times out.
+    fn watch_control(&self) {
+        if self.closed.get() {
+            return;
+        }
+        let mut byte = [0u8; 1];
+        let gone = match (&self.control).read(&mut byte) {
+            Ok(0) => Some("closed by the proxy".to_string()),
+            Ok(_) => None,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => None,
+            Err(err) => Some(err.to_string()),
+        };
+        if let Some(why) = gone {
+            log::warn!(
+                "socks5 association to {} lost: control connection {why}",
+                self.target
+            );
+            self.closed.set(true);
+        }
+    }
+}
+
+fn unspecified_like(v6: bool) -> SocketAddr {
+    if v6 {
+        SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+    } else {
+        SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+    }
+}
+
+fn put_addr(out: &mut Vec<u8>, addr: SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            out.push(ATYP_V4);
+            out.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            out.push(ATYP_V6);
+            out.extend_from_slice(&ip.octets());
+        }
+    }
+    out.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+/// Reads the address of a reply; a domain name is resolved.
+fn read_addr(stream: &mut TcpStream) -> io::Result<SocketAddr> {
+    let mut atyp = [0u8; 1];
+    stream.read_exact(&mut atyp)?;
+    let ip = match atyp[0] {
+        ATYP_V4 => {
+            let mut octets = [0u8; 4];
+            stream.read_exact(&mut octets)?;
+            IpAddr::from(octets)
+        }
+        ATYP_V6 => {
+            let mut octets = [0u8; 16];
+            stream.read_exact(&mut octets)?;
+            IpAddr::from(octets)
+        }
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            let mut name = vec![0u8; usize::from(len[0])];
+            stream.read_exact(&mut name)?;
+            let mut port = [0u8; 2];
+            stream.read_exact(&mut port)?;
+            let name = String::from_utf8_lossy(&name).into_owned();
+            return (name.as_str(), u16::from_be_bytes(port))
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| io::Error::other(format!("socks5 relay {name} did not resolve")));
+        }
+        other => {
+            return Err(io::Error::other(format!(
+                "socks5 reply has address type {other}"
+            )));
+        }
+    };
+    let mut port = [0u8; 2];
+    stream.read_exact(&mut port)?;
+    Ok(SocketAddr::new(ip, u16::from_be_bytes(port)))
+}
+
+/// The source and payload of a relayed datagram; `None` for a fragment
+/// or a malformed header.
+fn unwrap(data: &[u8]) -> Option<(SocketAddr, &[u8])> {
+    let (&[0, 0, frag, atyp], rest) = data.split_first_chunk::<4>()? else {
+        return None;
+    };
+    if frag != 0 {
+        return None;
+    }
+    let (ip, rest) = match atyp {
+        ATYP_V4 => {
+            let (octets, rest) = rest.split_first_chunk::<4>()?;
+            (IpAddr::from(*octets), rest)
+        }
+        ATYP_V6 => {
+            let (octets, rest) = rest.split_first_chunk::<16>()?;
+            (IpAddr::from(*octets), rest)
+        }
+        _ => return None,
+    };
+    let (port, payload) = rest.split_first_chunk::<2>()?;
+    Some((SocketAddr::new(ip, u16::from_be_bytes(*port)), payload))
+}
+
+fn reply_text(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+/// Keeps gateways from dropping an idle control connection.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn keepalive(stream: &TcpStream) {
+    use crate::socket::set_int_opt;
+    use std::os::fd::AsRawFd;
+
+    /// Idle time before the first probe.
+    const KEEPALIVE_IDLE: Duration = Duration::from_secs(30);
+    const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+    let fd = stream.as_raw_fd();
+    let ok = set_int_opt(fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1)
+        && set_int_opt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_KEEPIDLE,
+            KEEPALIVE_IDLE.as_secs() as libc::c_int,
+        )
+        && set_int_opt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_KEEPINTVL,
+            KEEPALIVE_INTERVAL.as_secs() as libc::c_int,
+        );
+    if !ok {
+        log::warn!(
+            "socks5 control keepalive unavailable: {}",
+            io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn keepalive(_stream: &TcpStream) {
+    log::warn!("socks5 control keepalive is not supported on this platform");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn socks_urls_and_auth_parse() {
+        let route = SocksRoute::parse("socks5://10.0.0.1", "").unwrap();
+        assert_eq!(
+            (route.host.as_str(), route.port, route.auth),
+            ("10.0.0.1", 1080, None)
+        );
+        let route = SocksRoute::parse("socks5://[fd00::1]:9050/", "me:p:w").unwrap();
+        assert_eq!(route.host, "fd00::1");
+        assert_eq!(route.port, 9050);
+        assert_eq!(route.auth, Some(("me".into(), "p:w".into())));
+        assert!(SocksRoute::parse("socks5://gw", "nopass").is_err());
+        assert!(SocksRoute::parse("https://gw", "").is_err());
+    }
+
+    #[test]
+    fn associates_and_relays_with_password_auth() {
+        let control = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = control.local_addr().unwrap();
+        let relay = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let relay_addr = relay.local_addr().unwrap();
+        let server: SocketAddr = "192.0.2.7:4433".parse().unwrap();
+        let gateway = thread::spawn(move || {
+            let (mut stream, _) = control.accept().unwrap();
+            let mut greeting = [0u8; 4];
+            stream.read_exact(&mut greeting).unwrap();
+            assert_eq!(greeting, [VERSION, 2, NO_AUTH, USER_PASS]);
+            stream.write_all(&[VERSION, USER_PASS]).unwrap();
+            let mut login = [0u8; 9];
+            stream.read_exact(&mut login).unwrap();
+            assert_eq!(&login, b"\x01\x02me\x04pass");
+            stream.write_all(&[1, 0]).unwrap();
+            let mut request = [0u8; 10];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(&request[..4], &[VERSION, UDP_ASSOCIATE, 0, ATYP_V4]);
+            let mut reply = vec![VERSION, 0, 0];
+            put_addr(&mut reply, relay_addr);
+            stream.write_all(&reply).unwrap();
+
+            let mut buf = [0u8; 64];
+            let (len, client) = relay.recv_from(&mut buf).unwrap();
+            assert_eq!(unwrap(&buf[..len]), Some((server, &b"ping"[..])));
+            let mut back = vec![0, 0, 0];
+            put_addr(&mut back, server);
+            back.extend_from_slice(b"pong");
+            relay.send_to(&back, client).unwrap();
+            stream
+        });
+
+        let route = SocksRoute::parse(&format!("socks5://{proxy}"), "me:pass").unwrap();
+        let tunnel = SocksTunnel::connect(&route, server, CONNECT_TIMEOUT).unwrap();
+        assert_eq!(tunnel.overhead(), 10);
+        tunnel.send(b"ping");
+        let control = gateway.join().unwrap();
+        let mut got = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while got.is_empty() && Instant::now() < deadline {
+            tunnel.recv(4, &mut |_, data, from| got.push((from, data.to_vec())));
+        }
+        assert_eq!(got, vec![(server, b"pong".to_vec())]);
+
+        drop(control);
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !tunnel.closed.get() && Instant::now() < deadline {
+            tunnel.recv(4, &mut |_, _, _| {});
+        }
+        assert!(
+            tunnel.closed.get(),
+            "a closed control connection ends the association"
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Named worker threads and their scheduling priority.
+
+use std::io;
+use std::panic;
+use std::sync::{mpsc, Arc, Mutex, Once, PoisonError};
+use std::thread;
+
+use super::Error;
+
+/// OS thread ids of the threads driving a handle.
+pub(crate) type WorkerThreads = Arc<Mutex<Vec<i32>>>;
+
+/// Scheduling classes accepted by `cc_quic_set_thread_priority`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum ThreadPriority {
+    Normal,
+    /// Nice -16, Android's `THREAD_PRIORITY_AUDIO`.
+    Audio,
+    /// `SCHED_FIFO`; usually needs `CAP_SYS_NICE` or an RT-enabled rlimit.
+    Realtime,
+}
+
+impl ThreadPriority {
+    pub(crate) fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(Self::Normal),
+            1 => Some(Self::Audio),
+            2 => Some(Self::Realtime),
+            _ => None,
+        }
+    }
+}
+
+/// Routes panic reports through `log` (stderr is invisible on Android) while
+/// keeping the previous hook's output.
+fn install_panic_hook() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let current = thread::current();
+            log::error!("panic in {}: {info}", current.name().unwrap_or("<unnamed>"));
+            previous(info);
+        }));
+    });
+}
+
+/// Spawns a named thread and records its OS thread id in `threads` before
+/// returning, so a priority request right after start finds it.
+pub(crate) fn spawn_worker<F>(name: String, threads: &WorkerThreads, body: F) -> io::Result<()>
+where
+    F: FnOnce() + Send + 'static,
+{
+    install_panic_hook();
+    let (tid_tx, tid_rx) = mpsc::channel();
+    thread::Builder::new().name(name).spawn(move || {
+        let _ = tid_tx.send(current_tid());
+        body();
+    })?;
+    if let Ok(tid) = tid_rx.recv() {
+        threads
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(tid);
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn current_tid() -> i32 {
+    unsafe { libc::syscall(libc::SYS_gettid) as i32 }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn current_tid() -> i32 {
+    0
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn set_priority(tid: i32, priority: ThreadPriority) -> Result<(), Error> {
+    const AUDIO_NICE: libc::c_int = -16;
+    const REALTIME_PRIORITY: libc::c_int = 2;
+
+    let (policy, sched_priority, nice) = match priority {
+        ThreadPriority::Normal => (libc::SCHED_OTHER, 0, 0),
+        ThreadPriority::Audio => (libc::SCHED_OTHER, 0, AUDIO_NICE),
+        ThreadPriority::Realtime => (libc::SCHED_FIFO, REALTIME_PRIORITY, 0),
+    };
+    let param = libc::sched_param { sched_priority };
+    if unsafe { libc::sched_setscheduler(tid, policy, &param) } != 0 {
+        return Err(os_error(tid));
+    }
+    if policy == libc::SCHED_OTHER
+        && unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, nice) } != 0
+    {
+        return Err(os_error(tid));
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn set_priority(_tid: i32, _priority: ThreadPriority) -> Result<(), Error> {
+    Err(Error::Unsupported(
+        "thread priorities need Linux or Android".into(),
+    ))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn os_error(tid: i32) -> Error {
+    let err = io::Error::last_os_error();
+    log::warn!("thread priority change failed: {err}");
+    let message = format!("thread {tid} priority: {err}");
+    match err.raw_os_error() {
+        Some(libc::EPERM) | Some(libc::EACCES) => Error::PermissionDenied(message),
+        _ => Error::Internal(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_named_worker_tid() {
+        let threads = WorkerThreads::default();
+        let (name_tx, name_rx) = mpsc::channel();
+        spawn_worker("cc-quic-test".to_string(), &threads, move || {
+            let _ = name_tx.send(thread::current().name().map(str::to_string));
+        })
+        .unwrap();
+        assert_eq!(name_rx.recv().unwrap().as_deref(), Some("cc-quic-test"));
+        assert_eq!(threads.lock().unwrap().len(), 1);
+        assert_eq!(ThreadPriority::from_code(3), None);
+    }
+}
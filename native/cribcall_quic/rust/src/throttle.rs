@@ -0,0 +1,235 @@
+//! Byte-rate budgets: the per-connection send cap applied in the worker's
+//! send path on top of quiche's pacing, and the dual-channel media pacer;
+//! also the per-class event budgets of `eventlimit.rs`, counted in events.
+//! `Pacer` holds packets until the release time quiche's pacing gave them.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Send-cap burst, as time at the configured rate.
+const SEND_BURST: Duration = Duration::from_millis(10);
+
+/// How early a paced packet may leave; the workers wake about this finely.
+const PACING_SLACK: Duration = Duration::from_millis(1);
+
+/// A connection's overrides for how its send cap paces; the defaults keep
+/// the cap as it is.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct SendPacing {
+    /// Suspends the cap, which keeps its rate for when pacing is back on.
+    pub off: bool,
+    /// Smallest burst in bytes; 0 for one full packet.
+    pub quantum: usize,
+    /// Largest burst in bytes, at least `quantum`; 0 for `SEND_BURST` at
+    /// the rate.
+    pub max_burst: usize,
+}
+
+impl SendPacing {
+    /// The send cap for `max_bps` under these overrides, if any applies.
+    pub(crate) fn cap(&self, max_bps: Option<u64>) -> Option<TokenBucket> {
+        let max_bps = max_bps.filter(|_| !self.off)?;
+        let quantum = match self.quantum {
+            0 => crate::MAX_DATAGRAM_SIZE,
+            quantum => quantum,
+        };
+        let mut cap = TokenBucket::new(max_bps, SEND_BURST, quantum);
+        if self.max_burst > 0 {
+            cap.burst = self.max_burst as f64;
+            cap.tokens = cap.burst;
+        }
+        Some(cap)
+    }
+}
+
+pub(crate) struct TokenBucket {
+    /// Bytes per second.
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled: Option<Instant>,
+}
+
+impl TokenBucket {
+    /// Starts full, allowing `burst` of time at `rate_bps` but never less
+    /// than `min_burst` bytes.
+    pub(crate) fn new(rate_bps: u64, burst: Duration, min_burst: usize) -> Self {
+        let rate = rate_bps as f64 / 8.0;
+        let burst = (rate * burst.as_secs_f64()).max(min_burst as f64);
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            refilled: None,
+        }
+    }
+
+    /// `per_sec` events a second, with as many again as burst.
+    pub(crate) fn events(per_sec: u32) -> Self {
+        let rate = f64::from(per_sec);
+        Self {
+            rate,
+            burst: rate,
+            tokens: rate,
+            refilled: None,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if let Some(at) = self.refilled {
+            let elapsed = now.saturating_duration_since(at).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        }
+        self.refilled = Some(now);
+    }
+
+    /// Takes `len` bytes if the budget holds them.
+    pub(crate) fn take(&mut self, len: usize, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens < len as f64 {
+            return false;
+        }
+        self.tokens -= len as f64;
+        true
+    }
+
+    /// Whether anything may go out now. Packets are sized only once built,
+    /// so the budget may then overdraw by one packet via `consume`.
+    pub(crate) fn ready(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens > 0.0
+    }
+
+    pub(crate) fn consume(&mut self, len: usize) {
+        self.tokens -= len as f64;
+    }
+}
+
+/// Packets quiche built ahead of their release time (`SendInfo::at`). The
+/// sockets have no `SO_TXTIME`, so a connection holds the first such packet
+/// per local address and builds no more there until it is due.
+#[derive(Default)]
+pub(crate) struct Pacer {
+    held: Vec<Held>,
+}
+
+struct Held {
+    /// The drain's local address filter, `None` for every path.
+    from: Option<SocketAddr>,
+    to: SocketAddr,
+    at: Instant,
+    data: Vec<u8>,
+}
+
+impl Pacer {
+    /// Whether a packet released at `at` must wait past `now`.
+    pub(crate) fn early(at: Instant, now: Instant) -> bool {
+        at > now + PACING_SLACK
+    }
+
+    pub(crate) fn hold(
+        &mut self,
+        from: Option<SocketAddr>,
+        to: SocketAddr,
+        at: Instant,
+        data: &[u8],
+    ) {
+        self.held.push(Held {
+            from,
+            to,
+            at,
+            data: data.to_vec(),
+        });
+    }
+
+    /// Takes the packet held for `from` once it is due.
+    pub(crate) fn take_due(
+        &mut self,
+        from: Option<SocketAddr>,
+        now: Instant,
+    ) -> Option<(Vec<u8>, SocketAddr)> {
+        let index = self
+            .held
+            .iter()
+            .position(|held| held.from == from && !Self::early(held.at, now))?;
+        let held = self.held.swap_remove(index);
+        Some((held.data, held.to))
+    }
+
+    /// Whether a packet for `from` is still waiting for its release time.
+    pub(crate) fn waiting(&self, from: Option<SocketAddr>) -> bool {
+        self.held.iter().any(|held| held.from == from)
+    }
+
+    /// Time until the next held packet is due, for the worker's wait.
+    pub(crate) fn next_release(&self, now: Instant) -> Option<Duration> {
+        self.held
+            .iter()
+            .map(|held| held.at.saturating_duration_since(now))
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overdrawn_send_cap_waits_for_refill() {
+        // 1 Mbit/s: 125 bytes per millisecond, 1350-byte floor on the burst.
+        let mut cap = SendPacing::default().cap(Some(1_000_000)).unwrap();
+        let start = Instant::now();
+        assert!(cap.ready(start));
+        cap.consume(1350);
+        cap.consume(1350);
+        assert!(!cap.ready(start + Duration::from_millis(10)));
+        assert!(cap.ready(start + Duration::from_millis(12)));
+    }
+
+    #[test]
+    fn pacing_overrides_reshape_or_suspend_the_cap() {
+        let burst = |pacing: SendPacing| pacing.cap(Some(1_000_000)).map(|cap| cap.burst);
+        assert_eq!(burst(SendPacing::default()), Some(1350.0));
+        let small = SendPacing {
+            quantum: 200,
+            ..SendPacing::default()
+        };
+        // 10 ms at 1 Mbit/s is 1250 bytes, above the smaller floor.
+        assert_eq!(burst(small), Some(1250.0));
+        let deep = SendPacing {
+            max_burst: 64 * 1024,
+            ..SendPacing::default()
+        };
+        assert_eq!(burst(deep), Some(65536.0));
+        let off = SendPacing {
+            off: true,
+            ..SendPacing::default()
+        };
+        assert!(off.cap(Some(1_000_000)).is_none());
+        assert!(SendPacing::default().cap(None).is_none());
+    }
+
+    #[test]
+    fn paced_packets_wait_for_their_release_time() {
+        let start = Instant::now();
+        let local: SocketAddr = "127.0.0.1:4433".parse().unwrap();
+        let peer: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let mut pacer = Pacer::default();
+        assert!(!Pacer::early(start, start));
+        assert!(Pacer::early(start + Duration::from_millis(3), start));
+        pacer.hold(Some(local), peer, start + Duration::from_millis(3), b"late");
+        assert!(pacer.waiting(Some(local)));
+        assert!(!pacer.waiting(None));
+        assert_eq!(pacer.next_release(start), Some(Duration::from_millis(3)));
+        assert_eq!(pacer.take_due(Some(local), start), None);
+        // Within the slack of its release time the packet may leave.
+        let due = start + Duration::from_micros(2500);
+        assert_eq!(pacer.take_due(None, due), None);
+        assert_eq!(
+            pacer.take_due(Some(local), due),
+            Some((b"late".to_vec(), peer))
+        );
+        assert!(!pacer.waiting(Some(local)));
+        assert_eq!(pacer.next_release(due), None);
+    }
+}
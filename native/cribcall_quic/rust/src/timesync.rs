@@ -0,0 +1,205 @@
+//! NTP-style clock offset estimation between the two ends of a connection.
+//! Probes run on a dedicated bidirectional stream (client- or
+//! server-initiated id 4/5) so app framing on the control stream is
+//! untouched; the responder echoes on whichever stream a probe came in on.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const CLIENT_STREAM: u64 = 4;
+const SERVER_STREAM: u64 = 5;
+const RECORD_LEN: usize = 25;
+const PROBE: u8 = 1;
+const REPLY: u8 = 2;
+/// Samples kept for the clock filter.
+const FILTER_LEN: usize = 8;
+
+pub(crate) fn is_timesync_stream(stream_id: u64) -> bool {
+    stream_id == CLIENT_STREAM || stream_id == SERVER_STREAM
+}
+
+fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or_default()
+}
+
+fn record(kind: u8, t1: u64, t2: u64, t3: u64) -> [u8; RECORD_LEN] {
+    let mut out = [0u8; RECORD_LEN];
+    out[0] = kind;
+    out[1..9].copy_from_slice(&t1.to_be_bytes());
+    out[9..17].copy_from_slice(&t2.to_be_bytes());
+    out[17..25].copy_from_slice(&t3.to_be_bytes());
+    out
+}
+
+fn field(rec: &[u8], at: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&rec[at..at + 8]);
+    u64::from_be_bytes(bytes)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Sample {
+    /// Peer clock minus local clock.
+    offset_us: i64,
+    rtt_us: u64,
+}
+
+/// Filtered estimate after each completed probe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Estimate {
+    pub offset_us: i64,
+    pub rtt_us: u64,
+    /// Spread of recent offsets around the lowest-delay sample.
+    pub dispersion_us: u64,
+    pub samples: usize,
+}
+
+pub(crate) struct TimeSync {
+    stream_id: u64,
+    interval: Option<Duration>,
+    next_probe: Instant,
+    partial: HashMap<u64, Vec<u8>>,
+    samples: VecDeque<Sample>,
+}
+
+impl TimeSync {
+    pub(crate) fn new(is_server: bool) -> Self {
+        Self {
+            stream_id: if is_server {
+                SERVER_STREAM
+            } else {
+                CLIENT_STREAM
+            },
+            interval: None,
+            next_probe: Instant::now(),
+            partial: HashMap::new(),
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Probes every `interval` from now on; `None` stops probing (replies to
+    /// the peer's probes continue either way).
+    pub(crate) fn set_interval(&mut self, interval: Option<Duration>) {
+        self.interval = interval;
+        self.next_probe = Instant::now();
+    }
+
+    /// Sends a probe when one is due.
+    pub(crate) fn on_timer(&mut self, conn: &mut quiche::Connection, now: Instant) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        if now < self.next_probe || !conn.is_established() {
+            return;
+        }
+        self.next_probe = now + interval;
+        let probe = record(PROBE, now_us(), 0, 0);
+        if let Err(err) = conn.stream_send(self.stream_id, &probe, false) {
+            log::debug!("timesync probe not sent: {err:?}");
+        }
+    }
+
+    /// Feeds bytes read from a timesync stream: answers probes and returns
+    /// an estimate for each reply.
+    pub(crate) fn on_data(
+        &mut self,
+        conn: &mut quiche::Connection,
+        stream_id: u64,
+        data: &[u8],
+        out: &mut Vec<Estimate>,
+    ) {
+        let received_us = now_us();
+        let mut buf = self.partial.remove(&stream_id).unwrap_or_default();
+        buf.extend_from_slice(data);
+        let mut records = buf.chunks_exact(RECORD_LEN);
+        for rec in &mut records {
+            match rec[0] {
+                PROBE => {
+                    let reply = record(REPLY, field(rec, 1), received_us, now_us());
+                    let _ = conn.stream_send(stream_id, &reply, false);
+                }
+                REPLY => {
+                    let sample = sample(field(rec, 1), field(rec, 9), field(rec, 17), received_us);
+                    out.push(self.add(sample));
+                }
+                _ => {}
+            }
+        }
+        let rest = records.remainder().to_vec();
+        if !rest.is_empty() {
+            self.partial.insert(stream_id, rest);
+        }
+    }
+
+    fn add(&mut self, sample: Sample) -> Estimate {
+        if self.samples.len() == FILTER_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+        let best = self
+            .samples
+            .iter()
+            .min_by_key(|s| s.rtt_us)
+            .copied()
+            .unwrap_or(sample);
+        let dispersion_us = self
+            .samples
+            .iter()
+            .map(|s| s.offset_us.abs_diff(best.offset_us))
+            .max()
+            .unwrap_or(0);
+        Estimate {
+            offset_us: best.offset_us,
+            rtt_us: best.rtt_us,
+            dispersion_us,
+            samples: self.samples.len(),
+        }
+    }
+}
+
+/// Standard four-timestamp offset/delay: t1 sent, t2 peer received, t3 peer
+/// replied, t4 reply received.
+fn sample(t1: u64, t2: u64, t3: u64, t4: u64) -> Sample {
+    let (t1, t2, t3, t4) = (t1 as i64, t2 as i64, t3 as i64, t4 as i64);
+    Sample {
+        offset_us: ((t2 - t1) + (t3 - t4)) / 2,
+        rtt_us: ((t4 - t1) - (t3 - t2)).max(0) as u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn four_timestamps_give_offset_and_delay() {
+        // Peer is 500 us ahead; 100 us each way; 20 us turnaround.
+        let s = sample(1_000, 1_600, 1_620, 1_220);
+        assert_eq!(
+            s,
+            Sample {
+                offset_us: 500,
+                rtt_us: 200
+            }
+        );
+    }
+
+    #[test]
+    fn filter_prefers_lowest_delay_sample() {
+        let mut sync = TimeSync::new(false);
+        sync.add(Sample {
+            offset_us: 900,
+            rtt_us: 5_000,
+        });
+        let estimate = sync.add(Sample {
+            offset_us: 500,
+            rtt_us: 200,
+        });
+        assert_eq!(estimate.offset_us, 500);
+        assert_eq!(estimate.dispersion_us, 400);
+        assert_eq!(estimate.samples, 2);
+    }
+}
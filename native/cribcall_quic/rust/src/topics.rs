@@ -0,0 +1,243 @@
+//! Publish/subscribe topics. A client subscribes with
+//! `cc_quic_topic_subscribe` and the server fans `cc_quic_server_publish`
+//! out to every connection subscribed to the topic, across all workers, so
+//! the Dart server needs no distribution lists of its own.
+//!
+//! Each connection's subscriptions live with it in its worker; a publish is
+//! broadcast to the workers and each one queues it on its own subscribers.
+//! Two unidirectional streams carry the records:
+//!
+//! - client id 6: `op (1 subscribe, 2 unsubscribe) | len u8 | topic`
+//! - server id 11: `len u8 | topic | data length u32 BE | data`
+
+use std::collections::HashSet;
+
+const CLIENT_STREAM: u64 = 6;
+const SERVER_STREAM: u64 = 11;
+const SUBSCRIBE: u8 = 1;
+const UNSUBSCRIBE: u8 = 2;
+/// Longest topic the one-byte length prefix can carry.
+pub(crate) const MAX_TOPIC_LEN: usize = 255;
+/// Publications a slow subscriber may have queued before new ones are
+/// dropped for it.
+const MAX_BACKLOG: usize = 4 * 1024 * 1024;
+
+pub(crate) fn is_topic_stream(stream_id: u64) -> bool {
+    stream_id == CLIENT_STREAM || stream_id == SERVER_STREAM
+}
+
+/// One publication as the client receives it.
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct Publication {
+    pub topic: String,
+    pub data: Vec<u8>,
+}
+
+/// Records waiting for stream credit; kept whole so a short write never
+/// splits the framing.
+#[derive(Default)]
+struct Outbox {
+    pending: Vec<u8>,
+}
+
+impl Outbox {
+    /// Writes what the stream takes; returns the bytes written.
+    fn flush(&mut self, conn: &mut quiche::Connection, stream_id: u64) -> usize {
+        if self.pending.is_empty() {
+            return 0;
+        }
+        match conn.stream_send(stream_id, &self.pending, false) {
+            Ok(written) => {
+                self.pending.drain(..written);
+                written
+            }
+            Err(quiche::Error::Done) => 0,
+            Err(err) => {
+                log::debug!("topic stream {stream_id} send error: {err:?}");
+                0
+            }
+        }
+    }
+}
+
+/// Client side: queues subscription changes and reassembles publications.
+#[derive(Default)]
+pub(crate) struct Subscriber {
+    out: Outbox,
+    partial: Vec<u8>,
+    broken: bool,
+}
+
+impl Subscriber {
+    /// Queues a subscribe (or unsubscribe) of `topic`; sent by `flush` once
+    /// the connection is established.
+    pub(crate) fn change(&mut self, topic: &str, subscribe: bool) {
+        self.out
+            .pending
+            .push(if subscribe { SUBSCRIBE } else { UNSUBSCRIBE });
+        self.out.pending.push(topic.len() as u8);
+        self.out.pending.extend_from_slice(topic.as_bytes());
+    }
+
+    pub(crate) fn flush(&mut self, conn: &mut quiche::Connection) -> usize {
+        if !conn.is_established() {
+            return 0;
+        }
+        self.out.flush(conn, CLIENT_STREAM)
+    }
+
+    /// Feeds bytes read from the publication stream; pushes each complete
+    /// publication.
+    pub(crate) fn on_data(&mut self, data: &[u8], out: &mut Vec<Publication>) {
+        if self.broken {
+            return;
+        }
+        self.partial.extend_from_slice(data);
+        let mut used = 0;
+        while let Some(&len) = self.partial.get(used) {
+            let topic_end = used + 1 + usize::from(len);
+            let Some(header) = self.partial.get(topic_end..topic_end + 4) else {
+                break;
+            };
+            let data_len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+            let data_len = data_len as usize;
+            if data_len > MAX_BACKLOG {
+                self.broken = true;
+                self.partial.clear();
+                return;
+            }
+            let data_start = topic_end + 4;
+            let Some(body) = self.partial.get(data_start..data_start + data_len) else {
+                break;
+            };
+            out.push(Publication {
+                topic: String::from_utf8_lossy(&self.partial[used + 1..topic_end]).into_owned(),
+                data: body.to_vec(),
+            });
+            used = data_start + data_len;
+        }
+        self.partial.drain(..used);
+    }
+}
+
+/// Server side: one connection's subscriptions and queued publications.
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    topics: HashSet<String>,
+    out: Outbox,
+    partial: Vec<u8>,
+    broken: bool,
+}
+
+impl Subscriptions {
+    /// Feeds bytes read from the subscription stream; pushes each change
+    /// to the set as `(topic, subscribed)`.
+    pub(crate) fn on_data(&mut self, data: &[u8], changes: &mut Vec<(String, bool)>) {
+        if self.broken {
+            return;
+        }
+        self.partial.extend_from_slice(data);
+        let mut used = 0;
+        while let Some(rest) = self.partial.get(used..) {
+            let (op, len) = match rest {
+                [op @ (SUBSCRIBE | UNSUBSCRIBE), len, ..] => (*op, usize::from(*len)),
+                [] | [SUBSCRIBE | UNSUBSCRIBE] => break,
+                _ => {
+                    self.broken = true;
+                    self.partial.clear();
+                    return;
+                }
+            };
+            let Some(topic) = rest.get(2..2 + len) else {
+                break;
+            };
+            let topic = String::from_utf8_lossy(topic).into_owned();
+            let changed = if op == SUBSCRIBE {
+                self.topics.insert(topic.clone())
+            } else {
+                self.topics.remove(&topic)
+            };
+            if changed {
+                changes.push((topic, op == SUBSCRIBE));
+            }
+            used += 2 + len;
+        }
+        self.partial.drain(..used);
+    }
+
+    pub(crate) fn subscribed(&self, topic: &str) -> bool {
+        self.topics.contains(topic)
+    }
+
+    /// Queues `data` for this subscriber; false if its backlog is full and
+    /// the publication was dropped.
+    pub(crate) fn publish(&mut self, topic: &str, data: &[u8]) -> bool {
+        let record_len = 1 + topic.len() + 4 + data.len();
+        if self.out.pending.len() + record_len > MAX_BACKLOG {
+            return false;
+        }
+        self.out.pending.push(topic.len() as u8);
+        self.out.pending.extend_from_slice(topic.as_bytes());
+        self.out
+            .pending
+            .extend_from_slice(&(data.len() as u32).to_be_bytes());
+        self.out.pending.extend_from_slice(data);
+        true
+    }
+
+    pub(crate) fn flush(&mut self, conn: &mut quiche::Connection) -> usize {
+        self.out.flush(conn, SERVER_STREAM)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_subscriptions_and_reassembles_publications() {
+        let mut subscriber = Subscriber::default();
+        subscriber.change("alerts", true);
+        subscriber.change("alerts", true);
+        subscriber.change("status", true);
+        subscriber.change("alerts", false);
+        let wire = std::mem::take(&mut subscriber.out.pending);
+
+        let mut subs = Subscriptions::default();
+        let mut changes = Vec::new();
+        subs.on_data(&wire[..5], &mut changes);
+        subs.on_data(&wire[5..], &mut changes);
+        assert_eq!(
+            changes,
+            [
+                ("alerts".to_string(), true),
+                ("status".to_string(), true),
+                ("alerts".to_string(), false),
+            ]
+        );
+        assert!(subs.subscribed("status"));
+        assert!(!subs.subscribed("alerts"));
+
+        assert!(subs.publish("status", b"crying"));
+        assert!(subs.publish("status", b""));
+        let wire = std::mem::take(&mut subs.out.pending);
+        let mut out = Vec::new();
+        subscriber.on_data(&wire[..9], &mut out);
+        assert!(out.is_empty());
+        subscriber.on_data(&wire[9..], &mut out);
+        assert_eq!(
+            out,
+            [
+                Publication {
+                    topic: "status".into(),
+                    data: b"crying".to_vec(),
+                },
+                Publication {
+                    topic: "status".into(),
+                    data: Vec::new(),
+                },
+            ]
+        );
+        assert!(!subs.publish("status", &vec![0; MAX_BACKLOG]));
+    }
+}
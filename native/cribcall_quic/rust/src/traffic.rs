@@ -0,0 +1,101 @@
+//! Per-class byte accounting for stats events, so adaptive logic can tell
+//! which kind of traffic is filling the path: `control` is the control
+//! stream, `media` the DATAGRAM flow (frames, FEC parity and redundant
+//! copies), `transfer` every other application stream. Counts are payload
+//! bytes handed to or read from quiche; what `sent_bytes`/`recv_bytes` show
+//! beyond their sum is QUIC framing, retransmission and the library's own
+//! timesync and identity streams.
+
+use serde::Serialize;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum TrafficClass {
+    Control,
+    Transfer,
+}
+
+impl TrafficClass {
+    /// Class of an application stream (never a timesync or identity one).
+    pub(crate) fn of_stream(stream_id: u64, control_stream_id: u64) -> Self {
+        if stream_id == control_stream_id {
+            Self::Control
+        } else {
+            Self::Transfer
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub(crate) struct ClassBytes {
+    pub sent_bytes: u64,
+    pub recv_bytes: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub(crate) struct TrafficStats {
+    pub control: ClassBytes,
+    pub media: ClassBytes,
+    pub transfer: ClassBytes,
+}
+
+/// Stream bytes by class. Media is counted where the datagrams are built
+/// and drained, and joined in by [`TrafficMeter::stats`].
+#[derive(Default)]
+pub(crate) struct TrafficMeter {
+    control: ClassBytes,
+    transfer: ClassBytes,
+}
+
+impl TrafficMeter {
+    fn class(&mut self, class: TrafficClass) -> &mut ClassBytes {
+        match class {
+            TrafficClass::Control => &mut self.control,
+            TrafficClass::Transfer => &mut self.transfer,
+        }
+    }
+
+    pub(crate) fn sent(&mut self, class: TrafficClass, bytes: usize) {
+        self.class(class).sent_bytes += bytes as u64;
+    }
+
+    pub(crate) fn received(&mut self, class: TrafficClass, bytes: usize) {
+        self.class(class).recv_bytes += bytes as u64;
+    }
+
+    pub(crate) fn stats(&self, media_sent: u64, media_recv: u64) -> TrafficStats {
+        TrafficStats {
+            control: self.control,
+            media: ClassBytes {
+                sent_bytes: media_sent,
+                recv_bytes: media_recv,
+            },
+            transfer: self.transfer,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_stream_bytes_by_class() {
+        let mut meter = TrafficMeter::default();
+        meter.sent(TrafficClass::of_stream(0, 0), 100);
+        meter.received(TrafficClass::of_stream(0, 0), 40);
+        meter.sent(TrafficClass::of_stream(8, 0), 5000);
+        meter.received(TrafficClass::of_stream(5, 0), 7);
+
+        let stats = meter.stats(900, 1200);
+        assert_eq!(
+            stats.control,
+            ClassBytes {
+                sent_bytes: 100,
+                recv_bytes: 40
+            }
+        );
+        assert_eq!(stats.transfer.sent_bytes, 5000);
+        assert_eq!(stats.transfer.recv_bytes, 7);
+        assert_eq!(stats.media.recv_bytes, 1200);
+    }
+}
@@ -0,0 +1,142 @@
+//! In-memory datagram transport for integration tests. A pair of
+//! [`MemoryEnd`]s stands in for two UDP sockets: each end has a synthetic
+//! address and hands datagrams sent to its peer's address straight to the
+//! peer's queue, so a client and server handle run the real handshake and
+//! event pipeline with no socket or port behind them. Datagrams go nowhere
+//! else, and a full queue drops them like a full socket buffer would.
+
+use std::collections::VecDeque;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Datagrams one end holds before dropping new ones.
+const QUEUE_LIMIT: usize = 1024;
+/// Port shared by every server end; pairs never see each other.
+const SERVER_PORT: u16 = 4433;
+
+/// Documentation-only (TEST-NET-1) addresses, so a synthetic address in a
+/// log or event can never be mistaken for a real peer.
+const SERVER_IP: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+const CLIENT_IP: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 2);
+
+static NEXT_CLIENT_PORT: AtomicU16 = AtomicU16::new(40_000);
+
+#[derive(Default)]
+struct Queue {
+    datagrams: Mutex<VecDeque<Vec<u8>>>,
+    ready: Condvar,
+}
+
+impl Queue {
+    fn lock(&self) -> MutexGuard<'_, VecDeque<Vec<u8>>> {
+        self.datagrams
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub(crate) struct MemoryEnd {
+    local: SocketAddr,
+    peer: SocketAddr,
+    inbox: Arc<Queue>,
+    outbox: Arc<Queue>,
+}
+
+/// A connected server end and client end, in that order.
+pub(crate) fn pair() -> (MemoryEnd, MemoryEnd) {
+    let server = SocketAddr::from((SERVER_IP, SERVER_PORT));
+    let client = SocketAddr::from((CLIENT_IP, NEXT_CLIENT_PORT.fetch_add(1, Ordering::Relaxed)));
+    let (to_server, to_client) = (Arc::new(Queue::default()), Arc::new(Queue::default()));
+    (
+        MemoryEnd {
+            local: server,
+            peer: client,
+            inbox: Arc::clone(&to_server),
+            outbox: Arc::clone(&to_client),
+        },
+        MemoryEnd {
+            local: client,
+            peer: server,
+            inbox: to_client,
+            outbox: to_server,
+        },
+    )
+}
+
+impl MemoryEnd {
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.local
+    }
+
+    pub(crate) fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Queues `data` for the peer if it is addressed to it.
+    pub(crate) fn send(&self, data: &[u8], to: SocketAddr) {
+        if to != self.peer {
+            return;
+        }
+        let mut queue = self.outbox.lock();
+        if queue.len() < QUEUE_LIMIT {
+            queue.push_back(data.to_vec());
+            self.outbox.ready.notify_all();
+        }
+    }
+
+    /// Moves up to `max` pending datagrams into `each`; the count moved.
+    pub(crate) fn recv(&self, max: usize, mut each: impl FnMut(usize, &[u8])) -> usize {
+        let mut queue = self.inbox.lock();
+        let count = max.min(queue.len());
+        for (i, datagram) in queue.drain(..count).enumerate() {
+            each(i, &datagram);
+        }
+        count
+    }
+
+    /// Blocks until a datagram is pending or `timeout` has passed.
+    pub(crate) fn wait_readable(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.inbox.lock();
+        while queue.is_empty() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return;
+            }
+            queue = self
+                .inbox
+                .ready
+                .wait_timeout(queue, left)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pair_delivers_only_to_the_peer() {
+        let (server, client) = pair();
+        assert_eq!(client.peer_addr(), server.local_addr());
+        client.send(b"hello", server.local_addr());
+        client.send(b"lost", "127.0.0.1:4433".parse().unwrap());
+
+        let start = Instant::now();
+        server.wait_readable(Duration::from_secs(5));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        let mut seen = Vec::new();
+        assert_eq!(server.recv(8, |_, data| seen.push(data.to_vec())), 1);
+        assert_eq!(seen, vec![b"hello".to_vec()]);
+        assert_eq!(server.recv(8, |_, _| unreachable!()), 0);
+
+        for _ in 0..QUEUE_LIMIT + 1 {
+            server.send(&[0], client.local_addr());
+        }
+        assert_eq!(client.recv(usize::MAX, |_, _| {}), QUEUE_LIMIT);
+    }
+}
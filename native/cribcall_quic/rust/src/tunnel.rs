@@ -0,0 +1,362 @@
+//! QUIC over TCP for networks that drop UDP (`cc_quic_config_set_tcp_fallback`).
+//! A tunnel carries exactly the datagrams quiche would have sent over UDP,
+//! each behind its length as a big-endian `u16`, so TLS, pinning, the
+//! allowlist and the event stream are the same as on UDP; only the bytes
+//! travel in a TCP stream. TCP's retransmissions sit under QUIC's, so a
+//! lossy path does worse than it would over UDP, and a middlebox that
+//! insists on real TLS on its port still blocks the tunnel.
+//!
+//! A client dials one [`TunnelStream`]; a server's [`TunnelListener`]
+//! accepts any number of them and tells their datagrams apart by the
+//! stream's peer address, which stands in for the UDP source address.
+
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+
+/// How long a client waits to hear from the server over UDP when
+/// `cc_quic_config_set_tcp_fallback` is given 0.
+pub(crate) const DEFAULT_UDP_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long a client waits for the tunnel's TCP connect.
+pub(crate) const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Bytes queued for a peer that reads slowly; past this new datagrams are
+/// dropped, as a full socket buffer would, and QUIC's loss recovery resends.
+const WRITE_LIMIT: usize = 256 * 1024;
+/// Tunneled clients one listener carries at once.
+const MAX_STREAMS: usize = 256;
+const READ_CHUNK: usize = 16 * 1024;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Fallback {
+    /// TCP port the server listens on and the client dials.
+    pub port: u16,
+    /// Client: dial the tunnel once UDP has been silent this long.
+    pub udp_timeout: Duration,
+}
+
+/// One TCP stream and its partial frames.
+struct Framed {
+    stream: TcpStream,
+    peer: SocketAddr,
+    read: Vec<u8>,
+    /// Start of the first frame not yet handed out.
+    read_at: usize,
+    write: Vec<u8>,
+    /// The peer closed its side, or the stream failed.
+    closed: bool,
+}
+
+impl Framed {
+    fn new(stream: TcpStream, peer: SocketAddr) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            peer,
+            read: Vec::new(),
+            read_at: 0,
+            write: Vec::new(),
+            closed: false,
+        })
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        let Ok(len) = u16::try_from(data.len()) else {
+            return;
+        };
+        if self.closed || self.write.len() + 2 + data.len() > WRITE_LIMIT {
+            return;
+        }
+        self.write.extend_from_slice(&len.to_be_bytes());
+        self.write.extend_from_slice(data);
+        self.flush();
+    }
+
+    /// Writes what the stream takes without blocking.
+    fn flush(&mut self) {
+        let mut written = 0;
+        while written < self.write.len() {
+            match self.stream.write(&self.write[written..]) {
+                Ok(0) => break,
+                Ok(n) => written += n,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    log::debug!("tunnel to {} write failed: {err}", self.peer);
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+        self.write.drain(..written);
+    }
+
+    /// Reads what the stream has without blocking.
+    fn fill(&mut self) {
+        if self.read_at > 0 {
+            self.read.drain(..self.read_at);
+            self.read_at = 0;
+        }
+        let mut chunk = [0u8; READ_CHUNK];
+        while !self.closed {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    log::debug!("tunnel to {} closed by the peer", self.peer);
+                    self.closed = true;
+                }
+                Ok(n) => self.read.extend_from_slice(&chunk[..n]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    log::debug!("tunnel to {} read failed: {err}", self.peer);
+                    self.closed = true;
+                }
+            }
+        }
+    }
+
+    /// The next whole datagram read, if any.
+    fn next_frame(&mut self) -> Option<&[u8]> {
+        loop {
+            let header = self.read.get(self.read_at..self.read_at + 2)?;
+            let len = usize::from(u16::from_be_bytes([header[0], header[1]]));
+            let start = self.read_at + 2;
+            if self.read.len() < start + len {
+                return None;
+            }
+            self.read_at = start + len;
+            if len > 0 {
+                return Some(&self.read[start..start + len]);
+            }
+        }
+    }
+
+    fn has_frame(&self) -> bool {
+        self.read
+            .get(self.read_at..self.read_at + 2)
+            .is_some_and(|header| {
+                let len = usize::from(u16::from_be_bytes([header[0], header[1]]));
+                self.read.len() >= self.read_at + 2 + len
+            })
+    }
+}
+
+/// A client's tunnel to its server.
+pub(crate) struct TunnelStream {
+    local: SocketAddr,
+    inner: RefCell<Framed>,
+}
+
+impl TunnelStream {
+    pub(crate) fn connect(peer: SocketAddr) -> io::Result<Self> {
+        let stream = TcpStream::connect_timeout(&peer, CONNECT_TIMEOUT)?;
+        Ok(Self {
+            local: stream.local_addr()?,
+            inner: RefCell::new(Framed::new(stream, peer)?),
+        })
+    }
+
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.local
+    }
+
+    /// Queues `data` for the server, wherever quiche addressed it.
+    pub(crate) fn send(&self, data: &[u8]) {
+        self.inner.borrow_mut().push(data);
+    }
+
+    /// Moves up to `max` received datagrams into `each`; the count moved.
+    /// A closed tunnel just goes quiet and the connection idles out.
+    pub(crate) fn recv(&self, max: usize, mut each: impl FnMut(usize, &[u8], SocketAddr)) -> usize {
+        let mut framed = self.inner.borrow_mut();
+        framed.flush();
+        framed.fill();
+        let peer = framed.peer;
+        let mut count = 0;
+        while count < max {
+            let Some(data) = framed.next_frame() else {
+                break;
+            };
+            each(count, data, peer);
+            count += 1;
+        }
+        count
+    }
+
+    /// Whether a wait should return at once: a datagram is already read.
+    pub(crate) fn has_pending(&self) -> bool {
+        self.inner.borrow().has_frame()
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) fn push_fds(&self, fds: &mut Vec<std::os::fd::RawFd>) {
+        use std::os::fd::AsRawFd;
+
+        let framed = self.inner.borrow();
+        if !framed.closed {
+            fds.push(framed.stream.as_raw_fd());
+        }
+    }
+}
+
+/// A server's TCP port for tunneled clients.
+pub(crate) struct TunnelListener {
+    listener: TcpListener,
+    streams: RefCell<Vec<Framed>>,
+}
+
+impl TunnelListener {
+    pub(crate) fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            streams: RefCell::default(),
+        })
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Queues `data` on the stream from `to`; dropped if it has gone.
+    pub(crate) fn send(&self, data: &[u8], to: SocketAddr) {
+        let mut streams = self.streams.borrow_mut();
+        if let Some(framed) = streams.iter_mut().find(|framed| framed.peer == to) {
+            framed.push(data);
+        }
+    }
+
+    /// Accepts new streams, then moves up to `max` datagrams from all of
+    /// them into `each` with the stream they came on; the count moved.
+    pub(crate) fn recv(&self, max: usize, mut each: impl FnMut(usize, &[u8], SocketAddr)) -> usize {
+        let mut streams = self.streams.borrow_mut();
+        loop {
+            match self.listener.accept() {
+                Ok((stream, peer)) if streams.len() < MAX_STREAMS => {
+                    match Framed::new(stream, peer) {
+                        Ok(framed) => {
+                            log::info!("tunnel from {peer} accepted");
+                            streams.push(framed);
+                        }
+                        Err(err) => log::warn!("tunnel from {peer} setup failed: {err}"),
+                    }
+                }
+                Ok((_, peer)) => log::warn!("tunnel from {peer} refused: {MAX_STREAMS} open"),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    log::warn!("tunnel accept failed: {err}");
+                    break;
+                }
+            }
+        }
+        let mut count = 0;
+        for framed in streams.iter_mut() {
+            framed.flush();
+            framed.fill();
+            while count < max {
+                let peer = framed.peer;
+                let Some(data) = framed.next_frame() else {
+                    break;
+                };
+                each(count, data, peer);
+                count += 1;
+            }
+        }
+        // A closed stream keeps its last frames until they are handed out.
+        streams.retain(|framed| !framed.closed || framed.has_frame());
+        count
+    }
+
+    pub(crate) fn has_pending(&self) -> bool {
+        self.streams.borrow().iter().any(Framed::has_frame)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) fn push_fds(&self, fds: &mut Vec<std::os::fd::RawFd>) {
+        use std::os::fd::AsRawFd;
+
+        fds.push(self.listener.as_raw_fd());
+        for framed in self.streams.borrow().iter() {
+            fds.push(framed.stream.as_raw_fd());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn recv_until<T>(
+        mut recv: impl FnMut(&mut Vec<(Vec<u8>, SocketAddr)>) -> T,
+        want: usize,
+    ) -> Vec<(Vec<u8>, SocketAddr)> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut seen = Vec::new();
+        while seen.len() < want && Instant::now() < deadline {
+            recv(&mut seen);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        seen
+    }
+
+    #[test]
+    fn datagrams_cross_in_both_directions() {
+        let listener = TunnelListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let client = TunnelStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.send(b"initial");
+        client.send(&[]);
+        client.send(&[7; 1350]);
+
+        let seen = recv_until(
+            |seen| listener.recv(8, |_, data, from| seen.push((data.to_vec(), from))),
+            2,
+        );
+        assert_eq!(seen.len(), 2, "the empty datagram is not sent");
+        assert_eq!(seen[0].0, b"initial");
+        assert_eq!(seen[1].0, vec![7; 1350]);
+        let from = seen[0].1;
+        assert_eq!(from, client.local_addr());
+
+        listener.send(b"handshake", from);
+        listener.send(b"lost", "127.0.0.1:9".parse().unwrap());
+        let seen = recv_until(
+            |seen| client.recv(8, |_, data, from| seen.push((data.to_vec(), from))),
+            1,
+        );
+        assert_eq!(
+            seen,
+            [(b"handshake".to_vec(), listener.local_addr().unwrap())]
+        );
+
+        drop(client);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !listener.streams.borrow().is_empty() && Instant::now() < deadline {
+            listener.recv(8, |_, _, _| {});
+        }
+        assert!(listener.streams.borrow().is_empty());
+    }
+
+    #[test]
+    fn frames_split_across_reads_are_reassembled() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut raw = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, peer) = listener.accept().unwrap();
+        let mut framed = Framed::new(accepted, peer).unwrap();
+        raw.write_all(&[0, 3, b'a']).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while framed.read.len() < 3 && Instant::now() < deadline {
+            framed.fill();
+        }
+        assert!(framed.next_frame().is_none());
+        raw.write_all(&[b'b', b'c', 0, 1]).unwrap();
+        while framed.read.len() < 7 && Instant::now() < deadline {
+            framed.fill();
+        }
+        assert_eq!(framed.next_frame(), Some(&b"abc"[..]));
+        assert!(framed.next_frame().is_none());
+        assert!(!framed.has_frame());
+    }
+}
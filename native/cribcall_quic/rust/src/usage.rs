@@ -0,0 +1,133 @@
+//! Per-peer data usage for `cc_quic_usage_stats`: bytes each peer
+//! fingerprint has moved over every connection of a handle, for metered
+//! links. Workers publish each live connection's totals about once a
+//! second and fold them in for good when it closes, so reads never wait on
+//! a worker.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// How often a worker republishes a live connection's byte counts.
+pub(crate) const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub(crate) struct PeerUsage {
+    pub fingerprint: String,
+    pub sent_bytes: u64,
+    pub recv_bytes: u64,
+    /// Connections seen, including any still open.
+    pub connections: u64,
+}
+
+struct Live {
+    fingerprint: String,
+    sent_bytes: u64,
+    recv_bytes: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct UsageBook {
+    closed: HashMap<String, PeerUsage>,
+    live: HashMap<Vec<u8>, Live>,
+}
+
+impl UsageBook {
+    /// Latest totals of an open connection; returns the bytes sent and
+    /// received since the previous report.
+    pub(crate) fn update(
+        &mut self,
+        conn_id: &[u8],
+        fingerprint: &str,
+        sent: u64,
+        recv: u64,
+    ) -> (u64, u64) {
+        let live = self.live.entry(conn_id.to_vec()).or_insert_with(|| Live {
+            fingerprint: fingerprint.to_string(),
+            sent_bytes: 0,
+            recv_bytes: 0,
+        });
+        let grown = (
+            sent.saturating_sub(live.sent_bytes),
+            recv.saturating_sub(live.recv_bytes),
+        );
+        live.sent_bytes = sent;
+        live.recv_bytes = recv;
+        grown
+    }
+
+    /// Final totals of a connection that closed; returns the bytes sent
+    /// and received since the previous report.
+    pub(crate) fn close(&mut self, conn_id: &[u8], sent: u64, recv: u64) -> (u64, u64) {
+        let Some(live) = self.live.remove(conn_id) else {
+            return (0, 0);
+        };
+        let grown = (
+            sent.saturating_sub(live.sent_bytes),
+            recv.saturating_sub(live.recv_bytes),
+        );
+        let peer = self.peer(&live.fingerprint);
+        peer.sent_bytes += sent;
+        peer.recv_bytes += recv;
+        peer.connections += 1;
+        grown
+    }
+
+    fn peer(&mut self, fingerprint: &str) -> &mut PeerUsage {
+        self.closed
+            .entry(fingerprint.to_string())
+            .or_insert_with(|| PeerUsage {
+                fingerprint: fingerprint.to_string(),
+                ..PeerUsage::default()
+            })
+    }
+
+    /// Totals per fingerprint, closed and open connections together.
+    pub(crate) fn snapshot(&self) -> Vec<PeerUsage> {
+        let mut peers = self.closed.clone();
+        for live in self.live.values() {
+            let peer = peers
+                .entry(live.fingerprint.clone())
+                .or_insert_with(|| PeerUsage {
+                    fingerprint: live.fingerprint.clone(),
+                    ..PeerUsage::default()
+                });
+            peer.sent_bytes += live.sent_bytes;
+            peer.recv_bytes += live.recv_bytes;
+            peer.connections += 1;
+        }
+        let mut peers: Vec<_> = peers.into_values().collect();
+        peers.sort_by(|a, b| a.fingerprint.cmp(&b.fingerprint));
+        peers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_closed_and_live_connections_per_fingerprint() {
+        let mut book = UsageBook::default();
+        book.update(b"c1", "aa", 10, 20);
+        assert_eq!(book.update(b"c1", "aa", 100, 200), (90, 180));
+        assert_eq!(book.close(b"c1", 150, 250), (50, 50));
+        book.update(b"c2", "aa", 1, 2);
+        book.update(b"c3", "bb", 5, 5);
+        // A connection never announced has nothing to fold in.
+        assert_eq!(book.close(b"c4", 9, 9), (0, 0));
+
+        let peers = book.snapshot();
+        assert_eq!(
+            peers[0],
+            PeerUsage {
+                fingerprint: "aa".into(),
+                sent_bytes: 151,
+                recv_bytes: 252,
+                connections: 2,
+            }
+        );
+        assert_eq!(peers[1].recv_bytes, 5);
+        assert_eq!(peers.len(), 2);
+    }
+}
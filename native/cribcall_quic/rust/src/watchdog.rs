@@ -0,0 +1,172 @@
+//! Stall detection for worker threads. Workers bump a heartbeat once per loop
+//! pass; a single watchdog thread posts `worker_stalled` when a heartbeat has
+//! not moved for longer than the configured threshold.
+
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{post_event, QuicEvent, CONNECTIONS};
+
+const TICK: Duration = Duration::from_millis(100);
+/// Heartbeat value while a worker is intentionally blocked waiting for work.
+const PARKED: u64 = u64::MAX;
+
+static EPOCH: Lazy<Instant> = Lazy::new(Instant::now);
+static WATCHED: Lazy<Mutex<Vec<Watched>>> = Lazy::new(|| {
+    if let Err(err) = thread::Builder::new()
+        .name("cc-quic-watchdog".to_string())
+        .spawn(run)
+    {
+        log::warn!("watchdog spawn failed: {err}");
+    }
+    Mutex::new(Vec::new())
+});
+
+fn now_ms() -> u64 {
+    EPOCH.elapsed().as_millis() as u64
+}
+
+/// Per-thread progress marker.
+pub(crate) struct Heartbeat {
+    last_ms: AtomicU64,
+}
+
+impl Heartbeat {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Self {
+            last_ms: AtomicU64::new(now_ms()),
+        })
+    }
+
+    pub(crate) fn beat(&self) {
+        self.last_ms.store(now_ms(), Ordering::Relaxed);
+    }
+
+    /// Marks the thread idle until the next `beat`, e.g. while blocked on a
+    /// channel with nothing to drive.
+    pub(crate) fn park(&self) {
+        self.last_ms.store(PARKED, Ordering::Relaxed);
+    }
+}
+
+struct Watched {
+    handle: u64,
+    dart_port: i64,
+    threshold_ms: u64,
+    heartbeat: Weak<Heartbeat>,
+    reported: bool,
+}
+
+/// Reports stalls of `heartbeat` to `handle`'s port; a zero threshold
+/// disables watching. The entry goes away with the heartbeat or the handle.
+pub(crate) fn watch(heartbeat: &Arc<Heartbeat>, handle: u64, dart_port: i64, threshold_ms: u64) {
+    if threshold_ms == 0 {
+        return;
+    }
+    WATCHED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(Watched {
+            handle,
+            dart_port,
+            threshold_ms,
+            heartbeat: Arc::downgrade(heartbeat),
+            reported: false,
+        });
+}
+
+fn run() {
+    loop {
+        thread::sleep(TICK);
+        let now = now_ms();
+        let mut watched = WATCHED.lock().unwrap_or_else(PoisonError::into_inner);
+        watched.retain_mut(|entry| {
+            let alive = CONNECTIONS
+                .get()
+                .is_some_and(|map| map.contains_key(&entry.handle));
+            let Some(heartbeat) = entry.heartbeat.upgrade().filter(|_| alive) else {
+                return false;
+            };
+            let last = heartbeat.last_ms.load(Ordering::Relaxed);
+            let stalled_ms = if last == PARKED {
+                0
+            } else {
+                now.saturating_sub(last)
+            };
+            if stalled_ms < entry.threshold_ms {
+                if entry.reported {
+                    log::info!("worker for handle {} resumed", entry.handle);
+                }
+                entry.reported = false;
+            } else if !entry.reported {
+                entry.reported = true;
+                log::warn!(
+                    "worker for handle {} stalled for {stalled_ms} ms",
+                    entry.handle
+                );
+                post_event(
+                    entry.dart_port,
+                    QuicEvent::WorkerStalled {
+                        handle: entry.handle,
+                        last_progress_ms: stalled_ms,
+                    },
+                );
+            }
+            true
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ConfigRole, ConnectionHandle, WorkerThreads};
+    use dashmap::DashMap;
+    use std::sync::mpsc;
+
+    fn reported(handle: u64) -> Option<bool> {
+        WATCHED
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|entry| entry.handle == handle)
+            .map(|entry| entry.reported)
+    }
+
+    #[test]
+    fn flags_stall_until_heartbeat_is_parked_or_dropped() {
+        let handle = u64::MAX - 1;
+        let (tx, _rx) = mpsc::channel();
+        CONNECTIONS.get_or_init(DashMap::new).insert(
+            handle,
+            ConnectionHandle {
+                tx,
+                threads: WorkerThreads::default(),
+                events: Default::default(),
+                live: Default::default(),
+                usage: Default::default(),
+                role: ConfigRole::Client,
+                blocklist: None,
+                roster: None,
+                allowlist: None,
+            },
+        );
+        let heartbeat = Heartbeat::new();
+        watch(&heartbeat, handle, 1, 150);
+
+        thread::sleep(Duration::from_millis(400));
+        assert_eq!(reported(handle), Some(true));
+
+        heartbeat.park();
+        thread::sleep(Duration::from_millis(250));
+        assert_eq!(reported(handle), Some(false));
+
+        drop(heartbeat);
+        thread::sleep(Duration::from_millis(250));
+        assert_eq!(reported(handle), None);
+        CONNECTIONS.get().unwrap().remove(&handle);
+    }
+}
@@ -9,6 +9,9 @@
 #define FFI_PLUGIN_EXPORT
 #endif
 
+// What cc_quic_abi_version returns for a library matching this header.
+#define CC_QUIC_ABI_VERSION 2
+
 typedef struct CcQuicConfig CcQuicConfig;
 
 enum {
@@ -20,14 +23,298 @@ enum {
   CC_QUIC_SOCKET_ERROR = 5,
   CC_QUIC_HANDSHAKE_ERROR = 6,
   CC_QUIC_EVENT_SEND_ERROR = 7,
+  CC_QUIC_PERMISSION_DENIED = 8,
+  CC_QUIC_UNSUPPORTED = 9,
+  CC_QUIC_WRONG_ROLE = 10,
+  CC_QUIC_UNKNOWN_CONNECTION = 11,
+  CC_QUIC_CERT_EXPIRED = 12,
+  CC_QUIC_KEY_OPEN_FAILED = 13,
+  CC_QUIC_STALE_HANDLE = 14,
   CC_QUIC_INTERNAL = 255,
 };
 
+enum {
+  CC_QUIC_THREAD_PRIORITY_NORMAL = 0,
+  CC_QUIC_THREAD_PRIORITY_AUDIO = 1,
+  CC_QUIC_THREAD_PRIORITY_REALTIME = 2,
+};
+
+enum {
+  CC_QUIC_DGRAM_DROP_FRONT = 0,
+  CC_QUIC_DGRAM_DROP_BACK = 1,
+};
+
+enum {
+  CC_QUIC_COMPRESSION_OFF = 0,
+  CC_QUIC_COMPRESSION_DEFLATE = 1,
+  CC_QUIC_COMPRESSION_ZSTD = 2,
+};
+
+// Pass as dart_port to queue a handle's events for cc_quic_events_poll.
+#define CC_QUIC_POLL_PORT 0
+
+// Event classes for cc_quic_subscribe.
+enum {
+  CC_QUIC_EVENTS_CONNECTION = 1 << 0,
+  CC_QUIC_EVENTS_MESSAGE = 1 << 1,
+  CC_QUIC_EVENTS_MEDIA = 1 << 2,
+  CC_QUIC_EVENTS_CHANNEL = 1 << 3,
+  CC_QUIC_EVENTS_STATS = 1 << 4,
+  CC_QUIC_EVENTS_HEALTH = 1 << 5,
+  CC_QUIC_EVENTS_ALL = (1 << 6) - 1,
+};
+
+// Flags for cc_quic_client_connect.
+enum {
+  // Accept an empty expected_server_fingerprint_hex (any certificate the
+  // CA trusts); the client then posts verification_skipped.
+  CC_QUIC_ALLOW_UNPINNED = 1 << 0,
+};
+
 FFI_PLUGIN_EXPORT int32_t cc_quic_init_dart_api(void* data);
 FFI_PLUGIN_EXPORT int32_t cc_quic_init_logging(void);
 FFI_PLUGIN_EXPORT const char* cc_quic_version(void);
+FFI_PLUGIN_EXPORT uint32_t cc_quic_abi_version(void);
 FFI_PLUGIN_EXPORT int32_t cc_quic_config_new(CcQuicConfig** out_config);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_new_client(CcQuicConfig** out_config);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_new_server(CcQuicConfig** out_config);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_new_preset(
+  const char* preset,
+  CcQuicConfig** out_config);
+// Builds a config from a JSON document of tunables; on failure
+// cc_quic_last_error explains which field was rejected.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_from_json(
+  const char* json,
+  CcQuicConfig** out_config);
+// Why this thread's last failed config, start, command, key seal or open,
+// rotate or priority call failed.
+FFI_PLUGIN_EXPORT int32_t cc_quic_last_error(uint8_t* out_buf, uintptr_t buf_len);
+// Connection history: JSON lines appended to path (NULL stops), rotated to
+// path.1 past max_bytes (0 = 256 KiB).
+FFI_PLUGIN_EXPORT int32_t cc_quic_audit_enable(const char* path, uint64_t max_bytes);
+FFI_PLUGIN_EXPORT int32_t cc_quic_audit_query(
+  uint64_t since_ts_ms,
+  uint8_t* out_buf,
+  uintptr_t buf_len,
+  uintptr_t* out_len);
+// Passphrase encryption for keys at rest (scrypt + ChaCha20-Poly1305). A
+// sealed blob is key_len + 51 bytes; *out_len always gets the size needed.
+FFI_PLUGIN_EXPORT int32_t cc_quic_key_seal(
+  const uint8_t* key,
+  uintptr_t key_len,
+  const uint8_t* passphrase,
+  uintptr_t passphrase_len,
+  uint8_t* out_blob,
+  uintptr_t out_cap,
+  uintptr_t* out_len);
+FFI_PLUGIN_EXPORT int32_t cc_quic_key_open(
+  const uint8_t* blob,
+  uintptr_t blob_len,
+  const uint8_t* passphrase,
+  uintptr_t passphrase_len,
+  uint8_t* out_key,
+  uintptr_t out_cap,
+  uintptr_t* out_len);
 FFI_PLUGIN_EXPORT void cc_quic_config_free(CcQuicConfig* config);
+// Counts received ECN codepoints into stats events; sends stay unmarked.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_ecn(CcQuicConfig* config, bool enabled);
+// Adds peer_cert_der_base64 to connected events.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_peer_cert_export(
+  CcQuicConfig* config,
+  bool enabled);
+// Keep each connection's TLS exporter secret for
+// cc_quic_conn_export_keying_material; off by default.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_keying_material_export(
+  CcQuicConfig* config,
+  bool enabled);
+// Post stream_writable (stream_id, channel, capacity) as open channels gain
+// send credit, for pull-based pacing of large transfers.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_writable_events(
+  CcQuicConfig* config,
+  bool enabled);
+// Server only: report each client's source address to it (observed_address
+// on the client). Off by default.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_observed_address(
+  CcQuicConfig* config,
+  bool enabled);
+// Server only: send every client the roster of connected peer fingerprints
+// and their labels on each change (roster_changed on both sides). Off by
+// default.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_roster(CcQuicConfig* config, bool enabled);
+// Offer features (1 framing-v2, 2 compression, 4 media datagrams, 8 FEC)
+// to each peer once connected; both sides post capabilities_negotiated.
+// 0, the default, sends no offer.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_capabilities(
+  CcQuicConfig* config,
+  uint32_t features);
+// Client only: token (1-4096 bytes) presented after the pin check; NULL
+// clears it.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_auth_token(
+  CcQuicConfig* config,
+  const char* token);
+// Server only: accept only tokens minted under secret (NULL or 0 length
+// turns the check off); rejected clients close with code 0x106.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_auth_hmac(
+  CcQuicConfig* config,
+  const uint8_t* secret,
+  size_t secret_len);
+// Server only: post auth_request and wait for cc_quic_server_auth_verdict
+// (10 s) before connected.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_auth_callback(
+  CcQuicConfig* config,
+  bool enabled);
+// Server only: admit clients without a certificate on their token alone
+// (null peer_fingerprint); needs an auth check. Off refuses them (0x103).
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_client_cert_optional(
+  CcQuicConfig* config,
+  bool enabled);
+// Server only: fail a certificate-less client's handshake with the
+// certificate_required alert (0x174). Not with client_cert_optional.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_require_client_cert(
+  CcQuicConfig* config,
+  bool enabled);
+// Server only: post connection_pending and wait up to timeout_ms for
+// cc_quic_server_decide before connected (0 = off); refusals close 0x107.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_connection_approval(
+  CcQuicConfig* config,
+  uint64_t timeout_ms);
+// Server only: present cert/key to clients whose SNI is server_name and
+// admit them by trusted_fingerprints_csv instead of the server's own list.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_add_sni_identity(
+  CcQuicConfig* config,
+  const char* server_name,
+  const char* cert_pem_path,
+  const char* key_pem_path,
+  const char* trusted_fingerprints_csv);
+// Client only: a pinned connect to a fingerprint with an established
+// connection returns that handle; cc_quic_conn_close is reference counted.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_coalesce(
+  CcQuicConfig* config,
+  bool enabled);
+// Client only: reconnects to a server within max_age_ms (0 = off) start
+// with the previous connection's congestion window, up to 64 packets.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_cwnd_resume(
+  CcQuicConfig* config,
+  uint64_t max_age_ms);
+// Client only: reverse connect mode. On a server's connect request the
+// client dials a new connection back (posting reverse_connect), pinning the
+// server's fingerprint or one of pins_csv (NULL = none besides it).
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_reverse_connect(
+  CcQuicConfig* config,
+  bool enabled,
+  const char* pins_csv);
+// QUIC over TCP on port (0 = off) for networks that drop UDP: servers also
+// listen there; clients dial it after udp_timeout_ms (0 = 3000) of UDP
+// silence, posting transport_fallback.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_tcp_fallback(
+  CcQuicConfig* config,
+  uint16_t port,
+  uint64_t udp_timeout_ms);
+// Also verify peers against the CAs in path (a PEM file or a directory of
+// hashed PEM names); NULL trusts the system store only.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_ca_path(
+  CcQuicConfig* config,
+  const char* path);
+// Days ahead to post cert_expiring_soon (default 30, 0 = never).
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_cert_expiry_warning(
+  CcQuicConfig* config,
+  uint32_t days);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_pmtu_discovery(
+  CcQuicConfig* config,
+  bool enabled,
+  uint32_t max_udp_payload);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_udp_offload(CcQuicConfig* config, bool enabled);
+// HyStart++ slow-start exit (default on).
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_hystart(CcQuicConfig* config, bool enabled);
+// Pacing (default on), capped at max_rate_kbps (0 = uncapped); a cap with
+// pacing off is rejected.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_pacing(
+  CcQuicConfig* config,
+  bool enabled,
+  uint64_t max_rate_kbps);
+// Advertised max_ack_delay (ms, < 16384) and ack_delay_exponent (<= 20);
+// 0 keeps the 25 ms / 3 defaults.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_ack_delay(
+  CcQuicConfig* config,
+  uint64_t max_ack_delay_ms,
+  uint32_t ack_delay_exponent);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_stats_interval(
+  CcQuicConfig* config,
+  uint64_t interval_ms);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_shared_runtime(
+  CcQuicConfig* config,
+  bool enabled);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_server_workers(
+  CcQuicConfig* config,
+  uint32_t workers);
+// Server only: close handshakes older than max_handshake_ms and established
+// connections older than max_lifetime_ms (0 = no limit), posting
+// connection_reaped.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_connection_limits(
+  CcQuicConfig* config,
+  uint64_t max_lifetime_ms,
+  uint64_t max_handshake_ms);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_watchdog(
+  CcQuicConfig* config,
+  uint64_t threshold_ms);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_dgram_queues(
+  CcQuicConfig* config,
+  uint32_t recv_len,
+  uint32_t send_len);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_dgram_drop_policy(
+  CcQuicConfig* config,
+  int32_t policy);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_media_reorder_window(
+  CcQuicConfig* config,
+  uint32_t packets);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_jitter_buffer(
+  CcQuicConfig* config,
+  uint32_t clock_rate,
+  uint32_t min_delay_ms,
+  uint32_t max_delay_ms);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_compression(
+  CcQuicConfig* config,
+  int32_t mode,
+  uint32_t min_size);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_flow_window(
+  CcQuicConfig* config,
+  uint64_t min_bytes,
+  uint64_t max_bytes);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_recv_limits(
+  CcQuicConfig* config,
+  uint64_t max_message_bytes,
+  uint64_t max_connection_bytes);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_dual_channel(
+  CcQuicConfig* config,
+  uint32_t media_kbps,
+  uint32_t control_backlog_bytes);
+// Queued datagrams go out before backlogged control data.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_datagrams_first(
+  CcQuicConfig* config,
+  bool enabled);
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_event_schema(
+  CcQuicConfig* config,
+  uint32_t version);
+// Cap events of each CC_QUIC_EVENTS_* class in event_mask at max_per_sec
+// (0 lifts the cap); held-back events are counted in events_suppressed.
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_event_rate_limit(
+  CcQuicConfig* config,
+  uint32_t event_mask,
+  uint32_t max_per_sec);
+// Hold back an error repeating one posted within window_ms (0 = off).
+FFI_PLUGIN_EXPORT int32_t cc_quic_config_set_error_coalescing(
+  CcQuicConfig* config,
+  uint64_t window_ms);
+FFI_PLUGIN_EXPORT uint32_t cc_quic_event_schema_version(void);
+// Test hook: the next cc_quic_server_start with server_config and the next
+// cc_quic_client_connect with client_config talk over an in-memory
+// transport; bind address, host and port are ignored.
+FFI_PLUGIN_EXPORT int32_t cc_quic_test_loopback_pair(
+  CcQuicConfig* server_config,
+  CcQuicConfig* client_config);
+// The config is only read: reuse it for more connections and free it with
+// cc_quic_config_free when done. An empty expected fingerprint is a
+// config error unless flags has CC_QUIC_ALLOW_UNPINNED.
 FFI_PLUGIN_EXPORT int32_t cc_quic_client_connect(
   CcQuicConfig* config,
   const char* host,
@@ -36,8 +323,33 @@ FFI_PLUGIN_EXPORT int32_t cc_quic_client_connect(
   const char* expected_server_fingerprint_hex,
   const char* cert_pem_path,
   const char* key_pem_path,
+  uint32_t flags,
+  int64_t dart_port,
+  uint64_t* out_handle);
+// Like cc_quic_client_connect, through the proxy at proxy_url: a MASQUE
+// (CONNECT-UDP) proxy as "https://host[:port]", with an optional path
+// template holding {target_host} and {target_port}, where proxy_auth is
+// sent as proxy-authorization unless empty; or a SOCKS5 proxy as
+// "socks5://host[:port]", where proxy_auth is "user:password" or empty.
+// proxy_ca_path (PEM file or directory; NULL or "" for the default store,
+// desktop Linux only) is trusted for a MASQUE proxy's certificate. host
+// must be an IP address.
+FFI_PLUGIN_EXPORT int32_t cc_quic_client_connect_via_proxy(
+  CcQuicConfig* config,
+  const char* proxy_url,
+  const char* proxy_auth,
+  const char* proxy_ca_path,
+  const char* host,
+  uint16_t port,
+  const char* server_name,
+  const char* expected_server_fingerprint_hex,
+  const char* cert_pem_path,
+  const char* key_pem_path,
+  uint32_t flags,
   int64_t dart_port,
   uint64_t* out_handle);
+// trusted_fingerprints_csv: entries "fp[;label=...][;expires=...]" or "*";
+// empty trusts every client. Labels show in connected.
 FFI_PLUGIN_EXPORT int32_t cc_quic_server_start(
   CcQuicConfig* config,
   const char* bind_addr,
@@ -47,10 +359,255 @@ FFI_PLUGIN_EXPORT int32_t cc_quic_server_start(
   const char* trusted_fingerprints_csv,
   int64_t dart_port,
   uint64_t* out_handle);
+// Listens on every address in the comma-separated bind_addrs from one
+// worker; the bound addresses (same form) go to out_bound_addrs if not NULL.
+FFI_PLUGIN_EXPORT int32_t cc_quic_server_start_multi(
+  CcQuicConfig* config,
+  const char* bind_addrs,
+  const char* cert_pem_path,
+  const char* key_pem_path,
+  const char* trusted_fingerprints_csv,
+  int64_t dart_port,
+  uint64_t* out_handle,
+  char* out_bound_addrs,
+  uintptr_t out_bound_len);
+// Decode the hex connection_id events carry into the raw id (at most 20
+// bytes) that conn_id parameters take; bad hex returns CC_QUIC_CONFIG_ERROR.
+FFI_PLUGIN_EXPORT int32_t cc_quic_conn_id_from_hex(
+  const uint8_t* hex,
+  uintptr_t hex_len,
+  uint8_t* out_id,
+  uintptr_t out_cap,
+  uintptr_t* out_len);
+// conn_id is the raw id; see cc_quic_conn_id_from_hex.
 FFI_PLUGIN_EXPORT int32_t cc_quic_conn_send(
   uint64_t handle,
   const uint8_t* conn_id,
   uintptr_t conn_id_len,
   const uint8_t* data,
   uintptr_t data_len);
+FFI_PLUGIN_EXPORT int32_t cc_quic_control_send(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  uintptr_t conn_id_len,
+  const uint8_t* data,
+  uintptr_t data_len);
+FFI_PLUGIN_EXPORT int32_t cc_quic_media_send(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  uintptr_t conn_id_len,
+  uint32_t timestamp,
+  bool marker,
+  const uint8_t* data,
+  uintptr_t data_len);
+FFI_PLUGIN_EXPORT int32_t cc_quic_media_set_fec(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  uintptr_t conn_id_len,
+  uint32_t k,
+  uint32_t n);
+FFI_PLUGIN_EXPORT int32_t cc_quic_media_set_redundancy(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  uintptr_t conn_id_len,
+  uint32_t copies,
+  uint32_t spread_ms);
+FFI_PLUGIN_EXPORT int32_t cc_quic_timesync_start(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  uintptr_t conn_id_len,
+  uint32_t interval_ms);
+// Installs the new cert/key (PEM paths) for later handshakes and announces
+// its fingerprint to connected peers (peer_identity_rotated).
+FFI_PLUGIN_EXPORT int32_t cc_quic_identity_rotate(
+  uint64_t handle,
+  const char* cert_pem_path,
+  const char* key_pem_path);
+// Bytes sent/received per peer fingerprint over the handle's lifetime, as a
+// NUL-terminated JSON array; *out_len always gets the text length.
+FFI_PLUGIN_EXPORT int32_t cc_quic_usage_stats(
+  uint64_t handle,
+  uint8_t* out_buf,
+  uintptr_t buf_len,
+  uintptr_t* out_len);
+// Write a JSON diagnostics bundle (connections, options, usage, recent
+// events and log lines) to out_path.
+FFI_PLUGIN_EXPORT int32_t cc_quic_diagnostics_dump(
+  uint64_t handle,
+  const char* out_path);
+// Only in builds with the `metrics` feature: serve Prometheus text-format
+// metrics for every handle at http://<bind_addr>/metrics ("ip:port").
+FFI_PLUGIN_EXPORT int32_t cc_quic_metrics_serve(const char* bind_addr);
+// Server only: drop datagrams from cidr ("addr/prefix" or a bare address)
+// before any QUIC processing; counted in the stats event's blocklist section.
+FFI_PLUGIN_EXPORT int32_t cc_quic_server_block_addr(uint64_t handle, const char* cidr);
+FFI_PLUGIN_EXPORT int32_t cc_quic_server_unblock_addr(uint64_t handle, const char* cidr);
+// Server only: steer clients to addr ("ip:port", one of the bound addresses;
+// NULL stops). Clients migrate and post server_relocated.
+FFI_PLUGIN_EXPORT int32_t cc_quic_server_set_preferred_address(
+  uint64_t handle,
+  const char* addr);
+// Server only: ask a reverse connect client to dial back to addr ("ip:port";
+// NULL = the address it reached), expecting pin_hex (NULL = this server).
+FFI_PLUGIN_EXPORT int32_t cc_quic_server_request_connect(
+  uint64_t handle,
+  const uint8_t* conn_id_ptr,
+  size_t conn_id_len,
+  const char* addr,
+  const char* pin_hex);
+// Server only: trust the fingerprints in the file at path (comma or newline
+// separated, # comments; empty trusts none), reloaded on change with
+// allowlist_reloaded. NULL stops watching. Unreadable: config_error.
+FFI_PLUGIN_EXPORT int32_t cc_quic_server_set_allowlist_file(
+  uint64_t handle,
+  const char* path);
+// Server only: read the allowlist file again now.
+FFI_PLUGIN_EXPORT int32_t cc_quic_server_reload_allowlist(uint64_t handle);
+// Server only: label a peer (SHA-256 hex fingerprint) on the roster; up to
+// 255 bytes, NULL or empty clears it.
+FFI_PLUGIN_EXPORT int32_t cc_quic_server_set_peer_label(
+  uint64_t handle,
+  const char* fingerprint,
+  const char* label);
+// Writes a NUL-terminated token for user (no '.') valid until expires (Unix
+// seconds, 0 = never); out_len gets the length even when buf_len is short.
+FFI_PLUGIN_EXPORT int32_t cc_quic_auth_mint_token(
+  const uint8_t* secret,
+  size_t secret_len,
+  const char* user,
+  uint64_t expires,
+  char* out_buf,
+  size_t buf_len,
+  size_t* out_len);
+// Server only: answer an auth_request; user (NULL = none) shows in connected.
+FFI_PLUGIN_EXPORT int32_t cc_quic_server_auth_verdict(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  size_t conn_id_len,
+  bool accept,
+  const char* user);
+// Server only: answer a connection_pending.
+FFI_PLUGIN_EXPORT int32_t cc_quic_server_decide(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  size_t conn_id_len,
+  bool accept);
+// Server only: refuse user from now on and close their connections; revoked
+// = false lets them back in.
+FFI_PLUGIN_EXPORT int32_t cc_quic_server_revoke_user(
+  uint64_t handle,
+  const char* user,
+  bool revoked);
+// Named channels: each name (1-255 bytes) gets its own stream. Both peers post
+// channel_opened once the peer accepts; messages on it carry "channel".
+FFI_PLUGIN_EXPORT int32_t cc_quic_channel_open(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  uintptr_t conn_id_len,
+  const char* name);
+FFI_PLUGIN_EXPORT int32_t cc_quic_channel_send(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  uintptr_t conn_id_len,
+  const char* name,
+  const uint8_t* data,
+  uintptr_t data_len);
+FFI_PLUGIN_EXPORT int32_t cc_quic_channel_close(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  uintptr_t conn_id_len,
+  const char* name);
+// Abort a channel stream (the stream_id of channel_opened) with an error code
+// below 2^62: reset drops what we have not delivered, stop_sending asks the
+// peer to drop what it has not. The peer posts stream_reset / stream_stopped.
+// A reset ends the channel; after stop_sending it still carries our sends
+// until cc_quic_channel_close.
+FFI_PLUGIN_EXPORT int32_t cc_quic_stream_reset(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  uintptr_t conn_id_len,
+  uint64_t stream_id,
+  uint64_t error_code);
+FFI_PLUGIN_EXPORT int32_t cc_quic_stream_stop_sending(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  uintptr_t conn_id_len,
+  uint64_t stream_id,
+  uint64_t error_code);
+// Client only: (un)subscribe to a server topic (1-255 bytes); publications
+// arrive as message events carrying "topic". The server posts subscription.
+FFI_PLUGIN_EXPORT int32_t cc_quic_topic_subscribe(uint64_t handle, const char* topic);
+FFI_PLUGIN_EXPORT int32_t cc_quic_topic_unsubscribe(uint64_t handle, const char* topic);
+// Server only: queue data to every client subscribed to topic.
+FFI_PLUGIN_EXPORT int32_t cc_quic_server_publish(
+  uint64_t handle,
+  const char* topic,
+  const uint8_t* data,
+  uintptr_t data_len);
+FFI_PLUGIN_EXPORT int32_t cc_quic_conn_set_rate_limit(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  uintptr_t conn_id_len,
+  uint64_t max_bps);
+// Pacing overrides for the rate limit: off (enabled false), burst quantum
+// and max burst in bytes; 0 keeps one packet / 10 ms at the rate.
+FFI_PLUGIN_EXPORT int32_t cc_quic_conn_set_pacing(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  uintptr_t conn_id_len,
+  bool enabled,
+  uint32_t quantum_bytes,
+  uint32_t max_burst_bytes);
+// Tag the connection's later events with "tag" until its close; 0 removes
+// it. An empty conn_id tags the handle's events no connection tag covers.
+FFI_PLUGIN_EXPORT int32_t cc_quic_conn_set_tag(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  uintptr_t conn_id_len,
+  uint64_t tag);
+// Write out_len (1..8160) bytes of RFC 8446 exporter keying material for
+// label and context; both ends get the same. context may be NULL when
+// context_len is 0. Unsupported without keying material export.
+FFI_PLUGIN_EXPORT int32_t cc_quic_conn_export_keying_material(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  uintptr_t conn_id_len,
+  const char* label,
+  const uint8_t* context,
+  uintptr_t context_len,
+  uint8_t* out,
+  uintptr_t out_len);
+// Write the connection's datagrams (encrypted) to path as pcapng until the
+// file would pass max_bytes (16 MiB when 0); NULL path stops.
+FFI_PLUGIN_EXPORT int32_t cc_quic_conn_capture(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  uintptr_t conn_id_len,
+  const char* path,
+  uint64_t max_bytes);
+// Test hook, only in builds with the `impairment` feature: loss, reorder,
+// latency/jitter and a bandwidth cap on what this side sends; all zero
+// removes it.
+FFI_PLUGIN_EXPORT int32_t cc_quic_conn_set_impairment(
+  uint64_t handle,
+  const uint8_t* conn_id,
+  uintptr_t conn_id_len,
+  float loss_pct,
+  float reorder_pct,
+  uint32_t latency_ms,
+  uint32_t jitter_ms,
+  uint32_t bandwidth_kbps,
+  uint64_t seed);
 FFI_PLUGIN_EXPORT int32_t cc_quic_conn_close(uint64_t handle);
+FFI_PLUGIN_EXPORT int32_t cc_quic_events_poll(
+  uint64_t handle,
+  uint8_t* out_buf,
+  uintptr_t buf_len,
+  uint32_t max_events);
+FFI_PLUGIN_EXPORT int32_t cc_quic_set_thread_priority(uint64_t handle, int32_t priority);
+// Also posts the handle's events in event_mask to dart_port; posting a
+// port again replaces its mask, and 0 unsubscribes it.
+FFI_PLUGIN_EXPORT int32_t cc_quic_subscribe(
+  uint64_t handle,
+  int64_t dart_port,
+  uint32_t event_mask);